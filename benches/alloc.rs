@@ -0,0 +1,39 @@
+//! Compares the allocators the runtime can be run with on the small, never
+//! freed allocations strings and instances make.
+//!
+//! Run with `cargo bench --bench alloc`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use pajama::allocator::Allocator;
+
+const ALLOCATIONS: usize = 100_000;
+const ROUNDS: u32 = 10;
+
+fn bench(name: &str, allocator: Allocator) {
+    let mut total = Duration::ZERO;
+
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+
+        for allocation in 0..ALLOCATIONS {
+            // Str headers, short strings and small instances
+            let size = 16 + (allocation % 4) * 16;
+            let ptr = allocator.allocate(size);
+
+            unsafe { *(ptr as *mut u8) = 1 };
+            black_box(ptr);
+        }
+
+        total += start.elapsed();
+    }
+
+    println!("{:<32} {:>10.3?} per round", name, total / ROUNDS);
+}
+
+fn main() {
+    bench("system", Allocator::System);
+    bench("mimalloc", Allocator::Mimalloc);
+    bench("bump", Allocator::Bump);
+}
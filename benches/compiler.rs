@@ -0,0 +1,273 @@
+//! Times each phase of compiling the programs in `benches/compiler`, records
+//! the times in a JSON Lines history, one run per line, and fails when a
+//! phase got slower than in the last run of another commit.
+//!
+//! Run with `cargo bench --bench compiler`. The history is kept in
+//! `target/compiler-bench-history.jsonl`, or `$PAJAMA_BENCH_HISTORY`, and a
+//! phase may get `$PAJAMA_BENCH_THRESHOLD` percent slower, 20 by default.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use pajama::cancellation::CancellationToken;
+use pajama::lexer::Lexer;
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::parser::{default_op_precedence, Parser};
+use pajama::semantic_analyzer::{Diagnostics, SemanticAnalyzer};
+use pajama::source::SourceFile;
+
+const PHASES: [&str; 4] = ["lex", "parse", "check", "codegen"];
+const ROUNDS: usize = 20;
+const DEFAULT_THRESHOLD: f64 = 20.0;
+/// Phases this many microseconds slower or less aren't regressions, however
+/// large the percentage, so timer noise on the short ones doesn't fail a run
+const NOISE_MICROS: u64 = 100;
+
+/// A run of the suite: the commit and the median microseconds of each phase,
+/// over the whole corpus
+struct Run {
+    commit: String,
+    micros: Vec<(String, u64)>,
+}
+
+impl Run {
+    fn to_json(&self) -> String {
+        let phases: Vec<String> = self
+            .micros
+            .iter()
+            .map(|(phase, micros)| format!("\"{}_us\": {}", phase, micros))
+            .collect();
+
+        format!("{{\"commit\": \"{}\", {}}}", self.commit, phases.join(", "))
+    }
+
+    /// Reads a line written by `to_json`, `None` for anything else.
+    fn from_json(line: &str) -> Option<Run> {
+        let fields = line.trim().strip_prefix('{')?.strip_suffix('}')?;
+        let mut commit = None;
+        let mut micros = vec![];
+
+        for field in fields.split(',') {
+            let (key, value) = field.split_once(':')?;
+            let key = key.trim().trim_matches('"');
+            let value = value.trim();
+
+            match key.strip_suffix("_us") {
+                Some(phase) => micros.push((phase.to_string(), value.parse().ok()?)),
+                None if key == "commit" => commit = Some(value.trim_matches('"').to_string()),
+                None => return None,
+            }
+        }
+
+        Some(Run {
+            commit: commit?,
+            micros,
+        })
+    }
+
+    fn phase(&self, phase: &str) -> Option<u64> {
+        self.micros
+            .iter()
+            .find(|(name, _)| name == phase)
+            .map(|(_, micros)| *micros)
+    }
+}
+
+fn corpus() -> Vec<SourceFile> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/compiler");
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "pjs"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| SourceFile {
+            path: path.file_name().unwrap().to_string_lossy().to_string(),
+            input: std::fs::read_to_string(&path).unwrap(),
+        })
+        .collect()
+}
+
+/// Compiles `source` as a program of its own, timing each of `PHASES`.
+fn compile(source: &SourceFile) -> [Duration; 4] {
+    let started = Instant::now();
+    let tokens = Lexer::new(&source.input).tokenize();
+    let lex = started.elapsed();
+
+    let started = Instant::now();
+    let mut parser_result = Parser::start_parse_files(
+        vec![(source.path.clone(), tokens)],
+        &mut default_op_precedence(),
+    )
+    .unwrap_or_else(|_| panic!("{} doesn't parse", source.path));
+    let parse = started.elapsed();
+
+    let started = Instant::now();
+    let analyzer = SemanticAnalyzer::transform_ast(
+        &mut parser_result,
+        Diagnostics::new(),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    let check = started.elapsed();
+
+    assert!(
+        analyzer.diagnostics.errors.is_empty(),
+        "{}: {:?}",
+        source.path,
+        analyzer.diagnostics.errors
+    );
+
+    let started = Instant::now();
+    PajamaCompiler::ast_to_ir(&parser_result, &CompileOptions::default()).unwrap();
+    let codegen = started.elapsed();
+
+    [lex, parse, check, codegen]
+}
+
+fn median(mut micros: Vec<u64>) -> u64 {
+    micros.sort_unstable();
+    micros[micros.len() / 2]
+}
+
+/// The short hash of HEAD, with `-dirty` when the tree has changes.
+fn current_commit() -> String {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let commit = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+
+    match git(&["status", "--porcelain"]) {
+        Some(status) if !status.is_empty() => format!("{}-dirty", commit),
+        _ => commit,
+    }
+}
+
+fn main() {
+    let history_path = std::env::var("PAJAMA_BENCH_HISTORY").unwrap_or_else(|_| {
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/target/compiler-bench-history.jsonl"
+        )
+        .to_string()
+    });
+    let threshold = match std::env::var("PAJAMA_BENCH_THRESHOLD") {
+        Ok(threshold) => threshold.parse::<f64>().unwrap_or_else(|_| {
+            eprintln!(
+                "PAJAMA_BENCH_THRESHOLD is `{}`, expected a percentage like 20",
+                threshold
+            );
+            std::process::exit(1);
+        }),
+        Err(_) => DEFAULT_THRESHOLD,
+    };
+
+    let corpus = corpus();
+    let mut rounds: Vec<Vec<u64>> = vec![vec![]; PHASES.len()];
+
+    for _ in 0..ROUNDS {
+        let mut totals = [Duration::ZERO; 4];
+
+        for source in &corpus {
+            for (total, phase) in totals.iter_mut().zip(compile(source)) {
+                *total += phase;
+            }
+        }
+
+        for (micros, total) in rounds.iter_mut().zip(totals) {
+            micros.push(total.as_micros() as u64);
+        }
+    }
+
+    let run = Run {
+        commit: current_commit(),
+        micros: PHASES
+            .iter()
+            .zip(rounds)
+            .map(|(phase, micros)| (phase.to_string(), median(micros)))
+            .collect(),
+    };
+
+    let history = std::fs::read_to_string(&history_path).unwrap_or_default();
+    let baseline = history
+        .lines()
+        .filter_map(Run::from_json)
+        .rfind(|previous| previous.commit != run.commit);
+
+    if let Some(dir) = Path::new(&history_path).parent() {
+        std::fs::create_dir_all(dir).unwrap_or_else(|err| panic!("{}: {}", dir.display(), err));
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_path)
+        .unwrap_or_else(|err| panic!("{}: {}", history_path, err));
+    writeln!(file, "{}", run.to_json()).unwrap();
+
+    let baseline = match baseline {
+        Some(baseline) => baseline,
+        None => {
+            println!("{:<8} {:>12}", "phase", run.commit);
+
+            for (phase, micros) in &run.micros {
+                println!("{:<8} {:>10}us", phase, micros);
+            }
+
+            return;
+        }
+    };
+
+    println!(
+        "{:<8} {:>12} {:>12} {:>8}",
+        "phase", baseline.commit, run.commit, "change"
+    );
+
+    let mut regressions = vec![];
+
+    for (phase, micros) in &run.micros {
+        // Phases added since the baseline have nothing to compare with
+        let before = match baseline.phase(phase) {
+            Some(before) => before,
+            None => {
+                println!("{:<8} {:>12} {:>10}us", phase, "", micros);
+                continue;
+            }
+        };
+
+        let change = (*micros as f64 - before as f64) / before.max(1) as f64 * 100.0;
+
+        println!(
+            "{:<8} {:>10}us {:>10}us {:>+7.1}%",
+            phase, before, micros, change
+        );
+
+        if change > threshold && micros.saturating_sub(before) > NOISE_MICROS {
+            regressions.push(format!(
+                "{} is {:.1}% slower than in {}",
+                phase, change, baseline.commit
+            ));
+        }
+    }
+
+    if !regressions.is_empty() {
+        for regression in &regressions {
+            eprintln!("regression: {}", regression);
+        }
+
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,81 @@
+//! Lexes and parses a synthetic 100 file program, comparing one thread with
+//! one per CPU for the per-file work: lexing and scanning each file for its
+//! definitions.
+//!
+//! Run with `cargo bench --bench parse`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pajama::lexer::{Lexer, Token};
+use pajama::parallel::par_map_with;
+use pajama::parser::Parser;
+
+const FILES: usize = 100;
+const DEFS_PER_FILE: usize = 200;
+const ROUNDS: u32 = 10;
+
+fn sources() -> Vec<(String, String)> {
+    (0..FILES)
+        .map(|file| {
+            let mut input = String::new();
+
+            for def in 0..DEFS_PER_FILE {
+                input.push_str(&format!(
+                    "def f{}_{}(a Int, b Int) -> Int\n   ret a\nend\n\n",
+                    file, def
+                ));
+            }
+
+            (format!("file{}.pjs", file), input)
+        })
+        .collect()
+}
+
+fn lex(workers: usize, sources: &[(String, String)]) -> Vec<(String, Vec<Token>)> {
+    par_map_with(workers, sources.iter().collect(), |(path, input)| {
+        (path.clone(), Lexer::new(input).tokenize())
+    })
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let started = Instant::now();
+
+    for _ in 0..ROUNDS {
+        f();
+    }
+
+    started.elapsed() / ROUNDS
+}
+
+fn main() {
+    let sources = sources();
+    let workers = thread::available_parallelism().map_or(1, |workers| workers.get());
+
+    let mut worker_counts = vec![1];
+
+    if workers > 1 {
+        worker_counts.push(workers);
+    }
+
+    for workers in worker_counts {
+        let lexing = time(|| {
+            black_box(lex(workers, &sources));
+        });
+
+        println!("lex, {} thread(s): {:?}", workers, lexing);
+    }
+
+    // Scanning for definitions runs per file, parsing the bodies doesn't, so
+    // this is timed apart from lexing to show how much of it is sequential
+    let files = lex(workers, &sources);
+    let parsing = time(|| {
+        let mut precedence_map = HashMap::new();
+
+        black_box(Parser::start_parse_files(files.clone(), &mut precedence_map).ok());
+    });
+
+    println!("scan and parse, {} thread(s): {:?}", workers, parsing);
+}
@@ -0,0 +1,132 @@
+//! Compares the callback based runtime sorting and searching functions with
+//! the same work written directly in Rust, to keep an eye on the cost of
+//! calling back into compiled comparators through a function pointer.
+//!
+//! Run with `cargo bench --bench sort`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use libc::c_void;
+use pajama::pajama_lib::{
+    pj_array_new, pj_array_push_ptr, pj_binary_search, pj_index_of, pj_sort, pj_sort_by, PjArray,
+};
+
+const ITEMS: usize = 100_000;
+const ROUNDS: u32 = 20;
+
+extern "C" fn compare(left: *mut c_void, right: *mut c_void) -> i64 {
+    unsafe { *(left as *const i64) - *(right as *const i64) }
+}
+
+extern "C" fn key(item: *mut c_void) -> i64 {
+    unsafe { *(item as *const i64) }
+}
+
+/// Pointers to heap values, the same layout as an array of class instances
+fn items() -> (Vec<i64>, Vec<*mut c_void>) {
+    let mut seed: i64 = 42;
+    let values: Vec<i64> = (0..ITEMS)
+        .map(|_| {
+            seed = (seed * 1_103_515_245 + 12_345) % 2_147_483_648;
+            seed
+        })
+        .collect();
+    let pointers = values
+        .iter()
+        .map(|value| value as *const i64 as *mut c_void)
+        .collect();
+
+    (values, pointers)
+}
+
+/// A runtime array holding `pointers`, like compiled code builds one
+fn array(pointers: &[*mut c_void]) -> &'static mut PjArray {
+    let array = unsafe { &mut *pj_array_new(pointers.len() as i64) };
+
+    for pointer in pointers {
+        pj_array_push_ptr(array, *pointer);
+    }
+
+    array
+}
+
+fn bench(name: &str, mut run: impl FnMut()) {
+    let mut total = Duration::ZERO;
+
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        run();
+        total += start.elapsed();
+    }
+
+    println!("{:<32} {:>10.3?} per round", name, total / ROUNDS);
+}
+
+fn main() {
+    let (_values, pointers) = items();
+
+    bench("pj_sort", || {
+        let items = array(&pointers);
+        pj_sort(items, compare);
+        black_box(items);
+    });
+
+    bench("handwritten sort", || {
+        let mut items = pointers.clone();
+        items.sort_by(|left, right| unsafe {
+            (*(*left as *const i64)).cmp(&*(*right as *const i64))
+        });
+        black_box(items);
+    });
+
+    bench("pj_sort_by", || {
+        let items = array(&pointers);
+        pj_sort_by(items, key);
+        black_box(items);
+    });
+
+    let mut sorted = pointers.clone();
+    sorted.sort_by(|left, right| unsafe { (*(*left as *const i64)).cmp(&*(*right as *const i64)) });
+    let sorted_array = array(&sorted);
+    let needles: Vec<*mut c_void> = sorted.iter().step_by(ITEMS / 1_000).copied().collect();
+
+    bench("pj_binary_search", || {
+        for needle in &needles {
+            black_box(pj_binary_search(sorted_array, *needle, compare));
+        }
+    });
+
+    bench("handwritten binary search", || {
+        for needle in &needles {
+            let needle = unsafe { *(*needle as *const i64) };
+            black_box(
+                sorted
+                    .binary_search_by(|probe| unsafe { (*(*probe as *const i64)).cmp(&needle) })
+                    .ok(),
+            );
+        }
+    });
+
+    bench("pj_index_of", || {
+        for needle in needles.iter().take(10) {
+            black_box(pj_index_of(sorted_array, *needle, compare));
+        }
+    });
+
+    bench("handwritten index_of loop", || {
+        for needle in needles.iter().take(10) {
+            let needle = unsafe { *(*needle as *const i64) };
+            let mut found = -1;
+
+            for (index, probe) in sorted.iter().enumerate() {
+                if unsafe { *(*probe as *const i64) } == needle {
+                    found = index as i64;
+                    break;
+                }
+            }
+
+            black_box(found);
+        }
+    });
+}
@@ -0,0 +1,92 @@
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+use libc::c_void;
+use mimalloc_rust::raw::basic_allocation::mi_malloc;
+
+/// Where the runtime gets memory from, picked with `--allocator`. Nothing the
+/// runtime allocates is freed yet, so only allocation goes through here.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Allocator {
+    /// libc `malloc`
+    #[default]
+    System,
+    Mimalloc,
+    /// Hands out the next bytes of a large chunk, for short lived scripts
+    /// that allocate a lot
+    Bump,
+}
+
+impl Allocator {
+    pub fn allocate(self, size: usize) -> *mut c_void {
+        match self {
+            Allocator::System => unsafe { libc::malloc(size as libc::size_t) },
+            Allocator::Mimalloc => unsafe { mi_malloc(size) },
+            Allocator::Bump => bump_allocate(size),
+        }
+    }
+}
+
+static SELECTED: AtomicU8 = AtomicU8::new(Allocator::System as u8);
+
+/// Makes `allocate` use `allocator` from here on.
+pub fn set_allocator(allocator: Allocator) {
+    SELECTED.store(allocator as u8, Ordering::Relaxed);
+}
+
+/// Allocates with the allocator picked by `set_allocator`.
+pub fn allocate(size: usize) -> *mut c_void {
+    let allocator = match SELECTED.load(Ordering::Relaxed) {
+        1 => Allocator::Mimalloc,
+        2 => Allocator::Bump,
+        _ => Allocator::System,
+    };
+
+    allocator.allocate(size)
+}
+
+pub fn parse_allocator(name: &str) -> Result<Allocator, String> {
+    match name {
+        "system" => Ok(Allocator::System),
+        "mimalloc" => Ok(Allocator::Mimalloc),
+        "bump" => Ok(Allocator::Bump),
+        _ => Err(format!(
+            "unknown allocator `{}`, expected system, mimalloc or bump",
+            name
+        )),
+    }
+}
+
+const BUMP_CHUNK_SIZE: usize = 1 << 20;
+const BUMP_ALIGNMENT: usize = 16;
+
+/// The next free address of the current chunk and its end
+static BUMP_CHUNK: Mutex<(usize, usize)> = Mutex::new((0, 0));
+
+fn bump_allocate(size: usize) -> *mut c_void {
+    let size = (size.max(1) + BUMP_ALIGNMENT - 1) & !(BUMP_ALIGNMENT - 1);
+
+    // Large allocations would waste most of a chunk
+    if size > BUMP_CHUNK_SIZE / 4 {
+        return unsafe { libc::malloc(size as libc::size_t) };
+    }
+
+    let mut chunk = BUMP_CHUNK.lock().unwrap();
+
+    if chunk.1 - chunk.0 < size {
+        // malloc aligns to 16 bytes, so every allocation in the chunk is too
+        let start = unsafe { libc::malloc(BUMP_CHUNK_SIZE as libc::size_t) } as usize;
+
+        if start == 0 {
+            return null_mut();
+        }
+
+        *chunk = (start, start + BUMP_CHUNK_SIZE);
+    }
+
+    let ptr = chunk.0;
+    chunk.0 += size;
+
+    ptr as *mut c_void
+}
@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+
+use crate::parser::{AssignLocalVar, Node};
+
+/// Visits the nodes of an AST. `visit_node` is called with every node, and
+/// walks the nodes below it with `walk_node`. An implementation that
+/// overrides it calls `walk_node` itself to keep going, or doesn't to skip
+/// the nodes below.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node);
+    }
+}
+
+/// A `Visitor` that can change the nodes it visits, for passes that rewrite
+/// the AST.
+pub trait VisitorMut {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+    }
+}
+
+/// Generates `walk_node` and `walk_node_mut` from the description of the
+/// nodes right below each kind of node, in source order: `one` for a single
+/// child and `all` for a list of them. Neither walk has a catch-all arm, so
+/// a `Node` variant added without a line here doesn't compile, and the two
+/// walks can't disagree about what's below a node.
+macro_rules! walks {
+    ($($variant:ident $(($node:tt))? => [$($kind:ident $($field:ident).+),*],)*) => {
+        /// Calls `visitor` with each node right below `node`, in source order.
+        pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+            match node {
+                $(Node::$variant $(($node))? => {
+                    $(walks!(@$kind visitor, visit_node, walk_all, &$($field).+);)*
+                })*
+            }
+        }
+
+        /// `walk_node` for a `VisitorMut`.
+        pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) {
+            match node {
+                $(Node::$variant $(($node))? => {
+                    $(walks!(@$kind visitor, visit_node_mut, walk_all_mut, &mut $($field).+);)*
+                })*
+            }
+        }
+    };
+    (@one $visitor:ident, $visit:ident, $walk_all:ident, $child:expr) => {
+        $visitor.$visit($child)
+    };
+    (@all $visitor:ident, $visit:ident, $walk_all:ident, $children:expr) => {
+        $walk_all($visitor, $children)
+    };
+}
+
+walks! {
+    Access(node) => [one node.receiver, one node.message],
+    Array(node) => [all node.items],
+    AssignAttribute(node) => [one node.value],
+    AssignAttributeAccess(node) => [
+        one node.access.receiver,
+        one node.access.message,
+        one node.value
+    ],
+    AssignConstant(node) => [one node.value],
+    AssignLocalVar(node) => [one node.value],
+    Attribute(_) => [],
+    Binary(node) => [one node.left, one node.right],
+    Bool(_) => [],
+    Break => [],
+    BuildStruct(node) => [all node.args],
+    Call(node) => [all node.args],
+    Class(_) => [],
+    Const(_) => [],
+    Def(node) => [all node.body],
+    DefE(_) => [],
+    Error(_) => [],
+    Float(_) => [],
+    FnRef(_) => [],
+    If(node) => [one node.condition, all node.then_body, all node.else_body],
+    Impl(node) => [all node.body],
+    Int(_) => [],
+    LocalVar(_) => [],
+    Loop(node) => [all node.body],
+    Module(node) => [all node.methods],
+    Next => [],
+    Nil(_) => [],
+    Ret(node) => [one node.value],
+    SelfRef(_) => [],
+    Send(node) => [one node.receiver, one node.message],
+    StringLiteral(_) => [],
+    Struct(_) => [],
+    Trait(node) => [all node.body],
+    While(node) => [one node.condition, all node.body],
+}
+
+fn walk_all<V: Visitor + ?Sized>(visitor: &mut V, nodes: &[Node]) {
+    for node in nodes {
+        visitor.visit_node(node);
+    }
+}
+
+fn walk_all_mut<V: VisitorMut + ?Sized>(visitor: &mut V, nodes: &mut [Node]) {
+    for node in nodes {
+        visitor.visit_node_mut(node);
+    }
+}
+
+struct VisitClosure<'a>(&'a mut dyn FnMut(&Node));
+
+impl Visitor for VisitClosure<'_> {
+    fn visit_node(&mut self, node: &Node) {
+        (self.0)(node);
+        walk_node(self, node);
+    }
+}
+
+/// Calls `visit` with `node` and every node below it, parents first.
+pub fn visit_nodes(node: &Node, visit: &mut dyn FnMut(&Node)) {
+    VisitClosure(visit).visit_node(node);
+}
+
+/// Whether evaluating `node` might do more than give its value, by calling
+/// something or assigning. Evaluation order is only observable through
+/// these, so the nodes without can be evaluated in any order.
+pub fn has_side_effects(node: &Node) -> bool {
+    let mut found = false;
+
+    visit_nodes(node, &mut |node| {
+        found |= matches!(
+            node,
+            Node::Call(_)
+                | Node::Send(_)
+                | Node::AssignAttribute(_)
+                | Node::AssignAttributeAccess(_)
+                | Node::AssignConstant(_)
+                | Node::AssignLocalVar(_)
+        );
+    });
+
+    found
+}
+
+/// The first assignment of each local in `body` that's first assigned in a
+/// branch of an `if` or in a loop body, rather than at the top level. The
+/// semantic analyzer lets the def read these after the branch when every
+/// path assigns them, so backends whose locals are scoped to the block
+/// they're declared in declare them at the top of the def.
+pub fn branch_locals(body: &[Node]) -> Vec<&AssignLocalVar> {
+    let mut assigned = HashSet::new();
+    let mut locals = vec![];
+
+    find_branch_locals(body, true, &mut assigned, &mut locals);
+
+    locals
+}
+
+fn find_branch_locals<'a>(
+    body: &'a [Node],
+    top_level: bool,
+    assigned: &mut HashSet<&'a str>,
+    locals: &mut Vec<&'a AssignLocalVar>,
+) {
+    for node in body {
+        match node {
+            Node::AssignLocalVar(assignment) => {
+                let first = assigned.insert(assignment.name.as_str());
+
+                if first && !top_level {
+                    locals.push(assignment);
+                }
+            }
+            Node::If(if_node) => {
+                find_branch_locals(&if_node.then_body, false, assigned, locals);
+                find_branch_locals(&if_node.else_body, false, assigned, locals);
+            }
+            Node::Loop(loop_node) => find_branch_locals(&loop_node.body, false, assigned, locals),
+            Node::While(while_node) => {
+                find_branch_locals(&while_node.body, false, assigned, locals)
+            }
+            _ => {}
+        }
+    }
+}
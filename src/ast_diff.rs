@@ -0,0 +1,268 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::ast::{walk_node, Visitor};
+use crate::parser::Node;
+
+/// The nodes that differ between two parses of a program, by id.
+///
+/// A top level item's id is its kind and name, like `def Dog.legs` or
+/// `class Dog`, so it stays the same across edits that don't rename it. A
+/// node in an item adds its index among its parent's children and its kind
+/// to its parent's id, like `def two/0:Ret/0:Int` for the value `two`
+/// returns.
+///
+/// Nodes are compared by structure, so changes to whitespace and comments
+/// don't show up. Each change is reported at the smallest subtree that has
+/// it: editing a literal reports the literal rather than the def it's in,
+/// and a statement added to a body reports only that statement.
+#[derive(Debug, Default, PartialEq)]
+pub struct AstDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl AstDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub fn diff_modules(old: &Node, new: &Node) -> AstDiff {
+    let old_items = items(old);
+    let new_items = items(new);
+
+    let mut diff = AstDiff::default();
+
+    for (id, new_item) in &new_items {
+        match old_items.get(id) {
+            Some(old_item) => diff_nodes(id, id, old_item, new_item, &mut diff),
+            None => diff.added.push(id.clone()),
+        }
+    }
+
+    for id in old_items.keys() {
+        if !new_items.contains_key(id) {
+            diff.removed.push(id.clone());
+        }
+    }
+
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+
+    diff
+}
+
+/// The stable id of a top level item, or None for nodes that aren't items.
+pub fn item_id(node: &Node) -> Option<String> {
+    match node {
+        Node::AssignConstant(node) => Some(format!("const {}", node.name)),
+        Node::Class(node) => Some(format!("class {}", node.name)),
+        Node::Def(node) => Some(format!("def {}", node.prototype.name)),
+        Node::DefE(node) => Some(format!("def_e {}", node.prototype.name)),
+        Node::Impl(node) => Some(format!("impl {}", node.name)),
+        Node::Struct(node) => Some(format!("struct {}", node.name)),
+        Node::Trait(node) => Some(format!("trait {}", node.name)),
+        _ => None,
+    }
+}
+
+/// A node and the nodes below it, compared by their debug output. The AST
+/// holds no source positions, so the output only changes when the structure
+/// does.
+struct Subtree {
+    text: String,
+    /// The debug output without the children's, which only differs between
+    /// two nodes when a field of their own does, like the name a local is
+    /// assigned to or the operator of a binary
+    fields: String,
+    children: Vec<Subtree>,
+}
+
+impl Subtree {
+    fn new(node: &Node) -> Self {
+        let text = format!("{:?}", node);
+        let mut children = Children(vec![]);
+        walk_node(&mut children, node);
+
+        // Longer children are taken out first, so one that's part of a
+        // sibling is taken out on its own, and a list of them is taken out
+        // whatever its length
+        let mut texts: Vec<&str> = children.0.iter().map(|child| child.text.as_str()).collect();
+        texts.sort_by_key(|text| Reverse(text.len()));
+
+        let mut fields = texts
+            .into_iter()
+            .fold(text.clone(), |text, child| text.replacen(child, "\0", 1));
+
+        while fields.contains("\0, \0") {
+            fields = fields.replace("\0, \0", "\0");
+        }
+
+        Subtree {
+            fields: fields.replace('\0', ""),
+            text,
+            children: children.0,
+        }
+    }
+
+    /// The variant of the node, like `Ret`.
+    fn kind(&self) -> &str {
+        self.text.split('(').next().unwrap_or_default()
+    }
+}
+
+/// The subtrees right below a node, in source order.
+struct Children(Vec<Subtree>);
+
+impl Visitor for Children {
+    fn visit_node(&mut self, node: &Node) {
+        self.0.push(Subtree::new(node));
+    }
+}
+
+fn items(module: &Node) -> HashMap<String, Subtree> {
+    let items = match module {
+        Node::Module(module) => &module.methods,
+        _ => return HashMap::new(),
+    };
+
+    items
+        .iter()
+        .filter_map(|node| Some((item_id(node)?, Subtree::new(node))))
+        .collect()
+}
+
+/// Adds the differences between `old` and `new`, the same node in two
+/// parses, with the ids `old_id` and `new_id`.
+fn diff_nodes(old_id: &str, new_id: &str, old: &Subtree, new: &Subtree, diff: &mut AstDiff) {
+    if old.text == new.text {
+        return;
+    }
+
+    // A node replaced by one of another kind
+    if old.kind() != new.kind() {
+        diff.removed.push(old_id.to_string());
+        diff.added.push(new_id.to_string());
+        return;
+    }
+
+    if old.fields != new.fields {
+        diff.changed.push(new_id.to_string());
+        return;
+    }
+
+    let ids = (old_id, new_id);
+
+    diff_children(ids, (&old.children, 0), (&new.children, 0), &PAIRINGS, diff);
+}
+
+/// How the children of a node in two parses are paired, from the closest
+/// pairs to the loosest: the ones that are the same, the ones whose own
+/// fields are, like two assignments to the same local, and the ones of the
+/// same kind, like two literals.
+const PAIRINGS: [fn(&Subtree, &Subtree) -> bool; 3] = [
+    |old, new| old.text == new.text,
+    |old, new| old.kind() == new.kind() && old.fields == new.fields,
+    |old, new| old.kind() == new.kind(),
+];
+
+/// Adds the differences between the children `old` and `new` of the nodes
+/// with `ids`, each with the index of its first child. The pairs found with
+/// the first of `pairings` are diffed, and the children between two of them
+/// are paired with the rest. Those left unpaired were added or removed.
+fn diff_children(
+    ids: (&str, &str),
+    (old, old_offset): (&[Subtree], usize),
+    (new, new_offset): (&[Subtree], usize),
+    pairings: &[fn(&Subtree, &Subtree) -> bool],
+    diff: &mut AstDiff,
+) {
+    let (old_id, new_id) = ids;
+
+    let (pairs, looser) = match pairings.split_first() {
+        Some((pairs, looser)) => (pairs, looser),
+        None => {
+            for (index, child) in old.iter().enumerate() {
+                diff.removed
+                    .push(child_id(old_id, old_offset + index, child));
+            }
+
+            for (index, child) in new.iter().enumerate() {
+                diff.added.push(child_id(new_id, new_offset + index, child));
+            }
+
+            return;
+        }
+    };
+
+    let (mut old_start, mut new_start) = (0, 0);
+    let paired = common_subsequence(old, new, *pairs);
+
+    for (old_end, new_end) in paired.into_iter().chain([(old.len(), new.len())]) {
+        diff_children(
+            ids,
+            (&old[old_start..old_end], old_offset + old_start),
+            (&new[new_start..new_end], new_offset + new_start),
+            looser,
+            diff,
+        );
+
+        if let (Some(old_child), Some(new_child)) = (old.get(old_end), new.get(new_end)) {
+            diff_nodes(
+                &child_id(old_id, old_offset + old_end, old_child),
+                &child_id(new_id, new_offset + new_end, new_child),
+                old_child,
+                new_child,
+                diff,
+            );
+        }
+
+        old_start = old_end + 1;
+        new_start = new_end + 1;
+    }
+}
+
+/// The indexes of the children of `old` and `new` that are `paired`, as many
+/// as can be kept in order: their longest common subsequence.
+fn common_subsequence(
+    old: &[Subtree],
+    new: &[Subtree],
+    paired: fn(&Subtree, &Subtree) -> bool,
+) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if paired(&old[i], &new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut common = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() && j < new.len() {
+        if paired(&old[i], &new[j]) {
+            common.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    common
+}
+
+fn child_id(parent_id: &str, index: usize, child: &Subtree) -> String {
+    format!("{}/{}:{}", parent_id, index, child.kind())
+}
@@ -0,0 +1,153 @@
+use std::ffi::{CStr, CString};
+
+use crate::bytecode::emit_bytecode;
+use crate::c_backend::emit_c;
+use crate::js_backend::emit_js;
+use crate::parser::ParserResult;
+
+/// The version of the `Backend` interface libraries are built against,
+/// bumped whenever the trait or the typed AST it's given changes.
+pub const BACKEND_ABI: u32 = 1;
+
+/// A target that checked programs are compiled to, selected with
+/// `--emit=<name>`.
+///
+/// A backend is given the program after analysis, with every node typed,
+/// defaults and named arguments filled in, and the builtins lowered to the
+/// runtime functions they call, as `--emit=c` and `--emit=js` are. It returns
+/// the artifact, or what it doesn't support, like the builtin ones do.
+///
+/// Backends are registered with `CompileOptions::backends`, like lints. The
+/// compiler's `--backend=<path>` loads one from a library with
+/// `load_backend`, which must export:
+///
+/// ```text
+/// #[no_mangle]
+/// pub extern "C" fn pajama_backend_abi() -> u32 {
+///     pajama::backend::BACKEND_ABI
+/// }
+///
+/// #[no_mangle]
+/// pub extern "C" fn pajama_backend() -> *mut Box<dyn Backend> {
+///     Box::into_raw(Box::new(Box::new(Bytecode)))
+/// }
+/// ```
+///
+/// Trait objects have no stable layout, so a library has to be built with
+/// the same compiler and `pajama` as the compiler loading it; the version is
+/// checked so one built against another interface is refused rather than
+/// called.
+pub trait Backend {
+    /// The name `--emit` selects the backend by
+    fn name(&self) -> &str;
+
+    fn emit(&self, result: &ParserResult) -> Result<Vec<u8>, String>;
+}
+
+/// C99, see `c_backend::emit_c`
+pub struct CBackend;
+
+impl Backend for CBackend {
+    fn name(&self) -> &str {
+        "c"
+    }
+
+    fn emit(&self, result: &ParserResult) -> Result<Vec<u8>, String> {
+        emit_c(result).map(String::into_bytes)
+    }
+}
+
+/// JavaScript, see `js_backend::emit_js`
+pub struct JsBackend;
+
+impl Backend for JsBackend {
+    fn name(&self) -> &str {
+        "js"
+    }
+
+    fn emit(&self, result: &ParserResult) -> Result<Vec<u8>, String> {
+        emit_js(result).map(String::into_bytes)
+    }
+}
+
+/// A .nlb file for the VM, see `bytecode::emit_bytecode`
+pub struct BytecodeBackend;
+
+impl Backend for BytecodeBackend {
+    fn name(&self) -> &str {
+        "bytecode"
+    }
+
+    fn emit(&self, result: &ParserResult) -> Result<Vec<u8>, String> {
+        emit_bytecode(result).map(|program| program.encode())
+    }
+}
+
+/// The backends built into the compiler
+const BUILTIN: &[&dyn Backend] = &[&CBackend, &JsBackend, &BytecodeBackend];
+
+/// The backend `--emit=<name>` selects: the last of `backends` named `name`,
+/// so a registered backend can take the place of a builtin one, or else the
+/// builtin one.
+pub fn find_backend<'a>(backends: &'a [Box<dyn Backend>], name: &str) -> Option<&'a dyn Backend> {
+    backends
+        .iter()
+        .rev()
+        .map(|backend| backend.as_ref())
+        .chain(BUILTIN.iter().copied())
+        .find(|backend| backend.name() == name)
+}
+
+/// Loads the backend exported by the library at `path`. The library stays
+/// loaded for as long as the compiler runs, since the backend's code is in
+/// it.
+pub fn load_backend(path: &str) -> Result<Box<dyn Backend>, String> {
+    let c_path = CString::new(path).map_err(|_| format!("{}: not a valid path", path))?;
+    let library = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+
+    if library.is_null() {
+        return Err(format!("{}: {}", path, dl_error()));
+    }
+
+    let symbol = |name: &CStr| {
+        let address = unsafe { libc::dlsym(library, name.as_ptr()) };
+
+        if address.is_null() {
+            return Err(format!(
+                "{}: not a backend, it doesn't export `{}`",
+                path,
+                name.to_string_lossy()
+            ));
+        }
+
+        Ok(address)
+    };
+
+    let abi: extern "C" fn() -> u32 =
+        unsafe { std::mem::transmute(symbol(c"pajama_backend_abi")?) };
+    let abi = abi();
+
+    if abi != BACKEND_ABI {
+        return Err(format!(
+            "{}: built for backend interface {}, this compiler has {}",
+            path, abi, BACKEND_ABI
+        ));
+    }
+
+    let create: extern "C" fn() -> *mut Box<dyn Backend> =
+        unsafe { std::mem::transmute(symbol(c"pajama_backend")?) };
+
+    Ok(*unsafe { Box::from_raw(create()) })
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+
+    if error.is_null() {
+        return "couldn't load the library".to_string();
+    }
+
+    unsafe { CStr::from_ptr(error) }
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::optimization::OptLevel;
+
+/// The section of an executable its build info is linked into, read back
+/// by `pajama inspect-binary`
+pub const SECTION: &str = "__nilla_build_info";
+
+/// What built an executable, kept in it to tell deployed artifacts apart.
+#[derive(Debug, PartialEq)]
+pub struct BuildInfo {
+    /// The version of the compiler
+    pub version: String,
+    /// The architecture and OS the executable is for, like `x86_64-linux`
+    pub target: String,
+    pub opt_level: String,
+    /// When it was built, in seconds since the Unix epoch, unless built with
+    /// `--reproducible`
+    pub timestamp: Option<u64>,
+}
+
+impl BuildInfo {
+    /// The build info of an executable built now for the host, which is the
+    /// only target executables are built for.
+    pub fn new(opt_level: OptLevel, reproducible: bool) -> BuildInfo {
+        let timestamp = if reproducible {
+            None
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|elapsed| elapsed.as_secs())
+        };
+
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+            opt_level: format!("{:?}", opt_level),
+            timestamp,
+        }
+    }
+
+    /// `key=value` lines, as they're stored in the section
+    pub fn encode(&self) -> String {
+        let mut encoded = format!(
+            "version={}\ntarget={}\nopt_level={}\n",
+            self.version, self.target, self.opt_level
+        );
+
+        if let Some(timestamp) = self.timestamp {
+            encoded.push_str(&format!("timestamp={}\n", timestamp));
+        }
+
+        encoded
+    }
+
+    pub fn decode(encoded: &str) -> Result<BuildInfo, String> {
+        let value = |key: &str| {
+            encoded
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        };
+        let required =
+            |key: &str| value(key).ok_or_else(|| format!("the build info has no `{}`", key));
+
+        let timestamp = match value("timestamp") {
+            Some(timestamp) => Some(
+                timestamp
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't a timestamp", timestamp))?,
+            ),
+            None => None,
+        };
+
+        Ok(BuildInfo {
+            version: required("version")?.to_string(),
+            target: required("target")?.to_string(),
+            opt_level: required("opt_level")?.to_string(),
+            timestamp,
+        })
+    }
+
+    /// A C file defining the section, compiled and linked in with the
+    /// program's object file by `cc`
+    pub fn to_c_source(&self) -> String {
+        let escaped = self.encode().replace('\\', "\\\\").replace('"', "\\\"");
+
+        format!(
+            "__attribute__((used, section(\"{}\")))\nstatic const char build_info[] = \"{}\";\n",
+            SECTION,
+            escaped.replace('\n', "\\n")
+        )
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "compiler: pajama {}", self.version)?;
+        writeln!(f, "target: {}", self.target)?;
+        writeln!(f, "opt level: -{}", self.opt_level)?;
+
+        match self.timestamp {
+            Some(timestamp) => writeln!(f, "built at: {} (Unix time)", timestamp),
+            None => writeln!(f, "built at: not recorded, built with --reproducible"),
+        }
+    }
+}
+
+/// Finds the build info section of `binary`, a 64-bit little-endian ELF
+/// executable or object file.
+pub fn read_build_info(binary: &[u8]) -> Result<BuildInfo, String> {
+    if !binary.starts_with(b"\x7fELF") {
+        return Err("not an ELF binary".to_string());
+    }
+
+    if binary.get(4..6) != Some(&[2, 1]) {
+        return Err("only 64-bit little-endian binaries can be inspected".to_string());
+    }
+
+    let cut_short = || "the binary is cut short".to_string();
+    let read = |offset: usize, len: usize| -> Result<u64, String> {
+        let bytes = binary
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(cut_short)?;
+
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u64))
+    };
+
+    let section_headers = read(0x28, 8)? as usize;
+    let header_size = read(0x3a, 2)? as usize;
+    let sections = read(0x3c, 2)? as usize;
+    let names_section = read(0x3e, 2)? as usize;
+
+    // The name, offset and size of a section
+    let section = |index: usize| -> Result<(usize, usize, usize), String> {
+        // Offsets come from the file, so they're added without overflowing
+        // and left for `get` to refuse
+        let header = section_headers.saturating_add(index.saturating_mul(header_size));
+
+        Ok((
+            read(header, 4)? as usize,
+            read(header.saturating_add(0x18), 8)? as usize,
+            read(header.saturating_add(0x20), 8)? as usize,
+        ))
+    };
+
+    let (_, names, _) = section(names_section)?;
+
+    for index in 0..sections {
+        let (name, offset, size) = section(index)?;
+        let name = binary
+            .get(names.saturating_add(name)..)
+            .ok_or_else(cut_short)?;
+
+        if name.starts_with(SECTION.as_bytes()) && name.get(SECTION.len()) == Some(&0) {
+            let contents = binary
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(cut_short)?;
+            let contents = String::from_utf8_lossy(contents);
+
+            return BuildInfo::decode(contents.trim_end_matches('\0'));
+        }
+    }
+
+    Err(format!(
+        "no {} section, the binary wasn't built by pajama --emit=exe",
+        SECTION
+    ))
+}
@@ -0,0 +1,691 @@
+use std::collections::HashMap;
+
+use crate::parser::{self, Node, ParserResult};
+
+/// The bytes a .nlb file starts with
+pub const MAGIC: &[u8; 4] = b"NLB\0";
+
+/// The version of the format, bumped whenever an op or the layout changes
+pub const VERSION: u8 = 1;
+
+/// The runtime functions the VM implements, by the name programs call them
+/// by. `Op::Native` refers to them by index, so new ones go at the end.
+pub const NATIVE_FNS: [&str; 11] = [
+    "pj_puts",
+    "pj_print",
+    "pj_int_to_s",
+    "pj_float_to_s",
+    "pj_bool_to_s",
+    "pj_str_concat",
+    "pj_int_to_f",
+    "pj_float_to_i",
+    "print_int",
+    "exit",
+    "pj_sandbox_denied",
+];
+
+/// An instruction of the VM, a stack machine. Ops pop their operands and
+/// push their result, and every call pushes a value, `Void` for functions
+/// without one, so a statement's value is always popped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Pushes the string at this index of `Program::strings`
+    Str(u32),
+    Void,
+    /// Pushes the local in this slot of the frame, the args come first
+    Load(u32),
+    Store(u32),
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Continues at this index of the function's code
+    Jump(u32),
+    /// Pops a Bool, and jumps when it's false
+    JumpIfFalse(u32),
+    /// Calls the function at this index of `Program::functions`, with its
+    /// args on the stack
+    Call(u32),
+    /// Calls the runtime function at this index of `NATIVE_FNS`
+    Native(u32),
+    /// Returns the value on the stack to the caller
+    Return,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub arity: u32,
+    /// How many slots the frame has, args included
+    pub locals: u32,
+    pub code: Vec<Op>,
+}
+
+/// A program compiled to bytecode, what a .nlb file holds.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    pub strings: Vec<String>,
+    pub functions: Vec<Function>,
+    /// The index of `main` in `functions`
+    pub main: u32,
+}
+
+/// Lowers a checked program to bytecode for `vm::run`, a format that starts
+/// fast without LLVM, for distributing programs as .nlb files.
+///
+/// Defs become functions and their locals numbered slots, and the runtime
+/// functions in `NATIVE_FNS` are implemented by the VM. Ints, Floats, Bools
+/// and Strs are supported, with arithmetic, comparisons, ifs, loops and
+/// calls; classes, arrays and the rest of the runtime aren't yet, and are
+/// reported rather than emitted wrong.
+pub fn emit_bytecode(result: &ParserResult) -> Result<Program, String> {
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => return Err("Expected a module to emit".to_string()),
+    };
+
+    let mut emitter = Emitter {
+        functions: HashMap::new(),
+        constants: HashMap::new(),
+        strings: vec![],
+    };
+    let mut defs = vec![];
+
+    for node in &module.methods {
+        match node {
+            Node::Def(def) if def.trait_name.is_empty() || !def.body.is_empty() => {
+                emitter
+                    .functions
+                    .insert(def.prototype.name.clone(), defs.len() as u32);
+                defs.push(def);
+            }
+            Node::Def(_) | Node::DefE(_) => {}
+            Node::AssignConstant(constant) => {
+                emitter
+                    .constants
+                    .insert(constant.name.clone(), constant.value.as_ref());
+            }
+            node => {
+                return Err(format!(
+                    "The bytecode backend can't emit a top level {}",
+                    node.kind()
+                ))
+            }
+        }
+    }
+
+    let main = defs
+        .iter()
+        .position(|def| def.main_fn)
+        .ok_or("The bytecode backend needs a main to run")?;
+
+    let mut functions = vec![];
+
+    for def in defs {
+        functions.push(emitter.function(def)?);
+    }
+
+    Ok(Program {
+        strings: emitter.strings,
+        functions,
+        main: main as u32,
+    })
+}
+
+struct Emitter<'a> {
+    functions: HashMap<String, u32>,
+    constants: HashMap<String, &'a Node>,
+    strings: Vec<String>,
+}
+
+struct FnCtx {
+    locals: HashMap<String, u32>,
+    code: Vec<Op>,
+    /// Where `next` jumps to in each enclosing loop, and the jumps `break`
+    /// left to patch once the loop's end is known
+    loops: Vec<(u32, Vec<usize>)>,
+}
+
+impl FnCtx {
+    fn slot(&mut self, name: &str) -> u32 {
+        let next = self.locals.len() as u32;
+
+        *self.locals.entry(name.to_string()).or_insert(next)
+    }
+
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    /// Emits a jump to be pointed somewhere with `patch` later.
+    fn jump(&mut self, op: fn(u32) -> Op) -> usize {
+        self.code.push(op(0));
+        self.code.len() - 1
+    }
+
+    fn patch(&mut self, index: usize) {
+        let target = self.here();
+
+        self.code[index] = match self.code[index] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            op => op,
+        };
+    }
+}
+
+impl Emitter<'_> {
+    fn function(&mut self, def: &parser::Def) -> Result<Function, String> {
+        let mut ctx = FnCtx {
+            locals: HashMap::new(),
+            code: vec![],
+            loops: vec![],
+        };
+
+        for arg in &def.prototype.args {
+            ctx.slot(&arg.name);
+        }
+
+        for node in &def.body {
+            self.statement(node, &mut ctx)?;
+        }
+
+        // Unreachable after a `ret`
+        ctx.code.extend([Op::Void, Op::Return]);
+
+        Ok(Function {
+            name: def.prototype.name.clone(),
+            arity: def.prototype.args.len() as u32,
+            locals: ctx.locals.len() as u32,
+            code: ctx.code,
+        })
+    }
+
+    fn statement(&mut self, node: &Node, ctx: &mut FnCtx) -> Result<(), String> {
+        match node {
+            Node::AssignLocalVar(node) => {
+                self.expr(&node.value, ctx)?;
+                let slot = ctx.slot(&node.name);
+                ctx.code.push(Op::Store(slot));
+            }
+            Node::Ret(node) => {
+                self.expr(&node.value, ctx)?;
+                ctx.code.push(Op::Return);
+            }
+            Node::Loop(node) => {
+                let start = ctx.here();
+
+                ctx.loops.push((start, vec![]));
+                self.body(&node.body, ctx)?;
+                ctx.code.push(Op::Jump(start));
+                self.end_loop(ctx);
+            }
+            Node::While(node) => {
+                let start = ctx.here();
+
+                self.expr(&node.condition, ctx)?;
+                let exit = ctx.jump(Op::JumpIfFalse);
+
+                ctx.loops.push((start, vec![exit]));
+                self.body(&node.body, ctx)?;
+                ctx.code.push(Op::Jump(start));
+                self.end_loop(ctx);
+            }
+            Node::Break => {
+                let jump = ctx.jump(Op::Jump);
+
+                match ctx.loops.last_mut() {
+                    Some((_, breaks)) => breaks.push(jump),
+                    None => return Err("`break` can only be used in a loop".to_string()),
+                }
+            }
+            Node::Next => match ctx.loops.last() {
+                Some((start, _)) => ctx.code.push(Op::Jump(*start)),
+                None => return Err("`next` can only be used in a loop".to_string()),
+            },
+            Node::If(node) => {
+                self.expr(&node.condition, ctx)?;
+                let else_jump = ctx.jump(Op::JumpIfFalse);
+
+                self.body(&node.then_body, ctx)?;
+
+                if node.else_body.is_empty() {
+                    ctx.patch(else_jump);
+                } else {
+                    let end_jump = ctx.jump(Op::Jump);
+
+                    ctx.patch(else_jump);
+                    self.body(&node.else_body, ctx)?;
+                    ctx.patch(end_jump);
+                }
+            }
+            node => {
+                self.expr(node, ctx)?;
+                ctx.code.push(Op::Pop);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn body(&mut self, body: &[Node], ctx: &mut FnCtx) -> Result<(), String> {
+        for node in body {
+            self.statement(node, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn end_loop(&mut self, ctx: &mut FnCtx) {
+        if let Some((_, breaks)) = ctx.loops.pop() {
+            for jump in breaks {
+                ctx.patch(jump);
+            }
+        }
+    }
+
+    fn expr(&mut self, node: &Node, ctx: &mut FnCtx) -> Result<(), String> {
+        match node {
+            // Literals are 64 bit patterns, like in compiled code
+            Node::Int(node) => ctx.code.push(Op::Int(node.value as i64)),
+            Node::Float(node) => ctx.code.push(Op::Float(node.value)),
+            Node::Bool(node) => ctx.code.push(Op::Bool(node.value)),
+            Node::StringLiteral(node) => {
+                let index = match self.strings.iter().position(|value| value == &node.value) {
+                    Some(index) => index,
+                    None => {
+                        self.strings.push(node.value.clone());
+                        self.strings.len() - 1
+                    }
+                };
+
+                ctx.code.push(Op::Str(index as u32));
+            }
+            Node::LocalVar(node) => match ctx.locals.get(&node.name) {
+                Some(slot) => ctx.code.push(Op::Load(*slot)),
+                None => return Err(format!("`{}` is read before it's assigned", node.name)),
+            },
+            Node::Const(node) => match self.constants.get(&node.name) {
+                Some(value) => self.expr(value, ctx)?,
+                None => return Err(format!("Unknown constant `{}`", node.name)),
+            },
+            Node::Binary(node) if node.op == "&&" || node.op == "||" => {
+                // Only evaluates the right operand when it decides the value
+                self.expr(&node.left, ctx)?;
+                let right_jump = ctx.jump(Op::JumpIfFalse);
+
+                if node.op == "&&" {
+                    self.expr(&node.right, ctx)?;
+                    let end_jump = ctx.jump(Op::Jump);
+                    ctx.patch(right_jump);
+                    ctx.code.push(Op::Bool(false));
+                    ctx.patch(end_jump);
+                } else {
+                    ctx.code.push(Op::Bool(true));
+                    let end_jump = ctx.jump(Op::Jump);
+                    ctx.patch(right_jump);
+                    self.expr(&node.right, ctx)?;
+                    ctx.patch(end_jump);
+                }
+            }
+            Node::Binary(node) => {
+                let op = binary_op(&node.op).ok_or_else(|| {
+                    format!("The bytecode backend doesn't support `{}` yet", node.op)
+                })?;
+
+                self.expr(&node.left, ctx)?;
+                self.expr(&node.right, ctx)?;
+                ctx.code.push(op);
+            }
+            Node::Call(node) => {
+                let op = match self.functions.get(&node.fn_name) {
+                    Some(index) => Op::Call(*index),
+                    None => match NATIVE_FNS.iter().position(|name| *name == node.fn_name) {
+                        Some(index) => Op::Native(index as u32),
+                        None => {
+                            return Err(format!(
+                                "The bytecode backend doesn't support {} yet",
+                                node.fn_name
+                            ))
+                        }
+                    },
+                };
+
+                for arg in &node.args {
+                    self.expr(arg, ctx)?;
+                }
+
+                ctx.code.push(op);
+            }
+            Node::If(node) => {
+                let (then_value, else_value) =
+                    match (node.then_body.split_last(), node.else_body.split_last()) {
+                        (Some(then_value), Some(else_value)) => (then_value, else_value),
+                        _ => return Err("An if used as a value needs both branches".to_string()),
+                    };
+
+                self.expr(&node.condition, ctx)?;
+                let else_jump = ctx.jump(Op::JumpIfFalse);
+
+                self.body(then_value.1, ctx)?;
+                self.expr(then_value.0, ctx)?;
+                let end_jump = ctx.jump(Op::Jump);
+
+                ctx.patch(else_jump);
+                self.body(else_value.1, ctx)?;
+                self.expr(else_value.0, ctx)?;
+                ctx.patch(end_jump);
+            }
+            node => {
+                return Err(format!(
+                    "The bytecode backend doesn't support {} expressions",
+                    node.kind()
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn binary_op(op: &str) -> Option<Op> {
+    Some(match op {
+        "+" => Op::Add,
+        "-" => Op::Sub,
+        "*" => Op::Mul,
+        "/" => Op::Div,
+        "&" => Op::BitAnd,
+        "|" => Op::BitOr,
+        "^" => Op::BitXor,
+        "<<" => Op::Shl,
+        ">>" => Op::Shr,
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        _ => return None,
+    })
+}
+
+impl Program {
+    /// The .nlb file of the program: `MAGIC`, `VERSION`, the strings, the
+    /// functions and the index of `main`. Counts, indexes and Ints are
+    /// LEB128 varints, Ints zigzag encoded, and Floats 8 little endian bytes,
+    /// so most ops take one or two bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        write_u64(&mut out, self.strings.len() as u64);
+
+        for string in &self.strings {
+            write_str(&mut out, string);
+        }
+
+        write_u64(&mut out, self.functions.len() as u64);
+
+        for function in &self.functions {
+            write_str(&mut out, &function.name);
+            write_u64(&mut out, function.arity as u64);
+            write_u64(&mut out, function.locals as u64);
+            write_u64(&mut out, function.code.len() as u64);
+
+            for op in &function.code {
+                encode_op(&mut out, op);
+            }
+        }
+
+        write_u64(&mut out, self.main as u64);
+
+        out
+    }
+
+    /// Reads a .nlb file written by `encode`, checking that every index in
+    /// it is in range, so the VM can't be made to read out of bounds.
+    pub fn decode(bytes: &[u8]) -> Result<Program, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a .nlb file".to_string());
+        }
+
+        let version = reader.u8()?;
+
+        if version != VERSION {
+            return Err(format!(
+                "written for bytecode version {}, this VM runs version {}",
+                version, VERSION
+            ));
+        }
+
+        let strings = (0..reader.u32()?)
+            .map(|_| reader.string())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut functions = vec![];
+
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let arity = reader.u32()?;
+            let locals = reader.u32()?;
+            let code = (0..reader.u32()?)
+                .map(|_| reader.op())
+                .collect::<Result<Vec<_>, _>>()?;
+
+            functions.push(Function {
+                name,
+                arity,
+                locals,
+                code,
+            });
+        }
+
+        let main = reader.u32()?;
+
+        if reader.pos != bytes.len() {
+            return Err("unexpected bytes after the program".to_string());
+        }
+
+        let program = Program {
+            strings,
+            functions,
+            main,
+        };
+
+        program.validate()?;
+
+        Ok(program)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let functions = self.functions.len() as u32;
+
+        if self.main >= functions {
+            return Err("main is out of range".to_string());
+        }
+
+        for function in &self.functions {
+            let in_range = |op: &Op| match *op {
+                Op::Str(index) => index < self.strings.len() as u32,
+                Op::Load(slot) | Op::Store(slot) => slot < function.locals,
+                Op::Jump(target) | Op::JumpIfFalse(target) => target < function.code.len() as u32,
+                Op::Call(index) => index < functions,
+                Op::Native(index) => index < NATIVE_FNS.len() as u32,
+                _ => true,
+            };
+
+            if function.arity > function.locals || !function.code.iter().all(in_range) {
+                return Err(format!(
+                    "`{}` refers to something out of range",
+                    function.name
+                ));
+            }
+
+            if function.code.last() != Some(&Op::Return) {
+                return Err(format!("`{}` doesn't end with a return", function.name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_op(out: &mut Vec<u8>, op: &Op) {
+    let (opcode, operand) = match *op {
+        Op::Int(value) => (0, Some((value << 1 ^ value >> 63) as u64)),
+        Op::Float(value) => {
+            out.push(1);
+            out.extend(value.to_le_bytes());
+            return;
+        }
+        Op::Bool(value) => (2, Some(value as u64)),
+        Op::Str(index) => (3, Some(index as u64)),
+        Op::Void => (4, None),
+        Op::Load(slot) => (5, Some(slot as u64)),
+        Op::Store(slot) => (6, Some(slot as u64)),
+        Op::Pop => (7, None),
+        Op::Add => (8, None),
+        Op::Sub => (9, None),
+        Op::Mul => (10, None),
+        Op::Div => (11, None),
+        Op::BitAnd => (12, None),
+        Op::BitOr => (13, None),
+        Op::BitXor => (14, None),
+        Op::Shl => (15, None),
+        Op::Shr => (16, None),
+        Op::Eq => (17, None),
+        Op::Ne => (18, None),
+        Op::Lt => (19, None),
+        Op::Le => (20, None),
+        Op::Gt => (21, None),
+        Op::Ge => (22, None),
+        Op::Jump(target) => (23, Some(target as u64)),
+        Op::JumpIfFalse(target) => (24, Some(target as u64)),
+        Op::Call(index) => (25, Some(index as u64)),
+        Op::Native(index) => (26, Some(index as u64)),
+        Op::Return => (27, None),
+    };
+
+    out.push(opcode);
+
+    if let Some(operand) = operand {
+        write_u64(out, operand);
+    }
+}
+
+fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u64(out, value.len() as u64);
+    out.extend(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("the program is cut short")?;
+        let bytes = &self.bytes[self.pos..end];
+
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err("a number is too long".to_string())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        u32::try_from(self.u64()?).map_err(|_| "a count or index is too large".to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "a string isn't UTF-8".to_string())
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        Ok(match self.u8()? {
+            0 => {
+                let value = self.u64()?;
+                Op::Int((value >> 1) as i64 ^ -((value & 1) as i64))
+            }
+            1 => Op::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => Op::Bool(self.u64()? != 0),
+            3 => Op::Str(self.u32()?),
+            4 => Op::Void,
+            5 => Op::Load(self.u32()?),
+            6 => Op::Store(self.u32()?),
+            7 => Op::Pop,
+            8 => Op::Add,
+            9 => Op::Sub,
+            10 => Op::Mul,
+            11 => Op::Div,
+            12 => Op::BitAnd,
+            13 => Op::BitOr,
+            14 => Op::BitXor,
+            15 => Op::Shl,
+            16 => Op::Shr,
+            17 => Op::Eq,
+            18 => Op::Ne,
+            19 => Op::Lt,
+            20 => Op::Le,
+            21 => Op::Gt,
+            22 => Op::Ge,
+            23 => Op::Jump(self.u32()?),
+            24 => Op::JumpIfFalse(self.u32()?),
+            25 => Op::Call(self.u32()?),
+            26 => Op::Native(self.u32()?),
+            27 => Op::Return,
+            opcode => return Err(format!("unknown opcode {}", opcode)),
+        })
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::HashSet;
+
+use crate::ast::{branch_locals, has_side_effects};
+use crate::parser::{self, BaseType, Node, ParserResult};
+use crate::semantic_analyzer::{array_slot_kind, pajama_class_name};
+
+const C_KEYWORDS: &str = "auto break case char const continue default do double else enum \
+    extern float for goto if inline int long register restrict return short signed sizeof static \
+    struct switch typedef union unsigned void volatile while _Bool _Complex _Imaginary";
+
+/// Lowers a checked program to readable C99, for platforms without LLVM and
+/// for reading what a program compiles to.
+///
+/// Classes become structs passed by pointer, methods become functions named
+/// like `Dog_speak` that take the receiver as `self`, and `def_e`
+/// declarations, including the runtime functions the analyzer adds, become
+/// prototypes. The output is linked against the pajama runtime library.
+///
+/// Constructs the backend doesn't support yet are reported rather than
+/// emitted wrong.
+pub fn emit_c(result: &ParserResult) -> Result<String, String> {
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => return Err("Expected a module to emit".to_string()),
+    };
+
+    let emitter = CEmitter { result };
+    let mut out = String::new();
+
+    out.push_str("/* Generated by pajama --emit=c, link with the pajama runtime */\n\n");
+    out.push_str("#include <stdbool.h>\n#include <stdint.h>\n\n");
+
+    let mut structs: Vec<&parser::Struct> = result.index.struct_index.values().collect();
+    structs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut classes: Vec<&parser::Class> = result.index.class_index.values().collect();
+    classes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for name in structs
+        .iter()
+        .map(|node| &node.name)
+        .chain(classes.iter().map(|node| &node.name))
+    {
+        out.push_str(&format!("typedef struct {} {};\n", name, name));
+    }
+
+    // Structs are held by value, so they come before the classes using them
+    let attribute_lists = structs
+        .iter()
+        .map(|node| (&node.name, &node.attributes))
+        .chain(classes.iter().map(|node| (&node.name, &node.attributes)));
+
+    for (name, attributes) in attribute_lists {
+        out.push_str(&format!("\nstruct {} {{\n", name));
+
+        for attribute in attributes {
+            out.push_str(&format!(
+                "    {};\n",
+                emitter.declaration(&attribute.return_type, &attribute.name)
+            ));
+        }
+
+        out.push_str("};\n");
+    }
+
+    out.push('\n');
+
+    for node in &module.methods {
+        match node {
+            Node::DefE(def_e) => {
+                out.push_str(&format!("{};\n", emitter.signature(&def_e.prototype)));
+            }
+            Node::Def(def) if emitted(def) && !def.main_fn => {
+                out.push_str(&format!("{};\n", emitter.signature(&def.prototype)));
+            }
+            _ => {}
+        }
+    }
+
+    for node in &module.methods {
+        if let Node::AssignConstant(constant) = node {
+            out.push_str(&format!(
+                "\nstatic const {} = {};\n",
+                emitter.declaration(&constant.return_type, &constant.name),
+                emitter.expr(&constant.value)?
+            ));
+        }
+    }
+
+    for node in &module.methods {
+        match node {
+            Node::Def(def) if emitted(def) => {
+                out.push('\n');
+                out.push_str(&emitter.def(def)?);
+            }
+            Node::Def(_) | Node::DefE(_) | Node::AssignConstant(_) => {}
+            node => {
+                return Err(format!(
+                    "The C backend can't emit a top level {}",
+                    node.kind()
+                ))
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Bodyless trait defs only declare a method, like in codegen.
+fn emitted(def: &parser::Def) -> bool {
+    def.trait_name.is_empty() || !def.body.is_empty()
+}
+
+struct CEmitter<'a> {
+    result: &'a ParserResult,
+}
+
+struct FnCtx {
+    declared: HashSet<String>,
+    indent: usize,
+    out: String,
+}
+
+impl FnCtx {
+    fn line(&mut self, line: &str) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+impl<'a> CEmitter<'a> {
+    fn def(&self, def: &parser::Def) -> Result<String, String> {
+        let mut ctx = FnCtx {
+            declared: def
+                .prototype
+                .args
+                .iter()
+                .map(|arg| arg.name.clone())
+                .collect(),
+            indent: 1,
+            out: String::new(),
+        };
+
+        let signature = if def.main_fn {
+            "int main(void)".to_string()
+        } else {
+            self.signature(&def.prototype)
+        };
+
+        ctx.out.push_str(&format!("{} {{\n", signature));
+
+        // Declared up front, so they can be read after their branch
+        for assignment in branch_locals(&def.body) {
+            if !ctx.declared.insert(assignment.name.clone()) {
+                continue;
+            }
+
+            let line = match node_type(&assignment.value) {
+                Some(return_type) => {
+                    format!("{};", self.declaration(&return_type, &assignment.name))
+                }
+                None => return Err(format!("Could not infer the type of `{}`", assignment.name)),
+            };
+
+            ctx.line(&line);
+        }
+
+        for node in &def.body {
+            self.statement(node, &mut ctx)?;
+        }
+
+        // Constructors fill in the instance they're given and hand it back
+        let name = &def.prototype.name;
+
+        if name.ends_with(".new") || name.ends_with(".alloca") {
+            ctx.line("return self;");
+        }
+
+        ctx.out.push_str("}\n");
+
+        Ok(ctx.out)
+    }
+
+    fn signature(&self, prototype: &parser::Prototype) -> String {
+        let return_type = match &prototype.return_type {
+            Some(return_type) => self.c_type(return_type),
+            None => "void".to_string(),
+        };
+
+        let args: Vec<String> = prototype
+            .args
+            .iter()
+            .map(|arg| self.declaration(&arg.return_type, &arg.name))
+            .collect();
+
+        let args = if args.is_empty() {
+            "void".to_string()
+        } else {
+            args.join(", ")
+        };
+
+        let name = c_name(&prototype.name);
+
+        if return_type.ends_with('*') {
+            format!("{}{}({})", return_type, name, args)
+        } else {
+            format!("{} {}({})", return_type, name, args)
+        }
+    }
+
+    fn statement(&self, node: &Node, ctx: &mut FnCtx) -> Result<(), String> {
+        match node {
+            Node::AssignLocalVar(node) => {
+                let name = c_name(&node.name);
+
+                if ctx.declared.contains(&node.name) {
+                    let line = format!("{} = {};", name, self.expr(&node.value)?);
+                    ctx.line(&line);
+
+                    return Ok(());
+                }
+
+                let line = match node_type(&node.value) {
+                    Some(return_type) => format!(
+                        "{} = {};",
+                        self.declaration(&return_type, &node.name),
+                        self.expr(&node.value)?
+                    ),
+                    None => return Err(format!("Could not infer the type of `{}`", node.name)),
+                };
+
+                ctx.declared.insert(node.name.clone());
+                ctx.line(&line);
+            }
+            Node::AssignAttribute(node) => {
+                let line = format!(
+                    "self->{} = {};",
+                    c_name(&node.name),
+                    self.expr(&node.value)?
+                );
+                ctx.line(&line);
+            }
+            Node::AssignAttributeAccess(node) => {
+                let line = format!(
+                    "{} = {};",
+                    self.access(&node.access)?,
+                    self.expr(&node.value)?
+                );
+                ctx.line(&line);
+            }
+            Node::Ret(node) => {
+                let line = format!("return {};", self.expr(&node.value)?);
+                ctx.line(&line);
+            }
+            Node::Loop(node) => {
+                ctx.line("for (;;) {");
+                ctx.indent += 1;
+
+                for node in &node.body {
+                    self.statement(node, ctx)?;
+                }
+
+                ctx.indent -= 1;
+                ctx.line("}");
+            }
+            Node::While(node) => {
+                let line = format!("while ({}) {{", self.expr(&node.condition)?);
+                ctx.line(&line);
+                self.branch(&node.body, ctx)?;
+                ctx.line("}");
+            }
+            Node::Break => ctx.line("break;"),
+            Node::Next => ctx.line("continue;"),
+            Node::If(node) => {
+                let line = format!("if ({}) {{", self.expr(&node.condition)?);
+                ctx.line(&line);
+                self.branch(&node.then_body, ctx)?;
+
+                if !node.else_body.is_empty() {
+                    ctx.line("} else {");
+                    self.branch(&node.else_body, ctx)?;
+                }
+
+                ctx.line("}");
+            }
+            node => {
+                let line = format!("{};", self.expr(node)?);
+                ctx.line(&line);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The body of an `if` branch or a `while`. The locals first assigned in
+    /// it are already declared by `def`.
+    fn branch(&self, body: &[Node], ctx: &mut FnCtx) -> Result<(), String> {
+        ctx.indent += 1;
+
+        for node in body {
+            self.statement(node, ctx)?;
+        }
+
+        ctx.indent -= 1;
+
+        Ok(())
+    }
+
+    fn expr(&self, node: &Node) -> Result<String, String> {
+        match node {
+            Node::Access(node) => self.access(node),
+            Node::Array(node) => self.array(node),
+            Node::Binary(node) => {
+                // `&&` and `||` are sequenced in C too
+                if !node.is_logical() {
+                    in_order(&format!("`{}`", node.op), [&*node.left, &*node.right])?;
+                }
+
+                Ok(format!(
+                    "{} {} {}",
+                    self.operand(&node.left)?,
+                    node.op,
+                    self.operand(&node.right)?
+                ))
+            }
+            Node::Bool(node) => Ok(node.value.to_string()),
+            Node::BuildStruct(node) => {
+                in_order(&format!("`{}`", node.name), &node.args)?;
+
+                let args: Result<Vec<String>, String> =
+                    node.args.iter().map(|arg| self.expr(arg)).collect();
+
+                Ok(format!("({}){{{}}}", node.name, args?.join(", ")))
+            }
+            Node::Call(node) => {
+                in_order(&format!("`{}`", node.fn_name), &node.args)?;
+
+                let args = self.args(&node.fn_name, 0, &node.args)?;
+
+                Ok(format!("{}({})", c_name(&node.fn_name), args.join(", ")))
+            }
+            Node::Const(node) => Ok(c_name(&node.name)),
+            Node::Float(node) => Ok(format!("{:?}", node.value)),
+            Node::FnRef(node) => Ok(format!("(void *){}", c_name(&node.fn_name))),
+            Node::If(node) => match (node.then_body.as_slice(), node.else_body.as_slice()) {
+                ([then_value], [else_value]) => Ok(format!(
+                    "({} ? {} : {})",
+                    self.expr(&node.condition)?,
+                    self.expr(then_value)?,
+                    self.expr(else_value)?
+                )),
+                _ => Err(
+                    "The C backend only supports if expressions with one expression per branch"
+                        .to_string(),
+                ),
+            },
+            Node::Int(node) => Ok(int_literal(node.value)),
+            Node::LocalVar(node) => Ok(c_name(&node.name)),
+            Node::Nil(_) => Ok("NULL".to_string()),
+            Node::SelfRef(_) => Ok("self".to_string()),
+            Node::Send(node) => self.send(node),
+            Node::StringLiteral(node) => Ok(format!(
+                "&(Str){{(uint8_t *){}, {}, {}}}",
+                string_literal(&node.value),
+                node.value.len(),
+                node.value.len()
+            )),
+            node => Err(format!(
+                "The C backend doesn't support {} expressions",
+                node.kind()
+            )),
+        }
+    }
+
+    /// An array literal, built like compiled code does by pushing each item
+    /// onto a new runtime array.
+    fn array(&self, array: &parser::Array) -> Result<String, String> {
+        let kind = match array_slot_kind(&array.item_type) {
+            Some(kind) => kind,
+            None => {
+                return Err(format!(
+                    "Arrays can't hold {} yet",
+                    pajama_class_name(&array.item_type)
+                ))
+            }
+        };
+
+        in_order("an array", &array.items)?;
+
+        let mut value = format!("pj_array_new({})", array.items.len());
+
+        for item in &array.items {
+            value = format!("pj_array_push_{}({}, {})", kind, value, self.expr(item)?);
+        }
+
+        Ok(value)
+    }
+
+    /// An expression used as an operand, parenthesized when it's itself an
+    /// operation, since C's precedence differs from ours for bitwise operators.
+    fn operand(&self, node: &Node) -> Result<String, String> {
+        match node {
+            Node::Binary(_) => Ok(format!("({})", self.expr(node)?)),
+            node => self.expr(node),
+        }
+    }
+
+    fn access(&self, access: &parser::Access) -> Result<String, String> {
+        let name = match access.message.as_ref() {
+            Node::Attribute(attribute) => c_name(&attribute.name),
+            _ => return Err("Expected an attribute to access".to_string()),
+        };
+
+        // Struct values are held directly, class instances through a pointer
+        match node_type(&access.receiver) {
+            Some(BaseType::Struct(_)) => {
+                Ok(format!("{}.{}", self.operand(&access.receiver)?, name))
+            }
+            _ => Ok(format!("{}->{}", self.operand(&access.receiver)?, name)),
+        }
+    }
+
+    fn send(&self, send: &parser::Send) -> Result<String, String> {
+        let call = match send.message.as_ref() {
+            Node::Call(call) => call,
+            _ => return Err("Expected the message of a send to be a call".to_string()),
+        };
+
+        in_order(
+            &format!("`{}`", call.fn_name),
+            std::iter::once(send.receiver.as_ref()).chain(&call.args),
+        )?;
+
+        if let Node::Const(class) = send.receiver.as_ref() {
+            if call.fn_name.ends_with(".new") || call.fn_name.ends_with(".alloca") {
+                let mut args = vec![format!("&({}){{0}}", class.name)];
+                args.extend(self.args(&call.fn_name, 1, &call.args)?);
+
+                return Ok(format!("{}({})", c_name(&call.fn_name), args.join(", ")));
+            }
+        }
+
+        if call.fn_name == "Array.each" || call.fn_name == "Array.map" {
+            return Err(format!(
+                "The C backend doesn't support {} yet",
+                call.fn_name
+            ));
+        }
+
+        if call.fn_name == "fn_ref" {
+            if let Node::LocalVar(local_var) = send.receiver.as_ref() {
+                return Ok(format!("(void *){}", c_name(&local_var.name)));
+            }
+        }
+
+        let fn_name = self.resolve_fn_name(&call.fn_name);
+        let receiver = self.expr(&send.receiver)?;
+
+        let receiver = match self.prototype_arg_type(&fn_name, 0) {
+            Some(arg_type) => self.cast(receiver, node_type(&send.receiver), arg_type),
+            None => receiver,
+        };
+
+        let mut args = vec![receiver];
+        args.extend(self.args(&fn_name, 1, &call.args)?);
+
+        let value = format!("{}({})", c_name(&fn_name), args.join(", "));
+
+        if !send.safe_navigation {
+            return Ok(value);
+        }
+
+        let nil = match send.return_type {
+            Some(BaseType::Void) | None => "(void)0",
+            Some(_) => "NULL",
+        };
+
+        // The receiver is checked before it's sent to, so it's only emitted
+        // twice when that has no side effects
+        match send.receiver.as_ref() {
+            Node::LocalVar(_) | Node::SelfRef(_) => Ok(format!(
+                "({} == NULL ? {} : {})",
+                self.expr(&send.receiver)?,
+                nil,
+                value
+            )),
+            _ => Err(
+                "The C backend only supports `&.` on locals, assign the receiver first".to_string(),
+            ),
+        }
+    }
+
+    /// Emits `args`, which are passed to `fn_name` starting at its argument
+    /// `offset`, casting instances to the class it expects.
+    fn args(&self, fn_name: &str, offset: usize, args: &[Node]) -> Result<Vec<String>, String> {
+        args.iter()
+            .enumerate()
+            .map(|(index, arg)| {
+                let value = self.expr(arg)?;
+
+                Ok(match self.prototype_arg_type(fn_name, offset + index) {
+                    Some(arg_type) => self.cast(value, node_type(arg), arg_type),
+                    None => value,
+                })
+            })
+            .collect()
+    }
+
+    fn cast(&self, value: String, from: Option<BaseType>, to: &BaseType) -> String {
+        match (&from, to) {
+            (Some(BaseType::Class(from)), BaseType::Class(to))
+                if from != to && self.result.index.class_index.contains_key(to) =>
+            {
+                format!("({} *){}", to, value)
+            }
+            _ => value,
+        }
+    }
+
+    fn prototype_arg_type(&self, fn_name: &str, index: usize) -> Option<&BaseType> {
+        let prototype = self.result.index.fn_prototype_index.get(fn_name)?;
+
+        prototype.args.get(index).map(|arg| &arg.return_type)
+    }
+
+    /// Maps a method name such as `Dog.speak` to the function that implements
+    /// it, like codegen does.
+    fn resolve_fn_name(&self, fn_name: &str) -> String {
+        let index = &self.result.index;
+
+        if index.fn_prototype_index.contains_key(fn_name) {
+            return fn_name.to_string();
+        }
+
+        match fn_name.split_once('.') {
+            Some((class_name, method_name)) => {
+                match index.resolve_method(class_name, method_name) {
+                    Ok(Some(resolved_name)) => resolved_name,
+                    _ => fn_name.to_string(),
+                }
+            }
+            None => fn_name.to_string(),
+        }
+    }
+
+    fn c_type(&self, base_type: &BaseType) -> String {
+        match base_type {
+            BaseType::Bool => "bool".to_string(),
+            BaseType::Byte => "uint8_t".to_string(),
+            BaseType::Int | BaseType::Int64 => "int64_t".to_string(),
+            BaseType::Int32 => "int32_t".to_string(),
+            BaseType::Int16 => "int16_t".to_string(),
+            BaseType::Float => "double".to_string(),
+            BaseType::FnRef => "void *".to_string(),
+            // The runtime array, whatever its item type
+            BaseType::Array(_) => "void *".to_string(),
+            // Trait methods take any implementing instance
+            BaseType::Class(name) if !self.result.index.class_index.contains_key(name) => {
+                "void *".to_string()
+            }
+            BaseType::Class(name) => format!("{} *", name),
+            // A null pointer when it's nil
+            BaseType::Optional(base_type) => self.c_type(base_type),
+            BaseType::Nil => "void *".to_string(),
+            BaseType::Struct(name) => name.clone(),
+            BaseType::BytePtr => "uint8_t *".to_string(),
+            BaseType::Void => "void".to_string(),
+        }
+    }
+
+    fn declaration(&self, base_type: &BaseType, name: &str) -> String {
+        let name = c_name(name);
+        let c_type = self.c_type(base_type);
+
+        if c_type.ends_with('*') {
+            format!("{}{}", c_type, name)
+        } else {
+            format!("{} {}", c_type, name)
+        }
+    }
+}
+
+/// The type of a checked expression, for declaring the local it's assigned to.
+/// Pajama evaluates args and operands left to right, but C leaves their
+/// order unspecified, so at most one of `nodes` can have side effects.
+fn in_order<'a>(what: &str, nodes: impl IntoIterator<Item = &'a Node>) -> Result<(), String> {
+    if nodes
+        .into_iter()
+        .filter(|node| has_side_effects(node))
+        .count()
+        > 1
+    {
+        return Err(format!(
+            "The C backend can't keep the side effects in {} in order, assign them to locals first",
+            what
+        ));
+    }
+
+    Ok(())
+}
+
+fn node_type(node: &Node) -> Option<BaseType> {
+    match node {
+        Node::Access(node) => node.return_type.clone(),
+        Node::Array(node) => Some(BaseType::Array(Box::new(node.item_type.clone()))),
+        Node::Binary(node) => node.return_type.clone(),
+        Node::Bool(_) => Some(BaseType::Bool),
+        Node::BuildStruct(node) => Some(node.return_type.clone()),
+        Node::Call(node) => node.return_type.clone(),
+        Node::Float(_) => Some(BaseType::Float),
+        Node::FnRef(_) => Some(BaseType::FnRef),
+        Node::If(node) => node.return_type.clone(),
+        Node::Int(_) => Some(BaseType::Int),
+        Node::LocalVar(node) => node.return_type.clone(),
+        Node::SelfRef(node) => Some(node.return_type.clone()),
+        Node::Send(node) => node.return_type.clone(),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+        _ => None,
+    }
+}
+
+/// `Dog.speak` becomes `Dog_speak`, `Vec.+` becomes `Vec_op_add`, the
+/// receiver `sret` becomes `self`, and names that are C keywords get a
+/// trailing underscore.
+fn c_name(name: &str) -> String {
+    if name == "sret" {
+        return "self".to_string();
+    }
+
+    let name = parser::mangle_method_name(name).replace('.', "_");
+
+    if C_KEYWORDS.split_whitespace().any(|keyword| keyword == name) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+fn int_literal(value: u64) -> String {
+    if value <= i64::MAX as u64 {
+        value.to_string()
+    } else {
+        // Like `~x`, which is parsed as `x ^ u64::MAX`
+        format!("(int64_t){}u", value)
+    }
+}
+
+fn string_literal(value: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for byte in value.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'\n' => literal.push_str("\\n"),
+            b'\r' => literal.push_str("\\r"),
+            b'\t' => literal.push_str("\\t"),
+            0x20..=0x7e => literal.push(byte as char),
+            // Octal escapes, unlike hex ones, stop after three digits
+            byte => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
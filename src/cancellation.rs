@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Lets a host abort a compile that's still running, like a language server
+/// whose user kept typing. Clones share one flag, so the host keeps a clone
+/// and cancels it from any thread.
+///
+/// Each phase checks the token between the items it works on, so a cancelled
+/// compile stops at the next item rather than straight away.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The result of a compile that was cancelled before it finished.
+#[derive(Debug, PartialEq)]
+pub struct Cancelled;
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        Ok(())
+    }
+}
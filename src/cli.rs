@@ -0,0 +1,414 @@
+use crate::allocator::{parse_allocator, Allocator};
+use crate::columns::{parse_tab_width, TabWidth};
+use crate::error_codes::is_error_code;
+use crate::explain::parse_position;
+use crate::graph::{parse_graph_format, GraphFormat};
+use crate::locale::DEFAULT_LOCALE;
+use crate::metrics::{parse_metrics_format, MetricsFormat};
+use crate::optimization::{parse_opt_level, OptLevel};
+use crate::resource_limits::{parse_duration, parse_size, ResourceLimits};
+use crate::runtime_profile::{parse_runtime_profile, RuntimeProfile};
+
+pub const USAGE: &str = "\
+usage: pajama [run] [options] <file>...
+       pajama [run] <file>.nlb
+       pajama repl [options] [<file>...]
+       pajama metrics [--format=table|json] [-o <path>] <file>...
+       pajama graph [--imports] [--format=dot|json] [-o <path>] <file>...
+       pajama explain <file>... --at <line>:<column>
+       pajama explain <code>
+       pajama profile run [options] <file>...
+       pajama inspect-binary <executable>
+       pajama dump-operators
+
+Compiles the files as one program and runs its main, with or without
+run. A file starting with a #!/usr/bin/env pajama line can be made
+executable and run as a script, and a .nlb file written with
+--emit=bytecode is run by the bytecode VM without compiling. repl reads
+inputs line by line instead, running each one with the files'
+definitions; :save <path> writes the inputs to a .nrepl file, and
+:load <path> replays one.
+metrics prints the statement count, nesting depth and complexity of each
+def, as a table or as JSON. graph prints which defs call each other, and
+the superclass and traits of each class, or with --imports which files
+import which, for Graphviz or as JSON. explain prints what's at a
+position of the first file, its type, and the def, class and file it's
+in, or what an error code like E0001 means, with examples. profile run
+builds an executable with frame pointers, runs it under perf and prints
+the functions it spent the most time in. inspect-binary prints the
+compiler version, target and opt level an executable was built with.
+dump-operators prints the symbol, precedence, associativity and arity of
+each operator as JSON, for formatters and editors.
+
+options:
+  --emit=<target>     write the program as ir, obj, exe, c, js or bytecode
+                      instead of running it, or with a backend --backend
+                      loaded
+  --backend=<path>    load a backend from the library at <path>, see
+                      backend::Backend for what it exports
+  -o <path>           write --emit, metrics or graph output to <path>
+                      instead of stdout, obj and exe always need one
+  --verbose           print tokens and the analyzed AST while compiling
+  --latin1            read files that aren't valid UTF-8 as Latin-1
+  --tab-width=<n>     line errors up under source indented with tabs as
+                      if each tab stop were <n> columns apart, 4 by default
+  --memory-stats      print compiler memory use after each phase
+  --stats-json=<path> write the number of defs, classes, nodes and errors,
+                      and the time each phase took, to <path> as JSON
+  --sandbox           stub out every def_e but the runtime functions that only
+                      compute, print or allocate
+  --strict            stop the program when Int +, - or * overflows instead
+                      of wrapping around
+  --freeze-literals   freeze locals assigned a string literal, so changing one
+                      stops the program
+  --print-dce         print the methods removed because main never reaches them
+  --reproducible      leave the build time out of the build info
+                      --emit=exe links in
+  --frame-pointers    keep frame pointers in the generated code, for perf and
+                      samply
+  --max-heap=<size>   stop the program once it allocates <size>, e.g. 64M
+  --max-time=<time>   stop the program after running for <time>, e.g. 5s
+  --profile-heap=<path>
+                      count what the program allocates from each runtime
+                      function, print it once main returns and write it to
+                      <path> as folded stacks for flamegraphs
+  --allocator=<name>  allocate with system (the default), mimalloc or bump
+  --runtime=minimal   only allow runtime functions that don't need an OS
+  -O0, -O1, -O2       how much to optimize, -O2 is the default
+  --locale=<name>     report errors in another language, read from
+                      <name>.catalog in $PAJAMA_LOCALE_DIR or in locales
+                      next to pajama, en (the default) is built in
+  -h, --help          print this message";
+
+#[derive(Debug, PartialEq)]
+pub enum Emit {
+    /// JIT compile and run `main`, the default
+    Run,
+    /// The MLIR module after lowering to the LLVM dialect
+    Ir,
+    /// A native object file that defines `main`
+    Obj,
+    /// An object file linked against the runtime with `cc`
+    Exe,
+    C,
+    Js,
+    /// A .nlb file for the bytecode VM
+    Bytecode,
+    /// A backend loaded with `--backend`, by name
+    Backend(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CliArgs {
+    pub paths: Vec<String>,
+    pub emit: Emit,
+    pub output: Option<String>,
+    /// The libraries `--backend` loads backends from
+    pub backends: Vec<String>,
+    pub verbose: bool,
+    pub latin1: bool,
+    pub memory_stats: bool,
+    /// Where `--stats-json` writes the compile's stats
+    pub stats_json: Option<String>,
+    pub sandbox: bool,
+    pub strict: bool,
+    pub freeze_literals: bool,
+    pub print_dce: bool,
+    pub frame_pointers: bool,
+    pub limits: ResourceLimits,
+    /// Where `--profile-heap` writes the folded stacks
+    pub profile_heap: Option<String>,
+    pub allocator: Allocator,
+    pub runtime: RuntimeProfile,
+    pub opt_level: OptLevel,
+    /// The language errors are reported in
+    pub locale: String,
+    pub tab_width: TabWidth,
+    /// `pajama repl`, the files are optional
+    pub repl: bool,
+    /// `pajama metrics`
+    pub metrics: bool,
+    pub metrics_format: MetricsFormat,
+    /// `pajama graph`
+    pub graph: bool,
+    pub graph_format: GraphFormat,
+    /// `pajama graph --imports`, the files' imports instead of the defs'
+    /// calls
+    pub graph_imports: bool,
+    /// `pajama explain`, with the `--at` line and column
+    pub explain: bool,
+    pub explain_at: Option<(usize, usize)>,
+    /// `pajama explain <code>`, which takes no files
+    pub explain_code: Option<String>,
+    /// `pajama profile run`
+    pub profile: bool,
+    /// `pajama inspect-binary`, the one path is the executable
+    pub inspect_binary: bool,
+    /// `pajama dump-operators`, which takes no files
+    pub dump_operators: bool,
+    pub reproducible: bool,
+    pub help: bool,
+}
+
+/// Parses the arguments after the program name. Every argument that isn't an
+/// option is an input file.
+pub fn parse_args(args: &[String]) -> Result<CliArgs, String> {
+    let mut cli_args = CliArgs {
+        paths: vec![],
+        emit: Emit::Run,
+        output: None,
+        backends: vec![],
+        verbose: false,
+        latin1: false,
+        memory_stats: false,
+        stats_json: None,
+        sandbox: false,
+        strict: false,
+        freeze_literals: false,
+        print_dce: false,
+        frame_pointers: false,
+        limits: ResourceLimits::default(),
+        profile_heap: None,
+        allocator: Allocator::default(),
+        runtime: RuntimeProfile::default(),
+        opt_level: OptLevel::default(),
+        locale: DEFAULT_LOCALE.to_string(),
+        tab_width: TabWidth::default(),
+        repl: false,
+        metrics: false,
+        metrics_format: MetricsFormat::Table,
+        graph: false,
+        graph_format: GraphFormat::Dot,
+        graph_imports: false,
+        explain: false,
+        explain_at: None,
+        explain_code: None,
+        profile: false,
+        inspect_binary: false,
+        dump_operators: false,
+        reproducible: false,
+        help: false,
+    };
+
+    let mut args = args.iter().peekable();
+
+    match args.peek().map(|arg| arg.as_str()) {
+        // Running is what happens without a subcommand too
+        Some("run") => {
+            args.next();
+        }
+        Some("repl") => {
+            cli_args.repl = true;
+            args.next();
+        }
+        Some("metrics") => {
+            cli_args.metrics = true;
+            args.next();
+        }
+        Some("graph") => {
+            cli_args.graph = true;
+            args.next();
+        }
+        Some("explain") => {
+            cli_args.explain = true;
+            args.next();
+
+            if let Some(code) = args.next_if(|arg| is_error_code(arg)) {
+                cli_args.explain_code = Some(code.clone());
+            }
+        }
+        Some("profile") => {
+            args.next();
+
+            if args.next().map(|arg| arg.as_str()) != Some("run") {
+                return Err("expected `profile run <file>...`".to_string());
+            }
+
+            cli_args.profile = true;
+            cli_args.frame_pointers = true;
+        }
+        Some("inspect-binary") => {
+            cli_args.inspect_binary = true;
+            args.next();
+        }
+        Some("dump-operators") => {
+            cli_args.dump_operators = true;
+            args.next();
+        }
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => cli_args.help = true,
+            "-o" => match args.next() {
+                Some(path) => cli_args.output = Some(path.clone()),
+                None => return Err("-o needs a path".to_string()),
+            },
+            "--at" => match args.next() {
+                Some(position) => cli_args.explain_at = Some(parse_position(position)?),
+                None => return Err("--at needs a <line>:<column>".to_string()),
+            },
+            "--verbose" => cli_args.verbose = true,
+            "--latin1" => cli_args.latin1 = true,
+            "--memory-stats" => cli_args.memory_stats = true,
+            "--sandbox" => cli_args.sandbox = true,
+            "--strict" => cli_args.strict = true,
+            "--freeze-literals" => cli_args.freeze_literals = true,
+            "--print-dce" => cli_args.print_dce = true,
+            "--frame-pointers" => cli_args.frame_pointers = true,
+            "--reproducible" => cli_args.reproducible = true,
+            "--imports" => cli_args.graph_imports = true,
+            _ => {
+                if let Some(target) = arg.strip_prefix("--emit=") {
+                    cli_args.emit = parse_emit(target);
+                } else if let Some(path) = arg.strip_prefix("--backend=") {
+                    cli_args.backends.push(path.to_string());
+                } else if let Some(size) = arg.strip_prefix("--max-heap=") {
+                    cli_args.limits.max_heap = Some(parse_size(size)?);
+                } else if let Some(duration) = arg.strip_prefix("--max-time=") {
+                    cli_args.limits.max_time = Some(parse_duration(duration)?);
+                } else if let Some(path) = arg.strip_prefix("--stats-json=") {
+                    cli_args.stats_json = Some(path.to_string());
+                } else if let Some(path) = arg.strip_prefix("--profile-heap=") {
+                    cli_args.profile_heap = Some(path.to_string());
+                } else if let Some(name) = arg.strip_prefix("--allocator=") {
+                    cli_args.allocator = parse_allocator(name)?;
+                } else if let Some(name) = arg.strip_prefix("--locale=") {
+                    cli_args.locale = name.to_string();
+                } else if let Some(width) = arg.strip_prefix("--tab-width=") {
+                    cli_args.tab_width = parse_tab_width(width)?;
+                } else if let Some(name) = arg.strip_prefix("--runtime=") {
+                    cli_args.runtime = parse_runtime_profile(name)?;
+                } else if let Some(name) = arg.strip_prefix("--format=") {
+                    if cli_args.metrics {
+                        cli_args.metrics_format = parse_metrics_format(name)?;
+                    } else if cli_args.graph {
+                        cli_args.graph_format = parse_graph_format(name)?;
+                    } else {
+                        return Err("--format is only for metrics and graph".to_string());
+                    }
+                } else if let Some(level) = arg.strip_prefix("-O") {
+                    cli_args.opt_level = parse_opt_level(level)?;
+                } else if arg.starts_with('-') {
+                    return Err(format!("unknown option `{}`", arg));
+                } else {
+                    cli_args.paths.push(arg.clone());
+                }
+            }
+        }
+    }
+
+    if cli_args.help {
+        return Ok(cli_args);
+    }
+
+    // Which backends a library has is only known once it's loaded
+    if let Emit::Backend(target) = &cli_args.emit {
+        if cli_args.backends.is_empty() {
+            return Err(format!(
+                "unknown --emit target `{}`, expected ir, obj, exe, c, js or bytecode",
+                target
+            ));
+        }
+    }
+
+    if cli_args.repl {
+        if cli_args.emit != Emit::Run || cli_args.output.is_some() {
+            return Err("repl runs each input, it can't --emit or -o".to_string());
+        }
+
+        return Ok(cli_args);
+    }
+
+    if cli_args.explain_code.is_some() {
+        if !cli_args.paths.is_empty()
+            || cli_args.explain_at.is_some()
+            || cli_args.emit != Emit::Run
+            || cli_args.output.is_some()
+        {
+            return Err("explain <code> prints the explanation, it takes no files".to_string());
+        }
+
+        return Ok(cli_args);
+    }
+
+    if cli_args.dump_operators {
+        if !cli_args.paths.is_empty() || cli_args.emit != Emit::Run || cli_args.output.is_some() {
+            return Err("dump-operators prints the operator table, it takes no files".to_string());
+        }
+
+        return Ok(cli_args);
+    }
+
+    if cli_args.inspect_binary {
+        if cli_args.paths.len() != 1 || cli_args.emit != Emit::Run || cli_args.output.is_some() {
+            return Err("inspect-binary prints the build info of one executable".to_string());
+        }
+
+        return Ok(cli_args);
+    }
+
+    if cli_args.paths.is_empty() {
+        return Err("no input files".to_string());
+    }
+
+    // A .nlb file is already compiled, the VM only runs it
+    if cli_args.paths.iter().any(|path| path.ends_with(".nlb"))
+        && (cli_args.paths.len() > 1
+            || cli_args.emit != Emit::Run
+            || cli_args.output.is_some()
+            || cli_args.metrics
+            || cli_args.graph
+            || cli_args.explain
+            || cli_args.profile)
+    {
+        return Err("a .nlb file is already compiled, it can only be run on its own".to_string());
+    }
+
+    if cli_args.graph_imports && !cli_args.graph {
+        return Err("--imports is only for graph".to_string());
+    }
+
+    if cli_args.explain_at.is_some() != cli_args.explain {
+        return Err("explain needs --at <line>:<column>, and --at is only for explain".to_string());
+    }
+
+    if cli_args.metrics || cli_args.graph || cli_args.explain || cli_args.profile {
+        if cli_args.emit != Emit::Run {
+            return Err(
+                "metrics, graph, explain and profile print a report, they can't --emit".to_string(),
+            );
+        }
+
+        if cli_args.profile && cli_args.output.is_some() {
+            return Err(
+                "profile prints its summary after the program's output, it can't -o".to_string(),
+            );
+        }
+
+        return Ok(cli_args);
+    }
+
+    if cli_args.output.is_some() && cli_args.emit == Emit::Run {
+        return Err("-o needs an --emit target, there's nothing to write when running".to_string());
+    }
+
+    if cli_args.output.is_none() && matches!(cli_args.emit, Emit::Obj | Emit::Exe) {
+        return Err("--emit=obj and --emit=exe need -o <path>".to_string());
+    }
+
+    Ok(cli_args)
+}
+
+fn parse_emit(target: &str) -> Emit {
+    match target {
+        "ir" => Emit::Ir,
+        "obj" => Emit::Obj,
+        "exe" => Emit::Exe,
+        "c" => Emit::C,
+        "js" => Emit::Js,
+        "bytecode" => Emit::Bytecode,
+        target => Emit::Backend(target.to_string()),
+    }
+}
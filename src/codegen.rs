@@ -1,4 +1,8 @@
+use crate::ast::branch_locals;
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::optimization::{self, OptLevel};
 use crate::parser::{BaseType, Def, FnRef, Node, ParserResult};
+use crate::semantic_analyzer::{array_slot_kind, array_slot_type};
 use crate::{parser};
 // use crate::mi_malloc;
 use melior::dialect::llvm::attributes::{linkage, Linkage};
@@ -7,7 +11,7 @@ use melior::dialect::scf;
 use melior::dialect::{index, llvm, memref};
 use melior::ir::attribute::{
     ArrayAttribute, DenseElementsAttribute, DenseI32ArrayAttribute, DenseI64ArrayAttribute,
-    FlatSymbolRefAttribute, IntegerAttribute,
+    FlatSymbolRefAttribute, FloatAttribute, IntegerAttribute,
 };
 use melior::ir::operation::{OperationBuilder, OperationResult};
 use melior::ir::r#type::{IntegerType, MemRefType, RankedTensorType};
@@ -70,13 +74,24 @@ static EXTERNAL_FNS4: [fn(*const u8, i64); 1] = [print_bytes];
 #[used]
 static EXTERNAL_FNS5: [fn(SockaddrIn); 1] = [print_class];
 
+/// The overflow flags analysis lowers `a.add_overflows(b)` and the others to,
+/// and the intrinsic computing each. The runtime functions are what the C and
+/// JavaScript backends call.
+const OVERFLOW_INTRINSICS: [(&str, &str); 3] = [
+    ("pj_add_overflows", "llvm.intr.sadd.with.overflow"),
+    ("pj_sub_overflows", "llvm.intr.ssub.with.overflow"),
+    ("pj_mul_overflows", "llvm.intr.smul.with.overflow"),
+];
+
 /// Defines the `Expr` compiler.
 #[derive(Debug, Clone, Copy)]
 pub struct LlvmTypes<'c> {
+    pub i1_type: Type<'c>,
     pub i8_type: Type<'c>,
     pub i16_type: Type<'c>,
     pub i32_type: Type<'c>,
     pub i64_type: Type<'c>,
+    pub f64_type: Type<'c>,
     pub i8_ptr_type: Type<'c>,
     pub i8_array_type: Type<'c>,
     pub i8_array_ptr_type: Type<'c>,
@@ -97,6 +112,10 @@ pub struct Compiler<'c, 'm> {
     pub llvm_types: LlvmTypes<'c>,
     pub class_type_index: HashMap<String, Type<'m>>,
     pub struct_type_index: HashMap<String, Type<'m>>,
+    /// Small defs are only marked `alwaysinline` above `O0`
+    pub opt_level: OptLevel,
+    /// Keep the frame pointer in every def, so profilers can walk the stack
+    pub frame_pointers: bool,
     // pub llvm_types: LlvmTypes<'m>,
     // pub class_type_index: HashMap<String, Type<'m>>,
 
@@ -114,19 +133,40 @@ pub struct ModuleCtx {
 pub struct FnCtx<'c, 'a> {
     pub lvars: HashMap<String, Value<'c, 'a>>,
     pub lvar_stores: HashMap<String, Value<'c, 'a>>,
+    /// Set on the body of a loop
+    pub loop_exits: Option<LoopExits<'c, 'a>>,
+    /// Set on the body of a def that returns early
+    pub fn_return: Option<FnReturn<'c, 'a>>,
     pub parent_ctx: Option<Box<&'c FnCtx<'c, 'a>>>,
 }
 
+/// Pointers to the `i1` flags `break` and `next` set, see `compile_while_loop`.
+#[derive(Clone, Copy, Debug)]
+pub struct LoopExits<'c, 'a> {
+    pub broken: Value<'c, 'a>,
+    pub skipped: Value<'c, 'a>,
+}
+
+/// Pointers to the `i1` flag `return` sets and to the value it returns, if
+/// the def returns one, see `compile_return`.
+#[derive(Clone, Copy, Debug)]
+pub struct FnReturn<'c, 'a> {
+    pub returned: Value<'c, 'a>,
+    pub value: Option<Value<'c, 'a>>,
+}
+
 impl<'c, 'm> Compiler<'c, 'm> {
     pub fn new(
         context: &'c Context,
         module: &'m Module<'c>,
         parser_result: &'m ParserResult,
     ) -> Self {
+        let i1_type = IntegerType::new(context, 1).into();
         let i8_type = IntegerType::new(context, 8).into();
         let i16_type = IntegerType::new(context, 16).into();
         let i32_type = IntegerType::new(context, 32).into();
         let i64_type = IntegerType::new(context, 64);
+        let f64_type = Type::float64(context);
         let i8_ptr_type = llvm::r#type::r#pointer(i8_type, 0);
         let i8_array_type = llvm::r#type::array(i8_type, 5);
         let i8_array_ptr_type = llvm::r#type::r#pointer(i8_array_type, 0);
@@ -143,9 +183,11 @@ impl<'c, 'm> Compiler<'c, 'm> {
         let void_type = llvm::r#type::void(context);
 
         let llvm_types = LlvmTypes {
+            i1_type,
             i16_type,
             i32_type,
             i64_type: i64_type.into(),
+            f64_type,
             i8_array_ptr_type,
             i8_array_type,
             i8_ptr_type,
@@ -191,10 +233,23 @@ impl<'c, 'm> Compiler<'c, 'm> {
             llvm_types,
             class_type_index,
             struct_type_index,
+            opt_level: OptLevel::default(),
+            frame_pointers: false,
         }
     }
 
     pub fn compile(&mut self) -> Result<(), &'static str> {
+        self.compile_cancellable(&CancellationToken::new()).unwrap();
+
+        Ok(())
+    }
+
+    /// Compiles the module, stopping before the next top level item once
+    /// `cancellation` is cancelled.
+    pub fn compile_cancellable(
+        &mut self,
+        cancellation: &CancellationToken,
+    ) -> Result<(), Cancelled> {
         // let registry = DialectRegistry::new();
         // register_all_dialects(&registry);
 
@@ -261,7 +316,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
         //     class_type_index,
         // };
 
-        self.compile_ast();
+        self.compile_ast(cancellation)?;
 
         // println!("PRE VERIFICATION:");
         // println!("{}", self.module.body().to_string());
@@ -321,24 +376,30 @@ impl<'c, 'm> Compiler<'c, 'm> {
         Ok(())
     }
 
-    fn compile_ast(&mut self) {
+    fn compile_ast(&mut self, cancellation: &CancellationToken) -> Result<(), Cancelled> {
         match &self.parser_result.module {
-            Node::Module(module) => {
-                self.compile_module(module);
-            }
+            Node::Module(module) => self.compile_module(module, cancellation),
             _ => {
                 panic!("Expected module to compile")
             }
         }
     }
 
-    fn compile_module(&mut self, module: &parser::Module) {
+    fn compile_module(
+        &mut self,
+        module: &parser::Module,
+        cancellation: &CancellationToken,
+    ) -> Result<(), Cancelled> {
         let mut mctx = ModuleCtx {
             global_var_counter: 0,
         };
 
         for node in module.methods.iter() {
+            cancellation.check()?;
+
             match &node {
+                // Bodyless trait defs only declare the method
+                Node::Def(def) if !def.trait_name.is_empty() && def.body.is_empty() => {}
                 Node::Def(def) => self.compile_def(def, &mut mctx),
                 Node::DefE(def_e) => self.compile_external_fn(def_e),
                 Node::AssignConstant(node) => self.compile_assign_constant(node, &mut mctx),
@@ -348,10 +409,12 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 Node::AssignLocalVar(_) => todo!(),
                 Node::Attribute(_) => todo!(),
                 Node::Binary(_) => todo!(),
+                Node::Bool(_) => todo!(),
                 Node::Call(_) => todo!(),
                 Node::Class(_) => panic!("Classes are not directly compiled"),
                 Node::Const(_) => todo!(),
                 Node::Impl(_) => todo!(),
+                Node::Float(_) => todo!(),
                 Node::Int(_) => todo!(),
                 Node::LocalVar(_) => todo!(),
                 Node::Loop(_) => todo!(),
@@ -365,8 +428,15 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 Node::BuildStruct(_) => todo!(),
                 Node::Struct(_) => todo!(),
                 Node::FnRef(_) => todo!(),
+                Node::If(_) => todo!(),
+                Node::While(_) => todo!(),
+                Node::Break => todo!(),
+                Node::Next => todo!(),
+                Node::Nil(_) => todo!(),
             }
         }
+
+        Ok(())
     }
 
     // fn compile_class(&mut self, class: &parser::Class) {
@@ -390,6 +460,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
         let node_type = self.basetype_to_mlir_type(&node.return_type);
         let node_value = match node.value.as_ref() {
             Node::Int(int_node) => int_node.value,
+            Node::Float(_) => todo!(),
+            Node::Bool(_) => todo!(),
             Node::FnRef(_) => todo!(),
             Node::Access(_) => todo!(),
             Node::AssignAttribute(_) => todo!(),
@@ -415,6 +487,11 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::Array(_) => todo!(),
             Node::BuildStruct(_) => todo!(),
             Node::Struct(_) => todo!(),
+            Node::If(_) => todo!(),
+            Node::While(_) => todo!(),
+            Node::Break => todo!(),
+            Node::Next => todo!(),
+            Node::Nil(_) => todo!(),
         };
 
         let int_attr = IntegerAttribute::new(node_type, node_value as i64).into();
@@ -436,7 +513,12 @@ impl<'c, 'm> Compiler<'c, 'm> {
     }
 
     fn compile_def(&mut self, node: &parser::Def, mctx: &mut ModuleCtx) {
-        let fn_name = StringAttribute::new(&self.context, node.prototype.name.as_str());
+        let _span = tracing::debug_span!("compile_def", name = %node.prototype.name).entered();
+
+        let fn_name = StringAttribute::new(
+            &self.context,
+            &parser::mangle_method_name(&node.prototype.name),
+        );
         let mut inputs = vec![];
 
         for arg in &node.prototype.args {
@@ -492,6 +574,23 @@ impl<'c, 'm> Compiler<'c, 'm> {
             // ));
         }
 
+        let mut passthrough = vec![];
+
+        if self.opt_level != OptLevel::O0 && optimization::is_small(node) {
+            passthrough.push("\"alwaysinline\"");
+        }
+
+        if self.frame_pointers {
+            passthrough.push("[\"frame-pointer\", \"all\"]");
+        }
+
+        if !passthrough.is_empty() {
+            attributes.push((
+                Identifier::new(&self.context, "passthrough"),
+                Attribute::parse(&self.context, &format!("[{}]", passthrough.join(", "))).unwrap(),
+            ));
+        }
+
         let location = Location::unknown(&self.context);
         // let operation = func::func(
         let operation = llvm::func(
@@ -588,6 +687,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
         let mut ctx = FnCtx {
             lvars: HashMap::new(),
             lvar_stores: HashMap::new(),
+            loop_exits: None,
+            fn_return: None,
             parent_ctx: None,
         };
 
@@ -604,8 +705,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             let success_int_value = block
                 .append_operation(arith::constant(
                     &self.context,
-                    IntegerAttribute::new(IntegerType::new(&self.context, 32).into(), 1 as i64)
-                        .into(),
+                    IntegerAttribute::new(IntegerType::new(&self.context, 32).into(), 0).into(),
                     Location::unknown(&self.context),
                 ))
                 .result(0)
@@ -623,7 +723,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 // BaseType::BytePtr => {}
                 // BaseType::Int => {}
                 // BaseType::Void => {}
-                BaseType::Class(_) => {
+                // An optional is the same pointer, null when it's nil
+                BaseType::Class(_) | BaseType::Optional(_) => {
                     // When a class is the first argument
                     // if index == 0 {
                     let arg_n = block.argument(index).unwrap();
@@ -640,6 +741,74 @@ impl<'c, 'm> Compiler<'c, 'm> {
             ctx.lvar_stores.insert(arg.name.clone(), ptr);
         }
 
+        // Locals first assigned in a branch can be read after it, so their
+        // slot is made here rather than in the branch. Instances get one too,
+        // which they're loaded from.
+        for assignment in branch_locals(&node.body) {
+            if self.get_lvar(&assignment.name, &ctx).is_some() {
+                continue;
+            }
+
+            let base_type = match self.node_base_type(&assignment.value) {
+                Some(BaseType::Struct(_) | BaseType::Void | BaseType::Nil) | None => continue,
+                Some(BaseType::Class(class_name)) if class_name == "Str" => continue,
+                Some(base_type) => base_type,
+            };
+
+            let slot = self.append_alloca_class(self.basetype_to_mlir_type(&base_type), &block);
+            ctx.lvar_stores.insert(assignment.name.clone(), slot);
+
+            if !matches!(base_type, BaseType::Class(_) | BaseType::Optional(_)) {
+                ctx.lvars.insert(assignment.name.clone(), slot);
+            }
+        }
+
+        if returns_early(&node.body) {
+            let false_value = self.compile_bool(&block, false);
+
+            let value = match &node.prototype.return_type {
+                Some(return_type) if !node.main_fn && *return_type != BaseType::Void => {
+                    Some(self.append_alloca_class(self.basetype_to_mlir_type(return_type), &block))
+                }
+                _ => None,
+            };
+
+            ctx.fn_return = Some(FnReturn {
+                returned: self.append_alloca_store(false_value, &block),
+                value,
+            });
+        }
+
+        // The body's `return`s store the value to return, as analysis made
+        // its last expression one too
+        if let Some(fn_return) = ctx.fn_return {
+            self.compile_statements(&block, &node.body, &mut ctx, mctx)?;
+
+            let return_val = match (fn_return.value, &node.prototype.return_type) {
+                (Some(value), Some(return_type)) => Some(
+                    block
+                        .append_operation(llvm::load(
+                            &self.context,
+                            value,
+                            self.basetype_to_mlir_type(return_type),
+                            Location::unknown(&self.context),
+                            Default::default(),
+                        ))
+                        .result(0)
+                        .unwrap()
+                        .into(),
+                ),
+                _ => None,
+            };
+
+            self.append_def_return(&block, node, return_val);
+
+            let region = Region::new();
+            region.append_block(block);
+
+            return Ok(region);
+        }
+
         let last_op_index = node.body.len();
 
         for (i, body_node) in node.body.iter().enumerate() {
@@ -651,60 +820,65 @@ impl<'c, 'm> Compiler<'c, 'm> {
 
             let last_node = i == last_op_index - 1;
             if last_node {
-                if node.main_fn {
-                    let success_int_value = block
-                        .append_operation(arith::constant(
-                            &self.context,
-                            IntegerAttribute::new(
-                                IntegerType::new(&self.context, 32).into(),
-                                1 as i64,
-                            )
-                            .into(),
-                            Location::unknown(&self.context),
-                        ))
-                        .result(0)
-                        .unwrap()
-                        .into();
+                self.append_def_return(&block, node, return_val);
+            }
+        }
 
-                    block.append_operation(llvm::r#return(
-                        Some(success_int_value),
-                        Location::unknown(&self.context),
-                    ));
-                } else {
-                    match &node.prototype.return_type {
-                        Some(rt) => match rt {
-                            BaseType::Void => {
-                                todo!()
-                            }
-                            _ => {
-                                if node.prototype.name.ends_with(".new") {
-                                    block.append_operation(llvm::r#return(
-                                        None,
-                                        Location::unknown(&self.context),
-                                    ));
-                                } else {
-                                    block.append_operation(llvm::r#return(
-                                        None,
-                                        Location::unknown(&self.context),
-                                    ));
-                                }
-                            }
-                        },
-                        None => {
+        let region = Region::new();
+        region.append_block(block);
+
+        Ok(region)
+    }
+
+    /// Ends a def with `llvm.return`: 0 from `main`, nothing from a
+    /// constructor, whose instance is returned by its `sret`, and `return_val`
+    /// from anything else.
+    fn append_def_return<'a>(
+        &self,
+        block: &'a Block<'c>,
+        node: &parser::Def,
+        return_val: Option<Value<'c, 'a>>,
+    ) {
+        if node.main_fn {
+            let success_int_value = block
+                .append_operation(arith::constant(
+                    &self.context,
+                    IntegerAttribute::new(IntegerType::new(&self.context, 32).into(), 0).into(),
+                    Location::unknown(&self.context),
+                ))
+                .result(0)
+                .unwrap()
+                .into();
+
+            block.append_operation(llvm::r#return(
+                Some(success_int_value),
+                Location::unknown(&self.context),
+            ));
+        } else {
+            match &node.prototype.return_type {
+                Some(rt) => match rt {
+                    BaseType::Void => {
+                        todo!()
+                    }
+                    _ => {
+                        if node.prototype.name.ends_with(".new") {
                             block.append_operation(llvm::r#return(
                                 None,
                                 Location::unknown(&self.context),
                             ));
+                        } else {
+                            block.append_operation(llvm::r#return(
+                                return_val,
+                                Location::unknown(&self.context),
+                            ));
                         }
                     }
+                },
+                None => {
+                    block.append_operation(llvm::r#return(None, Location::unknown(&self.context)));
                 }
             }
         }
-
-        let region = Region::new();
-        region.append_block(block);
-
-        Ok(region)
     }
 
     fn compile_expr<'a>(
@@ -725,10 +899,15 @@ impl<'c, 'm> Compiler<'c, 'm> {
             }
             Node::Binary(binary) => self.compile_binary(block, binary, ctx, mctx),
             Node::Call(call) => self.compile_call(block, call, ctx, mctx),
+            Node::Bool(node) => Ok(Some(self.compile_bool(block, node.value))),
             Node::Int(nb) => self.compile_int(block, nb),
+            Node::Float(float) => self.compile_float(block, float),
             Node::FnRef(fn_ref) => self.compile_fn_ref(block, fn_ref, ctx, mctx),
             Node::LocalVar(lvar) => self.compile_local_var(block, lvar, ctx, mctx),
             Node::Loop(node) => self.compile_loop(block, node, ctx, mctx),
+            Node::If(node) => self.compile_if(block, node, ctx, mctx),
+            Node::While(node) => self.compile_while(block, node, ctx, mctx),
+            Node::Break | Node::Next => self.compile_loop_exit(block, expr, ctx),
             Node::Ret(ret) => self.compile_return(block, ret, ctx, mctx),
             Node::SelfRef(lvar) => self.compile_self_ref(block, lvar, ctx, mctx),
             Node::Send(node) => self.compile_send(block, node, ctx, mctx),
@@ -736,6 +915,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::Const(node) => self.compile_const_ref(block, node),
             Node::Array(node) => self.compile_array(block, node, ctx, mctx),
             Node::BuildStruct(node) => self.compile_build_struct(block, node, ctx, mctx),
+            Node::Nil(node) => Ok(Some(self.compile_nil(block, node))),
             Node::AssignConstant(_) => panic!("Syntax error"),
             Node::Attribute(_) => panic!("Syntax error"),
             Node::Class(_) => panic!("Syntax error"),
@@ -838,7 +1018,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                         // BaseType::Int16 => todo!(),
                         // BaseType::Int32 => todo!(),
                         // BaseType::Int64 => todo!(),
-                        // BaseType::Array(_, _) => todo!(),
+                        // BaseType::Array(_) => todo!(),
                     }
                     None => todo!(),
                 }
@@ -888,7 +1068,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 //                 BaseType::Int16 => todo!(),
                 //                 BaseType::Int32 => todo!(),
                 //                 BaseType::Int64 => todo!(),
-                //                 BaseType::Array(_, _) => todo!(),
+                //                 BaseType::Array(_) => todo!(),
                 //             },
                 //             None => todo!(),
                 //         };
@@ -915,7 +1095,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 //     BaseType::Int16 => todo!(),
                 //     BaseType::Int32 => todo!(),
                 //     BaseType::Int64 => todo!(),
-                //     BaseType::Array(_, _) => todo!(),
+                //     BaseType::Array(_) => todo!(),
                 // }
             }
             _ => todo!(),
@@ -936,10 +1116,21 @@ impl<'c, 'm> Compiler<'c, 'm> {
             _ => return Err("Expected send_node message to be a Call"),
         };
 
-        println!("{:#?}", send_node);
+        tracing::trace!("{:#?}", send_node);
+
+        // Builtin iteration, unless the program defines its own Array methods
+        if (call_node.fn_name == "Array.each" || call_node.fn_name == "Array.map")
+            && !self
+                .parser_result
+                .index
+                .fn_prototype_index
+                .contains_key(&call_node.fn_name)
+        {
+            return self.compile_array_iteration(block, send_node, call_node, ctx, mctx);
+        }
 
-        println!("{:#?}", "ctx.lvars");
-        println!("{:#?}", ctx.lvars);
+        tracing::trace!("{:#?}", "ctx.lvars");
+        tracing::trace!("{:#?}", ctx.lvars);
 
         let value = match send_node.receiver.as_ref() {
             Node::LocalVar(local_var) => match &local_var.return_type {
@@ -981,6 +1172,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 Ok(Some(value))
             }
             Node::Access(access) => self.compile_attribute_access(block, access, ctx, mctx),
+            Node::SelfRef(self_ref) => self.compile_self_ref(block, self_ref, ctx, mctx),
             _ => return Err("Send only implements LocalVar so far"),
         };
 
@@ -990,15 +1182,19 @@ impl<'c, 'm> Compiler<'c, 'm> {
         // let mut inputs = vec![receiver_value.r#type()];
         let mut inputs = vec![];
 
-        println!("{:#?}", &call_node.fn_name);
+        tracing::trace!("{:#?}", &call_node.fn_name);
 
-        println!("{:#?}", self.parser_result.index.fn_prototype_index);
+        tracing::trace!("{:#?}", self.parser_result.index.fn_prototype_index);
+
+        let fn_name = self.resolve_fn_name(&call_node.fn_name);
+        // Operator methods like `Vec.+` aren't valid symbol names
+        let symbol_name = parser::mangle_method_name(&fn_name);
 
         let prototype = self
             .parser_result
             .index
             .fn_prototype_index
-            .get(&call_node.fn_name)
+            .get(&fn_name)
             .unwrap();
 
         for arg in &prototype.args {
@@ -1071,22 +1267,39 @@ impl<'c, 'm> Compiler<'c, 'm> {
         //     location,
         // ));
 
+        // An inherited method expects its own class (or trait) as the receiver
+        let receiver_type = self.node_base_type(send_node.receiver.as_ref());
+
+        let receiver_value = match receiver_type.as_ref().map(BaseType::without_optional) {
+            Some(receiver_type @ BaseType::Class(_))
+                if !matches!(send_node.receiver.as_ref(), Node::Const(_)) =>
+            {
+                self.compile_type_cast(
+                    block,
+                    receiver_value,
+                    receiver_type.clone(),
+                    prototype.args[0].return_type.clone(),
+                )
+            }
+            _ => receiver_value,
+        };
+
         let mut compiled_args = vec![receiver_value];
 
-        println!("call_node.args");
-        println!("{:#?}", call_node.args);
+        tracing::trace!("call_node.args");
+        tracing::trace!("{:#?}", call_node.args);
 
         for (index, arg) in call_node.args.iter().enumerate() {
             let mut value = self.compile_expr(block, &arg, ctx, mctx).unwrap().unwrap();
             let arg_return_type = self.node_base_type(arg).unwrap();
             let prototype_arg_type = prototype.args[index + 1].return_type.clone();
 
-            println!("prototype: {:#?}", prototype);
-            println!("arg_return_type: {:#?}", arg_return_type);
+            tracing::trace!("prototype: {:#?}", prototype);
+            tracing::trace!("arg_return_type: {:#?}", arg_return_type);
 
-            println!("prototype_arg_type: {:#?}", prototype_arg_type);
+            tracing::trace!("prototype_arg_type: {:#?}", prototype_arg_type);
 
-            println!("arg: {:#?}", arg);
+            tracing::trace!("arg: {:#?}", arg);
 
             // refactor, duplicate of call
             value = self.compile_type_cast(block, value, arg_return_type, prototype_arg_type);
@@ -1099,11 +1312,15 @@ impl<'c, 'm> Compiler<'c, 'm> {
         //     compiled_args.push(arg_value.unwrap());
         // }
 
+        if send_node.safe_navigation {
+            return self.compile_safe_call(block, &symbol_name, &compiled_args, &results);
+        }
+
         if let Some(_) = &send_node.return_type {
             if call_node.fn_name.ends_with(".new") || call_node.fn_name.ends_with(".alloca") {
                 block.append_operation(llvm::call(
                     &self.context,
-                    FlatSymbolRefAttribute::new(&self.context, call_node.fn_name.as_str()),
+                    FlatSymbolRefAttribute::new(&self.context, symbol_name.as_str()),
                     &compiled_args,
                     &results,
                     location,
@@ -1114,7 +1331,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 let value = block
                     .append_operation(llvm::call(
                         &self.context,
-                        FlatSymbolRefAttribute::new(&self.context, call_node.fn_name.as_str()),
+                        FlatSymbolRefAttribute::new(&self.context, symbol_name.as_str()),
                         &compiled_args,
                         &results,
                         location,
@@ -1127,7 +1344,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
         } else {
             block.append_operation(llvm::call(
                 &self.context,
-                FlatSymbolRefAttribute::new(&self.context, call_node.fn_name.as_str()),
+                FlatSymbolRefAttribute::new(&self.context, symbol_name.as_str()),
                 &compiled_args,
                 &results,
                 location,
@@ -1137,6 +1354,77 @@ impl<'c, 'm> Compiler<'c, 'm> {
         }
     }
 
+    /// A call sent with `&.`, which is skipped when the receiver, the first of
+    /// `args`, is nil. It returns nil then. The other arguments are compiled
+    /// either way.
+    fn compile_safe_call<'a>(
+        &self,
+        block: &'a Block<'c>,
+        symbol_name: &str,
+        args: &[Value<'c, '_>],
+        results: &[Type<'c>],
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let location = Location::unknown(&self.context);
+        let is_nil = self.compile_is_nil(block, args[0]);
+
+        let nil_block = Block::new(&[]);
+        let nil_values: Vec<Value> = results
+            .iter()
+            .map(|result| self.compile_null(&nil_block, *result))
+            .collect();
+        nil_block.append_operation(scf::r#yield(&nil_values, location));
+
+        let call_block = Block::new(&[]);
+        let call = call_block.append_operation(llvm::call(
+            &self.context,
+            FlatSymbolRefAttribute::new(&self.context, symbol_name),
+            args,
+            results,
+            location,
+        ));
+        let call_values: Vec<Value> = (0..results.len())
+            .map(|index| call.result(index).unwrap().into())
+            .collect();
+        call_block.append_operation(scf::r#yield(&call_values, location));
+
+        let then_region = Region::new();
+        then_region.append_block(nil_block);
+        let else_region = Region::new();
+        else_region.append_block(call_block);
+
+        let if_op = block.append_operation(scf::r#if(
+            is_nil,
+            results,
+            then_region,
+            else_region,
+            location,
+        ));
+
+        if results.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(if_op.result(0).unwrap().into()))
+        }
+    }
+
+    /// Maps a method name such as `Dog.speak` to the function that implements
+    /// it when `Dog` inherits the method from a superclass or a trait default.
+    fn resolve_fn_name(&self, fn_name: &str) -> String {
+        let index = &self.parser_result.index;
+
+        if index.fn_prototype_index.contains_key(fn_name) {
+            return fn_name.to_string();
+        }
+
+        match fn_name.split_once('.') {
+            Some((class_name, method_name)) => match index.resolve_method(class_name, method_name) {
+                Ok(Some(resolved_name)) => resolved_name,
+                _ => fn_name.to_string(),
+            },
+            None => fn_name.to_string(),
+        }
+    }
+
     fn compile_type_cast<'a>(
         &self,
         block: &'a Block<'c>,
@@ -1144,15 +1432,21 @@ impl<'c, 'm> Compiler<'c, 'm> {
         arg_return_type: BaseType,
         prototype_arg_type: BaseType,
     ) -> Value<'c, 'a> {
+        // An optional is cast like the class it wraps
+        let arg_return_type = arg_return_type.without_optional().clone();
+        let prototype_arg_type = prototype_arg_type.without_optional().clone();
+
         if arg_return_type != prototype_arg_type {
-            println!("{:#?}", "mismatch:");
-            println!("{:#?}", arg_return_type);
-            println!("{:#?}", prototype_arg_type);
+            tracing::trace!("{:#?}", "mismatch:");
+            tracing::trace!("{:#?}", arg_return_type);
+            tracing::trace!("{:#?}", prototype_arg_type);
 
             let cast_type = self.basetype_to_mlir_type(&prototype_arg_type);
 
             match arg_return_type {
+                BaseType::Bool => todo!(),
                 BaseType::Byte => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => todo!(),
                     BaseType::Int => {
                         value = block
@@ -1178,14 +1472,18 @@ impl<'c, 'm> Compiler<'c, 'm> {
                             .into();
                     }
                     BaseType::Int64 => todo!(),
-                    BaseType::Array(_, _) => todo!(),
+                    BaseType::Array(_) => todo!(),
                     BaseType::Class(_) => todo!(),
                     BaseType::BytePtr => todo!(),
                     BaseType::Void => todo!(),
                     BaseType::Struct(_) => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => todo!(),
                 },
                 BaseType::Int => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => todo!(),
                     BaseType::Int => todo!(),
                     BaseType::Int16 => {
@@ -1201,14 +1499,28 @@ impl<'c, 'm> Compiler<'c, 'm> {
                     }
                     BaseType::Int32 => todo!(),
                     BaseType::Int64 => todo!(),
-                    BaseType::Array(_, _) => todo!(),
+                    BaseType::Array(_) => todo!(),
                     BaseType::Class(_) => todo!(),
                     BaseType::Struct(_) => todo!(),
                     BaseType::BytePtr => todo!(),
                     BaseType::Void => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => {
+                        value = block
+                            .append_operation(arith::sitofp(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
                 },
                 BaseType::Int16 => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => {
                         value = block
                             .append_operation(arith::trunci(
@@ -1234,14 +1546,28 @@ impl<'c, 'm> Compiler<'c, 'm> {
                     BaseType::Int16 => todo!(),
                     BaseType::Int32 => todo!(),
                     BaseType::Int64 => todo!(),
-                    BaseType::Array(_, _) => todo!(),
+                    BaseType::Array(_) => todo!(),
                     BaseType::Class(_) => todo!(),
                     BaseType::BytePtr => todo!(),
                     BaseType::Void => todo!(),
                     BaseType::Struct(_) => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => {
+                        value = block
+                            .append_operation(arith::sitofp(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
                 },
                 BaseType::Int32 => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => {
                         value = block
                             .append_operation(arith::trunci(
@@ -1287,14 +1613,28 @@ impl<'c, 'm> Compiler<'c, 'm> {
                             .unwrap()
                             .into();
                     }
-                    BaseType::Array(_, _) => todo!(),
+                    BaseType::Array(_) => todo!(),
                     BaseType::Class(_) => todo!(),
                     BaseType::BytePtr => todo!(),
                     BaseType::Void => todo!(),
                     BaseType::Struct(_) => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => {
+                        value = block
+                            .append_operation(arith::sitofp(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
                 },
                 BaseType::Int64 => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => {
                         value = block
                             .append_operation(arith::trunci(
@@ -1330,20 +1670,34 @@ impl<'c, 'm> Compiler<'c, 'm> {
                             .into();
                     }
                     BaseType::Int64 => todo!(),
-                    BaseType::Array(_, _) => todo!(),
+                    BaseType::Array(_) => todo!(),
                     BaseType::Class(_) => {}
                     BaseType::BytePtr => todo!(),
                     BaseType::Void => todo!(),
                     BaseType::Struct(_) => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => {
+                        value = block
+                            .append_operation(arith::sitofp(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
                 },
-                BaseType::Array(_, _) => match prototype_arg_type {
+                BaseType::Array(_) => match prototype_arg_type {
+                    BaseType::Bool => todo!(),
                     BaseType::Byte => todo!(),
                     BaseType::Int => todo!(),
                     BaseType::Int16 => todo!(),
                     BaseType::Int32 => todo!(),
                     BaseType::Int64 => todo!(),
-                    BaseType::Array(_, _) => {
+                    BaseType::Array(_) => {
                         // value = block
                         //     .append_operation(
                         //         llvm::bitcast(value, cast_type, Location::unknown(&self.context))
@@ -1354,11 +1708,26 @@ impl<'c, 'm> Compiler<'c, 'm> {
                     }
                     BaseType::Class(_) => todo!(),
                     BaseType::Struct(_) => todo!(),
-                    BaseType::BytePtr => todo!(),
+                    BaseType::BytePtr => {
+                        // Arrays are already a pointer to the runtime array
+                        value = block
+                            .append_operation(llvm::bitcast(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
                     BaseType::Void => todo!(),
                     BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                    BaseType::Float => todo!(),
                 },
-                BaseType::Class(class_name) => {
+                // A nil literal without a type is a null `BytePtr`
+                BaseType::Class(_) | BaseType::Nil => {
                     value = block
                         .append_operation(llvm::bitcast(
                             value,
@@ -1371,13 +1740,28 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 }
                 BaseType::BytePtr => {
                     match prototype_arg_type {
+                        BaseType::Bool => todo!(),
                         BaseType::Byte => todo!(),
                         BaseType::Int => todo!(),
                         BaseType::Int16 => todo!(),
                         BaseType::Int32 => todo!(),
                         BaseType::Int64 => todo!(),
                         BaseType::FnRef => todo!(),
-                        BaseType::Array(_, _) => todo!(),
+                        BaseType::Float => todo!(),
+                        BaseType::Optional(_) => todo!(),
+                        BaseType::Nil => todo!(),
+                        // pj_array_push returns the array it was given
+                        BaseType::Array(_) => {
+                            value = block
+                                .append_operation(llvm::bitcast(
+                                    value,
+                                    cast_type,
+                                    Location::unknown(&self.context),
+                                ))
+                                .result(0)
+                                .unwrap()
+                                .into();
+                        }
                         BaseType::Class(class_name) => {
                             // pj_alloc_struct returns a BytePtr, this casts it to a user defined class
                             value = block
@@ -1395,7 +1779,35 @@ impl<'c, 'm> Compiler<'c, 'm> {
                         BaseType::Void => todo!(),
                     }
                 }
+                BaseType::Float => match prototype_arg_type {
+                    // Rounds towards zero
+                    BaseType::Int | BaseType::Int64 => {
+                        value = block
+                            .append_operation(arith::fptosi(
+                                value,
+                                cast_type,
+                                Location::unknown(&self.context),
+                            ))
+                            .result(0)
+                            .unwrap()
+                            .into();
+                    }
+                    BaseType::Bool => todo!(),
+                    BaseType::Byte => todo!(),
+                    BaseType::Int16 => todo!(),
+                    BaseType::Int32 => todo!(),
+                    BaseType::Float => {}
+                    BaseType::Array(_) => todo!(),
+                    BaseType::Class(_) => todo!(),
+                    BaseType::BytePtr => todo!(),
+                    BaseType::Void => todo!(),
+                    BaseType::Struct(_) => todo!(),
+                    BaseType::FnRef => todo!(),
+                    BaseType::Optional(_) => todo!(),
+                    BaseType::Nil => todo!(),
+                },
                 BaseType::Void => todo!(),
+                BaseType::Optional(_) => todo!(),
                 BaseType::Struct(_) => {}
                 BaseType::FnRef => {
                     // match prototype_arg_type {
@@ -1405,7 +1817,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                     //     BaseType::Int32 => todo!(),
                     //     BaseType::Int64 => todo!(),
                     //     BaseType::FnRef => todo!(),
-                    //     BaseType::Array(_, _) => todo!(),
+                    //     BaseType::Array(_) => todo!(),
                     //     BaseType::Class(_) => todo!(),
                     //     BaseType::Struct(_) => todo!(),
                     //     BaseType::BytePtr => {
@@ -1490,7 +1902,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
         let addressof_op = block
             .append_operation(llvm::addressof(
                 &self.context,
-                &fn_ref.fn_name,
+                &parser::mangle_method_name(&fn_ref.fn_name),
                 llvm::r#type::pointer(function_type, 0),
                 Location::unknown(&self.context),
             ))
@@ -1554,6 +1966,13 @@ impl<'c, 'm> Compiler<'c, 'm> {
         ctx: &mut FnCtx<'c, 'a>,
         mctx: &mut ModuleCtx,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        if let Some((_, intrinsic)) = OVERFLOW_INTRINSICS
+            .iter()
+            .find(|(fn_name, _)| *fn_name == call.fn_name)
+        {
+            return self.compile_overflow_flag(block, call, intrinsic, ctx, mctx);
+        }
+
         let prototype = self
             .parser_result
             .index
@@ -1564,10 +1983,10 @@ impl<'c, 'm> Compiler<'c, 'm> {
 
         let mut inputs = vec![];
 
-        println!("self.parser_result.index.fn_prototype_index");
-        println!("{:#?}", self.parser_result.index.fn_prototype_index);
+        tracing::trace!("self.parser_result.index.fn_prototype_index");
+        tracing::trace!("{:#?}", self.parser_result.index.fn_prototype_index);
 
-        println!("{:#?}", &call.fn_name);
+        tracing::trace!("{:#?}", &call.fn_name);
 
         for arg in &prototype.args {
             // use the prototype to find the value. 0 is causing i64 instead of the needed i32
@@ -1603,7 +2022,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             // inputs.push(arg_return_type);
         }
 
-        let results = match &call.return_type {
+        let results = match prototype.return_type.as_ref().or(call.return_type.as_ref()) {
             Some(base_type) => vec![self.basetype_to_mlir_type(&base_type)],
             None => vec![],
         };
@@ -1627,22 +2046,22 @@ impl<'c, 'm> Compiler<'c, 'm> {
 
         let mut compiled_args = vec![];
 
-        println!("{:#?}", "CALLLL");
-        println!("{:#?}", call);
+        tracing::trace!("{:#?}", "CALLLL");
+        tracing::trace!("{:#?}", call);
 
         for (index, arg) in call.args.iter().enumerate() {
             let mut value = self.compile_expr(block, &arg, ctx, mctx).unwrap().unwrap();
             let arg_return_type = self.node_base_type(arg).unwrap();
             let prototype_arg_type = prototype.args[index].return_type.clone();
 
-            println!("{:#?}", "AAAAAAAAAA");
+            tracing::trace!("{:#?}", "AAAAAAAAAA");
 
-            println!("{:#?}", prototype);
-            println!("{:#?}", arg_return_type);
+            tracing::trace!("{:#?}", prototype);
+            tracing::trace!("{:#?}", arg_return_type);
 
-            println!("{:#?}", prototype_arg_type);
+            tracing::trace!("{:#?}", prototype_arg_type);
 
-            println!("{:#?}", arg);
+            tracing::trace!("{:#?}", arg);
 
             value = self.compile_type_cast(block, value, arg_return_type, prototype_arg_type);
 
@@ -1675,7 +2094,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             //                     .unwrap()
             //                     .into();
             //             },
-            //             BaseType::Array(_, _) => todo!(),
+            //             BaseType::Array(_) => todo!(),
             //             BaseType::Class(_) => todo!(),
             //             BaseType::BytePtr => todo!(),
             //             BaseType::Void => todo!(),
@@ -1702,12 +2121,12 @@ impl<'c, 'm> Compiler<'c, 'm> {
             //                     .into();
             //             },
             //             BaseType::Int64 => todo!(),
-            //             BaseType::Array(_, _) => todo!(),
+            //             BaseType::Array(_) => todo!(),
             //             BaseType::Class(_) => todo!(),
             //             BaseType::BytePtr => todo!(),
             //             BaseType::Void => todo!(),
             //         },
-            //         BaseType::Array(_, _) => todo!(),
+            //         BaseType::Array(_) => todo!(),
             //         BaseType::Class(class) => {
             //             match prototype_arg_type {
             //                 BaseType::Byte => todo!(),
@@ -1715,7 +2134,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             //                 BaseType::Int16 => todo!(),
             //                 BaseType::Int32 => todo!(),
             //                 BaseType::Int64 => todo!(),
-            //                 BaseType::Array(_, _) => todo!(),
+            //                 BaseType::Array(_) => todo!(),
             //                 BaseType::Class(_) => {
             //                     value = block
             //                         .append_operation(
@@ -1737,8 +2156,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
             compiled_args.push(value);
         }
 
-        if let Some(_) = &call.return_type {
-            let value = block
+        if let Some(return_type) = &call.return_type {
+            let mut value = block
                 .append_operation(llvm::call(
                     &self.context,
                     FlatSymbolRefAttribute::new(&self.context, call.fn_name.as_str()),
@@ -1751,6 +2170,17 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 .unwrap()
                 .into();
 
+            // Runtime functions such as pj_min return a BytePtr that the call
+            // was inferred to be a class instance
+            if let Some(prototype_return_type) = &prototype.return_type {
+                value = self.compile_type_cast(
+                    block,
+                    value,
+                    prototype_return_type.clone(),
+                    return_type.clone(),
+                );
+            }
+
             Ok(Some(value))
         } else {
             block.append_operation(llvm::call(
@@ -1766,6 +2196,64 @@ impl<'c, 'm> Compiler<'c, 'm> {
         }
     }
 
+    /// Whether the Int operation `intrinsic` overflows, as an `i1`. The
+    /// intrinsic gives the wrapped result and the flag, and only the flag is
+    /// kept.
+    fn compile_overflow_flag<'a>(
+        &self,
+        block: &'a Block<'c>,
+        call: &parser::Call,
+        intrinsic: &str,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let location = Location::unknown(&self.context);
+        let mut operands = vec![];
+
+        for arg in &call.args {
+            match self.compile_expr(block, arg, ctx, mctx)? {
+                Some(value) => operands.push(value),
+                None => return Err("overflow operand has no value"),
+            }
+        }
+
+        let result_type = llvm::r#type::r#struct(
+            &self.context,
+            &[self.llvm_types.i64_type, self.llvm_types.i1_type],
+            false,
+        );
+
+        let with_overflow = block
+            .append_operation(
+                OperationBuilder::new(intrinsic, location)
+                    .add_operands(&operands)
+                    .add_results(&[result_type])
+                    .build()
+                    .unwrap(),
+            )
+            .result(0)
+            .unwrap()
+            .into();
+
+        let overflowed = block
+            .append_operation(
+                OperationBuilder::new("llvm.extractvalue", location)
+                    .add_attributes(&[(
+                        Identifier::new(&self.context, "position"),
+                        DenseI64ArrayAttribute::new(&self.context, &[1]).into(),
+                    )])
+                    .add_operands(&[with_overflow])
+                    .add_results(&[self.llvm_types.i1_type])
+                    .build()
+                    .unwrap(),
+            )
+            .result(0)
+            .unwrap()
+            .into();
+
+        Ok(Some(overflowed))
+    }
+
     fn compile_int<'a>(
         &self,
         block: &'a Block<'c>,
@@ -1785,21 +2273,39 @@ impl<'c, 'm> Compiler<'c, 'm> {
         Ok(Some(value))
     }
 
-    fn compile_string_literal<'a>(
+    fn compile_float<'a>(
         &self,
         block: &'a Block<'c>,
-        string: &parser::StringLiteral,
-        ctx: &mut FnCtx<'c, 'a>,
-        mctx: &mut ModuleCtx,
+        float: &parser::Float,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
-        let string_attr = StringAttribute::new(&self.context, &string.value);
-        let i8_array_type = llvm::r#type::array(self.llvm_types.i8_type, string.value.len() as u32);
-
-        let region = Region::new();
-        let temp_name = mctx.global_var_counter.to_string();
-
-        self.module.body().append_operation(llvm::global(
-            &self.context,
+        let value = block
+            .append_operation(arith::constant(
+                &self.context,
+                FloatAttribute::new(&self.context, float.value, self.llvm_types.f64_type).into(),
+                Location::unknown(&self.context),
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+
+        Ok(Some(value))
+    }
+
+    fn compile_string_literal<'a>(
+        &self,
+        block: &'a Block<'c>,
+        string: &parser::StringLiteral,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let string_attr = StringAttribute::new(&self.context, &string.value);
+        let i8_array_type = llvm::r#type::array(self.llvm_types.i8_type, string.value.len() as u32);
+
+        let region = Region::new();
+        let temp_name = mctx.global_var_counter.to_string();
+
+        self.module.body().append_operation(llvm::global(
+            &self.context,
             StringAttribute::new(&self.context, temp_name.as_str()),
             Some(string_attr.into()),
             i8_array_type,
@@ -1931,7 +2437,150 @@ impl<'c, 'm> Compiler<'c, 'm> {
         ctx: &mut FnCtx<'c, 'a>,
         mctx: &mut ModuleCtx,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
-        todo!()
+        if binary.is_logical() {
+            return self.compile_logical(block, binary, ctx, mctx);
+        }
+
+        let compares_nil = [&binary.left, &binary.right]
+            .iter()
+            .any(|operand| matches!(operand.as_ref(), Node::Nil(_)));
+
+        if compares_nil && (binary.op == "==" || binary.op == "!=") {
+            return self.compile_nil_comparison(block, binary, ctx, mctx);
+        }
+
+        let location = Location::unknown(&self.context);
+
+        // Comparisons return a Bool, their operands are compared as the type
+        // of the left one, or of the right one when the left is an integer
+        // literal. Integers are converted when the other operand is a Float.
+        let is_float = |node: &Node| self.node_base_type(node) == Some(BaseType::Float);
+
+        let cast_type = match binary.left.as_ref() {
+            _ if !binary.is_comparison() => binary.return_type.clone().unwrap(),
+            _ if is_float(&binary.left) || is_float(&binary.right) => BaseType::Float,
+            Node::Int(_) => self.node_base_type(&binary.right).unwrap(),
+            left => self.node_base_type(left).unwrap(),
+        };
+
+        let mut operands = vec![];
+
+        // Left to right, like call args. The calls in them aren't marked
+        // `readnone`, so LLVM keeps their effects in this order
+        for operand in [&binary.left, &binary.right] {
+            let value = self.compile_expr(block, operand, ctx, mctx)?.unwrap();
+            let operand_type = self.node_base_type(operand).unwrap();
+
+            operands.push(self.compile_type_cast(block, value, operand_type, cast_type.clone()));
+        }
+
+        let (lhs, rhs) = (operands[0], operands[1]);
+
+        if cast_type == BaseType::Float {
+            return self.compile_float_binary(block, binary, lhs, rhs);
+        }
+
+        let predicate = match binary.op.as_str() {
+            "==" => Some(arith::CmpiPredicate::Eq),
+            "!=" => Some(arith::CmpiPredicate::Ne),
+            "<" => Some(arith::CmpiPredicate::Slt),
+            "<=" => Some(arith::CmpiPredicate::Sle),
+            ">" => Some(arith::CmpiPredicate::Sgt),
+            ">=" => Some(arith::CmpiPredicate::Sge),
+            _ => None,
+        };
+
+        if let Some(predicate) = predicate {
+            let value = block
+                .append_operation(arith::cmpi(&self.context, predicate, lhs, rhs, location))
+                .result(0)
+                .unwrap()
+                .into();
+
+            return Ok(Some(value));
+        }
+
+        let operation = match binary.op.as_str() {
+            "+" => arith::addi(lhs, rhs, location),
+            "-" => arith::subi(lhs, rhs, location),
+            "*" => arith::muli(lhs, rhs, location),
+            "/" => arith::divsi(lhs, rhs, location),
+            "&" => arith::andi(lhs, rhs, location),
+            "|" => arith::ori(lhs, rhs, location),
+            "^" => arith::xori(lhs, rhs, location),
+            "<<" => arith::shli(lhs, rhs, location),
+            ">>" => arith::shrsi(lhs, rhs, location),
+            _ => return Err("Unsupported binary operator"),
+        };
+
+        let value = block.append_operation(operation).result(0).unwrap().into();
+
+        Ok(Some(value))
+    }
+
+    /// Float arithmetic and comparisons. Comparing with NaN is false, except
+    /// for `!=`.
+    fn compile_float_binary<'a>(
+        &self,
+        block: &'a Block<'c>,
+        binary: &parser::Binary,
+        lhs: Value<'c, 'a>,
+        rhs: Value<'c, 'a>,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let location = Location::unknown(&self.context);
+
+        let predicate = match binary.op.as_str() {
+            "==" => Some(arith::CmpfPredicate::Oeq),
+            // Unordered, so NaN != NaN
+            "!=" => Some(arith::CmpfPredicate::Une),
+            "<" => Some(arith::CmpfPredicate::Olt),
+            "<=" => Some(arith::CmpfPredicate::Ole),
+            ">" => Some(arith::CmpfPredicate::Ogt),
+            ">=" => Some(arith::CmpfPredicate::Oge),
+            _ => None,
+        };
+
+        let operation = match (predicate, binary.op.as_str()) {
+            (Some(predicate), _) => arith::cmpf(&self.context, predicate, lhs, rhs, location),
+            (None, "+") => arith::addf(lhs, rhs, location),
+            (None, "-") => arith::subf(lhs, rhs, location),
+            (None, "*") => arith::mulf(lhs, rhs, location),
+            (None, "/") => arith::divf(lhs, rhs, location),
+            _ => return Err("Unsupported binary operator for Float"),
+        };
+
+        let value = block.append_operation(operation).result(0).unwrap().into();
+
+        Ok(Some(value))
+    }
+
+    /// `a && b` is `if a then b else false` and `a || b` is
+    /// `if a then true else b`, so `b` only runs when it's needed.
+    fn compile_logical<'a>(
+        &self,
+        block: &'a Block<'c>,
+        binary: &parser::Binary,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let left = self.compile_condition(block, &binary.left, ctx, mctx)?;
+        let right = self.compile_condition_region(&binary.right, ctx, mctx)?;
+
+        let (then_region, else_region) = match binary.op.as_str() {
+            "&&" => (right, self.compile_bool_region(false)),
+            "||" => (self.compile_bool_region(true), right),
+            _ => return Err("Unsupported logical operator"),
+        };
+
+        let if_op = block.append_operation(scf::r#if(
+            left,
+            &[self.llvm_types.i1_type],
+            then_region,
+            else_region,
+            Location::unknown(&self.context),
+        ));
+
+        Ok(Some(if_op.result(0).unwrap().into()))
     }
 
     fn compile_local_var<'a>(
@@ -1943,12 +2592,30 @@ impl<'c, 'm> Compiler<'c, 'm> {
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
         let lvar_value = match self.get_lvar(&lvar.name, ctx) {
             Some(value) => value,
-            None => todo!(),
+            // An instance first assigned in a branch, kept in a slot
+            None => match (self.get_lvar_store(&lvar.name, ctx), &lvar.return_type) {
+                (Some(slot), Some(return_type)) => {
+                    let loaded_val = block
+                        .append_operation(llvm::load(
+                            &self.context,
+                            slot,
+                            self.basetype_to_mlir_type(return_type),
+                            Location::unknown(&self.context),
+                            Default::default(),
+                        ))
+                        .result(0)
+                        .unwrap()
+                        .into();
+
+                    return Ok(Some(loaded_val));
+                }
+                _ => todo!(),
+            },
         };
 
         let lvar_type = match &lvar.return_type {
             Some(base_type) => match base_type {
-                BaseType::Class(_) => return Ok(Some(lvar_value)),
+                BaseType::Class(_) | BaseType::Optional(_) => return Ok(Some(lvar_value)),
                 _base_type => self.basetype_to_mlir_type(_base_type),
             },
             None => todo!(),
@@ -2008,12 +2675,272 @@ impl<'c, 'm> Compiler<'c, 'm> {
         Ok(Some(loaded_val))
     }
 
+    /// Builds an array literal by pushing each item onto a new runtime array,
+    /// which is created with room for all of them.
     fn compile_array<'a>(
         &self,
         block: &'a Block<'c>,
         array_node: &parser::Array,
         ctx: &mut FnCtx<'c, 'a>,
         mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let kind = match array_slot_kind(&array_node.item_type) {
+            Some(kind) => kind,
+            None => return Err("Arrays can't hold items of this type yet"),
+        };
+        let slot_type = array_slot_type(kind);
+        let ptr_type: Type<'c> = self.llvm_types.i8_ptr_type.into();
+
+        let capacity = block
+            .append_operation(arith::constant(
+                &self.context,
+                IntegerAttribute::new(
+                    self.llvm_types.i64_type.clone(),
+                    array_node.items.len() as i64,
+                )
+                .into(),
+                Location::unknown(&self.context),
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+
+        let array = self
+            .append_call(block, "pj_array_new", &[capacity], Some(ptr_type))
+            .unwrap();
+        let push_fn_name = format!("pj_array_push_{}", kind);
+
+        for item in &array_node.items {
+            let value = self.compile_expr(block, item, ctx, mctx)?.unwrap();
+            let value = self.compile_type_cast(
+                block,
+                value,
+                self.node_base_type(item).unwrap(),
+                slot_type.clone(),
+            );
+
+            // Pushing returns the same array
+            self.append_call(block, &push_fn_name, &[array, value], Some(ptr_type));
+        }
+
+        Ok(Some(array))
+    }
+
+    /// `items.each(f)` and `items.map(f)` call the function `f` refers to
+    /// with each item in turn, counting up to the length the array had when
+    /// the loop started in an `scf.while`. `map` pushes each result onto a
+    /// new array and returns it.
+    fn compile_array_iteration<'a>(
+        &self,
+        block: &'a Block<'c>,
+        send_node: &parser::Send,
+        call_node: &parser::Call,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let location = Location::unknown(&self.context);
+        let ptr_type: Type<'c> = self.llvm_types.i8_ptr_type.into();
+        let i64_type: Type<'c> = self.llvm_types.i64_type.into();
+
+        let item_kind = match self.node_base_type(&send_node.receiver) {
+            Some(BaseType::Array(item_type)) => array_slot_kind(&item_type).unwrap(),
+            _ => return Err("Expected an array to iterate over"),
+        };
+        let item_slot_type = array_slot_type(item_kind);
+
+        let fn_name = match call_node.args.first() {
+            Some(Node::FnRef(fn_ref)) => &fn_ref.fn_name,
+            _ => return Err("Expected a function reference to call with each item"),
+        };
+        let prototype = self
+            .parser_result
+            .index
+            .fn_prototype_index
+            .get(fn_name)
+            .unwrap();
+
+        let array = self
+            .compile_expr(block, &send_node.receiver, ctx, mctx)?
+            .unwrap();
+        let length = self
+            .append_call(block, "pj_array_length", &[array], Some(i64_type))
+            .unwrap();
+
+        // The array `map` returns, and the slot type of its items
+        let mapped = match &send_node.return_type {
+            Some(BaseType::Array(result_type)) => {
+                let result_kind = array_slot_kind(result_type).unwrap();
+                let mapped = self
+                    .append_call(block, "pj_array_new", &[length], Some(ptr_type))
+                    .unwrap();
+
+                Some((mapped, result_kind))
+            }
+            _ => None,
+        };
+
+        let zero = block
+            .append_operation(arith::constant(
+                &self.context,
+                IntegerAttribute::new(self.llvm_types.i64_type.clone(), 0).into(),
+                location,
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+        let counter = self.append_alloca_store(zero, block);
+
+        let before = Block::new(&[]);
+        let index = before
+            .append_operation(llvm::load(
+                &self.context,
+                counter,
+                i64_type,
+                location,
+                Default::default(),
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+        let in_bounds = before
+            .append_operation(arith::cmpi(
+                &self.context,
+                arith::CmpiPredicate::Slt,
+                index,
+                length,
+                location,
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+        before.append_operation(scf::condition(in_bounds, &[], location));
+
+        let after = Block::new(&[]);
+        let index = after
+            .append_operation(llvm::load(
+                &self.context,
+                counter,
+                i64_type,
+                location,
+                Default::default(),
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+
+        let item = self
+            .append_call(
+                &after,
+                &format!("pj_array_get_{}", item_kind),
+                &[array, index],
+                Some(self.basetype_to_mlir_type(&item_slot_type)),
+            )
+            .unwrap();
+        let item = self.compile_type_cast(
+            &after,
+            item,
+            item_slot_type,
+            prototype.args[0].return_type.clone(),
+        );
+
+        let result = self.append_call(
+            &after,
+            &parser::mangle_method_name(fn_name),
+            &[item],
+            prototype
+                .return_type
+                .as_ref()
+                .map(|return_type| self.basetype_to_mlir_type(return_type)),
+        );
+
+        if let (Some((mapped, result_kind)), Some(result), Some(return_type)) =
+            (mapped, result, &prototype.return_type)
+        {
+            let result = self.compile_type_cast(
+                &after,
+                result,
+                return_type.clone(),
+                array_slot_type(result_kind),
+            );
+
+            self.append_call(
+                &after,
+                &format!("pj_array_push_{}", result_kind),
+                &[mapped, result],
+                Some(ptr_type),
+            );
+        }
+
+        let one = after
+            .append_operation(arith::constant(
+                &self.context,
+                IntegerAttribute::new(self.llvm_types.i64_type.clone(), 1).into(),
+                location,
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+        let next_index = after
+            .append_operation(arith::addi(index, one, location))
+            .result(0)
+            .unwrap()
+            .into();
+        after.append_operation(llvm::store(
+            &self.context,
+            next_index,
+            counter,
+            location,
+            Default::default(),
+        ));
+        after.append_operation(scf::r#yield(&[], location));
+
+        let before_region = Region::new();
+        before_region.append_block(before);
+
+        let after_region = Region::new();
+        after_region.append_block(after);
+
+        block.append_operation(scf::r#while(
+            &[],
+            &[],
+            before_region,
+            after_region,
+            location,
+        ));
+
+        Ok(mapped.map(|(mapped, _)| mapped))
+    }
+
+    /// Calls `fn_name` directly, for calls the compiler generates itself
+    /// rather than compiling from a node.
+    fn append_call<'a>(
+        &self,
+        block: &'a Block<'c>,
+        fn_name: &str,
+        args: &[Value<'c, '_>],
+        result: Option<Type<'c>>,
+    ) -> Option<Value<'c, 'a>> {
+        let results: Vec<Type<'c>> = result.into_iter().collect();
+
+        block
+            .append_operation(llvm::call(
+                &self.context,
+                FlatSymbolRefAttribute::new(&self.context, fn_name),
+                args,
+                &results,
+                Location::unknown(&self.context),
+            ))
+            .result(0)
+            .ok()
+            .map(|result| result.into())
+    }
+
+    fn compile_build_struct<'a>(
+        &self,
+        block: &'a Block<'c>,
+        build_struct_node: &parser::BuildStruct,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
         let size = block
             .append_operation(arith::constant(
@@ -2025,14 +2952,16 @@ impl<'c, 'm> Compiler<'c, 'm> {
             .unwrap()
             .into();
 
-        let array_item_type = self.basetype_to_mlir_type(&array_node.item_type);
-        let array_type = self.basetype_to_mlir_type(&BaseType::Array(
-            array_node.length,
-            Box::new(array_node.item_type.clone()),
-        ));
-        let ptr_type = r#type::pointer(array_type, 0);
+        let struct_node = self
+            .parser_result
+            .index
+            .struct_index
+            .get(&build_struct_node.name)
+            .unwrap();
+        let struct_type = self.basetype_to_mlir_type(&struct_node.return_type);
+        let ptr_type = r#type::pointer(struct_type, 0);
 
-        let array_alloca = block
+        let struct_alloca = block
             .append_operation(llvm::alloca(
                 &self.context,
                 size,
@@ -2046,15 +2975,17 @@ impl<'c, 'm> Compiler<'c, 'm> {
             .unwrap()
             .into();
 
-        for (index, arg) in array_node.items.iter().enumerate() {
+        for (index, arg) in build_struct_node.args.iter().enumerate() {
             let mut return_val = self.compile_expr(block, arg, ctx, mctx).unwrap();
+            let struct_attr_type = &struct_node.attributes[index].return_type;
+            let return_type = self.basetype_to_mlir_type(struct_attr_type);
 
             let gep = block
                 .append_operation(llvm::get_element_ptr(
                     &self.context,
-                    array_alloca,
+                    struct_alloca,
                     DenseI32ArrayAttribute::new(&self.context, &[0, index as i32]),
-                    llvm::r#type::r#pointer(array_item_type, 0),
+                    llvm::r#type::r#pointer(return_type, 0),
                     Location::unknown(&self.context),
                 ))
                 .result(0)
@@ -2065,7 +2996,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
                 block,
                 return_val.unwrap(),
                 self.node_base_type(arg).unwrap(),
-                array_node.item_type.clone(),
+                struct_attr_type.clone(),
             ));
 
             block.append_operation(llvm::store(
@@ -2080,7 +3011,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
         // let result = block
         //     .append_operation(llvm::load(
         //         &self.context,
-        //         array_alloca,
+        //         struct_alloca,
         //         struct_type,
         //         Location::unknown(&self.context),
         //         Default::default(),
@@ -2089,156 +3020,364 @@ impl<'c, 'm> Compiler<'c, 'm> {
         //     .unwrap()
         //     .into();
 
-        Ok(Some(array_alloca))
+        Ok(Some(struct_alloca))
         // Ok(Some(result))
     }
 
-    fn compile_build_struct<'a>(
+    fn compile_loop<'a>(
         &self,
         block: &'a Block<'c>,
-        build_struct_node: &parser::BuildStruct,
+        loop_node: &parser::Loop,
         ctx: &mut FnCtx<'c, 'a>,
         mctx: &mut ModuleCtx,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
-        let size = block
-            .append_operation(arith::constant(
-                &self.context,
-                IntegerAttribute::new(self.llvm_types.i64_type.clone(), 1).into(),
-                Location::unknown(&self.context),
-            ))
+        self.compile_while_loop(block, None, &loop_node.body, ctx, mctx)
+    }
+
+    fn compile_while<'a>(
+        &self,
+        block: &'a Block<'c>,
+        while_node: &parser::While,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        self.compile_while_loop(
+            block,
+            Some(&while_node.condition),
+            &while_node.body,
+            ctx,
+            mctx,
+        )
+    }
+
+    /// Lowers `loop` and `while` to `scf.while`, which can't be left in the
+    /// middle of an iteration. `break` and `next` set a flag instead: the rest
+    /// of the iteration only runs while neither is set, and the loop only
+    /// goes around again while `break` isn't. A `loop` has no condition.
+    fn compile_while_loop<'a>(
+        &self,
+        block: &'a Block<'c>,
+        condition: Option<&Node>,
+        body: &[Node],
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let false_value = self.compile_bool(block, false);
+
+        let exits = LoopExits {
+            broken: self.append_alloca_store(false_value, block),
+            skipped: self.append_alloca_store(false_value, block),
+        };
+
+        block.append_operation(scf::r#while(
+            &[],
+            &[],
+            self.compile_loop_condition(condition, exits, ctx, mctx)?,
+            self.compile_block(body, false, Some(exits), ctx, mctx)?,
+            Location::unknown(&self.context),
+        ));
+
+        Ok(None)
+    }
+
+    /// The before region of a loop, which goes around again while the
+    /// condition holds and nothing ran `break` or `return`.
+    fn compile_loop_condition(
+        &self,
+        condition: Option<&Node>,
+        exits: LoopExits<'c, 'm>,
+        ctx: &mut FnCtx<'c, 'm>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Region<'c>, &'static str> {
+        let location = Location::unknown(&self.context);
+        let builder = Block::new(&[]);
+        let fn_return = self.get_fn_return(ctx);
+
+        let mut block_ctx = FnCtx {
+            lvars: HashMap::new(),
+            lvar_stores: HashMap::new(),
+            loop_exits: None,
+            fn_return: None,
+            parent_ctx: Some(Box::new(ctx)),
+        };
+
+        let mut broken = self.load_flag(&builder, exits.broken);
+
+        // A `return` stops every loop it's in, not only the innermost one
+        if let Some(fn_return) = fn_return {
+            let returned = self.load_flag(&builder, fn_return.returned);
+
+            broken = builder
+                .append_operation(arith::ori(broken, returned, location))
+                .result(0)
+                .unwrap()
+                .into();
+        }
+
+        let true_value = self.compile_bool(&builder, true);
+        let not_broken = builder
+            .append_operation(arith::xori(broken, true_value, location))
             .result(0)
             .unwrap()
             .into();
 
-        let struct_node = self
-            .parser_result
-            .index
-            .struct_index
-            .get(&build_struct_node.name)
-            .unwrap();
-        let struct_type = self.basetype_to_mlir_type(&struct_node.return_type);
-        let ptr_type = r#type::pointer(struct_type, 0);
+        let keep_going = match condition {
+            Some(condition) => {
+                let condition =
+                    self.compile_condition(&builder, condition, &mut block_ctx, mctx)?;
+
+                builder
+                    .append_operation(arith::andi(condition, not_broken, location))
+                    .result(0)
+                    .unwrap()
+                    .into()
+            }
+            None => not_broken,
+        };
+
+        builder.append_operation(scf::condition(keep_going, &[], location));
+
+        let region = Region::new();
+        region.append_block(builder);
+        Ok(region)
+    }
+
+    /// `break` and `next` set a flag of the innermost loop, see
+    /// `compile_while_loop`.
+    fn compile_loop_exit<'a>(
+        &self,
+        block: &'a Block<'c>,
+        node: &Node,
+        ctx: &FnCtx<'c, 'a>,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let exits = match self.get_loop_exits(ctx) {
+            Some(exits) => exits,
+            None => return Err("`break` and `next` can only be used in a loop"),
+        };
+
+        let flag = match node {
+            Node::Break => exits.broken,
+            _ => exits.skipped,
+        };
+
+        let true_value = self.compile_bool(block, true);
+
+        block.append_operation(llvm::store(
+            &self.context,
+            true_value,
+            flag,
+            Location::unknown(&self.context),
+            Default::default(),
+        ));
+
+        Ok(None)
+    }
+
+    /// Lowers to `scf.if`, which becomes basic blocks joined by a block
+    /// argument when scf is lowered to cf. An `if` with a `return_type` yields
+    /// the value of the last expression of each branch.
+    fn compile_if<'a>(
+        &self,
+        block: &'a Block<'c>,
+        if_node: &parser::If,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let condition = self.compile_condition(block, &if_node.condition, ctx, mctx)?;
+
+        let result_types = match &if_node.return_type {
+            Some(return_type) => vec![self.basetype_to_mlir_type(return_type)],
+            None => vec![],
+        };
+
+        let yields_value = !result_types.is_empty();
+
+        let if_op = block.append_operation(scf::r#if(
+            condition,
+            &result_types,
+            self.compile_block(&if_node.then_body, yields_value, None, ctx, mctx)?,
+            self.compile_block(&if_node.else_body, yields_value, None, ctx, mctx)?,
+            Location::unknown(&self.context),
+        ));
+
+        if yields_value {
+            Ok(Some(if_op.result(0).unwrap().into()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The condition of an `if` or `while` as an `i1`. Integers are true when
+    /// they aren't 0.
+    fn compile_condition<'a>(
+        &self,
+        block: &'a Block<'c>,
+        node: &Node,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Value<'c, 'a>, &'static str> {
+        let location = Location::unknown(&self.context);
+
+        let condition = match self.compile_expr(block, node, ctx, mctx)? {
+            Some(value) => value,
+            None => return Err("condition has no value"),
+        };
 
-        let struct_alloca = block
-            .append_operation(llvm::alloca(
+        if condition.r#type() == Type::from(IntegerType::new(&self.context, 1)) {
+            return Ok(condition);
+        }
+
+        let zero = block.append_operation(arith::constant(
+            &self.context,
+            IntegerAttribute::new(condition.r#type(), 0).into(),
+            location,
+        ));
+
+        Ok(block
+            .append_operation(arith::cmpi(
                 &self.context,
-                size,
-                ptr_type,
-                // struct_type,
-                Location::unknown(&self.context),
-                Default::default(),
-                // AllocaOptions::new().elem_type(Some(TypeAttribute::new(value.r#type()))),
+                arith::CmpiPredicate::Ne,
+                condition,
+                zero.result(0).unwrap().into(),
+                location,
             ))
             .result(0)
             .unwrap()
-            .into();
-
-        for (index, arg) in build_struct_node.args.iter().enumerate() {
-            let mut return_val = self.compile_expr(block, arg, ctx, mctx).unwrap();
-            let struct_attr_type = &struct_node.attributes[index].return_type;
-            let return_type = self.basetype_to_mlir_type(struct_attr_type);
+            .into())
+    }
 
-            let gep = block
-                .append_operation(llvm::get_element_ptr(
-                    &self.context,
-                    struct_alloca,
-                    DenseI32ArrayAttribute::new(&self.context, &[0, index as i32]),
-                    llvm::r#type::r#pointer(return_type, 0),
-                    Location::unknown(&self.context),
-                ))
-                .result(0)
-                .unwrap()
-                .into();
+    /// A region of `nodes` ending in `scf.yield`, of the value of the last node
+    /// when `yields_value` is set. Locals first assigned in it are scoped to
+    /// it.
+    ///
+    /// The body of a loop is given the loop's flags, which are reset at the
+    /// start of each iteration.
+    fn compile_block(
+        &self,
+        nodes: &[Node],
+        yields_value: bool,
+        loop_exits: Option<LoopExits<'c, 'm>>,
+        ctx: &mut FnCtx<'c, 'm>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Region<'c>, &'static str> {
+        let builder = Block::new(&[]);
 
-            return_val = Some(self.compile_type_cast(
-                block,
-                return_val.unwrap(),
-                self.node_base_type(arg).unwrap(),
-                struct_attr_type.clone(),
-            ));
+        if let Some(exits) = loop_exits {
+            let false_value = self.compile_bool(&builder, false);
 
-            block.append_operation(llvm::store(
+            builder.append_operation(llvm::store(
                 &self.context,
-                return_val.unwrap(),
-                gep,
+                false_value,
+                exits.skipped,
                 Location::unknown(&self.context),
                 Default::default(),
             ));
         }
 
-        // let result = block
-        //     .append_operation(llvm::load(
-        //         &self.context,
-        //         struct_alloca,
-        //         struct_type,
-        //         Location::unknown(&self.context),
-        //         Default::default(),
-        //     ))
-        //     .result(0)
-        //     .unwrap()
-        //     .into();
+        let mut block_ctx = FnCtx {
+            lvars: HashMap::new(),
+            lvar_stores: HashMap::new(),
+            loop_exits,
+            fn_return: None,
+            parent_ctx: Some(Box::new(ctx)),
+        };
 
-        Ok(Some(struct_alloca))
-        // Ok(Some(result))
+        let last_value = self.compile_statements(&builder, nodes, &mut block_ctx, mctx)?;
+
+        let yielded = match (yields_value, last_value) {
+            (true, Some(value)) => vec![value],
+            (true, None) => return Err("if branch has no value to yield"),
+            (false, _) => vec![],
+        };
+
+        builder.append_operation(scf::r#yield(&yielded, Location::unknown(&self.context)));
+
+        let region = Region::new();
+        region.append_block(builder);
+        Ok(region)
     }
 
-    fn compile_loop<'a>(
+    /// Compiles `nodes` in order and returns the value of the last one. The
+    /// nodes after one that may run `break`, `next` or an early `return` are
+    /// wrapped in an `scf.if` on the loop's and the def's flags.
+    fn compile_statements<'a>(
         &self,
         block: &'a Block<'c>,
-        loop_node: &parser::Loop,
+        nodes: &[Node],
         ctx: &mut FnCtx<'c, 'a>,
         mctx: &mut ModuleCtx,
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
         let location = Location::unknown(&self.context);
-        let index_type = Type::index(&self.context);
+        let mut last_value = None;
 
-        let initial = block.append_operation(arith::constant(
-            &self.context,
-            // IntegerAttribute::new(index_type, 1).into(),
-            IntegerAttribute::new(IntegerType::new(&self.context, 1).into(), 1).into(),
-            location,
-        ));
+        for (index, node) in nodes.iter().enumerate() {
+            last_value = self.compile_expr(block, node, ctx, mctx)?;
 
-        block.append_operation(scf::r#while(
-            &[],
-            &[],
-            {
-                let before_block = Block::new(&[]);
-
-                // let condition = block.append_operation(arith::constant(
-                //     &self.context,
-                //     IntegerAttribute::new(IntegerType::new(&self.context, 1).into(), 0)
-                //         .into(),
-                //     location,
-                // ));
-
-                // let result = block.append_operation(arith::constant(
-                //     &self.context,
-                //     IntegerAttribute::new(Type::index(&self.context), 42).into(),
-                //     location,
-                // ));
-
-                before_block.append_operation(scf::condition(
-                    // arith::constant( &self.context, IntegerAttribute::new(IntegerType::new(&self.context, 1).into(), 0)).into()
-                    initial.result(0).unwrap().into(),
-                    &[],
-                    location,
-                ));
+            // Nothing after these runs
+            if matches!(node, Node::Break | Node::Next | Node::Ret(_)) {
+                return Ok(last_value);
+            }
 
-                let region = Region::new();
-                region.append_block(before_block);
-                region
-            },
-            self.compile_block(&loop_node.body, ctx, mctx)?,
-            location,
-        ));
+            let rest = &nodes[index + 1..];
+            let fn_return = self.get_fn_return(ctx).filter(|_| may_return(node));
 
-        Ok(None)
+            if rest.is_empty() || (!exits_loop(node) && fn_return.is_none()) {
+                continue;
+            }
+
+            let mut exited = self.compile_bool(block, false);
+
+            if exits_loop(node) {
+                let exits = match self.get_loop_exits(ctx) {
+                    Some(exits) => exits,
+                    None => return Err("`break` and `next` can only be used in a loop"),
+                };
+
+                let broken = self.load_flag(block, exits.broken);
+                let skipped = self.load_flag(block, exits.skipped);
+
+                exited = block
+                    .append_operation(arith::ori(broken, skipped, location))
+                    .result(0)
+                    .unwrap()
+                    .into();
+            }
+
+            if let Some(fn_return) = fn_return {
+                let returned = self.load_flag(block, fn_return.returned);
+
+                exited = block
+                    .append_operation(arith::ori(exited, returned, location))
+                    .result(0)
+                    .unwrap()
+                    .into();
+            }
+
+            let true_value = self.compile_bool(block, true);
+            let running = block
+                .append_operation(arith::xori(exited, true_value, location))
+                .result(0)
+                .unwrap()
+                .into();
+
+            block.append_operation(scf::r#if(
+                running,
+                &[],
+                self.compile_block(rest, false, None, ctx, mctx)?,
+                Region::new(),
+                location,
+            ));
+
+            return Ok(None);
+        }
+
+        Ok(last_value)
     }
 
-    fn compile_block(
+    /// A region yielding `node` as a condition, see `compile_condition`.
+    fn compile_condition_region(
         &self,
-        nodes: &Vec<Node>,
+        node: &Node,
         ctx: &mut FnCtx<'c, 'm>,
         mctx: &mut ModuleCtx,
     ) -> Result<Region<'c>, &'static str> {
@@ -2247,20 +3386,158 @@ impl<'c, 'm> Compiler<'c, 'm> {
         let mut block_ctx = FnCtx {
             lvars: HashMap::new(),
             lvar_stores: HashMap::new(),
+            loop_exits: None,
+            fn_return: None,
             parent_ctx: Some(Box::new(ctx)),
         };
 
-        nodes.iter().for_each(|node| {
-            self.compile_expr(&builder, node, &mut block_ctx, mctx);
-        });
+        let condition = self.compile_condition(&builder, node, &mut block_ctx, mctx)?;
 
-        builder.append_operation(scf::r#yield(&[], Location::unknown(&self.context)));
+        builder.append_operation(scf::r#yield(&[condition], Location::unknown(&self.context)));
 
         let region = Region::new();
         region.append_block(builder);
         Ok(region)
     }
 
+    /// `nil`, a null pointer of the optional it's given as, or a `BytePtr`
+    /// when that isn't known.
+    fn compile_nil<'a>(&self, block: &'a Block<'c>, nil: &parser::Nil) -> Value<'c, 'a> {
+        let base_type = nil.return_type.clone().unwrap_or(BaseType::Nil);
+
+        self.compile_null(block, self.basetype_to_mlir_type(&base_type))
+    }
+
+    fn compile_null<'a>(&self, block: &'a Block<'c>, r#type: Type<'c>) -> Value<'c, 'a> {
+        block
+            .append_operation(
+                OperationBuilder::new("llvm.mlir.zero", Location::unknown(&self.context))
+                    .add_results(&[r#type])
+                    .build()
+                    .unwrap(),
+            )
+            .result(0)
+            .unwrap()
+            .into()
+    }
+
+    /// Whether the pointer `value` is null, as an `i1`.
+    fn compile_is_nil<'a>(&self, block: &'a Block<'c>, value: Value<'c, '_>) -> Value<'c, 'a> {
+        let location = Location::unknown(&self.context);
+
+        let address = block
+            .append_operation(
+                OperationBuilder::new("llvm.ptrtoint", location)
+                    .add_operands(&[value])
+                    .add_results(&[self.llvm_types.i64_type.into()])
+                    .build()
+                    .unwrap(),
+            )
+            .result(0)
+            .unwrap()
+            .into();
+
+        let zero = block
+            .append_operation(arith::constant(
+                &self.context,
+                IntegerAttribute::new(self.llvm_types.i64_type.into(), 0).into(),
+                location,
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+
+        block
+            .append_operation(arith::cmpi(
+                &self.context,
+                arith::CmpiPredicate::Eq,
+                address,
+                zero,
+                location,
+            ))
+            .result(0)
+            .unwrap()
+            .into()
+    }
+
+    /// `x == nil` and `x != nil`, which compare the pointer `x` with null.
+    fn compile_nil_comparison<'a>(
+        &self,
+        block: &'a Block<'c>,
+        binary: &parser::Binary,
+        ctx: &mut FnCtx<'c, 'a>,
+        mctx: &mut ModuleCtx,
+    ) -> Result<Option<Value<'c, 'a>>, &'static str> {
+        let operand = match (binary.left.as_ref(), binary.right.as_ref()) {
+            (Node::Nil(_), operand) | (operand, Node::Nil(_)) => operand,
+            _ => return Err("Expected a comparison with nil"),
+        };
+
+        let is_nil = match operand {
+            // `nil == nil`
+            Node::Nil(_) => self.compile_bool(block, true),
+            operand => {
+                let value = self.compile_expr(block, operand, ctx, mctx)?.unwrap();
+                self.compile_is_nil(block, value)
+            }
+        };
+
+        if binary.op == "==" {
+            return Ok(Some(is_nil));
+        }
+
+        let true_value = self.compile_bool(block, true);
+        let is_not_nil = block
+            .append_operation(arith::xori(
+                is_nil,
+                true_value,
+                Location::unknown(&self.context),
+            ))
+            .result(0)
+            .unwrap()
+            .into();
+
+        Ok(Some(is_not_nil))
+    }
+
+    fn compile_bool_region(&self, value: bool) -> Region<'c> {
+        let builder = Block::new(&[]);
+        let value = self.compile_bool(&builder, value);
+
+        builder.append_operation(scf::r#yield(&[value], Location::unknown(&self.context)));
+
+        let region = Region::new();
+        region.append_block(builder);
+        region
+    }
+
+    fn compile_bool<'a>(&self, block: &'a Block<'c>, value: bool) -> Value<'c, 'a> {
+        block
+            .append_operation(arith::constant(
+                &self.context,
+                IntegerAttribute::new(IntegerType::new(&self.context, 1).into(), value as i64)
+                    .into(),
+                Location::unknown(&self.context),
+            ))
+            .result(0)
+            .unwrap()
+            .into()
+    }
+
+    fn load_flag<'a>(&self, block: &'a Block<'c>, flag: Value<'c, '_>) -> Value<'c, 'a> {
+        block
+            .append_operation(llvm::load(
+                &self.context,
+                flag,
+                IntegerType::new(&self.context, 1).into(),
+                Location::unknown(&self.context),
+                Default::default(),
+            ))
+            .result(0)
+            .unwrap()
+            .into()
+    }
+
     // let builder = Block::new(&[]);
     // let mut variables = variables.fork();
 
@@ -2310,11 +3587,13 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::AssignLocalVar(_) => todo!(),
             Node::Attribute(_) => todo!(),
             Node::Binary(_) => todo!(),
+            Node::Bool(_) => todo!(),
             Node::Call(_) => todo!(),
             Node::Class(_) => todo!(),
             Node::Def(_) => todo!(),
             Node::DefE(_) => todo!(),
             Node::Impl(_) => todo!(),
+            Node::Float(_) => todo!(),
             Node::Int(_) => todo!(),
             Node::StringLiteral(_) => todo!(),
             Node::LocalVar(lvar) => {
@@ -2341,6 +3620,11 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::BuildStruct(_) => todo!(),
             Node::Struct(_) => todo!(),
             Node::FnRef(_) => todo!(),
+            Node::If(_) => todo!(),
+            Node::While(_) => todo!(),
+            Node::Break => todo!(),
+            Node::Next => todo!(),
+            Node::Nil(_) => todo!(),
         };
 
         // let sret_value = ctx.lvar_stores.get(&asgn_attr.name);
@@ -2488,8 +3772,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
     ) -> Result<Option<Value<'c, 'a>>, &'static str> {
         // let sret_value = ctx.lvar_stores.get(&asgn_attr.name);
 
-        println!("lvar: {:#?}", asgn_attr.value.as_ref());
-        println!("{:#?}", ctx.lvars);
+        tracing::trace!("lvar: {:#?}", asgn_attr.value.as_ref());
+        tracing::trace!("{:#?}", ctx.lvars);
 
         let return_val = match asgn_attr.value.as_ref() {
             // Node::LocalVar(lvar) => match ctx.lvar_stores.get(&lvar.name) {
@@ -2585,8 +3869,31 @@ impl<'c, 'm> Compiler<'c, 'm> {
         //     Node::AssignConstant(_) => todo!(),
         // };
 
-        println!("asgn_lvar");
-        println!("{:#?}", asgn_lvar);
+        // A local assigned again keeps its slot, also from the body of an
+        // `if` or a loop, so the value is still there after the body. An
+        // instance only has a slot when it's first assigned in a branch, see
+        // `compile_fn_body`.
+        if let Some(value) = return_val {
+            let ptr_type = r#type::pointer(value.r#type(), 0);
+
+            if let Some(ptr) = self
+                .get_lvar_store(&asgn_lvar.name, ctx)
+                .filter(|ptr| ptr.r#type() == ptr_type)
+            {
+                block.append_operation(llvm::store(
+                    &self.context,
+                    value,
+                    ptr,
+                    Location::unknown(&self.context),
+                    Default::default(),
+                ));
+
+                return Ok(return_val);
+            }
+        }
+
+        tracing::trace!("asgn_lvar");
+        tracing::trace!("{:#?}", asgn_lvar);
 
         match &return_type {
             Some(base_type) => {
@@ -2604,11 +3911,13 @@ impl<'c, 'm> Compiler<'c, 'm> {
                             return Ok(return_val);
                         }
                     }
+                    BaseType::Bool => {}
                     BaseType::Byte => {}
                     BaseType::Int16 => {}
                     BaseType::Int32 => {}
                     BaseType::Int64 => {}
-                    BaseType::Array(_, _) => {}
+                    BaseType::Float => {}
+                    BaseType::Array(_) => {}
                     BaseType::Struct(_) => {
                         ctx.lvars
                             .insert(asgn_lvar.name.clone(), return_val.unwrap());
@@ -2616,6 +3925,12 @@ impl<'c, 'm> Compiler<'c, 'm> {
                         return Ok(return_val);
                     }
                     BaseType::FnRef => {}
+                    BaseType::Optional(_) => {
+                        ctx.lvars
+                            .insert(asgn_lvar.name.clone(), return_val.unwrap());
+                        return Ok(return_val);
+                    }
+                    BaseType::Nil => {}
                 }
             }
             None => todo!(),
@@ -2691,6 +4006,11 @@ impl<'c, 'm> Compiler<'c, 'm> {
             .into()
     }
 
+    /// A `return` that ends the body gives its value to `compile_fn_body`
+    /// to return. One that may run earlier can't leave the nested `scf`
+    /// regions it's in, so like `break` it stores its value and sets a flag:
+    /// the statements after it only run while the flag isn't set, loops stop
+    /// going around, and the def returns the stored value at its end.
     fn compile_return<'a>(
         &self,
         block: &'a Block<'c>,
@@ -2703,7 +4023,32 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Err(e) => return Err(e),
         };
 
-        Ok(return_val)
+        let fn_return = match self.get_fn_return(ctx) {
+            Some(fn_return) => fn_return,
+            None => return Ok(return_val),
+        };
+
+        if let (Some(value), Some(return_val)) = (fn_return.value, return_val) {
+            block.append_operation(llvm::store(
+                &self.context,
+                return_val,
+                value,
+                Location::unknown(&self.context),
+                Default::default(),
+            ));
+        }
+
+        let true_value = self.compile_bool(block, true);
+
+        block.append_operation(llvm::store(
+            &self.context,
+            true_value,
+            fn_return.returned,
+            Location::unknown(&self.context),
+            Default::default(),
+        ));
+
+        Ok(None)
     }
 
     fn get_lvar<'a>(&self, key: &String, ctx: &FnCtx<'c, 'a>) -> Option<Value<'c, 'a>> {
@@ -2716,11 +4061,31 @@ impl<'c, 'm> Compiler<'c, 'm> {
         }
     }
 
+    fn get_loop_exits<'a>(&self, ctx: &FnCtx<'c, 'a>) -> Option<LoopExits<'c, 'a>> {
+        if let Some(exits) = ctx.loop_exits {
+            Some(exits)
+        } else if let Some(parent_ctx) = &ctx.parent_ctx {
+            self.get_loop_exits(&parent_ctx)
+        } else {
+            None
+        }
+    }
+
+    fn get_fn_return<'a>(&self, ctx: &FnCtx<'c, 'a>) -> Option<FnReturn<'c, 'a>> {
+        if let Some(fn_return) = ctx.fn_return {
+            Some(fn_return)
+        } else if let Some(parent_ctx) = &ctx.parent_ctx {
+            self.get_fn_return(&parent_ctx)
+        } else {
+            None
+        }
+    }
+
     fn get_lvar_store<'a>(&self, key: &String, ctx: &FnCtx<'c, 'a>) -> Option<Value<'c, 'a>> {
         if let Some(value) = ctx.lvar_stores.get(key) {
             Some(value.clone())
         } else if let Some(parent_ctx) = &ctx.parent_ctx {
-            self.get_lvar(key, &parent_ctx)
+            self.get_lvar_store(key, &parent_ctx)
         } else {
             None
         }
@@ -2731,10 +4096,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::Send(send_node) => send_node.return_type.clone(),
             Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
             Node::Access(node) => node.return_type.clone(),
-            Node::Array(array) => Some(BaseType::Array(
-                array.length,
-                Box::new(array.item_type.clone()),
-            )),
+            Node::Array(array) => Some(BaseType::Array(Box::new(array.item_type.clone()))),
             Node::BuildStruct(struct_node) => {
                 let entry = self
                     .parser_result
@@ -2749,7 +4111,7 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::AssignAttributeAccess(_) => todo!(),
             Node::AssignLocalVar(_) => todo!(),
             Node::Attribute(_) => todo!(),
-            Node::Binary(_) => todo!(),
+            Node::Binary(binary_node) => binary_node.return_type.clone(),
             Node::Call(call_node) => call_node.return_type.clone(),
             Node::Class(_) => todo!(),
             Node::Const(const_node) => {
@@ -2763,6 +4125,8 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::Def(_) => todo!(),
             Node::DefE(_) => todo!(),
             Node::Impl(_) => todo!(),
+            Node::Bool(_) => Some(BaseType::Bool),
+            Node::Float(_) => Some(BaseType::Float),
             Node::Int(_) => Some(BaseType::Int64),
             Node::LocalVar(lvar) => lvar.return_type.clone(),
             Node::Loop(_) => todo!(),
@@ -2772,6 +4136,11 @@ impl<'c, 'm> Compiler<'c, 'm> {
             Node::Trait(_) => todo!(),
             Node::AssignConstant(_) => todo!(),
             Node::FnRef(_) => Some(BaseType::FnRef),
+            Node::If(if_node) => if_node.return_type.clone(),
+            Node::While(_) => None,
+            Node::Break => None,
+            Node::Next => None,
+            Node::Nil(nil) => nil.return_type.clone().or(Some(BaseType::Nil)),
         }
     }
 
@@ -2779,27 +4148,35 @@ impl<'c, 'm> Compiler<'c, 'm> {
         match return_type {
             BaseType::Int => self.llvm_types.i64_type.into(),
             BaseType::Class(name) => {
-                println!("class_name {:#?}", name);
+                tracing::trace!("class_name {:#?}", name);
 
-                println!("{:#?}", name);
+                tracing::trace!("{:#?}", name);
                 match self.class_type_index.get(name) {
                     Some(struct_type) => llvm::r#type::r#pointer(*struct_type, 0),
+                    // Trait default methods receive any implementing instance
+                    None if self.parser_result.index.trait_index.contains_key(name) => {
+                        self.llvm_types.ptr_type
+                    }
                     None => self.struct_type_index.get(name).unwrap().clone(),
                 }
             }
             BaseType::BytePtr => self.llvm_types.i8_ptr_type.into(),
+            BaseType::Bool => self.llvm_types.i1_type,
             BaseType::Byte => self.llvm_types.i8_type.into(),
             BaseType::Int16 => self.llvm_types.i16_type.into(),
             BaseType::Int32 => self.llvm_types.i32_type.into(),
             BaseType::Int64 => self.llvm_types.i64_type.into(),
-            BaseType::Array(length, base_type) => {
-                llvm::r#type::array(self.basetype_to_mlir_type(base_type), *length as u32)
-            }
+            BaseType::Float => self.llvm_types.f64_type,
+            // A pointer to the runtime array, whatever its item type
+            BaseType::Array(_) => self.llvm_types.i8_ptr_type.into(),
             BaseType::Void => todo!(),
             BaseType::Struct(struct_name) => {
                 self.struct_type_index.get(struct_name).unwrap().clone()
             }
             BaseType::FnRef => self.llvm_types.ptr_type.into(),
+            // A null pointer when it's nil
+            BaseType::Optional(base_type) => self.basetype_to_mlir_type(base_type),
+            BaseType::Nil => self.llvm_types.i8_ptr_type.into(),
             // BaseType::FnRef => {self.llvm_types.fn_ptr.into()},
             // BaseType::Struct(base_types) => todo!(),
         }
@@ -2812,25 +4189,28 @@ fn basetype_to_mlir_type<'c>(llvm_types: LlvmTypes<'c>, return_type: &BaseType)
         // self.basetype_to_mlir_type
         BaseType::Class(_) => llvm_types.ptr_type,
 
-        BaseType::Array(length, base_type) => {
-            llvm::r#type::array(basetype_to_mlir_type(llvm_types, base_type), *length as u32)
-        }
+        BaseType::Array(_) => llvm_types.i8_ptr_type.clone().into(),
+        BaseType::Bool => llvm_types.i1_type,
         BaseType::Byte => llvm_types.i8_type.into(),
         BaseType::BytePtr => llvm_types.i8_ptr_type.clone().into(),
         BaseType::Int => llvm_types.i64_type.into(),
         BaseType::Int16 => llvm_types.i16_type.into(),
         BaseType::Int32 => llvm_types.i32_type.into(),
         BaseType::Int64 => llvm_types.i64_type.into(),
+        BaseType::Float => llvm_types.f64_type,
         BaseType::Void => todo!(),
         BaseType::Struct(_) => todo!(),
         // BaseType::FnRef => { llvm_types.fn_ptr },
         BaseType::FnRef => llvm_types.ptr_type,
+        BaseType::Optional(base_type) => basetype_to_mlir_type(llvm_types, base_type),
+        BaseType::Nil => llvm_types.i8_ptr_type.clone().into(),
     }
 }
 
 pub fn pajama_class_name(base_type: &BaseType) -> String {
     match base_type {
-        BaseType::Array(_, _) => "Array".to_string(),
+        BaseType::Array(_) => "Array".to_string(),
+        BaseType::Bool => "Bool".to_string(),
         BaseType::Byte => "Byte".to_string(),
         BaseType::BytePtr => "BytePtr".to_string(),
         BaseType::Class(class_name) => class_name.clone(),
@@ -2838,8 +4218,51 @@ pub fn pajama_class_name(base_type: &BaseType) -> String {
         BaseType::Int16 => "Int16".to_string(),
         BaseType::Int32 => "Int32".to_string(),
         BaseType::Int64 => "Int64".to_string(),
+        BaseType::Float => "Float".to_string(),
         BaseType::Void => "".to_string(),
         BaseType::Struct(_) => "Struct".to_string(),
         BaseType::FnRef => "FnRef".to_string(),
+        BaseType::Optional(base_type) => format!("{}?", pajama_class_name(base_type)),
+        BaseType::Nil => "Nil".to_string(),
+    }
+}
+
+/// Whether `node` may run `break` or `next` for the loop it's in. Loops nested
+/// in it have their own.
+fn exits_loop(node: &Node) -> bool {
+    match node {
+        Node::Break | Node::Next => true,
+        Node::If(if_node) => if_node
+            .then_body
+            .iter()
+            .chain(&if_node.else_body)
+            .any(exits_loop),
+        _ => false,
+    }
+}
+
+/// Whether `node` may run `return`, which leaves every block and loop it's
+/// in.
+fn may_return(node: &Node) -> bool {
+    match node {
+        Node::Ret(_) => true,
+        Node::If(if_node) => if_node
+            .then_body
+            .iter()
+            .chain(&if_node.else_body)
+            .any(may_return),
+        Node::Loop(loop_node) => loop_node.body.iter().any(may_return),
+        Node::While(while_node) => while_node.body.iter().any(may_return),
+        _ => false,
+    }
+}
+
+/// Whether a def with `body` may return before its last statement, see
+/// `compile_return`.
+fn returns_early(body: &[Node]) -> bool {
+    match body.split_last() {
+        Some((Node::Ret(_), rest)) => rest.iter().any(may_return),
+        Some(_) => body.iter().any(may_return),
+        None => false,
     }
 }
@@ -0,0 +1,64 @@
+use unicode_width::UnicodeWidthChar;
+
+/// How many columns a tab advances to the next multiple of when a line of
+/// source is printed with carets under it, picked with `--tab-width`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TabWidth(pub usize);
+
+impl Default for TabWidth {
+    fn default() -> TabWidth {
+        TabWidth(4)
+    }
+}
+
+pub fn parse_tab_width(width: &str) -> Result<TabWidth, String> {
+    match width.parse() {
+        Ok(width) if width > 0 => Ok(TabWidth(width)),
+        _ => Err(format!(
+            "invalid --tab-width `{}`, expected a number of columns, e.g. 4",
+            width
+        )),
+    }
+}
+
+/// Where the character at `column` of `line` is displayed, both counting
+/// from 1. Token positions count characters, but a tab takes up to the next
+/// tab stop on a terminal, and wide characters like CJK ideographs take two
+/// columns and combining marks none, by their Unicode East Asian Width.
+/// Columns past the end of the line, like its newline, take one each.
+pub fn display_column(line: &str, column: usize, tab_width: TabWidth) -> usize {
+    let mut chars = line.chars();
+
+    (1..column).fold(1, |display, _| match chars.next() {
+        Some(ch) => display + char_width(ch, display, tab_width),
+        None => display + 1,
+    })
+}
+
+/// `line` with its tabs replaced by the spaces they take up, so it lines up
+/// with carets whatever the terminal's tab stops are.
+pub fn expand_tabs(line: &str, tab_width: TabWidth) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut display = 1;
+
+    for ch in line.chars() {
+        let width = char_width(ch, display, tab_width);
+
+        match ch {
+            '\t' => expanded.push_str(&" ".repeat(width)),
+            _ => expanded.push(ch),
+        }
+
+        display += width;
+    }
+
+    expanded
+}
+
+/// The columns `ch` takes up when displayed starting at `display`
+fn char_width(ch: char, display: usize, tab_width: TabWidth) -> usize {
+    match ch {
+        '\t' => tab_width.0 - (display - 1) % tab_width.0,
+        _ => ch.width().unwrap_or(0),
+    }
+}
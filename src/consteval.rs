@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use crate::parser::{BaseType, Def, Node, ParserResult};
+
+/// How many nodes evaluating one call may visit before it's left as it is
+pub const DEFAULT_FUEL: usize = 10_000;
+
+/// Constant calls
+///
+/// A call to a def returning an Int with integer literals for arguments is
+/// evaluated at compile time and replaced by its result, so configuration
+/// style computation like `buffer_size(4, 1024)` doesn't run each time the
+/// program does.
+///
+/// Only defs made of integer arithmetic, comparisons, local variables, `if`
+/// and calls to other such defs can be evaluated. Anything else, running out
+/// of `fuel`, dividing by zero or overflowing leaves the call in place.
+///
+/// Runs after analysis and before dead method elimination, which then removes
+/// defs that are no longer called. Returns how many calls were replaced.
+pub fn fold_constant_calls(result: &mut ParserResult, fuel: usize) -> usize {
+    let module = match &mut result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    let mut count = 0;
+
+    for index in 0..module.methods.len() {
+        // Taken out while it's folded, so calls a def makes to itself are left
+        // as they are
+        let mut body = match &mut module.methods[index] {
+            Node::Def(def) => std::mem::take(&mut def.body),
+            _ => continue,
+        };
+
+        let defs: HashMap<&str, &Def> = module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) if !def.main_fn => Some((def.prototype.name.as_str(), def)),
+                _ => None,
+            })
+            .collect();
+
+        let evaluator = Evaluator { defs: &defs };
+
+        for body_node in body.iter_mut() {
+            evaluator.fold(body_node, fuel, &mut count);
+        }
+
+        if let Node::Def(def) = &mut module.methods[index] {
+            def.body = body;
+        }
+    }
+
+    count
+}
+
+struct Evaluator<'a> {
+    defs: &'a HashMap<&'a str, &'a Def>,
+}
+
+impl Evaluator<'_> {
+    /// Replaces the constant calls in `node`, innermost first so their results
+    /// can be the arguments of the calls around them.
+    fn fold(&self, node: &mut Node, fuel: usize, count: &mut usize) {
+        match node {
+            Node::AssignLocalVar(node) => self.fold(&mut node.value, fuel, count),
+            Node::AssignAttribute(node) => self.fold(&mut node.value, fuel, count),
+            Node::AssignAttributeAccess(node) => self.fold(&mut node.value, fuel, count),
+            Node::Binary(node) => {
+                self.fold(&mut node.left, fuel, count);
+                self.fold(&mut node.right, fuel, count);
+            }
+            Node::Call(call) => {
+                for arg in call.args.iter_mut() {
+                    self.fold(arg, fuel, count);
+                }
+
+                let mut fuel = fuel;
+
+                if let Some(value) = self.eval_call(&call.fn_name, &call.args, &mut fuel) {
+                    *node = Node::Int(crate::parser::Int {
+                        value: value as u64,
+                    });
+                    *count += 1;
+                }
+            }
+            Node::If(node) => {
+                self.fold(&mut node.condition, fuel, count);
+
+                for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                    self.fold(body_node, fuel, count);
+                }
+            }
+            Node::Loop(node) => {
+                for body_node in node.body.iter_mut() {
+                    self.fold(body_node, fuel, count);
+                }
+            }
+            Node::Ret(node) => self.fold(&mut node.value, fuel, count),
+            Node::Send(node) => self.fold(&mut node.message, fuel, count),
+            Node::While(node) => {
+                self.fold(&mut node.condition, fuel, count);
+
+                for body_node in node.body.iter_mut() {
+                    self.fold(body_node, fuel, count);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn eval_call(&self, fn_name: &str, args: &[Node], fuel: &mut usize) -> Option<i64> {
+        let def = self.defs.get(fn_name)?;
+
+        if !is_int(&def.prototype.return_type)
+            || def.prototype.args.len() != args.len()
+            || def.body.is_empty()
+        {
+            return None;
+        }
+
+        let mut lvars = HashMap::new();
+
+        for (arg, value) in def.prototype.args.iter().zip(args) {
+            if !is_int(&Some(arg.return_type.clone())) {
+                return None;
+            }
+
+            // Only literals, a local of the caller can't be evaluated here
+            let value = match value {
+                Node::Int(int) => int.value as i64,
+                _ => return None,
+            };
+
+            lvars.insert(arg.name.clone(), value);
+        }
+
+        self.eval_body(&def.body, &mut lvars, fuel)
+    }
+
+    fn eval_body(
+        &self,
+        nodes: &[Node],
+        lvars: &mut HashMap<String, i64>,
+        fuel: &mut usize,
+    ) -> Option<i64> {
+        let mut last_value = None;
+
+        for node in nodes {
+            last_value = match node {
+                Node::AssignLocalVar(assignment) => {
+                    let value = self.eval(&assignment.value, lvars, fuel)?;
+                    lvars.insert(assignment.name.clone(), value);
+                    Some(value)
+                }
+                Node::Ret(ret) => return self.eval(&ret.value, lvars, fuel),
+                node => Some(self.eval(node, lvars, fuel)?),
+            };
+        }
+
+        last_value
+    }
+
+    fn eval(&self, node: &Node, lvars: &mut HashMap<String, i64>, fuel: &mut usize) -> Option<i64> {
+        *fuel = fuel.checked_sub(1)?;
+
+        match node {
+            Node::Int(int) => Some(int.value as i64),
+            Node::Bool(boolean) => Some(boolean.value as i64),
+            Node::LocalVar(lvar) => lvars.get(&lvar.name).copied(),
+            Node::Binary(binary) => {
+                let left = self.eval(&binary.left, lvars, fuel)?;
+
+                // `&&` and `||` only evaluate their right operand when needed
+                match (binary.op.as_str(), left != 0) {
+                    ("&&", false) => return Some(0),
+                    ("||", true) => return Some(1),
+                    _ => {}
+                }
+
+                let right = self.eval(&binary.right, lvars, fuel)?;
+
+                match binary.op.as_str() {
+                    "+" => left.checked_add(right),
+                    "-" => left.checked_sub(right),
+                    "*" => left.checked_mul(right),
+                    "/" => left.checked_div(right),
+                    "&" => Some(left & right),
+                    "|" => Some(left | right),
+                    "^" => Some(left ^ right),
+                    "<<" => left.checked_shl(u32::try_from(right).ok()?),
+                    ">>" => left.checked_shr(u32::try_from(right).ok()?),
+                    "==" => Some((left == right) as i64),
+                    "!=" => Some((left != right) as i64),
+                    "<" => Some((left < right) as i64),
+                    "<=" => Some((left <= right) as i64),
+                    ">" => Some((left > right) as i64),
+                    ">=" => Some((left >= right) as i64),
+                    "&&" | "||" => Some((right != 0) as i64),
+                    _ => None,
+                }
+            }
+            Node::Call(call) => {
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| {
+                        let value = self.eval(arg, lvars, fuel)?;
+                        Some(Node::Int(crate::parser::Int {
+                            value: value as u64,
+                        }))
+                    })
+                    .collect::<Option<Vec<Node>>>()?;
+
+                self.eval_call(&call.fn_name, &args, fuel)
+            }
+            Node::If(if_node) => {
+                let body = match self.eval(&if_node.condition, lvars, fuel)? {
+                    0 => &if_node.else_body,
+                    _ => &if_node.then_body,
+                };
+
+                self.eval_body(body, lvars, fuel)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_int(return_type: &Option<BaseType>) -> bool {
+    matches!(return_type, Some(BaseType::Int | BaseType::Int64))
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Functions that hold more of the samples than this are in the summary
+const PERCENT_LIMIT: &str = "1";
+
+/// A function and the share of samples taken while it was running, itself
+/// and not a function it called.
+#[derive(Debug, PartialEq)]
+pub struct HotFunction {
+    pub percent: f64,
+    pub name: String,
+}
+
+/// Runs the executable at `path` under `perf record`, sampling call stacks
+/// by frame pointer, and returns the hottest functions. The samples are
+/// written next to the executable.
+///
+/// Defs are emitted under their own names, like `Dog.walk`, so they show up
+/// in perf and samply as they're written without a symbol map; the runtime's
+/// Rust names are demangled by perf itself.
+pub fn profile_executable(path: &str) -> Result<(i32, Vec<HotFunction>), String> {
+    let data_path = format!("{}.perf.data", path);
+
+    // perf runs a bare name from PATH, not the working directory
+    let executable = Path::new(path)
+        .canonicalize()
+        .map_err(|err| format!("{}: {}", path, err))?;
+
+    let status = Command::new("perf")
+        .args(["record", "--call-graph=fp", "-o", &data_path, "--"])
+        .arg(executable)
+        .status()
+        .map_err(|err| format!("perf: {}, profile needs it installed", err))?;
+
+    let report = Command::new("perf")
+        .args(["report", "--stdio", "--no-children", "--sort=symbol"])
+        .args(["--call-graph=none", "--percent-limit", PERCENT_LIMIT])
+        .args(["-i", &data_path])
+        .output()
+        .map_err(|err| format!("perf: {}", err))?;
+
+    let _ = std::fs::remove_file(&data_path);
+
+    if !report.status.success() {
+        return Err(format!(
+            "perf report failed: {}",
+            String::from_utf8_lossy(&report.stderr)
+        ));
+    }
+
+    let hot_functions = parse_perf_report(&String::from_utf8_lossy(&report.stdout));
+
+    Ok((status.code().unwrap_or(1), hot_functions))
+}
+
+/// Reads the `<percent>%  [.] <symbol>` lines of `perf report --stdio`,
+/// skipping the `#` comments around them.
+pub fn parse_perf_report(report: &str) -> Vec<HotFunction> {
+    report
+        .lines()
+        .filter_map(|line| {
+            let (percent, symbol) = line.trim().split_once('%')?;
+            let percent = percent.parse::<f64>().ok()?;
+            // `[.]` is a user space symbol, `[k]` one in the kernel
+            let name = symbol
+                .trim()
+                .split_once("] ")
+                .map_or(symbol, |(_, name)| name);
+
+            Some(HotFunction {
+                percent,
+                name: name.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The hot functions, hottest first, like `perf report` orders them.
+pub fn format_summary(hot_functions: &[HotFunction]) -> String {
+    let mut summary = format!("{:>8}  {}\n", "self", "function");
+
+    for hot_function in hot_functions {
+        summary.push_str(&format!(
+            "{:>7.2}%  {}\n",
+            hot_function.percent, hot_function.name
+        ));
+    }
+
+    summary
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use crate::ast::visit_nodes;
+use crate::parser::{Node, ParserResult, ParserResultIndex};
+
+/// Dead method elimination
+///
+/// Compilation is whole program, so a `def` that can't be reached from `main`
+/// is never called and is removed before codegen. A def is reached when it's
+/// called, sent to an instance, including through a superclass or a trait
+/// default, or referenced with `fn_ref`.
+///
+/// `def_e` declarations are kept, they don't add anything to the output.
+/// Programs without a `main` are left as they are.
+///
+/// Returns the names of the removed defs, sorted.
+pub fn eliminate_dead_methods(result: &mut ParserResult) -> Vec<String> {
+    let module = match &mut result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    let is_main = |node: &Node| matches!(node, Node::Def(def) if def.main_fn);
+
+    if !module.methods.iter().any(is_main) {
+        return vec![];
+    }
+
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut pending: Vec<&Node> = module
+        .methods
+        .iter()
+        .filter(|node| is_main(node) || matches!(node, Node::AssignConstant(_)))
+        .collect();
+
+    while let Some(node) = pending.pop() {
+        for fn_name in referenced_fns(node, &result.index) {
+            if !reachable.insert(fn_name.clone()) {
+                continue;
+            }
+
+            pending.extend(module.methods.iter().filter(|node| match node {
+                Node::Def(def) => def.prototype.name == fn_name,
+                _ => false,
+            }));
+        }
+    }
+
+    let mut removed = vec![];
+
+    module.methods.retain(|node| match node {
+        Node::Def(def) if !def.main_fn && !reachable.contains(&def.prototype.name) => {
+            removed.push(def.prototype.name.clone());
+            false
+        }
+        _ => true,
+    });
+
+    for fn_name in &removed {
+        result.index.fn_prototype_index.remove(fn_name);
+    }
+
+    removed.sort();
+    removed
+}
+
+/// The functions `node` calls or takes a reference to, with sends resolved to
+/// the def that implements them.
+pub fn referenced_fns(node: &Node, index: &ParserResultIndex) -> Vec<String> {
+    let mut fn_names = vec![];
+
+    visit_nodes(node, &mut |node| match node {
+        Node::Call(call) => {
+            fn_names.push(call.fn_name.clone());
+
+            if let Some((class_name, method_name)) = call.fn_name.split_once('.') {
+                if let Ok(Some(resolved_name)) = index.resolve_method(class_name, method_name) {
+                    fn_names.push(resolved_name);
+                }
+            }
+        }
+        Node::FnRef(fn_ref) => fn_names.push(fn_ref.fn_name.clone()),
+        // `name.fn_ref()`
+        Node::Send(send) => match (send.receiver.as_ref(), send.message.as_ref()) {
+            (Node::LocalVar(local_var), Node::Call(call)) if call.fn_name == "fn_ref" => {
+                fn_names.push(local_var.name.clone())
+            }
+            _ => {}
+        },
+        _ => {}
+    });
+
+    fn_names
+}
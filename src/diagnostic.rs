@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::columns::{display_column, expand_tabs, TabWidth};
+use crate::error_codes::error_code_for;
+use crate::lexer::TokenPosition;
+use crate::locale::translate;
+use crate::source::SourceFile;
+
+/// An error found while parsing, with the file and the position of the token
+/// it was found at when there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub path: Option<String>,
+    pub position: Option<TokenPosition>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            path: None,
+            position: None,
+        }
+    }
+
+    /// The registered code of the diagnostic, which `pajama explain <code>`
+    /// describes at length.
+    pub fn code(&self) -> Option<&'static str> {
+        error_code_for(&self.message).map(|error_code| error_code.code)
+    }
+
+    /// Renders the diagnostic in the language of the locale set, with its
+    /// code, the offending line of its file and a caret under the token,
+    /// like:
+    ///
+    /// ```text
+    /// error[E0001]: Expected ')' character at end of parenthesized expression.
+    ///  --> main.pjs:2:11
+    ///   |
+    /// 2 |   n = (1 + 2
+    ///   |           ^
+    /// ```
+    ///
+    /// Without a position, or when the file isn't in `sources`, only the first
+    /// two lines are rendered. Tabs in the line are expanded to `tab_width`,
+    /// and the caret is under the token as it's displayed, however wide the
+    /// characters before it are.
+    pub fn render(&self, sources: &[SourceFile], tab_width: TabWidth) -> String {
+        let message = translate(&self.message);
+        let mut rendered = match self.code() {
+            Some(code) => format!("error[{}]: {}", code, message),
+            None => format!("error: {}", message),
+        };
+
+        let location = match (&self.path, &self.position) {
+            (Some(path), Some(position)) => {
+                format!("{}:{}:{}", path, position.line, position.start_column)
+            }
+            (Some(path), None) => path.clone(),
+            (None, Some(position)) => format!("{}:{}", position.line, position.start_column),
+            (None, None) => return rendered,
+        };
+
+        rendered.push_str(&format!("\n --> {}", location));
+
+        let position = match &self.position {
+            Some(position) => position,
+            None => return rendered,
+        };
+
+        let line = sources
+            .iter()
+            .find(|source| Some(&source.path) == self.path.as_ref())
+            .and_then(|source| source.input.lines().nth(position.line - 1));
+
+        if let Some(line) = line {
+            let gutter = " ".repeat(position.line.to_string().len());
+            let start = display_column(line, position.start_column, tab_width);
+            let end = display_column(line, position.end_column + 1, tab_width);
+
+            rendered.push_str(&format!(
+                "\n{} |\n{} | {}\n{} | {}{}",
+                gutter,
+                position.line,
+                expand_tabs(line, tab_width),
+                gutter,
+                " ".repeat(start - 1),
+                "^".repeat(end.saturating_sub(start).max(1))
+            ));
+        }
+
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = translate(&self.message);
+
+        match (&self.path, &self.position) {
+            (Some(path), Some(position)) => write!(
+                f,
+                "{}:{}:{}: {}",
+                path, position.line, position.start_column, message
+            ),
+            (Some(path), None) => write!(f, "{}: {}", path, message),
+            (None, Some(position)) => {
+                write!(
+                    f,
+                    "{}:{}: {}",
+                    position.line, position.start_column, message
+                )
+            }
+            (None, None) => write!(f, "{}", message),
+        }
+    }
+}
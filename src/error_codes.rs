@@ -0,0 +1,599 @@
+/// A code that diagnostics are known by, with the long-form explanation
+/// `pajama explain <code>` prints.
+#[derive(Debug, PartialEq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    /// The English message, with `{}` where diagnostics have a name or a
+    /// type, like the `format!` reporting it. Codes are found by message, so
+    /// the parser and the analyzer can keep reporting plain strings, and a
+    /// catalog translates the message with the parts in the `{}`.
+    pub message: &'static str,
+    /// What the error means and how to fix it, with examples
+    pub explanation: &'static str,
+}
+
+/// Every registered code, in order. A diagnostic gets the first code whose
+/// `message` its own contains, so the more specific messages come first.
+pub const ERROR_CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "E0001",
+        message: "Expected ')' character at end of parenthesized expression.",
+        explanation: "\
+A parenthesized expression isn't closed before the line ends.
+
+    def main
+      n = (1 + 2
+    end
+
+Close it with a `)` on the same line:
+
+    def main
+      n = (1 + 2)
+    end
+",
+    },
+    ErrorCode {
+        code: "E0002",
+        message: "Unknown expression.",
+        explanation: "\
+The parser expected an expression, like a literal, a local, a call or an
+operator and its operand, and found a token that can't start one.
+
+    def main
+      n = 4 $
+    end
+
+Remove the stray token, or finish the expression it belongs to:
+
+    def main
+      n = 4
+    end
+",
+    },
+    ErrorCode {
+        code: "E0003",
+        message: "Expected class, def, import or trait",
+        explanation: "\
+Only definitions can be at the top level of a file: classes, structs,
+traits, defs, def_e declarations, constants and imports. Statements go
+inside a def.
+
+    puts(\"hi\")
+
+Move them into main, which runs when the program starts:
+
+    def main
+      puts(\"hi\")
+    end
+",
+    },
+    ErrorCode {
+        code: "E0004",
+        message: "Expected 'end' before the next definition.",
+        explanation: "\
+A def is missing its `end`, so the next definition starts inside it.
+
+    def first
+      a = 1
+
+    def second
+    end
+
+Close each def before the next one:
+
+    def first
+      a = 1
+    end
+
+    def second
+    end
+",
+    },
+    ErrorCode {
+        code: "E0005",
+        message: "Arguments with a default value must come after the ones without.",
+        explanation: "\
+A call fills in arguments from the left, so an argument without a default
+can't follow one with a default.
+
+    def greet(greeting Str = \"Hello\", name Str) -> Str
+      ret greeting + name
+    end
+
+Put the arguments with defaults last:
+
+    def greet(name Str, greeting Str = \"Hello\") -> Str
+      ret greeting + name
+    end
+",
+    },
+    ErrorCode {
+        code: "E0006",
+        message: "Positional arguments must come before named ones.",
+        explanation: "\
+Once an argument is passed by name, the ones after it must be named too,
+since their position no longer says which argument they are.
+
+    greet(greeting: \"Hi\", \"Ann\")
+
+Pass the positional arguments first:
+
+    greet(\"Ann\", greeting: \"Hi\")
+",
+    },
+    ErrorCode {
+        code: "E0007",
+        message: "Superclass must be defined before the class that inherits from it",
+        explanation: "\
+A subclass starts with its superclass's attributes, so the superclass has
+to be defined first, earlier in the file or in a file imported before it.
+
+    class Dog < Animal
+    end
+
+    class Animal
+    end
+
+Define the superclass first:
+
+    class Animal
+    end
+
+    class Dog < Animal
+    end
+",
+    },
+    ErrorCode {
+        code: "E0008",
+        message: "defer can only be used at the root of a def",
+        explanation: "\
+`defer` runs its expression when the def returns, which is only certain
+for the def's own statements, not those inside an if or a loop.
+
+    def main
+      if verbose
+        defer puts(\"done\")
+      end
+    end
+
+Defer at the root of the def, and check inside the deferred call:
+
+    def main
+      defer finish(verbose)
+    end
+",
+    },
+    ErrorCode {
+        code: "E0009",
+        message: "`{}` can only be used in a loop",
+        explanation: "\
+`break` leaves the innermost `while` or `loop`, and `next` starts its next
+iteration, so neither means anything outside of one.
+
+    def main
+      break
+    end
+
+Use them in a loop, or `ret` to leave the def:
+
+    def main
+      loop {
+        break
+      }
+    end
+",
+    },
+    ErrorCode {
+        code: "E0010",
+        message: "super can only be used inside a class method",
+        explanation: "\
+`super` calls the superclass's method of the same name, so it needs a
+method of a class to be in.
+
+    def main
+      ret super
+    end
+
+Use it in a method that overrides one of the superclass:
+
+    class Dog < Animal
+      def speak -> Str
+        ret super + \"!\"
+      end
+    end
+",
+    },
+    ErrorCode {
+        code: "E0011",
+        message: "`{}` is already defined in {}",
+        explanation: "\
+Two classes, structs, traits or functions have the same name, in the same
+file or in files compiled together.
+
+    class Dog
+    end
+
+    class Dog
+    end
+
+Rename one of them, or merge their definitions.
+",
+    },
+    ErrorCode {
+        code: "E0012",
+        message: "`{}` might be read before it's assigned in `{}`",
+        explanation: "\
+A local is only assigned on some paths through the def, and read on a
+path where it might not have been.
+
+    def main
+      if ready
+        n = 1
+      end
+
+      puts(n.to_s())
+    end
+
+Assign it on every path before reading it:
+
+    def main
+      n = 0
+
+      if ready
+        n = 1
+      end
+
+      puts(n.to_s())
+    end
+",
+    },
+    ErrorCode {
+        code: "E0013",
+        message: "`{}` can't be applied to {} and {}",
+        explanation: "\
+A binary operator was used with operand types it isn't defined for, like
+adding a Str to an Int.
+
+    def main
+      n = 1 + \"2\"
+    end
+
+Convert one operand so the types agree:
+
+    def main
+      n = 1 + Int.parse(\"2\")
+    end
+",
+    },
+    ErrorCode {
+        code: "E0014",
+        message: "`{}` is sent to {}, which might be nil; check that it isn't or send it with `&.`",
+        explanation: "\
+A message is sent to a value of an optional type like Str?, which might
+be nil.
+
+    def shout(name Str?) -> Str
+      ret name.upcase()
+    end
+
+Send it with `&.`, which gives nil when the receiver is nil, or check
+that the value isn't nil first:
+
+    def shout(name Str?) -> Str?
+      ret name&.upcase()
+    end
+",
+    },
+    ErrorCode {
+        code: "E0015",
+        message: "`{}` is declared as {}, but only classes can be optional",
+        explanation: "\
+Only class types can be made optional with `?`, not Int, Float or Bool.
+
+    def find(id Int?)
+    end
+
+Use a class type, or a value like -1 for the missing case:
+
+    def find(id Int)
+    end
+",
+    },
+    ErrorCode {
+        code: "E0016",
+        message: "`{}` implements {} but does not define {}",
+        explanation: "\
+A class implements a trait without defining one of the trait's required
+methods, the ones declared without a body.
+
+    trait Named
+      def name -> Str
+    end
+
+    class Dog
+      impl Named
+      end
+    end
+
+Define the method in the impl block:
+
+    class Dog
+      impl Named
+        def name -> Str
+          ret \"Rex\"
+        end
+      end
+    end
+",
+    },
+    ErrorCode {
+        code: "E0017",
+        message: "`{}` does not match `{}`: expected {}, found {}",
+        explanation: "\
+A class defines a trait's method with other argument or return types than
+the trait declares.
+
+    trait Named
+      def name -> Str
+    end
+
+    class Dog
+      impl Named
+        def name -> Int
+          ret 1
+        end
+      end
+    end
+
+Give the method the types the trait declares.
+",
+    },
+    ErrorCode {
+        code: "E0018",
+        message: "`{}` should return {}, but its last statement has no value",
+        explanation: "\
+A def declares a return type, but its last statement, which is what it
+returns, doesn't produce a value.
+
+    def answer -> Int
+      n = 42
+    end
+
+End it with the value to return:
+
+    def answer -> Int
+      ret 42
+    end
+",
+    },
+    ErrorCode {
+        code: "E0019",
+        message: "`{}` isn't available with --runtime=minimal, it needs an operating system",
+        explanation: "\
+--runtime=minimal only allows the runtime functions that work without an
+operating system, so a program using files, the clock, the environment
+or the process doesn't compile with it.
+
+Compile without --runtime=minimal, or leave out the calls it reports.
+",
+    },
+    ErrorCode {
+        code: "E0020",
+        message: "{} requires the {} class to be defined",
+        explanation: "\
+The methods built into Str, Date and DateTime work on the attributes of
+those classes, which the program has to define.
+
+    def main
+      puts(\"hi\".upcase())
+    end
+
+Define the class with the attributes the runtime expects:
+
+    class Str
+      @buffer     BytePtr
+      @length     Int
+      @max_length Int
+    end
+",
+    },
+    ErrorCode {
+        code: "E0021",
+        message: "`{}` is read from {}, which might be nil; check that it isn't first",
+        explanation: "\
+An attribute is read from a value of an optional type like Dog?, which
+might be nil.
+
+    def age(dog Dog?) -> Int
+      ret dog.age
+    end
+
+Check that the value isn't nil before reading from it, or pass a Dog
+instead.
+",
+    },
+    ErrorCode {
+        code: "E0022",
+        message: "Returns {}, but only classes can be optional",
+        explanation: "\
+Like E0015, for a return type: only class types can be optional.
+
+    def find -> Int?
+    end
+
+Return a class type, or a value like -1 for the missing case.
+",
+    },
+    ErrorCode {
+        code: "E0023",
+        message: "{} require the {} class to be defined",
+        explanation: "\
+Like E0020, for a group of methods or functions: they work on the
+attributes of Str, Date or DateTime, which the program has to define.
+",
+    },
+    ErrorCode {
+        code: "E0024",
+        message: "Integer literal too big for 64 bits.",
+        explanation: "\
+An integer literal is bigger than an Int can hold. Ints are signed 64
+bits, up to 9223372036854775807, and there's no arbitrary-precision
+integer type to promote a literal to.
+
+    def main
+      n = 123456789012345678901234567890
+    end
+
+Split the number into parts that fit, or use a Float if an approximate
+value is enough:
+
+    def main
+      n = 123456789012345678901234567890.0
+    end
+",
+    },
+    ErrorCode {
+        code: "E0025",
+        message: "`{}` is never closed",
+        explanation: "\
+The file ends inside a string, a bracket or a block. The error points at
+the innermost one left open. For blocks, that can be an outer one whose
+`end` was taken by a block inside it:
+
+    def main
+      if ready
+        puts(\"go\")
+    end
+
+The `end` closes the `if`, so it's the `def` that's never closed. Add the
+`end` that's missing:
+
+    def main
+      if ready
+        puts(\"go\")
+      end
+    end
+",
+    },
+    ErrorCode {
+        code: "E0026",
+        message: "Expected the name of a def after `&`, like `&greet`",
+        explanation: "\
+`&` refers to a def by its name, and the name after it isn't one. Locals,
+methods and expressions can't be referred to.
+
+    def main
+      f = &greeting
+    end
+
+Refer to a def that exists, and call it through the reference like the def
+itself:
+
+    def greet(name Str)
+      puts(name)
+    end
+
+    def main
+      f = &greet
+      f(\"Rex\")
+    end
+",
+    },
+    ErrorCode {
+        code: "E0027",
+        message: "Expected '}' at the end of an interpolation.",
+        explanation: "\
+An interpolation starts with `#{` in a string, and the string ends before
+the `}` that closes it.
+
+    def main
+      name = \"Rex\"
+      puts(\"Hi #{name\")
+    end
+
+Close it with a `}`:
+
+    def main
+      name = \"Rex\"
+      puts(\"Hi #{name}\")
+    end
+",
+    },
+    ErrorCode {
+        code: "E0028",
+        message: "Expected a single expression in an interpolation.",
+        explanation: "\
+The value of an interpolation is put in the string, so what's between `#{`
+and `}` has to be one expression.
+
+    def main
+      n = 4
+      puts(\"#{n 2} dogs\")
+    end
+
+Interpolate each value on its own, or combine them into one expression:
+
+    def main
+      n = 4
+      puts(\"#{n} #{2} dogs\")
+    end
+",
+    },
+];
+
+/// Whether `arg` looks like a code, an E and four digits, rather than a
+/// path.
+pub fn is_error_code(arg: &str) -> bool {
+    arg.len() == 5 && arg.starts_with('E') && arg[1..].chars().all(|ch| ch.is_ascii_digit())
+}
+
+/// The registered code `code`, like E0001.
+pub fn find_error_code(code: &str) -> Option<&'static ErrorCode> {
+    ERROR_CODES
+        .iter()
+        .find(|error_code| error_code.code == code)
+}
+
+/// The code of a diagnostic with `message`, when one is registered for it.
+pub fn error_code_for(message: &str) -> Option<&'static ErrorCode> {
+    ERROR_CODES
+        .iter()
+        .find(|error_code| match_message(error_code.message, message).is_some())
+}
+
+/// Finds the English `template` in `message`, returning what `message` has
+/// before it, the parts in its `{}` and what comes after it. A `{}` takes
+/// as little as it can, except at the end, where it takes the rest.
+///
+/// The text around the template is from diagnostics that wrap others, like
+/// "`x` is declared as Int?, but only classes can be optional in `main`".
+pub fn match_message<'a>(
+    template: &str,
+    message: &'a str,
+) -> Option<(&'a str, Vec<&'a str>, &'a str)> {
+    let mut literals = template.split("{}");
+    let first = literals.next().unwrap_or_default();
+
+    // Without text before the first `{}`, it starts the message
+    let start = match first {
+        "" => 0,
+        first => message.find(first)?,
+    };
+    let prefix = &message[..start];
+    let mut pos = start + first.len();
+    let mut parts = vec![];
+
+    for literal in literals {
+        if literal.is_empty() {
+            parts.push(&message[pos..]);
+            pos = message.len();
+            continue;
+        }
+
+        let found = pos + message[pos..].find(literal)?;
+        parts.push(&message[pos..found]);
+        pos = found + literal.len();
+    }
+
+    Some((prefix, parts, &message[pos..]))
+}
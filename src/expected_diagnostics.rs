@@ -0,0 +1,109 @@
+use crate::diagnostic::Diagnostic;
+use crate::explain::parse_position;
+use crate::lexer::{Lexer, Token};
+
+/// A diagnostic a compile-fail fixture says it produces, from a comment like
+/// `#~ ERROR Expected ')' at 3:10`.
+#[derive(Debug, PartialEq)]
+pub struct ExpectedDiagnostic {
+    /// The line of the source the comment is on
+    pub line: usize,
+    /// Part of the message, which the diagnostic's must contain
+    pub message: String,
+    /// The line and column the diagnostic must be at, both counted from 1,
+    /// when the comment gives them. Analysis errors have no position yet, so
+    /// they're expected by message alone.
+    pub position: Option<(usize, usize)>,
+}
+
+/// Reads the `#~ ERROR <message>` and `#~ ERROR <message> at <line>:<column>`
+/// comments of a fixture. Any other `#~` comment is an error, so a typo
+/// doesn't silently expect nothing.
+pub fn parse_expected_diagnostics(input: &str) -> Result<Vec<ExpectedDiagnostic>, String> {
+    let mut expected = vec![];
+
+    for token in Lexer::new(input).tokenize() {
+        let (line, comment) = match &token {
+            Token::Comment(position, comment) => (position.line, comment.trim()),
+            _ => continue,
+        };
+
+        let annotation = match comment.strip_prefix("#~") {
+            Some(annotation) => annotation.trim(),
+            None => continue,
+        };
+
+        let message = match annotation.strip_prefix("ERROR ") {
+            Some(message) => message.trim(),
+            None => {
+                return Err(format!(
+                    "line {}: expected `#~ ERROR <message>`, got `{}`",
+                    line, comment
+                ))
+            }
+        };
+
+        // A message can say "at" too, so only a trailing position counts
+        let at = message
+            .rsplit_once(" at ")
+            .and_then(|(message, position)| Some((message, parse_position(position).ok()?)));
+
+        let (message, position) = match at {
+            Some((message, position)) => (message, Some(position)),
+            None => (message, None),
+        };
+
+        expected.push(ExpectedDiagnostic {
+            line,
+            message: message.to_string(),
+            position,
+        });
+    }
+
+    Ok(expected)
+}
+
+/// Matches `diagnostics` to the ones `expected`, each to one at most, and
+/// describes what doesn't match: expected diagnostics that weren't produced,
+/// then produced ones that weren't expected. Empty when they match.
+pub fn unmatched_diagnostics(
+    expected: &[ExpectedDiagnostic],
+    diagnostics: &[Diagnostic],
+) -> Vec<String> {
+    let mut unmatched: Vec<&Diagnostic> = diagnostics.iter().collect();
+    let mut problems = vec![];
+
+    for expectation in expected {
+        let found = unmatched.iter().position(|diagnostic| {
+            diagnostic.message.contains(&expectation.message)
+                && match expectation.position {
+                    Some((line, column)) => diagnostic.position.as_ref().is_some_and(|position| {
+                        position.line == line && position.start_column == column
+                    }),
+                    None => true,
+                }
+        });
+
+        match found {
+            Some(index) => {
+                unmatched.remove(index);
+            }
+            None => problems.push(match expectation.position {
+                Some((line, column)) => format!(
+                    "line {}: expected an error containing {:?} at {}:{}",
+                    expectation.line, expectation.message, line, column
+                ),
+                None => format!(
+                    "line {}: expected an error containing {:?}",
+                    expectation.line, expectation.message
+                ),
+            }),
+        }
+    }
+
+    for diagnostic in unmatched {
+        problems.push(format!("unexpected error: {}", diagnostic));
+    }
+
+    problems
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+
+use crate::ast::visit_nodes;
+use crate::lexer::{Lexer, Token, TokenPosition};
+use crate::parser::{BaseType, Def, Node, ParserResult, SourceLocation};
+use crate::semantic_analyzer::typed_node_base_type;
+use crate::type_checker::type_name;
+
+/// What the compiler made of the source at a position, printed by
+/// `pajama explain`.
+#[derive(Debug, PartialEq)]
+pub struct Explanation {
+    /// The name or literal at the position and what it is, e.g.
+    /// "local `steps`"
+    pub node: String,
+    /// The type analysis gave it, when it has one
+    pub type_name: Option<String>,
+    /// What it's in, innermost first, e.g. "def `Dog.walk`", "class `Dog`",
+    /// "file `main.pjs`"
+    pub enclosing: Vec<String>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.type_name {
+            Some(type_name) => writeln!(f, "{}: {}", self.node, type_name)?,
+            None => writeln!(f, "{}", self.node)?,
+        }
+
+        for enclosing in &self.enclosing {
+            writeln!(f, "  in {}", enclosing)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses `--at <line>:<column>`, both counted from 1 like in diagnostics.
+pub fn parse_position(position: &str) -> Result<(usize, usize), String> {
+    let parsed = position.split_once(':').and_then(|(line, column)| {
+        match (line.parse::<usize>(), column.parse::<usize>()) {
+            (Ok(line), Ok(column)) if line > 0 && column > 0 => Some((line, column)),
+            _ => None,
+        }
+    });
+
+    parsed.ok_or_else(|| {
+        format!(
+            "invalid position `{}`, expected <line>:<column>, e.g. 3:5",
+            position
+        )
+    })
+}
+
+/// Explains the name or literal at `line` and `column` of the file at
+/// `path`, whose source is `input`, in an analyzed program.
+///
+/// Analysis doesn't keep positions, so the token at the position is found by
+/// lexing `input` again, and matched to the definitions and call sites the
+/// parser indexed. The enclosing def is the last one defined at or before
+/// the line. Returns `None` when there's no name or literal there.
+pub fn explain(
+    result: &ParserResult,
+    path: &str,
+    input: &str,
+    line: usize,
+    column: usize,
+) -> Option<Explanation> {
+    let tokens = Lexer::new(input).tokenize();
+    let index = tokens.iter().position(|token| match token {
+        Token::Comment(..) => false,
+        token => token
+            .position()
+            .is_some_and(|position| is_at(position, line, column)),
+    })?;
+    let token = &tokens[index];
+    let after_dot = index > 0 && matches!(tokens[index - 1], Token::Dot | Token::SafeNav);
+
+    let defs: Vec<&Def> = match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) => Some(def),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let is_in_file = |location: &SourceLocation| location.path.as_deref() == Some(path);
+    let is_here = |location: &SourceLocation| {
+        is_in_file(location)
+            && location
+                .position
+                .as_ref()
+                .is_some_and(|position| is_at(position, line, column))
+    };
+
+    let defined_here = result
+        .index
+        .definition_index
+        .iter()
+        .find(|(_, location)| is_here(location));
+
+    // The def a definition's name is in is the definition itself
+    if let (Some((name, _)), Token::Ident(..)) = (defined_here, token) {
+        if let Some(def) = defs.iter().find(|def| &def.prototype.name == name) {
+            return Some(Explanation {
+                node: format!("def `{}`", name),
+                type_name: Some(return_type_name(&def.prototype.return_type)),
+                enclosing: enclosing_names(None, def_parents(def), path),
+            });
+        }
+    }
+
+    let enclosing_def = defs
+        .iter()
+        .filter_map(|def| {
+            let location = result.index.definition_index.get(&def.prototype.name)?;
+            let position = location.position.as_ref()?;
+
+            if is_in_file(location) && position.line <= line {
+                Some((position.line, *def))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(def_line, _)| *def_line)
+        .map(|(_, def)| def);
+
+    let (node, type_name) = match token {
+        Token::Ident(_, name) => {
+            let called_here = result
+                .index
+                .call_site_index
+                .get(name)
+                .is_some_and(|sites| sites.iter().any(|site| is_here(&site.location)));
+            let arg = enclosing_def
+                .and_then(|def| def.prototype.args.iter().find(|arg| &arg.name == name));
+
+            if after_dot {
+                let call = enclosing_def.and_then(|def| find_send(def, name));
+
+                match call {
+                    Some((fn_name, return_type)) => (
+                        format!("send of `{}`", fn_name),
+                        Some(return_type_name(&return_type)),
+                    ),
+                    None => (format!("send of `{}`", name), None),
+                }
+            } else if called_here {
+                let prototype = result.index.fn_prototype_index.get(name);
+
+                (
+                    format!("call to `{}`", name),
+                    prototype.map(|prototype| return_type_name(&prototype.return_type)),
+                )
+            } else if let Some(arg) = arg {
+                (
+                    format!("argument `{}`", name),
+                    Some(type_name(&arg.return_type)),
+                )
+            } else if let Some(local_type) = enclosing_def.and_then(|def| local_type(def, name)) {
+                (format!("local `{}`", name), Some(type_name(&local_type)))
+            } else {
+                (format!("`{}`", name), None)
+            }
+        }
+        Token::Const(_, name) => {
+            let index = &result.index;
+
+            if index.class_index.contains_key(name) {
+                (format!("class `{}`", name), None)
+            } else if index.struct_index.contains_key(name) {
+                (format!("struct `{}`", name), None)
+            } else if index.trait_index.contains_key(name) {
+                (format!("trait `{}`", name), None)
+            } else if let Some(constant_type) = index.constant_index.get(name) {
+                (
+                    format!("constant `{}`", name),
+                    Some(type_name(constant_type)),
+                )
+            } else {
+                (format!("`{}`", name), None)
+            }
+        }
+        Token::Attribute(_, name) => {
+            let attribute = enclosing_def
+                .and_then(|def| result.index.class_index.get(&def.class_name))
+                .and_then(|class| {
+                    class
+                        .attributes
+                        .iter()
+                        .find(|attribute| &attribute.name == name)
+                });
+
+            (
+                format!("attribute `@{}`", name),
+                attribute.map(|attribute| type_name(&attribute.return_type)),
+            )
+        }
+        Token::Number(_, number) => (format!("literal `{}`", number), Some("Int".to_string())),
+        Token::Float(_, float) => (format!("literal `{}`", float), Some("Float".to_string())),
+        Token::StringLiteral(_, string) => {
+            (format!("literal {:?}", string), Some("Str".to_string()))
+        }
+        _ => return None,
+    };
+
+    Some(Explanation {
+        node,
+        type_name,
+        enclosing: enclosing_names(enclosing_def, vec![], path),
+    })
+}
+
+fn is_at(position: &TokenPosition, line: usize, column: usize) -> bool {
+    position.line == line && (position.start_column..=position.end_column).contains(&column)
+}
+
+fn return_type_name(return_type: &Option<BaseType>) -> String {
+    match return_type {
+        Some(return_type) => type_name(return_type),
+        None => type_name(&BaseType::Void),
+    }
+}
+
+/// The impl, class or trait `def` is defined in, innermost first.
+fn def_parents(def: &Def) -> Vec<String> {
+    let mut parents = vec![];
+
+    if !def.impl_name.is_empty() {
+        parents.push(format!("impl `{}`", def.impl_name));
+    }
+
+    if !def.class_name.is_empty() {
+        parents.push(format!("class `{}`", def.class_name));
+    } else if !def.trait_name.is_empty() {
+        parents.push(format!("trait `{}`", def.trait_name));
+    }
+
+    parents
+}
+
+fn enclosing_names(def: Option<&Def>, parents: Vec<String>, path: &str) -> Vec<String> {
+    let mut enclosing = parents;
+
+    if let Some(def) = def {
+        enclosing.push(format!("def `{}`", def.prototype.name));
+        enclosing.extend(def_parents(def));
+    }
+
+    enclosing.push(format!("file `{}`", path));
+    enclosing
+}
+
+/// The type of the local `name` in `def`, from where it's read or assigned.
+fn local_type(def: &Def, name: &str) -> Option<BaseType> {
+    let mut found = None;
+
+    for node in &def.body {
+        visit_nodes(node, &mut |node| {
+            if found.is_some() {
+                return;
+            }
+
+            match node {
+                Node::LocalVar(local_var) if local_var.name == name => {
+                    found = local_var.return_type.clone();
+                }
+                Node::AssignLocalVar(assign) if assign.name == name => {
+                    found = typed_node_base_type(&assign.value);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    found
+}
+
+/// The def a send of `method_name` in `def` was resolved to, and what it
+/// returns. Analysis names the call after the receiver's class, like
+/// `Dog.walk`.
+fn find_send(def: &Def, method_name: &str) -> Option<(String, Option<BaseType>)> {
+    let mut found = None;
+
+    for node in &def.body {
+        visit_nodes(node, &mut |node| {
+            if let (None, Node::Call(call)) = (&found, node) {
+                let sent = call
+                    .fn_name
+                    .rsplit_once('.')
+                    .is_some_and(|(_, name)| name == method_name);
+
+                if sent {
+                    found = Some((call.fn_name.clone(), call.return_type.clone()));
+                }
+            }
+        });
+    }
+
+    found
+}
@@ -0,0 +1,247 @@
+use crate::dead_code::referenced_fns;
+use crate::parser::{Node, ParserResult};
+
+/// The calls between the defs of a program, and how its classes and traits
+/// are related, printed by `pajama graph`.
+#[derive(Debug, PartialEq)]
+pub struct ProgramGraph {
+    /// `(caller, callee)`, in the order the callers are defined. Sends are
+    /// resolved to the def that implements them, and only calls to defs are
+    /// kept, not to `def_e` functions or builtins.
+    pub calls: Vec<(String, String)>,
+    /// Sorted by name
+    pub classes: Vec<ClassNode>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ClassNode {
+    pub name: String,
+    pub superclass: Option<String>,
+    /// The traits the class implements itself, sorted
+    pub traits: Vec<String>,
+}
+
+/// Which files of a program import which, printed by `pajama graph
+/// --imports`, from the files `source::resolve_import_graph` loaded.
+#[derive(Debug, PartialEq)]
+pub struct ImportGraph {
+    /// Each after the files it imports
+    pub files: Vec<String>,
+    /// `(importer, imported)`, in the order the imports were read, including
+    /// the ones closing an import cycle
+    pub imports: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+pub fn parse_graph_format(name: &str) -> Result<GraphFormat, String> {
+    match name {
+        "dot" => Ok(GraphFormat::Dot),
+        "json" => Ok(GraphFormat::Json),
+        _ => Err(format!("unknown --format `{}`, expected dot or json", name)),
+    }
+}
+
+/// Builds the graph of an analyzed program from its defs and the class and
+/// trait indexes.
+pub fn program_graph(result: &ParserResult) -> ProgramGraph {
+    let defs: Vec<_> = match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) => Some(def),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let is_def = |name: &String| defs.iter().any(|def| &def.prototype.name == name);
+
+    let mut calls = vec![];
+
+    for def in &defs {
+        let mut callees: Vec<String> = vec![];
+
+        for node in &def.body {
+            for fn_name in referenced_fns(node, &result.index) {
+                if is_def(&fn_name) && !callees.contains(&fn_name) {
+                    callees.push(fn_name);
+                }
+            }
+        }
+
+        for callee in callees {
+            calls.push((def.prototype.name.clone(), callee));
+        }
+    }
+
+    let index = &result.index;
+    let mut classes: Vec<ClassNode> = index
+        .class_index
+        .values()
+        .map(|class| {
+            let mut traits: Vec<String> = index
+                .trait_index
+                .iter()
+                .filter(|(_, classes)| classes.iter().any(|other| other.name == class.name))
+                .map(|(trait_name, _)| trait_name.clone())
+                .collect();
+            traits.sort();
+
+            ClassNode {
+                name: class.name.clone(),
+                superclass: class.superclass.clone(),
+                traits,
+            }
+        })
+        .collect();
+    classes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    ProgramGraph { calls, classes }
+}
+
+pub fn format_graph(graph: &ProgramGraph, format: &GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => to_dot(graph),
+        GraphFormat::Json => to_json(graph),
+    }
+}
+
+/// A Graphviz digraph: calls are plain edges, classes are boxes with a solid
+/// hollow arrow to their superclass and a dashed one to each trait.
+fn to_dot(graph: &ProgramGraph) -> String {
+    let mut dot = "digraph program {\n".to_string();
+
+    for (caller, callee) in &graph.calls {
+        dot.push_str(&format!("  {} -> {};\n", quoted(caller), quoted(callee)));
+    }
+
+    for class in &graph.classes {
+        dot.push_str(&format!("  {} [shape=box];\n", quoted(&class.name)));
+
+        if let Some(superclass) = &class.superclass {
+            dot.push_str(&format!(
+                "  {} -> {} [arrowhead=empty];\n",
+                quoted(&class.name),
+                quoted(superclass)
+            ));
+        }
+
+        for trait_name in &class.traits {
+            dot.push_str(&format!(
+                "  {} -> {} [arrowhead=empty, style=dashed];\n",
+                quoted(&class.name),
+                quoted(trait_name)
+            ));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn to_json(graph: &ProgramGraph) -> String {
+    let calls: Vec<String> = graph
+        .calls
+        .iter()
+        .map(|(caller, callee)| {
+            format!(
+                "    {{\"caller\": {}, \"callee\": {}}}",
+                quoted(caller),
+                quoted(callee)
+            )
+        })
+        .collect();
+
+    let classes: Vec<String> = graph
+        .classes
+        .iter()
+        .map(|class| {
+            let superclass = match &class.superclass {
+                Some(superclass) => quoted(superclass),
+                None => "null".to_string(),
+            };
+            let traits: Vec<String> = class.traits.iter().map(|name| quoted(name)).collect();
+
+            format!(
+                "    {{\"name\": {}, \"superclass\": {}, \"traits\": [{}]}}",
+                quoted(&class.name),
+                superclass,
+                traits.join(", ")
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\n  \"calls\": {},\n  \"classes\": {}\n}}\n",
+        json_array(&calls),
+        json_array(&classes)
+    )
+}
+
+pub fn format_import_graph(graph: &ImportGraph, format: &GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => {
+            let mut dot = "digraph imports {\n".to_string();
+
+            for file in &graph.files {
+                dot.push_str(&format!("  {} [shape=note];\n", quoted(file)));
+            }
+
+            for (importer, imported) in &graph.imports {
+                dot.push_str(&format!(
+                    "  {} -> {};\n",
+                    quoted(importer),
+                    quoted(imported)
+                ));
+            }
+
+            dot.push_str("}\n");
+            dot
+        }
+        GraphFormat::Json => {
+            let files: Vec<String> = graph
+                .files
+                .iter()
+                .map(|file| format!("    {}", quoted(file)))
+                .collect();
+            let imports: Vec<String> = graph
+                .imports
+                .iter()
+                .map(|(importer, imported)| {
+                    format!(
+                        "    {{\"importer\": {}, \"imported\": {}}}",
+                        quoted(importer),
+                        quoted(imported)
+                    )
+                })
+                .collect();
+
+            format!(
+                "{{\n  \"files\": {},\n  \"imports\": {}\n}}\n",
+                json_array(&files),
+                json_array(&imports)
+            )
+        }
+    }
+}
+
+fn json_array(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[]".to_string();
+    }
+
+    format!("[\n{}\n  ]", items.join(",\n"))
+}
+
+/// A name in double quotes, which DOT IDs and JSON strings both escape the
+/// same way.
+fn quoted(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
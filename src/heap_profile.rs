@@ -0,0 +1,86 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Records what the runtime allocates while a program runs with
+/// `--profile-heap`, by allocation site.
+///
+/// Codegen doesn't map generated code back to the source yet, so a site is
+/// the runtime function the program called, like `pj_str_concat`, found by
+/// walking the stack. That's slow, which is why nothing is recorded unless
+/// `start` was called.
+static PROFILING: AtomicBool = AtomicBool::new(false);
+static SITES: Mutex<Option<HashMap<String, SiteStats>>> = Mutex::new(None);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SiteStats {
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+/// Starts recording allocations, forgetting any recorded before.
+pub fn start() {
+    *SITES.lock().unwrap() = Some(HashMap::new());
+    PROFILING.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording, returning what each site allocated, the sites that
+/// allocated the most bytes first.
+pub fn finish() -> Vec<(String, SiteStats)> {
+    PROFILING.store(false, Ordering::Relaxed);
+
+    let sites = SITES.lock().unwrap().take().unwrap_or_default();
+    let mut sites: Vec<(String, SiteStats)> = sites.into_iter().collect();
+    sites.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+
+    sites
+}
+
+/// Counts an allocation of `size` bytes against the site it's made from.
+pub fn record(size: usize) {
+    if !PROFILING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let site = allocation_site();
+
+    if let Some(sites) = SITES.lock().unwrap().as_mut() {
+        let stats = sites.entry(site).or_default();
+        stats.allocations += 1;
+        stats.bytes += size as u64;
+    }
+}
+
+/// The outermost `pj_*` runtime function on the stack, the one compiled code
+/// called into. Frames are printed innermost first, one per line as
+/// `  12: pajama::pajama_lib::pj_str_concat`.
+fn allocation_site() -> String {
+    let backtrace = Backtrace::force_capture().to_string();
+
+    backtrace
+        .lines()
+        .filter_map(|line| line.trim_start().split_once(": "))
+        .filter_map(|(_, function)| function.rsplit("::").next())
+        .rfind(|name| name.starts_with("pj_"))
+        .unwrap_or("runtime")
+        .to_string()
+}
+
+/// The sites as folded stacks, one `<site> <bytes>` line each, which
+/// `flamegraph.pl` and speedscope read.
+pub fn to_folded(sites: &[(String, SiteStats)]) -> String {
+    sites
+        .iter()
+        .map(|(site, stats)| format!("{} {}\n", site, stats.bytes))
+        .collect()
+}
+
+/// The sites as a table, like `--memory-stats` prints.
+pub fn print(sites: &[(String, SiteStats)]) {
+    eprintln!("{:<24} {:>12} {:>12}", "site", "allocations", "bytes");
+
+    for (site, stats) in sites {
+        eprintln!("{:<24} {:>12} {:>12}", site, stats.allocations, stats.bytes);
+    }
+}
@@ -0,0 +1,1195 @@
+use std::collections::HashSet;
+
+use crate::ast::branch_locals;
+use crate::parser::{self, BaseType, Node, ParserResult};
+use crate::semantic_analyzer::typed_node_base_type;
+
+const JS_KEYWORDS: &str = "arguments await break case catch class const continue debugger \
+    default delete do else enum eval export extends false finally for function if implements \
+    import in instanceof interface let new null package private protected public return static \
+    super switch this throw true try typeof var void while with yield";
+
+/// JavaScript versions of the runtime functions programs declare with `def_e`
+/// or that the analyzer adds. `Str` instances hold a JS string as their
+/// buffer.
+const RUNTIME: [(&str, &str); 68] = [
+    (
+        "print_int",
+        r#"function print_int(int_) {
+  console.log("print_int: " + int_);
+}"#,
+    ),
+    (
+        "print_bytes",
+        r#"function print_bytes(byte_ptr, int_) {
+  console.log(byte_ptr.slice(0, Number(int_)));
+}"#,
+    ),
+    (
+        "exit",
+        r#"function exit(code) {
+  process.exit(Number(code));
+}"#,
+    ),
+    (
+        "pj_puts",
+        r#"function pj_puts(str) {
+  console.log(str.buffer.slice(0, Number(str.length)));
+}"#,
+    ),
+    (
+        "pj_print",
+        r#"function pj_print(str) {
+  process.stdout.write(str.buffer.slice(0, Number(str.length)));
+}"#,
+    ),
+    (
+        "pj_bool_to_s",
+        r#"function pj_bool_to_s(value) {
+  return pjStr(String(value));
+}"#,
+    ),
+    (
+        "pj_int_to_s",
+        r#"function pj_int_to_s(int_) {
+  return pjStr(int_.toString());
+}"#,
+    ),
+    (
+        "pj_int_to_s_base",
+        r#"function pj_int_to_s_base(int_, base) {
+  return pjStr(int_.toString(Number(base)));
+}"#,
+    ),
+    (
+        "pj_int_to_f",
+        r#"function pj_int_to_f(int_) {
+  return Number(int_);
+}"#,
+    ),
+    (
+        "pj_float_to_i",
+        r#"function pj_float_to_i(float_) {
+  return BigInt.asIntN(64, BigInt(Math.trunc(float_)));
+}"#,
+    ),
+    (
+        "pj_float_to_s",
+        r#"function pj_float_to_s(float_) {
+  const text = String(float_);
+  return pjStr(/^-?\d+$/.test(text) ? text + ".0" : text);
+}"#,
+    ),
+    (
+        "pj_int_parse",
+        r#"function pj_int_parse(str, base) {
+  const text = str.buffer.slice(0, Number(str.length));
+  const digits = text.replace(/^[-+]/, "");
+  let value = 0n;
+  for (const digit of digits.toLowerCase()) {
+    const n = parseInt(digit, Number(base));
+    if (Number.isNaN(n)) {
+      console.error("invalid integer: " + text);
+      process.exit(1);
+    }
+    value = value * base + BigInt(n);
+  }
+  return BigInt.asIntN(64, text.startsWith("-") ? -value : value);
+}"#,
+    ),
+    (
+        "pj_str_concat",
+        r#"function pj_str_concat(left, right) {
+  return pjStr(left.buffer.slice(0, Number(left.length)) + right.buffer.slice(0, Number(right.length)));
+}"#,
+    ),
+    (
+        "pj_str_slice",
+        r#"function pj_str_slice(str, start, end) {
+  const text = str.buffer.slice(0, Number(str.length));
+  const from = Math.min(Math.max(Number(start), 0), text.length);
+  const to = Math.min(Math.max(Number(end), from), text.length);
+  return pjStr(text.slice(from, to));
+}"#,
+    ),
+    (
+        "pj_str_sub",
+        r#"function pj_str_sub(str, start, length) {
+  const text = str.buffer.slice(0, Number(str.length));
+  const from = Math.min(Math.max(Number(start), 0), text.length);
+  const to = Math.min(from + Math.max(Number(length), 0), text.length);
+  return pjStr(text.slice(from, to));
+}"#,
+    ),
+    (
+        "pj_str_trim",
+        r#"function pj_str_trim(str) {
+  return pjStr(str.buffer.slice(0, Number(str.length)).trim());
+}"#,
+    ),
+    (
+        "pj_str_starts_with",
+        r#"function pj_str_starts_with(str, prefix) {
+  return str.buffer.slice(0, Number(str.length)).startsWith(prefix.buffer.slice(0, Number(prefix.length)));
+}"#,
+    ),
+    (
+        "pj_str_ends_with",
+        r#"function pj_str_ends_with(str, suffix) {
+  return str.buffer.slice(0, Number(str.length)).endsWith(suffix.buffer.slice(0, Number(suffix.length)));
+}"#,
+    ),
+    (
+        "pj_str_index_of",
+        r#"function pj_str_index_of(str, needle) {
+  return BigInt(str.buffer.slice(0, Number(str.length)).indexOf(needle.buffer.slice(0, Number(needle.length))));
+}"#,
+    ),
+    (
+        "pj_str_length",
+        r#"function pj_str_length(str) {
+  return BigInt([...graphemes(str)].length);
+}"#,
+    ),
+    (
+        "pj_str_byte_length",
+        r#"function pj_str_byte_length(str) {
+  return BigInt(new TextEncoder().encode(str.buffer.slice(0, Number(str.length))).length);
+}"#,
+    ),
+    (
+        "pj_str_chars",
+        r#"function pj_str_chars(str) {
+  return [...graphemes(str)].map((grapheme) => pjStr(grapheme.segment));
+}"#,
+    ),
+    (
+        "pj_str_codepoints",
+        r#"function pj_str_codepoints(str) {
+  return [...str.buffer.slice(0, Number(str.length))].map((char) => BigInt(char.codePointAt(0)));
+}"#,
+    ),
+    (
+        "pj_str_to_nfc",
+        r#"function pj_str_to_nfc(str) {
+  return pjStr(str.buffer.slice(0, Number(str.length)).normalize("NFC"));
+}"#,
+    ),
+    (
+        "pj_str_to_nfd",
+        r#"function pj_str_to_nfd(str) {
+  return pjStr(str.buffer.slice(0, Number(str.length)).normalize("NFD"));
+}"#,
+    ),
+    (
+        "pj_str_to_nfkc",
+        r#"function pj_str_to_nfkc(str) {
+  return pjStr(str.buffer.slice(0, Number(str.length)).normalize("NFKC"));
+}"#,
+    ),
+    (
+        "pj_str_to_nfkd",
+        r#"function pj_str_to_nfkd(str) {
+  return pjStr(str.buffer.slice(0, Number(str.length)).normalize("NFKD"));
+}"#,
+    ),
+    (
+        "pj_str_encode",
+        r#"function pj_str_encode(str, encoding, onInvalid) {
+  const text = str.buffer.slice(0, Number(str.length));
+  const name = encodingName(encoding);
+  const replace = replacesInvalid(onInvalid);
+  if (name === "utf-8") {
+    return [...new TextEncoder().encode(text)].map(BigInt);
+  }
+  if (name === "latin-1") {
+    return [...text].map((char) => {
+      const codepoint = char.codePointAt(0);
+      if (codepoint <= 0xff) return BigInt(codepoint);
+      if (replace) return 63n;
+      console.error("can't encode " + JSON.stringify(char) + " as Latin-1");
+      process.exit(1);
+    });
+  }
+  const bytes = [];
+  for (let i = 0; i < text.length; i++) {
+    const unit = text.charCodeAt(i);
+    const pair = name === "utf-16le" ? [unit & 0xff, unit >> 8] : [unit >> 8, unit & 0xff];
+    bytes.push(BigInt(pair[0]), BigInt(pair[1]));
+  }
+  return bytes;
+}"#,
+    ),
+    (
+        "pj_bytes_decode",
+        r#"function pj_bytes_decode(bytes, encoding, onInvalid) {
+  const name = encodingName(encoding);
+  const replace = replacesInvalid(onInvalid);
+  const raw = Uint8Array.from(bytes, (item, index) => {
+    if (item < 0n || item > 255n) {
+      console.error(item + " at " + index + " isn't a byte");
+      process.exit(1);
+    }
+    return Number(item);
+  });
+  if (name === "latin-1") {
+    return pjStr(Array.from(raw, (byte) => String.fromCharCode(byte)).join(""));
+  }
+  try {
+    return pjStr(new TextDecoder(name, { fatal: !replace }).decode(raw));
+  } catch (err) {
+    console.error("invalid " + name + " bytes");
+    process.exit(1);
+  }
+}"#,
+    ),
+    (
+        "pj_path_join",
+        r#"function pj_path_join(path, other) {
+  const base = path.buffer.slice(0, Number(path.length));
+  const name = other.buffer.slice(0, Number(other.length));
+  if (name.startsWith("/") || base === "") return pjStr(name);
+  return pjStr(base.endsWith("/") ? base + name : base + "/" + name);
+}"#,
+    ),
+    (
+        "pj_path_basename",
+        r#"function pj_path_basename(path) {
+  const name = require("path").posix.basename(path.buffer.slice(0, Number(path.length)));
+  return pjStr(name === ".." ? "" : name);
+}"#,
+    ),
+    (
+        "pj_path_dirname",
+        r#"function pj_path_dirname(path) {
+  return pjStr(require("path").posix.dirname(path.buffer.slice(0, Number(path.length))));
+}"#,
+    ),
+    (
+        "pj_path_extension",
+        r#"function pj_path_extension(path) {
+  return pjStr(require("path").posix.extname(path.buffer.slice(0, Number(path.length))).slice(1));
+}"#,
+    ),
+    (
+        "pj_path_absolute",
+        r#"function pj_path_absolute(path) {
+  let text = path.buffer.slice(0, Number(path.length));
+  if (text === "~" || text.startsWith("~/")) text = require("os").homedir() + text.slice(1);
+  return pjStr(require("path").resolve(text));
+}"#,
+    ),
+    (
+        "pj_csv_parse",
+        r#"function pj_csv_parse(text) {
+  const source = text.buffer.slice(0, Number(text.length));
+  const rows = [];
+  let row = [];
+  let field = "";
+  let quoted = false;
+  let started = false;
+  for (let i = 0; i < source.length; i++) {
+    const char = source[i];
+    if (quoted) {
+      if (char === '"' && source[i + 1] === '"') {
+        field += '"';
+        i++;
+      } else if (char === '"') {
+        quoted = false;
+      } else {
+        field += char;
+      }
+    } else if (char === '"') {
+      quoted = true;
+      started = true;
+    } else if (char === ",") {
+      row.push(pjStr(field));
+      field = "";
+      started = true;
+    } else if (char === "\n" || char === "\r") {
+      if (char === "\r" && source[i + 1] === "\n") i++;
+      if (started || field !== "") {
+        row.push(pjStr(field));
+        rows.push(row);
+      }
+      row = [];
+      field = "";
+      started = false;
+    } else {
+      field += char;
+      started = true;
+    }
+  }
+  if (started || field !== "") {
+    row.push(pjStr(field));
+    rows.push(row);
+  }
+  return rows;
+}"#,
+    ),
+    (
+        "pj_csv_write",
+        r#"function pj_csv_write(rows) {
+  const lines = rows.map((row) => {
+    const line = row
+      .map((field) => {
+        const text = field.buffer.slice(0, Number(field.length));
+        return /[",\r\n]/.test(text) ? '"' + text.replace(/"/g, '""') + '"' : text;
+      })
+      .join(",");
+    return (line === "" ? '""' : line) + "\n";
+  });
+  return pjStr(lines.join(""));
+}"#,
+    ),
+    (
+        "pj_log_info",
+        r#"function pj_log_info(message) {
+  pjLog("info", message);
+}"#,
+    ),
+    (
+        "pj_log_warn",
+        r#"function pj_log_warn(message) {
+  pjLog("warn", message);
+}"#,
+    ),
+    (
+        "pj_log_error",
+        r#"function pj_log_error(message) {
+  pjLog("error", message);
+}"#,
+    ),
+    (
+        "pj_array_new",
+        r#"function pj_array_new(capacity) {
+  return [];
+}"#,
+    ),
+    (
+        "pj_array_length",
+        r#"function pj_array_length(array) {
+  return BigInt(array.length);
+}"#,
+    ),
+    (
+        "pj_array_push_int",
+        r#"function pj_array_push_int(array, item) {
+  array.push(item);
+  return array;
+}"#,
+    ),
+    (
+        "pj_array_push_float",
+        r#"function pj_array_push_float(array, item) {
+  array.push(item);
+  return array;
+}"#,
+    ),
+    (
+        "pj_array_push_bool",
+        r#"function pj_array_push_bool(array, item) {
+  array.push(item);
+  return array;
+}"#,
+    ),
+    (
+        "pj_array_push_ptr",
+        r#"function pj_array_push_ptr(array, item) {
+  array.push(item);
+  return array;
+}"#,
+    ),
+    (
+        "pj_array_get_int",
+        r#"function pj_array_get_int(array, index) {
+  return array[arraySlot(array, index)];
+}"#,
+    ),
+    (
+        "pj_array_get_float",
+        r#"function pj_array_get_float(array, index) {
+  return array[arraySlot(array, index)];
+}"#,
+    ),
+    (
+        "pj_array_get_bool",
+        r#"function pj_array_get_bool(array, index) {
+  return array[arraySlot(array, index)];
+}"#,
+    ),
+    (
+        "pj_array_get_ptr",
+        r#"function pj_array_get_ptr(array, index) {
+  return array[arraySlot(array, index)];
+}"#,
+    ),
+    (
+        "pj_array_set_int",
+        r#"function pj_array_set_int(array, index, item) {
+  array[arraySlot(array, index)] = item;
+}"#,
+    ),
+    (
+        "pj_array_set_float",
+        r#"function pj_array_set_float(array, index, item) {
+  array[arraySlot(array, index)] = item;
+}"#,
+    ),
+    (
+        "pj_array_set_bool",
+        r#"function pj_array_set_bool(array, index, item) {
+  array[arraySlot(array, index)] = item;
+}"#,
+    ),
+    (
+        "pj_array_set_ptr",
+        r#"function pj_array_set_ptr(array, index, item) {
+  array[arraySlot(array, index)] = item;
+}"#,
+    ),
+    (
+        "pj_checked_add",
+        r#"function pj_checked_add(left, right) {
+  return checkedOrExit(left + right, "+");
+}"#,
+    ),
+    (
+        "pj_checked_sub",
+        r#"function pj_checked_sub(left, right) {
+  return checkedOrExit(left - right, "-");
+}"#,
+    ),
+    (
+        "pj_checked_mul",
+        r#"function pj_checked_mul(left, right) {
+  return checkedOrExit(left * right, "*");
+}"#,
+    ),
+    (
+        "pj_saturating_mul",
+        r#"function pj_saturating_mul(left, right) {
+  return saturate(left * right);
+}"#,
+    ),
+    (
+        "pj_add_overflows",
+        r#"function pj_add_overflows(left, right) {
+  return overflows(left + right);
+}"#,
+    ),
+    (
+        "pj_sub_overflows",
+        r#"function pj_sub_overflows(left, right) {
+  return overflows(left - right);
+}"#,
+    ),
+    (
+        "pj_mul_overflows",
+        r#"function pj_mul_overflows(left, right) {
+  return overflows(left * right);
+}"#,
+    ),
+    (
+        "llvm.sadd.sat.i64",
+        r#"function llvm_sadd_sat_i64(left, right) {
+  return saturate(left + right);
+}"#,
+    ),
+    (
+        "llvm.ssub.sat.i64",
+        r#"function llvm_ssub_sat_i64(left, right) {
+  return saturate(left - right);
+}"#,
+    ),
+    (
+        "pj_freeze",
+        r#"function pj_freeze(object) {
+  frozen.add(object);
+}"#,
+    ),
+    (
+        "pj_unfreeze",
+        r#"function pj_unfreeze(object) {
+  frozen.delete(object);
+}"#,
+    ),
+    (
+        "pj_sandbox_denied",
+        r#"function pj_sandbox_denied(name) {
+  console.error("sandbox: `" + name.buffer + "` isn't allowed");
+  process.exit(1);
+}"#,
+    ),
+    (
+        "pj_signal_trap",
+        r#"function pj_signal_trap(name, handler) {
+  const signal = "SIG" + name.buffer;
+  process.removeAllListeners(signal);
+  process.on(signal, () => handler());
+}"#,
+    ),
+    (
+        "pj_at_exit",
+        r#"function pj_at_exit(handler) {
+  if (exitHandlers.length === 0) {
+    process.on("exit", () => {
+      while (exitHandlers.length > 0) exitHandlers.pop()();
+    });
+  }
+  exitHandlers.push(handler);
+}"#,
+    ),
+    (
+        "pj_check_frozen",
+        r#"function pj_check_frozen(object, site) {
+  if (frozen.has(object)) {
+    console.error("can't modify frozen object: " + site.buffer);
+    process.exit(1);
+  }
+}"#,
+    ),
+];
+
+/// Helpers the runtime functions above share.
+const RUNTIME_HELPERS: &str = r#"const I64_MIN = -(2n ** 63n);
+const I64_MAX = 2n ** 63n - 1n;
+const frozen = new WeakSet();
+const exitHandlers = [];
+
+function pjStr(text) {
+  return new Str(text, BigInt(text.length), BigInt(text.length));
+}
+
+function graphemes(str) {
+  return new Intl.Segmenter().segment(str.buffer.slice(0, Number(str.length)));
+}
+
+function encodingName(encoding) {
+  const name = encoding.buffer.slice(0, Number(encoding.length)).toLowerCase();
+  const names = {
+    "utf-8": "utf-8", utf8: "utf-8", "utf-16": "utf-16le", "utf-16le": "utf-16le",
+    "utf-16be": "utf-16be", "latin-1": "latin-1", latin1: "latin-1", "iso-8859-1": "latin-1",
+  };
+  if (!(name in names)) {
+    console.error("unknown encoding `" + name + "`, expected utf-8, utf-16, utf-16le, utf-16be or latin-1");
+    process.exit(1);
+  }
+  return names[name];
+}
+
+function replacesInvalid(onInvalid) {
+  const handling = onInvalid.buffer.slice(0, Number(onInvalid.length));
+  if (handling !== "replace" && handling !== "error") {
+    console.error("unknown invalid sequence handling `" + handling + "`, expected replace or error");
+    process.exit(1);
+  }
+  return handling === "replace";
+}
+
+function saturate(value) {
+  return value < I64_MIN ? I64_MIN : value > I64_MAX ? I64_MAX : value;
+}
+
+function arraySlot(array, index) {
+  if (index < 0n || index >= BigInt(array.length)) {
+    console.error("index " + index + " is out of bounds for an array of length " + array.length);
+    process.exit(1);
+  }
+  return Number(index);
+}
+
+function pjLog(level, message) {
+  const levels = ["info", "warn", "error"];
+  const setting = process.env.PAJAMA_LOG_LEVEL || "info";
+  if (!levels.includes(setting.toLowerCase())) {
+    console.error("PAJAMA_LOG_LEVEL is `" + setting + "`, expected info, warn or error");
+    process.exit(1);
+  }
+  if (levels.indexOf(level) < levels.indexOf(setting.toLowerCase())) return;
+  const now = new Date();
+  const pad = (number, width = 2) => String(number).padStart(width, "0");
+  const offset = -now.getTimezoneOffset();
+  const zone = offset === 0 ? "Z" : (offset < 0 ? "-" : "+") +
+    pad(Math.floor(Math.abs(offset) / 60)) + ":" + pad(Math.abs(offset) % 60);
+  const time = now.getFullYear() + "-" + pad(now.getMonth() + 1) + "-" + pad(now.getDate()) +
+    "T" + pad(now.getHours()) + ":" + pad(now.getMinutes()) + ":" + pad(now.getSeconds()) +
+    "." + pad(now.getMilliseconds(), 3) + zone;
+  const text = message.buffer.slice(0, Number(message.length));
+  console.error(time + " " + level.toUpperCase().padEnd(5) + " " + text);
+}
+
+function overflows(value) {
+  return value < I64_MIN || value > I64_MAX;
+}
+
+function checkedOrExit(value, op) {
+  if (overflows(value)) {
+    console.error("Int " + op + " overflowed");
+    process.exit(1);
+  }
+  return value;
+}
+"#;
+
+/// Lowers a checked program to JavaScript, so simple programs run in Node or
+/// a browser. Experimental.
+///
+/// Classes become JS classes, with `C.new` as their constructor, and Int
+/// values become BigInts, so arithmetic is exact but doesn't wrap. Floats are
+/// plain numbers. Runtime functions without a JavaScript version throw when
+/// called.
+pub fn emit_js(result: &ParserResult) -> Result<String, String> {
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => return Err("Expected a module to emit".to_string()),
+    };
+
+    let emitter = JsEmitter { result };
+    let mut out = String::new();
+
+    out.push_str("// Generated by pajama --emit=js, experimental\n\"use strict\";\n\n");
+    out.push_str(RUNTIME_HELPERS);
+
+    for node in &module.methods {
+        if let Node::DefE(def_e) = node {
+            let name = &def_e.prototype.name;
+
+            out.push('\n');
+
+            match RUNTIME.iter().find(|(runtime_name, _)| runtime_name == name) {
+                Some((_, function)) => out.push_str(function),
+                None => out.push_str(&format!(
+                    "function {}() {{\n  throw new Error(\"`{}` isn't available in JavaScript\");\n}}",
+                    js_name(name),
+                    name
+                )),
+            }
+
+            out.push('\n');
+        }
+    }
+
+    for node in &module.methods {
+        if let Node::AssignConstant(constant) = node {
+            out.push_str(&format!(
+                "\nconst {} = {};\n",
+                js_name(&constant.name),
+                emitter.expr(&constant.value, "self")?
+            ));
+        }
+    }
+
+    // A superclass has to be declared before the classes extending it
+    let mut class_names: Vec<&String> = result.index.class_index.keys().collect();
+    class_names.sort();
+
+    let mut declared = HashSet::new();
+
+    for class_name in class_names {
+        for ancestor in result.index.class_ancestors(class_name).iter().rev() {
+            if declared.insert(ancestor.clone()) {
+                out.push('\n');
+                out.push_str(&emitter.class(&result.index.class_index[ancestor], module)?);
+            }
+        }
+    }
+
+    let mut has_main = false;
+
+    for node in &module.methods {
+        match node {
+            Node::Def(def) if emitter.method_class(def).is_some() => {}
+            Node::Def(def) if !def.trait_name.is_empty() && def.body.is_empty() => {}
+            Node::Def(def) => {
+                has_main |= def.main_fn;
+
+                out.push('\n');
+                out.push_str(&emitter.function(def, 0)?);
+            }
+            Node::DefE(_) | Node::AssignConstant(_) => {}
+            node => {
+                return Err(format!(
+                    "The JavaScript backend can't emit a top level {}",
+                    node.kind()
+                ))
+            }
+        }
+    }
+
+    if has_main {
+        out.push_str("\nmain();\n");
+    }
+
+    Ok(out)
+}
+
+struct JsEmitter<'a> {
+    result: &'a ParserResult,
+}
+
+struct FnCtx {
+    declared: HashSet<String>,
+    self_name: &'static str,
+    indent: usize,
+    out: String,
+}
+
+impl FnCtx {
+    fn line(&mut self, line: &str) {
+        self.out.push_str(&"  ".repeat(self.indent));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+impl<'a> JsEmitter<'a> {
+    fn class(&self, class: &parser::Class, module: &parser::Module) -> Result<String, String> {
+        let mut out = match &class.superclass {
+            Some(superclass) => format!("class {} extends {} {{\n", class.name, superclass),
+            None => format!("class {} {{\n", class.name),
+        };
+
+        let mut methods: Vec<&parser::Def> = module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) if def.prototype.name.ends_with(".alloca") => None,
+                Node::Def(def) if self.method_class(def) == Some(&class.name) => Some(def),
+                _ => None,
+            })
+            .collect();
+
+        // The constructor reads best first
+        methods.sort_by_key(|def| !def.prototype.name.ends_with(".new"));
+
+        for (index, def) in methods.iter().enumerate() {
+            if index > 0 {
+                out.push('\n');
+            }
+
+            out.push_str(&self.function(def, 1)?);
+        }
+
+        out.push_str("}\n");
+
+        Ok(out)
+    }
+
+    /// The class a def is a method of, when it's emitted inside that class.
+    /// Trait default methods stay functions taking the receiver as `self`.
+    fn method_class(&self, def: &parser::Def) -> Option<&String> {
+        let (class_name, _) = def.prototype.name.split_once('.')?;
+        let (class_name, _) = self.result.index.class_index.get_key_value(class_name)?;
+
+        Some(class_name)
+    }
+
+    fn function(&self, def: &parser::Def, indent: usize) -> Result<String, String> {
+        let name = &def.prototype.name;
+        let is_method = self.method_class(def).is_some();
+        let is_constructor = is_method && name.ends_with(".new");
+
+        // Methods get their receiver as `this` rather than as an argument
+        let args: Vec<&parser::Arg> = def
+            .prototype
+            .args
+            .iter()
+            .skip(if is_method { 1 } else { 0 })
+            .collect();
+
+        let mut ctx = FnCtx {
+            declared: args.iter().map(|arg| arg.name.clone()).collect(),
+            self_name: if is_method { "this" } else { "self" },
+            indent: indent + 1,
+            out: String::new(),
+        };
+
+        let arg_names: Vec<String> = args
+            .iter()
+            .map(|arg| match arg.name.as_str() {
+                "sret" => "self".to_string(),
+                name => js_name(name),
+            })
+            .collect();
+
+        let header = if is_constructor {
+            format!("constructor({}) {{", arg_names.join(", "))
+        } else if is_method {
+            format!(
+                "{}({}) {{",
+                js_name(name.split_once('.').unwrap().1),
+                arg_names.join(", ")
+            )
+        } else {
+            format!("function {}({}) {{", js_name(name), arg_names.join(", "))
+        };
+
+        ctx.out.push_str(&"  ".repeat(indent));
+        ctx.out.push_str(&header);
+        ctx.out.push('\n');
+
+        if is_constructor {
+            if let Some(class) = self.result.index.class_index.get(&def.class_name) {
+                if class.superclass.is_some() {
+                    ctx.line("super();");
+                }
+            }
+        }
+
+        // Declared up front, so they can be read after their branch
+        for assignment in branch_locals(&def.body) {
+            if ctx.declared.insert(assignment.name.clone()) {
+                let line = format!("let {};", js_name(&assignment.name));
+                ctx.line(&line);
+            }
+        }
+
+        for node in &def.body {
+            self.statement(node, &mut ctx)?;
+        }
+
+        ctx.out.push_str(&"  ".repeat(indent));
+        ctx.out.push_str("}\n");
+
+        Ok(ctx.out)
+    }
+
+    fn statement(&self, node: &Node, ctx: &mut FnCtx) -> Result<(), String> {
+        match node {
+            Node::AssignLocalVar(node) => {
+                let value = self.expr(&node.value, ctx.self_name)?;
+
+                let line = if ctx.declared.insert(node.name.clone()) {
+                    format!("let {} = {};", js_name(&node.name), value)
+                } else {
+                    format!("{} = {};", js_name(&node.name), value)
+                };
+
+                ctx.line(&line);
+            }
+            Node::AssignAttribute(node) => {
+                let line = format!(
+                    "{}.{} = {};",
+                    ctx.self_name,
+                    node.name,
+                    self.expr(&node.value, ctx.self_name)?
+                );
+                ctx.line(&line);
+            }
+            Node::AssignAttributeAccess(node) => {
+                let line = format!(
+                    "{} = {};",
+                    self.access(&node.access, ctx.self_name)?,
+                    self.expr(&node.value, ctx.self_name)?
+                );
+                ctx.line(&line);
+            }
+            Node::Ret(node) => {
+                let line = format!("return {};", self.expr(&node.value, ctx.self_name)?);
+                ctx.line(&line);
+            }
+            Node::Loop(node) => {
+                ctx.line("for (;;) {");
+                ctx.indent += 1;
+
+                for node in &node.body {
+                    self.statement(node, ctx)?;
+                }
+
+                ctx.indent -= 1;
+                ctx.line("}");
+            }
+            Node::While(node) => {
+                let line = format!("while ({}) {{", self.expr(&node.condition, ctx.self_name)?);
+                ctx.line(&line);
+                self.branch(&node.body, ctx)?;
+                ctx.line("}");
+            }
+            Node::Break => ctx.line("break;"),
+            Node::Next => ctx.line("continue;"),
+            Node::If(node) => {
+                let line = format!("if ({}) {{", self.expr(&node.condition, ctx.self_name)?);
+                ctx.line(&line);
+                self.branch(&node.then_body, ctx)?;
+
+                if !node.else_body.is_empty() {
+                    ctx.line("} else {");
+                    self.branch(&node.else_body, ctx)?;
+                }
+
+                ctx.line("}");
+            }
+            node => {
+                let line = format!("{};", self.expr(node, ctx.self_name)?);
+                ctx.line(&line);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The body of an `if` branch or a `while`. The locals first assigned in
+    /// it are already declared by `function`.
+    fn branch(&self, body: &[Node], ctx: &mut FnCtx) -> Result<(), String> {
+        ctx.indent += 1;
+
+        for node in body {
+            self.statement(node, ctx)?;
+        }
+
+        ctx.indent -= 1;
+
+        Ok(())
+    }
+
+    fn expr(&self, node: &Node, self_name: &str) -> Result<String, String> {
+        match node {
+            Node::Access(node) => self.access(node, self_name),
+            Node::Array(node) => Ok(format!("[{}]", self.exprs(&node.items, self_name)?)),
+            Node::Binary(node) => Ok(format!(
+                "{} {} {}",
+                self.numeric_operand(node, &node.left, self_name)?,
+                js_op(&node.op),
+                self.numeric_operand(node, &node.right, self_name)?
+            )),
+            Node::Bool(node) => Ok(node.value.to_string()),
+            Node::BuildStruct(node) => {
+                let attributes = match self.result.index.struct_index.get(&node.name) {
+                    Some(struct_node) => &struct_node.attributes,
+                    None => return Err(format!("Unknown struct `{}`", node.name)),
+                };
+
+                let fields: Result<Vec<String>, String> = attributes
+                    .iter()
+                    .zip(&node.args)
+                    .map(|(attribute, arg)| {
+                        Ok(format!(
+                            "{}: {}",
+                            attribute.name,
+                            self.expr(arg, self_name)?
+                        ))
+                    })
+                    .collect();
+
+                Ok(format!("{{ {} }}", fields?.join(", ")))
+            }
+            Node::Call(node) => Ok(format!(
+                "{}({})",
+                js_name(&node.fn_name),
+                self.exprs(&node.args, self_name)?
+            )),
+            Node::Const(node) => Ok(js_name(&node.name)),
+            Node::Float(node) => Ok(format!("{:?}", node.value)),
+            Node::FnRef(node) => Ok(js_name(&node.fn_name)),
+            Node::If(node) => match (node.then_body.as_slice(), node.else_body.as_slice()) {
+                ([then_value], [else_value]) => Ok(format!(
+                    "({} ? {} : {})",
+                    self.expr(&node.condition, self_name)?,
+                    self.expr(then_value, self_name)?,
+                    self.expr(else_value, self_name)?
+                )),
+                _ => Err(
+                    "The JavaScript backend only supports if expressions with one expression per branch"
+                        .to_string(),
+                ),
+            },
+            // Values past i64::MAX, like the mask `~x` is parsed with, are
+            // their two's complement
+            Node::Int(node) => Ok(format!("{}n", node.value as i64)),
+            Node::LocalVar(node) if node.name == "sret" => Ok(self_name.to_string()),
+            Node::LocalVar(node) => Ok(js_name(&node.name)),
+            Node::Nil(_) => Ok("null".to_string()),
+            Node::SelfRef(_) => Ok(self_name.to_string()),
+            Node::Send(node) => self.send(node, self_name),
+            Node::StringLiteral(node) => {
+                let length = node.value.encode_utf16().count();
+
+                Ok(format!(
+                    "new Str({}, {}n, {}n)",
+                    string_literal(&node.value),
+                    length,
+                    length
+                ))
+            }
+            node => Err(format!(
+                "The JavaScript backend doesn't support {} expressions",
+                node.kind()
+            )),
+        }
+    }
+
+    fn exprs(&self, nodes: &[Node], self_name: &str) -> Result<String, String> {
+        let values: Result<Vec<String>, String> = nodes
+            .iter()
+            .map(|node| self.expr(node, self_name))
+            .collect();
+
+        Ok(values?.join(", "))
+    }
+
+    /// An expression used as an operand, parenthesized when it's itself an
+    /// operation, since JS's precedence differs from ours for bitwise
+    /// operators.
+    fn operand(&self, node: &Node, self_name: &str) -> Result<String, String> {
+        match node {
+            Node::Binary(_) => Ok(format!("({})", self.expr(node, self_name)?)),
+            node => self.expr(node, self_name),
+        }
+    }
+
+    /// An operand of `binary`. Ints are BigInts and floats are numbers, which
+    /// don't mix, so an Int is converted when the other operand is a float.
+    fn numeric_operand(
+        &self,
+        binary: &parser::Binary,
+        operand: &Node,
+        self_name: &str,
+    ) -> Result<String, String> {
+        let value = self.operand(operand, self_name)?;
+
+        let is_float = |node: &Node| typed_node_base_type(node) == Some(BaseType::Float);
+
+        if !is_float(operand) && (is_float(&binary.left) || is_float(&binary.right)) {
+            Ok(format!("Number({})", value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn access(&self, access: &parser::Access, self_name: &str) -> Result<String, String> {
+        match access.message.as_ref() {
+            Node::Attribute(attribute) => Ok(format!(
+                "{}.{}",
+                self.operand(&access.receiver, self_name)?,
+                attribute.name
+            )),
+            _ => Err("Expected an attribute to access".to_string()),
+        }
+    }
+
+    fn send(&self, send: &parser::Send, self_name: &str) -> Result<String, String> {
+        let call = match send.message.as_ref() {
+            Node::Call(call) => call,
+            _ => return Err("Expected the message of a send to be a call".to_string()),
+        };
+
+        if let Node::Const(class) = send.receiver.as_ref() {
+            if call.fn_name.ends_with(".new") {
+                return Ok(format!(
+                    "new {}({})",
+                    class.name,
+                    self.exprs(&call.args, self_name)?
+                ));
+            }
+
+            if call.fn_name.ends_with(".alloca") {
+                return Ok(format!("new {}()", class.name));
+            }
+        }
+
+        if call.fn_name == "fn_ref" {
+            if let Node::LocalVar(local_var) = send.receiver.as_ref() {
+                return Ok(js_name(&local_var.name));
+            }
+        }
+
+        let fn_name = self.resolve_fn_name(&call.fn_name);
+        let receiver = self.operand(&send.receiver, self_name)?;
+        let args = self.exprs(&call.args, self_name)?;
+        // `&.` is JS's optional chaining
+        let dot = if send.safe_navigation { "?." } else { "." };
+
+        // Arrays are JS arrays, which iterate themselves
+        if !self.result.index.fn_prototype_index.contains_key(&fn_name) {
+            match fn_name.as_str() {
+                "Array.each" => return Ok(format!("{}{}forEach({})", receiver, dot, args)),
+                "Array.map" => return Ok(format!("{}{}map({})", receiver, dot, args)),
+                _ => {}
+            }
+        }
+
+        match fn_name.split_once('.') {
+            Some((class_name, method_name))
+                if self.result.index.class_index.contains_key(class_name) =>
+            {
+                Ok(format!(
+                    "{}{}{}({})",
+                    receiver,
+                    dot,
+                    js_name(method_name),
+                    args
+                ))
+            }
+            _ if send.safe_navigation => Err(format!(
+                "The JavaScript backend only supports `&.` for methods of classes, not `{}`",
+                fn_name
+            )),
+            _ if args.is_empty() => Ok(format!("{}({})", js_name(&fn_name), receiver)),
+            _ => Ok(format!("{}({}, {})", js_name(&fn_name), receiver, args)),
+        }
+    }
+
+    /// Maps a method name such as `Dog.speak` to the function that implements
+    /// it, like codegen does.
+    fn resolve_fn_name(&self, fn_name: &str) -> String {
+        let index = &self.result.index;
+
+        if index.fn_prototype_index.contains_key(fn_name) {
+            return fn_name.to_string();
+        }
+
+        match fn_name.split_once('.') {
+            Some((class_name, method_name)) => {
+                match index.resolve_method(class_name, method_name) {
+                    Ok(Some(resolved_name)) => resolved_name,
+                    _ => fn_name.to_string(),
+                }
+            }
+            None => fn_name.to_string(),
+        }
+    }
+}
+
+/// `==` and `!=` compare strictly, JS's loose equality converts between types.
+fn js_op(op: &str) -> &str {
+    match op {
+        "==" => "===",
+        "!=" => "!==",
+        op => op,
+    }
+}
+
+/// `Speak.greet` becomes `Speak_greet`, `Vec.+` becomes `Vec_op_add`, and
+/// names that are reserved in JS get a trailing underscore.
+fn js_name(name: &str) -> String {
+    let name = parser::mangle_method_name(name).replace('.', "_");
+
+    if JS_KEYWORDS
+        .split_whitespace()
+        .any(|keyword| keyword == name)
+    {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+fn string_literal(value: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for ch in value.chars() {
+        match ch {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            ch if ch.is_control() => literal.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => literal.push(ch),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
@@ -1,10 +1,68 @@
 use std::{iter::Peekable, str::Chars};
 
-#[derive(Debug, Clone)]
+/// Where a token is in its file. Lines and columns start at 1, and
+/// `end_column` is the column of the token's last character.
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenPosition {
-    line: usize,
-    start_column: usize,
-    end_column: usize,
+    pub line: usize,
+    pub start_column: usize,
+    pub end_column: usize,
+}
+
+/// What a `Token` is without what it carries, so the parser can check for and
+/// expect tokens without building or cloning one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Arrow,
+    Assign,
+    Attribute,
+    Binary,
+    Break,
+    Class,
+    Colon,
+    Comma,
+    Const,
+    Def,
+    DefE,
+    Defer,
+    Do,
+    Dot,
+    DotDot,
+    Else,
+    Elsif,
+    End,
+    False,
+    Float,
+    Ident,
+    If,
+    Illegal,
+    Impl,
+    Import,
+    LCurlyBrace,
+    Loop,
+    LParen,
+    LSquareBrace,
+    NewLine,
+    Next,
+    Nil,
+    Number,
+    Op,
+    RCurlyBrace,
+    Ret,
+    RParen,
+    RSquareBrace,
+    SafeNav,
+    SelfRef,
+    Space,
+    Spaceship,
+    StringLiteral,
+    Super,
+    Comment,
+    Trait,
+    True,
+    Unary,
+    Struct,
+    While,
 }
 
 #[derive(Debug, Clone)]
@@ -13,34 +71,145 @@ pub enum Token {
     Assign,
     Attribute(TokenPosition, String),
     Binary,
+    Break,
     Class,
+    Colon,
     Comma,
     Const(TokenPosition, String),
     Def,
     DefE,
+    Defer,
+    Do,
     Dot,
+    DotDot,
+    Else,
+    Elsif,
     End,
+    False,
+    Float(TokenPosition, f64),
     Ident(TokenPosition, String),
+    If,
     Illegal(TokenPosition, String),
     Impl,
-    LCurlyBrace,
+    Import,
+    LCurlyBrace(TokenPosition),
     Loop,
-    LParen,
-    LSquareBrace,
+    LParen(TokenPosition),
+    LSquareBrace(TokenPosition),
     NewLine(usize),
+    Next,
+    Nil,
     Number(TokenPosition, u64),
-    Op(char),
+    Op(String),
     RCurlyBrace,
     Ret,
     RParen,
     RSquareBrace,
+    SafeNav,
     SelfRef,
     Space(usize),
+    Spaceship,
     StringLiteral(TokenPosition, String),
+    Super,
     Comment(TokenPosition, String),
     Trait,
+    True,
     Unary,
     Struct,
+    While,
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Arrow => TokenKind::Arrow,
+            Token::Assign => TokenKind::Assign,
+            Token::Attribute(..) => TokenKind::Attribute,
+            Token::Binary => TokenKind::Binary,
+            Token::Break => TokenKind::Break,
+            Token::Class => TokenKind::Class,
+            Token::Colon => TokenKind::Colon,
+            Token::Comma => TokenKind::Comma,
+            Token::Const(..) => TokenKind::Const,
+            Token::Def => TokenKind::Def,
+            Token::DefE => TokenKind::DefE,
+            Token::Defer => TokenKind::Defer,
+            Token::Do => TokenKind::Do,
+            Token::Dot => TokenKind::Dot,
+            Token::DotDot => TokenKind::DotDot,
+            Token::Else => TokenKind::Else,
+            Token::Elsif => TokenKind::Elsif,
+            Token::End => TokenKind::End,
+            Token::False => TokenKind::False,
+            Token::Ident(..) => TokenKind::Ident,
+            Token::Float(..) => TokenKind::Float,
+            Token::If => TokenKind::If,
+            Token::Illegal(..) => TokenKind::Illegal,
+            Token::Impl => TokenKind::Impl,
+            Token::Import => TokenKind::Import,
+            Token::LCurlyBrace(..) => TokenKind::LCurlyBrace,
+            Token::Loop => TokenKind::Loop,
+            Token::LParen(..) => TokenKind::LParen,
+            Token::LSquareBrace(..) => TokenKind::LSquareBrace,
+            Token::NewLine(..) => TokenKind::NewLine,
+            Token::Next => TokenKind::Next,
+            Token::Nil => TokenKind::Nil,
+            Token::Number(..) => TokenKind::Number,
+            Token::Op(..) => TokenKind::Op,
+            Token::RCurlyBrace => TokenKind::RCurlyBrace,
+            Token::Ret => TokenKind::Ret,
+            Token::RParen => TokenKind::RParen,
+            Token::RSquareBrace => TokenKind::RSquareBrace,
+            Token::SafeNav => TokenKind::SafeNav,
+            Token::SelfRef => TokenKind::SelfRef,
+            Token::Space(..) => TokenKind::Space,
+            Token::Spaceship => TokenKind::Spaceship,
+            Token::StringLiteral(..) => TokenKind::StringLiteral,
+            Token::Super => TokenKind::Super,
+            Token::Comment(..) => TokenKind::Comment,
+            Token::Trait => TokenKind::Trait,
+            Token::True => TokenKind::True,
+            Token::Unary => TokenKind::Unary,
+            Token::Struct => TokenKind::Struct,
+            Token::While => TokenKind::While,
+        }
+    }
+
+    /// The position of tokens that carry one. Keywords and punctuation don't,
+    /// except for the brackets that open something, which errors point at.
+    pub fn position(&self) -> Option<&TokenPosition> {
+        match self {
+            Token::Attribute(position, _)
+            | Token::Comment(position, _)
+            | Token::Const(position, _)
+            | Token::Float(position, _)
+            | Token::Ident(position, _)
+            | Token::Illegal(position, _)
+            | Token::LCurlyBrace(position)
+            | Token::LParen(position)
+            | Token::LSquareBrace(position)
+            | Token::Number(position, _)
+            | Token::StringLiteral(position, _) => Some(position),
+            _ => None,
+        }
+    }
+
+    pub fn position_mut(&mut self) -> Option<&mut TokenPosition> {
+        match self {
+            Token::Attribute(position, _)
+            | Token::Comment(position, _)
+            | Token::Const(position, _)
+            | Token::Float(position, _)
+            | Token::Ident(position, _)
+            | Token::Illegal(position, _)
+            | Token::LCurlyBrace(position)
+            | Token::LParen(position)
+            | Token::LSquareBrace(position)
+            | Token::Number(position, _)
+            | Token::StringLiteral(position, _) => Some(position),
+            _ => None,
+        }
+    }
 }
 
 pub struct Lexer<'a> {
@@ -53,12 +222,39 @@ pub struct Lexer<'a> {
 
 impl Lexer<'_> {
     pub fn new(input: &str) -> Lexer {
-        Lexer {
+        let mut lexer = Lexer {
             input,
             chars: Box::new(input.chars().peekable()),
             char_pos: 0,
             line_pos: 1,
             column_pos: 0,
+        };
+
+        // A `#!/usr/bin/env pajama` line is for the shell running the file
+        // as a script, it's skipped rather than lexed as a comment
+        if input.starts_with("#!") {
+            let line = input.split_inclusive('\n').next().unwrap_or(input);
+
+            for _ in line.chars() {
+                lexer.chars.next();
+            }
+
+            lexer.char_pos = line.len();
+
+            if line.ends_with('\n') {
+                lexer.line_pos += 1;
+            }
+        }
+
+        lexer
+    }
+
+    /// The position of the one character token just read
+    fn position_here(&self) -> TokenPosition {
+        TokenPosition {
+            line: self.line_pos,
+            start_column: self.column_pos,
+            end_column: self.column_pos,
         }
     }
 
@@ -94,11 +290,14 @@ impl Lexer<'_> {
                     end_column: self.column_pos,
                 };
 
+                let mut ends_line = false;
+
                 loop {
                     let ch = self.chars.next();
 
                     self.column_pos += 1;
-                    pos += 1;
+                    // Slices of the input are by byte, and stop at its end
+                    pos += ch.map_or(0, char::len_utf8);
 
                     let ch = match ch {
                         Some(ch) => ch,
@@ -106,12 +305,20 @@ impl Lexer<'_> {
                     };
 
                     if let '\n' = ch {
+                        ends_line = true;
                         break;
                     }
                 }
 
                 token_pos.end_column = self.column_pos;
 
+                // The comment takes the newline with it, so the line is
+                // counted here rather than by a NewLine token
+                if ends_line {
+                    self.line_pos += 1;
+                    self.column_pos = 0;
+                }
+
                 Token::Comment(token_pos, src[start..pos].to_string())
             }
             ' ' => {
@@ -162,13 +369,24 @@ impl Lexer<'_> {
 
                 Token::NewLine(newline_length)
             }
-            '(' => Token::LParen,
+            '(' => Token::LParen(self.position_here()),
             ')' => Token::RParen,
-            '[' => Token::LSquareBrace,
+            '[' => Token::LSquareBrace(self.position_here()),
             ']' => Token::RSquareBrace,
-            '{' => Token::LCurlyBrace,
+            '{' => Token::LCurlyBrace(self.position_here()),
             '}' => Token::RCurlyBrace,
             ',' => Token::Comma,
+            // Follows the name of an argument given by keyword, as in `greet(name: "Rex")`
+            ':' => Token::Colon,
+            // `..` separates the ends of a range, like in `s[0..5]`
+            '.' if self.chars.peek() == Some(&'.') => {
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                Token::DotDot
+            }
             '.' => Token::Dot,
             '"' => {
                 let mut token_pos = TokenPosition {
@@ -177,13 +395,16 @@ impl Lexer<'_> {
                     end_column: self.column_pos,
                 };
 
+                let quote = start;
                 let mut string = String::new();
+                let mut terminated = false;
 
                 loop {
                     let ch = self.chars.next();
 
                     self.column_pos += 1;
-                    pos += 1;
+                    // Slices of the input are by byte
+                    pos += ch.map_or(1, char::len_utf8);
 
                     let ch = match ch {
                         Some(ch) => ch,
@@ -191,7 +412,10 @@ impl Lexer<'_> {
                     };
 
                     match ch {
-                        '"' => break,
+                        '"' => {
+                            terminated = true;
+                            break;
+                        }
                         '\\' => match self.chars.peek() {
                             Some(next_ch) => match next_ch {
                                 'n' => {
@@ -224,11 +448,17 @@ impl Lexer<'_> {
                     }
                 }
 
-                token_pos.end_column = self.column_pos;
+                // Runs to the end of the input, the parser reports it at
+                // the opening quote
+                if !terminated {
+                    Token::Illegal(token_pos, src[quote..].to_string())
+                } else {
+                    token_pos.end_column = self.column_pos;
 
-                string.push_str(&src[start + 1..pos - 1]);
+                    string.push_str(&src[start + 1..pos - 1]);
 
-                Token::StringLiteral(token_pos, string)
+                    Token::StringLiteral(token_pos, string)
+                }
             }
 
             '0'..='9' => {
@@ -238,6 +468,8 @@ impl Lexer<'_> {
                     end_column: self.column_pos,
                 };
 
+                let mut is_float = false;
+
                 // Parse number literal
                 loop {
                     let next_ch = match self.chars.peek() {
@@ -252,19 +484,34 @@ impl Lexer<'_> {
                             self.column_pos += 1;
                             pos += 1;
                         }
-                        // '.' => {
-                        //     self.chars.next();
+                        // Only a fraction when a digit follows, `3.times` is a send
+                        '.' if !is_float
+                            && src[pos + 1..].starts_with(|ch: char| ch.is_ascii_digit()) =>
+                        {
+                            self.chars.next();
 
-                        //     self.column_pos += 1;
-                        //     pos += 1;
-                        // },
+                            self.column_pos += 1;
+                            pos += 1;
+                            is_float = true;
+                        }
                         _ => break,
                     }
                 }
 
                 token_pos.end_column = self.column_pos;
 
-                Token::Number(token_pos, src[start..pos].parse().unwrap())
+                if is_float {
+                    match src[start..pos].parse() {
+                        Ok(value) => Token::Float(token_pos, value),
+                        Err(_) => Token::Illegal(token_pos, src[start..pos].to_string()),
+                    }
+                } else {
+                    match src[start..pos].parse() {
+                        Ok(value) => Token::Number(token_pos, value),
+                        // Too big for a u64
+                        Err(_) => Token::Illegal(token_pos, src[start..pos].to_string()),
+                    }
+                }
             }
 
             'A'..='Z' => {
@@ -282,6 +529,14 @@ impl Lexer<'_> {
 
                     match ch {
                         'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {}
+                        // Optional types like `Str?` end with a question mark
+                        '?' => {
+                            self.chars.next();
+
+                            self.column_pos += 1;
+                            pos += 1;
+                            break;
+                        }
                         _ => break,
                     }
 
@@ -315,7 +570,17 @@ impl Lexer<'_> {
                     };
 
                     match ch {
-                        'a'..='z' | '_' | '0'..='9' => {}
+                        // Uppercase is lexed so `camelCase` names reach the
+                        // naming-conventions lint instead of splitting in two
+                        'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => {}
+                        // Predicates like `empty?` end with a question mark
+                        '?' => {
+                            self.chars.next();
+
+                            self.column_pos += 1;
+                            pos += 1;
+                            break;
+                        }
                         _ => break,
                     }
 
@@ -329,17 +594,30 @@ impl Lexer<'_> {
 
                 match src_ident {
                     "binary" => Token::Binary,
+                    "break" => Token::Break,
                     "class" => Token::Class,
                     "def_e" => Token::DefE,
                     "def" => Token::Def,
+                    "defer" => Token::Defer,
+                    "do" => Token::Do,
+                    "else" => Token::Else,
+                    "elsif" => Token::Elsif,
                     "end" => Token::End,
+                    "false" => Token::False,
+                    "if" => Token::If,
                     "impl" => Token::Impl,
+                    "import" => Token::Import,
                     "loop" => Token::Loop,
-                    "ret" => Token::Ret,
+                    "next" => Token::Next,
+                    "nil" => Token::Nil,
+                    "ret" | "return" => Token::Ret,
                     "self" => Token::SelfRef,
                     "struct" => Token::Struct,
+                    "super" => Token::Super,
                     "trait" => Token::Trait,
+                    "true" => Token::True,
                     "unary" => Token::Unary,
+                    "while" => Token::While,
                     ident => {
                         token_pos.end_column = self.column_pos;
                         Token::Ident(token_pos, ident.to_string())
@@ -350,12 +628,12 @@ impl Lexer<'_> {
             '-' => {
                 let next_chr = match self.chars.peek() {
                     Some(ch) => *ch,
-                    None => return Some(Token::Op('-')),
+                    None => return Some(Token::Op("-".to_string())),
                 };
 
                 if next_chr != '>' {
                     self.char_pos = pos;
-                    return Some(Token::Op('-'));
+                    return Some(Token::Op("-".to_string()));
                 }
 
                 self.chars.next();
@@ -366,9 +644,102 @@ impl Lexer<'_> {
                 Token::Arrow
             }
 
-            '>' => Token::Op('>'),
+            '<' => {
+                if self.chars.peek() == Some(&'<') {
+                    self.chars.next();
+
+                    self.column_pos += 1;
+                    pos += 1;
+
+                    self.char_pos = pos;
+                    return Some(Token::Op("<<".to_string()));
+                }
+
+                if self.chars.peek() != Some(&'=') {
+                    self.char_pos = pos;
+                    return Some(Token::Op("<".to_string()));
+                }
+
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                match self.chars.peek() {
+                    Some('>') => {
+                        self.chars.next();
+
+                        self.column_pos += 1;
+                        pos += 1;
+
+                        Token::Spaceship
+                    }
+                    _ => Token::Op("<=".to_string()),
+                }
+            }
+            '>' => {
+                let op = match self.chars.peek() {
+                    Some('>') => ">>",
+                    Some('=') => ">=",
+                    _ => {
+                        self.char_pos = pos;
+                        return Some(Token::Op(">".to_string()));
+                    }
+                };
+
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                Token::Op(op.to_string())
+            }
+            // `&.` sends a message unless the receiver is nil
+            '&' if self.chars.peek() == Some(&'.') => {
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                Token::SafeNav
+            }
+            // `&&` and `||` short-circuit, `&` and `|` are bitwise
+            '&' | '|' => {
+                if self.chars.peek() != Some(&ch) {
+                    self.char_pos = pos;
+                    return Some(Token::Op(ch.to_string()));
+                }
+
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
 
-            '=' => Token::Assign,
+                Token::Op(format!("{}{}", ch, ch))
+            }
+            '+' | '*' | '/' | '^' | '~' => Token::Op(ch.to_string()),
+
+            '=' => {
+                if self.chars.peek() != Some(&'=') {
+                    self.char_pos = pos;
+                    return Some(Token::Assign);
+                }
+
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                Token::Op("==".to_string())
+            }
+            '!' if self.chars.peek() == Some(&'=') => {
+                self.chars.next();
+
+                self.column_pos += 1;
+                pos += 1;
+
+                Token::Op("!=".to_string())
+            }
 
             '@' => {
                 let mut token_pos = TokenPosition {
@@ -402,8 +773,17 @@ impl Lexer<'_> {
             }
 
             _ => {
-                println!("NOT IMPL{:#?}", ch);
-                todo!()
+                // Slices of the input are by byte
+                pos += ch.len_utf8() - 1;
+
+                Token::Illegal(
+                    TokenPosition {
+                        line: self.line_pos,
+                        start_column: self.column_pos,
+                        end_column: self.column_pos,
+                    },
+                    ch.to_string(),
+                )
             } // op => {
               //     // Parse operator
               //     Ok(Token::Op(op))
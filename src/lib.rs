@@ -1,6 +1,110 @@
 pub mod pajama_compiler;
 pub mod pajama_lib;
 pub mod codegen;
+pub mod allocator;
+pub mod ast;
+pub mod ast_diff;
+pub mod backend;
+pub mod build_info;
+pub mod bytecode;
+pub mod c_backend;
+pub mod cancellation;
+pub mod cli;
+pub mod columns;
+pub mod consteval;
+pub mod cpu_profile;
+pub mod dead_code;
+pub mod diagnostic;
+pub mod error_codes;
+pub mod expected_diagnostics;
+pub mod explain;
+pub mod graph;
+pub mod heap_profile;
+pub mod js_backend;
 pub mod lexer;
+pub mod lints;
+pub mod locale;
+pub mod memory_stats;
+pub mod metrics;
+pub mod operators;
+pub mod optimization;
+pub mod parallel;
 pub mod parser;
+pub mod queries;
+pub mod relex;
+pub mod repl;
+pub mod resource_limits;
+pub mod runtime_profile;
 pub mod semantic_analyzer;
+pub mod source;
+pub mod type_checker;
+pub mod vm;
+
+use cancellation::CancellationToken;
+use diagnostic::Diagnostic;
+use lexer::{Lexer, Token};
+use pajama_compiler::{CompileOptions, PajamaCompiler};
+use parser::{default_op_precedence, Parser, ParserResult};
+use source::SourceFile;
+
+/// The path diagnostics name for a program compiled from a string
+const INPUT_PATH: &str = "main.pjs";
+
+/// Lexes, parses and analyzes `input` as a program of one file, returning
+/// the diagnostics instead of printing them. Tools embedding the compiler,
+/// like an editor or a test harness, start here; `PajamaCompiler` has the
+/// entry points for several files and other options.
+///
+/// Files `input` imports are found relative to the working directory.
+pub fn compile_to_ast(input: &str) -> Result<ParserResult, Vec<Diagnostic>> {
+    let (sources, diagnostics) = source::resolve_imports(
+        vec![SourceFile {
+            path: INPUT_PATH.to_string(),
+            input: input.to_string(),
+        }],
+        false,
+    );
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    // Nothing cancels a compile with the default options
+    PajamaCompiler::compile_to_ast(&sources, &CompileOptions::default()).unwrap()
+}
+
+/// Lexes `input` without parsing it, for tools that run several analyses,
+/// like formatting and linting, over one lexed buffer with `parse_tokens`.
+pub fn lex_str(input: &str) -> Vec<Token> {
+    Lexer::new(input).tokenize()
+}
+
+/// Parses `tokens` as a program of one file, borrowing them instead of
+/// taking or copying them, so the caller can keep parsing the same buffer.
+/// Imports aren't followed and the program isn't analyzed.
+pub fn parse_tokens(tokens: &[Token]) -> Result<ParserResult, Vec<Diagnostic>> {
+    let mut op_precedence = default_op_precedence();
+
+    // Nothing cancels a parse with a token no one else holds
+    Parser::parse_tokens(
+        tokens.into(),
+        &mut op_precedence,
+        &CancellationToken::new(),
+        vec![],
+    )
+    .unwrap()
+}
+
+/// Compiles `input` to MLIR in the LLVM dialect, like `--emit=ir`.
+pub fn compile_to_ir(input: &str) -> Result<String, Vec<Diagnostic>> {
+    let parser_result = compile_to_ast(input)?;
+
+    Ok(PajamaCompiler::ast_to_ir(&parser_result, &CompileOptions::default()).unwrap())
+}
+
+/// Compiles and runs `input`, returning the status its `main` exits with.
+pub fn run(input: &str) -> Result<i32, Vec<Diagnostic>> {
+    let parser_result = compile_to_ast(input)?;
+
+    Ok(PajamaCompiler::run_ast(&parser_result, &CompileOptions::default()).unwrap())
+}
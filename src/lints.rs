@@ -0,0 +1,783 @@
+use crate::ast::{visit_nodes, walk_node, Visitor};
+use crate::parser::{BaseType, Binary, Node, ParserResult};
+use crate::semantic_analyzer::{pajama_class_name, typed_node_base_type, Diagnostics};
+
+/// A house rule, like a naming convention or a banned builtin, checked
+/// against the typed AST once semantic analysis is done.
+///
+/// Lints are registered with `CompileOptions::lints` or `Database::add_lint`,
+/// and their messages are reported alongside the compiler's own, prefixed with
+/// the lint's name.
+pub trait LintPlugin {
+    fn name(&self) -> &str;
+
+    fn check(&self, result: &ParserResult) -> Vec<String>;
+}
+
+pub fn run_lints(
+    lints: &[Box<dyn LintPlugin>],
+    result: &ParserResult,
+    diagnostics: &mut Diagnostics,
+) {
+    for lint in lints {
+        for message in lint.check(result) {
+            diagnostics.error(format!("{}: {}", lint.name(), message));
+        }
+    }
+}
+
+/// Descriptors returned by runtime functions, and the function closing them
+const RESOURCE_FNS: [(&str, &str); 4] = [
+    ("open", "close"),
+    ("fopen", "fclose"),
+    ("socket", "close"),
+    ("accept", "close"),
+];
+
+/// Instances of these classes are closed with their `close` method
+const RESOURCE_CLASSES: [&str; 2] = ["File", "Socket"];
+
+/// Reports files and sockets a def opens into a local but doesn't close on
+/// every path it returns from: instances of `File` or `Socket`, and the
+/// descriptors `open`, `fopen`, `socket` and `accept` return.
+///
+/// Runs on the typed AST, where `defer` is already lowered into a close
+/// before each return. Its ifs, loops and returns are the def's control
+/// flow, so each path through them is followed. A resource that's returned
+/// or assigned to an attribute is left for its new owner to close.
+pub struct UnclosedResources;
+
+impl LintPlugin for UnclosedResources {
+    fn name(&self) -> &str {
+        "unclosed-resources"
+    }
+
+    fn check(&self, result: &ParserResult) -> Vec<String> {
+        let module = match &result.module {
+            Node::Module(module) => module,
+            _ => return vec![],
+        };
+
+        let mut messages = vec![];
+
+        for node in &module.methods {
+            let def = match node {
+                Node::Def(def) => def,
+                _ => continue,
+            };
+
+            let mut unclosed = vec![];
+
+            if let Some(open) = check_resource_paths(&def.body, vec![], &mut unclosed) {
+                add_unclosed(&mut unclosed, open);
+            }
+
+            for resource in unclosed {
+                messages.push(format!(
+                    "`{}` is opened in `{}` but not closed on every path, `defer {}` closes it when `{}` returns",
+                    resource.name, def.prototype.name, resource.close, def.prototype.name
+                ));
+            }
+        }
+
+        messages
+    }
+}
+
+/// A local holding an open resource, and how it's closed, like `close(fd)`
+#[derive(Clone, PartialEq)]
+struct Resource {
+    name: String,
+    close: String,
+}
+
+/// Follows `body` with the `open` resources, adding the ones still open when
+/// it returns to `unclosed`. The resources open after it, or `None` when it
+/// always returns.
+fn check_resource_paths(
+    body: &[Node],
+    mut open: Vec<Resource>,
+    unclosed: &mut Vec<Resource>,
+) -> Option<Vec<Resource>> {
+    for node in body {
+        match node {
+            Node::Ret(ret) => {
+                close_resources(node, &mut open);
+
+                if let Node::LocalVar(lvar) = ret.value.as_ref() {
+                    open.retain(|resource| resource.name != lvar.name);
+                }
+
+                add_unclosed(unclosed, open);
+                return None;
+            }
+            // Continues after the loop, which joins the paths that skip it
+            Node::Break | Node::Next => return Some(open),
+            Node::If(if_node) => {
+                close_resources(&if_node.condition, &mut open);
+
+                let then_open = check_resource_paths(&if_node.then_body, open.clone(), unclosed);
+                let else_open = check_resource_paths(&if_node.else_body, open, unclosed);
+
+                open = match (then_open, else_open) {
+                    (Some(then_open), Some(else_open)) => join_resources(then_open, else_open),
+                    (Some(branch_open), None) | (None, Some(branch_open)) => branch_open,
+                    (None, None) => return None,
+                };
+            }
+            Node::Loop(loop_node) => {
+                if let Some(body_open) =
+                    check_resource_paths(&loop_node.body, open.clone(), unclosed)
+                {
+                    open = join_resources(open, body_open);
+                }
+            }
+            Node::While(while_node) => {
+                close_resources(&while_node.condition, &mut open);
+
+                if let Some(body_open) =
+                    check_resource_paths(&while_node.body, open.clone(), unclosed)
+                {
+                    open = join_resources(open, body_open);
+                }
+            }
+            Node::AssignLocalVar(assign) => {
+                close_resources(&assign.value, &mut open);
+
+                if let Some(close) = resource_close(&assign.name, &assign.value) {
+                    open.push(Resource {
+                        name: assign.name.clone(),
+                        close,
+                    });
+                }
+            }
+            Node::AssignAttribute(assign) => {
+                close_resources(&assign.value, &mut open);
+
+                if let Node::LocalVar(lvar) = assign.value.as_ref() {
+                    open.retain(|resource| resource.name != lvar.name);
+                }
+            }
+            node => close_resources(node, &mut open),
+        }
+    }
+
+    Some(open)
+}
+
+/// How the resource `value` opens into the local `name` is closed, `None`
+/// when it isn't a resource.
+fn resource_close(name: &str, value: &Node) -> Option<String> {
+    let return_type = match value {
+        Node::Call(call) => {
+            if let Some((_, close_fn)) = RESOURCE_FNS
+                .iter()
+                .find(|(open_fn, _)| *open_fn == call.fn_name)
+            {
+                return Some(format!("{}({})", close_fn, name));
+            }
+
+            &call.return_type
+        }
+        Node::Send(send) => &send.return_type,
+        _ => return None,
+    };
+
+    match return_type {
+        Some(BaseType::Class(class_name)) if RESOURCE_CLASSES.contains(&class_name.as_str()) => {
+            Some(format!("{}.close()", name))
+        }
+        _ => None,
+    }
+}
+
+/// Removes the resources `node` closes from `open`.
+fn close_resources(node: &Node, open: &mut Vec<Resource>) {
+    visit_nodes(node, &mut |node| {
+        let closed = match node {
+            Node::Call(call) => match call.args.first() {
+                Some(Node::LocalVar(lvar))
+                    if RESOURCE_FNS
+                        .iter()
+                        .any(|(_, close_fn)| *close_fn == call.fn_name) =>
+                {
+                    &lvar.name
+                }
+                _ => return,
+            },
+            Node::Send(send) => match (send.receiver.as_ref(), send.message.as_ref()) {
+                (Node::LocalVar(lvar), Node::Call(call)) if call.fn_name.ends_with(".close") => {
+                    &lvar.name
+                }
+                _ => return,
+            },
+            _ => return,
+        };
+
+        open.retain(|resource| &resource.name != closed);
+    });
+}
+
+/// The resources open after either of two paths.
+fn join_resources(mut open: Vec<Resource>, other_open: Vec<Resource>) -> Vec<Resource> {
+    for resource in other_open {
+        if !open.contains(&resource) {
+            open.push(resource);
+        }
+    }
+
+    open
+}
+
+fn add_unclosed(unclosed: &mut Vec<Resource>, open: Vec<Resource>) {
+    for resource in open {
+        if !unclosed.iter().any(|other| other.name == resource.name) {
+            unclosed.push(resource);
+        }
+    }
+}
+
+/// Reports conditions and comparisons whose result is known before the
+/// program runs, which usually means a typo or leftover debugging:
+///
+/// * `if` and `while` conditions that are literals, like `if true`
+/// * comparisons of a local, `self` or an attribute with itself, like
+///   `x == x`. Floats are left out, NaN isn't equal to itself.
+/// * comparisons of two literals, including literals of types that are never
+///   equal, like `1 == "1"`
+/// * `while` conditions that only read locals the loop never assigns and
+///   can't be left otherwise, so it runs forever or not at all
+///
+/// Analysis doesn't track positions yet, so messages quote the condition and
+/// name the def it's in.
+pub struct ConstantConditions;
+
+impl LintPlugin for ConstantConditions {
+    fn name(&self) -> &str {
+        "constant-conditions"
+    }
+
+    fn check(&self, result: &ParserResult) -> Vec<String> {
+        let module = match &result.module {
+            Node::Module(module) => module,
+            _ => return vec![],
+        };
+
+        let mut lint = ConstantConditionLint {
+            fn_name: String::new(),
+            messages: vec![],
+        };
+
+        for node in &module.methods {
+            if let Node::Def(def) = node {
+                lint.fn_name = def.prototype.name.clone();
+                lint.visit_node(node);
+            }
+        }
+
+        lint.messages
+    }
+}
+
+/// The result of a condition or comparison, and why when it isn't obvious
+/// from the condition itself
+struct Constant {
+    value: bool,
+    because: Option<String>,
+}
+
+struct ConstantConditionLint {
+    fn_name: String,
+    messages: Vec<String>,
+}
+
+impl ConstantConditionLint {
+    fn report(&mut self, node: &Node, constant: Constant, consequence: Option<&str>) {
+        let mut message = format!(
+            "`{}` is always {} in `{}`",
+            source_text(node),
+            constant.value,
+            self.fn_name
+        );
+
+        if let Some(because) = constant.because {
+            message.push_str(&format!(", because {}", because));
+        }
+
+        if let Some(consequence) = consequence {
+            message.push_str(&format!(", so {}", consequence));
+        }
+
+        self.messages.push(message);
+    }
+}
+
+impl Visitor for ConstantConditionLint {
+    fn visit_node(&mut self, node: &Node) {
+        match node {
+            Node::If(if_node) => match constant_condition(&if_node.condition) {
+                Some(constant) => {
+                    let consequence = if constant.value {
+                        "the `if` always runs its then branch"
+                    } else {
+                        "the `if` never runs its then branch"
+                    };
+
+                    self.report(&if_node.condition, constant, Some(consequence));
+
+                    for node in if_node.then_body.iter().chain(&if_node.else_body) {
+                        self.visit_node(node);
+                    }
+                }
+                None => walk_node(self, node),
+            },
+            Node::While(while_node) => {
+                if let Some(constant) = constant_condition(&while_node.condition) {
+                    let consequence = if constant.value {
+                        "the `while` only stops at a `break`, like a `loop`"
+                    } else {
+                        "the `while` never runs"
+                    };
+
+                    self.report(&while_node.condition, constant, Some(consequence));
+
+                    for node in &while_node.body {
+                        self.visit_node(node);
+                    }
+
+                    return;
+                }
+
+                let unassigned = unassigned_locals(&while_node.condition, &while_node.body);
+
+                if !unassigned.is_empty() && !can_leave_loop(&while_node.body) {
+                    self.messages.push(format!(
+                        "`{}` never changes in the `while` in `{}`, which doesn't assign {}, so it runs forever or not at all",
+                        source_text(&while_node.condition),
+                        self.fn_name,
+                        unassigned
+                            .iter()
+                            .map(|name| format!("`{}`", name))
+                            .collect::<Vec<String>>()
+                            .join(" or ")
+                    ));
+                }
+
+                walk_node(self, node);
+            }
+            Node::Binary(binary) if binary.is_comparison() => match compare(binary) {
+                Some(constant) => self.report(node, constant, None),
+                None => walk_node(self, node),
+            },
+            node => walk_node(self, node),
+        }
+    }
+}
+
+/// The value of the condition `node` when it's known, where integers are
+/// true when they aren't 0.
+fn constant_condition(node: &Node) -> Option<Constant> {
+    match node {
+        Node::Bool(bool_node) => Some(Constant {
+            value: bool_node.value,
+            because: None,
+        }),
+        Node::Int(int_node) => Some(Constant {
+            value: int_node.value != 0,
+            because: None,
+        }),
+        Node::Binary(binary) if binary.is_comparison() => compare(binary),
+        Node::Binary(binary) if binary.is_logical() => {
+            let left = constant_condition(&binary.left)?;
+
+            // `false && x` and `true || x`
+            if left.value == (binary.op == "||") {
+                return Some(left);
+            }
+
+            constant_condition(&binary.right)
+        }
+        _ => None,
+    }
+}
+
+/// The result of the comparison `binary` when it's known.
+fn compare(binary: &Binary) -> Option<Constant> {
+    let ordering = match (binary.left.as_ref(), binary.right.as_ref()) {
+        (Node::Int(left), Node::Int(right)) => {
+            (left.value as i64).partial_cmp(&(right.value as i64))
+        }
+        (Node::Float(left), Node::Float(right)) => left.value.partial_cmp(&right.value),
+        (Node::Int(left), Node::Float(right)) => {
+            (left.value as i64 as f64).partial_cmp(&right.value)
+        }
+        (Node::Float(left), Node::Int(right)) => {
+            left.value.partial_cmp(&(right.value as i64 as f64))
+        }
+        (Node::StringLiteral(left), Node::StringLiteral(right)) if is_equality(binary) => {
+            Some(left.value.cmp(&right.value))
+        }
+        (Node::Bool(left), Node::Bool(right)) if is_equality(binary) => {
+            Some(left.value.cmp(&right.value))
+        }
+        (Node::Nil(_), Node::Nil(_)) if is_equality(binary) => Some(std::cmp::Ordering::Equal),
+        (left, right) if is_literal(left) && is_literal(right) && is_equality(binary) => {
+            return Some(Constant {
+                value: binary.op == "!=",
+                because: Some(format!(
+                    "{} is never equal to {}",
+                    literal_type_name(left),
+                    literal_type_name(right)
+                )),
+            });
+        }
+        (left, right) if is_same_value(left, right) => {
+            return Some(Constant {
+                value: matches!(binary.op.as_str(), "==" | "<=" | ">="),
+                because: Some(format!("`{}` is compared with itself", source_text(left))),
+            });
+        }
+        _ => return None,
+    };
+
+    // NaN is unordered, and only `!=` is true for it
+    let value = match ordering {
+        Some(ordering) => match binary.op.as_str() {
+            "==" => ordering.is_eq(),
+            "!=" => ordering.is_ne(),
+            "<" => ordering.is_lt(),
+            "<=" => ordering.is_le(),
+            ">" => ordering.is_gt(),
+            ">=" => ordering.is_ge(),
+            _ => return None,
+        },
+        None => binary.op == "!=",
+    };
+
+    Some(Constant {
+        value,
+        because: None,
+    })
+}
+
+fn is_equality(binary: &Binary) -> bool {
+    binary.op == "==" || binary.op == "!="
+}
+
+fn is_literal(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Bool(_) | Node::Float(_) | Node::Int(_) | Node::Nil(_) | Node::StringLiteral(_)
+    )
+}
+
+fn literal_type_name(node: &Node) -> String {
+    let class_name = match node {
+        Node::Nil(_) => return "nil".to_string(),
+        node => typed_node_base_type(node)
+            .map(|base_type| pajama_class_name(&base_type))
+            .unwrap_or_default(),
+    };
+
+    if class_name.starts_with(['A', 'E', 'I', 'O', 'U']) {
+        format!("an {}", class_name)
+    } else {
+        format!("a {}", class_name)
+    }
+}
+
+/// Whether `left` and `right` read the same local, `self` or attribute of one,
+/// which compares equal unless it might be a Float.
+fn is_same_value(left: &Node, right: &Node) -> bool {
+    let might_be_float = match typed_node_base_type(left) {
+        Some(BaseType::Class(class_name)) => class_name == "Float",
+        Some(base_type) => base_type == BaseType::Float,
+        None => true,
+    };
+
+    !might_be_float && is_plain_read(left) && source_text(left) == source_text(right)
+}
+
+/// A local, `self`, or an attribute of one, which reading doesn't change
+fn is_plain_read(node: &Node) -> bool {
+    match node {
+        Node::LocalVar(_) | Node::SelfRef(_) => true,
+        Node::Access(access) => {
+            matches!(access.message.as_ref(), Node::Attribute(_)) && is_plain_read(&access.receiver)
+        }
+        _ => false,
+    }
+}
+
+/// The locals `condition` reads that `body` never assigns, when it reads
+/// nothing but locals and literals. Empty when the condition can change.
+fn unassigned_locals(condition: &Node, body: &[Node]) -> Vec<String> {
+    let mut names = vec![];
+    let mut only_locals = true;
+
+    visit_nodes(condition, &mut |node| match node {
+        Node::LocalVar(lvar) if !names.contains(&lvar.name) => names.push(lvar.name.clone()),
+        Node::LocalVar(_) | Node::Binary(_) => {}
+        node if is_literal(node) => {}
+        _ => only_locals = false,
+    });
+
+    if !only_locals {
+        return vec![];
+    }
+
+    for node in body {
+        visit_nodes(node, &mut |node| {
+            if let Node::AssignLocalVar(assign) = node {
+                names.retain(|name| name != &assign.name);
+            }
+        });
+    }
+
+    names
+}
+
+/// Whether `body` can leave the loop it's in, with a `break` of that loop or a
+/// return.
+fn can_leave_loop(body: &[Node]) -> bool {
+    body.iter().any(|node| match node {
+        Node::Break | Node::Ret(_) => true,
+        Node::If(if_node) => {
+            can_leave_loop(&if_node.then_body) || can_leave_loop(&if_node.else_body)
+        }
+        // A `break` in a nested loop leaves that loop
+        Node::Loop(loop_node) => returns(&loop_node.body),
+        Node::While(while_node) => returns(&while_node.body),
+        _ => false,
+    })
+}
+
+fn returns(body: &[Node]) -> bool {
+    body.iter().any(|node| {
+        let mut found = false;
+
+        visit_nodes(node, &mut |node| found |= matches!(node, Node::Ret(_)));
+
+        found
+    })
+}
+
+/// `node` as it's written in source, close enough to find it by. Nodes that
+/// don't appear in conditions are elided.
+fn source_text(node: &Node) -> String {
+    match node {
+        Node::Access(access) => match (access.receiver.as_ref(), access.message.as_ref()) {
+            (Node::SelfRef(_), Node::Attribute(attribute)) => format!("@{}", attribute.name),
+            (receiver, Node::Attribute(attribute)) => {
+                format!("{}.{}", source_text(receiver), attribute.name)
+            }
+            _ => "...".to_string(),
+        },
+        Node::Binary(binary) => {
+            let operand = |node: &Node| match node {
+                Node::Binary(_) => format!("({})", source_text(node)),
+                node => source_text(node),
+            };
+
+            format!(
+                "{} {} {}",
+                operand(&binary.left),
+                binary.op,
+                operand(&binary.right)
+            )
+        }
+        Node::Bool(bool_node) => bool_node.value.to_string(),
+        Node::Call(call) => format!("{}({})", call.fn_name, source_texts(&call.args)),
+        Node::Float(float) => format!("{:?}", float.value),
+        Node::Int(int) => (int.value as i64).to_string(),
+        Node::LocalVar(lvar) => lvar.name.clone(),
+        Node::Nil(_) => "nil".to_string(),
+        Node::SelfRef(_) => "self".to_string(),
+        Node::Send(send) => match send.message.as_ref() {
+            Node::Call(call) => format!(
+                "{}{}{}({})",
+                source_text(&send.receiver),
+                if send.safe_navigation { "&." } else { "." },
+                call.fn_name.rsplit('.').next().unwrap_or_default(),
+                source_texts(&call.args)
+            ),
+            _ => "...".to_string(),
+        },
+        Node::StringLiteral(string) => format!("{:?}", string.value),
+        _ => "...".to_string(),
+    }
+}
+
+fn source_texts(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(source_text)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Reports names that don't follow the naming conventions: defs, their
+/// arguments and locals are snake_case, and classes, structs and traits are
+/// CamelCase. Each convention can be turned off.
+///
+/// `def_e` functions are left out, they're named by the library they come
+/// from, and so are operator methods like `+`.
+pub struct NamingConventions {
+    /// Defs, including methods, are snake_case
+    pub methods: bool,
+    /// Arguments and locals are snake_case
+    pub locals: bool,
+    /// Classes, structs and traits are CamelCase
+    pub types: bool,
+}
+
+impl Default for NamingConventions {
+    fn default() -> NamingConventions {
+        NamingConventions {
+            methods: true,
+            locals: true,
+            types: true,
+        }
+    }
+}
+
+impl LintPlugin for NamingConventions {
+    fn name(&self) -> &str {
+        "naming-conventions"
+    }
+
+    fn check(&self, result: &ParserResult) -> Vec<String> {
+        let mut messages = vec![];
+
+        if self.types {
+            let index = &result.index;
+            let mut types: Vec<(&str, &String)> = index
+                .class_index
+                .keys()
+                .map(|name| ("class", name))
+                .chain(index.struct_index.keys().map(|name| ("struct", name)))
+                .chain(index.trait_index.keys().map(|name| ("trait", name)))
+                .collect();
+            types.sort();
+
+            for (kind, name) in types {
+                if !is_camel_case(name) {
+                    messages.push(format!(
+                        "{} `{}` should be CamelCase, like `{}`",
+                        kind,
+                        name,
+                        to_camel_case(name)
+                    ));
+                }
+            }
+        }
+
+        let module = match &result.module {
+            Node::Module(module) => module,
+            _ => return messages,
+        };
+
+        for node in &module.methods {
+            let def = match node {
+                Node::Def(def) => def,
+                _ => continue,
+            };
+
+            let name = &def.prototype.name;
+            let method_name = name.rsplit('.').next().unwrap_or_default();
+
+            if self.methods && !def.prototype.is_op && !is_snake_case(method_name) {
+                let message = format!(
+                    "def `{}` should be snake_case, like `{}`",
+                    name,
+                    to_snake_case(method_name)
+                );
+
+                // Trait defaults are copied into each implementing class
+                if !messages.contains(&message) {
+                    messages.push(message);
+                }
+            }
+
+            if !self.locals {
+                continue;
+            }
+
+            let mut locals: Vec<String> = def
+                .prototype
+                .args
+                .iter()
+                .map(|arg| arg.name.clone())
+                .collect();
+
+            for node in &def.body {
+                visit_nodes(node, &mut |node| {
+                    if let Node::AssignLocalVar(assign) = node {
+                        if !locals.contains(&assign.name) {
+                            locals.push(assign.name.clone());
+                        }
+                    }
+                });
+            }
+
+            for local in &locals {
+                if !is_snake_case(local) {
+                    messages.push(format!(
+                        "`{}` in `{}` should be snake_case, like `{}`",
+                        local,
+                        name,
+                        to_snake_case(local)
+                    ));
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+fn is_snake_case(name: &str) -> bool {
+    !name.chars().any(char::is_uppercase)
+}
+
+fn is_camel_case(name: &str) -> bool {
+    name.starts_with(char::is_uppercase) && !name.contains('_')
+}
+
+/// `printInt` becomes `print_int`, and `HTTPServer` becomes `http_server`.
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake_case = String::new();
+
+    for (position, char) in chars.iter().enumerate() {
+        if char.is_uppercase() && position > 0 && chars[position - 1] != '_' {
+            let after_lowercase = !chars[position - 1].is_uppercase();
+            let before_lowercase = chars
+                .get(position + 1)
+                .is_some_and(|next| next.is_lowercase());
+
+            if after_lowercase || before_lowercase {
+                snake_case.push('_');
+            }
+        }
+
+        snake_case.extend(char.to_lowercase());
+    }
+
+    snake_case
+}
+
+/// `http_server` becomes `HttpServer`.
+fn to_camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
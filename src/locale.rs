@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::error_codes::{error_code_for, find_error_code, match_message};
+
+/// The locale whose messages are the ones the compiler reports
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The catalog diagnostics are rendered with, English until `set_catalog`.
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// Translations of diagnostic messages, by error code.
+///
+/// A catalog file has a `<code> = <message>` line per translated code, and
+/// `#` comments. A message has `{0}`, `{1}` and so on where the English one
+/// in `ERROR_CODES` has its first, second and later `{}`, so translations
+/// can put the names and types in another order:
+///
+/// ```text
+/// # French
+/// E0012 = `{0}` pourrait être lu avant d'être assigné dans `{1}`
+/// ```
+///
+/// Messages without a code, or without a translation, stay in English.
+#[derive(Debug, Default, PartialEq)]
+pub struct Catalog {
+    pub messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Reads a catalog file, checking its codes are registered and that its
+    /// messages only use the parts the English ones have.
+    pub fn parse(input: &str) -> Result<Catalog, String> {
+        let mut messages = HashMap::new();
+
+        for (index, line) in input.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (code, message) = line
+                .split_once('=')
+                .map(|(code, message)| (code.trim(), message.trim()))
+                .ok_or_else(|| format!("line {}: expected `<code> = <message>`", index + 1))?;
+
+            let error_code = find_error_code(code)
+                .ok_or_else(|| format!("line {}: unknown error code `{}`", index + 1, code))?;
+            let parts = error_code.message.matches("{}").count();
+
+            for placeholder in placeholders(message) {
+                if placeholder >= parts {
+                    return Err(format!(
+                        "line {}: {} has {} part(s) to place, not {{{}}}",
+                        index + 1,
+                        code,
+                        parts,
+                        placeholder
+                    ));
+                }
+            }
+
+            messages.insert(code.to_string(), message.to_string());
+        }
+
+        Ok(Catalog { messages })
+    }
+
+    /// The catalog of `locale`, empty for English. Others are read from
+    /// `<locale>.catalog` in `$PAJAMA_LOCALE_DIR`, or in the `locales`
+    /// directory next to the compiler.
+    pub fn load(locale: &str) -> Result<Catalog, String> {
+        if locale == DEFAULT_LOCALE {
+            return Ok(Catalog::default());
+        }
+
+        let dir = match std::env::var("PAJAMA_LOCALE_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => std::env::current_exe()
+                .map_err(|err| err.to_string())?
+                .with_file_name("locales"),
+        };
+        let path = dir.join(format!("{}.catalog", locale));
+        let input = std::fs::read_to_string(&path)
+            .map_err(|err| format!("locale `{}`: {}: {}", locale, path.display(), err))?;
+
+        Catalog::parse(&input).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// `message` in the catalog's language, when it has a translation for
+    /// the message's code.
+    pub fn translate(&self, message: &str) -> String {
+        let translated = error_code_for(message).and_then(|error_code| {
+            let translation = self.messages.get(error_code.code)?;
+            let (prefix, parts, suffix) = match_message(error_code.message, message)?;
+            let mut translated = translation.clone();
+
+            for (index, part) in parts.iter().enumerate() {
+                translated = translated.replace(&format!("{{{}}}", index), part);
+            }
+
+            Some(format!("{}{}{}", prefix, translated, suffix))
+        });
+
+        translated.unwrap_or_else(|| message.to_string())
+    }
+}
+
+/// The numbers of the `{0}`, `{1}`... in `message`.
+fn placeholders(message: &str) -> Vec<usize> {
+    message
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}')?.0.parse().ok())
+        .collect()
+}
+
+/// Renders diagnostics with `catalog` from now on. Only the first catalog
+/// set is used, the compiler sets one at startup.
+pub fn set_catalog(catalog: Catalog) {
+    let _ = CATALOG.set(catalog);
+}
+
+/// `message` in the language of the catalog set, English without one.
+pub fn translate(message: &str) -> String {
+    match CATALOG.get() {
+        Some(catalog) => catalog.translate(message),
+        None => message.to_string(),
+    }
+}
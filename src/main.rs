@@ -1,11 +1,52 @@
+mod allocator;
+mod ast;
+mod ast_diff;
+mod backend;
+mod build_info;
+mod bytecode;
+mod c_backend;
+mod cancellation;
+mod cli;
 mod codegen;
+mod columns;
+mod consteval;
+mod cpu_profile;
+mod dead_code;
+mod diagnostic;
+mod error_codes;
+mod explain;
+mod graph;
+mod heap_profile;
+mod js_backend;
 mod lexer;
+mod lints;
+mod locale;
+mod memory_stats;
+mod metrics;
+mod operators;
+mod optimization;
 mod pajama_compiler;
 mod pajama_lib;
+mod parallel;
 mod parser;
+mod queries;
+mod relex;
+mod repl;
+mod resource_limits;
+mod runtime_profile;
 mod semantic_analyzer;
+mod source;
+mod type_checker;
+mod vm;
 
-use pajama_compiler::PajamaCompiler;
+use std::io::Write;
+
+use cli::Emit;
+use columns::TabWidth;
+use diagnostic::Diagnostic;
+use pajama_compiler::{CompileOptions, LinkError, PajamaCompiler};
+use source::SourceFile;
+use tracing_subscriber::EnvFilter;
 
 use mimalloc_rust::raw::basic_allocation::*;
 use mimalloc_rust::GlobalMiMalloc;
@@ -14,6 +55,330 @@ use mimalloc_rust::GlobalMiMalloc;
 static GLOBAL_MIMALLOC: GlobalMiMalloc = GlobalMiMalloc;
 
 pub fn main() {
-    let input = std::fs::read_to_string("dev.pjs").unwrap();
-    PajamaCompiler::compile_and_invoke(&input);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let cli_args = match cli::parse_args(&args) {
+        Ok(cli_args) => cli_args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if cli_args.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    // e.g. PAJAMA_LOG=debug or PAJAMA_LOG=pajama::codegen=trace, --verbose
+    // traces the tokens and AST unless PAJAMA_LOG says otherwise
+    let env_filter = match std::env::var("PAJAMA_LOG") {
+        Err(_) if cli_args.verbose => EnvFilter::new("pajama::pajama_compiler=trace"),
+        _ => EnvFilter::from_env("PAJAMA_LOG"),
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    match locale::Catalog::load(&cli_args.locale) {
+        Ok(catalog) => locale::set_catalog(catalog),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if cli_args.dump_operators {
+        print!("{}", operators::format_operators());
+        return;
+    }
+
+    if let Some(code) = &cli_args.explain_code {
+        match error_codes::find_error_code(code) {
+            Some(error_code) => print!("{}", error_code.explanation),
+            None => {
+                eprintln!("{} isn't a known error code", code);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let mut options = CompileOptions {
+        memory_stats: cli_args.memory_stats,
+        stats_json: cli_args.stats_json,
+        sandbox: cli_args.sandbox,
+        strict: cli_args.strict,
+        freeze_literals: cli_args.freeze_literals,
+        print_dce: cli_args.print_dce,
+        frame_pointers: cli_args.frame_pointers,
+        limits: cli_args.limits,
+        profile_heap: cli_args.profile_heap,
+        allocator: cli_args.allocator,
+        runtime: cli_args.runtime,
+        opt_level: cli_args.opt_level,
+        reproducible: cli_args.reproducible,
+        tab_width: cli_args.tab_width,
+        ..Default::default()
+    };
+
+    for path in &cli_args.backends {
+        match backend::load_backend(path) {
+            Ok(loaded) => options.backends.push(loaded),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if cli_args.inspect_binary {
+        return inspect_binary(&cli_args.paths[0]);
+    }
+
+    if let [path] = cli_args.paths.as_slice() {
+        if path.ends_with(".nlb") {
+            return run_bytecode(path);
+        }
+    }
+
+    let mut sources = vec![];
+
+    for path in &cli_args.paths {
+        match source::read_source(path, cli_args.latin1) {
+            Ok(input) => sources.push(SourceFile {
+                path: path.clone(),
+                input,
+            }),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let (sources, imports, diagnostics) = source::resolve_import_graph(sources, cli_args.latin1);
+
+    if !diagnostics.is_empty() {
+        exit_with_diagnostics(&diagnostics, &sources, options.tab_width);
+    }
+
+    if cli_args.repl {
+        return repl::run(sources, &options);
+    }
+
+    // Nothing cancels a compile started from the command line
+    let output = if cli_args.metrics {
+        match PajamaCompiler::compile_to_metrics(&sources, &options).unwrap() {
+            Ok(metrics) => metrics::format_metrics(&metrics, &cli_args.metrics_format),
+            Err(diagnostics) => exit_with_diagnostics(&diagnostics, &sources, options.tab_width),
+        }
+    } else if cli_args.graph && cli_args.graph_imports {
+        graph::format_import_graph(&imports, &cli_args.graph_format)
+    } else if cli_args.graph {
+        match PajamaCompiler::compile_to_graph(&sources, &options).unwrap() {
+            Ok(graph) => graph::format_graph(&graph, &cli_args.graph_format),
+            Err(diagnostics) => exit_with_diagnostics(&diagnostics, &sources, options.tab_width),
+        }
+    } else if let Some((line, column)) = cli_args.explain_at {
+        let path = &cli_args.paths[0];
+
+        match PajamaCompiler::compile_to_explanation(&sources, &options, path, line, column)
+            .unwrap()
+        {
+            Ok(Some(explanation)) => explanation.to_string(),
+            Ok(None) => {
+                eprintln!("{}:{}:{}: nothing to explain here", path, line, column);
+                std::process::exit(1);
+            }
+            Err(diagnostics) => exit_with_diagnostics(&diagnostics, &sources, options.tab_width),
+        }
+    } else if cli_args.profile {
+        let exe_path = std::env::temp_dir().join(format!("pajama-profile-{}", std::process::id()));
+        let exe_path = exe_path.to_string_lossy();
+
+        match PajamaCompiler::compile_to_executable(&sources, &options, &exe_path) {
+            Ok(()) => {}
+            Err(LinkError::Diagnostics(diagnostics)) => {
+                exit_with_diagnostics(&diagnostics, &sources, options.tab_width)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+
+        let profiled = cpu_profile::profile_executable(&exe_path);
+        let _ = std::fs::remove_file(exe_path.as_ref());
+
+        match profiled {
+            Ok((status, hot_functions)) => {
+                eprint!("{}", cpu_profile::format_summary(&hot_functions));
+
+                if status != 0 {
+                    std::process::exit(status);
+                }
+
+                return;
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        let failed = |diagnostics: Vec<Diagnostic>| -> ! {
+            exit_with_diagnostics(&diagnostics, &sources, options.tab_width)
+        };
+
+        match &cli_args.emit {
+            Emit::Run => {
+                let status = PajamaCompiler::compile_and_invoke(&sources, &options)
+                    .unwrap()
+                    .unwrap_or_else(|diagnostics| failed(diagnostics));
+
+                if status != 0 {
+                    std::process::exit(status);
+                }
+
+                return;
+            }
+            Emit::Obj => {
+                let path = cli_args.output.as_ref().unwrap();
+
+                return PajamaCompiler::compile_to_object(&sources, &options, path)
+                    .unwrap()
+                    .unwrap_or_else(|diagnostics| failed(diagnostics));
+            }
+            Emit::Exe => {
+                let path = cli_args.output.as_ref().unwrap();
+
+                match PajamaCompiler::compile_to_executable(&sources, &options, path) {
+                    Ok(()) => {}
+                    Err(LinkError::Diagnostics(diagnostics)) => {
+                        exit_with_diagnostics(&diagnostics, &sources, options.tab_width)
+                    }
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                }
+
+                return;
+            }
+            Emit::Ir => PajamaCompiler::compile_to_ir(&sources, &options)
+                .unwrap()
+                .unwrap_or_else(|diagnostics| failed(diagnostics)),
+            Emit::C => PajamaCompiler::compile_to_c(&sources, &options)
+                .unwrap()
+                .unwrap_or_else(|diagnostics| failed(diagnostics)),
+            Emit::Js => PajamaCompiler::compile_to_js(&sources, &options)
+                .unwrap()
+                .unwrap_or_else(|diagnostics| failed(diagnostics)),
+            Emit::Bytecode => {
+                let program = PajamaCompiler::compile_to_bytecode(&sources, &options)
+                    .unwrap()
+                    .unwrap_or_else(|diagnostics| failed(diagnostics));
+
+                return write_artifact(&cli_args.output, &program);
+            }
+            Emit::Backend(name) => {
+                let backend = match backend::find_backend(&options.backends, name) {
+                    Some(backend) => backend,
+                    None => {
+                        eprintln!(
+                            "none of the --backend libraries has a backend named `{}`",
+                            name
+                        );
+                        std::process::exit(1);
+                    }
+                };
+
+                let artifact = PajamaCompiler::compile_with_backend(&sources, &options, backend)
+                    .unwrap()
+                    .unwrap_or_else(|diagnostics| failed(diagnostics));
+
+                return write_artifact(&cli_args.output, &artifact);
+            }
+        }
+    };
+
+    match &cli_args.output {
+        Some(path) => {
+            if let Err(err) = std::fs::write(path, output) {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", output),
+    }
+}
+
+/// Prints the diagnostics a compile stopped with, and exits.
+fn exit_with_diagnostics(
+    diagnostics: &[Diagnostic],
+    sources: &[SourceFile],
+    tab_width: TabWidth,
+) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}\n", diagnostic.render(sources, tab_width));
+    }
+
+    std::process::exit(1);
+}
+
+/// Writes a binary artifact, from a backend or --emit=bytecode, as is to
+/// `output` or to stdout.
+fn write_artifact(output: &Option<String>, artifact: &[u8]) {
+    let written = match output {
+        Some(path) => std::fs::write(path, artifact).map_err(|err| format!("{}: {}", path, err)),
+        None => std::io::stdout()
+            .write_all(artifact)
+            .map_err(|err| err.to_string()),
+    };
+
+    if let Err(err) = written {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Runs a .nlb file written with --emit=bytecode on the VM, exiting with
+/// the status its main exits with.
+fn run_bytecode(path: &str) {
+    let program = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| bytecode::Program::decode(&bytes));
+
+    let status = program.and_then(|program| vm::run(&program, &mut std::io::stdout().lock()));
+
+    let status = match status {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+
+    if status != 0 {
+        std::process::exit(status);
+    }
+}
+
+/// Prints the build info of an executable built with --emit=exe.
+fn inspect_binary(path: &str) {
+    let build_info = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|binary| build_info::read_build_info(&binary));
+
+    match build_info {
+        Ok(build_info) => print!("{}", build_info),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+use crate::parser::{Node, ParserResult};
+
+/// Collects what the compiler itself uses after each phase, printed with
+/// `--memory-stats`, and what it compiled, written with `--stats-json`.
+///
+/// Peak RSS only grows, so the phase where it jumps is the one to look at.
+pub struct MemoryStats {
+    phases: Vec<PhaseStats>,
+    /// When the last phase was recorded, or the stats were created
+    checkpoint: Instant,
+    /// The defs and classes of the program, once it's parsed
+    pub defs: Option<usize>,
+    pub classes: Option<usize>,
+    /// The errors the compile stopped with
+    pub diagnostics: usize,
+}
+
+struct PhaseStats {
+    name: &'static str,
+    peak_rss_kb: i64,
+    tokens: Option<usize>,
+    nodes: Option<usize>,
+    /// The time since the phase before
+    elapsed: Duration,
+}
+
+impl Default for MemoryStats {
+    fn default() -> MemoryStats {
+        MemoryStats::new()
+    }
+}
+
+impl MemoryStats {
+    pub fn new() -> MemoryStats {
+        MemoryStats {
+            phases: vec![],
+            checkpoint: Instant::now(),
+            defs: None,
+            classes: None,
+            diagnostics: 0,
+        }
+    }
+
+    pub fn record(&mut self, name: &'static str, tokens: Option<usize>, nodes: Option<usize>) {
+        self.phases.push(PhaseStats {
+            name,
+            peak_rss_kb: peak_rss_kb(),
+            tokens,
+            nodes,
+            elapsed: self.checkpoint.elapsed(),
+        });
+
+        self.checkpoint = Instant::now();
+    }
+
+    /// Counts the defs and classes of a parsed program. Only defs in the
+    /// source count, not the ones the parser adds, like attribute readers.
+    pub fn count_program(&mut self, parser_result: &ParserResult) {
+        let index = &parser_result.index;
+        let defs = match &parser_result.module {
+            Node::Module(module) => module
+                .methods
+                .iter()
+                .filter(|node| match node {
+                    Node::Def(def) => index.definition_index.contains_key(&def.prototype.name),
+                    _ => false,
+                })
+                .count(),
+            _ => 0,
+        };
+
+        self.defs = Some(defs);
+        self.classes = Some(index.class_index.len());
+    }
+
+    /// The counts and the time and peak RSS of each phase, as JSON for build
+    /// dashboards. Counts the compile didn't get to are null.
+    pub fn to_json(&self) -> String {
+        let count = |count: Option<usize>| match count {
+            Some(count) => count.to_string(),
+            None => "null".to_string(),
+        };
+        let nodes = self.phases.iter().rev().find_map(|phase| phase.nodes);
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|phase| {
+                format!(
+                    "{{\"name\": \"{}\", \"micros\": {}, \"peak_rss_kb\": {}}}",
+                    phase.name,
+                    phase.elapsed.as_micros(),
+                    phase.peak_rss_kb
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"defs\": {}, \"classes\": {}, \"nodes\": {}, \"diagnostics\": {}, \"phases\": [{}]}}\n",
+            count(self.defs),
+            count(self.classes),
+            count(nodes),
+            self.diagnostics,
+            phases.join(", ")
+        )
+    }
+
+    pub fn print(&self) {
+        eprintln!(
+            "{:<10} {:>14} {:>10} {:>10}",
+            "phase", "peak rss (kb)", "tokens", "nodes"
+        );
+
+        for phase in &self.phases {
+            let count = |count: Option<usize>| match count {
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+
+            eprintln!(
+                "{:<10} {:>14} {:>10} {:>10}",
+                phase.name,
+                phase.peak_rss_kb,
+                count(phase.tokens),
+                count(phase.nodes)
+            );
+        }
+    }
+}
+
+/// The most memory the process has had resident so far, in kilobytes.
+pub fn peak_rss_kb() -> i64 {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return 0;
+    }
+
+    // Reported in bytes on macOS and kilobytes everywhere else
+    if cfg!(target_os = "macos") {
+        usage.ru_maxrss / 1024
+    } else {
+        usage.ru_maxrss
+    }
+}
+
+/// Counts `node` and every node below it.
+pub fn count_nodes(node: &Node) -> usize {
+    let count_all = |nodes: &Vec<Node>| nodes.iter().map(count_nodes).sum::<usize>();
+
+    1 + match node {
+        Node::Access(node) => count_nodes(&node.receiver) + count_nodes(&node.message),
+        Node::Array(node) => count_all(&node.items),
+        Node::AssignAttribute(node) => count_nodes(&node.value),
+        Node::AssignAttributeAccess(node) => {
+            count_nodes(&node.access.receiver)
+                + count_nodes(&node.access.message)
+                + count_nodes(&node.value)
+        }
+        Node::AssignConstant(node) => count_nodes(&node.value),
+        Node::AssignLocalVar(node) => count_nodes(&node.value),
+        Node::Attribute(_) => 0,
+        Node::Binary(node) => count_nodes(&node.left) + count_nodes(&node.right),
+        Node::Bool(_) => 0,
+        Node::Break => 0,
+        Node::BuildStruct(node) => count_all(&node.args),
+        Node::Call(node) => count_all(&node.args),
+        Node::Class(_) => 0,
+        Node::Const(_) => 0,
+        Node::Def(node) => count_all(&node.body),
+        Node::DefE(_) => 0,
+        Node::Error(_) => 0,
+        Node::Float(_) => 0,
+        Node::FnRef(_) => 0,
+        Node::If(node) => {
+            count_nodes(&node.condition) + count_all(&node.then_body) + count_all(&node.else_body)
+        }
+        Node::Impl(node) => count_all(&node.body),
+        Node::Int(_) => 0,
+        Node::LocalVar(_) => 0,
+        Node::Loop(node) => count_all(&node.body),
+        Node::Module(node) => count_all(&node.methods),
+        Node::Next => 0,
+        Node::Nil(_) => 0,
+        Node::Ret(node) => count_nodes(&node.value),
+        Node::SelfRef(_) => 0,
+        Node::Send(node) => count_nodes(&node.receiver) + count_nodes(&node.message),
+        Node::StringLiteral(_) => 0,
+        Node::Struct(_) => 0,
+        Node::Trait(node) => count_all(&node.body),
+        Node::While(node) => count_nodes(&node.condition) + count_all(&node.body),
+    }
+}
@@ -0,0 +1,156 @@
+use crate::ast::visit_nodes;
+use crate::parser::{Node, ParserResult};
+
+/// How big and how branchy a def is, printed by `pajama metrics`.
+#[derive(Debug, PartialEq)]
+pub struct DefMetrics {
+    pub name: String,
+    /// Statements in the body, counting the ones inside `if`s and loops
+    pub statements: usize,
+    /// How deep `if`s and loops are nested, 0 for a body without any
+    pub nesting_depth: usize,
+    /// Cyclomatic complexity: 1, plus 1 for each `if`, loop, `&&` and `||`
+    pub complexity: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MetricsFormat {
+    Table,
+    Json,
+}
+
+pub fn parse_metrics_format(name: &str) -> Result<MetricsFormat, String> {
+    match name {
+        "table" => Ok(MetricsFormat::Table),
+        "json" => Ok(MetricsFormat::Json),
+        _ => Err(format!(
+            "unknown --format `{}`, expected table or json",
+            name
+        )),
+    }
+}
+
+/// The metrics of every def in an analyzed program, in the order they're
+/// defined. `def_e` declarations have no body and are left out.
+pub fn def_metrics(result: &ParserResult) -> Vec<DefMetrics> {
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => return vec![],
+    };
+
+    module
+        .methods
+        .iter()
+        .filter_map(|node| match node {
+            Node::Def(def) => Some(DefMetrics {
+                name: def.prototype.name.clone(),
+                statements: count_statements(&def.body),
+                nesting_depth: nesting_depth(&def.body),
+                complexity: 1 + def.body.iter().map(decision_points).sum::<usize>(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn count_statements(body: &[Node]) -> usize {
+    body.iter()
+        .map(|node| {
+            1 + match node {
+                Node::If(if_node) => {
+                    count_statements(&if_node.then_body) + count_statements(&if_node.else_body)
+                }
+                Node::Loop(loop_node) => count_statements(&loop_node.body),
+                Node::While(while_node) => count_statements(&while_node.body),
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+fn nesting_depth(body: &[Node]) -> usize {
+    body.iter()
+        .map(|node| match node {
+            Node::If(if_node) => {
+                1 + nesting_depth(&if_node.then_body).max(nesting_depth(&if_node.else_body))
+            }
+            Node::Loop(loop_node) => 1 + nesting_depth(&loop_node.body),
+            Node::While(while_node) => 1 + nesting_depth(&while_node.body),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn decision_points(node: &Node) -> usize {
+    let mut count = 0;
+
+    visit_nodes(node, &mut |node| match node {
+        Node::If(_) | Node::Loop(_) | Node::While(_) => count += 1,
+        Node::Binary(binary) if binary.is_logical() => count += 1,
+        _ => {}
+    });
+
+    count
+}
+
+pub fn format_metrics(metrics: &[DefMetrics], format: &MetricsFormat) -> String {
+    match format {
+        MetricsFormat::Table => to_table(metrics),
+        MetricsFormat::Json => to_json(metrics),
+    }
+}
+
+fn to_table(metrics: &[DefMetrics]) -> String {
+    let width = metrics
+        .iter()
+        .map(|def| def.name.len())
+        .chain(std::iter::once("def".len()))
+        .max()
+        .unwrap_or_default();
+
+    let mut table = format!(
+        "{:<width$} {:>10} {:>7} {:>10}\n",
+        "def",
+        "statements",
+        "depth",
+        "complexity",
+        width = width
+    );
+
+    for def in metrics {
+        table.push_str(&format!(
+            "{:<width$} {:>10} {:>7} {:>10}\n",
+            def.name,
+            def.statements,
+            def.nesting_depth,
+            def.complexity,
+            width = width
+        ));
+    }
+
+    table
+}
+
+/// One object per def, in an array, so the output can be diffed between
+/// commits or fed to a dashboard.
+fn to_json(metrics: &[DefMetrics]) -> String {
+    let defs: Vec<String> = metrics
+        .iter()
+        .map(|def| {
+            format!(
+                "  {{\"name\": \"{}\", \"statements\": {}, \"nesting_depth\": {}, \"complexity\": {}}}",
+                def.name.replace('\\', "\\\\").replace('"', "\\\""),
+                def.statements,
+                def.nesting_depth,
+                def.complexity
+            )
+        })
+        .collect();
+
+    if defs.is_empty() {
+        return "[]\n".to_string();
+    }
+
+    format!("[\n{}\n]\n", defs.join(",\n"))
+}
@@ -0,0 +1,84 @@
+/// How a chain of operators of the same precedence groups, like `a - b - c`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Associativity {
+    /// `(a - b) - c`
+    Left,
+    /// `~(~a)`, which is how prefix operators nest
+    Right,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Operator {
+    pub symbol: &'static str,
+    /// Higher binds tighter. Prefix operators have none, they bind tighter
+    /// than every binary operator.
+    pub precedence: Option<i32>,
+    pub associativity: Associativity,
+    /// 1 for prefix operators, 2 for binary ones
+    pub arity: usize,
+}
+
+const fn binary(symbol: &'static str, precedence: i32) -> Operator {
+    Operator {
+        symbol,
+        precedence: Some(precedence),
+        associativity: Associativity::Left,
+        arity: 2,
+    }
+}
+
+/// The builtin operators, the parser's precedences are read from here.
+/// Printed by `pajama dump-operators` for formatters and editors.
+pub const OPERATORS: [Operator; 19] = [
+    binary("||", 5),
+    binary("&&", 6),
+    binary("==", 9),
+    binary("!=", 9),
+    binary("<", 10),
+    binary(">", 10),
+    binary("<=", 10),
+    binary(">=", 10),
+    // Sends `<=>` to the left operand, see the Comparable trait
+    binary("<=>", 10),
+    binary("|", 12),
+    binary("^", 13),
+    binary("&", 14),
+    binary("<<", 15),
+    binary(">>", 15),
+    binary("+", 20),
+    binary("-", 20),
+    binary("*", 40),
+    binary("/", 40),
+    // Flips every bit, parsed as `a ^ -1`
+    Operator {
+        symbol: "~",
+        precedence: None,
+        associativity: Associativity::Right,
+        arity: 1,
+    },
+];
+
+/// `OPERATORS` as JSON, in the order they're listed.
+pub fn format_operators() -> String {
+    let operators: Vec<String> = OPERATORS
+        .iter()
+        .map(|operator| {
+            let precedence = match operator.precedence {
+                Some(precedence) => precedence.to_string(),
+                None => "null".to_string(),
+            };
+            let associativity = match operator.associativity {
+                Associativity::Left => "left",
+                Associativity::Right => "right",
+            };
+
+            format!(
+                "  {{\"symbol\": \"{}\", \"precedence\": {}, \
+                 \"associativity\": \"{}\", \"arity\": {}}}",
+                operator.symbol, precedence, associativity, operator.arity
+            )
+        })
+        .collect();
+
+    format!("[\n{}\n]\n", operators.join(",\n"))
+}
@@ -0,0 +1,85 @@
+use melior::pass::{transform, PassManager};
+
+use crate::parser::{Def, Node};
+
+/// How much the compiler optimizes, picked with `-O0`, `-O1` or `-O2`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OptLevel {
+    /// No passes, the IR is what codegen emitted
+    O0,
+    /// Locals are kept in registers instead of stack slots, constants are
+    /// folded and repeated expressions are computed once
+    O1,
+    /// `O1`, and loop invariant code is hoisted out of loops and constants
+    /// are propagated across branches
+    #[default]
+    O2,
+}
+
+/// Statements a def can have and still be inlined everywhere, see `is_small`
+const SMALL_DEF_STATEMENTS: usize = 3;
+
+impl OptLevel {
+    /// The level LLVM's own pipeline runs at once the module is translated to
+    /// LLVM IR, for the JIT and for object files.
+    pub fn llvm_level(self) -> usize {
+        self as usize
+    }
+
+    /// Adds the passes that work on the loops and branches codegen emits, to
+    /// run before they're lowered to the LLVM dialect.
+    pub fn add_structured_passes(self, pass_manager: &PassManager) {
+        if self == OptLevel::O2 {
+            pass_manager.add_pass(transform::create_loop_invariant_code_motion());
+        }
+    }
+
+    /// Adds the passes that run once the module is in the LLVM dialect.
+    /// Codegen keeps every local in an `alloca`, mem2reg is what turns them
+    /// into SSA values.
+    pub fn add_llvm_passes(self, pass_manager: &PassManager) {
+        if self == OptLevel::O0 {
+            return;
+        }
+
+        pass_manager.add_pass(transform::create_mem_2_reg());
+        pass_manager.add_pass(transform::create_canonicalizer());
+        pass_manager.add_pass(transform::create_cse());
+
+        if self == OptLevel::O2 {
+            pass_manager.add_pass(transform::create_sccp());
+            pass_manager.add_pass(transform::create_canonicalizer());
+        }
+    }
+}
+
+pub fn parse_opt_level(level: &str) -> Result<OptLevel, String> {
+    match level {
+        "0" => Ok(OptLevel::O0),
+        "1" => Ok(OptLevel::O1),
+        "2" => Ok(OptLevel::O2),
+        _ => Err(format!(
+            "unknown optimization level `-O{}`, expected -O0, -O1 or -O2",
+            level
+        )),
+    }
+}
+
+/// Whether `def` is marked `alwaysinline` at `-O1` and above: a few
+/// statements without a loop, like accessors and arithmetic helpers, which
+/// cost more to call than to run. LLVM's inliner decides for everything else.
+pub fn is_small(def: &Def) -> bool {
+    !def.main_fn && def.body.len() <= SMALL_DEF_STATEMENTS && !def.body.iter().any(has_loop)
+}
+
+fn has_loop(node: &Node) -> bool {
+    match node {
+        Node::Loop(_) | Node::While(_) => true,
+        Node::If(if_node) => if_node
+            .then_body
+            .iter()
+            .chain(if_node.else_body.iter())
+            .any(has_loop),
+        _ => false,
+    }
+}
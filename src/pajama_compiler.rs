@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
 
 use melior::dialect::DialectRegistry;
 use melior::ir::{Location, Module};
@@ -6,39 +8,157 @@ use melior::pass::{conversion, PassManager};
 use melior::utility::{register_all_dialects, register_all_llvm_translations};
 use melior::{pass, Context, ExecutionEngine};
 
+use crate::allocator::{self, Allocator};
+use crate::backend::Backend;
+use crate::build_info::BuildInfo;
+use crate::bytecode::emit_bytecode;
+use crate::c_backend::emit_c;
+use crate::cancellation::{CancellationToken, Cancelled};
 use crate::codegen::Compiler;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::semantic_analyzer::SemanticAnalyzer;
+use crate::columns::TabWidth;
+use crate::consteval::{fold_constant_calls, DEFAULT_FUEL};
+use crate::dead_code::eliminate_dead_methods;
+use crate::diagnostic::Diagnostic;
+use crate::explain::{explain, Explanation};
+use crate::graph::{program_graph, ProgramGraph};
+use crate::heap_profile;
+use crate::js_backend::emit_js;
+use crate::lexer::{Lexer, Token};
+use crate::lints::{run_lints, LintPlugin};
+use crate::memory_stats::{count_nodes, MemoryStats};
+use crate::metrics::{def_metrics, DefMetrics};
+use crate::optimization::OptLevel;
+use crate::pajama_lib;
+use crate::parallel::par_map;
+use crate::parser::{default_op_precedence, Parser, ParserResult};
+use crate::resource_limits::{ResourceLimits, Watchdog};
+use crate::runtime_profile::{check_runtime_profile, RuntimeProfile};
+use crate::semantic_analyzer::{
+    apply_freeze_literals, apply_sandbox, apply_strict, Diagnostics, SemanticAnalyzer,
+};
+use crate::source::SourceFile;
+use crate::vm;
 
 pub struct PajamaCompiler {}
 
+/// Why `compile_to_executable` didn't produce an executable.
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    Cancelled,
+    /// The program has errors, they're returned to be rendered
+    Diagnostics(Vec<Diagnostic>),
+    /// The runtime library is missing or `cc` failed, with the reason
+    Failed(String),
+}
+
+impl From<Cancelled> for LinkError {
+    fn from(_: Cancelled) -> LinkError {
+        LinkError::Cancelled
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Cancelled => write!(f, "compile cancelled"),
+            LinkError::Diagnostics(diagnostics) => {
+                write!(f, "compile failed with {} errors", diagnostics.len())
+            }
+            LinkError::Failed(reason) => write!(f, "linking failed: {}", reason),
+        }
+    }
+}
+
+/// What running a program with `eval_sandboxed` came to.
+#[derive(Debug, PartialEq)]
+pub enum EvalResult {
+    /// `main` returned, or the program called `exit`
+    Exited { status: i32, output: String },
+    /// The program didn't compile, or uses what the VM doesn't support
+    Failed(Vec<Diagnostic>),
+    /// A runtime error or a limit stopped the program, after it printed
+    /// `output`
+    Stopped { error: String, output: String },
+}
+
+#[derive(Default)]
+pub struct CompileOptions {
+    /// Print peak RSS, token and node counts after each phase
+    pub memory_stats: bool,
+    /// Write the program's counts and the time of each phase to this path
+    /// as JSON, see `MemoryStats::to_json`
+    pub stats_json: Option<String>,
+    /// Stops the compile early, before anything is run
+    pub cancellation: CancellationToken,
+    /// House rules checked after semantic analysis
+    pub lints: Vec<Box<dyn LintPlugin>>,
+    /// Targets for `--emit` besides the builtin ones, see `backend::Backend`
+    pub backends: Vec<Box<dyn Backend>>,
+    /// Stub out every function but the safe runtime ones, see `apply_sandbox`
+    pub sandbox: bool,
+    /// Stop on Int overflow instead of wrapping, see `apply_strict`
+    pub strict: bool,
+    /// Freeze locals assigned a string literal, see `apply_freeze_literals`
+    pub freeze_literals: bool,
+    /// Heap and time caps for the program once it runs
+    pub limits: ResourceLimits,
+    /// Profile what the program allocates, and write the folded stacks to
+    /// this path, see `heap_profile`
+    pub profile_heap: Option<String>,
+    /// Print the defs dead method elimination removed
+    pub print_dce: bool,
+    /// Where the runtime allocates from once the program runs
+    pub allocator: Allocator,
+    /// The runtime functions the program may declare
+    pub runtime: RuntimeProfile,
+    /// The passes run on the generated IR, and the level LLVM optimizes at
+    pub opt_level: OptLevel,
+    /// Keep frame pointers in the generated code, for `perf` and samply
+    pub frame_pointers: bool,
+    /// Leave the build time out of an executable's build info, so building
+    /// the same program twice gives the same executable
+    pub reproducible: bool,
+    /// How wide a tab is in the source lines diagnostics print
+    pub tab_width: TabWidth,
+}
+
 impl PajamaCompiler {
     pub fn compile_to_string(input: &str) -> String {
-        let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = tracing::info_span!("lex").in_scope(|| Lexer::new(input).tokenize());
 
-        println!("{:#?}", tokens);
+        tracing::trace!("tokens: {:#?}", tokens);
 
-        let mut precedence_map = PajamaCompiler::build_op_precedence_map();
-        let mut parser_result = Parser::start_parse(tokens, &mut precedence_map);
+        let mut precedence_map = default_op_precedence();
+        let mut parser_result = tracing::info_span!("parse")
+            .in_scope(|| Parser::start_parse(tokens, &mut precedence_map));
 
-        SemanticAnalyzer::run(&mut parser_result);
+        let analyzer =
+            tracing::info_span!("analyze").in_scope(|| SemanticAnalyzer::run(&mut parser_result));
+
+        for error in &analyzer.diagnostics.errors {
+            eprintln!("{}", error);
+        }
 
-        println!("ParserResult after analysis: ######");
-        println!("{:#?}", parser_result);
+        assert!(
+            analyzer.diagnostics.errors.is_empty(),
+            "Semantic analysis failed"
+        );
+
+        tracing::trace!("parser result after analysis: {:#?}", parser_result);
 
         let mlir_context = PajamaCompiler::create_mlir_context();
         let location = Location::unknown(&mlir_context);
         let mut mlir_module = Module::new(location);
         let mut compiler = Compiler::new(&mlir_context, &mlir_module, &parser_result);
+        compiler.opt_level = OptLevel::O0;
 
-        compiler.compile();
+        tracing::info_span!("codegen")
+            .in_scope(|| compiler.compile())
+            .expect("Code generation failed");
 
         //
 
-        println!("PRE VERIFICATION:");
-        println!("{}", mlir_module.body().to_string());
+        tracing::debug!("before verification:\n{}", mlir_module.body().to_string());
 
         assert!(mlir_module.as_operation().verify());
 
@@ -57,47 +177,278 @@ impl PajamaCompiler {
 
         pass_manager.add_pass(conversion::create_func_to_llvm());
 
-        pass_manager.run(&mut mlir_module).unwrap();
+        tracing::info_span!("lower").in_scope(|| pass_manager.run(&mut mlir_module).unwrap());
 
         assert!(mlir_module.as_operation().verify());
 
-        println!("POST VERIFICATION:");
-        println!("{}", mlir_module.body().to_string());
+        tracing::debug!("after lowering:\n{}", mlir_module.body().to_string());
 
         mlir_module.body().to_string()
 
         // PajamaCompiler::invoke(&mlir_module);
     }
 
-    pub fn compile_and_invoke(input: &str) {
-        let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+    /// Compiles `sources` as one program and runs its `main`, unless
+    /// `options.cancellation` is cancelled first. Returns the status `main`
+    /// exits with, or the diagnostics of a program that doesn't compile.
+    pub fn compile_and_invoke(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<i32, Vec<Diagnostic>>, Cancelled> {
+        let mlir_context = PajamaCompiler::create_mlir_context();
+        let mlir_module = match PajamaCompiler::compile_to_mlir(&mlir_context, sources, options)? {
+            Ok(mlir_module) => mlir_module,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+
+        options.cancellation.check()?;
+
+        Ok(Ok(PajamaCompiler::invoke(&mlir_module, options)))
+    }
+
+    /// Lexes, parses and analyzes `sources` as one program. Unlike the other
+    /// entry points nothing is printed: the diagnostics of the first phase
+    /// that reports any are returned for the caller to show.
+    pub fn compile_to_ast(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        PajamaCompiler::check(sources, options, &mut MemoryStats::new())
+    }
 
-        println!("{:#?}", tokens);
+    /// Analyzes `sources` as one program and measures each def, see
+    /// `metrics::def_metrics`. Defs `main` never reaches are measured too.
+    pub fn compile_to_metrics(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<Vec<DefMetrics>, Vec<Diagnostic>>, Cancelled> {
+        let checked = PajamaCompiler::check_source(sources, options, &mut MemoryStats::new())?;
+
+        Ok(checked.map(|parser_result| def_metrics(&parser_result)))
+    }
 
-        let mut precedence_map = PajamaCompiler::build_op_precedence_map();
-        let mut parser_result = Parser::start_parse(tokens, &mut precedence_map);
+    /// Analyzes `sources` as one program and graphs its calls and classes,
+    /// see `graph::program_graph`. Like `compile_to_metrics`, defs `main`
+    /// never reaches are kept.
+    pub fn compile_to_graph(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<ProgramGraph, Vec<Diagnostic>>, Cancelled> {
+        let checked = PajamaCompiler::check_source(sources, options, &mut MemoryStats::new())?;
 
-        SemanticAnalyzer::run(&mut parser_result);
+        Ok(checked.map(|parser_result| program_graph(&parser_result)))
+    }
 
-        println!("ParserResult after analysis: ######");
-        println!("{:#?}", parser_result);
+    /// Analyzes `sources` as one program and explains what's at `line` and
+    /// `column` of the one at `path`, see `explain::explain`. `None` when
+    /// there's nothing there, or no source at `path`.
+    pub fn compile_to_explanation(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        path: &str,
+        line: usize,
+        column: usize,
+    ) -> Result<Result<Option<Explanation>, Vec<Diagnostic>>, Cancelled> {
+        let checked = PajamaCompiler::check_source(sources, options, &mut MemoryStats::new())?;
+
+        Ok(checked.map(|parser_result| {
+            let source = sources.iter().find(|source| source.path == path)?;
+
+            explain(&parser_result, path, &source.input, line, column)
+        }))
+    }
 
+    /// Lowers a program from `compile_to_ast` to MLIR in the LLVM dialect, like
+    /// `compile_to_ir`.
+    pub fn ast_to_ir(
+        parser_result: &ParserResult,
+        options: &CompileOptions,
+    ) -> Result<String, Cancelled> {
         let mlir_context = PajamaCompiler::create_mlir_context();
-        let location = Location::unknown(&mlir_context);
+        let mlir_module = PajamaCompiler::lower_to_mlir(
+            &mlir_context,
+            parser_result,
+            options,
+            &mut MemoryStats::new(),
+        )?;
+
+        Ok(mlir_module.body().to_string())
+    }
+
+    /// JIT compiles a program from `compile_to_ast` and runs its `main`,
+    /// returning the status it exits with.
+    pub fn run_ast(
+        parser_result: &ParserResult,
+        options: &CompileOptions,
+    ) -> Result<i32, Cancelled> {
+        let mlir_context = PajamaCompiler::create_mlir_context();
+        let mlir_module = PajamaCompiler::lower_to_mlir(
+            &mlir_context,
+            parser_result,
+            options,
+            &mut MemoryStats::new(),
+        )?;
+
+        options.cancellation.check()?;
+
+        Ok(PajamaCompiler::invoke(&mlir_module, options))
+    }
+
+    /// Compiles `sources` as one program to MLIR, printed after lowering to
+    /// the LLVM dialect.
+    pub fn compile_to_ir(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<String, Vec<Diagnostic>>, Cancelled> {
+        let mlir_context = PajamaCompiler::create_mlir_context();
+        let compiled = PajamaCompiler::compile_to_mlir(&mlir_context, sources, options)?;
+
+        Ok(compiled.map(|mlir_module| mlir_module.body().to_string()))
+    }
+
+    /// Compiles `sources` as one program to a native object file at `path`.
+    /// The object defines `main` and leaves the `pj_*` runtime functions for
+    /// the linker to resolve.
+    pub fn compile_to_object(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        path: &str,
+    ) -> Result<Result<(), Vec<Diagnostic>>, Cancelled> {
+        let mlir_context = PajamaCompiler::create_mlir_context();
+        let mlir_module = match PajamaCompiler::compile_to_mlir(&mlir_context, sources, options)? {
+            Ok(mlir_module) => mlir_module,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+
+        options.cancellation.check()?;
+
+        // The engine runs the LLVM target machine for the host, it's never
+        // asked to run `main` here
+        let engine = ExecutionEngine::new(&mlir_module, options.opt_level.llvm_level(), &[], false);
+
+        tracing::info_span!("emit", phase = "object").in_scope(|| engine.dump_to_object_file(path));
+
+        Ok(Ok(()))
+    }
+
+    /// Compiles `sources` as one program to a native executable at `path`,
+    /// linking the object file against the runtime library with `cc`, or
+    /// `$CC` when it's set. The build info `pajama inspect-binary` reads is
+    /// compiled and linked in too, see `build_info`.
+    pub fn compile_to_executable(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        path: &str,
+    ) -> Result<(), LinkError> {
+        let runtime = PajamaCompiler::runtime_library().map_err(LinkError::Failed)?;
+        let object_path = format!("{}.o", path);
+        let build_info_path = format!("{}.build_info.c", path);
+
+        PajamaCompiler::compile_to_object(sources, options, &object_path)?
+            .map_err(LinkError::Diagnostics)?;
+
+        let build_info = BuildInfo::new(options.opt_level, options.reproducible);
+
+        if let Err(err) = std::fs::write(&build_info_path, build_info.to_c_source()) {
+            let _ = std::fs::remove_file(&object_path);
+
+            return Err(LinkError::Failed(format!("{}: {}", build_info_path, err)));
+        }
+
+        let linker = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = tracing::info_span!("link").in_scope(|| {
+            Command::new(&linker)
+                .arg(&object_path)
+                .arg(&build_info_path)
+                .arg(&runtime)
+                .args(["-o", path, "-lpthread", "-ldl", "-lm"])
+                .status()
+        });
+
+        let _ = std::fs::remove_file(&object_path);
+        let _ = std::fs::remove_file(&build_info_path);
+
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(LinkError::Failed(format!(
+                "{} exited with {}",
+                linker, status
+            ))),
+            Err(err) => Err(LinkError::Failed(format!("{}: {}", linker, err))),
+        }
+    }
+
+    /// The static runtime library executables link against,
+    /// `$PAJAMA_RUNTIME_LIB` or the `libpajama.a` Cargo builds next to the
+    /// compiler.
+    fn runtime_library() -> Result<PathBuf, String> {
+        let path = match std::env::var_os("PAJAMA_RUNTIME_LIB") {
+            Some(path) => PathBuf::from(path),
+            None => std::env::current_exe()
+                .map_err(|err| err.to_string())?
+                .with_file_name("libpajama.a"),
+        };
+
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(format!(
+                "no runtime library at {}, set PAJAMA_RUNTIME_LIB",
+                path.display()
+            ))
+        }
+    }
+
+    fn compile_to_mlir<'c>(
+        mlir_context: &'c Context,
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<Module<'c>, Vec<Diagnostic>>, Cancelled> {
+        let mut memory_stats = MemoryStats::new();
+
+        let parser_result = match PajamaCompiler::analyze(sources, options, &mut memory_stats)? {
+            Ok(parser_result) => parser_result,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+
+        let mlir_module = PajamaCompiler::lower_to_mlir(
+            mlir_context,
+            &parser_result,
+            options,
+            &mut memory_stats,
+        )?;
+
+        PajamaCompiler::report_stats(&memory_stats, options);
+
+        Ok(Ok(mlir_module))
+    }
+
+    /// Compiles an analyzed program to MLIR and lowers it to the LLVM dialect.
+    fn lower_to_mlir<'c>(
+        mlir_context: &'c Context,
+        parser_result: &ParserResult,
+        options: &CompileOptions,
+        memory_stats: &mut MemoryStats,
+    ) -> Result<Module<'c>, Cancelled> {
+        let location = Location::unknown(mlir_context);
         let mut mlir_module = Module::new(location);
-        let mut compiler = Compiler::new(&mlir_context, &mlir_module, &parser_result);
+        let mut compiler = Compiler::new(mlir_context, &mlir_module, parser_result);
+        compiler.opt_level = options.opt_level;
+        compiler.frame_pointers = options.frame_pointers;
+
+        tracing::info_span!("codegen")
+            .in_scope(|| compiler.compile_cancellable(&options.cancellation))?;
 
-        compiler.compile();
+        memory_stats.record("codegen", None, None);
 
         //
 
-        println!("PRE VERIFICATION:");
-        println!("{}", mlir_module.body().to_string());
+        tracing::debug!("before verification:\n{}", mlir_module.body().to_string());
 
         assert!(mlir_module.as_operation().verify());
 
-        let pass_manager = PassManager::new(&mlir_context);
+        let pass_manager = PassManager::new(mlir_context);
+        options.opt_level.add_structured_passes(&pass_manager);
         pass_manager.add_pass(conversion::create_func_to_llvm());
 
         pass_manager
@@ -112,22 +463,298 @@ impl PajamaCompiler {
 
         pass_manager.add_pass(conversion::create_func_to_llvm());
 
-        pass_manager.run(&mut mlir_module).unwrap();
+        options.opt_level.add_llvm_passes(&pass_manager);
+
+        tracing::info_span!("lower").in_scope(|| pass_manager.run(&mut mlir_module).unwrap());
 
         assert!(mlir_module.as_operation().verify());
 
-        PajamaCompiler::invoke(&mlir_module);
+        memory_stats.record("lower", None, None);
+
+        Ok(mlir_module)
+    }
+
+    /// Compiles `sources` as one program to C99, see `c_backend::emit_c`.
+    pub fn compile_to_c(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<String, Vec<Diagnostic>>, Cancelled> {
+        PajamaCompiler::compile_to_source(sources, options, "emit_c", emit_c)
+    }
+
+    /// Compiles `sources` as one program to JavaScript, see
+    /// `js_backend::emit_js`.
+    pub fn compile_to_js(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<String, Vec<Diagnostic>>, Cancelled> {
+        PajamaCompiler::compile_to_source(sources, options, "emit_js", emit_js)
     }
 
-    pub fn invoke(mlir_module: &Module) {
-        let engine = ExecutionEngine::new(mlir_module, 2, &[], false);
+    /// Compiles `sources` as one program to a .nlb file for the bytecode
+    /// VM, see `bytecode::emit_bytecode`.
+    pub fn compile_to_bytecode(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+    ) -> Result<Result<Vec<u8>, Vec<Diagnostic>>, Cancelled> {
+        PajamaCompiler::compile_to_source(sources, options, "emit_bytecode", |parser_result| {
+            emit_bytecode(parser_result).map(|program| program.encode())
+        })
+    }
+
+    /// Compiles and runs a program someone else wrote, like a web playground
+    /// does for each request, without touching the filesystem or ending the
+    /// process: with `--sandbox`, on the bytecode VM within `limits`, and
+    /// with what it prints returned rather than written to stdout.
+    ///
+    /// Imports are refused, since they read files, and the compiler
+    /// panicking on a program is reported as a diagnostic like any other.
+    pub fn eval_sandboxed(input: &str, limits: &ResourceLimits) -> EvalResult {
+        let failed = |message: &str| EvalResult::Failed(vec![Diagnostic::new(message)]);
+
+        let tokens = Lexer::new(input).tokenize();
+
+        if tokens.iter().any(|token| matches!(token, Token::Import)) {
+            return failed("imports aren't available when evaluating sandboxed");
+        }
+
+        let compiled = std::panic::catch_unwind(|| {
+            let sources = [SourceFile {
+                path: "main.pjs".to_string(),
+                input: input.to_string(),
+            }];
+            let options = CompileOptions {
+                sandbox: true,
+                limits: limits.clone(),
+                ..Default::default()
+            };
+
+            // Nothing cancels an evaluation
+            let parser_result = PajamaCompiler::compile_to_ast(&sources, &options).unwrap()?;
+
+            emit_bytecode(&parser_result).map_err(|message| vec![Diagnostic::new(message)])
+        });
+
+        let program = match compiled {
+            Ok(Ok(program)) => program,
+            Ok(Err(diagnostics)) => return EvalResult::Failed(diagnostics),
+            Err(_) => return failed("the compiler crashed on this program"),
+        };
+
+        let mut output = vec![];
+        let ran = vm::run_limited(&program, &mut output, limits);
+        let output = String::from_utf8_lossy(&output).to_string();
+
+        match ran {
+            Ok(status) => EvalResult::Exited { status, output },
+            Err(error) => EvalResult::Stopped { error, output },
+        }
+    }
+
+    /// Compiles `sources` as one program with `backend`, returning the
+    /// artifact it emits, see `backend::Backend`.
+    pub fn compile_with_backend(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        backend: &dyn Backend,
+    ) -> Result<Result<Vec<u8>, Vec<Diagnostic>>, Cancelled> {
+        PajamaCompiler::compile_to_source(sources, options, "emit_backend", |parser_result| {
+            backend.emit(parser_result)
+        })
+    }
+
+    fn compile_to_source<T>(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        phase: &'static str,
+        emit: impl Fn(&ParserResult) -> Result<T, String>,
+    ) -> Result<Result<T, Vec<Diagnostic>>, Cancelled> {
+        let mut memory_stats = MemoryStats::new();
+
+        let parser_result = match PajamaCompiler::analyze(sources, options, &mut memory_stats)? {
+            Ok(parser_result) => parser_result,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+        let source = tracing::info_span!("emit", phase).in_scope(|| emit(&parser_result));
+
+        memory_stats.record(phase, None, None);
+
+        PajamaCompiler::report_stats(&memory_stats, options);
+
+        // Backends report what they can't emit as a message
+        Ok(source.map_err(|error| vec![Diagnostic::new(error)]))
+    }
+
+    /// Prints the stats with `--memory-stats` and writes them with
+    /// `--stats-json`.
+    fn report_stats(memory_stats: &MemoryStats, options: &CompileOptions) {
+        if options.memory_stats {
+            memory_stats.print();
+        }
+
+        if let Some(path) = &options.stats_json {
+            if let Err(err) = std::fs::write(path, memory_stats.to_json()) {
+                eprintln!("{}: {}", path, err);
+            }
+        }
+    }
+
+    /// `check`, reporting the stats of a compile that stops with
+    /// diagnostics before returning them.
+    fn analyze(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        memory_stats: &mut MemoryStats,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        let checked = PajamaCompiler::check(sources, options, memory_stats)?;
+
+        if let Err(diagnostics) = &checked {
+            memory_stats.diagnostics = diagnostics.len();
+            PajamaCompiler::report_stats(memory_stats, options);
+        }
+
+        Ok(checked)
+    }
+
+    /// Lexes, parses and analyzes `sources` as one program, stopping on the
+    /// first phase that reports errors.
+    fn check(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        memory_stats: &mut MemoryStats,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        let checked = PajamaCompiler::check_source(sources, options, memory_stats)?;
+
+        let mut parser_result = match checked {
+            Ok(parser_result) => parser_result,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+
+        let folded = tracing::info_span!("consteval")
+            .in_scope(|| fold_constant_calls(&mut parser_result, DEFAULT_FUEL));
+
+        tracing::debug!("consteval: replaced {} calls", folded);
+
+        let removed =
+            tracing::info_span!("dce").in_scope(|| eliminate_dead_methods(&mut parser_result));
+
+        if options.print_dce {
+            for fn_name in &removed {
+                eprintln!("dce: removed {}", fn_name);
+            }
+        }
+
+        tracing::trace!("parser result after analysis: {:#?}", parser_result);
+
+        Ok(Ok(parser_result))
+    }
+
+    /// `check` without constant folding and dead method elimination, so the
+    /// program still has every def it was written with.
+    fn check_source(
+        sources: &[SourceFile],
+        options: &CompileOptions,
+        memory_stats: &mut MemoryStats,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        let cancellation = &options.cancellation;
+
+        let files: Vec<(String, Vec<Token>)> = tracing::info_span!("lex").in_scope(|| {
+            par_map(sources.iter().collect(), |source: &SourceFile| {
+                (source.path.clone(), Lexer::new(&source.input).tokenize())
+            })
+        });
+
+        tracing::trace!("tokens: {:#?}", files);
+        memory_stats.record(
+            "lex",
+            Some(files.iter().map(|(_, tokens)| tokens.len()).sum()),
+            None,
+        );
+
+        cancellation.check()?;
+
+        let mut precedence_map = default_op_precedence();
+        let parsed = tracing::info_span!("parse").in_scope(|| {
+            Parser::start_parse_files_cancellable(files, &mut precedence_map, cancellation)
+        })?;
+
+        let mut parser_result = match parsed {
+            Ok(parser_result) => parser_result,
+            Err(diagnostics) => return Ok(Err(diagnostics)),
+        };
+
+        memory_stats.record("parse", None, Some(count_nodes(&parser_result.module)));
+        memory_stats.count_program(&parser_result);
+
+        if options.freeze_literals {
+            apply_freeze_literals(&mut parser_result);
+        }
+
+        let mut analyzer = tracing::info_span!("analyze").in_scope(|| {
+            SemanticAnalyzer::transform_ast(&mut parser_result, Diagnostics::new(), cancellation)
+        })?;
+
+        check_runtime_profile(&parser_result, options.runtime, &mut analyzer.diagnostics);
+
+        if options.sandbox {
+            apply_sandbox(&mut parser_result, &mut analyzer.diagnostics);
+        }
+
+        if options.strict {
+            apply_strict(&mut parser_result);
+        }
+
+        run_lints(&options.lints, &parser_result, &mut analyzer.diagnostics);
+
+        memory_stats.record("analyze", None, Some(count_nodes(&parser_result.module)));
+
+        if !analyzer.diagnostics.errors.is_empty() {
+            // Analysis doesn't track positions yet, so there's only the message
+            return Ok(Err(analyzer
+                .diagnostics
+                .errors
+                .into_iter()
+                .map(Diagnostic::new)
+                .collect()));
+        }
+
+        Ok(Ok(parser_result))
+    }
+
+    /// Runs `main` of a lowered module, returning the status it exits with.
+    pub fn invoke(mlir_module: &Module, options: &CompileOptions) -> i32 {
+        let engine = ExecutionEngine::new(mlir_module, options.opt_level.llvm_level(), &[], false);
+
+        allocator::set_allocator(options.allocator);
+
+        // Only `main` is held to the limits, not the JIT compile before it
+        pajama_lib::set_heap_limit(options.limits.max_heap);
+        let _watchdog = options.limits.max_time.map(Watchdog::start);
+
+        if options.profile_heap.is_some() {
+            heap_profile::start();
+        }
+
+        let mut status_code: i32 = 0;
 
         unsafe {
             engine
-                // .invoke_packed("main", &mut [&mut status_code as *mut i32 as *mut ()])
-                .invoke_packed("main", &mut [])
+                .invoke_packed("main", &mut [&mut status_code as *mut i32 as *mut ()])
                 .unwrap();
         }
+
+        pajama_lib::run_exit_handlers();
+
+        if let Some(path) = &options.profile_heap {
+            let sites = heap_profile::finish();
+            heap_profile::print(&sites);
+
+            if let Err(err) = std::fs::write(path, heap_profile::to_folded(&sites)) {
+                eprintln!("{}: {}", path, err);
+            }
+        }
+
+        status_code
     }
 
     fn create_mlir_context() -> Context {
@@ -147,16 +774,4 @@ impl PajamaCompiler {
 
         context
     }
-
-    fn build_op_precedence_map() -> HashMap<char, i32> {
-        let mut op_precedence_map = HashMap::with_capacity(6);
-
-        op_precedence_map.insert('<', 10);
-        op_precedence_map.insert('+', 20);
-        op_precedence_map.insert('-', 20);
-        op_precedence_map.insert('*', 40);
-        op_precedence_map.insert('/', 40);
-
-        op_precedence_map
-    }
 }
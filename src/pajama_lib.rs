@@ -47,18 +47,30 @@ static EXTERNAL_FNS3: [fn(i64); 1] = [print_int];
 // #[used]
 // static EXTERNAL_FNS15: [fn(PjStr); 1] = [base_print];
 
-use libc::{c_void, malloc};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, LocalResult, NaiveDate, SecondsFormat,
+    TimeZone, Timelike, Utc,
+};
+use libc::c_void;
 // You can run this example from the root of the mio repo:
 // cargo run --example tcp_server --features="os-poll net"
 use mio::event::Event;
 use mio::net::{TcpListener, TcpStream};
 use mio::{Events, Interest, Poll, Registry, Token};
 use safer_ffi::vec;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Read, Write};
 use std::mem::size_of;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
 
+use crate::allocator;
 use crate::codegen::print_bytes;
+use crate::heap_profile;
+use crate::resource_limits::HEAP_LIMIT_EXIT_CODE;
 
 // // Setup some tokens to allow us to identify which event is for which socket.
 const SERVER: Token = Token(0);
@@ -66,10 +78,44 @@ const SERVER: Token = Token(0);
 // // Some data we'll send over the connection.
 // const DATA: &[u8] = b"Hello world!\n";
 
+// Everything the runtime allocates is counted against `--max-heap`, including
+// the slots arrays grow into. Nothing is freed yet so the count only grows
+static HEAP_LIMIT: AtomicU64 = AtomicU64::new(u64::MAX);
+static HEAP_USED: AtomicU64 = AtomicU64::new(0);
+
+/// Caps what the runtime may allocate from here on, see `ResourceLimits`.
+pub fn set_heap_limit(max_heap: Option<u64>) {
+    HEAP_LIMIT.store(max_heap.unwrap_or(u64::MAX), Ordering::Relaxed);
+    HEAP_USED.store(0, Ordering::Relaxed);
+}
+
+fn track_allocation(size: usize) {
+    let used = HEAP_USED.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+    let limit = HEAP_LIMIT.load(Ordering::Relaxed);
+
+    if used > limit {
+        eprintln!("heap limit of {} bytes exceeded", limit);
+        std::process::exit(HEAP_LIMIT_EXIT_CODE);
+    }
+}
+
+/// Counts `size` bytes the runtime is about to allocate, against the heap
+/// limit and in the heap profile.
+fn count_allocation(size: usize) {
+    track_allocation(size);
+    heap_profile::record(size);
+}
+
+fn pj_malloc(size: usize) -> *mut c_void {
+    count_allocation(size);
+
+    allocator::allocate(size)
+}
+
 #[used]
 static EXTERNAL_FNS6: [extern "C" fn(&PjStr) -> *mut c_void; 1] = [pj_malloc_struct];
 
-fn pjstr_to_str(pj_str: &PjStr) -> &str {
+pub fn pjstr_to_str(pj_str: &PjStr) -> &str {
     unsafe {
         // Create a slice from the raw buffer and length
         let slice = core::slice::from_raw_parts(pj_str.buffer as *const u8, pj_str.length as usize);
@@ -86,35 +132,35 @@ pub extern "C" fn pj_malloc_struct(pj_name: &PjStr) -> *mut c_void {
     match name {
         "TcpListener" => {
             let struct_size = size_of::<TcpListener>();
-            let ptr = unsafe { malloc(struct_size as libc::size_t) as *mut c_void };
+            let ptr = pj_malloc(struct_size);
 
             // unsafe { std::ptr::write(ptr as *mut TcpListener, TcpListener::new().unwrap()) };
             ptr
         }
         "IoPoll" => {
             let struct_size = size_of::<Poll>();
-            let ptr = unsafe { malloc(struct_size as libc::size_t) as *mut c_void };
+            let ptr = pj_malloc(struct_size);
 
             unsafe { std::ptr::write(ptr as *mut Poll, Poll::new().unwrap()) };
             ptr
         }
         "IoEvents" => {
             let struct_size = size_of::<Events>();
-            let ptr = unsafe { malloc(struct_size as libc::size_t) as *mut c_void };
+            let ptr = pj_malloc(struct_size);
 
             unsafe { std::ptr::write(ptr as *mut Events, Events::with_capacity(128)) };
             ptr
         }
         "IoConnections" => {
             let struct_size = size_of::<HashMap<Token, TcpStream>>();
-            let ptr = unsafe { malloc(struct_size as libc::size_t) as *mut c_void };
+            let ptr = pj_malloc(struct_size);
 
             unsafe { std::ptr::write(ptr as *mut HashMap<Token, TcpStream>, HashMap::new()) };
             ptr
         }
         "IoBuffers" => {
             let struct_size = size_of::<HashMap<Token, Vec<u8>>>();
-            let ptr = unsafe { malloc(struct_size as libc::size_t) as *mut c_void };
+            let ptr = pj_malloc(struct_size);
 
             unsafe { std::ptr::write(ptr as *mut HashMap<Token, Vec<u8>>, HashMap::new()) };
             ptr
@@ -512,6 +558,1716 @@ pub extern "C" fn pj_tcp_connection_write(pj_tcp_connection: &mut PjTcpConnectio
     }
 }
 
+pub fn string_to_pjstr(string: String) -> *mut PjStr {
+    let length = string.len();
+
+    // Strings are never freed yet
+    let buffer = pj_malloc(length) as *mut u8;
+    let pj_str = pj_malloc(size_of::<PjStr>()) as *mut PjStr;
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(string.as_ptr(), buffer, length);
+
+        pj_str.write(PjStr {
+            buffer: buffer as *const i8,
+            length: length as i64,
+            max_length: length as i64,
+        });
+    }
+
+    pj_str
+}
+
+type PjPrintHook = extern "C" fn(&PjStr);
+
+// Where `puts` writes instead of stdout, for targets without one
+static PRINT_HOOK: Mutex<Option<PjPrintHook>> = Mutex::new(None);
+
+#[used]
+static EXTERNAL_FNS21: [extern "C" fn(&PjStr); 1] = [pj_puts];
+
+#[no_mangle]
+pub extern "C" fn pj_puts(pj_str: &PjStr) {
+    match *PRINT_HOOK.lock().unwrap() {
+        Some(hook) => hook(pj_str),
+        None => println!("{}", pjstr_to_str(pj_str)),
+    }
+}
+
+#[used]
+static EXTERNAL_FNS90: [extern "C" fn(&PjStr); 1] = [pj_print];
+
+/// `puts` without the newline
+#[no_mangle]
+pub extern "C" fn pj_print(pj_str: &PjStr) {
+    match *PRINT_HOOK.lock().unwrap() {
+        Some(hook) => hook(pj_str),
+        None => {
+            print!("{}", pjstr_to_str(pj_str));
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+#[used]
+static EXTERNAL_FNS35: [extern "C" fn(PjPrintHook); 1] = [pj_set_print_hook];
+
+/// Sends everything `puts` prints to `hook`, see `RuntimeProfile::Minimal`.
+#[no_mangle]
+pub extern "C" fn pj_set_print_hook(hook: PjPrintHook) {
+    *PRINT_HOOK.lock().unwrap() = Some(hook);
+}
+
+// The REPL prints the value of each input with these, and its type, like
+// `=> 42 : Int`
+
+fn print_repl_value(value: String, type_name: &str) {
+    pj_puts(unsafe { &*string_to_pjstr(format!("=> {} : {}", value, type_name)) });
+}
+
+#[used]
+static EXTERNAL_FNS84: [extern "C" fn(i64); 1] = [pj_repl_print_int];
+
+#[no_mangle]
+pub extern "C" fn pj_repl_print_int(value: i64) {
+    print_repl_value(value.to_string(), "Int");
+}
+
+#[used]
+static EXTERNAL_FNS85: [extern "C" fn(f64); 1] = [pj_repl_print_float];
+
+#[no_mangle]
+pub extern "C" fn pj_repl_print_float(value: f64) {
+    print_repl_value(format!("{:?}", value), "Float");
+}
+
+#[used]
+static EXTERNAL_FNS86: [extern "C" fn(bool); 1] = [pj_repl_print_bool];
+
+#[no_mangle]
+pub extern "C" fn pj_repl_print_bool(value: bool) {
+    print_repl_value(value.to_string(), "Bool");
+}
+
+#[used]
+static EXTERNAL_FNS87: [extern "C" fn(&PjStr); 1] = [pj_repl_print_str];
+
+/// Quoted, with escapes for quotes and control characters
+#[no_mangle]
+pub extern "C" fn pj_repl_print_str(value: &PjStr) {
+    print_repl_value(format!("{:?}", pjstr_to_str(value)), "Str");
+}
+
+#[used]
+static EXTERNAL_FNS22: [extern "C" fn(i64) -> *mut PjStr; 1] = [pj_int_to_s];
+
+#[no_mangle]
+pub extern "C" fn pj_int_to_s(int: i64) -> *mut PjStr {
+    string_to_pjstr(int.to_string())
+}
+
+#[used]
+static EXTERNAL_FNS91: [extern "C" fn(bool) -> *mut PjStr; 1] = [pj_bool_to_s];
+
+#[no_mangle]
+pub extern "C" fn pj_bool_to_s(value: bool) -> *mut PjStr {
+    string_to_pjstr(value.to_string())
+}
+
+#[used]
+static EXTERNAL_FNS23: [extern "C" fn(&PjStr, &PjStr) -> *mut PjStr; 1] = [pj_str_concat];
+
+#[no_mangle]
+pub extern "C" fn pj_str_concat(left: &PjStr, right: &PjStr) -> *mut PjStr {
+    string_to_pjstr(format!("{}{}", pjstr_to_str(left), pjstr_to_str(right)))
+}
+
+#[used]
+static EXTERNAL_FNS30: [extern "C" fn(i64, i64) -> *mut PjStr; 1] = [pj_int_to_s_base];
+
+#[no_mangle]
+pub extern "C" fn pj_int_to_s_base(int: i64, base: i64) -> *mut PjStr {
+    if !(2..=36).contains(&base) {
+        eprintln!("invalid base: {}", base);
+        std::process::exit(1);
+    }
+
+    let mut digits = vec![];
+    let mut remaining = int.unsigned_abs();
+
+    loop {
+        let digit = (remaining % base as u64) as u32;
+        digits.push(char::from_digit(digit, base as u32).unwrap());
+        remaining /= base as u64;
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    if int < 0 {
+        digits.push('-');
+    }
+
+    string_to_pjstr(digits.iter().rev().collect())
+}
+
+#[used]
+static EXTERNAL_FNS31: [extern "C" fn(&PjStr, i64) -> i64; 1] = [pj_int_parse];
+
+#[no_mangle]
+pub extern "C" fn pj_int_parse(str: &PjStr, base: i64) -> i64 {
+    let text = pjstr_to_str(str);
+
+    if !(2..=36).contains(&base) {
+        eprintln!("invalid base: {}", base);
+        std::process::exit(1);
+    }
+
+    match i64::from_str_radix(text.trim(), base as u32) {
+        Ok(int) => int,
+        Err(_) => {
+            eprintln!("invalid Int: {:?}", text);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[used]
+static EXTERNAL_FNS36: [extern "C" fn(f64) -> *mut PjStr; 1] = [pj_float_to_s];
+
+/// The shortest digits that read back as `float`, always with a fraction or
+/// an exponent, like `1.0` or `1e20`.
+#[no_mangle]
+pub extern "C" fn pj_float_to_s(float: f64) -> *mut PjStr {
+    string_to_pjstr(format!("{:?}", float))
+}
+
+#[used]
+static EXTERNAL_FNS37: [extern "C" fn(i64) -> f64; 1] = [pj_int_to_f];
+
+#[no_mangle]
+pub extern "C" fn pj_int_to_f(int: i64) -> f64 {
+    int as f64
+}
+
+#[used]
+static EXTERNAL_FNS38: [extern "C" fn(f64) -> i64; 1] = [pj_float_to_i];
+
+/// Rounds towards zero, saturating at the bounds of an Int. NaN is 0.
+#[no_mangle]
+pub extern "C" fn pj_float_to_i(float: f64) -> i64 {
+    float as i64
+}
+
+#[used]
+static EXTERNAL_FNS39: [extern "C" fn(&PjStr, i64, i64) -> *mut PjStr; 2] =
+    [pj_str_slice, pj_str_sub];
+
+/// The bytes from `start` up to, but not including, `end`. Offsets past the
+/// ends of `str` are clamped to them, and an `end` before `start` gives an
+/// empty Str.
+#[no_mangle]
+pub extern "C" fn pj_str_slice(str: &PjStr, start: i64, end: i64) -> *mut PjStr {
+    let text = pjstr_to_str(str);
+    let start = start.clamp(0, text.len() as i64) as usize;
+    let end = end.clamp(start as i64, text.len() as i64) as usize;
+
+    match text.get(start..end) {
+        Some(slice) => string_to_pjstr(slice.to_string()),
+        None => {
+            eprintln!(
+                "can't slice {:?} at {}..{}, it's inside a character",
+                text, start, end
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `length` bytes from `start`, see `pj_str_slice`.
+#[no_mangle]
+pub extern "C" fn pj_str_sub(str: &PjStr, start: i64, length: i64) -> *mut PjStr {
+    pj_str_slice(str, start, start.saturating_add(length.max(0)))
+}
+
+#[used]
+static EXTERNAL_FNS40: [extern "C" fn(&PjStr) -> *mut PjStr; 1] = [pj_str_trim];
+
+/// Without the whitespace at either end.
+#[no_mangle]
+pub extern "C" fn pj_str_trim(str: &PjStr) -> *mut PjStr {
+    string_to_pjstr(pjstr_to_str(str).trim().to_string())
+}
+
+#[used]
+static EXTERNAL_FNS41: [extern "C" fn(&PjStr, &PjStr) -> bool; 2] =
+    [pj_str_starts_with, pj_str_ends_with];
+
+#[no_mangle]
+pub extern "C" fn pj_str_starts_with(str: &PjStr, prefix: &PjStr) -> bool {
+    pjstr_to_str(str).starts_with(pjstr_to_str(prefix))
+}
+
+#[no_mangle]
+pub extern "C" fn pj_str_ends_with(str: &PjStr, suffix: &PjStr) -> bool {
+    pjstr_to_str(str).ends_with(pjstr_to_str(suffix))
+}
+
+#[used]
+static EXTERNAL_FNS42: [extern "C" fn(&PjStr, &PjStr) -> i64; 1] = [pj_str_index_of];
+
+/// The byte offset of the first `needle` in `str`, -1 when there's none.
+#[no_mangle]
+pub extern "C" fn pj_str_index_of(str: &PjStr, needle: &PjStr) -> i64 {
+    match pjstr_to_str(str).find(pjstr_to_str(needle)) {
+        Some(offset) => offset as i64,
+        None => -1,
+    }
+}
+
+// Characters are grapheme clusters, what a reader sees as one character,
+// like "é" written as an "e" and a combining accent
+
+#[used]
+static EXTERNAL_FNS57: [extern "C" fn(&PjStr) -> i64; 2] = [pj_str_length, pj_str_byte_length];
+
+/// The number of characters in `str`.
+#[no_mangle]
+pub extern "C" fn pj_str_length(str: &PjStr) -> i64 {
+    pjstr_to_str(str).graphemes(true).count() as i64
+}
+
+/// The number of bytes in `str`, which offsets into it count.
+#[no_mangle]
+pub extern "C" fn pj_str_byte_length(str: &PjStr) -> i64 {
+    pjstr_to_str(str).len() as i64
+}
+
+#[used]
+static EXTERNAL_FNS58: [extern "C" fn(&PjStr) -> *mut PjArray; 2] =
+    [pj_str_chars, pj_str_codepoints];
+
+/// An array of a Str for each character of `str`.
+#[no_mangle]
+pub extern "C" fn pj_str_chars(str: &PjStr) -> *mut PjArray {
+    let chars = pj_array_new(0);
+
+    for grapheme in pjstr_to_str(str).graphemes(true) {
+        let char_str = string_to_pjstr(grapheme.to_string()) as *mut c_void;
+        pj_array_push_ptr(unsafe { &mut *chars }, char_str);
+    }
+
+    chars
+}
+
+/// An array of the Unicode code points of `str`, as Ints.
+#[no_mangle]
+pub extern "C" fn pj_str_codepoints(str: &PjStr) -> *mut PjArray {
+    let codepoints = pj_array_new(0);
+
+    for codepoint in pjstr_to_str(str).chars() {
+        pj_array_push_int(unsafe { &mut *codepoints }, codepoint as i64);
+    }
+
+    codepoints
+}
+
+#[used]
+static EXTERNAL_FNS59: [extern "C" fn(&PjStr) -> *mut PjStr; 4] =
+    [pj_str_to_nfc, pj_str_to_nfd, pj_str_to_nfkc, pj_str_to_nfkd];
+
+/// `str` in Unicode normalization form C, with characters composed where
+/// possible, so equal looking strings compare equal.
+#[no_mangle]
+pub extern "C" fn pj_str_to_nfc(str: &PjStr) -> *mut PjStr {
+    string_to_pjstr(pjstr_to_str(str).nfc().collect())
+}
+
+/// `str` in normalization form D, with characters decomposed.
+#[no_mangle]
+pub extern "C" fn pj_str_to_nfd(str: &PjStr) -> *mut PjStr {
+    string_to_pjstr(pjstr_to_str(str).nfd().collect())
+}
+
+/// `str` in normalization form KC, which also replaces compatibility
+/// characters like "ﬁ" with their plain equivalent.
+#[no_mangle]
+pub extern "C" fn pj_str_to_nfkc(str: &PjStr) -> *mut PjStr {
+    string_to_pjstr(pjstr_to_str(str).nfkc().collect())
+}
+
+/// `str` in normalization form KD.
+#[no_mangle]
+pub extern "C" fn pj_str_to_nfkd(str: &PjStr) -> *mut PjStr {
+    string_to_pjstr(pjstr_to_str(str).nfkd().collect())
+}
+
+/// The encodings `pj_str_encode` and `pj_bytes_decode` convert between
+#[derive(Clone, Copy)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+fn parse_encoding(encoding: &PjStr) -> Encoding {
+    match pjstr_to_str(encoding).to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => Encoding::Utf8,
+        // Little endian without a byte order mark, like most files on disk
+        "utf-16" | "utf-16le" => Encoding::Utf16Le,
+        "utf-16be" => Encoding::Utf16Be,
+        "latin-1" | "latin1" | "iso-8859-1" => Encoding::Latin1,
+        encoding => {
+            eprintln!(
+                "unknown encoding `{}`, expected utf-8, utf-16, utf-16le, utf-16be or latin-1",
+                encoding
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Whether `on_invalid` asks to replace what can't be converted rather than
+/// stop the program.
+fn replaces_invalid(on_invalid: &PjStr) -> bool {
+    match pjstr_to_str(on_invalid) {
+        "replace" => true,
+        "error" => false,
+        on_invalid => {
+            eprintln!(
+                "unknown invalid sequence handling `{}`, expected replace or error",
+                on_invalid
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[used]
+static EXTERNAL_FNS60: [extern "C" fn(&PjStr, &PjStr, &PjStr) -> *mut PjArray; 1] = [pj_str_encode];
+
+/// The bytes of `str` in `encoding`, as an array of Ints. Only Latin-1 can't
+/// hold every character, so characters past U+00FF become "?" when
+/// `on_invalid` is "replace" and stop the program when it's "error".
+#[no_mangle]
+pub extern "C" fn pj_str_encode(str: &PjStr, encoding: &PjStr, on_invalid: &PjStr) -> *mut PjArray {
+    let text = pjstr_to_str(str);
+    let encoding = parse_encoding(encoding);
+    let replace = replaces_invalid(on_invalid);
+
+    let bytes: Vec<u8> = match encoding {
+        Encoding::Utf8 => text.as_bytes().to_vec(),
+        Encoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        Encoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        Encoding::Latin1 => text
+            .chars()
+            .map(|char| match u8::try_from(char) {
+                Ok(byte) => byte,
+                Err(_) if replace => b'?',
+                Err(_) => {
+                    eprintln!("can't encode {:?} as Latin-1", char);
+                    std::process::exit(1);
+                }
+            })
+            .collect(),
+    };
+
+    let array = pj_array_new(bytes.len() as i64);
+
+    for byte in bytes {
+        pj_array_push_int(unsafe { &mut *array }, byte as i64);
+    }
+
+    array
+}
+
+#[used]
+static EXTERNAL_FNS61: [extern "C" fn(&PjArray, &PjStr, &PjStr) -> *mut PjStr; 1] =
+    [pj_bytes_decode];
+
+/// The Str `bytes` hold in `encoding`. Invalid sequences become U+FFFD when
+/// `on_invalid` is "replace" and stop the program when it's "error", while
+/// items that aren't a byte always stop it.
+#[no_mangle]
+pub extern "C" fn pj_bytes_decode(
+    bytes: &PjArray,
+    encoding: &PjStr,
+    on_invalid: &PjStr,
+) -> *mut PjStr {
+    let encoding = parse_encoding(encoding);
+    let replace = replaces_invalid(on_invalid);
+
+    let invalid = |message: String| {
+        if !replace {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
+    let raw: Vec<u8> = bytes
+        .slots
+        .iter()
+        .enumerate()
+        .map(|(index, slot)| match u8::try_from(*slot as i64) {
+            Ok(byte) => byte,
+            Err(_) => {
+                eprintln!("{} at {} isn't a byte", *slot as i64, index);
+                std::process::exit(1);
+            }
+        })
+        .collect();
+
+    let text = match encoding {
+        Encoding::Utf8 => match std::str::from_utf8(&raw) {
+            Ok(text) => text.to_string(),
+            Err(err) => {
+                invalid(format!("invalid UTF-8 at byte {}", err.valid_up_to()));
+                String::from_utf8_lossy(&raw).into_owned()
+            }
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            if !raw.len().is_multiple_of(2) {
+                invalid(format!(
+                    "UTF-16 needs an even number of bytes, not {}",
+                    raw.len()
+                ));
+            }
+
+            let units = raw.chunks_exact(2).map(|pair| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let mut text = String::new();
+
+            for (index, char) in char::decode_utf16(units).enumerate() {
+                match char {
+                    Ok(char) => text.push(char),
+                    Err(err) => {
+                        invalid(format!(
+                            "unpaired UTF-16 surrogate {:#06x} at unit {}",
+                            err.unpaired_surrogate(),
+                            index
+                        ));
+                        text.push(char::REPLACEMENT_CHARACTER);
+                    }
+                }
+            }
+
+            if !raw.len().is_multiple_of(2) {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+
+            text
+        }
+        // Every byte is a Latin-1 code point, so this can't fail
+        Encoding::Latin1 => raw.iter().map(|byte| *byte as char).collect(),
+    };
+
+    string_to_pjstr(text)
+}
+
+/// An instant, shown at a fixed offset from UTC. Matches the `DateTime`
+/// class programs declare, see `apply_date_methods`.
+#[repr(C)]
+pub struct PjDateTime {
+    /// Since the Unix epoch, in UTC
+    seconds: i64,
+    nanos: i64,
+    /// Seconds east of UTC the instant is shown in
+    offset: i64,
+}
+
+/// A calendar day without a time or offset. Matches the `Date` class.
+#[repr(C)]
+pub struct PjDate {
+    /// Since 1970-01-01
+    days: i64,
+}
+
+/// Days from 0001-01-01, which chrono counts from, to 1970-01-01
+const UNIX_EPOCH_DAYS_FROM_CE: i64 = 719_163;
+
+fn to_chrono_datetime(datetime: &PjDateTime) -> DateTime<FixedOffset> {
+    let offset = FixedOffset::east_opt(datetime.offset as i32).unwrap();
+
+    match Utc.timestamp_opt(datetime.seconds, datetime.nanos as u32) {
+        LocalResult::Single(utc) => utc.with_timezone(&offset),
+        _ => date_out_of_range(),
+    }
+}
+
+fn datetime_to_pj(datetime: DateTime<FixedOffset>) -> *mut PjDateTime {
+    let pj_datetime = pj_malloc(size_of::<PjDateTime>()) as *mut PjDateTime;
+
+    unsafe {
+        pj_datetime.write(PjDateTime {
+            seconds: datetime.timestamp(),
+            nanos: datetime.timestamp_subsec_nanos() as i64,
+            offset: datetime.offset().local_minus_utc() as i64,
+        });
+    }
+
+    pj_datetime
+}
+
+fn to_chrono_date(date: &PjDate) -> NaiveDate {
+    i32::try_from(date.days + UNIX_EPOCH_DAYS_FROM_CE)
+        .ok()
+        .and_then(NaiveDate::from_num_days_from_ce_opt)
+        .unwrap_or_else(|| date_out_of_range())
+}
+
+fn date_to_pj(date: NaiveDate) -> *mut PjDate {
+    let pj_date = pj_malloc(size_of::<PjDate>()) as *mut PjDate;
+
+    unsafe {
+        pj_date.write(PjDate {
+            days: date.num_days_from_ce() as i64 - UNIX_EPOCH_DAYS_FROM_CE,
+        });
+    }
+
+    pj_date
+}
+
+/// `datetime` in the offset the system's time zone has at that instant.
+fn in_local_offset(datetime: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let local = datetime.with_timezone(&Local);
+
+    local.with_timezone(local.offset())
+}
+
+fn date_out_of_range() -> ! {
+    eprintln!("date out of range");
+    std::process::exit(1);
+}
+
+/// Formats with strftime style `%` directives, stopping the program on one
+/// chrono doesn't know.
+fn format_date_or_exit(formatted: impl std::fmt::Display, pattern: &str) -> *mut PjStr {
+    use std::fmt::Write as _;
+
+    let mut out = String::new();
+
+    if write!(out, "{}", formatted).is_err() {
+        eprintln!("invalid date format {:?}", pattern);
+        std::process::exit(1);
+    }
+
+    string_to_pjstr(out)
+}
+
+#[used]
+static EXTERNAL_FNS62: [extern "C" fn() -> *mut PjDateTime; 2] =
+    [pj_datetime_now, pj_datetime_now_utc];
+
+/// The current time, at the system's UTC offset.
+#[no_mangle]
+pub extern "C" fn pj_datetime_now() -> *mut PjDateTime {
+    datetime_to_pj(in_local_offset(now().fixed_offset()))
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_now_utc() -> *mut PjDateTime {
+    datetime_to_pj(now().fixed_offset())
+}
+
+/// The time the clock is frozen at, if it is. Tests freeze it so the times a
+/// program reads don't change between runs.
+static FROZEN_TIME: OnceLock<Mutex<Option<DateTime<Utc>>>> = OnceLock::new();
+
+/// The time `$PAJAMA_FROZEN_TIME` freezes the clock at, none when it's unset.
+pub fn parse_frozen_time(time: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let time = match time {
+        None | Some("") => return Ok(None),
+        Some(time) => time,
+    };
+
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| Some(time.with_timezone(&Utc)))
+        .map_err(|_| {
+            format!(
+                "PAJAMA_FROZEN_TIME is `{}`, expected a time like 2024-01-01T00:00:00Z",
+                time
+            )
+        })
+}
+
+fn frozen_time() -> &'static Mutex<Option<DateTime<Utc>>> {
+    FROZEN_TIME.get_or_init(|| {
+        let time = std::env::var("PAJAMA_FROZEN_TIME").ok();
+
+        Mutex::new(
+            parse_frozen_time(time.as_deref()).unwrap_or_else(|message| {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }),
+        )
+    })
+}
+
+/// The time the clock is frozen at, or the system's.
+fn now() -> DateTime<Utc> {
+    frozen_time().lock().unwrap().unwrap_or_else(Utc::now)
+}
+
+#[used]
+static EXTERNAL_FNS92: [extern "C" fn(&PjDateTime); 1] = [pj_datetime_freeze_clock];
+
+/// Stops the clock at `at`, until it's advanced or unfrozen.
+#[no_mangle]
+pub extern "C" fn pj_datetime_freeze_clock(at: &PjDateTime) {
+    *frozen_time().lock().unwrap() = Some(to_chrono_datetime(at).with_timezone(&Utc));
+}
+
+#[used]
+static EXTERNAL_FNS93: [extern "C" fn(i64); 1] = [pj_datetime_advance_clock];
+
+/// Moves the clock `seconds` ahead, freezing it at the current time first if
+/// it isn't frozen.
+#[no_mangle]
+pub extern "C" fn pj_datetime_advance_clock(seconds: i64) {
+    let mut frozen = frozen_time().lock().unwrap();
+    let time = frozen.unwrap_or_else(Utc::now);
+
+    match time.checked_add_signed(Duration::seconds(seconds)) {
+        Some(later) => *frozen = Some(later),
+        None => date_out_of_range(),
+    }
+}
+
+#[used]
+static EXTERNAL_FNS94: [extern "C" fn(); 1] = [pj_datetime_unfreeze_clock];
+
+/// Lets the clock follow the system's again.
+#[no_mangle]
+pub extern "C" fn pj_datetime_unfreeze_clock() {
+    *frozen_time().lock().unwrap() = None;
+}
+
+#[used]
+static EXTERNAL_FNS63: [extern "C" fn(&PjStr) -> *mut PjDateTime; 1] = [pj_datetime_parse];
+
+/// Parses an RFC 3339 timestamp like "2024-01-01T00:00:00Z", keeping its
+/// offset. Anything else stops the program.
+#[no_mangle]
+pub extern "C" fn pj_datetime_parse(str: &PjStr) -> *mut PjDateTime {
+    let text = pjstr_to_str(str);
+
+    match DateTime::parse_from_rfc3339(text) {
+        Ok(datetime) => datetime_to_pj(datetime),
+        Err(err) => {
+            eprintln!(
+                "can't parse {:?} as a DateTime, expected one like 2024-01-01T00:00:00Z: {}",
+                text, err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[used]
+static EXTERNAL_FNS64: [extern "C" fn(i64) -> *mut PjDateTime; 1] = [pj_datetime_from_unix];
+
+/// The instant `seconds` after the Unix epoch, in UTC.
+#[no_mangle]
+pub extern "C" fn pj_datetime_from_unix(seconds: i64) -> *mut PjDateTime {
+    match Utc.timestamp_opt(seconds, 0) {
+        LocalResult::Single(utc) => datetime_to_pj(utc.fixed_offset()),
+        _ => date_out_of_range(),
+    }
+}
+
+#[used]
+static EXTERNAL_FNS65: [extern "C" fn(&PjDateTime) -> i64; 8] = [
+    pj_datetime_to_unix,
+    pj_datetime_year,
+    pj_datetime_month,
+    pj_datetime_day,
+    pj_datetime_hour,
+    pj_datetime_minute,
+    pj_datetime_second,
+    pj_datetime_weekday,
+];
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_to_unix(datetime: &PjDateTime) -> i64 {
+    datetime.seconds
+}
+
+// Fields are read in the offset the DateTime is shown in
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_year(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).year() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_month(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).month() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_day(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).day() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_hour(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).hour() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_minute(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).minute() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_datetime_second(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).second() as i64
+}
+
+/// 1 for Monday through 7 for Sunday, as in ISO 8601.
+#[no_mangle]
+pub extern "C" fn pj_datetime_weekday(datetime: &PjDateTime) -> i64 {
+    to_chrono_datetime(datetime).weekday().number_from_monday() as i64
+}
+
+#[used]
+static EXTERNAL_FNS66: [extern "C" fn(&PjDateTime, i64) -> *mut PjDateTime; 2] =
+    [pj_datetime_add_seconds, pj_datetime_add_days];
+
+/// `seconds` later, or earlier when it's negative, keeping the offset.
+#[no_mangle]
+pub extern "C" fn pj_datetime_add_seconds(datetime: &PjDateTime, seconds: i64) -> *mut PjDateTime {
+    match to_chrono_datetime(datetime).checked_add_signed(Duration::seconds(seconds)) {
+        Some(later) => datetime_to_pj(later),
+        None => date_out_of_range(),
+    }
+}
+
+/// `days` of 24 hours later, so the wall clock time shifts when a daylight
+/// saving change falls in between.
+#[no_mangle]
+pub extern "C" fn pj_datetime_add_days(datetime: &PjDateTime, days: i64) -> *mut PjDateTime {
+    match days.checked_mul(86_400) {
+        Some(seconds) => pj_datetime_add_seconds(datetime, seconds),
+        None => date_out_of_range(),
+    }
+}
+
+#[used]
+static EXTERNAL_FNS67: [extern "C" fn(&PjDateTime, &PjDateTime) -> i64; 1] =
+    [pj_datetime_seconds_until];
+
+/// Whole seconds from `datetime` to `other`, negative when `other` is
+/// earlier.
+#[no_mangle]
+pub extern "C" fn pj_datetime_seconds_until(datetime: &PjDateTime, other: &PjDateTime) -> i64 {
+    (to_chrono_datetime(other) - to_chrono_datetime(datetime)).num_seconds()
+}
+
+#[used]
+static EXTERNAL_FNS68: [extern "C" fn(&PjDateTime) -> *mut PjDateTime; 2] =
+    [pj_datetime_to_utc, pj_datetime_to_local];
+
+/// The same instant shown in UTC.
+#[no_mangle]
+pub extern "C" fn pj_datetime_to_utc(datetime: &PjDateTime) -> *mut PjDateTime {
+    let utc = to_chrono_datetime(datetime).with_timezone(&Utc);
+
+    datetime_to_pj(utc.fixed_offset())
+}
+
+/// The same instant shown at the system's UTC offset.
+#[no_mangle]
+pub extern "C" fn pj_datetime_to_local(datetime: &PjDateTime) -> *mut PjDateTime {
+    datetime_to_pj(in_local_offset(to_chrono_datetime(datetime)))
+}
+
+#[used]
+static EXTERNAL_FNS69: [extern "C" fn(&PjDateTime) -> *mut PjDate; 1] = [pj_datetime_to_date];
+
+/// The day `datetime` falls on in its offset.
+#[no_mangle]
+pub extern "C" fn pj_datetime_to_date(datetime: &PjDateTime) -> *mut PjDate {
+    date_to_pj(to_chrono_datetime(datetime).date_naive())
+}
+
+#[used]
+static EXTERNAL_FNS70: [extern "C" fn(&PjDateTime, &PjStr) -> *mut PjStr; 1] = [pj_datetime_format];
+
+/// Formats with strftime directives like "%Y-%m-%d %H:%M".
+#[no_mangle]
+pub extern "C" fn pj_datetime_format(datetime: &PjDateTime, pattern: &PjStr) -> *mut PjStr {
+    let pattern = pjstr_to_str(pattern);
+
+    format_date_or_exit(to_chrono_datetime(datetime).format(pattern), pattern)
+}
+
+#[used]
+static EXTERNAL_FNS71: [extern "C" fn(&PjDateTime) -> *mut PjStr; 1] = [pj_datetime_iso8601];
+
+/// RFC 3339, with "Z" for UTC and fractional seconds only when there are any.
+#[no_mangle]
+pub extern "C" fn pj_datetime_iso8601(datetime: &PjDateTime) -> *mut PjStr {
+    string_to_pjstr(to_chrono_datetime(datetime).to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+#[used]
+static EXTERNAL_FNS72: [extern "C" fn() -> *mut PjDate; 1] = [pj_date_today];
+
+/// Today, in the system's time zone.
+#[no_mangle]
+pub extern "C" fn pj_date_today() -> *mut PjDate {
+    date_to_pj(in_local_offset(now().fixed_offset()).date_naive())
+}
+
+#[used]
+static EXTERNAL_FNS73: [extern "C" fn(&PjStr) -> *mut PjDate; 1] = [pj_date_parse];
+
+/// Parses a date like "2024-01-01". Anything else stops the program.
+#[no_mangle]
+pub extern "C" fn pj_date_parse(str: &PjStr) -> *mut PjDate {
+    let text = pjstr_to_str(str);
+
+    match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        Ok(date) => date_to_pj(date),
+        Err(err) => {
+            eprintln!(
+                "can't parse {:?} as a Date, expected one like 2024-01-01: {}",
+                text, err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[used]
+static EXTERNAL_FNS74: [extern "C" fn(&PjDate) -> i64; 4] =
+    [pj_date_year, pj_date_month, pj_date_day, pj_date_weekday];
+
+#[no_mangle]
+pub extern "C" fn pj_date_year(date: &PjDate) -> i64 {
+    to_chrono_date(date).year() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_date_month(date: &PjDate) -> i64 {
+    to_chrono_date(date).month() as i64
+}
+
+#[no_mangle]
+pub extern "C" fn pj_date_day(date: &PjDate) -> i64 {
+    to_chrono_date(date).day() as i64
+}
+
+/// 1 for Monday through 7 for Sunday.
+#[no_mangle]
+pub extern "C" fn pj_date_weekday(date: &PjDate) -> i64 {
+    to_chrono_date(date).weekday().number_from_monday() as i64
+}
+
+#[used]
+static EXTERNAL_FNS75: [extern "C" fn(&PjDate, i64) -> *mut PjDate; 1] = [pj_date_add_days];
+
+#[no_mangle]
+pub extern "C" fn pj_date_add_days(date: &PjDate, days: i64) -> *mut PjDate {
+    match date.days.checked_add(days) {
+        Some(days) => date_to_pj(to_chrono_date(&PjDate { days })),
+        None => date_out_of_range(),
+    }
+}
+
+#[used]
+static EXTERNAL_FNS76: [extern "C" fn(&PjDate, &PjDate) -> i64; 1] = [pj_date_days_until];
+
+/// Days from `date` to `other`, negative when `other` is earlier.
+#[no_mangle]
+pub extern "C" fn pj_date_days_until(date: &PjDate, other: &PjDate) -> i64 {
+    other.days - date.days
+}
+
+#[used]
+static EXTERNAL_FNS77: [extern "C" fn(&PjDate, &PjStr) -> *mut PjStr; 1] = [pj_date_format];
+
+#[no_mangle]
+pub extern "C" fn pj_date_format(date: &PjDate, pattern: &PjStr) -> *mut PjStr {
+    let pattern = pjstr_to_str(pattern);
+
+    format_date_or_exit(to_chrono_date(date).format(pattern), pattern)
+}
+
+#[used]
+static EXTERNAL_FNS78: [extern "C" fn(&PjDate) -> *mut PjStr; 1] = [pj_date_iso8601];
+
+#[no_mangle]
+pub extern "C" fn pj_date_iso8601(date: &PjDate) -> *mut PjStr {
+    string_to_pjstr(to_chrono_date(date).format("%Y-%m-%d").to_string())
+}
+
+fn path_to_pjstr(path: &Path) -> *mut PjStr {
+    string_to_pjstr(path.to_string_lossy().into_owned())
+}
+
+#[used]
+static EXTERNAL_FNS79: [extern "C" fn(&PjStr, &PjStr) -> *mut PjStr; 1] = [pj_path_join];
+
+/// `path` followed by `other`, or just `other` when it's absolute.
+#[no_mangle]
+pub extern "C" fn pj_path_join(path: &PjStr, other: &PjStr) -> *mut PjStr {
+    path_to_pjstr(&Path::new(pjstr_to_str(path)).join(pjstr_to_str(other)))
+}
+
+#[used]
+static EXTERNAL_FNS80: [extern "C" fn(&PjStr) -> *mut PjStr; 4] = [
+    pj_path_basename,
+    pj_path_dirname,
+    pj_path_extension,
+    pj_path_absolute,
+];
+
+/// The last component of `path`, "" when it ends in "..".
+#[no_mangle]
+pub extern "C" fn pj_path_basename(path: &PjStr) -> *mut PjStr {
+    match Path::new(pjstr_to_str(path)).file_name() {
+        Some(name) => path_to_pjstr(Path::new(name)),
+        None => string_to_pjstr(String::new()),
+    }
+}
+
+/// Everything but the last component of `path`, "." for a relative path of
+/// one component.
+#[no_mangle]
+pub extern "C" fn pj_path_dirname(path: &PjStr) -> *mut PjStr {
+    let path = Path::new(pjstr_to_str(path));
+
+    match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => string_to_pjstr(".".to_string()),
+        Some(parent) => path_to_pjstr(parent),
+        // The root is its own parent
+        None => path_to_pjstr(path),
+    }
+}
+
+/// The extension of `path` without its dot, "" when it has none.
+#[no_mangle]
+pub extern "C" fn pj_path_extension(path: &PjStr) -> *mut PjStr {
+    match Path::new(pjstr_to_str(path)).extension() {
+        Some(extension) => path_to_pjstr(Path::new(extension)),
+        None => string_to_pjstr(String::new()),
+    }
+}
+
+/// `path` from the root, resolved against the working directory with a
+/// leading "~" expanded to `$HOME`. "." and ".." are removed without looking
+/// at the filesystem, so symlinks aren't followed.
+#[no_mangle]
+pub extern "C" fn pj_path_absolute(path: &PjStr) -> *mut PjStr {
+    let text = pjstr_to_str(path);
+
+    let expanded = match text.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest.trim_start_matches('/')),
+            None => PathBuf::from(text),
+        },
+        _ => PathBuf::from(text),
+    };
+
+    let joined = match std::env::current_dir() {
+        Ok(working_dir) => working_dir.join(expanded),
+        Err(err) => {
+            eprintln!("can't make {:?} absolute: {}", text, err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut absolute = PathBuf::new();
+
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+
+    path_to_pjstr(&absolute)
+}
+
+#[used]
+static EXTERNAL_FNS81: [extern "C" fn(&PjStr) -> *mut PjArray; 1] = [pj_path_glob];
+
+/// The paths matching a shell style `pattern` like "logs/**/*.log", in
+/// alphabetical order. Paths that can't be read are skipped, an invalid
+/// pattern stops the program.
+#[no_mangle]
+pub extern "C" fn pj_path_glob(pattern: &PjStr) -> *mut PjArray {
+    let pattern = pjstr_to_str(pattern);
+
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(err) => {
+            eprintln!("invalid glob pattern {:?}: {}", pattern, err);
+            std::process::exit(1);
+        }
+    };
+
+    let matches = pj_array_new(0);
+
+    for path in paths.flatten() {
+        let path_str = path_to_pjstr(&path) as *mut c_void;
+        pj_array_push_ptr(unsafe { &mut *matches }, path_str);
+    }
+
+    matches
+}
+
+#[used]
+static EXTERNAL_FNS82: [extern "C" fn(&PjStr) -> *mut PjArray; 1] = [pj_csv_parse];
+
+/// The rows of CSV `text`, each an array of its fields. Quoted fields can
+/// hold commas, newlines and `""` for a quote. Rows can have different
+/// lengths and blank lines are skipped.
+#[no_mangle]
+pub extern "C" fn pj_csv_parse(text: &PjStr) -> *mut PjArray {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(pjstr_to_str(text).as_bytes());
+
+    let rows = pj_array_new(0);
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                eprintln!("invalid CSV: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        let row = pj_array_new(record.len() as i64);
+
+        for field in record.iter() {
+            let field = string_to_pjstr(field.to_string()) as *mut c_void;
+            pj_array_push_ptr(unsafe { &mut *row }, field);
+        }
+
+        pj_array_push_ptr(unsafe { &mut *rows }, row as *mut c_void);
+    }
+
+    rows
+}
+
+#[used]
+static EXTERNAL_FNS83: [extern "C" fn(&PjArray) -> *mut PjStr; 1] = [pj_csv_write];
+
+/// `rows` of Strs as CSV text, each row ending in a newline. Fields with a
+/// comma, quote or newline are quoted.
+#[no_mangle]
+pub extern "C" fn pj_csv_write(rows: &PjArray) -> *mut PjStr {
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+
+    for row in &rows.slots {
+        let row = unsafe { &*(*row as *const PjArray) };
+        let fields = row
+            .slots
+            .iter()
+            .map(|field| pjstr_to_str(unsafe { &*(*field as *const PjStr) }));
+
+        // Writing to memory can't fail
+        writer.write_record(fields).unwrap();
+    }
+
+    let text = writer.into_inner().unwrap();
+
+    // Every field was a Str, so the fields and the quoting are valid UTF-8
+    string_to_pjstr(String::from_utf8(text).unwrap())
+}
+
+/// Levels `Log` writes at, least severe first
+pub const LOG_LEVELS: [&str; 3] = ["info", "warn", "error"];
+
+static LOG_THRESHOLD: OnceLock<usize> = OnceLock::new();
+
+/// The least severe level `$PAJAMA_LOG_LEVEL` lets through, info when it's
+/// unset.
+pub fn parse_log_level(level: Option<&str>) -> Result<usize, String> {
+    let level = match level {
+        None | Some("") => return Ok(0),
+        Some(level) => level,
+    };
+
+    LOG_LEVELS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(level))
+        .ok_or_else(|| {
+            format!(
+                "PAJAMA_LOG_LEVEL is `{}`, expected info, warn or error",
+                level
+            )
+        })
+}
+
+/// A line like `2024-05-01T09:30:00.250+02:00 WARN  disk almost full`
+pub fn log_line(level: usize, message: &str, time: DateTime<FixedOffset>) -> String {
+    format!(
+        "{} {:<5} {}",
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        LOG_LEVELS[level].to_uppercase(),
+        message
+    )
+}
+
+fn log(level: usize, message: &PjStr) {
+    let threshold = *LOG_THRESHOLD.get_or_init(|| {
+        let level = std::env::var("PAJAMA_LOG_LEVEL").ok();
+
+        parse_log_level(level.as_deref()).unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        })
+    });
+
+    if level < threshold {
+        return;
+    }
+
+    let time = in_local_offset(now().fixed_offset());
+
+    eprintln!("{}", log_line(level, pjstr_to_str(message), time));
+}
+
+#[used]
+static EXTERNAL_FNS88: [extern "C" fn(&PjStr); 3] = [pj_log_info, pj_log_warn, pj_log_error];
+
+/// Writes `message` to stderr with the time and level, if
+/// `$PAJAMA_LOG_LEVEL` lets the level through.
+#[no_mangle]
+pub extern "C" fn pj_log_info(message: &PjStr) {
+    log(0, message);
+}
+
+#[no_mangle]
+pub extern "C" fn pj_log_warn(message: &PjStr) {
+    log(1, message);
+}
+
+#[no_mangle]
+pub extern "C" fn pj_log_error(message: &PjStr) {
+    log(2, message);
+}
+
+#[used]
+static EXTERNAL_FNS32: [extern "C" fn(i64, i64) -> i64; 4] =
+    [pj_checked_add, pj_checked_sub, pj_checked_mul, pj_saturating_mul];
+
+// `--strict` lowers Int `+`, `-` and `*` to these
+fn checked_or_exit(result: Option<i64>, operator: &str) -> i64 {
+    match result {
+        Some(int) => int,
+        None => {
+            eprintln!("Int {} overflowed", operator);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pj_checked_add(left: i64, right: i64) -> i64 {
+    checked_or_exit(left.checked_add(right), "+")
+}
+
+#[no_mangle]
+pub extern "C" fn pj_checked_sub(left: i64, right: i64) -> i64 {
+    checked_or_exit(left.checked_sub(right), "-")
+}
+
+#[no_mangle]
+pub extern "C" fn pj_checked_mul(left: i64, right: i64) -> i64 {
+    checked_or_exit(left.checked_mul(right), "*")
+}
+
+#[no_mangle]
+pub extern "C" fn pj_saturating_mul(left: i64, right: i64) -> i64 {
+    left.saturating_mul(right)
+}
+
+#[used]
+static EXTERNAL_FNS95: [extern "C" fn(i64, i64) -> bool; 3] =
+    [pj_add_overflows, pj_sub_overflows, pj_mul_overflows];
+
+// `a.add_overflows(b)` and the others, see `apply_overflow_arithmetic`
+
+#[no_mangle]
+pub extern "C" fn pj_add_overflows(left: i64, right: i64) -> bool {
+    left.overflowing_add(right).1
+}
+
+#[no_mangle]
+pub extern "C" fn pj_sub_overflows(left: i64, right: i64) -> bool {
+    left.overflowing_sub(right).1
+}
+
+#[no_mangle]
+pub extern "C" fn pj_mul_overflows(left: i64, right: i64) -> bool {
+    left.overflowing_mul(right).1
+}
+
+/// A growable array. Every item takes an 8 byte slot whatever its type, so
+/// the same functions serve arrays of any item type. Arrays are never freed
+/// yet, like strings.
+#[repr(C)]
+pub struct PjArray {
+    slots: Vec<u64>,
+}
+
+impl PjArray {
+    fn slot(&self, index: i64) -> usize {
+        if index < 0 || index >= self.slots.len() as i64 {
+            eprintln!(
+                "index {} is out of bounds for an array of length {}",
+                index,
+                self.slots.len()
+            );
+            std::process::exit(1);
+        }
+
+        index as usize
+    }
+
+    /// Adds a slot, doubling the capacity when the array is full, like `Vec`
+    /// does but counting what the growth allocates first.
+    fn push(&mut self, slot: u64) -> &mut PjArray {
+        let capacity = self.slots.capacity();
+
+        if self.slots.len() == capacity {
+            let grown = (capacity * 2).max(4);
+
+            count_allocation((grown - capacity) * size_of::<u64>());
+            self.slots.reserve_exact(grown - capacity);
+        }
+
+        self.slots.push(slot);
+        self
+    }
+}
+
+#[used]
+static EXTERNAL_FNS43: [extern "C" fn(i64) -> *mut PjArray; 1] = [pj_array_new];
+
+/// An empty array with room for `capacity` items before it grows.
+#[no_mangle]
+pub extern "C" fn pj_array_new(capacity: i64) -> *mut PjArray {
+    let capacity = capacity.max(0) as usize;
+
+    count_allocation(size_of::<PjArray>() + capacity * size_of::<u64>());
+
+    Box::into_raw(Box::new(PjArray {
+        slots: Vec::with_capacity(capacity),
+    }))
+}
+
+#[used]
+static EXTERNAL_FNS44: [extern "C" fn(&PjArray) -> i64; 1] = [pj_array_length];
+
+#[no_mangle]
+pub extern "C" fn pj_array_length(array: &PjArray) -> i64 {
+    array.slots.len() as i64
+}
+
+// Pushing returns the array, so literals are built by chaining pushes
+
+#[used]
+static EXTERNAL_FNS45: [extern "C" fn(&mut PjArray, i64) -> &mut PjArray; 1] =
+    [pj_array_push_int];
+
+#[no_mangle]
+pub extern "C" fn pj_array_push_int(array: &mut PjArray, item: i64) -> &mut PjArray {
+    array.push(item as u64)
+}
+
+#[used]
+static EXTERNAL_FNS46: [extern "C" fn(&mut PjArray, f64) -> &mut PjArray; 1] =
+    [pj_array_push_float];
+
+#[no_mangle]
+pub extern "C" fn pj_array_push_float(array: &mut PjArray, item: f64) -> &mut PjArray {
+    array.push(item.to_bits())
+}
+
+#[used]
+static EXTERNAL_FNS47: [extern "C" fn(&mut PjArray, bool) -> &mut PjArray; 1] =
+    [pj_array_push_bool];
+
+#[no_mangle]
+pub extern "C" fn pj_array_push_bool(array: &mut PjArray, item: bool) -> &mut PjArray {
+    array.push(item as u64)
+}
+
+#[used]
+static EXTERNAL_FNS48: [extern "C" fn(&mut PjArray, *mut c_void) -> &mut PjArray; 1] =
+    [pj_array_push_ptr];
+
+/// Instances, strings and nested arrays are held by pointer.
+#[no_mangle]
+pub extern "C" fn pj_array_push_ptr(array: &mut PjArray, item: *mut c_void) -> &mut PjArray {
+    array.push(item as u64)
+}
+
+// Indexing outside of the array stops the program
+
+#[used]
+static EXTERNAL_FNS49: [extern "C" fn(&PjArray, i64) -> i64; 1] = [pj_array_get_int];
+
+#[no_mangle]
+pub extern "C" fn pj_array_get_int(array: &PjArray, index: i64) -> i64 {
+    array.slots[array.slot(index)] as i64
+}
+
+#[used]
+static EXTERNAL_FNS50: [extern "C" fn(&PjArray, i64) -> f64; 1] = [pj_array_get_float];
+
+#[no_mangle]
+pub extern "C" fn pj_array_get_float(array: &PjArray, index: i64) -> f64 {
+    f64::from_bits(array.slots[array.slot(index)])
+}
+
+#[used]
+static EXTERNAL_FNS51: [extern "C" fn(&PjArray, i64) -> bool; 1] = [pj_array_get_bool];
+
+#[no_mangle]
+pub extern "C" fn pj_array_get_bool(array: &PjArray, index: i64) -> bool {
+    array.slots[array.slot(index)] != 0
+}
+
+#[used]
+static EXTERNAL_FNS52: [extern "C" fn(&PjArray, i64) -> *mut c_void; 1] = [pj_array_get_ptr];
+
+#[no_mangle]
+pub extern "C" fn pj_array_get_ptr(array: &PjArray, index: i64) -> *mut c_void {
+    array.slots[array.slot(index)] as *mut c_void
+}
+
+#[used]
+static EXTERNAL_FNS53: [extern "C" fn(&mut PjArray, i64, i64); 1] = [pj_array_set_int];
+
+#[no_mangle]
+pub extern "C" fn pj_array_set_int(array: &mut PjArray, index: i64, item: i64) {
+    let slot = array.slot(index);
+    array.slots[slot] = item as u64;
+}
+
+#[used]
+static EXTERNAL_FNS54: [extern "C" fn(&mut PjArray, i64, f64); 1] = [pj_array_set_float];
+
+#[no_mangle]
+pub extern "C" fn pj_array_set_float(array: &mut PjArray, index: i64, item: f64) {
+    let slot = array.slot(index);
+    array.slots[slot] = item.to_bits();
+}
+
+#[used]
+static EXTERNAL_FNS55: [extern "C" fn(&mut PjArray, i64, bool); 1] = [pj_array_set_bool];
+
+#[no_mangle]
+pub extern "C" fn pj_array_set_bool(array: &mut PjArray, index: i64, item: bool) {
+    let slot = array.slot(index);
+    array.slots[slot] = item as u64;
+}
+
+#[used]
+static EXTERNAL_FNS56: [extern "C" fn(&mut PjArray, i64, *mut c_void); 1] = [pj_array_set_ptr];
+
+#[no_mangle]
+pub extern "C" fn pj_array_set_ptr(array: &mut PjArray, index: i64, item: *mut c_void) {
+    let slot = array.slot(index);
+    array.slots[slot] = item as u64;
+}
+
+type PjCompareFn = extern "C" fn(*mut c_void, *mut c_void) -> i64;
+
+/// The items of an array of instances.
+fn pj_items(array: &mut PjArray) -> &mut [*mut c_void] {
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            array.slots.as_mut_ptr() as *mut *mut c_void,
+            array.slots.len(),
+        )
+    }
+}
+
+#[used]
+static EXTERNAL_FNS24: [extern "C" fn(&mut PjArray, PjCompareFn); 1] = [pj_sort];
+
+#[no_mangle]
+pub extern "C" fn pj_sort(array: &mut PjArray, compare: PjCompareFn) {
+    pj_items(array).sort_by(|left, right| compare(*left, *right).cmp(&0));
+}
+
+#[used]
+static EXTERNAL_FNS25: [extern "C" fn(&mut PjArray, PjCompareFn) -> *mut c_void; 2] =
+    [pj_min, pj_max];
+
+#[no_mangle]
+pub extern "C" fn pj_min(array: &mut PjArray, compare: PjCompareFn) -> *mut c_void {
+    let min = pj_items(array)
+        .iter()
+        .copied()
+        .min_by(|left, right| compare(*left, *right).cmp(&0));
+
+    min.unwrap_or_else(|| empty_array_has_no("min"))
+}
+
+#[no_mangle]
+pub extern "C" fn pj_max(array: &mut PjArray, compare: PjCompareFn) -> *mut c_void {
+    let max = pj_items(array)
+        .iter()
+        .copied()
+        .max_by(|left, right| compare(*left, *right).cmp(&0));
+
+    max.unwrap_or_else(|| empty_array_has_no("max"))
+}
+
+/// Stops the program, since the compiled code would read an instance
+/// through the null pointer that's all there is to return.
+fn empty_array_has_no(name: &str) -> ! {
+    eprintln!("an empty array has no {}", name);
+    std::process::exit(1);
+}
+
+type PjKeyFn = extern "C" fn(*mut c_void) -> i64;
+
+#[used]
+static EXTERNAL_FNS26: [extern "C" fn(&mut PjArray, PjKeyFn); 1] = [pj_sort_by];
+
+#[no_mangle]
+pub extern "C" fn pj_sort_by(array: &mut PjArray, key: PjKeyFn) {
+    pj_items(array).sort_by_cached_key(|item| key(*item));
+}
+
+#[used]
+static EXTERNAL_FNS27: [extern "C" fn(&mut PjArray, *mut c_void, PjCompareFn) -> i64; 2] =
+    [pj_binary_search, pj_index_of];
+
+#[no_mangle]
+pub extern "C" fn pj_binary_search(
+    array: &mut PjArray,
+    item: *mut c_void,
+    compare: PjCompareFn,
+) -> i64 {
+    match pj_items(array).binary_search_by(|probe| compare(*probe, item).cmp(&0)) {
+        Ok(index) => index as i64,
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pj_index_of(array: &mut PjArray, item: *mut c_void, compare: PjCompareFn) -> i64 {
+    match pj_items(array)
+        .iter()
+        .position(|probe| compare(*probe, item) == 0)
+    {
+        Some(index) => index as i64,
+        None => -1,
+    }
+}
+
+// Addresses of frozen instances, see `freeze` in the semantic analyzer
+static FROZEN: Mutex<Option<HashSet<usize>>> = Mutex::new(None);
+
+#[used]
+static EXTERNAL_FNS28: [extern "C" fn(*mut c_void); 2] = [pj_freeze, pj_unfreeze];
+
+#[no_mangle]
+pub extern "C" fn pj_freeze(object: *mut c_void) {
+    FROZEN
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashSet::new)
+        .insert(object as usize);
+}
+
+#[no_mangle]
+pub extern "C" fn pj_unfreeze(object: *mut c_void) {
+    if let Some(frozen) = FROZEN.lock().unwrap().as_mut() {
+        frozen.remove(&(object as usize));
+    }
+}
+
+#[used]
+static EXTERNAL_FNS29: [extern "C" fn(*mut c_void, &PjStr); 1] = [pj_check_frozen];
+
+#[no_mangle]
+pub extern "C" fn pj_check_frozen(object: *mut c_void, site: &PjStr) {
+    let frozen = match FROZEN.lock().unwrap().as_ref() {
+        Some(frozen) => frozen.contains(&(object as usize)),
+        None => false,
+    };
+
+    if frozen {
+        eprintln!("can't modify frozen object: {}", pjstr_to_str(site));
+        std::process::exit(1);
+    }
+}
+
+#[used]
+static EXTERNAL_FNS33: [extern "C" fn(&PjStr) -> i64; 1] = [pj_sandbox_denied];
+
+/// Stands in for the functions `--sandbox` takes away, see `apply_sandbox`.
+#[no_mangle]
+pub extern "C" fn pj_sandbox_denied(name: &PjStr) -> i64 {
+    eprintln!("sandbox: `{}` isn't allowed", pjstr_to_str(name));
+    std::process::exit(1);
+}
+
+type PjSignalHandler = extern "C" fn();
+
+// Signals a program can trap, see `Signal.trap` in the semantic analyzer
+const SIGNALS: [(&str, libc::c_int); 6] = [
+    ("INT", libc::SIGINT),
+    ("TERM", libc::SIGTERM),
+    ("HUP", libc::SIGHUP),
+    ("QUIT", libc::SIGQUIT),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+];
+
+static SIGNAL_HANDLERS: Mutex<Option<HashMap<libc::c_int, PjSignalHandler>>> = Mutex::new(None);
+
+// Write end of the pipe the dispatcher thread reads signal numbers from
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+#[used]
+static EXTERNAL_FNS34: [extern "C" fn(&PjStr, PjSignalHandler); 1] = [pj_signal_trap];
+
+/// Runs `handler` each time the signal named `name` arrives, replacing the
+/// handler trapped before it.
+///
+/// Handlers can't run inside the signal handler itself, so it only writes the
+/// signal number to a pipe and a dispatcher thread calls the handler.
+#[no_mangle]
+pub extern "C" fn pj_signal_trap(name: &PjStr, handler: PjSignalHandler) {
+    let name = pjstr_to_str(name);
+    let signal = match SIGNALS.iter().find(|(signal_name, _)| *signal_name == name) {
+        Some((_, signal)) => *signal,
+        None => {
+            eprintln!("can't trap unknown signal: {}", name);
+            std::process::exit(1);
+        }
+    };
+
+    let mut handlers = SIGNAL_HANDLERS.lock().unwrap();
+
+    if handlers.is_none() {
+        start_signal_dispatcher();
+    }
+
+    handlers
+        .get_or_insert_with(HashMap::new)
+        .insert(signal, handler);
+
+    unsafe {
+        libc::signal(signal, forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let byte = signal as u8;
+
+    // write is async-signal-safe, unlike anything that locks
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::Relaxed),
+            &byte as *const u8 as *const c_void,
+            1,
+        );
+    }
+}
+
+fn start_signal_dispatcher() {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        eprintln!("can't trap signals: {}", io::Error::last_os_error());
+        std::process::exit(1);
+    }
+
+    let [read_fd, write_fd] = fds;
+    SIGNAL_PIPE.store(write_fd, Ordering::Relaxed);
+
+    std::thread::spawn(move || loop {
+        let mut byte = 0u8;
+        let read = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut c_void, 1) };
+
+        if read != 1 {
+            if interrupted(&io::Error::last_os_error()) {
+                continue;
+            }
+
+            return;
+        }
+
+        let handler = SIGNAL_HANDLERS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|handlers| handlers.get(&(byte as libc::c_int)).copied());
+
+        if let Some(handler) = handler {
+            handler();
+        }
+    });
+}
+
+// Handlers registered with `at_exit`, `None` until `atexit` runs them
+static EXIT_HANDLERS: Mutex<Option<Vec<PjSignalHandler>>> = Mutex::new(None);
+
+#[used]
+static EXTERNAL_FNS89: [extern "C" fn(PjSignalHandler); 1] = [pj_at_exit];
+
+/// Runs `handler` once the program ends, after the handlers registered after
+/// it. Runtime errors end the program with `exit`, so they run the handlers
+/// too.
+#[no_mangle]
+pub extern "C" fn pj_at_exit(handler: PjSignalHandler) {
+    let mut handlers = EXIT_HANDLERS.lock().unwrap();
+
+    if handlers.is_none() {
+        unsafe {
+            libc::atexit(run_exit_handlers_at_exit);
+        }
+    }
+
+    handlers.get_or_insert_with(Vec::new).push(handler);
+}
+
+/// Runs the `at_exit` handlers that haven't run yet, latest first. The JIT
+/// runs them as `main` returns, while the code they're in is still there.
+pub fn run_exit_handlers() {
+    loop {
+        // Unlocked while the handler runs, it may register another one
+        let handler = EXIT_HANDLERS
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|handlers| handlers.pop());
+
+        match handler {
+            Some(handler) => handler(),
+            None => return,
+        }
+    }
+}
+
+extern "C" fn run_exit_handlers_at_exit() {
+    run_exit_handlers();
+}
+
 fn would_block(err: &io::Error) -> bool {
     err.kind() == io::ErrorKind::WouldBlock
 }
@@ -0,0 +1,56 @@
+use std::thread;
+
+/// Maps `items` with `f` on up to one thread per CPU, keeping their order.
+///
+/// Each thread takes a contiguous chunk, so this suits many similar sized
+/// items like the files of a program.
+pub fn par_map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let workers = thread::available_parallelism().map_or(1, |workers| workers.get());
+
+    par_map_with(workers, items, f)
+}
+
+/// `par_map` with a fixed number of threads, 1 runs on the calling thread.
+pub fn par_map_with<T, R, F>(workers: usize, items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    if workers <= 1 || items.len() <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(workers);
+    let mut chunks = vec![];
+    let mut items = items.into_iter();
+
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        chunks.push(chunk);
+    }
+
+    let f = &f;
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || chunk.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
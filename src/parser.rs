@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs::OpenOptions,
     hash::Hash,
@@ -7,9 +8,12 @@ use std::{
 
 use melior::ir::attribute;
 
-use crate::lexer::Token;
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Lexer, Token, TokenKind, TokenPosition};
+use crate::operators::OPERATORS;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Access {
     pub receiver: Box<Node>,
     pub message: Box<Node>,
@@ -17,83 +21,120 @@ pub struct Access {
     pub return_type: Option<BaseType>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Array {
     pub items: Vec<Node>,
     pub item_type: BaseType,
-    pub length: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Attribute {
     pub name: String,
     pub index: i32,
     pub return_type: BaseType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignAttribute {
     pub name: String,
     pub index: i32,
     pub value: Box<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignAttributeAccess {
     pub access: Access,
     pub value: Box<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignLocalVar {
     pub name: String,
     pub value: Box<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignConstant {
     pub name: String,
     pub value: Box<Node>,
     pub return_type: BaseType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Binary {
-    pub op: char,
+    pub op: String,
     pub left: Box<Node>,
     pub right: Box<Node>,
+    pub return_type: Option<BaseType>,
 }
 
-#[derive(Debug)]
+impl Binary {
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`, which compare their operands and
+    /// return a Bool
+    pub fn is_comparison(&self) -> bool {
+        matches!(self.op.as_str(), "==" | "!=" | "<" | "<=" | ">" | ">=")
+    }
+
+    /// `&&` or `||`, which only evaluate their right operand when the left
+    /// one doesn't decide the result
+    pub fn is_logical(&self) -> bool {
+        matches!(self.op.as_str(), "&&" | "||")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Call {
     pub fn_name: String,
     pub args: Vec<Node>,
     pub return_type: Option<BaseType>,
+    /// The names of the last `arg_names.len()` args, given by keyword as in
+    /// `greet(name: "Rex")`. Analysis puts them in order and clears this.
+    pub arg_names: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Send {
     pub receiver: Box<Node>,
     pub message: Box<Node>,
     pub return_type: Option<BaseType>,
+    /// Sent with `&.`, as in `name&.upcase()`, which gives nil instead of
+    /// sending the message when the receiver is nil
+    pub safe_navigation: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnRef {
     pub fn_name: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct Bool {
+    pub value: bool,
+}
+
+/// `nil`, typed by analysis as the optional type it's given as, e.g. `Str?`
+/// when it's passed for an argument declared as one.
+#[derive(Debug, Clone)]
+pub struct Nil {
+    pub return_type: Option<BaseType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Float {
+    pub value: f64,
+}
+
+#[derive(Debug, Clone)]
 pub struct Int {
     pub value: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StringLiteral {
     pub value: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LocalVar {
     pub name: String,
     pub return_type: Option<BaseType>,
@@ -103,10 +144,12 @@ impl LocalVar {
     pub fn pajama_class_name(&self) -> &str {
         match &self.return_type {
             Some(rt) => match rt {
-                BaseType::Array(_, _) => "Array",
+                BaseType::Array(_) => "Array",
+                BaseType::Bool => "Bool",
                 BaseType::Byte => "Byte",
                 BaseType::BytePtr => "BytePtr",
                 BaseType::Class(class_name) => class_name.as_str(),
+                BaseType::Float => "Float",
                 BaseType::FnRef => "FnRef",
                 BaseType::Int => "Int",
                 BaseType::Int16 => "Int16",
@@ -114,65 +157,80 @@ impl LocalVar {
                 BaseType::Int64 => "Int64",
                 BaseType::Void => "",
                 BaseType::Struct(_) => "Struct",
+                // Sends to an optional are resolved by the class it wraps
+                BaseType::Optional(base_type) => match base_type.as_ref() {
+                    BaseType::Class(class_name) => class_name.as_str(),
+                    _ => "",
+                },
+                BaseType::Nil => "Nil",
             },
             None => "",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Module {
     pub methods: Vec<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Class {
     pub name: String,
     pub attributes: Vec<Attribute>,
+    pub superclass: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Struct {
     pub name: String,
     pub attributes: Vec<Attribute>,
     pub return_type: BaseType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BuildStruct {
     pub name: String,
     pub args: Vec<Node>,
     pub return_type: BaseType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Trait {
     pub name: String,
     pub body: Vec<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Impl {
     pub name: String,
     pub body: Vec<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SelfRef {
     pub return_type: BaseType,
 }
 
-#[derive(Debug)]
+/// Stands in for an item or a statement that couldn't be parsed, so the rest
+/// of the file is still parsed around it, see `Parser::parse`.
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+#[derive(Debug, Clone)]
 pub struct Ret {
     pub value: Box<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Const {
     pub name: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Node {
     Access(Access),
     Array(Array),
@@ -182,29 +240,80 @@ pub enum Node {
     AssignLocalVar(AssignLocalVar),
     Attribute(Attribute),
     Binary(Binary),
+    Bool(Bool),
+    Break,
     BuildStruct(BuildStruct),
     Call(Call),
     Class(Class),
     Const(Const),
     Def(Def),
     DefE(DefE),
+    Error(Error),
+    Float(Float),
     FnRef(FnRef),
+    If(If),
     Impl(Impl),
     Int(Int),
     LocalVar(LocalVar),
     Loop(Loop),
     Module(Module),
+    Next,
+    Nil(Nil),
     Ret(Ret),
     SelfRef(SelfRef),
     Send(Send),
     StringLiteral(StringLiteral),
     Struct(Struct),
     Trait(Trait),
+    While(While),
+}
+
+impl Node {
+    /// What kind of node this is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Node::Access(_) => "attribute access",
+            Node::Array(_) => "array",
+            Node::AssignAttribute(_) => "attribute assignment",
+            Node::AssignAttributeAccess(_) => "attribute assignment",
+            Node::AssignConstant(_) => "constant",
+            Node::AssignLocalVar(_) => "assignment",
+            Node::Attribute(_) => "attribute",
+            Node::Binary(_) => "binary",
+            Node::Bool(_) => "boolean",
+            Node::Break => "break",
+            Node::BuildStruct(_) => "struct",
+            Node::Call(_) => "call",
+            Node::Class(_) => "class",
+            Node::Const(_) => "constant",
+            Node::Def(_) => "def",
+            Node::DefE(_) => "def_e",
+            Node::Error(_) => "syntax error",
+            Node::Float(_) => "float",
+            Node::FnRef(_) => "function reference",
+            Node::If(_) => "if",
+            Node::Impl(_) => "impl",
+            Node::Int(_) => "integer",
+            Node::LocalVar(_) => "local variable",
+            Node::Loop(_) => "loop",
+            Node::Module(_) => "module",
+            Node::Next => "next",
+            Node::Nil(_) => "nil",
+            Node::Ret(_) => "return",
+            Node::SelfRef(_) => "self",
+            Node::Send(_) => "method call",
+            Node::StringLiteral(_) => "string",
+            Node::Struct(_) => "struct",
+            Node::Trait(_) => "trait",
+            Node::While(_) => "while",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BaseType {
     // Integer Types
+    Bool, // i1
     Byte,
     Int, // Int64 by default
     Int16,
@@ -212,37 +321,65 @@ pub enum BaseType {
     Int64,
     FnRef,
 
+    // Floating Point Types
+    Float, // f64
+
     // Dynamic Types
-    Array(i64, Box<BaseType>),
+    Array(Box<BaseType>), // growable, of its item type
     Class(String),
     Struct(String),
 
     // Pointer Types
     BytePtr,
+    /// A class that might be nil, like `Str?`
+    Optional(Box<BaseType>),
+    /// The type of `nil` where it isn't given as an optional
+    Nil,
 
     // To Remove
     Void,
 }
 
+impl BaseType {
+    /// The class an optional wraps, which sends to it are resolved by.
+    pub fn without_optional(&self) -> &BaseType {
+        match self {
+            BaseType::Optional(base_type) => base_type,
+            base_type => base_type,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Arg {
     pub name: String,
     pub return_type: BaseType,
+    /// The value given when a call leaves the argument out, as in
+    /// `def greet(name Str = "world")`
+    pub default: Option<Node>,
 }
 
 impl Arg {
     pub fn pajama_class_name(&self) -> &str {
         match &self.return_type {
-            BaseType::Array(_, _) => "Array",
+            BaseType::Array(_) => "Array",
+            BaseType::Bool => "Bool",
             BaseType::Byte => "Byte",
             BaseType::BytePtr => "BytePtr",
             BaseType::Class(class_name) => class_name.as_str(),
+            BaseType::Float => "Float",
             BaseType::FnRef => "FnRef",
             BaseType::Int => "Int",
             BaseType::Int16 => "Int16",
             BaseType::Int32 => "Int32",
             BaseType::Int64 => "Int64",
             BaseType::Struct(_) => "Struct",
+            // Sends to an optional are resolved by the class it wraps
+            BaseType::Optional(base_type) => match base_type.as_ref() {
+                BaseType::Class(class_name) => class_name.as_str(),
+                _ => "",
+            },
+            BaseType::Nil => "Nil",
             BaseType::Void => "",
         }
     }
@@ -257,7 +394,42 @@ pub struct Prototype {
     pub prec: usize,
 }
 
-#[derive(Debug)]
+/// Operators a class can define as methods, like `def +(other Vec)`, and
+/// the names they get where symbols can't contain them.
+pub const OPERATOR_METHODS: [(&str, &str); 8] = [
+    ("+", "op_add"),
+    ("-", "op_sub"),
+    ("*", "op_mul"),
+    ("/", "op_div"),
+    ("==", "op_eq"),
+    ("<=>", "op_cmp"),
+    ("[]", "op_index"),
+    ("[]=", "op_index_set"),
+];
+
+/// `Vec.+` becomes `Vec.op_add` and `Str.empty?` becomes `Str.empty_q`, other
+/// names are left as they are.
+pub fn mangle_method_name(name: &str) -> String {
+    let (class_name, method_name) = match name.rsplit_once('.') {
+        Some((class_name, method_name)) => (Some(class_name), method_name),
+        None => (None, name),
+    };
+
+    let mangled = match OPERATOR_METHODS.iter().find(|(op, _)| *op == method_name) {
+        Some((_, mangled)) => mangled.to_string(),
+        None => match method_name.strip_suffix('?') {
+            Some(predicate) => format!("{}_q", predicate),
+            None => return name.to_string(),
+        },
+    };
+
+    match class_name {
+        Some(class_name) => format!("{}.{}", class_name, mangled),
+        None => mangled.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Def {
     pub main_fn: bool,
     pub prototype: Prototype,
@@ -267,17 +439,42 @@ pub struct Def {
     pub trait_name: String,
 }
 
-#[derive(Debug)]
+/// A method declared by a trait. Required methods have no body, every class
+/// implementing the trait has to define them.
+#[derive(Debug, Clone)]
+pub struct TraitMethod {
+    pub prototype: Prototype,
+    pub required: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct DefE {
     pub prototype: Prototype,
 }
 
-#[derive(Debug)]
+/// `if` with its `elsif`s nested in `else_body`. `return_type` is set by the
+/// semantic analyzer when both branches end in a value of the same type, the
+/// `if` can then be used as an expression.
+#[derive(Debug, Clone)]
+pub struct If {
+    pub condition: Box<Node>,
+    pub then_body: Vec<Node>,
+    pub else_body: Vec<Node>,
+    pub return_type: Option<BaseType>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Loop {
     // pub args: HashMap<String, LocalVar>,
     pub body: Vec<Node>,
 }
 
+#[derive(Debug, Clone)]
+pub struct While {
+    pub condition: Box<Node>,
+    pub body: Vec<Node>,
+}
+
 #[derive(Debug)]
 pub struct ParserResult {
     pub module: Node,
@@ -287,15 +484,157 @@ pub struct ParserResult {
 #[derive(Debug)]
 pub struct ParserResultIndex {
     pub trait_index: HashMap<String, Vec<Class>>,
+    /// The methods each trait declares, in order, e.g. `Speak.greet`
+    pub trait_method_index: HashMap<String, Vec<TraitMethod>>,
     pub class_index: HashMap<String, Class>,
     pub struct_index: HashMap<String, Struct>,
     pub constant_index: HashMap<String, BaseType>,
     pub fn_prototype_index: HashMap<String, Prototype>,
+    /// Where each class, struct, trait, constant and def is defined, by the
+    /// name it's referred to with, e.g. `Dog.walk`
+    pub definition_index: HashMap<String, SourceLocation>,
+    /// The calls to each function, by the function's name. Only calls by
+    /// name are known while parsing, which method a send calls is only
+    /// resolved once types are inferred
+    pub call_site_index: HashMap<String, Vec<CallSite>>,
+    /// The methods of each class, with those of its `impl` blocks and the
+    /// generated `new` and `alloca`, in order
+    pub class_method_index: HashMap<String, Vec<String>>,
+}
+
+/// Where something is in the source, like a `Diagnostic`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub path: Option<String>,
+    pub position: Option<TokenPosition>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallSite {
+    /// The def the call is in
+    pub caller: String,
+    pub location: SourceLocation,
+}
+
+/// Method resolution order
+///
+/// A method sent to an instance of a class is looked up in:
+///
+/// 1. the class itself, including methods from its `impl` blocks
+/// 2. the superclass chain, nearest superclass first
+/// 3. the default methods of every trait implemented by the class or one of
+///    its superclasses
+///
+/// The first class in the chain that defines the method wins. Trait defaults
+/// have no order between them, so when more than one implemented trait
+/// provides a default for the same method, and no class in the chain defines
+/// it, the send is ambiguous and reported as an error.
+///
+/// `super` inside a method continues the lookup from the step after the class
+/// that defines the calling method.
+impl ParserResultIndex {
+    pub fn resolve_method(
+        &self,
+        class_name: &str,
+        method_name: &str,
+    ) -> Result<Option<String>, String> {
+        let classes = self.class_ancestors(class_name);
+
+        self.resolve_method_in(class_name, &classes, &classes, method_name)
+    }
+
+    pub fn resolve_super_method(
+        &self,
+        class_name: &str,
+        superclass: Option<&str>,
+        method_name: &str,
+    ) -> Result<Option<String>, String> {
+        let classes = match superclass {
+            Some(superclass) => self.class_ancestors(superclass),
+            None => vec![],
+        };
+
+        let mut trait_implementors = vec![class_name.to_string()];
+        trait_implementors.extend(classes.iter().cloned());
+
+        self.resolve_method_in(class_name, &classes, &trait_implementors, method_name)
+    }
+
+    /// Returns the class followed by its superclass chain.
+    pub fn class_ancestors(&self, class_name: &str) -> Vec<String> {
+        let mut ancestors = vec![class_name.to_string()];
+        let mut current = self.class_index.get(class_name);
+
+        while let Some(class) = current {
+            match &class.superclass {
+                Some(superclass) if !ancestors.contains(superclass) => {
+                    ancestors.push(superclass.clone());
+                    current = self.class_index.get(superclass);
+                }
+                _ => break,
+            }
+        }
+
+        ancestors
+    }
+
+    /// Returns the traits implemented by any of the given classes, sorted by
+    /// name.
+    pub fn implemented_traits(&self, class_names: &[String]) -> Vec<String> {
+        let mut traits: Vec<String> = self
+            .trait_index
+            .iter()
+            .filter(|(_, classes)| {
+                classes
+                    .iter()
+                    .any(|class| class_names.contains(&class.name))
+            })
+            .map(|(trait_name, _)| trait_name.clone())
+            .collect();
+
+        traits.sort();
+        traits
+    }
+
+    fn resolve_method_in(
+        &self,
+        class_name: &str,
+        classes: &[String],
+        trait_implementors: &[String],
+        method_name: &str,
+    ) -> Result<Option<String>, String> {
+        for class in classes {
+            let fn_name = format!("{}.{}", class, method_name);
+
+            if self.fn_prototype_index.contains_key(&fn_name) {
+                return Ok(Some(fn_name));
+            }
+        }
+
+        let providers: Vec<String> = self
+            .implemented_traits(trait_implementors)
+            .iter()
+            .map(|trait_name| format!("{}.{}", trait_name, method_name))
+            .filter(|fn_name| self.fn_prototype_index.contains_key(fn_name))
+            .collect();
+
+        if providers.len() > 1 {
+            return Err(format!(
+                "Ambiguous method `{}` for `{}`: default implementations are provided by {}",
+                method_name,
+                class_name,
+                providers.join(", ")
+            ));
+        }
+
+        Ok(providers.into_iter().next())
+    }
 }
 
 #[derive(Debug)]
 pub struct ParserModuleCtx {
     pub class_name: String,
+    pub superclass: Option<String>,
     pub self_node: Option<Node>,
 }
 
@@ -308,12 +647,66 @@ pub struct ParserFunctionCtx {
     pub parsing_returnable_loc: bool,
 }
 
+/// How deeply expressions can nest before parsing stops with an error, rather
+/// than overflowing the stack on generated or hostile input. Later phases walk
+/// the tree recursively too, so this bounds them as well. Chains count as
+/// nesting, since each operator in `1 + 2 + 3` and each send in `a.b().c()`
+/// is one level deeper in the tree than the next.
+pub const MAX_EXPR_DEPTH: usize = 128;
+
+/// The error for a def that runs into the next top level item.
+const MISSING_END: &str = "Expected 'end' before the next definition.";
+
+/// The error for named args given to a call through a function reference.
+const FN_REF_ARG_NAMES: &str =
+    "Functions called through a reference only take positional arguments.";
+
+/// The error `parse` stops with once its cancellation token is cancelled.
+const CANCELLED: &str = "parsing cancelled";
+
+/// The builtin an interpolated string like `"Hi #{name}"` is parsed into, a
+/// call with the literal parts and the expressions as arguments. The semantic
+/// analyzer lowers it to `to_s` conversions and concatenations.
+pub const INTERPOLATE_FN: &str = "#{}";
+
+/// The precedence of the builtin binary operators, higher binds tighter.
+pub fn default_op_precedence() -> HashMap<String, i32> {
+    OPERATORS
+        .iter()
+        .filter_map(|operator| Some((operator.symbol.to_string(), operator.precedence?)))
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct Parser<'a> {
-    pub tokens: Vec<Token>,
+    /// Borrowed when the caller keeps its tokens, like a tool running
+    /// several analyses over one lexed buffer
+    pub tokens: Cow<'a, [Token]>,
     pub pos: usize,
-    pub op_precedence: &'a mut HashMap<char, i32>,
+    pub op_precedence: &'a mut HashMap<String, i32>,
     pub index: ParserResultIndex,
+    pub expr_depth: usize,
+    /// How many loops the current expression is in, `break` and `next` need
+    /// at least one
+    pub loop_depth: usize,
+    pub cancellation: CancellationToken,
+    /// The token each file starts at, when several were joined by
+    /// `start_parse_files`
+    pub files: Vec<(usize, String)>,
+    /// The defs `at_exit` blocks are parsed into, added to the end of the
+    /// module
+    pub at_exit_defs: Vec<Node>,
+    /// The syntax errors found so far, each parsed past
+    pub diagnostics: Vec<Diagnostic>,
+    /// The openers, like `(` or `def`, of what's being parsed, innermost
+    /// last, by their token and name. See `enclosed`.
+    pub openers: Vec<(usize, &'static str)>,
+    /// The innermost opener the input ended inside of, once the parse of
+    /// what it encloses failed
+    pub unclosed: Option<(usize, &'static str)>,
+    /// Where the input ended once that's been reported, so the errors of the
+    /// openers around it aren't
+    pub reported_end: Option<usize>,
 }
 
 impl<'a> Parser<'a> {
@@ -329,34 +722,328 @@ impl<'a> Parser<'a> {
     //     }
     // }
 
-    pub fn start_parse(tokens: Vec<Token>, op_precedence: &mut HashMap<char, i32>) -> ParserResult {
-        let mut parser = Parser {
+    pub fn start_parse<'t>(
+        tokens: impl Into<Cow<'t, [Token]>>,
+        op_precedence: &mut HashMap<String, i32>,
+    ) -> ParserResult {
+        Self::start_parse_cancellable(tokens, op_precedence, &CancellationToken::new()).unwrap()
+    }
+
+    /// `start_parse`, stopping before the next top level item once
+    /// `cancellation` is cancelled.
+    pub fn start_parse_cancellable<'t>(
+        tokens: impl Into<Cow<'t, [Token]>>,
+        op_precedence: &mut HashMap<String, i32>,
+        cancellation: &CancellationToken,
+    ) -> Result<ParserResult, Cancelled> {
+        match Parser::parse_tokens(tokens.into(), op_precedence, cancellation, vec![])? {
+            Ok(parser_result) => Ok(parser_result),
+            Err(diagnostics) => {
+                let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+
+                panic!("{}", messages.join("\n"))
+            }
+        }
+    }
+
+    /// Parses `tokens` like `start_parse`, but returns the AST even when it
+    /// has syntax errors, along with them. The broken parts are `Node::Error`s,
+    /// see `parse`. For editors, which show what they can of a file while
+    /// it's being typed.
+    pub fn start_parse_partial<'t>(
+        tokens: impl Into<Cow<'t, [Token]>>,
+        op_precedence: &mut HashMap<String, i32>,
+    ) -> (ParserResult, Vec<Diagnostic>) {
+        Parser::parse_partial(
+            tokens.into(),
+            op_precedence,
+            &CancellationToken::new(),
+            vec![],
+        )
+    }
+
+    pub(crate) fn parse_tokens(
+        tokens: Cow<[Token]>,
+        op_precedence: &mut HashMap<String, i32>,
+        cancellation: &CancellationToken,
+        files: Vec<(usize, String)>,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        let (parser_result, diagnostics) =
+            Parser::parse_partial(tokens, op_precedence, cancellation, files);
+
+        if cancellation.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        if !diagnostics.is_empty() {
+            return Ok(Err(diagnostics));
+        }
+
+        Ok(Ok(parser_result))
+    }
+
+    fn parse_partial(
+        tokens: Cow<[Token]>,
+        op_precedence: &mut HashMap<String, i32>,
+        cancellation: &CancellationToken,
+        files: Vec<(usize, String)>,
+    ) -> (ParserResult, Vec<Diagnostic>) {
+        let mut parser = Parser::with_tokens(tokens, op_precedence);
+        parser.cancellation = cancellation.clone();
+        parser.files = files;
+
+        // Built in traits, implemented with `impl` like any other trait
+        parser
+            .index
+            .trait_index
+            .insert("Comparable".to_string(), vec![]);
+
+        parser.predeclare_prototypes();
+
+        let (module, diagnostics) = parser.parse();
+
+        (
+            ParserResult {
+                module,
+                index: parser.index,
+            },
+            diagnostics,
+        )
+    }
+
+    /// Parses several files, each lexed on its own, as one program. A class or
+    /// function defined in more than one file is an error naming both files;
+    /// `def_e` declarations can be repeated.
+    ///
+    /// Files are scanned for their definitions in parallel. The bodies are
+    /// parsed together afterwards, since they refer to each other's classes.
+    pub fn start_parse_files(
+        files: Vec<(String, Vec<Token>)>,
+        op_precedence: &mut HashMap<String, i32>,
+    ) -> Result<ParserResult, Vec<Diagnostic>> {
+        Self::start_parse_files_cancellable(files, op_precedence, &CancellationToken::new())
+            .unwrap()
+    }
+
+    /// `start_parse_files`, stopping early once `cancellation` is cancelled.
+    /// Parse errors are only reported for compiles that ran to the end.
+    pub fn start_parse_files_cancellable(
+        files: Vec<(String, Vec<Token>)>,
+        op_precedence: &mut HashMap<String, i32>,
+        cancellation: &CancellationToken,
+    ) -> Result<Result<ParserResult, Vec<Diagnostic>>, Cancelled> {
+        let file_op_precedence: &HashMap<String, i32> = op_precedence;
+
+        let scanned = crate::parallel::par_map(files, |(path, file_tokens)| {
+            if cancellation.is_cancelled() {
+                return (path, vec![], vec![]);
+            }
+
+            let mut op_precedence = file_op_precedence.clone();
+            let mut parser = Parser::with_tokens(file_tokens.into(), &mut op_precedence);
+            parser.predeclare_prototypes();
+
+            let names = parser.defined_names();
+
+            (path, parser.tokens.into_owned(), names)
+        });
+
+        let mut defined_in: HashMap<String, String> = HashMap::new();
+        let mut errors = vec![];
+        let mut tokens = vec![];
+        let mut file_starts = vec![];
+
+        for (path, file_tokens, names) in scanned {
+            for name in names {
+                match defined_in.get(&name) {
+                    Some(other_path) => errors.push(Diagnostic {
+                        path: Some(path.clone()),
+                        ..Diagnostic::new(format!(
+                            "`{}` is already defined in {}",
+                            name, other_path
+                        ))
+                    }),
+                    None => {
+                        defined_in.insert(name, path.clone());
+                    }
+                }
+            }
+
+            file_starts.push((tokens.len(), path));
+            tokens.extend(file_tokens);
+            tokens.push(Token::NewLine(1));
+        }
+
+        cancellation.check()?;
+
+        if !errors.is_empty() {
+            return Ok(Err(errors));
+        }
+
+        Parser::parse_tokens(tokens.into(), op_precedence, cancellation, file_starts)
+    }
+
+    /// The classes and functions, but not the `def_e` declarations, found by
+    /// `predeclare_prototypes`, sorted.
+    fn defined_names(&self) -> Vec<String> {
+        let keyword_names = |keyword: fn(&Token) -> bool| {
+            self.tokens
+                .iter()
+                .zip(self.tokens.iter().skip(2))
+                .filter_map(move |pair| match pair {
+                    (token, Token::Const(_, name) | Token::Ident(_, name)) if keyword(token) => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                })
+        };
+
+        let external_names: Vec<String> =
+            keyword_names(|token| matches!(token, Token::DefE)).collect();
+
+        let mut names: Vec<String> = self
+            .index
+            .fn_prototype_index
+            .keys()
+            .filter(|name| !external_names.contains(name))
+            .cloned()
+            .chain(keyword_names(|token| matches!(token, Token::Class)))
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    fn with_tokens<'t>(
+        tokens: Cow<'t, [Token]>,
+        op_precedence: &'t mut HashMap<String, i32>,
+    ) -> Parser<'t> {
+        Parser {
             tokens,
             op_precedence,
             pos: 0,
             index: ParserResultIndex {
                 trait_index: HashMap::new(),
+                trait_method_index: HashMap::new(),
                 class_index: HashMap::new(),
                 struct_index: HashMap::new(),
                 constant_index: HashMap::new(),
                 fn_prototype_index: HashMap::new(),
+                definition_index: HashMap::new(),
+                call_site_index: HashMap::new(),
+                class_method_index: HashMap::new(),
             },
+            expr_depth: 0,
+            loop_depth: 0,
+            cancellation: CancellationToken::new(),
+            files: vec![],
+            at_exit_defs: vec![],
+            diagnostics: vec![],
+            openers: vec![],
+            unclosed: None,
+            reported_end: None,
+        }
+    }
+
+    /// Scans the whole token stream for `def` and `def_e` prototypes before any
+    /// body is parsed, so calls to functions that are defined further down the
+    /// file (or that are mutually recursive) resolve the same way as calls to
+    /// functions defined above them.
+    pub fn predeclare_prototypes(&mut self) {
+        let mut mctx = ParserModuleCtx {
+            self_node: None,
+            class_name: "".to_string(),
+            superclass: None,
         };
 
-        let module = parser.parse().unwrap();
+        let mut depth = 0;
+        let mut class_depth = None;
+        let mut expecting_class_name = false;
+        let mut in_trait = false;
+
+        self.pos = 0;
+
+        while !self.at_end() {
+            match self.curr() {
+                Token::Class | Token::Trait => {
+                    in_trait = matches!(self.curr(), Token::Trait);
+                    depth += 1;
+                    class_depth = Some(depth);
+                    expecting_class_name = true;
+                }
+                Token::Const(_, name) if expecting_class_name => {
+                    mctx.class_name = name;
+                    expecting_class_name = false;
+                }
+                Token::Struct | Token::Impl => depth += 1,
+                // Blocks in bodies close with an `end` too
+                Token::Do | Token::If | Token::Loop | Token::While => depth += 1,
+                Token::End => {
+                    if class_depth == Some(depth) {
+                        mctx.class_name = "".to_string();
+                        class_depth = None;
+                        in_trait = false;
+                    }
+
+                    depth -= 1;
+                }
+                Token::Def | Token::DefE => {
+                    if let Token::Def = self.curr() {
+                        depth += 1;
+                    }
+
+                    let def_pos = self.pos;
+
+                    // Advance past the keyword, mirroring parse_def
+                    self.pos += 1;
+
+                    match self.parse_prototype(&mut mctx) {
+                        Ok(prototype) => {
+                            self.index
+                                .fn_prototype_index
+                                .insert(prototype.name.clone(), prototype);
+
+                            // A bodyless trait def has no `end` of its own
+                            self.advance_optional_whitespace();
+
+                            let bodyless =
+                                matches!(self.kind(), Some(TokenKind::End | TokenKind::Def));
 
-        ParserResult {
-            module,
-            index: parser.index,
+                            if in_trait && bodyless {
+                                depth -= 1;
+                            }
+                        }
+                        // The real parse reports the error
+                        Err(_) => self.pos = def_pos + 1,
+                    }
+
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.pos += 1;
         }
+
+        self.pos = 0;
     }
 
     // pub fn parse(&mut self) -> Result<ParserResult, &'static str> {
-    pub fn parse(&mut self) -> Result<Node, &'static str> {
+    /// Parses every top level item, carrying on after one with an error so
+    /// each item's first error is reported.
+    /// Parses the module, and returns it with the syntax errors found.
+    ///
+    /// An error doesn't stop the parse. The broken statement, or the broken
+    /// item when it's outside a def body, is replaced with a `Node::Error`.
+    /// Parsing picks up again at the next line of a def, past any block the
+    /// statement opened, or at the next top level item. Editors get an AST
+    /// of everything else, and every error at once.
+    pub fn parse(&mut self) -> (Node, Vec<Diagnostic>) {
         let mut methods = vec![];
         let mut mctx = ParserModuleCtx {
             self_node: None,
             class_name: "".to_string(),
+            superclass: None,
         };
 
         loop {
@@ -366,31 +1053,54 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let results = match self.current()? {
+            if self.cancellation.is_cancelled() {
+                self.diagnostics.push(Diagnostic::new(CANCELLED));
+                break;
+            }
+
+            let item_start = self.pos;
+            let results = match self.curr() {
                 Token::Const(pos, name) => self.parse_constant_assignment_expr(&mut mctx),
-                Token::Class => self.parse_class(&mut mctx),
-                Token::Struct => self.parse_struct(&mut mctx),
-                Token::Trait => self.parse_trait(&mut mctx),
-                Token::Def => self.parse_def(
-                    &mut mctx,
-                    "".to_string(),
-                    "".to_string(),
-                    "".to_string(),
-                    None,
-                ),
+                Token::Class => self.enclosed("class", |parser| parser.parse_class(&mut mctx)),
+                Token::Struct => self.enclosed("struct", |parser| parser.parse_struct(&mut mctx)),
+                Token::Trait => self.enclosed("trait", |parser| parser.parse_trait(&mut mctx)),
+                Token::Def => self.enclosed("def", |parser| {
+                    parser.parse_def(
+                        &mut mctx,
+                        "".to_string(),
+                        "".to_string(),
+                        "".to_string(),
+                        None,
+                    )
+                }),
                 Token::DefE => self.parse_def_e(&mut mctx),
+                Token::Import => self.parse_import(),
+                // A string the input ended in, like in an expression
+                Token::Illegal(_, text) if text.starts_with('"') => self.enclosed("\"", |parser| {
+                    parser.pos += 1;
+                    Err("Unexpected end of file.")
+                }),
                 _ => {
-                    println!("{:#?}", self.curr());
-                    Err("Expected class, def, or trait")
+                    tracing::trace!("{:#?}", self.curr());
+                    Err("Expected class, def, import or trait")
                 }
             };
 
-            for result in results? {
-                methods.push(result);
+            match results {
+                Ok(results) => methods.extend(results),
+                Err(error) => {
+                    methods.push(self.error_node(error));
+                    self.skip_to_next_item(item_start);
+                }
             }
         }
 
-        Ok(Node::Module(Module { methods }))
+        methods.append(&mut self.at_exit_defs);
+
+        (
+            Node::Module(Module { methods }),
+            std::mem::take(&mut self.diagnostics),
+        )
 
         // Ok(ParserResult {
         //     module: Node::Module(Module { methods }),
@@ -398,62 +1108,325 @@ impl<'a> Parser<'a> {
         // })
     }
 
-    // fn parse_comment(&mut self, mctx: &mut ParserModuleCtx) -> Result<Vec<Node>, &'static str> {
-    //     match self.curr() {
-    //         Token::Comment(pos, text) => {
-    //             self.advance()?;
-    //             self.advance_optional_whitespace();
+    /// A diagnostic for `message` at the current token, or the nearest one
+    /// before it with a position.
+    fn diagnostic(&self, message: &str) -> Diagnostic {
+        let location = self.location_at(self.pos);
 
-    //             Ok(vec![Node::Comment(Comment { text })])
-    //         }
-    //         _ => return Err("Expected const node")
-    //     }
-    // }
+        Diagnostic {
+            message: message.to_string(),
+            path: location.path,
+            position: location.position,
+        }
+    }
 
-    fn parse_constant_assignment_expr(
-        &mut self,
-        mctx: &mut ParserModuleCtx,
-    ) -> Result<Vec<Node>, &'static str> {
-        println!("{:#?}", self.curr());
+    /// The location of the token at `pos`, or the nearest one before it with
+    /// a position.
+    fn location_at(&self, pos: usize) -> SourceLocation {
+        let last = pos.min(self.tokens.len().saturating_sub(1));
+
+        let position = self
+            .tokens
+            .iter()
+            .take(last + 1)
+            .rev()
+            .find_map(Token::position)
+            .cloned();
+
+        let path = self
+            .files
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= last)
+            .map(|(_, path)| path.clone());
+
+        SourceLocation { path, position }
+    }
 
-        let name = match self.current()? {
-            Token::Const(pos, name) => {
-                self.advance()?;
-                self.advance_optional_whitespace();
+    /// Where the input ends, when only whitespace and comments are left
+    /// before it: the token the next file starts at, or the end of the
+    /// tokens.
+    fn input_end(&self) -> Option<usize> {
+        let end = self
+            .files
+            .iter()
+            .map(|(start, _)| *start)
+            .find(|start| *start > 0 && *start >= self.pos)
+            .unwrap_or(self.tokens.len());
+
+        self.tokens
+            .get(self.pos..end)?
+            .iter()
+            .all(|token| {
+                matches!(
+                    token,
+                    Token::Space(_) | Token::NewLine(_) | Token::Comment(_, _)
+                )
+            })
+            .then_some(end)
+    }
 
-                name
-            }
-            _ => return Err("Expected const node"),
-        };
+    /// The location of the opener at `pos` named `name`. Keywords carry no
+    /// position, so theirs is worked out from the spaces between them and a
+    /// token on the same line that does, or the start of the line.
+    fn opener_location(&self, pos: usize, name: &str) -> SourceLocation {
+        let mut location = self.location_at(pos);
 
-        println!("name {:#?}", name);
-        println!("{:#?}", self.curr());
+        if self.tokens[pos].position().is_some() {
+            return location;
+        }
 
-        let return_type = match self.current()? {
-            Token::Const(_type_pos, type_name) => {
-                self.advance();
-                self.advance_optional_whitespace();
+        // How wide the spaces are, and the token past them
+        fn spaces<'t>(tokens: impl Iterator<Item = &'t Token>) -> (usize, Option<&'t Token>) {
+            let mut width = 0;
 
-                self.class_base_type(type_name)
+            for token in tokens {
+                match token {
+                    Token::Space(length) => width += length,
+                    token => return (width, Some(token)),
+                }
             }
-            _ => return Err("Expected type for constant"),
-        };
 
-        println!("{:#?}", self.curr());
+            (width, None)
+        }
 
-        match self.current()? {
-            Token::Assign => {
-                self.advance();
-                self.advance_optional_whitespace();
+        let position = match spaces(self.tokens[pos + 1..].iter()) {
+            // `def main`
+            (width, Some(token)) if token.position().is_some() => {
+                let after = token.position().unwrap();
+                let start_column = after.start_column.saturating_sub(width + name.len());
+
+                Some((after.line, start_column))
             }
-            _ => return Err("Expected constant assignment"),
+            _ => match spaces(self.tokens[..pos].iter().rev()) {
+                // `at_exit do`
+                (width, Some(token)) if token.position().is_some() => {
+                    let before = token.position().unwrap();
+
+                    Some((before.line, before.end_column + width + 1))
+                }
+                // `loop do` starting a line
+                (width, Some(Token::NewLine(_)) | None) => {
+                    Some((self.line_at(pos), width + 1))
+                }
+                _ => None,
+            },
         };
 
-        let value = Box::new(self.parse_constant_value_expr(mctx).unwrap());
+        if let Some((line, start_column)) = position {
+            location.position = Some(TokenPosition {
+                line,
+                start_column,
+                end_column: start_column + name.len() - 1,
+            });
+        }
+
+        location
+    }
+
+    /// The line of the token at `pos`, counted from the nearest token before
+    /// it with a position in the same file.
+    fn line_at(&self, pos: usize) -> usize {
+        let file_start = self
+            .files
+            .iter()
+            .rev()
+            .map(|(start, _)| *start)
+            .find(|start| *start <= pos)
+            .unwrap_or(0);
+        let mut line = 1;
+
+        for token in self.tokens[file_start..pos].iter().rev() {
+            match token {
+                Token::NewLine(count) => line += count,
+                // A comment takes its newline with it
+                Token::Comment(position, text) => {
+                    return line + position.line - 1 + usize::from(text.ends_with('\n'));
+                }
+                token => {
+                    if let Some(position) = token.position() {
+                        return line + position.line - 1;
+                    }
+                }
+            }
+        }
+
+        line
+    }
+
+    /// Records `message` as a diagnostic at the current token, and returns the
+    /// `Node::Error` standing in for what couldn't be parsed.
+    ///
+    /// An error from running out of input is reported as the innermost
+    /// opener that's never closed, at the opener, and only once.
+    fn error_node(&mut self, message: &str) -> Node {
+        let end = self.input_end();
+        let unclosed = self.unclosed.take().or(self.openers.last().copied());
+        let diagnostic = match (end, unclosed) {
+            (Some(end), _) if self.reported_end == Some(end) => None,
+            (Some(end), Some((opener, name))) => {
+                let location = self.opener_location(opener, name);
+
+                self.reported_end = Some(end);
+
+                Some(Diagnostic {
+                    message: format!("`{}` is never closed", name),
+                    path: location.path,
+                    position: location.position,
+                })
+            }
+            _ => Some(self.diagnostic(message)),
+        };
+        let location = match &diagnostic {
+            Some(diagnostic) => SourceLocation {
+                path: diagnostic.path.clone(),
+                position: diagnostic.position.clone(),
+            },
+            None => self.location_at(self.pos),
+        };
+
+        self.diagnostics.extend(diagnostic);
+
+        Node::Error(Error {
+            message: message.to_string(),
+            location,
+        })
+    }
+
+    /// Records that `name` is defined at `location`.
+    fn define(&mut self, name: &str, location: SourceLocation) {
+        self.index
+            .definition_index
+            .insert(name.to_string(), location);
+    }
+
+    /// Skips to the next line that starts with a top level item, past the rest
+    /// of the item starting at `item_start` an error was found in.
+    fn skip_to_next_item(&mut self, item_start: usize) {
+        self.expr_depth = 0;
+        self.loop_depth = 0;
+        self.pos = self.pos.max(item_start + 1);
+
+        while self.pos < self.tokens.len() && !self.is_item_start(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the token at `pos` starts a line with a top level item.
+    fn is_item_start(&self, pos: usize) -> bool {
+        let at_line_start = pos == 0 || matches!(self.tokens[pos - 1], Token::NewLine(_));
+        let at_item = matches!(
+            self.tokens.get(pos),
+            Some(
+                Token::Class
+                    | Token::Const(_, _)
+                    | Token::Def
+                    | Token::DefE
+                    | Token::Import
+                    | Token::Struct
+                    | Token::Trait
+            )
+        );
+
+        at_line_start && at_item
+    }
+
+    /// Recovers from `message`, an error in the statement of a def body
+    /// starting at `start`. Skips to the next line after the statement,
+    /// past the `end` of any block it opened, or to the def's own `end`.
+    /// Errors when that would skip into the next top level item, which
+    /// means the def is missing its `end`.
+    fn recover_statement(
+        &mut self,
+        message: &'static str,
+        start: usize,
+    ) -> Result<Node, &'static str> {
+        if self.is_item_start(start) {
+            self.pos = start;
+            return Err(MISSING_END);
+        }
+
+        let node = self.error_node(message);
+        let mut depth = 0;
+
+        self.pos = start;
+
+        while self.pos < self.tokens.len() && !self.is_item_start(self.pos) {
+            match self.tokens[self.pos] {
+                Token::Do | Token::If | Token::Loop | Token::While => depth += 1,
+                Token::End if depth == 0 => return Ok(node),
+                Token::End => depth -= 1,
+                Token::NewLine(_) if depth == 0 => {
+                    self.pos += 1;
+                    return Ok(node);
+                }
+                _ => {}
+            }
+
+            self.pos += 1;
+        }
+
+        Err(MISSING_END)
+    }
+
+    // fn parse_comment(&mut self, mctx: &mut ParserModuleCtx) -> Result<Vec<Node>, &'static str> {
+    //     match self.curr() {
+    //         Token::Comment(pos, text) => {
+    //             self.advance()?;
+    //             self.advance_optional_whitespace();
+
+    //             Ok(vec![Node::Comment(Comment { text })])
+    //         }
+    //         _ => return Err("Expected const node")
+    //     }
+    // }
+
+    fn parse_constant_assignment_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+    ) -> Result<Vec<Node>, &'static str> {
+        tracing::trace!("{:#?}", self.curr());
+
+        let location = self.location_at(self.pos);
+        let name = match self.current()? {
+            Token::Const(pos, name) => {
+                self.advance()?;
+                self.advance_optional_whitespace();
+
+                name
+            }
+            _ => return Err("Expected const node"),
+        };
+
+        tracing::trace!("name {:#?}", name);
+        tracing::trace!("{:#?}", self.curr());
+
+        let return_type = match self.current()? {
+            Token::Const(_type_pos, type_name) => {
+                self.advance();
+                self.advance_optional_whitespace();
+
+                self.class_base_type(type_name)
+            }
+            _ => return Err("Expected type for constant"),
+        };
+
+        tracing::trace!("{:#?}", self.curr());
+
+        match self.current()? {
+            Token::Assign => {
+                self.advance();
+                self.advance_optional_whitespace();
+            }
+            _ => return Err("Expected constant assignment"),
+        };
+
+        let value = Box::new(self.parse_constant_value_expr(mctx)?);
 
         self.index
             .constant_index
             .insert(name.clone(), return_type.clone());
+        self.define(&name, location);
 
         Ok(vec![Node::AssignConstant(AssignConstant {
             name,
@@ -484,6 +1457,7 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
+        let location = self.location_at(self.pos);
         let (pos, class_name) = match self.current()? {
             Token::Const(pos, name) => {
                 self.advance()?;
@@ -494,19 +1468,55 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
-        match self.curr() {
-            Token::NewLine(_) => self.advance(),
-            _ => return Err("Expected a new line after class name"),
+        let superclass = match self.current()? {
+            Token::Op(op) if op == "<" => {
+                self.advance()?;
+                self.advance_optional_space();
+
+                let superclass = match self.current()? {
+                    Token::Const(_, name) => {
+                        self.advance()?;
+                        name
+                    }
+                    _ => return Err("Expected superclass name after '<'"),
+                };
+
+                if !self.index.class_index.contains_key(&superclass) {
+                    return Err(
+                        "Superclass must be defined before the class that inherits from it",
+                    );
+                }
+
+                self.advance_optional_space();
+
+                Some(superclass)
+            }
+            _ => None,
         };
 
-        let attributes = self.parse_attributes().unwrap();
+        self.expect(TokenKind::NewLine, "Expected a new line after class name")?;
+
+        // Inherited attributes come first so a subclass instance has the same
+        // layout as its superclass for the attributes they share
+        let mut attributes = match &superclass {
+            Some(superclass) => self.index.class_index[superclass].attributes.clone(),
+            None => vec![],
+        };
+
+        attributes.extend(self.parse_attributes()?);
+
+        for (index, attribute) in attributes.iter_mut().enumerate() {
+            attribute.index = index as i32;
+        }
 
         let class_node = Class {
             name: class_name.clone(),
             attributes,
+            superclass: superclass.clone(),
         };
 
         mctx.class_name = class_name.clone();
+        mctx.superclass = superclass;
         mctx.self_node = Some(Node::SelfRef(SelfRef {
             return_type: BaseType::Class(mctx.class_name.clone()),
         }));
@@ -518,14 +1528,18 @@ impl<'a> Parser<'a> {
             self.advance_optional_whitespace();
 
             let results = match self.current()? {
-                Token::Def => self.parse_def(
-                    mctx,
-                    class_name.clone(),
-                    "".to_string(),
-                    "".to_string(),
-                    new_fn,
-                ),
-                Token::Impl => self.parse_impl(mctx, class_name.clone()),
+                Token::Def => self.enclosed("def", |parser| {
+                    parser.parse_def(
+                        mctx,
+                        class_name.clone(),
+                        "".to_string(),
+                        "".to_string(),
+                        new_fn,
+                    )
+                }),
+                Token::Impl => {
+                    self.enclosed("impl", |parser| parser.parse_impl(mctx, class_name.clone()))
+                }
                 Token::End => {
                     self.advance();
                     break;
@@ -545,24 +1559,60 @@ impl<'a> Parser<'a> {
             let mut args = vec![Arg {
                 name: "sret".to_string(),
                 return_type: BaseType::Class(mctx.class_name.clone()),
+                default: None,
             }];
 
             let mut body = vec![];
 
-            for (index, attribute) in class_node.attributes.iter().enumerate() {
-                args.push(Arg {
-                    name: attribute.name.clone(),
-                    return_type: attribute.return_type.clone(),
-                });
+            let init = self
+                .index
+                .fn_prototype_index
+                .get(&format!("{}.init", class_name))
+                .cloned();
+
+            match init {
+                // `def init(...)` sets up the instance, `new` takes its
+                // arguments and passes them on
+                Some(init) => {
+                    let mut init_args = vec![Node::SelfRef(SelfRef {
+                        return_type: BaseType::Class(class_name.clone()),
+                    })];
+
+                    for arg in init.args.iter().skip(1) {
+                        args.push(arg.clone());
+
+                        init_args.push(Node::LocalVar(LocalVar {
+                            name: arg.name.clone(),
+                            return_type: Some(arg.return_type.clone()),
+                        }));
+                    }
 
-                body.push(Node::AssignAttribute(AssignAttribute {
-                    name: attribute.name.clone(),
-                    index: index as i32,
-                    value: Box::new(Node::LocalVar(LocalVar {
-                        name: attribute.name.clone(),
-                        return_type: Some(attribute.return_type.clone()),
-                    })),
-                }))
+                    body.push(Node::Call(Call {
+                        fn_name: init.name,
+                        args: init_args,
+                        return_type: init.return_type,
+                        arg_names: vec![],
+                    }));
+                }
+                // Otherwise `new` takes a value for each attribute
+                None => {
+                    for (index, attribute) in class_node.attributes.iter().enumerate() {
+                        args.push(Arg {
+                            name: attribute.name.clone(),
+                            return_type: attribute.return_type.clone(),
+                            default: None,
+                        });
+
+                        body.push(Node::AssignAttribute(AssignAttribute {
+                            name: attribute.name.clone(),
+                            index: index as i32,
+                            value: Box::new(Node::LocalVar(LocalVar {
+                                name: attribute.name.clone(),
+                                return_type: Some(attribute.return_type.clone()),
+                            })),
+                        }))
+                    }
+                }
             }
 
             let prototype = Prototype {
@@ -592,6 +1642,7 @@ impl<'a> Parser<'a> {
             let mut args = vec![Arg {
                 name: "sret".to_string(),
                 return_type: BaseType::Class(mctx.class_name.clone()),
+                default: None,
             }];
 
             let mut body = vec![];
@@ -623,8 +1674,22 @@ impl<'a> Parser<'a> {
         self.index
             .class_index
             .insert(class_name.clone(), class_node);
+        self.define(&class_name, location);
+
+        let method_names = functions
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) => Some(def.prototype.name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        self.index
+            .class_method_index
+            .insert(class_name.clone(), method_names);
 
         mctx.class_name = "".to_string();
+        mctx.superclass = None;
         mctx.self_node = None;
 
         Ok(functions)
@@ -636,6 +1701,7 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
+        let location = self.location_at(self.pos);
         let (pos, struct_name) = match self.current()? {
             Token::Const(pos, name) => {
                 self.advance()?;
@@ -646,12 +1712,9 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
-        match self.curr() {
-            Token::NewLine(_) => self.advance(),
-            _ => return Err("Expected a new line after class name"),
-        };
+        self.expect(TokenKind::NewLine, "Expected a new line after class name")?;
 
-        let attributes = self.parse_attributes().unwrap();
+        let attributes = self.parse_attributes()?;
 
         match self.current()? {
             Token::End => {
@@ -673,6 +1736,7 @@ impl<'a> Parser<'a> {
         self.index
             .struct_index
             .insert(struct_name.clone(), struct_struct);
+        self.define(&struct_name, location);
 
         mctx.class_name = "".to_string();
         mctx.self_node = None;
@@ -696,42 +1760,10 @@ impl<'a> Parser<'a> {
                             self.advance();
                             self.class_base_type(type_name)
                         }
-                        Token::LSquareBrace => {
+                        Token::LSquareBrace(_) => {
+                            let array_type = self.parse_array_type()?;
                             self.advance();
-
-                            let length = match self.current()? {
-                                Token::Number(_, n) => n,
-                                _ => return Err("Expected length of array"),
-                            };
-
-                            self.advance();
-                            self.advance_optional_space();
-
-                            match self.current()? {
-                                Token::Ident(_, sym) => match sym.as_str() {
-                                    "x" => {
-                                        self.advance();
-                                        self.advance_optional_space();
-                                    }
-                                    _ => return Err("Expected an 'x' for such as [4 x Byte]"),
-                                },
-                                _ => return Err("Expected type for array 1"),
-                            };
-
-                            let array_return_type = match self.current()? {
-                                Token::Const(_type_pos, type_name) => {
-                                    self.advance();
-                                    self.class_base_type(type_name)
-                                }
-                                _ => return Err("Expected type for array 2"),
-                            };
-
-                            match self.current()? {
-                                Token::RSquareBrace => self.advance(),
-                                _ => return Err("Expected ] to end array type"),
-                            };
-
-                            BaseType::Array(length as i64, Box::new(array_return_type))
+                            array_type
                         }
                         _ => return Err("Expected a type after the attribute name"),
                     };
@@ -758,6 +1790,7 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
+        let location = self.location_at(self.pos);
         let name = match self.current()? {
             Token::Const(pos, name) => {
                 self.advance()?;
@@ -768,33 +1801,57 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
-        match self.curr() {
-            Token::NewLine(_) => self.advance(),
-            _ => return Err("Expected a new line after class name"),
-        };
+        self.expect(TokenKind::NewLine, "Expected a new line after class name")?;
+
+        self.index
+            .trait_index
+            .entry(name.clone())
+            .or_insert_with(Vec::new);
+        self.define(&name, location);
+
+        // Default methods are namespaced by the trait, e.g. `Speak.greet`, and
+        // receive the implementing instance as `self`
+        mctx.class_name = name.clone();
+        mctx.self_node = Some(Node::SelfRef(SelfRef {
+            return_type: BaseType::Class(name.clone()),
+        }));
 
         loop {
             self.advance_optional_whitespace();
 
             let results = match self.current()? {
-                Token::Def => {
-                    self.parse_def(mctx, "".to_string(), "".to_string(), name.clone(), None)
-                }
+                Token::Def => self.enclosed("def", |parser| {
+                    parser.parse_def(mctx, "".to_string(), "".to_string(), name.clone(), None)
+                }),
                 Token::End => {
                     self.advance();
                     break;
                 }
                 _ => {
-                    println!("{:#?}", self.curr());
+                    tracing::trace!("{:#?}", self.curr());
                     return Err("Expected only def within a trait");
                 }
             };
 
             for result in results? {
+                if let Node::Def(def) = &result {
+                    self.index
+                        .trait_method_index
+                        .entry(name.clone())
+                        .or_insert_with(Vec::new)
+                        .push(TraitMethod {
+                            prototype: def.prototype.clone(),
+                            required: def.body.is_empty(),
+                        });
+                }
+
                 functions.push(result)
             }
         }
 
+        mctx.class_name = "".to_string();
+        mctx.self_node = None;
+
         Ok(functions)
     }
 
@@ -818,15 +1875,13 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_space();
 
-        match self.curr() {
-            Token::NewLine(_) => self.advance(),
-            _ => return Err("Expected a new line after impl name"),
-        };
+        self.expect(TokenKind::NewLine, "Expected a new line after impl name")?;
 
         if let Some(nodes) = self.index.trait_index.get_mut(&impl_name) {
             nodes.push(Class {
                 name: class_name.clone(),
                 attributes: vec![],
+                superclass: None,
             });
         } else {
             self.index.trait_index.insert(
@@ -835,11 +1890,12 @@ impl<'a> Parser<'a> {
                     (Class {
                         name: class_name.clone(),
                         attributes: vec![],
+                        superclass: None,
                     }),
                 ],
             );
 
-            println!("{:#?}", self);
+            tracing::trace!("{:#?}", self);
         };
 
         let mut functions = vec![];
@@ -848,13 +1904,15 @@ impl<'a> Parser<'a> {
             self.advance_optional_whitespace();
 
             let results = match self.current()? {
-                Token::Def => self.parse_def(
-                    mctx,
-                    class_name.clone(),
-                    impl_name.clone(),
-                    "".to_string(),
-                    None,
-                ),
+                Token::Def => self.enclosed("def", |parser| {
+                    parser.parse_def(
+                        mctx,
+                        class_name.clone(),
+                        impl_name.clone(),
+                        "".to_string(),
+                        None,
+                    )
+                }),
                 Token::End => {
                     self.advance();
                     break;
@@ -883,6 +1941,8 @@ impl<'a> Parser<'a> {
         // Advance past 'def' keyword
         self.pos += 1;
 
+        // The name, past the space after the keyword
+        let location = self.location_at(self.pos + 1);
         let prototype = self.parse_prototype(mctx)?;
 
         self.advance_optional_whitespace();
@@ -899,6 +1959,8 @@ impl<'a> Parser<'a> {
             self.advance_optional_whitespace();
 
             match self.current()? {
+                // A required trait method, followed by the next one
+                Token::Def if !trait_name.is_empty() && ctx.body.is_empty() => break,
                 Token::End => {
                     ctx.parsing_returnable_loc = false;
 
@@ -909,9 +1971,26 @@ impl<'a> Parser<'a> {
                     self.advance();
                     break;
                 }
-                _ => {
-                    let expr = self.parse_expr(mctx, &ctx)?;
-                    ctx.body.push(expr);
+                token => {
+                    let start = self.pos;
+                    let (expr_depth, loop_depth) = (self.expr_depth, self.loop_depth);
+
+                    let expr = match token {
+                        Token::Defer => self.parse_defer_expr(mctx, &ctx),
+                        _ => self.parse_expr(mctx, &ctx),
+                    };
+
+                    match expr {
+                        Ok(expr) => ctx.body.push(expr),
+                        Err(message) => {
+                            self.expr_depth = expr_depth;
+                            self.loop_depth = loop_depth;
+
+                            let error = self.recover_statement(message, start)?;
+                            ctx.body.push(error);
+                        }
+                    }
+
                     ctx.parsing_returnable_loc = true
                 }
             }
@@ -931,6 +2010,7 @@ impl<'a> Parser<'a> {
         self.index
             .fn_prototype_index
             .insert(def_node.prototype.name.clone(), def_node.prototype.clone());
+        self.define(&def_node.prototype.name, location);
 
         Ok(vec![Node::Def(def_node)])
 
@@ -967,6 +2047,7 @@ impl<'a> Parser<'a> {
         // Advance past 'def' keyword
         self.pos += 1;
 
+        let location = self.location_at(self.pos + 1);
         let prototype = self.parse_prototype(mctx)?;
 
         self.advance_optional_whitespace();
@@ -988,10 +2069,28 @@ impl<'a> Parser<'a> {
             def_e_node.prototype.name.clone(),
             def_e_node.prototype.clone(),
         );
+        self.define(&def_e_node.prototype.name, location);
 
         Ok(vec![Node::DefE(def_e_node)])
     }
 
+    /// Parses `import "path"`. The file it names was loaded by
+    /// `source::resolve_imports` and is parsed with the others, so the
+    /// statement itself adds nothing to the module.
+    fn parse_import(&mut self) -> Result<Vec<Node>, &'static str> {
+        // Advance past 'import' keyword
+        self.pos += 1;
+        self.advance_optional_space();
+
+        match self.current()? {
+            Token::StringLiteral(_, _) => {
+                self.pos += 1;
+                Ok(vec![])
+            }
+            _ => Err("Expected a path string after import"),
+        }
+    }
+
     /// Parses the prototype of a function, whether external or user-defined.
     fn parse_prototype(&mut self, mctx: &mut ParserModuleCtx) -> Result<Prototype, &'static str> {
         match self.current()? {
@@ -1001,12 +2100,35 @@ impl<'a> Parser<'a> {
             _ => return Err("Expected space after def keyword"),
         }
 
-        let (id, is_operator, precedence) = match self.curr() {
+        let (id, is_operator, precedence) = match self.current()? {
             Token::Ident(pos, id) => {
                 self.advance()?;
 
                 (id, false, 0)
             }
+            Token::Op(op) if OPERATOR_METHODS.iter().any(|(name, _)| *name == op) => {
+                self.advance()?;
+
+                (op, true, 0)
+            }
+            Token::Spaceship => {
+                self.advance()?;
+
+                ("<=>".to_string(), true, 0)
+            }
+            Token::LSquareBrace(_) => {
+                self.advance()?;
+                self.expect(
+                    TokenKind::RSquareBrace,
+                    "Expected ']' after '[' in method name",
+                )?;
+
+                if self.eat(TokenKind::Assign) {
+                    ("[]=".to_string(), true, 0)
+                } else {
+                    ("[]".to_string(), true, 0)
+                }
+            }
             _ => return { Err("Expected identifier in prototype declaration.") },
         };
 
@@ -1020,12 +2142,13 @@ impl<'a> Parser<'a> {
             args.push(Arg {
                 name: "sret".to_string(),
                 return_type: BaseType::Class(mctx.class_name.clone()),
+                default: None,
             });
 
             id = format!("{}.{}", mctx.class_name, id);
         }
 
-        match self.curr() {
+        match self.current()? {
             Token::Arrow => {
                 let return_type = self.parse_return_type()?;
                 return Ok(Prototype {
@@ -1036,7 +2159,7 @@ impl<'a> Parser<'a> {
                     prec: precedence,
                 });
             }
-            Token::LParen => {
+            Token::LParen(_) => {
                 self.advance();
             }
             Token::NewLine(_) => {
@@ -1051,16 +2174,14 @@ impl<'a> Parser<'a> {
                 });
             }
             _ => {
-                println!("{:#?}", self.curr());
+                tracing::trace!("{:#?}", self.curr());
                 return Err("Expected '(' character in prototype declaration. 2");
             }
         }
 
         self.advance_optional_whitespace();
 
-        if let Token::RParen = self.curr() {
-            self.advance();
-
+        if self.eat(TokenKind::RParen) {
             let return_type = self.parse_return_type()?;
 
             return Ok(Prototype {
@@ -1077,7 +2198,7 @@ impl<'a> Parser<'a> {
 
             // println!("{:#?}", self.curr());
 
-            let arg_name = match self.curr() {
+            let arg_name = match self.current()? {
                 Token::Ident(pos, name) => name,
                 _ => return Err("Expected identifier in parameter declaration."),
             };
@@ -1085,60 +2206,33 @@ impl<'a> Parser<'a> {
             self.advance()?;
             self.advance_optional_space();
 
-            let return_type = match self.curr() {
+            let return_type = match self.current()? {
                 Token::Const(pos, type_name) => self.class_base_type(type_name),
-                Token::LSquareBrace => {
-                    self.advance();
-
-                    let length = match self.current()? {
-                        Token::Number(_, n) => n,
-                        _ => return Err("Expected length of array"),
-                    };
-
-                    self.advance_optional_space();
-
-                    match self.current()? {
-                        Token::Ident(_, sym) => {
-                            self.advance();
-
-                            match sym.as_str() {
-                                "x" => {
-                                    self.advance();
-                                    self.advance_optional_space();
-                                }
-                                _ => return Err("Expected an 'x' for such as [4 x Byte]"),
-                            }
-                        }
-                        _ => return Err("Expected type for array 3"),
-                    };
-
-                    let array_return_type = match self.current()? {
-                        Token::Const(_type_pos, type_name) => {
-                            self.advance();
-                            self.class_base_type(type_name)
-                        }
-                        _ => return Err("Expected type for array 4"),
-                    };
+                Token::LSquareBrace(_) => self.parse_array_type()?,
+                _ => return Err("Expected type name for argument"),
+            };
 
-                    match self.current()? {
-                        Token::RSquareBrace => self.advance(),
-                        _ => return Err("Expected ] to end array type"),
-                    };
+            self.advance()?;
+            self.advance_optional_space();
 
-                    BaseType::Array(length as i64, Box::new(array_return_type))
-                }
-                _ => return Err("Expected type name for argument"),
+            let default = if self.eat(TokenKind::Assign) {
+                self.advance_optional_space();
+                Some(self.parse_default_value()?)
+            } else if args.iter().any(|arg: &Arg| arg.default.is_some()) {
+                return Err("Arguments with a default value must come after the ones without.");
+            } else {
+                None
             };
 
             args.push(Arg {
                 name: arg_name,
                 return_type,
+                default,
             });
 
-            self.advance()?;
             self.advance_optional_whitespace();
 
-            match self.curr() {
+            match self.current()? {
                 Token::RParen => {
                     self.advance();
                     break;
@@ -1161,6 +2255,21 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Parses the default value of an argument, which is a literal so it
+    /// means the same at every call site it's given at.
+    fn parse_default_value(&mut self) -> Result<Node, &'static str> {
+        match self.current()? {
+            Token::Float(_, _) | Token::Number(_, _) => self.parse_nb_expr(),
+            Token::False | Token::True => self.parse_bool_expr(),
+            Token::Nil => self.parse_nil_expr(),
+            Token::StringLiteral(_, string) if !string.contains("#{") => {
+                self.advance()?;
+                Ok(Node::StringLiteral(StringLiteral { value: string }))
+            }
+            _ => Err("Expected a number, string, boolean or nil literal as the default value."),
+        }
+    }
+
     fn parse_return_type(&mut self) -> Result<Option<BaseType>, &'static str> {
         match self.current()? {
             Token::NewLine(_) => {
@@ -1174,7 +2283,7 @@ impl<'a> Parser<'a> {
             Token::Space(_) => {
                 self.advance_optional_space();
 
-                match self.curr() {
+                match self.current()? {
                     Token::Arrow => {
                         self.advance()?;
                         self.advance_optional_space();
@@ -1189,27 +2298,87 @@ impl<'a> Parser<'a> {
             _ => return Err("Expected an end to the function definition"),
         }
 
-        match self.curr() {
+        match self.current()? {
             Token::Const(pos, type_name) => {
                 self.advance()?;
                 Ok(Some(self.class_base_type(type_name)))
             }
+            Token::LSquareBrace(_) => {
+                let array_type = self.parse_array_type()?;
+                self.advance()?;
+                Ok(Some(array_type))
+            }
             _ => Err("Expected a return type after an arrow"),
         }
     }
 
+    /// An array type, like `[Int]` or `[[Str]]`, leaving the parser on its
+    /// closing `]`.
+    fn parse_array_type(&mut self) -> Result<BaseType, &'static str> {
+        // Advance past '['
+        self.pos += 1;
+
+        let item_type = match self.current()? {
+            Token::Const(_, type_name) => self.class_base_type(type_name),
+            Token::LSquareBrace(_) => self.parse_array_type()?,
+            _ => return Err("Expected an item type in an array type, such as [Int]"),
+        };
+
+        self.advance()?;
+
+        match self.current()? {
+            Token::RSquareBrace => Ok(BaseType::Array(Box::new(item_type))),
+            _ => Err("Expected ']' to end an array type"),
+        }
+    }
+
     fn parse_expr(
         &mut self,
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
-        match self.parse_unary_expr(mctx, ctx) {
+        self.nested(|parser| match parser.parse_unary_expr(mctx, ctx) {
             Ok(left) => {
-                self.advance_optional_whitespace();
-                self.parse_binary_expr(mctx, ctx, 0, left)
+                parser.advance_optional_whitespace();
+                parser.parse_binary_expr(mctx, ctx, 0, left)
             }
             err => err,
+        })
+    }
+
+    /// Runs `parse` on what the `opener` at the current token encloses, like
+    /// the arguments after a `(` or the body of a `def`. When the input ends
+    /// before it's closed, the opener is what `error_node` reports.
+    fn enclosed<T>(
+        &mut self,
+        opener: &'static str,
+        parse: impl FnOnce(&mut Self) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        self.openers.push((self.pos, opener));
+        let result = parse(self);
+        let opener = self.openers.pop();
+
+        if result.is_err() && self.unclosed.is_none() && self.input_end().is_some() {
+            self.unclosed = opener;
+        }
+
+        result
+    }
+
+    /// Runs `parse` one level deeper, see `MAX_EXPR_DEPTH`.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node, &'static str>,
+    ) -> Result<Node, &'static str> {
+        if self.expr_depth >= MAX_EXPR_DEPTH {
+            return Err("expression too deeply nested");
         }
+
+        self.expr_depth += 1;
+        let result = parse(self);
+        self.expr_depth -= 1;
+
+        result
     }
 
     /// Parses an unary expression.
@@ -1219,21 +2388,64 @@ impl<'a> Parser<'a> {
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
         let op = match self.current()? {
-            Token::Op(ch) => {
+            Token::Op(op) => {
                 self.advance()?;
-                ch
+                op
             }
             _ => return self.parse_primary(mctx, ctx),
         };
 
+        // `&greet` refers to the def `greet`, like `greet.fn_ref()`
+        if op == "&" {
+            return match self.current()? {
+                Token::Ident(_, name) if self.index.fn_prototype_index.contains_key(&name) => {
+                    self.advance()?;
+
+                    Ok(fn_ref_send(name))
+                }
+                _ => Err("Expected the name of a def after `&`, like `&greet`"),
+            };
+        }
+
+        // `~a` flips every bit, which is `a ^ -1`
+        if op == "~" {
+            return Ok(Node::Binary(Binary {
+                op: "^".to_string(),
+                left: Box::new(self.nested(|parser| parser.parse_unary_expr(mctx, ctx))?),
+                right: Box::new(Node::Int(Int { value: u64::MAX })),
+                return_type: None,
+            }));
+        }
+
+        // `-5` is a negative constant, and `-a` is `0 - a`
+        if op == "-" {
+            return Ok(
+                match self.nested(|parser| parser.parse_unary_expr(mctx, ctx))? {
+                    Node::Int(int) => Node::Int(Int {
+                        value: (int.value as i64).wrapping_neg() as u64,
+                    }),
+                    Node::Float(float) => Node::Float(Float {
+                        value: -float.value,
+                    }),
+                    operand => Node::Binary(Binary {
+                        op: "-".to_string(),
+                        left: Box::new(Node::Int(Int { value: 0 })),
+                        right: Box::new(operand),
+                        return_type: None,
+                    }),
+                },
+            );
+        }
+
         let mut name = String::from("unary");
 
-        name.push(op);
+        name.push_str(&op);
 
         Ok(Node::Call(Call {
             fn_name: name,
-            args: vec![self.parse_unary_expr(mctx, ctx)?],
+            args: vec![self.nested(|parser| parser.parse_unary_expr(mctx, ctx))?],
             return_type: None,
+            arg_names: vec![],
         }))
     }
 
@@ -1245,20 +2457,41 @@ impl<'a> Parser<'a> {
         // println!("current:");
         // println!("{:#?}", self.curr());
 
-        let node = match self.curr() {
+        let node = match self.current()? {
             Token::Attribute(_, _) => self.parse_attribute_expr(mctx, ctx),
+            Token::Break => self.parse_loop_exit_expr(Node::Break),
             Token::Const(_, _) => self.parse_const_expr(mctx, ctx),
+            Token::Defer => Err("defer can only be used at the root of a def"),
+            Token::False | Token::True => self.parse_bool_expr(),
             Token::Ident(_, _) => self.parse_ident_expr(mctx, ctx),
-            Token::Loop => self.parse_loop_expr(mctx, ctx),
-            Token::LParen => self.parse_paren_expr(mctx, ctx),
-            Token::LSquareBrace => self.parse_array_expr(mctx, ctx),
-            Token::Number(_, _) => self.parse_nb_expr(),
+            Token::If => self.enclosed("if", |parser| parser.parse_if_expr(mctx, ctx)),
+            Token::Loop => self.enclosed("loop", |parser| parser.parse_loop_expr(mctx, ctx)),
+            Token::LParen(_) => self.enclosed("(", |parser| parser.parse_paren_expr(mctx, ctx)),
+            Token::LSquareBrace(_) => {
+                self.enclosed("[", |parser| parser.parse_array_expr(mctx, ctx))
+            }
+            Token::Next => self.parse_loop_exit_expr(Node::Next),
+            Token::Nil => self.parse_nil_expr(),
+            Token::Float(_, _) | Token::Number(_, _) => self.parse_nb_expr(),
             Token::Ret => self.parse_ret_expr(mctx, ctx),
             Token::SelfRef => self.parse_self_ref_expr(mctx, ctx),
-            Token::StringLiteral(_, _) => self.parse_string_expr(),
+            Token::StringLiteral(_, _) => self.parse_string_expr(mctx, ctx),
+            Token::Super => self.parse_super_expr(mctx, ctx),
+            Token::While => self.enclosed("while", |parser| parser.parse_while_expr(mctx, ctx)),
+            // The lexer keeps the digits of a literal too big for a u64
+            Token::Illegal(_, text) if text.bytes().all(|byte| byte.is_ascii_digit()) => {
+                Err("Integer literal too big for 64 bits.")
+            }
+            // A string the input ended in, see `enclosed`
+            Token::Illegal(_, text) if text.starts_with('"') => {
+                self.enclosed("\"", |parser| {
+                    parser.pos += 1;
+                    Err("Unexpected end of file.")
+                })
+            }
             _ => {
-                println!("Debug:");
-                println!("{:#?}", self.curr());
+                tracing::trace!("Debug:");
+                tracing::trace!("{:#?}", self.curr());
 
                 // panic!("{:#?}", self.curr());
                 // panic!("{:#?}", self);
@@ -1266,20 +2499,98 @@ impl<'a> Parser<'a> {
             }
         };
 
+        let node = match node {
+            Ok(node) => self.parse_postfix_expr(mctx, ctx, node),
+            err => err,
+        };
+
         self.advance_optional_whitespace();
 
-        match self.curr() {
-            Token::Dot => self.parse_dot_expr(mctx, ctx, node),
+        match self.kind() {
+            Some(TokenKind::Dot | TokenKind::SafeNav) => self.parse_dot_expr(mctx, ctx, node),
             _ => node,
         }
     }
 
+    /// Indexes and calls right after an expression, like `items[0]` or
+    /// `make_adder(1)(2)`.
+    fn parse_postfix_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+        node: Node,
+    ) -> Result<Node, &'static str> {
+        match self.kind() {
+            Some(TokenKind::LSquareBrace) => self.nested(|parser| {
+                parser.enclosed("[", |parser| parser.parse_index_expr(mctx, ctx, node))
+            }),
+            Some(TokenKind::LParen) => {
+                let args = self.positional_call_args(mctx, ctx)?;
+
+                self.nested(|parser| parser.parse_postfix_expr(mctx, ctx, call_send(node, args)))
+            }
+            _ => Ok(node),
+        }
+    }
+
+    /// `items[key]` sends `[]` to `items`, and `items[key] = value` sends `[]=`.
+    /// A range, like `text[start..end]`, is given as its two ends.
+    fn parse_index_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+        receiver: Node,
+    ) -> Result<Node, &'static str> {
+        // Advance past '['
+        self.pos += 1;
+        self.advance_optional_whitespace();
+
+        let mut keys = vec![self.parse_expr(mctx, ctx)?];
+
+        self.advance_optional_whitespace();
+
+        if self.eat(TokenKind::DotDot) {
+            self.advance_optional_whitespace();
+            keys.push(self.parse_expr(mctx, ctx)?);
+            self.advance_optional_whitespace();
+        }
+
+        self.expect(
+            TokenKind::RSquareBrace,
+            "Expected ']' character after index.",
+        )?;
+        self.advance_optional_space();
+
+        let (fn_name, args) = if self.eat(TokenKind::Assign) {
+            self.advance_optional_whitespace();
+
+            keys.push(self.parse_expr(mctx, ctx)?);
+            ("[]=", keys)
+        } else {
+            ("[]", keys)
+        };
+
+        let node = Node::Send(Send {
+            receiver: Box::new(receiver),
+            message: Box::new(Node::Call(Call {
+                fn_name: fn_name.to_string(),
+                args,
+                return_type: None,
+                arg_names: vec![],
+            })),
+            return_type: None,
+            safe_navigation: false,
+        });
+
+        self.parse_postfix_expr(mctx, ctx, node)
+    }
+
     fn parse_attribute_expr(
         &mut self,
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
-        match self.curr() {
+        match self.current()? {
             Token::Attribute(pos, name) => {
                 self.advance();
                 self.advance_optional_whitespace();
@@ -1315,7 +2626,7 @@ impl<'a> Parser<'a> {
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
-        match self.curr() {
+        match self.current()? {
             Token::Ret => {
                 if !ctx.parsing_returnable_loc {
                     return Err("Return can only be used at the root of a function.");
@@ -1331,12 +2642,30 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses `defer expr`, which runs `expr` once the def returns, see
+    /// `apply_defers`. It's kept as a call to `defer` until then.
+    fn parse_defer_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Node, &'static str> {
+        self.advance()?; // Advance past 'defer' keyword
+        self.advance_optional_whitespace();
+
+        Ok(Node::Call(Call {
+            fn_name: "defer".to_string(),
+            args: vec![self.parse_expr(mctx, ctx)?],
+            return_type: None,
+            arg_names: vec![],
+        }))
+    }
+
     fn parse_self_ref_expr(
         &mut self,
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
-        match self.curr() {
+        match self.current()? {
             Token::SelfRef => {
                 self.advance();
 
@@ -1348,69 +2677,141 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Parses an expression that starts with an identifier (either a variable or a function call).
-    fn parse_ident_expr(
+    /// Parses `super` or `super(args)` into a direct call of the method the
+    /// current one overrides. A bare `super` forwards the current arguments.
+    fn parse_super_expr(
         &mut self,
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
-        let ident_name = match self.curr() {
-            Token::Ident(pos, id) => {
-                self.advance();
-                id
-            }
-            _ => return Err("Expected identifier."),
+        self.advance()?;
+
+        if ctx.class_name.is_empty() || self.index.trait_index.contains_key(&ctx.class_name) {
+            return Err("super can only be used inside a class method");
+        }
+
+        let method_name = match ctx.prototype.name.split_once('.') {
+            Some((_, method_name)) => method_name.to_string(),
+            None => return Err("super can only be used inside a class method"),
         };
 
-        self.advance_optional_whitespace();
+        let fn_name = match self.index.resolve_super_method(
+            &ctx.class_name,
+            mctx.superclass.as_deref(),
+            &method_name,
+        ) {
+            Ok(Some(fn_name)) => fn_name,
+            Ok(None) => return Err("No superclass or trait method found for super"),
+            Err(_) => return Err("Ambiguous super call, more than one trait provides the method"),
+        };
 
-        match self.curr() {
-            Token::LParen => {
+        let mut args = vec![Node::SelfRef(SelfRef {
+            return_type: BaseType::Class(ctx.class_name.clone()),
+        })];
+
+        match self.current()? {
+            Token::LParen(_) => {
                 self.advance()?;
                 self.advance_optional_whitespace();
 
-                if let Token::RParen = self.curr() {
-                    self.advance();
+                if !self.eat(TokenKind::RParen) {
+                    loop {
+                        self.advance_optional_whitespace();
 
-                    return Ok(Node::Call(Call {
-                        fn_name: ident_name,
-                        args: vec![],
-                        return_type: None,
+                        args.push(self.parse_expr(mctx, ctx)?);
+
+                        self.advance_optional_whitespace();
+
+                        match self.current()? {
+                            Token::RParen => {
+                                self.advance()?;
+                                break;
+                            }
+                            Token::Comma => {
+                                self.advance()?;
+                            }
+                            _ => return Err("Expected ',' or ')' character in super call."),
+                        }
+                    }
+                }
+            }
+            _ => {
+                for arg in ctx.prototype.args.iter().skip(1) {
+                    args.push(Node::LocalVar(LocalVar {
+                        name: arg.name.clone(),
+                        return_type: Some(arg.return_type.clone()),
                     }));
                 }
+            }
+        }
 
-                let mut args = vec![];
-
-                loop {
-                    self.advance_optional_whitespace();
+        Ok(Node::Call(Call {
+            fn_name,
+            args,
+            return_type: None,
+            arg_names: vec![],
+        }))
+    }
 
-                    args.push(self.parse_expr(mctx, ctx)?);
+    /// Parses an expression that starts with an identifier (either a variable or a function call).
+    fn parse_ident_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Node, &'static str> {
+        let location = self.location_at(self.pos);
+        let ident_name = match self.current()? {
+            Token::Ident(pos, id) => {
+                self.advance();
+                id
+            }
+            _ => return Err("Expected identifier."),
+        };
 
-                    self.advance_optional_whitespace();
+        self.advance_optional_whitespace();
 
-                    match self.curr() {
-                        Token::RParen => {
-                            self.advance();
-                            break;
-                        }
-                        Token::Comma => {
-                            self.advance();
-                        }
-                        _ => return Err("Expected ',' or ')' character in function call."),
+        match self.current()? {
+            Token::Do if ident_name == "at_exit" => {
+                self.enclosed("do", |parser| parser.parse_at_exit_expr(mctx))
+            }
+            Token::LParen(_) => {
+                let (args, arg_names) =
+                    self.enclosed("(", |parser| parser.parse_call_args(mctx, ctx))?;
+
+                // `adder(1)` on a local sends it `call`
+                if !self.index.fn_prototype_index.contains_key(&ident_name)
+                    && is_local_var(ctx, &ident_name)
+                {
+                    if !arg_names.is_empty() {
+                        return Err(FN_REF_ARG_NAMES);
                     }
+
+                    let receiver = self.parse_local_var(ctx, ident_name)?;
+
+                    return Ok(call_send(receiver, args));
                 }
 
+                self.index
+                    .call_site_index
+                    .entry(ident_name.clone())
+                    .or_insert_with(Vec::new)
+                    .push(CallSite {
+                        caller: ctx.prototype.name.clone(),
+                        location,
+                    });
+
                 Ok(Node::Call(Call {
                     fn_name: ident_name,
                     args,
                     return_type: None,
+                    arg_names,
                 }))
             }
 
             _ => {
                 self.advance_optional_space();
 
-                match self.curr() {
+                match self.current()? {
                     Token::Assign => {
                         self.advance()?;
                         self.advance_optional_whitespace();
@@ -1420,79 +2821,261 @@ impl<'a> Parser<'a> {
                             value: Box::new(self.parse_expr(mctx, ctx)?),
                         }))
                     }
-                    _ => {
-                        // After all that, it's just a lvar. Fetch the type from the nearest assignment.
-                        let closest_assignment = ctx.body.iter().rev().find(|node| match node {
-                            Node::AssignLocalVar(asgnLvar) => asgnLvar.name == ident_name,
-                            _ => false,
-                        });
+                    _ => self.parse_local_var(ctx, ident_name),
+                }
+            }
+        }
+    }
+
+    /// Parses `at_exit do ... end`, starting at `do`. The block becomes a def
+    /// of its own, which is registered with `at_exit` like
+    /// `at_exit(handler.fn_ref())` would. It runs once the program has ended,
+    /// so it can't use the locals of the def it's in.
+    fn parse_at_exit_expr(&mut self, mctx: &mut ParserModuleCtx) -> Result<Node, &'static str> {
+        self.advance()?; // Advance past 'do' keyword
+
+        let fn_name = format!("__at_exit_{}", self.at_exit_defs.len() + 1);
+        let prototype = Prototype {
+            name: fn_name.clone(),
+            args: vec![],
+            return_type: None,
+            is_op: false,
+            prec: 0,
+        };
+
+        let mut ctx = ParserFunctionCtx {
+            class_name: "".to_string(),
+            body: vec![],
+            prototype,
+            parsing_dot: false,
+            parsing_returnable_loc: true,
+        };
+
+        // `break` and `next` can't leave the block for a loop around it
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+
+        loop {
+            self.advance_optional_whitespace();
+
+            match self.current()? {
+                Token::End => {
+                    self.advance()?;
+                    break;
+                }
+                _ => {
+                    let expr = self.parse_expr(mctx, &ctx)?;
+                    ctx.body.push(expr);
+                }
+            }
+        }
+
+        self.loop_depth = loop_depth;
+
+        self.index
+            .fn_prototype_index
+            .insert(fn_name.clone(), ctx.prototype.clone());
+        self.at_exit_defs.push(Node::Def(Def {
+            main_fn: false,
+            prototype: ctx.prototype,
+            body: ctx.body,
+            class_name: "".to_string(),
+            impl_name: "".to_string(),
+            trait_name: "".to_string(),
+        }));
+
+        Ok(Node::Call(Call {
+            fn_name: "at_exit".to_string(),
+            args: vec![fn_ref_send(fn_name)],
+            return_type: None,
+            arg_names: vec![],
+        }))
+    }
+
+    /// Parses `(arg, ..., name: arg, ...)`, starting at the '('. Returns the
+    /// args and the names of the ones given by keyword, see `Call::arg_names`.
+    fn parse_call_args(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<(Vec<Node>, Vec<String>), &'static str> {
+        self.advance()?;
+        self.advance_optional_whitespace();
+
+        let mut args = vec![];
+        let mut arg_names = vec![];
+
+        if self.eat(TokenKind::RParen) {
+            return Ok((args, arg_names));
+        }
+
+        loop {
+            self.advance_optional_whitespace();
+
+            match (self.current()?, self.tokens.get(self.pos + 1)) {
+                (Token::Ident(_, name), Some(Token::Colon)) => {
+                    self.advance()?;
+                    self.advance()?;
+                    self.advance_optional_space();
+
+                    arg_names.push(name);
+                }
+                _ if !arg_names.is_empty() => {
+                    return Err("Positional arguments must come before named ones.")
+                }
+                _ => {}
+            }
+
+            args.push(self.parse_expr(mctx, ctx)?);
+
+            self.advance_optional_whitespace();
 
-                        match closest_assignment {
-                            Some(asgnLvar) => match asgnLvar {
-                                Node::AssignLocalVar(asgnLvar) => {
-                                    let return_type_name = match asgnLvar.value.as_ref() {
-                                        Node::Call(call) => {
-                                            self.pajama_class_name(&call.return_type)
-                                        }
-                                        Node::Int(_) => "Int".to_string(),
-                                        Node::LocalVar(val) => val.pajama_class_name().to_string(),
-                                        Node::Send(send) => {
-                                            self.pajama_class_name(&send.return_type)
-                                        }
-                                        Node::StringLiteral(_) => "Str".to_string(),
-                                        Node::BuildStruct(build) => {
-                                            return Ok(Node::LocalVar(LocalVar {
-                                                name: ident_name,
-                                                return_type: Some(build.return_type.clone()),
-                                            }))
-                                        }
-                                        Node::Array(array) => {
-                                            return Ok(Node::LocalVar(LocalVar {
-                                                name: ident_name,
-                                                return_type: Some(BaseType::Array(
-                                                    array.length,
-                                                    Box::new(array.item_type.clone()),
-                                                )),
-                                            }))
-                                        }
-                                        _ => {
-                                            println!("{:#?}", asgnLvar.value.as_ref());
-                                            return Err("Local variable assignment was given an unsupprted node, given");
-                                        }
-                                    };
-
-                                    Ok(Node::LocalVar(LocalVar {
+            match self.current()? {
+                Token::RParen => {
+                    self.advance()?;
+                    break;
+                }
+                Token::Comma => {
+                    self.advance()?;
+                }
+                _ => return Err("Expected ',' or ')' character in function call."),
+            }
+        }
+
+        Ok((args, arg_names))
+    }
+
+    /// `parse_call_args` for a call through a function reference, which
+    /// doesn't know the names of its arguments.
+    fn positional_call_args(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Vec<Node>, &'static str> {
+        match self.enclosed("(", |parser| parser.parse_call_args(mctx, ctx))? {
+            (args, arg_names) if arg_names.is_empty() => Ok(args),
+            _ => Err(FN_REF_ARG_NAMES),
+        }
+    }
+
+    /// A local variable or argument, typed from its nearest assignment. That
+    /// it's assigned on every path to here is checked by the semantic analyzer.
+    fn parse_local_var(
+        &self,
+        ctx: &ParserFunctionCtx,
+        ident_name: String,
+    ) -> Result<Node, &'static str> {
+        let closest_assignment = closest_assignment(&ctx.body, &ident_name);
+
+        match closest_assignment {
+            Some(asgn_lvar) => match asgn_lvar {
+                // A local assigned `&greet` stands for the reference itself,
+                // which is known while parsing
+                Node::AssignLocalVar(assignment) if referenced_def(&assignment.value).is_some() => {
+                    Ok(assignment.value.as_ref().clone())
+                }
+                Node::AssignLocalVar(asgn_lvar) => {
+                    let return_type_name = match asgn_lvar.value.as_ref() {
+                        Node::Call(call) => {
+                            // Prototypes are predeclared, so this also
+                            // covers functions defined further down.
+                            let prototype_return_type = self
+                                .index
+                                .fn_prototype_index
+                                .get(&call.fn_name)
+                                .and_then(|prototype| prototype.return_type.clone());
+
+                            match prototype_return_type {
+                                Some(return_type) => {
+                                    return Ok(Node::LocalVar(LocalVar {
                                         name: ident_name,
-                                        return_type: Some(BaseType::Class(return_type_name)),
+                                        return_type: Some(return_type),
                                     }))
                                 }
-                                _ => Err("Node other than AssignLocalVar in closest_assignment"),
-                            },
-                            None => {
-                                let arg_assignment = ctx
-                                    .prototype
-                                    .args
-                                    .iter()
-                                    .find(|node| node.name == ident_name);
-
-                                println!("{:#?}", ident_name);
-
-                                match arg_assignment {
-                                    Some(arg) => Ok(Node::LocalVar(LocalVar {
-                                        name: ident_name,
-                                        return_type: Some(BaseType::Class(
-                                            arg.pajama_class_name().to_string(),
-                                        )),
-                                    })),
-                                    // maybe a function reference, or just a typo lool
-                                    None => Ok(Node::LocalVar(LocalVar {
-                                        name: ident_name,
-                                        return_type: None,
-                                    })),
-                                }
+                                None => self.pajama_class_name(&call.return_type),
                             }
                         }
-                    }
+                        Node::Bool(_) => {
+                            return Ok(Node::LocalVar(LocalVar {
+                                name: ident_name,
+                                return_type: Some(BaseType::Bool),
+                            }))
+                        }
+                        Node::Float(_) => "Float".to_string(),
+                        Node::Int(_) => "Int".to_string(),
+                        Node::Nil(_) => {
+                            return Ok(Node::LocalVar(LocalVar {
+                                name: ident_name,
+                                return_type: Some(BaseType::Nil),
+                            }))
+                        }
+                        Node::LocalVar(val) => val.pajama_class_name().to_string(),
+                        Node::Send(send) => self.pajama_class_name(&send.return_type),
+                        Node::StringLiteral(_) => "Str".to_string(),
+                        Node::BuildStruct(build) => {
+                            return Ok(Node::LocalVar(LocalVar {
+                                name: ident_name,
+                                return_type: Some(build.return_type.clone()),
+                            }))
+                        }
+                        // The item type is inferred from the items later
+                        Node::Array(_) => {
+                            return Ok(Node::LocalVar(LocalVar {
+                                name: ident_name,
+                                return_type: None,
+                            }))
+                        }
+                        // The type of an `if` depends on both of
+                        // its branches, and of a binary on its
+                        // operator, they're inferred later
+                        Node::Binary(_) | Node::If(_) => {
+                            return Ok(Node::LocalVar(LocalVar {
+                                name: ident_name,
+                                return_type: None,
+                            }))
+                        }
+                        _ => {
+                            tracing::trace!("{:#?}", asgn_lvar.value.as_ref());
+                            return Err(
+                                "Local variable assignment was given an unsupprted node, given",
+                            );
+                        }
+                    };
+
+                    Ok(Node::LocalVar(LocalVar {
+                        name: ident_name,
+                        return_type: Some(BaseType::Class(return_type_name)),
+                    }))
+                }
+                _ => Err("Node other than AssignLocalVar in closest_assignment"),
+            },
+            None => {
+                let arg_assignment = ctx
+                    .prototype
+                    .args
+                    .iter()
+                    .find(|node| node.name == ident_name);
+
+                tracing::trace!("{:#?}", ident_name);
+
+                match arg_assignment {
+                    Some(
+                        arg @ Arg {
+                            return_type: BaseType::Array(_) | BaseType::Optional(_),
+                            ..
+                        },
+                    ) => Ok(Node::LocalVar(LocalVar {
+                        name: ident_name,
+                        return_type: Some(arg.return_type.clone()),
+                    })),
+                    Some(arg) => Ok(Node::LocalVar(LocalVar {
+                        name: ident_name,
+                        return_type: Some(BaseType::Class(arg.pajama_class_name().to_string())),
+                    })),
+                    // maybe a function reference, or just a typo lool
+                    None => Ok(Node::LocalVar(LocalVar {
+                        name: ident_name,
+                        return_type: None,
+                    })),
                 }
             }
         }
@@ -1509,17 +3092,31 @@ impl<'a> Parser<'a> {
             Err(err) => return Err(err),
         };
 
+        let safe_navigation = self.kind() == Some(TokenKind::SafeNav);
+
         self.advance();
 
         let node = match self.peek()? {
-            Token::LParen => match self.parse_dot_send_expr(mctx, ctx) {
+            // Only sends have a result that can be nil
+            _ if safe_navigation && self.kind() != Some(TokenKind::Ident) => {
+                return Err("Expected a message to send after `&.`, like `name&.upcase()`")
+            }
+            Token::LParen(_) => match self.parse_dot_send_expr(mctx, ctx) {
                 Ok(node) => Ok(Node::Send(Send {
                     receiver: Box::new(receiver),
                     message: Box::new(node),
                     return_type: None,
+                    safe_navigation,
                 })),
                 Err(err) => return Err(err),
             },
+            _ if safe_navigation => {
+                return Err("Expected a message to send after `&.`, like `name&.upcase()`")
+            }
+            // `adder.(1)` sends `call`
+            _ if self.kind() == Some(TokenKind::LParen) => {
+                Ok(call_send(receiver, self.positional_call_args(mctx, ctx)?))
+            }
             _ => match self.parse_dot_attribute_expr(mctx, ctx) {
                 Ok(node) => Ok(Node::Access(Access {
                     receiver: Box::new(receiver),
@@ -1534,9 +3131,16 @@ impl<'a> Parser<'a> {
 
         self.advance_optional_whitespace();
 
-        match self.curr() {
-            Token::Dot => self.parse_dot_expr(mctx, ctx, node),
+        match self.current()? {
+            Token::Dot | Token::SafeNav => {
+                self.nested(|parser| parser.parse_dot_expr(mctx, ctx, node))
+            }
             Token::Assign => self.parse_assignment_expr(mctx, ctx, node),
+            Token::LSquareBrace(_) => {
+                let node = node?;
+
+                self.enclosed("[", |parser| parser.parse_index_expr(mctx, ctx, node))
+            }
             _ => node,
         }
     }
@@ -1555,7 +3159,7 @@ impl<'a> Parser<'a> {
         self.advance();
         self.advance_optional_whitespace();
 
-        let value = Box::new(self.parse_expr(mctx, ctx).unwrap());
+        let value = Box::new(self.parse_expr(mctx, ctx)?);
 
         match receiver {
             Node::Access(access) => Ok(Node::AssignAttributeAccess(AssignAttributeAccess {
@@ -1572,7 +3176,10 @@ impl<'a> Parser<'a> {
             Node::Const(_) => todo!(),
             Node::Def(_) => todo!(),
             Node::DefE(_) => todo!(),
+            Node::Error(_) => todo!(),
             Node::Impl(_) => todo!(),
+            Node::Bool(_) => todo!(),
+            Node::Float(_) => todo!(),
             Node::Int(_) => todo!(),
             Node::LocalVar(_) => todo!(),
             Node::Loop(_) => todo!(),
@@ -1587,6 +3194,11 @@ impl<'a> Parser<'a> {
             Node::BuildStruct(_) => todo!(),
             Node::Array(_) => todo!(),
             Node::FnRef(_) => todo!(),
+            Node::If(_) => todo!(),
+            Node::While(_) => todo!(),
+            Node::Break => todo!(),
+            Node::Next => todo!(),
+            Node::Nil(_) => todo!(),
         }
     }
 
@@ -1618,26 +3230,149 @@ impl<'a> Parser<'a> {
 
     /// Parses a literal number.
     fn parse_nb_expr(&mut self) -> Result<Node, &'static str> {
-        match self.curr() {
-            Token::Number(pos, nb) => {
-                self.advance();
+        match self.current()? {
+            // Ints are signed, `~` and `-` make the values above this
+            Token::Number(_, nb) if nb > i64::MAX as u64 => {
+                Err("Integer literal too big for 64 bits.")
+            }
+            Token::Number(_, nb) => {
+                self.advance()?;
                 Ok(Node::Int(Int { value: nb }))
             }
+            Token::Float(_, value) => {
+                self.advance()?;
+                Ok(Node::Float(Float { value }))
+            }
             _ => Err("Expected number literal."),
         }
     }
 
+    /// Parses `true` or `false`.
+    fn parse_bool_expr(&mut self) -> Result<Node, &'static str> {
+        let value = match self.current()? {
+            Token::True => true,
+            Token::False => false,
+            _ => return Err("Expected boolean literal."),
+        };
+
+        self.advance()?;
+
+        Ok(Node::Bool(Bool { value }))
+    }
+
+    fn parse_nil_expr(&mut self) -> Result<Node, &'static str> {
+        self.advance()?;
+
+        Ok(Node::Nil(Nil { return_type: None }))
+    }
+
     /// Parses a literal string.
-    fn parse_string_expr(&mut self) -> Result<Node, &'static str> {
-        match self.curr() {
+    fn parse_string_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Node, &'static str> {
+        match self.current()? {
             Token::StringLiteral(pos, string) => {
                 self.advance();
-                Ok(Node::StringLiteral(StringLiteral { value: string }))
+
+                if string.contains("#{") {
+                    self.parse_interpolation(mctx, ctx, &string)
+                } else {
+                    Ok(Node::StringLiteral(StringLiteral { value: string }))
+                }
             }
             _ => Err("Expected string literal."),
         }
     }
 
+    /// Splits `"Hi #{name}!"` into `"Hi "`, `name` and `"!"`, see
+    /// `INTERPOLATE_FN`.
+    fn parse_interpolation(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+        string: &str,
+    ) -> Result<Node, &'static str> {
+        let mut parts = vec![];
+        let mut rest = string;
+
+        while let Some(start) = rest.find("#{") {
+            if start > 0 {
+                parts.push(Node::StringLiteral(StringLiteral {
+                    value: rest[..start].to_string(),
+                }));
+            }
+
+            let source = &rest[start + 2..];
+            let mut depth = 0;
+
+            let end = source
+                .char_indices()
+                .find(|(_, ch)| {
+                    match ch {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => return true,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+
+                    false
+                })
+                .map(|(end, _)| end)
+                .ok_or("Expected '}' at the end of an interpolation.")?;
+
+            parts.push(self.parse_embedded_expr(mctx, ctx, &source[..end])?);
+            rest = &source[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Node::StringLiteral(StringLiteral {
+                value: rest.to_string(),
+            }));
+        }
+
+        Ok(Node::Call(Call {
+            fn_name: INTERPOLATE_FN.to_string(),
+            args: parts,
+            return_type: Some(BaseType::Class("Str".to_string())),
+            arg_names: vec![],
+        }))
+    }
+
+    /// Parses the expression between `#{` and `}`, with its own tokens.
+    fn parse_embedded_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+        source: &str,
+    ) -> Result<Node, &'static str> {
+        let mut tokens = Lexer::new(source).tokenize();
+
+        // Expressions stop at `end`, like at the end of a block
+        tokens.push(Token::NewLine(1));
+        tokens.push(Token::End);
+
+        let tokens = std::mem::replace(&mut self.tokens, tokens.into());
+        let pos = std::mem::replace(&mut self.pos, 0);
+
+        self.advance_optional_whitespace();
+
+        let expr = self.parse_expr(mctx, ctx);
+
+        self.advance_optional_whitespace();
+
+        let complete = self.kind() == Some(TokenKind::End);
+
+        self.tokens = tokens;
+        self.pos = pos;
+
+        match expr {
+            Ok(_) if !complete => Err("Expected a single expression in an interpolation."),
+            expr => expr,
+        }
+    }
+
     fn parse_const_expr(
         &mut self,
         mctx: &mut ParserModuleCtx,
@@ -1651,7 +3386,7 @@ impl<'a> Parser<'a> {
         //     _ => Err("Expected string literal."),
         // }
 
-        let const_name = match self.curr() {
+        let const_name = match self.current()? {
             Token::Const(pos, name) => {
                 self.advance();
                 name
@@ -1659,35 +3394,9 @@ impl<'a> Parser<'a> {
             _ => return Err("Expected string literal."),
         };
 
-        match self.curr() {
-            Token::LParen => {
-                self.advance()?;
-                self.advance_optional_whitespace();
-
-                if let Token::RParen = self.curr() {
-                    return Err("At least one struct field is required");
-                }
-
-                let mut args = vec![];
-
-                loop {
-                    self.advance_optional_whitespace();
-
-                    args.push(self.parse_expr(mctx, ctx)?);
-
-                    self.advance_optional_whitespace();
-
-                    match self.curr() {
-                        Token::RParen => {
-                            self.advance();
-                            break;
-                        }
-                        Token::Comma => {
-                            self.advance();
-                        }
-                        _ => return Err("Expected ',' or ')' character in struct build."),
-                    }
-                }
+        match self.current()? {
+            Token::LParen(_) => {
+                let args = self.enclosed("(", |parser| parser.parse_struct_fields(mctx, ctx))?;
 
                 Ok(Node::BuildStruct(BuildStruct {
                     name: const_name.clone(),
@@ -1699,6 +3408,43 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// The fields of a struct build, like `Point(1, 2)`, starting at its `(`.
+    fn parse_struct_fields(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Vec<Node>, &'static str> {
+        self.advance()?;
+        self.advance_optional_whitespace();
+
+        if self.kind() == Some(TokenKind::RParen) {
+            return Err("At least one struct field is required");
+        }
+
+        let mut args = vec![];
+
+        loop {
+            self.advance_optional_whitespace();
+
+            args.push(self.parse_expr(mctx, ctx)?);
+
+            self.advance_optional_whitespace();
+
+            match self.current()? {
+                Token::RParen => {
+                    self.advance()?;
+                    break;
+                }
+                Token::Comma => {
+                    self.advance()?;
+                }
+                _ => return Err("Expected ',' or ')' character in struct build."),
+            }
+        }
+
+        Ok(args)
+    }
+
     /// Parses an expression enclosed in parenthesis.
     fn parse_paren_expr(
         &mut self,
@@ -1706,7 +3452,7 @@ impl<'a> Parser<'a> {
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
         match self.current()? {
-            Token::LParen => (),
+            Token::LParen(_) => (),
             _ => return Err("Expected '(' character at start of parenthesized expression."),
         }
 
@@ -1731,26 +3477,21 @@ impl<'a> Parser<'a> {
         ctx: &ParserFunctionCtx,
     ) -> Result<Node, &'static str> {
         match self.current()? {
-            Token::LSquareBrace => (),
+            Token::LSquareBrace(_) => (),
             _ => return Err("Expected '[' character at start of an array."),
         }
 
         self.advance_optional_whitespace();
         self.advance()?;
+        self.advance_optional_whitespace();
 
         let mut items = vec![];
 
-        match self.current()? {
-            Token::RSquareBrace => {
-                self.advance()?;
-                let default_type = BaseType::Byte;
-                return Ok(Node::Array(Array {
-                    items,
-                    item_type: default_type,
-                    length: 0,
-                }));
-            }
-            _ => {}
+        if self.eat(TokenKind::RSquareBrace) {
+            return Ok(Node::Array(Array {
+                items,
+                item_type: BaseType::Int,
+            }));
         }
 
         loop {
@@ -1760,7 +3501,7 @@ impl<'a> Parser<'a> {
 
             self.advance_optional_whitespace();
 
-            match self.curr() {
+            match self.current()? {
                 Token::RSquareBrace => {
                     self.advance();
                     break;
@@ -1772,16 +3513,11 @@ impl<'a> Parser<'a> {
             }
         }
 
-        // Hardcode byte for now. Use semantic analysis to look at the first
-        // element for dynamic types
-        let item_type = BaseType::Byte;
-
-        let length = items.len() as i64;
-
+        // Inferred from the items during semantic analysis, an empty array
+        // holds Ints
         Ok(Node::Array(Array {
             items,
-            item_type,
-            length,
+            item_type: BaseType::Int,
         }))
     }
 
@@ -1794,12 +3530,14 @@ impl<'a> Parser<'a> {
         self.advance_optional_whitespace();
 
         match self.current()? {
-            Token::LCurlyBrace => self.advance()?,
+            Token::LCurlyBrace(_) => self.advance()?,
             _ => return Err("Expected a curly brace after loop"),
         }
 
         let mut body = vec![];
 
+        self.loop_depth += 1;
+
         loop {
             self.advance_optional_whitespace();
 
@@ -1814,13 +3552,138 @@ impl<'a> Parser<'a> {
             }
         }
 
+        self.loop_depth -= 1;
+
         let loop_node = Loop { body };
 
         Ok(Node::Loop(loop_node))
     }
 
+    fn parse_while_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Node, &'static str> {
+        self.pos += 1; // Advance past 'while' keyword
+        self.advance_optional_space();
+
+        let condition = self.nested(|parser| parser.parse_expr(mctx, ctx))?;
+
+        let mut body = vec![];
+
+        self.loop_depth += 1;
+
+        loop {
+            self.advance_optional_whitespace();
+
+            match self.current()? {
+                Token::End => {
+                    self.advance()?;
+                    break;
+                }
+                _ => body.push(self.parse_expr(mctx, ctx)?),
+            }
+        }
+
+        self.loop_depth -= 1;
+
+        Ok(Node::While(While {
+            condition: Box::new(condition),
+            body,
+        }))
+    }
+
+    /// Parses `break` or `next`, given the node it becomes.
+    fn parse_loop_exit_expr(&mut self, node: Node) -> Result<Node, &'static str> {
+        if self.loop_depth == 0 {
+            return match node {
+                Node::Break => Err("`break` can only be used in a loop"),
+                _ => Err("`next` can only be used in a loop"),
+            };
+        }
+
+        self.pos += 1; // Advance past 'break' or 'next' keyword
+
+        Ok(node)
+    }
+
+    /// Parses `if`, and `elsif` as an `if` nested in the else body, so only
+    /// the outermost `if` consumes the `end`.
+    fn parse_if_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+    ) -> Result<Node, &'static str> {
+        self.pos += 1; // Advance past 'if' or 'elsif' keyword
+        self.advance_optional_space();
+
+        let condition = self.nested(|parser| parser.parse_expr(mctx, ctx))?;
+
+        let mut then_body = vec![];
+        let mut else_body = vec![];
+
+        loop {
+            self.advance_optional_whitespace();
+
+            match self.current()? {
+                Token::Elsif => {
+                    else_body.push(self.nested(|parser| parser.parse_if_expr(mctx, ctx))?);
+                    break;
+                }
+                Token::Else => {
+                    self.advance()?;
+
+                    loop {
+                        self.advance_optional_whitespace();
+
+                        match self.current()? {
+                            Token::End => {
+                                self.advance()?;
+                                break;
+                            }
+                            Token::Elsif | Token::Else => {
+                                return Err("Expected 'end' after the else branch of an if")
+                            }
+                            _ => else_body.push(self.parse_expr(mctx, ctx)?),
+                        }
+                    }
+
+                    break;
+                }
+                Token::End => {
+                    self.advance()?;
+                    break;
+                }
+                _ => then_body.push(self.parse_expr(mctx, ctx)?),
+            }
+        }
+
+        Ok(Node::If(If {
+            condition: Box::new(condition),
+            then_body,
+            else_body,
+            return_type: None,
+        }))
+    }
+
     /// Parses a binary expression, given its left-hand expression.
     fn parse_binary_expr(
+        &mut self,
+        mctx: &mut ParserModuleCtx,
+        ctx: &ParserFunctionCtx,
+        prec: i32,
+        left: Node,
+    ) -> Result<Node, &'static str> {
+        let expr_depth = self.expr_depth;
+        let result = self.parse_binary_operands(mctx, ctx, prec, left);
+        self.expr_depth = expr_depth;
+
+        result
+    }
+
+    /// `parse_binary_expr`, one level deeper for each operator it adds to the
+    /// chain.
+    fn parse_binary_operands(
         &mut self,
         mctx: &mut ParserModuleCtx,
         ctx: &ParserFunctionCtx,
@@ -1828,7 +3691,7 @@ impl<'a> Parser<'a> {
         mut left: Node,
     ) -> Result<Node, &'static str> {
         loop {
-            if let Ok(Token::End) = self.current() {
+            if self.kind() == Some(TokenKind::End) {
                 // self.advance()?;
                 return Ok(left);
             }
@@ -1839,8 +3702,14 @@ impl<'a> Parser<'a> {
                 return Ok(left);
             }
 
-            let op = match self.curr() {
-                Token::Op(op) => op,
+            let op = match self.current()? {
+                // Operators without a precedence would bind tighter than
+                // everything else
+                Token::Op(op) if !self.op_precedence.contains_key(&op) => {
+                    return Err("Unknown binary operator.")
+                }
+                Token::Op(op) => Some(op),
+                Token::Spaceship => None,
                 _ => return Err("Invalid operator."),
             };
 
@@ -1856,11 +3725,32 @@ impl<'a> Parser<'a> {
                 right = self.parse_binary_expr(mctx, ctx, curr_prec + 1, right)?;
             }
 
-            left = Node::Binary(Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            });
+            if self.expr_depth >= MAX_EXPR_DEPTH {
+                return Err("expression too deeply nested");
+            }
+
+            self.expr_depth += 1;
+
+            left = match op {
+                Some(op) => Node::Binary(Binary {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    return_type: None,
+                }),
+                // `a <=> b` sends `<=>` to `a`, see the Comparable trait
+                None => Node::Send(Send {
+                    receiver: Box::new(left),
+                    message: Box::new(Node::Call(Call {
+                        fn_name: "<=>".to_string(),
+                        args: vec![right],
+                        return_type: None,
+                        arg_names: vec![],
+                    })),
+                    return_type: None,
+                    safe_navigation: false,
+                }),
+            };
         }
     }
 
@@ -1881,7 +3771,7 @@ impl<'a> Parser<'a> {
     /// indicates that the end of the file has been unexpectedly reached if it is the case.
     fn current(&self) -> Result<Token, &'static str> {
         if self.pos >= self.tokens.len() {
-            Err("Position doesn't match the token count")
+            Err("Unexpected end of file.")
         } else {
             Ok(self.tokens[self.pos].clone())
         }
@@ -1902,6 +3792,30 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// The kind of the current token, `None` past the end of the input.
+    fn kind(&self) -> Option<TokenKind> {
+        self.tokens.get(self.pos).map(Token::kind)
+    }
+
+    /// Advances past the current token if it's a `kind`, returning whether it was.
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.kind() == Some(kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Advances past the current token, or returns `err` if it isn't a `kind`.
+    fn expect(&mut self, kind: TokenKind, err: &'static str) -> Result<(), &'static str> {
+        if self.eat(kind) {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
     fn advance_token(&mut self) -> Result<Token, &'static str> {
         let npos = self.pos + 1;
 
@@ -1915,32 +3829,13 @@ impl<'a> Parser<'a> {
     }
 
     fn advance_optional_whitespace(&mut self) {
-        while let Ok(token) = self.current() {
-            match token {
-                Token::Space(_) => {
-                    self.advance();
-                }
-                Token::NewLine(_) => {
-                    self.advance();
-                }
-                Token::Comment(_, _) => {
-                    self.advance();
-                }
-                _ => break,
-            }
+        while let Some(TokenKind::Space | TokenKind::NewLine | TokenKind::Comment) = self.kind() {
+            self.pos += 1;
         }
     }
 
     fn advance_optional_space(&mut self) {
-        match self.current() {
-            Ok(token) => match token {
-                Token::Space(_) => {
-                    self.advance();
-                }
-                _ => {}
-            },
-            Err(_) => {}
-        }
+        self.eat(TokenKind::Space);
     }
 
     /// Returns a value indicating whether or not the `Parser`
@@ -1949,28 +3844,35 @@ impl<'a> Parser<'a> {
         self.pos >= self.tokens.len()
     }
 
-    /// Returns the precedence of the current `Token`, or 0 if it is not recognized as a binary operator.
+    /// Returns the precedence of the current `Token`, or -1 if it is not a
+    /// binary operator. Operators missing from `op_precedence` get 100, so
+    /// `parse_binary_expr` reaches them and reports them.
     fn get_tok_precedence(&self) -> i32 {
-        if let Ok(Token::Op(op)) = self.current() {
-            *self.op_precedence.get(&op).unwrap_or(&100)
-        } else {
-            -1
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => *self.op_precedence.get(op).unwrap_or(&100),
+            Some(Token::Spaceship) => *self.op_precedence.get("<=>").unwrap_or(&100),
+            _ => -1,
         }
     }
 
-    pub fn pajama_class_name(&self, return_type: &'a Option<BaseType>) -> String {
+    pub fn pajama_class_name(&self, return_type: &Option<BaseType>) -> String {
         match return_type {
             Some(rt) => match rt {
-                BaseType::Array(_, _) => "Array".to_string(),
+                BaseType::Array(_) => "Array".to_string(),
+                BaseType::Bool => "Bool".to_string(),
                 BaseType::Byte => "Byte".to_string(),
                 BaseType::BytePtr => "BytePtr".to_string(),
                 BaseType::Class(class_name) => class_name.to_string(),
+                BaseType::Float => "Float".to_string(),
                 BaseType::FnRef => "FnRef".to_string(),
                 BaseType::Int => "Int".to_string(),
                 BaseType::Int16 => "Int16".to_string(),
                 BaseType::Int32 => "Int32".to_string(),
                 BaseType::Int64 => "Int64".to_string(),
                 BaseType::Struct(_) => "Struct".to_string(),
+                // Sends to an optional are resolved by the class it wraps
+                BaseType::Optional(base_type) => self.pajama_class_name(&Some(*base_type.clone())),
+                BaseType::Nil => "Nil".to_string(),
                 BaseType::Void => "".to_string(),
             },
             None => "".to_string(),
@@ -1978,10 +3880,15 @@ impl<'a> Parser<'a> {
     }
 
     pub fn class_base_type(&self, type_name: String) -> BaseType {
+        if let Some(type_name) = type_name.strip_suffix('?') {
+            return BaseType::Optional(Box::new(self.class_base_type(type_name.to_string())));
+        }
+
         match type_name.as_str() {
-            // "Array" => BaseType::Array(_, _),
+            "Bool" => BaseType::Bool,
             "Byte" => BaseType::Byte,
             "BytePtr" => BaseType::BytePtr,
+            "Float" => BaseType::Float,
             "Int" => BaseType::Int,
             "Int16" => BaseType::Int16,
             "Int32" => BaseType::Int32,
@@ -1991,3 +3898,80 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+/// Whether `name` is assigned earlier in the def, or is one of its arguments.
+fn is_local_var(ctx: &ParserFunctionCtx, name: &str) -> bool {
+    closest_assignment(&ctx.body, name).is_some()
+        || ctx.prototype.args.iter().any(|arg| arg.name == name)
+}
+
+/// The last assignment to `name` in `body`, including the ones in the
+/// branches of an `if` and in loop bodies.
+fn closest_assignment<'a>(body: &'a [Node], name: &str) -> Option<&'a Node> {
+    body.iter().rev().find_map(|node| match node {
+        Node::AssignLocalVar(assignment) if assignment.name == name => Some(node),
+        Node::If(if_node) => closest_assignment(&if_node.else_body, name)
+            .or_else(|| closest_assignment(&if_node.then_body, name)),
+        Node::Loop(loop_node) => closest_assignment(&loop_node.body, name),
+        Node::While(while_node) => closest_assignment(&while_node.body, name),
+        _ => None,
+    })
+}
+
+/// Calling an object, `adder(1)` or `adder.(1)`, sends it `call`. Calling a
+/// reference to a def, like `f = &greet` then `f("Rex")`, calls the def, so
+/// its arguments are checked like any other call's.
+fn call_send(receiver: Node, args: Vec<Node>) -> Node {
+    if let Some(fn_name) = referenced_def(&receiver) {
+        return Node::Call(Call {
+            fn_name: fn_name.to_string(),
+            args,
+            return_type: None,
+            arg_names: vec![],
+        });
+    }
+
+    Node::Send(Send {
+        receiver: Box::new(receiver),
+        message: Box::new(Node::Call(Call {
+            fn_name: "call".to_string(),
+            args,
+            return_type: None,
+            arg_names: vec![],
+        })),
+        return_type: None,
+        safe_navigation: false,
+    })
+}
+
+/// `name.fn_ref()`, the reference to the def `name` that `&name` is parsed
+/// into.
+fn fn_ref_send(fn_name: String) -> Node {
+    Node::Send(Send {
+        receiver: Box::new(Node::LocalVar(LocalVar {
+            name: fn_name,
+            return_type: None,
+        })),
+        message: Box::new(Node::Call(Call {
+            fn_name: "fn_ref".to_string(),
+            args: vec![],
+            return_type: None,
+            arg_names: vec![],
+        })),
+        return_type: None,
+        safe_navigation: false,
+    })
+}
+
+/// The def `node` refers to, when it's `name.fn_ref()` or `&name`.
+fn referenced_def(node: &Node) -> Option<&str> {
+    match node {
+        Node::Send(send) => match (send.receiver.as_ref(), send.message.as_ref()) {
+            (Node::LocalVar(local_var), Node::Call(call)) if call.fn_name == "fn_ref" => {
+                Some(&local_var.name)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
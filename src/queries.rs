@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Token;
+use crate::lints::{run_lints, LintPlugin};
+use crate::parser::{Parser, ParserResult};
+use crate::relex::LexedText;
+use crate::semantic_analyzer::{Diagnostics, SemanticAnalyzer};
+
+/// Memoized compiler queries for long running hosts like a language server or
+/// watch mode.
+///
+/// Inputs are file texts, set with `set_file_text` or changed a piece at a
+/// time with `edit_file`. Every query remembers the revision its result was
+/// computed at and is only recomputed once one of its inputs changed after
+/// that:
+///
+/// * `tokens(path)` depends on the text of that one file, though after
+///   `edit_file` only the lines around the edit are lexed again
+/// * `program()` lexes, parses and analyzes every file as one program, so it
+///   depends on all of them
+///
+/// Setting a file to the text it already has isn't a change. Adding a lint is
+/// one for `program()`.
+#[derive(Default)]
+pub struct Database {
+    revision: u64,
+    files: BTreeMap<String, FileInput>,
+    lints: Vec<Box<dyn LintPlugin>>,
+    tokens: HashMap<String, (u64, LexedText)>,
+    program: Option<(u64, Program)>,
+    pub stats: QueryStats,
+}
+
+/// How many times each query actually ran, rather than being answered from
+/// memory.
+#[derive(Debug, Default, PartialEq)]
+pub struct QueryStats {
+    pub tokens: usize,
+    pub program: usize,
+    /// Lines lexed again to catch up with `edit_file`, without lexing the
+    /// whole file
+    pub relexed_lines: usize,
+}
+
+pub struct Program {
+    pub parser_result: Result<ParserResult, Vec<Diagnostic>>,
+    pub errors: Vec<String>,
+}
+
+struct FileInput {
+    text: String,
+    changed_at: u64,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database::default()
+    }
+
+    pub fn set_file_text(&mut self, path: &str, text: &str) {
+        if let Some(file) = self.files.get(path) {
+            if file.text == text {
+                return;
+            }
+        }
+
+        self.revision += 1;
+        self.files.insert(
+            path.to_string(),
+            FileInput {
+                text: text.to_string(),
+                changed_at: self.revision,
+            },
+        );
+    }
+
+    /// Replaces `range`, in bytes, of the file's text with `text`, like an
+    /// editor sending what changed rather than the whole buffer. The file's
+    /// tokens, if they were up to date, are caught up right away by lexing
+    /// only the lines around the edit. Does nothing for a file that isn't
+    /// set.
+    pub fn edit_file(&mut self, path: &str, range: Range<usize>, text: &str) {
+        let file = match self.files.get_mut(path) {
+            Some(file) => file,
+            None => return,
+        };
+
+        if range.is_empty() && text.is_empty() {
+            return;
+        }
+
+        let fresh = match self.tokens.get(path) {
+            Some((computed_at, _)) => *computed_at >= file.changed_at,
+            None => false,
+        };
+
+        self.revision += 1;
+        file.text.replace_range(range.clone(), text);
+        file.changed_at = self.revision;
+
+        if fresh {
+            let (computed_at, lexed) = self.tokens.get_mut(path).unwrap();
+            let relexed = lexed.edit(&file.text, range, text.len());
+
+            *computed_at = self.revision;
+            self.stats.relexed_lines += file.text[relexed].lines().count();
+        }
+    }
+
+    pub fn remove_file(&mut self, path: &str) {
+        if self.files.remove(path).is_some() {
+            self.revision += 1;
+            self.tokens.remove(path);
+        }
+    }
+
+    pub fn add_lint(&mut self, lint: Box<dyn LintPlugin>) {
+        self.revision += 1;
+        self.lints.push(lint);
+    }
+
+    pub fn tokens(&mut self, path: &str) -> Option<&[Token]> {
+        let file = self.files.get(path)?;
+
+        let fresh = match self.tokens.get(path) {
+            Some((computed_at, _)) => *computed_at >= file.changed_at,
+            None => false,
+        };
+
+        if !fresh {
+            self.stats.tokens += 1;
+
+            let lexed = LexedText::new(&file.text);
+            self.tokens.insert(path.to_string(), (self.revision, lexed));
+        }
+
+        self.tokens.get(path).map(|(_, lexed)| lexed.tokens())
+    }
+
+    pub fn program(&mut self) -> &Program {
+        self.program_cancellable(&CancellationToken::new()).unwrap()
+    }
+
+    /// `program`, giving up once `cancellation` is cancelled, like when the
+    /// file is edited again mid compile. Nothing is remembered then, so the
+    /// next call starts over.
+    pub fn program_cancellable(
+        &mut self,
+        cancellation: &CancellationToken,
+    ) -> Result<&Program, Cancelled> {
+        let fresh = match &self.program {
+            Some((computed_at, _)) => *computed_at == self.revision,
+            None => false,
+        };
+
+        if !fresh {
+            self.stats.program += 1;
+
+            let paths: Vec<String> = self.files.keys().cloned().collect();
+            let mut files = vec![];
+
+            for path in paths {
+                let tokens = self.tokens(&path).unwrap().to_vec();
+                files.push((path, tokens));
+            }
+
+            let mut precedence_map = crate::parser::default_op_precedence();
+            let mut parser_result =
+                Parser::start_parse_files_cancellable(files, &mut precedence_map, cancellation)?;
+
+            let errors = match &mut parser_result {
+                Ok(parser_result) => {
+                    let mut analyzer = SemanticAnalyzer::transform_ast(
+                        parser_result,
+                        Diagnostics::new(),
+                        cancellation,
+                    )?;
+
+                    run_lints(&self.lints, parser_result, &mut analyzer.diagnostics);
+                    analyzer.diagnostics.errors
+                }
+                Err(diagnostics) => diagnostics.iter().map(ToString::to_string).collect(),
+            };
+
+            self.program = Some((
+                self.revision,
+                Program {
+                    parser_result,
+                    errors,
+                },
+            ));
+        }
+
+        Ok(&self.program.as_ref().unwrap().1)
+    }
+}
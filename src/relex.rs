@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use crate::lexer::{Lexer, Token};
+
+/// The tokens of a file, kept in chunks that start at the lines lexing can
+/// restart from, so an edit only relexes the chunks around it instead of the
+/// whole file.
+///
+/// A line is a restart point when the lexer reaches it with nothing half
+/// lexed: the newline before it isn't in a string literal, the line doesn't
+/// start with another newline that NewLine token would take, and it doesn't
+/// start with `#!`, which only the first line may skip. Lexing from there
+/// gives the same tokens lexing the whole file does, on lines counted from 1,
+/// so each chunk's tokens are moved down to the line the chunk starts on.
+pub struct LexedText {
+    tokens: Vec<Token>,
+    chunks: Vec<Chunk>,
+}
+
+struct Chunk {
+    start: usize,
+    line: usize,
+    tokens: usize,
+}
+
+impl LexedText {
+    pub fn new(text: &str) -> LexedText {
+        let mut chunks = vec![Chunk {
+            start: 0,
+            line: 1,
+            tokens: 0,
+        }];
+
+        for (start, line) in RestartPoints::new(text, 0, 1) {
+            chunks.push(Chunk {
+                start,
+                line,
+                tokens: 0,
+            });
+        }
+
+        let mut tokens = vec![];
+        lex_chunks(text, &mut chunks, text.len(), &mut tokens);
+
+        LexedText { tokens, chunks }
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Catches up with `text`, the text these tokens were lexed from after
+    /// `range` of it was replaced by `inserted` bytes. Lexing restarts at the
+    /// last restart point safely before the edit and stops at the first one
+    /// after it that was also one before the edit, where the old tokens are
+    /// reused with their lines shifted by however many lines the edit added or
+    /// removed.
+    ///
+    /// Returns the bytes of `text` that were lexed again.
+    pub fn edit(&mut self, text: &str, range: Range<usize>, inserted: usize) -> Range<usize> {
+        // A restart point right before the edit might not be one anymore,
+        // like when the edit starts its line with a newline or `#!`
+        let first = self
+            .chunks
+            .iter()
+            .rposition(|chunk| chunk.start == 0 || chunk.start + 2 <= range.start)
+            .unwrap();
+
+        let start = self.chunks[first].start;
+        let edit_end = range.start + inserted;
+
+        let mut chunks = vec![Chunk {
+            start,
+            line: self.chunks[first].line,
+            tokens: 0,
+        }];
+        let mut resync = None;
+
+        for (offset, line) in RestartPoints::new(text, start, self.chunks[first].line) {
+            if offset >= edit_end {
+                let old_offset = offset - edit_end + range.end;
+
+                if let Ok(index) = self
+                    .chunks
+                    .binary_search_by_key(&old_offset, |chunk| chunk.start)
+                {
+                    resync = Some((index, offset, line));
+                    break;
+                }
+            }
+
+            chunks.push(Chunk {
+                start: offset,
+                line,
+                tokens: 0,
+            });
+        }
+
+        let (last, end) = match resync {
+            Some((index, offset, _)) => (index, offset),
+            None => (self.chunks.len(), text.len()),
+        };
+
+        let mut tokens = vec![];
+        lex_chunks(text, &mut chunks, end, &mut tokens);
+
+        let token_start: usize = self.chunks[..first].iter().map(|chunk| chunk.tokens).sum();
+        let old_tokens: usize = self.chunks[first..last]
+            .iter()
+            .map(|chunk| chunk.tokens)
+            .sum();
+        let token_end = token_start + tokens.len();
+
+        self.tokens
+            .splice(token_start..token_start + old_tokens, tokens);
+
+        if let Some((index, offset, line)) = resync {
+            let old_line = self.chunks[index].line;
+
+            for chunk in &mut self.chunks[index..] {
+                chunk.start = chunk.start - range.end + edit_end;
+                chunk.line = chunk.line + line - old_line;
+            }
+
+            if line != old_line {
+                for token in &mut self.tokens[token_end..] {
+                    if let Some(position) = token.position_mut() {
+                        position.line = position.line + line - old_line;
+                    }
+                }
+            }
+
+            debug_assert_eq!(self.chunks[index].start, offset);
+        }
+
+        self.chunks.splice(first..last, chunks);
+
+        start..end
+    }
+}
+
+/// Lexes each of `chunks`, the last one ending at `end`, onto `tokens`.
+fn lex_chunks(text: &str, chunks: &mut [Chunk], end: usize, tokens: &mut Vec<Token>) {
+    for index in 0..chunks.len() {
+        let chunk_end = chunks.get(index + 1).map_or(end, |next| next.start);
+        let chunk = &mut chunks[index];
+        let before = tokens.len();
+
+        tokens.extend(Lexer::new(&text[chunk.start..chunk_end]).tokenize());
+
+        for token in &mut tokens[before..] {
+            if let Some(position) = token.position_mut() {
+                position.line += chunk.line - 1;
+            }
+        }
+
+        chunk.tokens = tokens.len() - before;
+    }
+}
+
+/// The restart points after `start`, itself a restart point the lexer
+/// reaches on `line`, with the line it reaches each of them on. Newlines in
+/// string literals aren't counted, as the lexer doesn't count them either.
+struct RestartPoints<'a> {
+    text: &'a [u8],
+    offset: usize,
+    line: usize,
+    in_string: bool,
+    in_comment: bool,
+}
+
+impl RestartPoints<'_> {
+    fn new(text: &str, start: usize, line: usize) -> RestartPoints<'_> {
+        RestartPoints {
+            text: text.as_bytes(),
+            offset: start,
+            line,
+            in_string: false,
+            in_comment: false,
+        }
+    }
+}
+
+impl Iterator for RestartPoints<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        while let Some(&byte) = self.text.get(self.offset) {
+            self.offset += 1;
+
+            match byte {
+                // Escapes in string literals never escape a quote
+                b'"' if !self.in_comment => self.in_string = !self.in_string,
+                b'#' if !self.in_string => self.in_comment = true,
+                b'\n' if !self.in_string => {
+                    self.in_comment = false;
+                    self.line += 1;
+
+                    let rest = &self.text[self.offset..];
+
+                    if !rest.is_empty() && rest[0] != b'\n' && !rest.starts_with(b"#!") {
+                        return Some((self.offset, self.line));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,460 @@
+use std::io::{self, BufRead, Write};
+
+use crate::lexer::{Lexer, Token};
+use crate::pajama_compiler::{CompileOptions, PajamaCompiler};
+use crate::parser::{self, BaseType, Node, ParserResult};
+use crate::semantic_analyzer::typed_node_base_type;
+use crate::source::SourceFile;
+
+/// What an input to the REPL adds to the session.
+#[derive(Debug, PartialEq)]
+pub enum InputKind {
+    /// A class, def, trait, struct, impl, constant or import, kept for every
+    /// later input
+    Definition,
+    /// `name = value`, replayed before every later input so `name` stays
+    /// available
+    Assignment(String),
+    /// Evaluated once and printed
+    Expression,
+}
+
+pub fn input_kind(input: &str) -> InputKind {
+    let tokens: Vec<Token> = Lexer::new(input)
+        .tokenize()
+        .into_iter()
+        .filter(|token| {
+            !matches!(
+                token,
+                Token::Space(_) | Token::NewLine(_) | Token::Comment(..)
+            )
+        })
+        .collect();
+
+    match tokens.as_slice() {
+        [Token::Class | Token::Def | Token::DefE | Token::Trait, ..]
+        | [Token::Struct | Token::Impl | Token::Import, ..]
+        | [Token::Const(..), Token::Assign, ..] => InputKind::Definition,
+        [Token::Ident(_, name), Token::Assign, ..] => InputKind::Assignment(name.clone()),
+        _ => InputKind::Expression,
+    }
+}
+
+/// Whether `input` closes every block it opens, so it can be compiled. Trait
+/// methods without a body have no `end`, an empty line compiles what's been
+/// typed regardless.
+pub fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+
+    for token in Lexer::new(input).tokenize() {
+        match token {
+            Token::Class
+            | Token::Def
+            | Token::Do
+            | Token::If
+            | Token::Impl
+            | Token::Loop
+            | Token::Struct
+            | Token::Trait
+            | Token::While => depth += 1,
+            Token::End => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth <= 0
+}
+
+/// The definitions and assignments entered so far.
+///
+/// The JIT can't add functions to a module it has already compiled, so each
+/// input is compiled again together with everything before it. Expressions
+/// are put in a `main` after the assignments, which means an assignment with
+/// a side effect, like printing, repeats it on every later input.
+///
+/// That makes the inputs the whole state of a session, so `:save` writes them
+/// to a file and `:load` replays them, see `Session::save`.
+#[derive(Default)]
+pub struct Session {
+    pub definitions: Vec<SourceFile>,
+    pub assignments: Vec<String>,
+    /// Every input that compiled and ran, in order
+    pub history: Vec<String>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// The files to compile `input` as. Definitions are compiled on their
+    /// own, anything else in a `main` that runs the assignments and then
+    /// `input`. The last statement of `main` is the value to print: the
+    /// input itself, or the variable an assignment sets.
+    pub fn sources(&self, input: &str) -> Vec<SourceFile> {
+        let path = format!("(input {})", self.history.len() + 1);
+        let mut sources = self.definitions.clone();
+
+        let body = match input_kind(input) {
+            InputKind::Definition => {
+                sources.push(SourceFile {
+                    path,
+                    input: input.to_string(),
+                });
+
+                return sources;
+            }
+            InputKind::Assignment(name) => format!("{}\n{}", input.trim_end(), name),
+            InputKind::Expression => input.trim_end().to_string(),
+        };
+
+        sources.push(self.main(path, &body));
+        sources
+    }
+
+    /// Like `sources`, for an input whose value is an instance of
+    /// `class_name`: the value is kept in a local and printed with `to_s`,
+    /// so it's shown with its attributes unless the class defines its own.
+    /// Only an input with a single expression compiles this way, and only
+    /// with the Str class defined.
+    pub fn inspect_sources(&self, input: &str, class_name: &str) -> Vec<SourceFile> {
+        let path = format!("(input {})", self.history.len() + 1);
+        let mut sources = self.definitions.clone();
+
+        let (assignment, name) = match input_kind(input) {
+            InputKind::Assignment(name) => (input.trim_end().to_string(), name),
+            _ => (
+                format!("{} = {}", REPL_VALUE, input.trim_end()),
+                REPL_VALUE.to_string(),
+            ),
+        };
+        let body = format!(
+            "{}\nputs(\"=> #{{{}}} : {}\")",
+            assignment, name, class_name
+        );
+
+        sources.push(self.main(path, &body));
+        sources
+    }
+
+    /// A `main` running the assignments and then `body`.
+    fn main(&self, path: String, body: &str) -> SourceFile {
+        let mut main = String::from("def main\n");
+
+        for line in self.assignments.iter().map(String::as_str).chain([body]) {
+            main.push_str(&format!("{}\n", line.trim_end()));
+        }
+
+        main.push_str("end\n");
+
+        SourceFile { path, input: main }
+    }
+
+    /// Keeps what `input` added, once it compiled and ran.
+    pub fn commit(&mut self, input: &str) {
+        self.history.push(input.to_string());
+
+        match input_kind(input) {
+            InputKind::Definition => self.definitions.push(SourceFile {
+                path: format!("(input {})", self.history.len()),
+                input: input.to_string(),
+            }),
+            InputKind::Assignment(_) => self.assignments.push(input.to_string()),
+            InputKind::Expression => {}
+        }
+    }
+
+    /// The session as a .nrepl file: a comment, then the inputs in order,
+    /// with an empty line after each. Inputs never have empty lines of their
+    /// own, an empty line ends one, so the file is read back by splitting it
+    /// on them. The files given to `pajama repl` aren't in it, they're given
+    /// again when it's loaded.
+    pub fn save(&self) -> String {
+        let mut saved = String::from(SESSION_HEADER);
+
+        for input in &self.history {
+            saved.push_str(&format!("\n{}", input));
+        }
+
+        saved
+    }
+}
+
+/// The first line of a .nrepl file
+const SESSION_HEADER: &str = "# pajama repl session, resume it with :load <path>\n";
+
+/// The inputs of a session written by `Session::save`, to replay in order.
+/// Parts that are only comments have nothing to replay, and are skipped.
+pub fn saved_inputs(saved: &str) -> Vec<String> {
+    let mut inputs = vec![];
+    let mut input = String::new();
+
+    for line in saved.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            input.push_str(&format!("{}\n", line));
+            continue;
+        }
+
+        let only_comments = input.lines().all(|line| line.trim_start().starts_with('#'));
+
+        if !only_comments {
+            inputs.push(input.clone());
+        }
+
+        input.clear();
+    }
+
+    inputs
+}
+
+/// The local an input's value is kept in by `Session::inspect_sources`
+const REPL_VALUE: &str = "__repl_value";
+
+/// The type the typechecker inferred for the last statement of `main`, the
+/// value of the input.
+pub fn last_value_type(parser_result: &ParserResult) -> Option<BaseType> {
+    let module = match &parser_result.module {
+        Node::Module(module) => module,
+        _ => return None,
+    };
+
+    let last = module.methods.iter().find_map(|node| match node {
+        Node::Def(def) if def.main_fn => def.body.last(),
+        _ => None,
+    })?;
+
+    typed_node_base_type(last)
+}
+
+/// Makes the last statement of `main` print its value and type, like
+/// `=> 42 : Int`. Ints, Floats, Bools and Strs are printed by the runtime as
+/// `main` ends, a description of anything else is returned to print instead.
+pub fn print_last_value(parser_result: &mut ParserResult) -> Option<String> {
+    let value_type = last_value_type(parser_result)?;
+
+    let fn_name = match &value_type {
+        BaseType::Int => "pj_repl_print_int",
+        BaseType::Float => "pj_repl_print_float",
+        BaseType::Bool => "pj_repl_print_bool",
+        BaseType::Class(class_name) if class_name == "Str" => "pj_repl_print_str",
+        value_type => {
+            let name = type_name(value_type);
+
+            return Some(format!("#<{}> : {}", name, name));
+        }
+    };
+
+    let module = match &mut parser_result.module {
+        Node::Module(module) => module,
+        _ => return None,
+    };
+
+    let last = module.methods.iter_mut().find_map(|node| match node {
+        Node::Def(def) if def.main_fn => def.body.last_mut(),
+        _ => None,
+    })?;
+
+    let value = std::mem::replace(last, Node::Int(parser::Int { value: 0 }));
+
+    *last = Node::Call(parser::Call {
+        fn_name: fn_name.to_string(),
+        args: vec![value],
+        return_type: None,
+        arg_names: vec![],
+    });
+
+    let prototype = parser::Prototype {
+        name: fn_name.to_string(),
+        args: vec![parser::Arg {
+            name: "value".to_string(),
+            return_type: value_type,
+            default: None,
+        }],
+        return_type: None,
+        is_op: false,
+        prec: 0,
+    };
+
+    parser_result
+        .index
+        .fn_prototype_index
+        .insert(prototype.name.clone(), prototype.clone());
+    module.methods.push(Node::DefE(parser::DefE { prototype }));
+
+    None
+}
+
+fn type_name(base_type: &BaseType) -> String {
+    match base_type {
+        BaseType::Array(item_type) => format!("[{}]", type_name(item_type)),
+        BaseType::Class(name) | BaseType::Struct(name) => name.clone(),
+        base_type => format!("{:?}", base_type),
+    }
+}
+
+/// Reads inputs from stdin until it closes, starting with the definitions in
+/// `sources`, like the files given to `pajama repl`.
+///
+/// Each input runs in a child process, so a runtime error stops only that
+/// input; the session keeps it only when the child exits with 0.
+pub fn run(sources: Vec<SourceFile>, options: &CompileOptions) {
+    let mut session = Session::new();
+    session.definitions = sources;
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        let mut input = String::new();
+
+        loop {
+            print!("{}", if input.is_empty() { ">> " } else { ".. " });
+            io::stdout().flush().unwrap();
+
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => return,
+            };
+
+            if line.trim().is_empty() && !input.is_empty() {
+                break;
+            }
+
+            input.push_str(&line);
+            input.push('\n');
+
+            if is_complete(&input) {
+                break;
+            }
+        }
+
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(command) = input.trim().strip_prefix(':') {
+            run_command(&mut session, command, options);
+            continue;
+        }
+
+        if evaluate(&session, &input, options) {
+            session.commit(&input);
+        }
+    }
+}
+
+/// Runs `:save <path>`, which writes the session's inputs to a .nrepl file,
+/// or `:load <path>`, which replays the inputs of one as if they were typed,
+/// stopping at the first that fails.
+fn run_command(session: &mut Session, command: &str, options: &CompileOptions) {
+    let (name, path) = match command.split_once(' ') {
+        Some((name, path)) => (name, path.trim()),
+        None => (command, ""),
+    };
+
+    match (name, path) {
+        ("save", path) if !path.is_empty() => match std::fs::write(path, session.save()) {
+            Ok(()) => println!("saved {} inputs to {}", session.history.len(), path),
+            Err(err) => eprintln!("{}: {}", path, err),
+        },
+        ("load", path) if !path.is_empty() => {
+            let inputs = match std::fs::read_to_string(path) {
+                Ok(saved) => saved_inputs(&saved),
+                Err(err) => {
+                    eprintln!("{}: {}", path, err);
+                    return;
+                }
+            };
+
+            for (index, input) in inputs.iter().enumerate() {
+                print!(">> {}", input);
+
+                if !evaluate(session, input, options) {
+                    eprintln!(
+                        "{}: stopped loading at input {} of {}",
+                        path,
+                        index + 1,
+                        inputs.len()
+                    );
+                    return;
+                }
+
+                session.commit(input);
+            }
+        }
+        _ => eprintln!(
+            "unknown command `:{}`, expected :save <path> or :load <path>",
+            command
+        ),
+    }
+}
+
+/// Compiles and runs `input` in a child process, returning whether it
+/// succeeded.
+fn evaluate(session: &Session, input: &str, options: &CompileOptions) -> bool {
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("couldn't start the input: {}", io::Error::last_os_error());
+            false
+        }
+        0 => {
+            let status = run_input(session, input, options);
+
+            io::stdout().flush().unwrap();
+            std::process::exit(status);
+        }
+        child => {
+            let mut status = 0;
+
+            unsafe { libc::waitpid(child, &mut status, 0) };
+
+            libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+        }
+    }
+}
+
+fn run_input(session: &Session, input: &str, options: &CompileOptions) -> i32 {
+    let sources = session.sources(input);
+
+    // Nothing cancels an input
+    let mut parser_result = match PajamaCompiler::compile_to_ast(&sources, options).unwrap() {
+        Ok(parser_result) => parser_result,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}\n", diagnostic.render(&sources, options.tab_width));
+            }
+
+            return 1;
+        }
+    };
+
+    if input_kind(input) == InputKind::Definition {
+        return 0;
+    }
+
+    // Instances are shown with `to_s`, when the input compiles that way
+    if let Some(BaseType::Class(class_name)) = last_value_type(&parser_result) {
+        let inspected = match class_name.as_str() {
+            "Str" => None,
+            _ => PajamaCompiler::compile_to_ast(
+                &session.inspect_sources(input, &class_name),
+                options,
+            )
+            .unwrap()
+            .ok(),
+        };
+
+        if let Some(parser_result) = inspected {
+            return PajamaCompiler::run_ast(&parser_result, options).unwrap();
+        }
+    }
+
+    let described = print_last_value(&mut parser_result);
+    let status = PajamaCompiler::run_ast(&parser_result, options).unwrap();
+
+    if let Some(described) = described {
+        println!("=> {}", described);
+    }
+
+    status
+}
@@ -0,0 +1,92 @@
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// Exit code of a program that allocated past `--max-heap`, the same one the
+/// OOM killer leaves behind.
+pub const HEAP_LIMIT_EXIT_CODE: i32 = 137;
+
+/// Exit code of a program still running after `--max-time`, the same one
+/// `timeout` uses.
+pub const TIME_LIMIT_EXIT_CODE: i32 = 124;
+
+/// Caps on what a program run by `compile_and_invoke` may use.
+///
+/// The program runs in the compiler's own process, so going over a limit
+/// ends the process with a message and one of the exit codes above.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Bytes the runtime may allocate, counted by `pajama_lib`
+    pub max_heap: Option<u64>,
+    /// Wall clock time `main` may run for
+    pub max_time: Option<Duration>,
+}
+
+/// Ends the process once `max_time` has passed, unless it's dropped first.
+pub struct Watchdog {
+    _disarm: Sender<()>,
+}
+
+impl Watchdog {
+    pub fn start(max_time: Duration) -> Watchdog {
+        let (disarm, disarmed) = channel::<()>();
+
+        std::thread::spawn(move || {
+            // Dropping the watchdog disconnects the channel
+            if let Err(RecvTimeoutError::Timeout) = disarmed.recv_timeout(max_time) {
+                eprintln!("time limit of {:?} exceeded", max_time);
+                std::process::exit(TIME_LIMIT_EXIT_CODE);
+            }
+        });
+
+        Watchdog { _disarm: disarm }
+    }
+}
+
+/// Parses a byte count like `512`, `64K`, `16M` or `1G`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, unit) = split_unit(size);
+
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("unknown size unit `{}`, expected K, M or G", unit)),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size `{}`", size))
+}
+
+/// Parses a duration like `500ms`, `30s` or `2m`. Bare numbers are seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (digits, unit) = split_unit(duration);
+
+    let count = match digits.parse::<u64>() {
+        Ok(count) => count,
+        Err(_) => return Err(format!("invalid duration `{}`", duration)),
+    };
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(count)),
+        "" | "s" => Ok(Duration::from_secs(count)),
+        "m" => count
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("invalid duration `{}`", duration)),
+        _ => Err(format!(
+            "unknown duration unit `{}`, expected ms, s or m",
+            unit
+        )),
+    }
+}
+
+fn split_unit(value: &str) -> (&str, &str) {
+    match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    }
+}
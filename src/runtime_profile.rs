@@ -0,0 +1,112 @@
+use crate::parser::{Node, ParserResult};
+use crate::semantic_analyzer::Diagnostics;
+
+/// Which parts of the runtime a program may use, picked with `--runtime`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RuntimeProfile {
+    #[default]
+    Full,
+    /// Only what a target without an operating system can provide: no files,
+    /// network, processes or threads. Printing goes through the hook set with
+    /// `pj_set_print_hook` when there is one.
+    Minimal,
+}
+
+/// Functions for files, the network and processes
+const OPERATING_SYSTEM_FNS: [&str; 31] = [
+    // Files
+    "open",
+    "openat",
+    "creat",
+    "fopen",
+    "read",
+    "write",
+    "close",
+    "unlink",
+    "rename",
+    "mkdir",
+    "rmdir",
+    "chmod",
+    // Network
+    "socket",
+    "bind",
+    "connect",
+    "listen",
+    "accept",
+    "send",
+    "recv",
+    "pj_listen",
+    "pj_poll",
+    "pj_check_events",
+    "pj_tcp_connection_buffer",
+    "pj_tcp_connection_flush",
+    "pj_tcp_connection_write",
+    // Processes
+    "system",
+    "fork",
+    "execv",
+    "execve",
+    "kill",
+    "popen",
+];
+
+/// Runtime functions that need threads, on top of `OPERATING_SYSTEM_FNS`
+const THREADED_FNS: [&str; 1] = ["pj_signal_trap"];
+
+/// Runtime functions that read the system clock or time zone
+const CLOCK_FNS: [&str; 4] = [
+    "pj_datetime_now",
+    "pj_datetime_now_utc",
+    "pj_datetime_to_local",
+    "pj_date_today",
+];
+
+/// Runtime functions that look at the file system or working directory
+const FILESYSTEM_FNS: [&str; 2] = ["pj_path_absolute", "pj_path_glob"];
+
+pub fn parse_runtime_profile(name: &str) -> Result<RuntimeProfile, String> {
+    match name {
+        "full" => Ok(RuntimeProfile::Full),
+        "minimal" => Ok(RuntimeProfile::Minimal),
+        _ => Err(format!(
+            "unknown runtime `{}`, expected full or minimal",
+            name
+        )),
+    }
+}
+
+/// Reports every `def_e` the profile doesn't provide. Runs after analysis, so
+/// the runtime functions it declares, like `pj_signal_trap` for
+/// `Signal.trap`, are checked too.
+pub fn check_runtime_profile(
+    result: &ParserResult,
+    profile: RuntimeProfile,
+    diagnostics: &mut Diagnostics,
+) {
+    if profile == RuntimeProfile::Full {
+        return;
+    }
+
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    for node in &module.methods {
+        let fn_name = match node {
+            Node::DefE(def_e) => def_e.prototype.name.as_str(),
+            _ => continue,
+        };
+
+        if OPERATING_SYSTEM_FNS.contains(&fn_name)
+            || THREADED_FNS.contains(&fn_name)
+            || CLOCK_FNS.contains(&fn_name)
+            || FILESYSTEM_FNS.contains(&fn_name)
+        {
+            diagnostics.error(format!(
+                "`{}` isn't available with --runtime=minimal, it needs an operating system",
+                fn_name
+            ));
+        }
+    }
+}
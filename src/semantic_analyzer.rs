@@ -1,6 +1,8 @@
-use std::{borrow::BorrowMut, collections::HashMap, hash::Hash, ops::Deref};
+use std::collections::{HashMap, HashSet};
 
-use crate::parser::{self, BaseType, Def, Node, Parser, ParserResult, Struct};
+use crate::ast::{has_side_effects, walk_node, walk_node_mut, Visitor, VisitorMut};
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::parser::{self, BaseType, Def, Node, ParserResult};
 
 #[derive(Debug)]
 pub struct SemanticAnalyzer {
@@ -8,71 +10,4495 @@ pub struct SemanticAnalyzer {
     pub diagnostics: Diagnostics,
 }
 
-#[derive(Debug)]
-pub struct Diagnostics {}
+#[derive(Default)]
+pub struct Diagnostics {
+    pub errors: Vec<String>,
+    sink: Option<Box<dyn DiagnosticSink>>,
+}
+
+/// Receives each diagnostic as soon as it is produced, so embedders like an
+/// editor can show errors while the rest of the program is still checked.
+///
+/// Closures taking the message implement it, as in
+/// `SemanticAnalyzer::run_with_sink(&mut result, Box::new(|message: &str| ...))`.
+pub trait DiagnosticSink {
+    fn report(&mut self, message: &str);
+}
+
+impl<F: FnMut(&str)> DiagnosticSink for F {
+    fn report(&mut self, message: &str) {
+        self(message)
+    }
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn with_sink(sink: Box<dyn DiagnosticSink>) -> Diagnostics {
+        Diagnostics {
+            errors: vec![],
+            sink: Some(sink),
+        }
+    }
+
+    pub fn error(&mut self, message: String) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.report(&message);
+        }
+
+        self.errors.push(message);
+    }
+}
+
+impl std::fmt::Debug for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Diagnostics")
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+impl SemanticAnalyzer {
+    pub fn run(result: &mut ParserResult) -> SemanticAnalyzer {
+        Self::transform_ast(result, Diagnostics::new(), &CancellationToken::new()).unwrap()
+    }
+
+    /// Runs the analysis, handing each diagnostic to `sink` as it is found.
+    /// They are all still collected in `diagnostics` too.
+    pub fn run_with_sink(
+        result: &mut ParserResult,
+        sink: Box<dyn DiagnosticSink>,
+    ) -> SemanticAnalyzer {
+        Self::transform_ast(
+            result,
+            Diagnostics::with_sink(sink),
+            &CancellationToken::new(),
+        )
+        .unwrap()
+    }
+
+    /// Runs the analysis, stopping between its passes once `cancellation` is
+    /// cancelled. The AST is left partly transformed then, so it should be
+    /// thrown away.
+    pub fn transform_ast(
+        result: &mut ParserResult,
+        mut diagnostics: Diagnostics,
+        cancellation: &CancellationToken,
+    ) -> Result<SemanticAnalyzer, Cancelled> {
+        let mut attribute_index = HashMap::new();
+        let mut method_index = HashMap::new();
+
+        match &mut result.module {
+            Node::Module(module) => {
+                cancellation.check()?;
+                apply_freeze_protocol(module, &mut result.index, &mut diagnostics);
+                populate_class_index(&result.index.class_index, &mut attribute_index);
+                populate_method_index(module, &mut method_index);
+
+                for builtin in [
+                    "puts",
+                    "print",
+                    "sort",
+                    "min",
+                    "max",
+                    "sort_by",
+                    "Signal.trap",
+                    "at_exit",
+                    "defer",
+                ] {
+                    method_index.entry(builtin.to_string()).or_insert(None);
+                }
+
+                method_index.insert(
+                    parser::INTERPOLATE_FN.to_string(),
+                    Some(BaseType::Class("Str".to_string())),
+                );
+
+                for builtin in ["binary_search", "index_of", "Int.parse"] {
+                    method_index
+                        .entry(builtin.to_string())
+                        .or_insert(Some(BaseType::Int));
+                }
+
+                for (variant, _) in OVERFLOW_VARIANTS {
+                    method_index
+                        .entry(format!("Int.{}", variant))
+                        .or_insert(Some(overflow_variant_type(variant)));
+                }
+
+                for int_class in INT_CLASSES {
+                    for to_s in ["to_s", "to_string"] {
+                        method_index
+                            .entry(format!("{}.{}", int_class, to_s))
+                            .or_insert(Some(BaseType::Class("Str".to_string())));
+                    }
+                    method_index
+                        .entry(format!("{}.to_f", int_class))
+                        .or_insert(Some(BaseType::Float));
+                }
+
+                for to_s in ["Float.to_s", "Float.to_string"] {
+                    method_index
+                        .entry(to_s.to_string())
+                        .or_insert(Some(BaseType::Class("Str".to_string())));
+                }
+                method_index
+                    .entry("Float.to_i".to_string())
+                    .or_insert(Some(BaseType::Int));
+
+                for (method_name, _, _, return_type) in str_methods() {
+                    method_index
+                        .entry(format!("Str.{}", method_name))
+                        .or_insert(Some(return_type));
+                }
+
+                for (class_name, method_name, _, return_type, _) in date_methods() {
+                    method_index
+                        .entry(format!("{}.{}", class_name, method_name))
+                        .or_insert(date_method_return_type(return_type));
+                }
+
+                for (class_name, name, _, return_type) in builtin_functions() {
+                    method_index
+                        .entry(format!("{}.{}", class_name, name))
+                        .or_insert(return_type);
+                }
+
+                // Typed by the item type of the array they're sent to
+                for method_name in ARRAY_METHODS {
+                    method_index
+                        .entry(format!("Array.{}", method_name))
+                        .or_insert(None);
+                }
+
+                populate_inherited_method_index(&result.index, &mut method_index, &mut diagnostics);
+                check_trait_conformance(&result.index, &mut diagnostics);
+                cancellation.check()?;
+                run_type_inference(
+                    module,
+                    method_index,
+                    attribute_index,
+                    &result.index.struct_index,
+                    &mut diagnostics,
+                );
+                apply_nil_checks(module);
+                apply_call_arguments(module, &result.index, &mut diagnostics);
+                check_required_trait_sends(module, &result.index, &mut diagnostics);
+                check_definite_assignment(module, &mut diagnostics);
+                cancellation.check()?;
+                apply_comparable_protocol(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_numeric_formatting(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_str_methods(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_array_methods(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_date_methods(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_builtin_functions(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_signal_traps(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_exit_hooks(module, &mut result.index, &mut diagnostics);
+                cancellation.check()?;
+                apply_overflow_arithmetic(module, &mut result.index);
+                cancellation.check()?;
+                apply_to_s_protocol(module, &mut result.index, &mut diagnostics);
+                apply_implicit_returns(module, &mut diagnostics);
+                apply_defers(module);
+                type_nil_literals(module, &result.index);
+            }
+            _ => todo!(),
+        }
+
+        cancellation.check()?;
+        crate::type_checker::check_types(result, &mut diagnostics);
+
+        Ok(SemanticAnalyzer { diagnostics })
+    }
+}
+
+fn populate_class_index(
+    class_index: &HashMap<String, parser::Class>,
+    attribute_index: &mut HashMap<String, (i32, BaseType)>,
+) {
+    class_index.values().for_each(|class| {
+        for attribute in &class.attributes {
+            attribute_index.insert(
+                format!("{}.{}", class.name, attribute.name),
+                (attribute.index, attribute.return_type.clone()),
+            );
+        }
+    });
+}
+
+fn populate_method_index(
+    module: &mut crate::parser::Module,
+    method_index: &mut HashMap<String, Option<BaseType>>,
+) {
+    module.methods.iter_mut().for_each(|node| match node {
+        Node::Def(def_node) => {
+            method_index.insert(
+                def_node.prototype.name.clone(),
+                def_node.prototype.return_type.clone(),
+            );
+        }
+        Node::DefE(def_e_node) => {
+            method_index.insert(
+                def_e_node.prototype.name.clone(),
+                def_e_node.prototype.return_type.clone(),
+            );
+        }
+        _ => {}
+    });
+}
+
+/// Adds an entry for every method a class inherits from a superclass or a
+/// trait default, e.g. `Dog.speak` for `Animal.speak`, so sends to the
+/// subclass infer the same return type.
+fn populate_inherited_method_index(
+    index: &parser::ParserResultIndex,
+    method_index: &mut HashMap<String, Option<BaseType>>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut class_names: Vec<&String> = index.class_index.keys().collect();
+    class_names.sort();
+
+    for class_name in class_names {
+        let ancestors = index.class_ancestors(class_name);
+        let mut owners = index.implemented_traits(&ancestors);
+        owners.extend(ancestors);
+
+        let mut method_names: Vec<&str> = index
+            .fn_prototype_index
+            .keys()
+            .filter_map(|fn_name| fn_name.split_once('.'))
+            .filter(|(owner, _)| owners.iter().any(|name| name == owner))
+            .map(|(_, method_name)| method_name)
+            .collect();
+
+        method_names.sort();
+        method_names.dedup();
+
+        for method_name in method_names {
+            let fn_name = format!("{}.{}", class_name, method_name);
+
+            match index.resolve_method(class_name, method_name) {
+                Ok(Some(resolved_name)) if resolved_name != fn_name => {
+                    let return_type = index.fn_prototype_index[&resolved_name].return_type.clone();
+                    method_index.insert(fn_name, return_type);
+                }
+                Ok(_) => {}
+                Err(error) => diagnostics.error(error),
+            }
+        }
+    }
+}
+
+/// Traits
+///
+/// A trait declares methods, with a body for a default or without one for a
+/// method every implementing class has to define:
+///
+/// ```text
+/// trait Shape
+///   def area() -> Int
+///
+///   def corners() -> Int
+///     0
+///   end
+/// end
+/// ```
+///
+/// `impl Shape` in a class checks that each declared method resolves, for the
+/// class and through it for its subclasses, to a method taking the same
+/// arguments and returning the same type. The trait stands for the
+/// implementing class in the declared types, so `def <=>(other Shape)` is
+/// implemented by `def <=>(other Circle)`.
+fn check_trait_conformance(index: &parser::ParserResultIndex, diagnostics: &mut Diagnostics) {
+    let mut trait_names: Vec<&String> = index.trait_method_index.keys().collect();
+    trait_names.sort();
+
+    for trait_name in trait_names {
+        let mut class_names: Vec<&String> = match index.trait_index.get(trait_name) {
+            Some(classes) => classes.iter().map(|class| &class.name).collect(),
+            None => vec![],
+        };
+
+        class_names.sort();
+        class_names.dedup();
+
+        for class_name in class_names {
+            let ancestors = index.class_ancestors(class_name);
+
+            for method in &index.trait_method_index[trait_name] {
+                let method_name = &method.prototype.name[trait_name.len() + 1..];
+
+                let fn_name = match index.resolve_method(class_name, method_name) {
+                    Ok(Some(fn_name)) if fn_name != method.prototype.name => fn_name,
+                    Ok(_) if !method.required => continue,
+                    Ok(_) => {
+                        diagnostics.error(format!(
+                            "`{}` implements {} but does not define {}",
+                            class_name, trait_name, method_name
+                        ));
+                        continue;
+                    }
+                    // Reported with the inherited methods
+                    Err(_) => continue,
+                };
+
+                let prototype = &index.fn_prototype_index[&fn_name];
+                let conforms = |declared: &BaseType, defined: &BaseType| match declared {
+                    BaseType::Class(name) if name == trait_name => {
+                        matches!(defined, BaseType::Class(name) if ancestors.contains(name))
+                    }
+                    _ => declared == defined,
+                };
+
+                let args_conform = prototype.args.len() == method.prototype.args.len()
+                    && prototype.args[1..]
+                        .iter()
+                        .zip(&method.prototype.args[1..])
+                        .all(|(defined, declared)| {
+                            conforms(&declared.return_type, &defined.return_type)
+                        });
+
+                let return_conforms = match (&method.prototype.return_type, &prototype.return_type)
+                {
+                    (Some(declared), Some(defined)) => conforms(declared, defined),
+                    (declared, defined) => declared == defined,
+                };
+
+                if !args_conform || !return_conforms {
+                    diagnostics.error(format!(
+                        "`{}` does not match `{}`: expected {}, found {}",
+                        fn_name,
+                        method.prototype.name,
+                        signature(&method.prototype),
+                        signature(prototype)
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// `(Int, Shape) -> Int`, leaving out the receiver
+fn signature(prototype: &parser::Prototype) -> String {
+    let args: Vec<String> = prototype
+        .args
+        .iter()
+        .skip(1)
+        .map(|arg| pajama_class_name(&arg.return_type))
+        .collect();
+
+    match &prototype.return_type {
+        Some(return_type) => format!(
+            "({}) -> {}",
+            args.join(", "),
+            pajama_class_name(return_type)
+        ),
+        None => format!("({})", args.join(", ")),
+    }
+}
+
+/// Sends are resolved by the receiver's type at compile time, so a required
+/// method sent to a value typed as the trait itself, like an argument declared
+/// as `named Named`, has nothing to call.
+fn check_required_trait_sends(
+    module: &parser::Module,
+    index: &parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut messages = vec![];
+
+    for node in &module.methods {
+        crate::ast::visit_nodes(node, &mut |node| {
+            let fn_name = match node {
+                Node::Send(send) => match send.message.as_ref() {
+                    Node::Call(call) => &call.fn_name,
+                    _ => return,
+                },
+                _ => return,
+            };
+
+            let (trait_name, method_name) = match fn_name.split_once('.') {
+                Some(names) => names,
+                None => return,
+            };
+
+            let required = match index.trait_method_index.get(trait_name) {
+                Some(methods) => methods
+                    .iter()
+                    .any(|method| method.required && &method.prototype.name == fn_name),
+                None => false,
+            };
+
+            if required {
+                messages.push(format!(
+                    "Can't send `{}` to a {}: it has no default, and sends aren't dispatched to the implementing class",
+                    method_name, trait_name
+                ));
+            }
+        });
+    }
+
+    for message in messages {
+        diagnostics.error(message);
+    }
+}
+
+/// Definite assignment
+///
+/// A local has to be assigned on every path that reaches a read of it. One
+/// assigned in only one branch of an `if`, or only in a `while` body that
+/// might not run, can't be read after it:
+///
+/// ```text
+/// def sign(n Int) -> Int
+///   if n < 0
+///     s = -1
+///   end
+///
+///   s
+/// end
+/// ```
+///
+/// Branches that return, `break` or `next` don't reach what comes after
+/// them, so they don't need to assign it. After a `loop`, what's assigned is
+/// what every `break` out of it had assigned.
+fn check_definite_assignment(module: &parser::Module, diagnostics: &mut Diagnostics) {
+    for node in &module.methods {
+        let def_node = match node {
+            Node::Def(def_node) => def_node,
+            _ => continue,
+        };
+
+        let mut locals = HashSet::new();
+
+        for node in &def_node.body {
+            crate::ast::visit_nodes(node, &mut |node| {
+                if let Node::AssignLocalVar(assignment) = node {
+                    locals.insert(assignment.name.clone());
+                }
+            });
+        }
+
+        let mut check = DefiniteAssignment {
+            fn_name: &def_node.prototype.name,
+            locals,
+            assigned: Some(
+                def_node
+                    .prototype
+                    .args
+                    .iter()
+                    .map(|arg| arg.name.clone())
+                    .collect(),
+            ),
+            loop_exits: vec![],
+            reported: HashSet::new(),
+            diagnostics,
+        };
+
+        for node in &def_node.body {
+            check.visit_node(node);
+        }
+    }
+}
+
+struct DefiniteAssignment<'a> {
+    fn_name: &'a str,
+    /// The names assigned anywhere in the def, other names read as locals
+    /// are function references
+    locals: HashSet<String>,
+    /// The locals assigned on every path to the node being visited, `None`
+    /// when no path reaches it
+    assigned: Option<HashSet<String>>,
+    /// For each `loop` being visited, what was assigned at each `break`
+    loop_exits: Vec<Vec<Option<HashSet<String>>>>,
+    reported: HashSet<String>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl DefiniteAssignment<'_> {
+    fn visit_body(&mut self, body: &[Node]) {
+        for node in body {
+            self.visit_node(node);
+        }
+    }
+}
+
+impl Visitor for DefiniteAssignment<'_> {
+    fn visit_node(&mut self, node: &Node) {
+        let assigned = match &self.assigned {
+            Some(assigned) => assigned,
+            None => return,
+        };
+
+        match node {
+            Node::AssignLocalVar(assignment) => {
+                self.visit_node(&assignment.value);
+
+                if let Some(assigned) = self.assigned.as_mut() {
+                    assigned.insert(assignment.name.clone());
+                }
+            }
+            Node::LocalVar(lvar) => {
+                if self.locals.contains(&lvar.name)
+                    && !assigned.contains(&lvar.name)
+                    && self.reported.insert(lvar.name.clone())
+                {
+                    self.diagnostics.error(format!(
+                        "`{}` might be read before it's assigned in `{}`",
+                        lvar.name, self.fn_name
+                    ));
+                }
+            }
+            Node::If(if_node) => {
+                self.visit_node(&if_node.condition);
+
+                let before = self.assigned.clone();
+                self.visit_body(&if_node.then_body);
+                let after_then = std::mem::replace(&mut self.assigned, before);
+                self.visit_body(&if_node.else_body);
+
+                self.assigned = assigned_on_both(after_then, self.assigned.take());
+            }
+            Node::While(while_node) => {
+                self.visit_node(&while_node.condition);
+
+                let before = self.assigned.clone();
+                self.loop_exits.push(vec![]);
+                self.visit_body(&while_node.body);
+                self.loop_exits.pop();
+                self.assigned = before;
+            }
+            Node::Loop(loop_node) => {
+                self.loop_exits.push(vec![]);
+                self.visit_body(&loop_node.body);
+
+                let exits = self.loop_exits.pop().unwrap_or_default();
+                self.assigned = exits.into_iter().fold(None, assigned_on_both);
+            }
+            Node::Break => {
+                let exit = self.assigned.take();
+
+                if let Some(exits) = self.loop_exits.last_mut() {
+                    exits.push(exit);
+                }
+            }
+            Node::Next => self.assigned = None,
+            Node::Ret(ret) => {
+                self.visit_node(&ret.value);
+                self.assigned = None;
+            }
+            _ => walk_node(self, node),
+        }
+    }
+}
+
+/// What's assigned where two paths join, a path that doesn't get there
+/// doesn't count.
+fn assigned_on_both(
+    left: Option<HashSet<String>>,
+    right: Option<HashSet<String>>,
+) -> Option<HashSet<String>> {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(left.intersection(&right).cloned().collect()),
+        (left, None) => left,
+        (None, right) => right,
+    }
+}
+
+/// Nil and optional types
+///
+/// A class declared with a question mark, like `Str?`, can be `nil` as well
+/// as an instance. Nothing can be sent to one that might be nil, the type
+/// checker reports it, until an `if` has checked that it isn't:
+///
+/// ```text
+/// def greet(name Str?) -> Str?
+///   if name != nil
+///     name.upcase()
+///   end
+///
+///   name&.upcase()
+/// end
+/// ```
+///
+/// `name&.upcase()` sends `upcase` only when `name` isn't nil, and is nil
+/// otherwise. In the body of an `if x != nil`, the else body of an
+/// `if x == nil`, the rest of an `&&` after `x != nil`, and after an
+/// `if x == nil` that returns, `break`s or `next`s, the local `x` is typed as
+/// the class it wraps. Attributes can't be checked like this, as anything
+/// could set them in between, so they're copied to a local first.
+///
+/// Runs right after type inference, which types locals as they're declared.
+fn apply_nil_checks(module: &mut crate::parser::Module) {
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            NilChecks {
+                checked: HashSet::new(),
+            }
+            .visit_body(&mut def_node.body, vec![]);
+        }
+    }
+}
+
+struct NilChecks {
+    /// The locals checked not to be nil where the visited node runs
+    checked: HashSet<String>,
+}
+
+impl NilChecks {
+    /// Visits `body`, with the `checked` locals known not to be nil in it
+    /// until they're assigned.
+    fn visit_body(&mut self, body: &mut [Node], checked: Vec<String>) {
+        let outer = self.checked.clone();
+        self.checked.extend(checked);
+
+        for node in body {
+            self.visit_node_mut(node);
+
+            crate::ast::visit_nodes(node, &mut |node| {
+                if let Node::AssignLocalVar(assignment) = node {
+                    self.checked.remove(&assignment.name);
+                }
+            });
+
+            // `if x == nil return end`
+            if let Node::If(if_node) = node {
+                if if_node.else_body.is_empty() && if_node.then_body.last().is_some_and(exits) {
+                    self.checked.extend(nil_checked(&if_node.condition).1);
+                }
+            }
+        }
+
+        self.checked = outer;
+    }
+}
+
+impl VisitorMut for NilChecks {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        match node {
+            Node::LocalVar(lvar) if self.checked.contains(&lvar.name) => {
+                if let Some(BaseType::Optional(base_type)) = &lvar.return_type {
+                    lvar.return_type = Some(*base_type.clone());
+                }
+            }
+            Node::If(if_node) => {
+                self.visit_node_mut(&mut if_node.condition);
+
+                let (then_checked, else_checked) = nil_checked(&if_node.condition);
+                self.visit_body(&mut if_node.then_body, then_checked);
+                self.visit_body(&mut if_node.else_body, else_checked);
+            }
+            Node::Binary(binary) if binary.op == "&&" => {
+                self.visit_node_mut(&mut binary.left);
+
+                let outer = self.checked.clone();
+                self.checked.extend(nil_checked(&binary.left).0);
+                self.visit_node_mut(&mut binary.right);
+                self.checked = outer;
+            }
+            // A local assigned in a loop might be nil again when it goes
+            // around
+            Node::Loop(_) | Node::While(_) => {
+                let outer = self.checked.clone();
+
+                crate::ast::visit_nodes(node, &mut |node| {
+                    if let Node::AssignLocalVar(assignment) = node {
+                        self.checked.remove(&assignment.name);
+                    }
+                });
+
+                match node {
+                    Node::Loop(loop_node) => self.visit_body(&mut loop_node.body, vec![]),
+                    Node::While(while_node) => {
+                        self.visit_node_mut(&mut while_node.condition);
+                        self.visit_body(&mut while_node.body, vec![]);
+                    }
+                    _ => {}
+                }
+
+                self.checked = outer;
+            }
+            _ => walk_node_mut(self, node),
+        }
+    }
+}
+
+/// The locals `condition` checks aren't nil when it's true, and when it's
+/// false.
+fn nil_checked(condition: &Node) -> (Vec<String>, Vec<String>) {
+    let binary = match condition {
+        Node::Binary(binary) => binary,
+        _ => return (vec![], vec![]),
+    };
+
+    if binary.op == "&&" {
+        let mut checked = nil_checked(&binary.left).0;
+        checked.extend(nil_checked(&binary.right).0);
+
+        return (checked, vec![]);
+    }
+
+    let name = match (binary.left.as_ref(), binary.right.as_ref()) {
+        (Node::LocalVar(lvar), Node::Nil(_)) | (Node::Nil(_), Node::LocalVar(lvar)) => {
+            lvar.name.clone()
+        }
+        _ => return (vec![], vec![]),
+    };
+
+    match binary.op.as_str() {
+        "!=" => (vec![name], vec![]),
+        "==" => (vec![], vec![name]),
+        _ => (vec![], vec![]),
+    }
+}
+
+/// Whether `node` leaves the body it's in, with `return`, `break` or `next`.
+fn exits(node: &Node) -> bool {
+    matches!(node, Node::Ret(_) | Node::Break | Node::Next)
+}
+
+/// Types each `nil` given as an optional argument, held by an array of
+/// optionals, returned from a def that returns an optional, assigned to an
+/// optional attribute or last in a branch of an `if` that's an optional.
+/// Codegen compiles it to a null pointer of that type.
+///
+/// Runs once every call has its arguments in order and every def returns
+/// with `Ret`.
+fn type_nil_literals(module: &mut crate::parser::Module, index: &parser::ParserResultIndex) {
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            let mut nil_types = NilTypes {
+                index,
+                class_name: def_node
+                    .prototype
+                    .name
+                    .split_once('.')
+                    .map(|(class_name, _)| class_name.to_string()),
+                return_type: def_node.prototype.return_type.clone(),
+            };
+
+            for node in def_node.body.iter_mut() {
+                nil_types.visit_node_mut(node);
+            }
+        }
+    }
+}
+
+struct NilTypes<'a> {
+    index: &'a parser::ParserResultIndex,
+    /// The class of the def being visited, whose attributes `@name = nil`
+    /// assigns
+    class_name: Option<String>,
+    return_type: Option<BaseType>,
+}
+
+impl VisitorMut for NilTypes<'_> {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        match node {
+            Node::Ret(ret) => type_nil(&mut ret.value, self.return_type.as_ref()),
+            Node::Array(array) => {
+                for item in array.items.iter_mut() {
+                    type_nil(item, Some(&array.item_type));
+                }
+            }
+            Node::Call(call) => {
+                if let Some(prototype) =
+                    crate::type_checker::resolve_prototype(&call.fn_name, self.index)
+                {
+                    // Aligned from the end, as sends don't give their `sret`
+                    for (arg, value) in prototype.args.iter().rev().zip(call.args.iter_mut().rev())
+                    {
+                        type_nil(value, Some(&arg.return_type));
+                    }
+                }
+            }
+            Node::If(if_node) => {
+                for body in [&mut if_node.then_body, &mut if_node.else_body] {
+                    if let Some(last) = body.last_mut() {
+                        type_nil(last, if_node.return_type.as_ref());
+                    }
+                }
+            }
+            Node::AssignAttribute(assignment) => {
+                let attribute_type = self
+                    .class_name
+                    .as_ref()
+                    .and_then(|class_name| self.index.class_index.get(class_name))
+                    .and_then(|class| {
+                        class
+                            .attributes
+                            .iter()
+                            .find(|attribute| attribute.name == assignment.name)
+                    })
+                    .map(|attribute| attribute.return_type.clone());
+
+                type_nil(&mut assignment.value, attribute_type.as_ref());
+            }
+            Node::AssignAttributeAccess(assignment) => {
+                type_nil(
+                    &mut assignment.value,
+                    assignment.access.return_type.as_ref(),
+                );
+            }
+            _ => {}
+        }
+
+        walk_node_mut(self, node);
+    }
+}
+
+fn type_nil(node: &mut Node, expected: Option<&BaseType>) {
+    if let (Node::Nil(nil), Some(expected @ BaseType::Optional(_))) = (node, expected) {
+        nil.return_type = Some(expected.clone());
+    }
+}
+
+/// Named and default arguments
+///
+/// An argument declared with a default value, as in
+/// `def greet(name Str = "world", times Int = 1)`, can be left out of calls
+/// and sends, and arguments can be given by name after the positional ones:
+///
+/// ```text
+/// greet()
+/// greet("Rex", times: 2)
+/// greet(times: 3)
+/// ```
+///
+/// Each call is rewritten to give every argument in declaration order, with
+/// a copy of the default for each one left out, so backends compile the
+/// default at the call site like any other argument.
+///
+/// Arguments are evaluated left to right as they're written, like operands
+/// and the parts of an interpolated string. When putting named arguments in
+/// declaration order would run two with side effects in another order, the
+/// ones with side effects are kept in locals first:
+///
+/// ```text
+/// draw(y: read(), x: read())
+/// # becomes
+/// __arg_0 = read()
+/// __arg_1 = read()
+/// draw(__arg_1, __arg_0)
+/// ```
+///
+/// That's only done for the call a statement makes, an assignment assigns or
+/// a `ret` returns, since for calls nested in other expressions the locals
+/// would run before the rest of the statement. Those are reported instead.
+///
+/// Runs right after type inference, which prefixes sends with the class of
+/// their receiver so they can be matched to their prototype.
+fn apply_call_arguments(
+    module: &mut crate::parser::Module,
+    index: &parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut resolver = CallArguments {
+        index,
+        messages: vec![],
+        temp_count: 0,
+    };
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            resolver.order_body(&mut def_node.body);
+        }
+    }
+
+    for message in resolver.messages {
+        diagnostics.error(message);
+    }
+}
+
+struct CallArguments<'a> {
+    index: &'a parser::ParserResultIndex,
+    messages: Vec<String>,
+    temp_count: usize,
+}
+
+impl CallArguments<'_> {
+    fn order_body(&mut self, body: &mut Vec<Node>) {
+        let mut ordered = vec![];
+
+        for node in std::mem::take(body) {
+            self.order_statement(node, &mut ordered);
+        }
+
+        *body = ordered;
+    }
+
+    /// Orders the calls in `node` and pushes it onto `ordered`, after the
+    /// locals keeping its own call's arguments in order.
+    fn order_statement(&mut self, mut node: Node, ordered: &mut Vec<Node>) {
+        match &mut node {
+            Node::If(if_node) => {
+                self.visit_node_mut(&mut if_node.condition);
+                self.order_body(&mut if_node.then_body);
+                self.order_body(&mut if_node.else_body);
+            }
+            Node::While(while_node) => {
+                self.visit_node_mut(&mut while_node.condition);
+                self.order_body(&mut while_node.body);
+            }
+            Node::Loop(loop_node) => self.order_body(&mut loop_node.body),
+            _ => {
+                if let Some(call_node) = statement_call(&mut node) {
+                    for assignment in self.keep_args_in_order(call_node) {
+                        self.order_statement(assignment, ordered);
+                    }
+                }
+
+                self.visit_node_mut(&mut node);
+            }
+        }
+
+        ordered.push(node);
+    }
+
+    /// Replaces the args of `call_node` that have side effects with locals,
+    /// returning their assignments in source order, when ordering its args
+    /// would otherwise run them in another order.
+    fn keep_args_in_order(&mut self, call_node: &mut parser::Call) -> Vec<Node> {
+        let reorders = crate::type_checker::resolve_prototype(&call_node.fn_name, self.index)
+            .is_some_and(|prototype| reorders_side_effects(call_node, &prototype.args));
+
+        // Args the analyzer couldn't type are left for the error
+        if !reorders
+            || call_node
+                .args
+                .iter()
+                .any(|arg| has_side_effects(arg) && typed_node_base_type(arg).is_none())
+        {
+            return vec![];
+        }
+
+        let mut assignments = vec![];
+
+        for arg in call_node
+            .args
+            .iter_mut()
+            .filter(|arg| has_side_effects(arg))
+        {
+            let name = format!("__arg_{}", self.temp_count);
+            self.temp_count += 1;
+
+            let local = Node::LocalVar(parser::LocalVar {
+                name: name.clone(),
+                return_type: typed_node_base_type(arg),
+            });
+
+            assignments.push(Node::AssignLocalVar(parser::AssignLocalVar {
+                name,
+                value: Box::new(std::mem::replace(arg, local)),
+            }));
+        }
+
+        assignments
+    }
+}
+
+/// The call `node` makes before anything else it does: its own, or the one
+/// it assigns or returns. Sends count when their receiver has no side
+/// effects, except `&.` ones, whose args aren't evaluated for nil.
+fn statement_call(node: &mut Node) -> Option<&mut parser::Call> {
+    let value = match node {
+        Node::AssignLocalVar(assignment) => assignment.value.as_mut(),
+        Node::Ret(ret_node) => ret_node.value.as_mut(),
+        node => node,
+    };
+
+    match value {
+        Node::Call(call_node) => Some(call_node),
+        Node::Send(send_node)
+            if !send_node.safe_navigation && !has_side_effects(&send_node.receiver) =>
+        {
+            match send_node.message.as_mut() {
+                Node::Call(call_node) => Some(call_node),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether putting the named args of `call_node` in the order of `args`
+/// would run two with side effects in another order than they're given in.
+/// Positional args come first either way, and defaults are literals.
+fn reorders_side_effects(call_node: &parser::Call, args: &[parser::Arg]) -> bool {
+    let positional_count = call_node.args.len() - call_node.arg_names.len();
+    let positions: Vec<usize> = call_node
+        .arg_names
+        .iter()
+        .zip(&call_node.args[positional_count..])
+        .filter(|(_, value)| has_side_effects(value))
+        .filter_map(|(name, _)| args.iter().position(|arg| &arg.name == name))
+        .collect();
+
+    positions.windows(2).any(|pair| pair[0] > pair[1])
+}
+
+impl VisitorMut for CallArguments<'_> {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+
+        if let Node::Call(call_node) = node {
+            if let Err(message) = order_call_args(call_node, self.index) {
+                self.messages.push(message);
+            }
+        }
+    }
+}
+
+/// Puts the args of `call_node` in the order its prototype declares them and
+/// fills in the defaults. A call that's given too few args without naming
+/// any is left for the type checker to report, as are ones given too many.
+fn order_call_args(
+    call_node: &mut parser::Call,
+    index: &parser::ParserResultIndex,
+) -> Result<(), String> {
+    let prototype = match crate::type_checker::resolve_prototype(&call_node.fn_name, index) {
+        Some(prototype) => prototype,
+        None if call_node.arg_names.is_empty() => return Ok(()),
+        None => {
+            return Err(format!(
+                "`{}` doesn't take named arguments",
+                call_node.fn_name
+            ))
+        }
+    };
+
+    // Calls with every arg, including calls generated with the receiver as
+    // `sret`, are already in order
+    if call_node.arg_names.is_empty() && call_node.args.len() >= prototype.args.len() {
+        return Ok(());
+    }
+
+    let expected = match prototype.args.first() {
+        Some(arg) if arg.name == "sret" => &prototype.args[1..],
+        _ => &prototype.args[..],
+    };
+
+    let positional_count = call_node.args.len() - call_node.arg_names.len();
+
+    if positional_count > expected.len() {
+        return Ok(());
+    }
+
+    let mut slots: Vec<Option<&Node>> = vec![None; expected.len()];
+
+    for (slot, value) in slots.iter_mut().zip(&call_node.args[..positional_count]) {
+        *slot = Some(value);
+    }
+
+    for (name, value) in call_node
+        .arg_names
+        .iter()
+        .zip(&call_node.args[positional_count..])
+    {
+        match expected.iter().position(|arg| &arg.name == name) {
+            Some(position) if slots[position].is_none() => slots[position] = Some(value),
+            Some(_) => {
+                return Err(format!(
+                    "`{}` is given `{}` more than once",
+                    call_node.fn_name, name
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "`{}` has no argument named `{}`",
+                    call_node.fn_name, name
+                ))
+            }
+        }
+    }
+
+    if reorders_side_effects(call_node, expected) {
+        return Err(format!(
+            "`{}` is given named arguments with side effects out of order; give them in order or assign them to locals first",
+            call_node.fn_name
+        ));
+    }
+
+    let mut args = vec![];
+
+    for (slot, arg) in slots.into_iter().zip(expected) {
+        match (slot, &arg.default) {
+            (Some(value), _) | (None, Some(value)) => args.push(value.clone()),
+            (None, None) if call_node.arg_names.is_empty() => return Ok(()),
+            (None, None) => {
+                return Err(format!(
+                    "`{}` is missing an argument for `{}`",
+                    call_node.fn_name, arg.name
+                ))
+            }
+        }
+    }
+
+    call_node.args = args;
+    call_node.arg_names.clear();
+
+    Ok(())
+}
+
+/// Freezing
+///
+/// `value.freeze()` marks an instance as frozen, after which assigning one of
+/// its attributes stops the program with the class, attribute and method of
+/// the assignment. Instances have no header to keep the flag in, so the
+/// runtime tracks frozen addresses and every attribute assignment checks
+/// them. The checks are only emitted when the program calls `freeze`, and
+/// go right before the statement with the assignment, wherever it's nested.
+///
+/// Runs before type inference, so the nodes it adds are inferred like the
+/// rest of the program.
+fn apply_freeze_protocol(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let uses_freeze = module.methods.iter().any(|node| match node {
+        Node::Def(def_node) => def_node.body.iter().any(sends_freeze),
+        _ => false,
+    });
+
+    if !uses_freeze {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        diagnostics.error("freeze requires the Str class to be defined".to_string());
+        return;
+    }
+
+    let str_type = BaseType::Class("Str".to_string());
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![
+            ("pj_freeze", vec![("object", BaseType::BytePtr)], None),
+            ("pj_unfreeze", vec![("object", BaseType::BytePtr)], None),
+            (
+                "pj_check_frozen",
+                vec![("object", BaseType::BytePtr), ("site", str_type)],
+                None,
+            ),
+        ],
+    );
+
+    for node in module.methods.iter_mut() {
+        let def_node = match node {
+            Node::Def(def_node) => def_node,
+            _ => continue,
+        };
+
+        // Instances live on the stack, so a new one can reuse the address of
+        // a frozen one that went out of scope
+        if def_node.prototype.name == format!("{}.new", def_node.class_name) {
+            let self_node = Node::SelfRef(parser::SelfRef {
+                return_type: BaseType::Class(def_node.class_name.clone()),
+            });
+
+            def_node
+                .body
+                .insert(0, runtime_call("pj_unfreeze", vec![self_node]));
+            continue;
+        }
+
+        FrozenChecks {
+            class_name: def_node.class_name.clone(),
+            fn_name: def_node.prototype.name.clone(),
+        }
+        .visit_body(&mut def_node.body);
+    }
+
+    let mut class_names: Vec<String> = index.class_index.keys().cloned().collect();
+    class_names.sort();
+
+    for class_name in class_names {
+        let fn_name = format!("{}.freeze", class_name);
+
+        if index.fn_prototype_index.contains_key(&fn_name) {
+            continue;
+        }
+
+        let class_type = BaseType::Class(class_name.clone());
+        let def_node = Def {
+            main_fn: false,
+            prototype: parser::Prototype {
+                name: fn_name,
+                args: vec![parser::Arg {
+                    name: "sret".to_string(),
+                    return_type: class_type.clone(),
+                    default: None,
+                }],
+                return_type: None,
+                is_op: false,
+                prec: 0,
+            },
+            body: vec![runtime_call(
+                "pj_freeze",
+                vec![Node::SelfRef(parser::SelfRef {
+                    return_type: class_type,
+                })],
+            )],
+            class_name,
+            impl_name: "".to_string(),
+            trait_name: "".to_string(),
+        };
+
+        index
+            .fn_prototype_index
+            .insert(def_node.prototype.name.clone(), def_node.prototype.clone());
+        module.methods.push(Node::Def(def_node));
+    }
+}
+
+fn runtime_call(fn_name: &str, args: Vec<Node>) -> Node {
+    Node::Call(parser::Call {
+        fn_name: fn_name.to_string(),
+        args,
+        return_type: None,
+        arg_names: vec![],
+    })
+}
+
+/// Whether `freeze` is sent anywhere in `node`, as a statement or as part of
+/// an assignment, a `ret` or another call's arguments.
+fn sends_freeze(node: &Node) -> bool {
+    let mut found = false;
+
+    crate::ast::visit_nodes(node, &mut |node| {
+        if let Node::Send(send_node) = node {
+            found |= matches!(
+                send_node.message.as_ref(),
+                Node::Call(call_node) if call_node.fn_name == "freeze"
+            );
+        }
+    });
+
+    found
+}
+
+/// Adds a `pj_check_frozen` before each statement of a def that assigns an
+/// attribute, see `apply_freeze_protocol`.
+struct FrozenChecks {
+    class_name: String,
+    /// The def the checks are in, for the site they report
+    fn_name: String,
+}
+
+impl FrozenChecks {
+    fn visit_body(&mut self, body: &mut Vec<Node>) {
+        for mut node in std::mem::take(body) {
+            let mut assignments = AttributeAssignments {
+                self_type: BaseType::Class(self.class_name.clone()),
+                found: vec![],
+            };
+            assignments.visit_node(&node);
+
+            for (receiver, attribute_name) in assignments.found {
+                let site = format!("{}= in {}", attribute_name, self.fn_name);
+
+                body.push(runtime_call(
+                    "pj_check_frozen",
+                    vec![
+                        receiver,
+                        Node::StringLiteral(parser::StringLiteral { value: site }),
+                    ],
+                ));
+            }
+
+            self.visit_node_mut(&mut node);
+            body.push(node);
+        }
+    }
+}
+
+impl VisitorMut for FrozenChecks {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        match node {
+            Node::If(if_node) => {
+                self.visit_node_mut(&mut if_node.condition);
+                self.visit_body(&mut if_node.then_body);
+                self.visit_body(&mut if_node.else_body);
+            }
+            Node::While(while_node) => {
+                self.visit_node_mut(&mut while_node.condition);
+                self.visit_body(&mut while_node.body);
+            }
+            Node::Loop(loop_node) => self.visit_body(&mut loop_node.body),
+            _ => walk_node_mut(self, node),
+        }
+    }
+}
+
+/// The receivers and names of the attributes a statement assigns, leaving
+/// out the bodies nested in it, which get their own checks.
+struct AttributeAssignments {
+    self_type: BaseType,
+    found: Vec<(Node, String)>,
+}
+
+impl Visitor for AttributeAssignments {
+    fn visit_node(&mut self, node: &Node) {
+        let self_node = || {
+            Node::SelfRef(parser::SelfRef {
+                return_type: self.self_type.clone(),
+            })
+        };
+
+        match node {
+            Node::If(if_node) => return self.visit_node(&if_node.condition),
+            Node::While(while_node) => return self.visit_node(&while_node.condition),
+            Node::Loop(_) => return,
+            Node::AssignAttribute(node) => self.found.push((self_node(), node.name.clone())),
+            Node::AssignAttributeAccess(node) => {
+                match (node.access.receiver.as_ref(), node.access.message.as_ref()) {
+                    (Node::LocalVar(lvar), Node::Attribute(attribute)) => self.found.push((
+                        Node::LocalVar(parser::LocalVar {
+                            name: lvar.name.clone(),
+                            return_type: lvar.return_type.clone(),
+                        }),
+                        attribute.name.clone(),
+                    )),
+                    (Node::SelfRef(_), Node::Attribute(attribute)) => {
+                        self.found.push((self_node(), attribute.name.clone()))
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        walk_node(self, node);
+    }
+}
+
+/// `--freeze-literals`
+///
+/// Freezes each local assigned a string literal right after the assignment,
+/// since literals are shared globals rather than copies, so changing one
+/// would change it everywhere else it's written. The `freeze` sends it adds
+/// are handled by `apply_freeze_protocol` like the program's own.
+///
+/// Runs before analysis.
+pub fn apply_freeze_literals(result: &mut ParserResult) {
+    if let Node::Module(module) = &mut result.module {
+        for node in module.methods.iter_mut() {
+            if let Node::Def(def_node) = node {
+                FrozenLiterals.visit_body(&mut def_node.body);
+            }
+        }
+    }
+}
+
+struct FrozenLiterals;
+
+impl FrozenLiterals {
+    fn visit_body(&mut self, body: &mut Vec<Node>) {
+        for mut node in std::mem::take(body) {
+            self.visit_node_mut(&mut node);
+
+            let frozen_literal = match &node {
+                Node::AssignLocalVar(node) => match node.value.as_ref() {
+                    Node::StringLiteral(_) => Some(node.name.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            body.push(node);
+
+            if let Some(name) = frozen_literal {
+                body.push(Node::Send(parser::Send {
+                    receiver: Box::new(Node::LocalVar(parser::LocalVar {
+                        name,
+                        return_type: Some(BaseType::Class("Str".to_string())),
+                    })),
+                    message: Box::new(Node::Call(parser::Call {
+                        fn_name: "freeze".to_string(),
+                        args: vec![],
+                        return_type: None,
+                        arg_names: vec![],
+                    })),
+                    return_type: None,
+                    safe_navigation: false,
+                }));
+            }
+        }
+    }
+}
+
+impl VisitorMut for FrozenLiterals {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        match node {
+            Node::If(if_node) => {
+                self.visit_body(&mut if_node.then_body);
+                self.visit_body(&mut if_node.else_body);
+            }
+            Node::While(while_node) => self.visit_body(&mut while_node.body),
+            Node::Loop(loop_node) => self.visit_body(&mut loop_node.body),
+            _ => walk_node_mut(self, node),
+        }
+    }
+}
+
+/// External functions that touch files, the network or other processes, which
+/// `--sandbox` replaces with stubs
+/// The `def_e`s a sandboxed program may call: the runtime functions that
+/// only compute, print or allocate. Anything else, including what a program
+/// declares itself, is taken away by `apply_sandbox`.
+const SANDBOX_ALLOWED_FNS: [&str; 108] = [
+    // Printing
+    "print_int",
+    "print_bytes",
+    "pj_puts",
+    "pj_print",
+    "pj_set_print_hook",
+    "pj_repl_print_bool",
+    "pj_repl_print_float",
+    "pj_repl_print_int",
+    "pj_repl_print_str",
+    "pj_log_info",
+    "pj_log_warn",
+    "pj_log_error",
+    // Conversions and arithmetic
+    "pj_bool_to_s",
+    "pj_float_to_i",
+    "pj_float_to_s",
+    "pj_int_parse",
+    "pj_int_to_f",
+    "pj_int_to_s",
+    "pj_int_to_s_base",
+    "pj_checked_add",
+    "pj_checked_sub",
+    "pj_checked_mul",
+    "pj_saturating_mul",
+    "pj_add_overflows",
+    "pj_sub_overflows",
+    "pj_mul_overflows",
+    // Strings
+    "pj_str_byte_length",
+    "pj_str_chars",
+    "pj_str_codepoints",
+    "pj_str_concat",
+    "pj_str_encode",
+    "pj_str_ends_with",
+    "pj_str_index_of",
+    "pj_str_length",
+    "pj_str_slice",
+    "pj_str_starts_with",
+    "pj_str_sub",
+    "pj_str_to_nfc",
+    "pj_str_to_nfd",
+    "pj_str_to_nfkc",
+    "pj_str_to_nfkd",
+    "pj_str_trim",
+    "pj_bytes_decode",
+    "pj_csv_parse",
+    "pj_csv_write",
+    // Paths, only the ones that don't look at the file system
+    "pj_path_basename",
+    "pj_path_dirname",
+    "pj_path_extension",
+    "pj_path_join",
+    // Arrays and instances
+    "pj_malloc_struct",
+    "pj_array_new",
+    "pj_array_length",
+    "pj_array_get_bool",
+    "pj_array_get_float",
+    "pj_array_get_int",
+    "pj_array_get_ptr",
+    "pj_array_push_bool",
+    "pj_array_push_float",
+    "pj_array_push_int",
+    "pj_array_push_ptr",
+    "pj_array_set_bool",
+    "pj_array_set_float",
+    "pj_array_set_int",
+    "pj_array_set_ptr",
+    "pj_sort",
+    "pj_sort_by",
+    "pj_min",
+    "pj_max",
+    "pj_binary_search",
+    "pj_index_of",
+    "pj_freeze",
+    "pj_unfreeze",
+    "pj_check_frozen",
+    // Dates and times
+    "pj_date_add_days",
+    "pj_date_day",
+    "pj_date_days_until",
+    "pj_date_format",
+    "pj_date_iso8601",
+    "pj_date_month",
+    "pj_date_parse",
+    "pj_date_today",
+    "pj_date_weekday",
+    "pj_date_year",
+    "pj_datetime_add_days",
+    "pj_datetime_add_seconds",
+    "pj_datetime_advance_clock",
+    "pj_datetime_day",
+    "pj_datetime_format",
+    "pj_datetime_freeze_clock",
+    "pj_datetime_from_unix",
+    "pj_datetime_hour",
+    "pj_datetime_iso8601",
+    "pj_datetime_minute",
+    "pj_datetime_month",
+    "pj_datetime_now",
+    "pj_datetime_now_utc",
+    "pj_datetime_parse",
+    "pj_datetime_second",
+    "pj_datetime_seconds_until",
+    "pj_datetime_to_date",
+    "pj_datetime_to_local",
+    "pj_datetime_to_unix",
+    "pj_datetime_to_utc",
+    "pj_datetime_unfreeze_clock",
+    "pj_datetime_weekday",
+    "pj_datetime_year",
+    // Exiting
+    "pj_at_exit",
+    "pj_sandbox_denied",
+];
+
+/// The sandbox
+///
+/// For running untrusted programs with fewer capabilities. Every `def_e` not
+/// in `SANDBOX_ALLOWED_FNS` becomes a def of the same name that stops the
+/// program with an error naming the function, so callers are left as they
+/// are. Files, the network, processes and signals are out of reach that way.
+///
+/// Runs after analysis, so the runtime functions it declares are checked too.
+/// Stubs can only return nothing or an Int, any other `def_e` is an error.
+pub fn apply_sandbox(result: &mut ParserResult, diagnostics: &mut Diagnostics) {
+    let module = match &mut result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    let sandboxed = |node: &Node| match node {
+        Node::DefE(def_e) => !SANDBOX_ALLOWED_FNS.contains(&def_e.prototype.name.as_str()),
+        _ => false,
+    };
+
+    if !module.methods.iter().any(sandboxed) {
+        return;
+    }
+
+    if !result.index.class_index.contains_key("Str") {
+        diagnostics.error("--sandbox requires the Str class to be defined".to_string());
+        return;
+    }
+
+    declare_runtime_fns(
+        module,
+        &mut result.index,
+        vec![(
+            "pj_sandbox_denied",
+            vec![("name", BaseType::Class("Str".to_string()))],
+            Some(BaseType::Int),
+        )],
+    );
+
+    for node in module.methods.iter_mut() {
+        if !sandboxed(node) {
+            continue;
+        }
+
+        let prototype = match node {
+            Node::DefE(def_e) => def_e.prototype.clone(),
+            _ => unreachable!(),
+        };
+
+        let denied = Node::Call(parser::Call {
+            fn_name: "pj_sandbox_denied".to_string(),
+            args: vec![Node::StringLiteral(parser::StringLiteral {
+                value: prototype.name.clone(),
+            })],
+            return_type: Some(BaseType::Int),
+            arg_names: vec![],
+        });
+
+        // The call never returns, its value only satisfies the signature
+        let body = match &prototype.return_type {
+            None => vec![denied],
+            Some(BaseType::Int | BaseType::Int64) => {
+                vec![Node::Ret(parser::Ret {
+                    value: Box::new(denied),
+                })]
+            }
+            Some(_) => {
+                diagnostics.error(format!(
+                    "`{}` isn't available with --sandbox",
+                    prototype.name
+                ));
+                continue;
+            }
+        };
+
+        *node = Node::Def(Def {
+            main_fn: false,
+            prototype,
+            body,
+            class_name: "".to_string(),
+            impl_name: "".to_string(),
+            trait_name: "".to_string(),
+        });
+    }
+}
+
+/// The `to_s` protocol
+///
+/// Every value passed to `puts` or `print` or interpolated into a string, as
+/// in `"#{name} is #{age}"`, is converted to a `Str` first:
+///
+/// * `Str` is printed as is
+/// * integers use the builtin `pj_int_to_s`, floats `pj_float_to_s` and
+///   booleans `pj_bool_to_s`
+/// * classes call their own or an inherited `to_s`, which must return `Str`
+/// * classes without one get a generated default showing the class name and
+///   its attributes, e.g. `Dog(legs: 4, name: Rex)`
+///
+/// The parts of an interpolated string are then joined with `pj_str_concat`,
+/// as are two `Str`s added with `+`. Parts known at compile time, the text
+/// and interpolated literals, are joined first, so `"#{n} of #{10} left"`
+/// only concatenates at runtime once. `print` is `puts` without the newline.
+///
+/// Runs after type inference, so the nodes it builds carry their types.
+fn apply_to_s_protocol(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut concatenations = StrConcatenations { used: false };
+    let mut conversions = ToSConversions {
+        index,
+        default_to_s_classes: vec![],
+        uses_puts: false,
+        uses_interpolation: false,
+        diagnostics,
+    };
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                concatenations.visit_node_mut(body_node);
+                conversions.visit_node_mut(body_node);
+            }
+        }
+    }
+
+    let ToSConversions {
+        mut default_to_s_classes,
+        uses_puts,
+        uses_interpolation,
+        ..
+    } = conversions;
+
+    if !uses_puts && !uses_interpolation && !concatenations.used {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        let feature = if uses_puts {
+            "puts and print"
+        } else {
+            "string interpolation"
+        };
+
+        diagnostics.error(format!("{} requires the Str class to be defined", feature));
+        return;
+    }
+
+    // Generating a default can require defaults for the classes of its
+    // attributes, so this grows while it is walked
+    let mut generated = 0;
+
+    while generated < default_to_s_classes.len() {
+        let class_name = default_to_s_classes[generated].clone();
+        let def_node =
+            build_default_to_s(&class_name, index, &mut default_to_s_classes, diagnostics);
+
+        index
+            .fn_prototype_index
+            .insert(def_node.prototype.name.clone(), def_node.prototype.clone());
+        module.methods.push(Node::Def(def_node));
+
+        generated += 1;
+    }
+
+    let str_type = BaseType::Class("Str".to_string());
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![
+            ("pj_puts", vec![("str", str_type.clone())], None),
+            ("pj_print", vec![("str", str_type.clone())], None),
+            (
+                "pj_bool_to_s",
+                vec![("value", BaseType::Bool)],
+                Some(str_type.clone()),
+            ),
+            (
+                "pj_int_to_s",
+                vec![("int", BaseType::Int)],
+                Some(str_type.clone()),
+            ),
+            (
+                "pj_float_to_s",
+                vec![("float", BaseType::Float)],
+                Some(str_type.clone()),
+            ),
+            (
+                "pj_str_concat",
+                vec![("left", str_type.clone()), ("right", str_type.clone())],
+                Some(str_type.clone()),
+            ),
+        ],
+    );
+}
+
+/// Return values
+///
+/// A def with a return type returns the value of its last expression, as if
+/// it was given to `return`. When it ends with an `if`, each branch returns
+/// its own last expression instead, so it needs an `else`. `return value`,
+/// or `ret value`, returns early from anywhere in the body.
+///
+/// `main` and constructors are left alone, they don't return a value of
+/// their own.
+fn apply_implicit_returns(module: &mut crate::parser::Module, diagnostics: &mut Diagnostics) {
+    for node in module.methods.iter_mut() {
+        let def_node = match node {
+            Node::Def(def_node) => def_node,
+            _ => continue,
+        };
+
+        let return_type = match &def_node.prototype.return_type {
+            Some(BaseType::Void) | None => continue,
+            Some(return_type) => return_type.clone(),
+        };
+
+        let name = &def_node.prototype.name;
+
+        if def_node.main_fn
+            || def_node.body.is_empty()
+            || name.ends_with(".new")
+            || name.ends_with(".alloca")
+        {
+            continue;
+        }
+
+        if !return_last_value(&mut def_node.body) {
+            diagnostics.error(format!(
+                "`{}` should return {}, but its last statement has no value",
+                name,
+                pajama_class_name(&return_type)
+            ));
+        }
+    }
+}
+
+/// Wraps the last node of `body` in a `Ret`, or the last nodes of both
+/// branches of an `if`. False when there's no value to return.
+fn return_last_value(body: &mut [Node]) -> bool {
+    let last = match body.last_mut() {
+        Some(last) => last,
+        None => return false,
+    };
+
+    match last {
+        Node::Ret(_) => true,
+        Node::If(if_node) => {
+            // The branches return, rather than yield, their values
+            if_node.return_type = None;
+
+            !if_node.else_body.is_empty()
+                && return_last_value(&mut if_node.then_body)
+                && return_last_value(&mut if_node.else_body)
+        }
+        Node::Call(call_node) if call_node.fn_name == "defer" => false,
+        Node::Loop(_)
+        | Node::While(_)
+        | Node::Break
+        | Node::Next
+        | Node::AssignLocalVar(_)
+        | Node::AssignAttribute(_)
+        | Node::AssignAttributeAccess(_)
+        | Node::AssignConstant(_) => false,
+        _ => {
+            let value = std::mem::replace(last, Node::Int(parser::Int { value: 0 }));
+
+            *last = Node::Ret(parser::Ret {
+                value: Box::new(value),
+            });
+            true
+        }
+    }
+}
+
+/// Deferred statements
+///
+/// `defer expr` at the root of a def runs `expr` when the def returns, from
+/// the end of its body or from a `return`:
+///
+/// ```text
+/// def copy(path Str)
+///   file = File.open(path)
+///   defer file.close()
+///   ...
+/// end
+/// ```
+///
+/// Only the defers reached before the def returns run, the latest first. A
+/// returned value is computed before them, so a defer can't change it.
+///
+/// Runs after implicit returns are added, so each `Ret` in the body is a
+/// place the def returns from.
+fn apply_defers(module: &mut crate::parser::Module) {
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            let mut temp_count = 0;
+            def_node.body = lower_defers(std::mem::take(&mut def_node.body), &mut temp_count);
+        }
+    }
+}
+
+fn lower_defers(body: Vec<Node>, temp_count: &mut usize) -> Vec<Node> {
+    let mut deferred = vec![];
+    let mut lowered = vec![];
+
+    for node in body {
+        match node {
+            Node::Call(mut call_node) if call_node.fn_name == "defer" => {
+                deferred.push(call_node.args.remove(0));
+            }
+            _ => {
+                let mut statements = vec![node];
+
+                if !deferred.is_empty() {
+                    run_deferred_before_returns(&mut statements, &deferred, temp_count);
+                }
+
+                lowered.extend(statements);
+            }
+        }
+    }
+
+    if !lowered.last().is_some_and(always_returns) {
+        lowered.extend(deferred.into_iter().rev());
+    }
+
+    lowered
+}
+
+/// Whether the def has returned once `node` runs, so nothing after it does.
+fn always_returns(node: &Node) -> bool {
+    match node {
+        Node::Ret(_) => true,
+        Node::If(if_node) => {
+            if_node.then_body.last().is_some_and(always_returns)
+                && if_node.else_body.last().is_some_and(always_returns)
+        }
+        _ => false,
+    }
+}
+
+/// Puts the `deferred` statements, latest first, before every `Ret` in `body`.
+/// A returned value is kept in a local first, so it's computed before them.
+fn run_deferred_before_returns(body: &mut Vec<Node>, deferred: &[Node], temp_count: &mut usize) {
+    let mut lowered = vec![];
+
+    for mut node in std::mem::take(body) {
+        match &mut node {
+            Node::If(if_node) => {
+                run_deferred_before_returns(&mut if_node.then_body, deferred, temp_count);
+                run_deferred_before_returns(&mut if_node.else_body, deferred, temp_count);
+            }
+            Node::Loop(loop_node) => {
+                run_deferred_before_returns(&mut loop_node.body, deferred, temp_count)
+            }
+            Node::While(while_node) => {
+                run_deferred_before_returns(&mut while_node.body, deferred, temp_count)
+            }
+            Node::Ret(ret_node) => {
+                if let Some(return_type) = typed_node_base_type(&ret_node.value) {
+                    let name = format!("__deferred_return_{}", temp_count);
+                    *temp_count += 1;
+
+                    let value = std::mem::replace(
+                        ret_node.value.as_mut(),
+                        Node::LocalVar(parser::LocalVar {
+                            name: name.clone(),
+                            return_type: Some(return_type),
+                        }),
+                    );
+
+                    lowered.push(Node::AssignLocalVar(parser::AssignLocalVar {
+                        name,
+                        value: Box::new(value),
+                    }));
+                }
+
+                lowered.extend(deferred.iter().rev().cloned());
+            }
+            _ => {}
+        }
+
+        lowered.push(node);
+    }
+
+    *body = lowered;
+}
+
+type RuntimeFn<'a> = (&'a str, Vec<(&'a str, BaseType)>, Option<BaseType>);
+
+/// Declares the runtime functions in `pajama_lib` a lowering calls into, unless
+/// the program already declared them with `def_e`.
+fn declare_runtime_fns(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    runtime_fns: Vec<RuntimeFn>,
+) {
+    for (name, args, return_type) in runtime_fns {
+        if index.fn_prototype_index.contains_key(name) {
+            continue;
+        }
+
+        let prototype = parser::Prototype {
+            name: name.to_string(),
+            args: args
+                .into_iter()
+                .map(|(arg_name, return_type)| parser::Arg {
+                    name: arg_name.to_string(),
+                    return_type,
+                    default: None,
+                })
+                .collect(),
+            return_type,
+            is_op: false,
+            prec: 0,
+        };
+
+        index
+            .fn_prototype_index
+            .insert(prototype.name.clone(), prototype.clone());
+        module.methods.push(Node::DefE(parser::DefE { prototype }));
+    }
+}
+
+/// Converts the values passed to `puts` and `print` and the parts of
+/// interpolated strings to `Str`, wherever they are nested.
+struct ToSConversions<'a> {
+    index: &'a parser::ParserResultIndex,
+    /// The classes printed without a `to_s` of their own, to generate one for
+    default_to_s_classes: Vec<String>,
+    uses_puts: bool,
+    uses_interpolation: bool,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl ToSConversions<'_> {
+    fn convert_to_s(&mut self, node: Node) -> Node {
+        to_s_expr(
+            node,
+            self.index,
+            &mut self.default_to_s_classes,
+            self.diagnostics,
+        )
+    }
+
+    fn rewrite_puts_call(&mut self, call_node: &mut parser::Call) {
+        self.uses_puts = true;
+
+        if call_node.args.len() != 1 {
+            self.diagnostics
+                .error(format!("{} takes exactly one argument", call_node.fn_name));
+            return;
+        }
+
+        let arg = call_node.args.remove(0);
+
+        call_node.fn_name = format!("pj_{}", call_node.fn_name);
+        call_node.args = vec![self.convert_to_s(arg)];
+    }
+
+    /// Replaces the interpolated string `node` with the concatenation of its
+    /// `parts`, see `parser::INTERPOLATE_FN`.
+    fn rewrite_interpolation(&mut self, node: &mut Node, parts: Vec<Node>) {
+        self.uses_interpolation = true;
+
+        let mut merged: Vec<Node> = vec![];
+
+        for part in parts {
+            let part = self.convert_to_s(part);
+
+            match (merged.last_mut(), constant_str(&part)) {
+                (Some(Node::StringLiteral(last)), Some(value)) => last.value.push_str(&value),
+                (_, Some(value)) => {
+                    merged.push(Node::StringLiteral(parser::StringLiteral { value }))
+                }
+                (_, None) => merged.push(part),
+            }
+        }
+
+        let str_type = BaseType::Class("Str".to_string());
+        let mut value = None;
+
+        for part in merged {
+            value = Some(match value {
+                Some(left) => Node::Call(parser::Call {
+                    fn_name: "pj_str_concat".to_string(),
+                    args: vec![left, part],
+                    return_type: Some(str_type.clone()),
+                    arg_names: vec![],
+                }),
+                None => part,
+            });
+        }
+
+        if let Some(value) = value {
+            *node = value;
+        }
+    }
+}
+
+impl VisitorMut for ToSConversions<'_> {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+
+        match node {
+            Node::Call(call_node)
+                if call_node.fn_name == "puts" || call_node.fn_name == "print" =>
+            {
+                self.rewrite_puts_call(call_node)
+            }
+            Node::Call(call_node) if call_node.fn_name == parser::INTERPOLATE_FN => {
+                let parts = std::mem::take(&mut call_node.args);
+                self.rewrite_interpolation(node, parts);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The text of an interpolated part known at compile time: a literal, or an
+/// integer or boolean literal converted like `pj_int_to_s` and
+/// `pj_bool_to_s` would at runtime.
+fn constant_str(part: &Node) -> Option<String> {
+    let call = match part {
+        Node::StringLiteral(literal) => return Some(literal.value.clone()),
+        Node::Call(call) if call.args.len() == 1 => call,
+        _ => return None,
+    };
+
+    match (call.fn_name.as_str(), &call.args[0]) {
+        ("pj_int_to_s", Node::Int(int)) => Some((int.value as i64).to_string()),
+        ("pj_bool_to_s", Node::Bool(bool)) => Some(bool.value.to_string()),
+        _ => None,
+    }
+}
+
+/// Replaces `left + right` on two `Str`s with `pj_str_concat(left, right)`.
+struct StrConcatenations {
+    used: bool,
+}
+
+impl VisitorMut for StrConcatenations {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+
+        let str_type = Some(BaseType::Class("Str".to_string()));
+
+        let (left, right) = match node {
+            Node::Binary(binary)
+                if binary.op == "+"
+                    && typed_node_base_type(&binary.left) == str_type
+                    && typed_node_base_type(&binary.right) == str_type =>
+            {
+                (
+                    std::mem::replace(binary.left.as_mut(), Node::Int(parser::Int { value: 0 })),
+                    std::mem::replace(binary.right.as_mut(), Node::Int(parser::Int { value: 0 })),
+                )
+            }
+            _ => return,
+        };
+
+        self.used = true;
+        *node = Node::Call(parser::Call {
+            fn_name: "pj_str_concat".to_string(),
+            args: vec![left, right],
+            return_type: str_type,
+            arg_names: vec![],
+        });
+    }
+}
+
+/// Wraps `node` so it evaluates to a `Str`.
+fn to_s_expr(
+    node: Node,
+    index: &parser::ParserResultIndex,
+    default_to_s_classes: &mut Vec<String>,
+    diagnostics: &mut Diagnostics,
+) -> Node {
+    let str_type = BaseType::Class("Str".to_string());
+
+    let base_type = match typed_node_base_type(&node) {
+        Some(base_type) => base_type,
+        None => {
+            diagnostics
+                .error("Could not infer the type of the value to convert to Str".to_string());
+            return node;
+        }
+    };
+
+    match &base_type {
+        BaseType::Class(class_name) if class_name == "Str" => node,
+        BaseType::Int | BaseType::Int64 | BaseType::Int32 | BaseType::Int16 | BaseType::Byte => {
+            Node::Call(parser::Call {
+                fn_name: "pj_int_to_s".to_string(),
+                args: vec![node],
+                return_type: Some(str_type),
+                arg_names: vec![],
+            })
+        }
+        BaseType::Float => Node::Call(parser::Call {
+            fn_name: "pj_float_to_s".to_string(),
+            args: vec![node],
+            return_type: Some(str_type),
+            arg_names: vec![],
+        }),
+        BaseType::Bool => Node::Call(parser::Call {
+            fn_name: "pj_bool_to_s".to_string(),
+            args: vec![node],
+            return_type: Some(str_type),
+            arg_names: vec![],
+        }),
+        BaseType::Class(class_name) => {
+            match index.resolve_method(class_name, "to_s") {
+                Ok(Some(fn_name)) => {
+                    let return_type = &index.fn_prototype_index[&fn_name].return_type;
+
+                    if return_type.as_ref() != Some(&str_type) {
+                        diagnostics.error(format!("`{}` must return Str", fn_name));
+                    }
+                }
+                Ok(None) => {
+                    if !default_to_s_classes.contains(class_name) {
+                        default_to_s_classes.push(class_name.clone());
+                    }
+                }
+                Err(error) => diagnostics.error(error),
+            }
+
+            Node::Send(parser::Send {
+                receiver: Box::new(node),
+                message: Box::new(Node::Call(parser::Call {
+                    fn_name: format!("{}.to_s", class_name),
+                    args: vec![],
+                    return_type: Some(str_type.clone()),
+                    arg_names: vec![],
+                })),
+                return_type: Some(str_type),
+                safe_navigation: false,
+            })
+        }
+        _ => {
+            diagnostics.error(format!(
+                "`{}` does not implement to_s",
+                pajama_class_name(&base_type)
+            ));
+            node
+        }
+    }
+}
+
+fn build_default_to_s(
+    class_name: &str,
+    index: &parser::ParserResultIndex,
+    default_to_s_classes: &mut Vec<String>,
+    diagnostics: &mut Diagnostics,
+) -> Def {
+    let str_type = BaseType::Class("Str".to_string());
+    let class_type = BaseType::Class(class_name.to_string());
+
+    let string_literal = |value: String| Node::StringLiteral(parser::StringLiteral { value });
+    let concat = |left: Node, right: Node| {
+        Node::Call(parser::Call {
+            fn_name: "pj_str_concat".to_string(),
+            args: vec![left, right],
+            return_type: Some(BaseType::Class("Str".to_string())),
+            arg_names: vec![],
+        })
+    };
+
+    let attributes = match index.class_index.get(class_name) {
+        Some(class) => class.attributes.clone(),
+        None => vec![],
+    };
+
+    let mut value = string_literal(format!("{}(", class_name));
+
+    for (position, attribute) in attributes.iter().enumerate() {
+        let separator = if position == 0 { "" } else { ", " };
+        value = concat(
+            value,
+            string_literal(format!("{}{}: ", separator, attribute.name)),
+        );
+
+        let attribute_value = match &attribute.return_type {
+            BaseType::Class(_)
+            | BaseType::Int
+            | BaseType::Int64
+            | BaseType::Int32
+            | BaseType::Int16
+            | BaseType::Byte
+            | BaseType::Float => {
+                let access = Node::Access(parser::Access {
+                    receiver: Box::new(Node::SelfRef(parser::SelfRef {
+                        return_type: class_type.clone(),
+                    })),
+                    message: Box::new(Node::Attribute(parser::Attribute {
+                        name: attribute.name.clone(),
+                        index: attribute.index,
+                        return_type: attribute.return_type.clone(),
+                    })),
+                    index: attribute.index,
+                    return_type: Some(attribute.return_type.clone()),
+                });
+
+                to_s_expr(access, index, default_to_s_classes, diagnostics)
+            }
+            // Raw pointers, function references and aggregates have no
+            // printable form yet
+            other => string_literal(format!("<{}>", pajama_class_name(other))),
+        };
+
+        value = concat(value, attribute_value);
+    }
+
+    value = concat(value, string_literal(")".to_string()));
+
+    Def {
+        main_fn: false,
+        prototype: parser::Prototype {
+            name: format!("{}.to_s", class_name),
+            args: vec![parser::Arg {
+                name: "sret".to_string(),
+                return_type: class_type,
+                default: None,
+            }],
+            return_type: Some(str_type),
+            is_op: false,
+            prec: 0,
+        },
+        body: vec![Node::Ret(parser::Ret {
+            value: Box::new(value),
+        })],
+        class_name: class_name.to_string(),
+        impl_name: "".to_string(),
+        trait_name: "".to_string(),
+    }
+}
+
+/// The type of an already inferred expression.
+pub fn typed_node_base_type(node: &Node) -> Option<BaseType> {
+    match node {
+        Node::Access(node) => node.return_type.clone(),
+        Node::Binary(node) => node.return_type.clone(),
+        Node::Call(node) => node.return_type.clone(),
+        Node::Bool(_) => Some(BaseType::Bool),
+        Node::Float(_) => Some(BaseType::Float),
+        Node::Int(_) => Some(BaseType::Int),
+        Node::LocalVar(node) => node.return_type.clone(),
+        Node::SelfRef(node) => Some(node.return_type.clone()),
+        Node::Send(node) => node.return_type.clone(),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+        Node::FnRef(_) => Some(BaseType::FnRef),
+        Node::If(node) => node.return_type.clone(),
+        Node::Array(node) => Some(BaseType::Array(Box::new(node.item_type.clone()))),
+        Node::Nil(node) => node.return_type.clone().or(Some(BaseType::Nil)),
+        _ => None,
+    }
+}
+
+const INT_CLASSES: [&str; 5] = ["Int", "Int64", "Int32", "Int16", "Byte"];
+
+/// Numeric formatting, parsing and conversions
+///
+/// * `n.to_s()` and `n.to_s(base)` format an integer in base 2 to 36
+/// * `Int.parse(str)` and `Int.parse(str, base)` parse one back, stopping the
+///   program when `str` isn't a valid integer
+/// * `x.to_s()` formats a float with the fewest digits that read back as it
+/// * `to_string` is another name for `to_s`, on integers and floats
+/// * `n.to_f()` converts an integer to a float, and `x.to_i()` a float to an
+///   integer, rounding towards zero
+///
+/// They're lowered to runtime functions that don't depend on the C locale, so
+/// output is the same on every platform.
+fn apply_numeric_formatting(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut uses_builtins = false;
+    let mut uses_conversions = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_numeric_calls(
+                    body_node,
+                    &mut uses_builtins,
+                    &mut uses_conversions,
+                    diagnostics,
+                );
+            }
+        }
+    }
+
+    if uses_conversions {
+        declare_runtime_fns(
+            module,
+            index,
+            vec![
+                (
+                    "pj_int_to_f",
+                    vec![("int", BaseType::Int)],
+                    Some(BaseType::Float),
+                ),
+                (
+                    "pj_float_to_i",
+                    vec![("float", BaseType::Float)],
+                    Some(BaseType::Int),
+                ),
+            ],
+        );
+    }
+
+    if !uses_builtins {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        diagnostics.error("to_s and parse require the Str class to be defined".to_string());
+        return;
+    }
+
+    let str_type = BaseType::Class("Str".to_string());
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![
+            (
+                "pj_int_to_s_base",
+                vec![("int", BaseType::Int), ("base", BaseType::Int)],
+                Some(str_type.clone()),
+            ),
+            (
+                "pj_int_parse",
+                vec![("str", str_type.clone()), ("base", BaseType::Int)],
+                Some(BaseType::Int),
+            ),
+            (
+                "pj_float_to_s",
+                vec![("float", BaseType::Float)],
+                Some(str_type),
+            ),
+        ],
+    );
+}
+
+fn rewrite_numeric_calls(
+    node: &mut Node,
+    uses_builtins: &mut bool,
+    uses_conversions: &mut bool,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut rewrite = |node: &mut Node, uses_builtins: &mut bool| {
+        rewrite_numeric_calls(node, uses_builtins, uses_conversions, diagnostics)
+    };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), uses_builtins),
+        Node::AssignAttributeAccess(node) => rewrite(node.value.as_mut(), uses_builtins),
+        Node::Ret(node) => rewrite(node.value.as_mut(), uses_builtins),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, uses_builtins);
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite(send_node.receiver.as_mut(), uses_builtins);
+            rewrite(send_node.message.as_mut(), uses_builtins);
+
+            let message = match send_node.message.as_mut() {
+                Node::Call(call_node) => call_node,
+                _ => return,
+            };
+
+            // `to_string` is another name for `to_s`
+            let is_to_s = INT_CLASSES.iter().any(|int_class| {
+                message.fn_name == format!("{}.to_s", int_class)
+                    || message.fn_name == format!("{}.to_string", int_class)
+            });
+            let is_to_f = INT_CLASSES
+                .iter()
+                .any(|int_class| message.fn_name == format!("{}.to_f", int_class));
+
+            // Conversions take the receiver as their only argument
+            let conversion_fn_name = match message.fn_name.as_str() {
+                _ if is_to_f => Some("pj_int_to_f"),
+                "Float.to_i" => Some("pj_float_to_i"),
+                "Float.to_s" | "Float.to_string" => Some("pj_float_to_s"),
+                _ => None,
+            };
+
+            if let Some(runtime_fn_name) = conversion_fn_name {
+                if !message.args.is_empty() {
+                    diagnostics.error(format!("`{}` takes no arguments", message.fn_name));
+                    return;
+                }
+
+                if runtime_fn_name == "pj_float_to_s" {
+                    *uses_builtins = true;
+                } else {
+                    *uses_conversions = true;
+                }
+
+                let receiver = std::mem::replace(
+                    send_node.receiver.as_mut(),
+                    Node::Int(parser::Int { value: 0 }),
+                );
+
+                *node = Node::Call(parser::Call {
+                    fn_name: runtime_fn_name.to_string(),
+                    args: vec![receiver],
+                    return_type: send_node.return_type.clone(),
+                    arg_names: vec![],
+                });
+                return;
+            }
+
+            let runtime_fn_name = match send_node.receiver.as_ref() {
+                Node::Const(_) if message.fn_name == "Int.parse" => {
+                    if message.args.is_empty() || message.args.len() > 2 {
+                        diagnostics
+                            .error("`Int.parse` takes a Str and an optional base".to_string());
+                        return;
+                    }
+
+                    "pj_int_parse"
+                }
+                _ if is_to_s => {
+                    if message.args.len() > 1 {
+                        diagnostics.error("`to_s` takes an optional base".to_string());
+                        return;
+                    }
+
+                    "pj_int_to_s_base"
+                }
+                _ => return,
+            };
+
+            let mut args: Vec<Node> = message.args.drain(..).collect();
+
+            if runtime_fn_name == "pj_int_to_s_base" {
+                // The receiver is replaced along with the send below
+                let receiver = std::mem::replace(
+                    send_node.receiver.as_mut(),
+                    Node::Int(parser::Int { value: 0 }),
+                );
+                args.insert(0, receiver);
+            }
+
+            if args.len() == 1 {
+                args.push(Node::Int(parser::Int { value: 10 }));
+            }
+
+            if let Node::Int(base) = &args[1] {
+                if !(2..=36).contains(&base.value) {
+                    diagnostics.error(format!(
+                        "Base {} is out of range, expected 2 to 36",
+                        base.value
+                    ));
+                }
+            }
+
+            *uses_builtins = true;
+            *node = Node::Call(parser::Call {
+                fn_name: runtime_fn_name.to_string(),
+                args,
+                return_type: send_node.return_type.clone(),
+                arg_names: vec![],
+            });
+        }
+        _ => {}
+    }
+}
+
+type StrMethod = (
+    &'static str,
+    &'static str,
+    Vec<(&'static str, BaseType)>,
+    BaseType,
+);
+
+/// Builtin Str methods: the name they're sent as, the runtime function they're
+/// lowered to, the arguments it takes after the receiver and what it returns
+fn str_methods() -> Vec<StrMethod> {
+    let str_type = BaseType::Class("Str".to_string());
+
+    vec![
+        (
+            "[]",
+            "pj_str_slice",
+            vec![("start", BaseType::Int), ("end", BaseType::Int)],
+            str_type.clone(),
+        ),
+        (
+            "sub",
+            "pj_str_sub",
+            vec![("start", BaseType::Int), ("length", BaseType::Int)],
+            str_type.clone(),
+        ),
+        ("trim", "pj_str_trim", vec![], str_type.clone()),
+        (
+            "starts_with?",
+            "pj_str_starts_with",
+            vec![("prefix", str_type.clone())],
+            BaseType::Bool,
+        ),
+        (
+            "ends_with?",
+            "pj_str_ends_with",
+            vec![("suffix", str_type.clone())],
+            BaseType::Bool,
+        ),
+        (
+            "index_of",
+            "pj_str_index_of",
+            vec![("needle", str_type.clone())],
+            BaseType::Int,
+        ),
+        ("length", "pj_str_length", vec![], BaseType::Int),
+        ("byte_length", "pj_str_byte_length", vec![], BaseType::Int),
+        (
+            "chars",
+            "pj_str_chars",
+            vec![],
+            BaseType::Array(Box::new(str_type.clone())),
+        ),
+        (
+            "codepoints",
+            "pj_str_codepoints",
+            vec![],
+            BaseType::Array(Box::new(BaseType::Int)),
+        ),
+        ("to_nfc", "pj_str_to_nfc", vec![], str_type.clone()),
+        ("to_nfd", "pj_str_to_nfd", vec![], str_type.clone()),
+        ("to_nfkc", "pj_str_to_nfkc", vec![], str_type.clone()),
+        ("to_nfkd", "pj_str_to_nfkd", vec![], str_type.clone()),
+        (
+            "encode",
+            "pj_str_encode",
+            vec![("encoding", str_type.clone()), ("on_invalid", str_type)],
+            BaseType::Array(Box::new(BaseType::Int)),
+        ),
+    ]
+}
+
+/// Str methods
+///
+/// * `s[start..end]` is the bytes from `start` up to `end`, and
+///   `s.sub(start, length)` the `length` bytes from `start`
+/// * `s.trim()` drops the whitespace at either end
+/// * `s.starts_with?(prefix)` and `s.ends_with?(suffix)`
+/// * `s.index_of(needle)` is the byte offset of `needle`, -1 when it's missing
+/// * `s.length()` is the number of characters and `s.byte_length()` the number
+///   of bytes
+/// * `s.chars()` is a `[Str]` of the characters and `s.codepoints()` an
+///   `[Int]` of the Unicode code points
+/// * `s.to_nfc()`, `s.to_nfd()`, `s.to_nfkc()` and `s.to_nfkd()` convert to
+///   a Unicode normalization form
+/// * `s.encode(encoding, on_invalid)` is an `[Int]` of the bytes of `s` in
+///   "utf-8", "utf-16" (little endian), "utf-16be" or "latin-1". With
+///   `on_invalid` "replace", characters Latin-1 can't hold become "?", and
+///   with "error" they stop the program
+///
+/// A character is a grapheme cluster, what a reader sees as one character
+/// even when it's several code points. Offsets are in bytes and clamped to
+/// the ends of the Str, and slicing through the bytes of a code point stops
+/// the program. A Str class defining a method of the same name keeps its own.
+fn apply_str_methods(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut used_fns = vec![];
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_str_methods(body_node, index, &mut used_fns, diagnostics);
+            }
+        }
+    }
+
+    if used_fns.is_empty() {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        diagnostics.error("Str methods require the Str class to be defined".to_string());
+        return;
+    }
+
+    let runtime_fns = str_methods()
+        .into_iter()
+        .filter(|(_, runtime_fn_name, _, _)| used_fns.contains(runtime_fn_name))
+        .map(|(_, runtime_fn_name, args, return_type)| {
+            let receiver = ("str", BaseType::Class("Str".to_string()));
+
+            (
+                runtime_fn_name,
+                std::iter::once(receiver).chain(args).collect(),
+                Some(return_type),
+            )
+        })
+        .collect();
+
+    declare_runtime_fns(module, index, runtime_fns);
+}
+
+fn rewrite_str_methods(
+    node: &mut Node,
+    index: &parser::ParserResultIndex,
+    used_fns: &mut Vec<&'static str>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut rewrite = |node: &mut Node, used_fns: &mut Vec<&'static str>| {
+        rewrite_str_methods(node, index, used_fns, diagnostics)
+    };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::AssignAttributeAccess(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Ret(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::Binary(node) => {
+            rewrite(node.left.as_mut(), used_fns);
+            rewrite(node.right.as_mut(), used_fns);
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, used_fns);
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite(send_node.receiver.as_mut(), used_fns);
+            rewrite(send_node.message.as_mut(), used_fns);
+
+            let message = match send_node.message.as_mut() {
+                Node::Call(call_node) => call_node,
+                _ => return,
+            };
+
+            // Methods the program defines itself are sent as usual
+            if index.fn_prototype_index.contains_key(&message.fn_name) {
+                return;
+            }
+
+            let (method_name, runtime_fn_name, args) = match str_methods()
+                .into_iter()
+                .find(|(method_name, _, _, _)| message.fn_name == format!("Str.{}", method_name))
+            {
+                Some((method_name, runtime_fn_name, args, _)) => {
+                    (method_name, runtime_fn_name, args)
+                }
+                None => return,
+            };
+
+            if message.args.len() != args.len() {
+                let arg_names: Vec<&str> = args.iter().map(|(arg_name, _)| *arg_name).collect();
+
+                diagnostics.error(match method_name {
+                    "[]" => "Str indexing takes a range, like `s[0..5]`".to_string(),
+                    _ if arg_names.is_empty() => format!("`{}` takes no arguments", method_name),
+                    _ => format!("`{}` takes {}", method_name, arg_names.join(" and ")),
+                });
+                return;
+            }
+
+            let receiver = std::mem::replace(
+                send_node.receiver.as_mut(),
+                Node::Int(parser::Int { value: 0 }),
+            );
+
+            let mut args = vec![receiver];
+            args.append(&mut message.args);
+
+            if !used_fns.contains(&runtime_fn_name) {
+                used_fns.push(runtime_fn_name);
+            }
+
+            *node = Node::Call(parser::Call {
+                fn_name: runtime_fn_name.to_string(),
+                args,
+                return_type: send_node.return_type.clone(),
+                arg_names: vec![],
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Builtin Array methods, typed by the item type of the array they're sent to
+const ARRAY_METHODS: [&str; 10] = [
+    "[]", "[]=", "length", "push", "each", "map", "decode", "sort", "min", "max",
+];
+
+/// The runtime slot items of `item_type` are kept in, which names the
+/// `pj_array_*` functions handling them. `None` for items arrays can't hold
+/// yet.
+pub fn array_slot_kind(item_type: &BaseType) -> Option<&'static str> {
+    match pajama_class_name(item_type).as_str() {
+        "Int" | "Int64" => Some("int"),
+        "Float" => Some("float"),
+        "Bool" => Some("bool"),
+        "" | "Byte" | "BytePtr" | "FnRef" | "Int16" | "Int32" | "Struct" => None,
+        // Instances and nested arrays are kept as a pointer
+        _ => Some("ptr"),
+    }
+}
+
+/// The type runtime functions take and return the items in a slot of `kind`
+/// as.
+pub fn array_slot_type(kind: &str) -> BaseType {
+    match kind {
+        "int" => BaseType::Int,
+        "float" => BaseType::Float,
+        "bool" => BaseType::Bool,
+        _ => BaseType::BytePtr,
+    }
+}
+
+/// Arrays
+///
+/// `[1, 2, 3]` builds a growable array of the type of its first item, written
+/// `[Int]` where a type is declared. Arrays hold Ints, Floats, Bools, instances
+/// and other arrays.
+///
+/// * `items[index]` reads an item and `items[index] = item` replaces it,
+///   stopping the program when `index` is out of bounds
+/// * `items.length()` is the number of items
+/// * `items.push(item)` adds an item at the end and returns the array
+/// * `items.each(f.fn_ref())` calls `f` with each item, and
+///   `items.map(f.fn_ref())` returns an array of what `f` returns for each
+/// * `bytes.decode(encoding, on_invalid)` is the Str an `[Int]` of bytes holds,
+///   see `s.encode` for the encodings
+///
+/// Indexing, `length` and `push` are lowered to runtime functions, while
+/// codegen compiles `each` and `map` to a loop calling `f`. An Array class
+/// defining a method of the same name keeps its own.
+fn apply_array_methods(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut used_kinds = vec![];
+    let mut decodes_bytes = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_array_methods(
+                    body_node,
+                    index,
+                    &mut used_kinds,
+                    &mut decodes_bytes,
+                    diagnostics,
+                );
+            }
+        }
+    }
+
+    if used_kinds.is_empty() {
+        return;
+    }
+
+    let mut runtime_fns = vec![
+        (
+            "pj_array_new".to_string(),
+            vec![("capacity", BaseType::Int)],
+            Some(BaseType::BytePtr),
+        ),
+        (
+            "pj_array_length".to_string(),
+            vec![("array", BaseType::BytePtr)],
+            Some(BaseType::Int),
+        ),
+    ];
+
+    if decodes_bytes {
+        let str_type = BaseType::Class("Str".to_string());
+
+        runtime_fns.push((
+            "pj_bytes_decode".to_string(),
+            vec![
+                ("bytes", BaseType::BytePtr),
+                ("encoding", str_type.clone()),
+                ("on_invalid", str_type.clone()),
+            ],
+            Some(str_type),
+        ));
+    }
+
+    for kind in used_kinds {
+        let slot_type = array_slot_type(kind);
+
+        runtime_fns.push((
+            format!("pj_array_push_{}", kind),
+            vec![("array", BaseType::BytePtr), ("item", slot_type.clone())],
+            Some(BaseType::BytePtr),
+        ));
+        runtime_fns.push((
+            format!("pj_array_get_{}", kind),
+            vec![("array", BaseType::BytePtr), ("index", BaseType::Int)],
+            Some(slot_type.clone()),
+        ));
+        runtime_fns.push((
+            format!("pj_array_set_{}", kind),
+            vec![
+                ("array", BaseType::BytePtr),
+                ("index", BaseType::Int),
+                ("item", slot_type),
+            ],
+            None,
+        ));
+    }
+
+    declare_runtime_fns(
+        module,
+        index,
+        runtime_fns
+            .iter()
+            .map(|(name, args, return_type)| (name.as_str(), args.clone(), return_type.clone()))
+            .collect(),
+    );
+}
+
+fn rewrite_array_methods(
+    node: &mut Node,
+    index: &parser::ParserResultIndex,
+    used_kinds: &mut Vec<&'static str>,
+    decodes_bytes: &mut bool,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut rewrite =
+        |node: &mut Node, used_kinds: &mut Vec<&'static str>, decodes_bytes: &mut bool| {
+            rewrite_array_methods(node, index, used_kinds, decodes_bytes, diagnostics)
+        };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), used_kinds, decodes_bytes),
+        Node::AssignAttribute(node) => rewrite(node.value.as_mut(), used_kinds, decodes_bytes),
+        Node::AssignAttributeAccess(node) => {
+            rewrite(node.value.as_mut(), used_kinds, decodes_bytes)
+        }
+        Node::Ret(node) => rewrite(node.value.as_mut(), used_kinds, decodes_bytes),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_kinds, decodes_bytes);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), used_kinds, decodes_bytes);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_kinds, decodes_bytes);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), used_kinds, decodes_bytes);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, used_kinds, decodes_bytes);
+            }
+        }
+        Node::Binary(node) => {
+            rewrite(node.left.as_mut(), used_kinds, decodes_bytes);
+            rewrite(node.right.as_mut(), used_kinds, decodes_bytes);
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, used_kinds, decodes_bytes);
+            }
+        }
+        Node::BuildStruct(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, used_kinds, decodes_bytes);
+            }
+        }
+        Node::Array(array) => {
+            for item in array.items.iter_mut() {
+                rewrite(item, used_kinds, decodes_bytes);
+            }
+
+            match array_slot_kind(&array.item_type) {
+                Some(kind) if !used_kinds.contains(&kind) => used_kinds.push(kind),
+                Some(_) => {}
+                None => diagnostics.error(format!(
+                    "Arrays can't hold {} yet",
+                    pajama_class_name(&array.item_type)
+                )),
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite(send_node.receiver.as_mut(), used_kinds, decodes_bytes);
+            rewrite(send_node.message.as_mut(), used_kinds, decodes_bytes);
+
+            let message = match send_node.message.as_mut() {
+                Node::Call(call_node) => call_node,
+                _ => return,
+            };
+
+            // Methods the program defines itself are sent as usual
+            if index.fn_prototype_index.contains_key(&message.fn_name) {
+                return;
+            }
+
+            let method_name = match message.fn_name.strip_prefix("Array.") {
+                Some(method_name) => method_name.to_string(),
+                None => return,
+            };
+
+            let item_type = match typed_node_base_type(&send_node.receiver) {
+                Some(BaseType::Array(item_type)) => *item_type,
+                _ => return,
+            };
+
+            let kind = match array_slot_kind(&item_type) {
+                Some(kind) => kind,
+                None => {
+                    diagnostics.error(format!(
+                        "Arrays can't hold {} yet",
+                        pajama_class_name(&item_type)
+                    ));
+                    return;
+                }
+            };
+
+            let arity = match method_name.as_str() {
+                "length" => 0,
+                "[]=" | "decode" => 2,
+                _ => 1,
+            };
+
+            if message.args.len() != arity {
+                diagnostics.error(match method_name.as_str() {
+                    "[]" | "[]=" => "Arrays are indexed by one Int, like `items[0]`".to_string(),
+                    "length" => "`length` takes no arguments".to_string(),
+                    "decode" => "`decode` takes encoding and on_invalid".to_string(),
+                    method_name => format!("`{}` takes one argument", method_name),
+                });
+                return;
+            }
+
+            if method_name == "decode" {
+                if kind != "int" {
+                    diagnostics.error(format!(
+                        "`decode` is sent to bytes, an [Int], not an array of {}",
+                        pajama_class_name(&item_type)
+                    ));
+                    return;
+                }
+
+                if !index.class_index.contains_key("Str") {
+                    diagnostics.error("`decode` requires the Str class to be defined".to_string());
+                    return;
+                }
+
+                *decodes_bytes = true;
+            }
+
+            if !used_kinds.contains(&kind) {
+                used_kinds.push(kind);
+            }
+
+            let (runtime_fn_name, return_type) = match method_name.as_str() {
+                "[]" => (format!("pj_array_get_{}", kind), Some(item_type)),
+                "[]=" => (format!("pj_array_set_{}", kind), None),
+                "length" => ("pj_array_length".to_string(), Some(BaseType::Int)),
+                "decode" => (
+                    "pj_bytes_decode".to_string(),
+                    Some(BaseType::Class("Str".to_string())),
+                ),
+                "push" => (
+                    format!("pj_array_push_{}", kind),
+                    Some(BaseType::Array(Box::new(item_type))),
+                ),
+                "each" | "map" => {
+                    rewrite_array_iteration(
+                        &method_name,
+                        message,
+                        &item_type,
+                        index,
+                        used_kinds,
+                        diagnostics,
+                    );
+                    return;
+                }
+                _ => return,
+            };
+
+            let receiver = std::mem::replace(
+                send_node.receiver.as_mut(),
+                Node::Int(parser::Int { value: 0 }),
+            );
+
+            let mut args = vec![receiver];
+            args.append(&mut message.args);
+
+            *node = Node::Call(parser::Call {
+                fn_name: runtime_fn_name,
+                args,
+                return_type,
+                arg_names: vec![],
+            });
+        }
+        _ => {}
+    }
+}
+
+/// `each` and `map` stay sends for codegen to compile to a loop, given the
+/// function they call as a `FnRef` once it's checked to take an item.
+fn rewrite_array_iteration(
+    method_name: &str,
+    message: &mut parser::Call,
+    item_type: &BaseType,
+    index: &parser::ParserResultIndex,
+    used_kinds: &mut Vec<&'static str>,
+    diagnostics: &mut Diagnostics,
+) {
+    let fn_name = referenced_fn_name(&message.args[0]);
+    let prototype = fn_name
+        .as_ref()
+        .and_then(|fn_name| index.fn_prototype_index.get(fn_name));
+
+    let takes_item = |prototype: &parser::Prototype| {
+        prototype.args.len() == 1
+            && pajama_class_name(&prototype.args[0].return_type) == pajama_class_name(item_type)
+    };
+
+    let result_kind = prototype
+        .and_then(|prototype| prototype.return_type.as_ref())
+        .and_then(array_slot_kind);
+
+    match (fn_name, prototype) {
+        (Some(fn_name), Some(prototype))
+            if takes_item(prototype) && (method_name == "each" || result_kind.is_some()) =>
+        {
+            if let Some(kind) = result_kind.filter(|_| method_name == "map") {
+                if !used_kinds.contains(&kind) {
+                    used_kinds.push(kind);
+                }
+            }
+
+            message.args[0] = Node::FnRef(parser::FnRef { fn_name });
+        }
+        _ if method_name == "map" => diagnostics.error(format!(
+            "`map` expects a function reference taking a {} and returning a value arrays can hold",
+            pajama_class_name(item_type)
+        )),
+        _ => diagnostics.error(format!(
+            "`each` expects a function reference taking a {}",
+            pajama_class_name(item_type)
+        )),
+    }
+}
+
+type DateMethod = (
+    &'static str,
+    &'static str,
+    Vec<(&'static str, BaseType)>,
+    BaseType,
+    bool,
+);
+
+/// Builtin Date and DateTime methods: the class, the name they're sent as, the
+/// arguments they take after the receiver, what they return, `Void` for
+/// nothing, and whether they're sent to the class itself, like
+/// `DateTime.now()`. Each is lowered to `pj_date_<name>` or
+/// `pj_datetime_<name>`.
+fn date_methods() -> Vec<DateMethod> {
+    let str_type = BaseType::Class("Str".to_string());
+    let date_type = BaseType::Class("Date".to_string());
+    let datetime_type = BaseType::Class("DateTime".to_string());
+
+    let mut methods = vec![
+        ("DateTime", "now", vec![], datetime_type.clone(), true),
+        ("DateTime", "now_utc", vec![], datetime_type.clone(), true),
+        (
+            "DateTime",
+            "parse",
+            vec![("str", str_type.clone())],
+            datetime_type.clone(),
+            true,
+        ),
+        (
+            "DateTime",
+            "from_unix",
+            vec![("seconds", BaseType::Int)],
+            datetime_type.clone(),
+            true,
+        ),
+        ("DateTime", "to_unix", vec![], BaseType::Int, false),
+        (
+            "DateTime",
+            "freeze_clock",
+            vec![("at", datetime_type.clone())],
+            BaseType::Void,
+            true,
+        ),
+        (
+            "DateTime",
+            "advance_clock",
+            vec![("seconds", BaseType::Int)],
+            BaseType::Void,
+            true,
+        ),
+        ("DateTime", "unfreeze_clock", vec![], BaseType::Void, true),
+        (
+            "DateTime",
+            "add_seconds",
+            vec![("seconds", BaseType::Int)],
+            datetime_type.clone(),
+            false,
+        ),
+        (
+            "DateTime",
+            "add_days",
+            vec![("days", BaseType::Int)],
+            datetime_type.clone(),
+            false,
+        ),
+        (
+            "DateTime",
+            "seconds_until",
+            vec![("other", datetime_type.clone())],
+            BaseType::Int,
+            false,
+        ),
+        ("DateTime", "to_utc", vec![], datetime_type.clone(), false),
+        ("DateTime", "to_local", vec![], datetime_type.clone(), false),
+        ("DateTime", "to_date", vec![], date_type.clone(), false),
+        (
+            "DateTime",
+            "format",
+            vec![("pattern", str_type.clone())],
+            str_type.clone(),
+            false,
+        ),
+        ("DateTime", "iso8601", vec![], str_type.clone(), false),
+        ("Date", "today", vec![], date_type.clone(), true),
+        (
+            "Date",
+            "parse",
+            vec![("str", str_type.clone())],
+            date_type.clone(),
+            true,
+        ),
+        (
+            "Date",
+            "add_days",
+            vec![("days", BaseType::Int)],
+            date_type.clone(),
+            false,
+        ),
+        (
+            "Date",
+            "days_until",
+            vec![("other", date_type)],
+            BaseType::Int,
+            false,
+        ),
+        (
+            "Date",
+            "format",
+            vec![("pattern", str_type.clone())],
+            str_type.clone(),
+            false,
+        ),
+        ("Date", "iso8601", vec![], str_type, false),
+    ];
+
+    let date_fields = ["year", "month", "day", "weekday"];
+    let time_fields = ["hour", "minute", "second"];
+
+    for field in date_fields {
+        methods.push(("Date", field, vec![], BaseType::Int, false));
+    }
+
+    for field in date_fields.into_iter().chain(time_fields) {
+        methods.push(("DateTime", field, vec![], BaseType::Int, false));
+    }
+
+    methods
+}
+
+fn date_method_runtime_fn_name(class_name: &str, method_name: &str) -> String {
+    format!("pj_{}_{}", class_name.to_lowercase(), method_name)
+}
+
+fn date_method_return_type(return_type: BaseType) -> Option<BaseType> {
+    match return_type {
+        BaseType::Void => None,
+        return_type => Some(return_type),
+    }
+}
+
+/// Dates and times
+///
+/// Programs declare the classes, laid out like the runtime's:
+///
+/// ```text
+/// class DateTime
+///   @seconds Int  # since the Unix epoch, in UTC
+///   @nanos   Int
+///   @offset  Int  # seconds east of UTC it's shown in
+/// end
+///
+/// class Date
+///   @days Int     # since 1970-01-01
+/// end
+/// ```
+///
+/// * `DateTime.now()` is the current time at the system's UTC offset, and
+///   `DateTime.now_utc()` the same instant in UTC
+/// * `DateTime.parse("2024-01-01T00:00:00Z")` parses RFC 3339, keeping the
+///   offset, and `DateTime.from_unix(seconds)` converts a Unix timestamp
+/// * `t.year()`, `month`, `day`, `hour`, `minute`, `second` and `weekday`
+///   (1 for Monday) read a field in the offset `t` is shown in
+/// * `t.add_seconds(n)` and `t.add_days(n)` move it, and
+///   `t.seconds_until(other)` measures the gap
+/// * `t.to_utc()` and `t.to_local()` show the same instant at another offset,
+///   and `t.to_date()` is the day it falls on
+/// * `t.format("%Y-%m-%d %H:%M")` formats with strftime directives and
+///   `t.iso8601()` as RFC 3339
+/// * `Date.today()`, `Date.parse("2024-01-01")`, `d.add_days(n)`,
+///   `d.days_until(other)` and the `year`, `month`, `day`, `weekday`, `format`
+///   and `iso8601` methods do the same for days
+/// * `DateTime.freeze_clock(t)` stops the clock `now`, `now_utc`, `today` and
+///   `Log` read at `t`, `DateTime.advance_clock(n)` moves it `n` seconds ahead
+///   and `DateTime.unfreeze_clock()` lets it run again, so tests get the same
+///   times on every run. `$PAJAMA_FROZEN_TIME` freezes it before the program
+///   starts.
+///
+/// Parsing something invalid stops the program. A Date or DateTime class
+/// defining a method of the same name keeps its own.
+fn apply_date_methods(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut used_fns = vec![];
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_date_methods(body_node, index, &mut used_fns, diagnostics);
+            }
+        }
+    }
+
+    if used_fns.is_empty() {
+        return;
+    }
+
+    let used_methods: Vec<DateMethod> = date_methods()
+        .into_iter()
+        .filter(|(class_name, method_name, _, _, _)| {
+            used_fns.contains(&date_method_runtime_fn_name(class_name, method_name))
+        })
+        .collect();
+
+    for class_name in ["Str", "Date", "DateTime"] {
+        let class_type = BaseType::Class(class_name.to_string());
+        let needed = used_methods
+            .iter()
+            .any(|(receiver_class, _, args, return_type, _)| {
+                *receiver_class == class_name
+                    || *return_type == class_type
+                    || args.iter().any(|(_, arg_type)| *arg_type == class_type)
+            });
+
+        if needed && !index.class_index.contains_key(class_name) {
+            diagnostics.error(format!(
+                "Date and DateTime methods require the {} class to be defined",
+                class_name
+            ));
+            return;
+        }
+    }
+
+    let mut runtime_fns = vec![];
+
+    for (class_name, method_name, args, return_type, class_method) in used_methods {
+        let receiver_name = match class_name {
+            "Date" => "date",
+            _ => "datetime",
+        };
+        let receiver = (receiver_name, BaseType::Class(class_name.to_string()));
+        let args: Vec<(&str, BaseType)> = match class_method {
+            true => args,
+            false => std::iter::once(receiver).chain(args).collect(),
+        };
+
+        runtime_fns.push((
+            date_method_runtime_fn_name(class_name, method_name),
+            args,
+            date_method_return_type(return_type),
+        ));
+    }
+
+    declare_runtime_fns(
+        module,
+        index,
+        runtime_fns
+            .iter()
+            .map(|(name, args, return_type)| (name.as_str(), args.clone(), return_type.clone()))
+            .collect(),
+    );
+}
+
+fn rewrite_date_methods(
+    node: &mut Node,
+    index: &parser::ParserResultIndex,
+    used_fns: &mut Vec<String>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut rewrite = |node: &mut Node, used_fns: &mut Vec<String>| {
+        rewrite_date_methods(node, index, used_fns, diagnostics)
+    };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::AssignAttribute(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::AssignAttributeAccess(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Ret(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::Binary(node) => {
+            rewrite(node.left.as_mut(), used_fns);
+            rewrite(node.right.as_mut(), used_fns);
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, used_fns);
+            }
+        }
+        Node::Array(array) => {
+            for item in array.items.iter_mut() {
+                rewrite(item, used_fns);
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite(send_node.receiver.as_mut(), used_fns);
+            rewrite(send_node.message.as_mut(), used_fns);
+
+            let message = match send_node.message.as_mut() {
+                Node::Call(call_node) => call_node,
+                _ => return,
+            };
+
+            // Methods the program defines itself are sent as usual
+            if index.fn_prototype_index.contains_key(&message.fn_name) {
+                return;
+            }
+
+            let (class_name, method_name, args, _, class_method) = match date_methods()
+                .into_iter()
+                .find(|(class_name, method_name, _, _, _)| {
+                    message.fn_name == format!("{}.{}", class_name, method_name)
+                }) {
+                Some(method) => method,
+                None => return,
+            };
+
+            let sent_to_class = matches!(send_node.receiver.as_ref(), Node::Const(_));
+
+            if sent_to_class != class_method {
+                diagnostics.error(match class_method {
+                    true => format!(
+                        "`{}` is sent to the class, like `{}.{}()`",
+                        method_name, class_name, method_name
+                    ),
+                    false => format!(
+                        "`{}` is sent to a {}, not the class",
+                        method_name, class_name
+                    ),
+                });
+                return;
+            }
+
+            if message.args.len() != args.len() {
+                let arg_names: Vec<&str> = args.iter().map(|(arg_name, _)| *arg_name).collect();
+
+                diagnostics.error(match arg_names.is_empty() {
+                    true => format!("`{}` takes no arguments", method_name),
+                    false => format!("`{}` takes {}", method_name, arg_names.join(" and ")),
+                });
+                return;
+            }
+
+            let mut args = vec![];
+
+            if !class_method {
+                args.push(std::mem::replace(
+                    send_node.receiver.as_mut(),
+                    Node::Int(parser::Int { value: 0 }),
+                ));
+            }
+
+            args.append(&mut message.args);
+
+            let runtime_fn_name = date_method_runtime_fn_name(class_name, method_name);
+
+            if !used_fns.contains(&runtime_fn_name) {
+                used_fns.push(runtime_fn_name.clone());
+            }
+
+            *node = Node::Call(parser::Call {
+                fn_name: runtime_fn_name,
+                args,
+                return_type: send_node.return_type.clone(),
+                arg_names: vec![],
+            });
+        }
+        _ => {}
+    }
+}
+
+type BuiltinFunction = (
+    &'static str,
+    &'static str,
+    Vec<(&'static str, BaseType)>,
+    Option<BaseType>,
+);
+
+/// Builtin functions sent to a class that's never defined, like `Path`: the
+/// class, the name, their arguments and what they return, if anything. Each
+/// is lowered to `pj_<class>_<name>`, like `pj_path_join`.
+fn builtin_functions() -> Vec<BuiltinFunction> {
+    let str_type = BaseType::Class("Str".to_string());
+    let path = || vec![("path", BaseType::Class("Str".to_string()))];
+    let message = || vec![("message", BaseType::Class("Str".to_string()))];
+    let rows_type = BaseType::Array(Box::new(BaseType::Array(Box::new(str_type.clone()))));
+
+    vec![
+        (
+            "Path",
+            "join",
+            vec![("path", str_type.clone()), ("other", str_type.clone())],
+            Some(str_type.clone()),
+        ),
+        ("Path", "basename", path(), Some(str_type.clone())),
+        ("Path", "dirname", path(), Some(str_type.clone())),
+        ("Path", "extension", path(), Some(str_type.clone())),
+        ("Path", "absolute", path(), Some(str_type.clone())),
+        (
+            "Path",
+            "glob",
+            vec![("pattern", str_type.clone())],
+            Some(BaseType::Array(Box::new(str_type.clone()))),
+        ),
+        (
+            "Csv",
+            "parse",
+            vec![("text", str_type.clone())],
+            Some(rows_type.clone()),
+        ),
+        ("Csv", "write", vec![("rows", rows_type)], Some(str_type)),
+        ("Log", "info", message(), None),
+        ("Log", "warn", message(), None),
+        ("Log", "error", message(), None),
+    ]
+}
+
+fn builtin_function_runtime_fn_name(class_name: &str, name: &str) -> String {
+    format!("pj_{}_{}", class_name.to_lowercase(), name)
+}
+
+/// Paths
+///
+/// Paths are Strs, taken apart and put together by functions sent to `Path`:
+///
+/// * `Path.join(dir, name)` appends `name`, unless it's absolute
+/// * `Path.basename(path)` is the last component, `Path.dirname(path)` the
+///   rest and `Path.extension(path)` the extension without its dot
+/// * `Path.absolute(path)` resolves `path` against the working directory,
+///   expanding a leading "~" to `$HOME`
+/// * `Path.glob("logs/**/*.log")` is a `[Str]` of the matching paths
+///
+/// CSV
+///
+/// `Csv.parse(text)` splits CSV text into a `[[Str]]` of rows, the header row
+/// first when there is one, and `Csv.write(rows)` joins rows back into text,
+/// quoting the fields that need it.
+///
+/// Logging
+///
+/// `Log.info(message)`, `Log.warn(message)` and `Log.error(message)` write
+/// `message` to stderr after the time and level. `$PAJAMA_LOG_LEVEL=warn`
+/// leaves out info messages, `error` leaves out warnings too.
+fn apply_builtin_functions(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut used_fns = vec![];
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_builtin_functions(body_node, index, &mut used_fns, diagnostics);
+            }
+        }
+    }
+
+    if used_fns.is_empty() {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        let mut class_names = vec![];
+
+        for (class_name, _) in &used_fns {
+            if !class_names.contains(class_name) {
+                class_names.push(*class_name);
+            }
+        }
+
+        for class_name in class_names {
+            diagnostics.error(format!(
+                "{} functions require the Str class to be defined",
+                class_name
+            ));
+        }
+        return;
+    }
+
+    let runtime_fns: Vec<_> = builtin_functions()
+        .into_iter()
+        .filter(|(class_name, name, _, _)| used_fns.contains(&(*class_name, *name)))
+        .map(|(class_name, name, args, return_type)| {
+            (
+                builtin_function_runtime_fn_name(class_name, name),
+                args,
+                return_type,
+            )
+        })
+        .collect();
+
+    declare_runtime_fns(
+        module,
+        index,
+        runtime_fns
+            .iter()
+            .map(|(name, args, return_type)| (name.as_str(), args.clone(), return_type.clone()))
+            .collect(),
+    );
+}
+
+fn rewrite_builtin_functions(
+    node: &mut Node,
+    index: &parser::ParserResultIndex,
+    used_fns: &mut Vec<(&'static str, &'static str)>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut rewrite = |node: &mut Node, used_fns: &mut Vec<(&'static str, &'static str)>| {
+        rewrite_builtin_functions(node, index, used_fns, diagnostics)
+    };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::AssignAttribute(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::AssignAttributeAccess(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Ret(node) => rewrite(node.value.as_mut(), used_fns),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), used_fns);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, used_fns);
+            }
+        }
+        Node::Binary(node) => {
+            rewrite(node.left.as_mut(), used_fns);
+            rewrite(node.right.as_mut(), used_fns);
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite(arg, used_fns);
+            }
+        }
+        Node::Array(array) => {
+            for item in array.items.iter_mut() {
+                rewrite(item, used_fns);
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite(send_node.receiver.as_mut(), used_fns);
+            rewrite(send_node.message.as_mut(), used_fns);
+
+            let message = match (send_node.receiver.as_ref(), send_node.message.as_mut()) {
+                (Node::Const(_), Node::Call(call_node)) => call_node,
+                _ => return,
+            };
+
+            // Methods the program defines itself are sent as usual
+            if index.fn_prototype_index.contains_key(&message.fn_name) {
+                return;
+            }
+
+            let (class_name, name, args, _) =
+                match builtin_functions()
+                    .into_iter()
+                    .find(|(class_name, name, _, _)| {
+                        message.fn_name == format!("{}.{}", class_name, name)
+                    }) {
+                    Some(function) => function,
+                    None => return,
+                };
+
+            if message.args.len() != args.len() {
+                let arg_names: Vec<&str> = args.iter().map(|(arg_name, _)| *arg_name).collect();
+
+                diagnostics.error(format!(
+                    "`{}.{}` takes {}",
+                    class_name,
+                    name,
+                    arg_names.join(" and ")
+                ));
+                return;
+            }
+
+            if !used_fns.contains(&(class_name, name)) {
+                used_fns.push((class_name, name));
+            }
+
+            *node = Node::Call(parser::Call {
+                fn_name: builtin_function_runtime_fn_name(class_name, name),
+                args: message.args.drain(..).collect(),
+                return_type: send_node.return_type.clone(),
+                arg_names: vec![],
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Signals `Signal.trap` accepts, by the name they're given without `SIG`
+pub const TRAPPABLE_SIGNALS: [&str; 6] = ["INT", "TERM", "HUP", "QUIT", "USR1", "USR2"];
+
+/// Signal traps
+///
+/// `Signal.trap("INT", on_interrupt.fn_ref())` registers `on_interrupt` to run
+/// each time the program gets SIGINT, so a long running service can shut down
+/// cleanly. It's lowered to `pj_signal_trap`, which runs handlers on a thread
+/// of their own rather than inside the signal handler.
+fn apply_signal_traps(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut uses_traps = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_signal_traps(body_node, &mut uses_traps, diagnostics);
+            }
+        }
+    }
+
+    if !uses_traps {
+        return;
+    }
+
+    if !index.class_index.contains_key("Str") {
+        diagnostics.error("Signal.trap requires the Str class to be defined".to_string());
+        return;
+    }
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![(
+            "pj_signal_trap",
+            vec![
+                ("name", BaseType::Class("Str".to_string())),
+                ("handler", BaseType::FnRef),
+            ],
+            None,
+        )],
+    );
+}
+
+fn rewrite_signal_traps(node: &mut Node, uses_traps: &mut bool, diagnostics: &mut Diagnostics) {
+    let message = match node {
+        Node::Loop(loop_node) => {
+            for body_node in loop_node.body.iter_mut() {
+                rewrite_signal_traps(body_node, uses_traps, diagnostics);
+            }
+
+            return;
+        }
+        Node::If(if_node) => {
+            for body_node in if_node
+                .then_body
+                .iter_mut()
+                .chain(if_node.else_body.iter_mut())
+            {
+                rewrite_signal_traps(body_node, uses_traps, diagnostics);
+            }
+
+            return;
+        }
+        Node::While(while_node) => {
+            for body_node in while_node.body.iter_mut() {
+                rewrite_signal_traps(body_node, uses_traps, diagnostics);
+            }
+
+            return;
+        }
+        Node::Send(send_node) => match send_node.message.as_mut() {
+            Node::Call(call_node) if call_node.fn_name == "Signal.trap" => call_node,
+            _ => return,
+        },
+        _ => return,
+    };
+
+    if message.args.len() != 2 || typed_node_base_type(&message.args[1]) != Some(BaseType::FnRef) {
+        diagnostics.error(
+            "`Signal.trap` takes a signal name and a handler, like `handler.fn_ref()`".to_string(),
+        );
+        return;
+    }
+
+    match &message.args[0] {
+        Node::StringLiteral(name) if !TRAPPABLE_SIGNALS.contains(&name.value.as_str()) => {
+            diagnostics.error(format!(
+                "Can't trap `{}`, expected one of {}",
+                name.value,
+                TRAPPABLE_SIGNALS.join(", ")
+            ));
+            return;
+        }
+        _ => {}
+    }
+
+    *uses_traps = true;
+    *node = Node::Call(parser::Call {
+        fn_name: "pj_signal_trap".to_string(),
+        args: message.args.drain(..).collect(),
+        return_type: None,
+        arg_names: vec![],
+    });
+}
+
+/// Exit hooks
+///
+/// `at_exit(flush.fn_ref())`, or an `at_exit do ... end` block, registers a
+/// function to run once the program ends, after `main` returns or a runtime
+/// error stops it. Hooks run in the reverse of the order they were
+/// registered, the way `atexit` runs them, so one registered later can still
+/// use what an earlier one cleans up. It's lowered to `pj_at_exit`.
+fn apply_exit_hooks(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut uses_hooks = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_exit_hooks(body_node, &mut uses_hooks, diagnostics);
+            }
+        }
+    }
+
+    if !uses_hooks {
+        return;
+    }
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![("pj_at_exit", vec![("handler", BaseType::FnRef)], None)],
+    );
+}
+
+fn rewrite_exit_hooks(node: &mut Node, uses_hooks: &mut bool, diagnostics: &mut Diagnostics) {
+    let call_node = match node {
+        Node::Loop(loop_node) => {
+            for body_node in loop_node.body.iter_mut() {
+                rewrite_exit_hooks(body_node, uses_hooks, diagnostics);
+            }
+
+            return;
+        }
+        Node::If(if_node) => {
+            for body_node in if_node
+                .then_body
+                .iter_mut()
+                .chain(if_node.else_body.iter_mut())
+            {
+                rewrite_exit_hooks(body_node, uses_hooks, diagnostics);
+            }
+
+            return;
+        }
+        Node::While(while_node) => {
+            for body_node in while_node.body.iter_mut() {
+                rewrite_exit_hooks(body_node, uses_hooks, diagnostics);
+            }
+
+            return;
+        }
+        Node::Call(call_node) if call_node.fn_name == "at_exit" => call_node,
+        _ => return,
+    };
+
+    if call_node.args.len() != 1
+        || typed_node_base_type(&call_node.args[0]) != Some(BaseType::FnRef)
+    {
+        diagnostics.error(
+            "`at_exit` takes a handler, like `handler.fn_ref()`, or a `do ... end` block"
+                .to_string(),
+        );
+        return;
+    }
+
+    *uses_hooks = true;
+    call_node.fn_name = "pj_at_exit".to_string();
+}
+
+/// Arithmetic with explicit overflow behaviour, and what each lowers to
+const OVERFLOW_VARIANTS: [(&str, &str); 12] = [
+    ("wrapping_add", "+"),
+    ("wrapping_sub", "-"),
+    ("wrapping_mul", "*"),
+    ("saturating_add", "llvm.sadd.sat.i64"),
+    ("saturating_sub", "llvm.ssub.sat.i64"),
+    ("saturating_mul", "pj_saturating_mul"),
+    ("checked_add", "pj_checked_add"),
+    ("checked_sub", "pj_checked_sub"),
+    ("checked_mul", "pj_checked_mul"),
+    ("add_overflows", "pj_add_overflows"),
+    ("sub_overflows", "pj_sub_overflows"),
+    ("mul_overflows", "pj_mul_overflows"),
+];
+
+/// What an overflow variant returns: the flag for `*_overflows`, the result
+/// for the others.
+fn overflow_variant_type(variant: &str) -> BaseType {
+    if variant.ends_with("_overflows") {
+        BaseType::Bool
+    } else {
+        BaseType::Int
+    }
+}
+
+/// The runtime functions `--strict` lowers Int operators to
+const CHECKED_OPERATORS: [(&str, &str); 3] = [
+    ("+", "pj_checked_add"),
+    ("-", "pj_checked_sub"),
+    ("*", "pj_checked_mul"),
+];
+
+/// Overflow arithmetic
+///
+/// `a.wrapping_add(b)`, `a.saturating_add(b)` and `a.checked_add(b)`, and the
+/// `_sub` and `_mul` variants, pick how an Int operation overflows:
+///
+/// * `wrapping_*` wraps around, which is what the LLVM instructions do
+/// * `saturating_*` clamps to the smallest or largest Int. Addition and
+///   subtraction call the LLVM intrinsics directly
+/// * `checked_*` stops the program, like Int operators do with `--strict`
+///
+/// `a.add_overflows(b)`, `sub_overflows` and `mul_overflows` tell whether the
+/// operation would overflow, so a program can check before using the result
+/// of `wrapping_*` rather than stop. Optionals only hold instances, so there
+/// is no checked variant returning nil instead. codegen lowers them to the
+/// `llvm.s*.with.overflow` intrinsics, see `OVERFLOW_INTRINSICS`.
+///
+/// Operations without an intrinsic are runtime functions.
+fn apply_overflow_arithmetic(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+) {
+    let mut uses_builtins = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_overflow_calls(body_node, &mut uses_builtins);
+            }
+        }
+    }
+
+    if !uses_builtins {
+        return;
+    }
+
+    let int_args = || vec![("left", BaseType::Int), ("right", BaseType::Int)];
+
+    declare_runtime_fns(
+        module,
+        index,
+        OVERFLOW_VARIANTS
+            .iter()
+            .filter(|(_, lowered)| lowered.starts_with(char::is_alphabetic))
+            .map(|(variant, lowered)| (*lowered, int_args(), Some(overflow_variant_type(variant))))
+            .collect(),
+    );
+}
+
+/// Strict mode
+///
+/// `--strict` is for programs that would rather stop than go on with a wrong
+/// value. Int `+`, `-` and `*` are checked, so an overflow stops the program
+/// instead of wrapping around.
+///
+/// The other checks a strict mode would turn on are always on: reading a
+/// local that might not be assigned is an error, a def can only return a
+/// value its return type names, and every diagnostic is an error.
+///
+/// Runs after analysis, once operands are typed.
+pub fn apply_strict(result: &mut ParserResult) {
+    let module = match &mut result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    let mut checked = CheckedArithmetic { used: false };
+
+    for node in module.methods.iter_mut() {
+        checked.visit_node_mut(node);
+    }
+
+    if !checked.used {
+        return;
+    }
+
+    let int_args = || vec![("left", BaseType::Int), ("right", BaseType::Int)];
+
+    declare_runtime_fns(
+        module,
+        &mut result.index,
+        CHECKED_OPERATORS
+            .iter()
+            .map(|(_, fn_name)| (*fn_name, int_args(), Some(BaseType::Int)))
+            .collect(),
+    );
+}
+
+/// Replaces `left + right`, `-` and `*` on two Ints with calls to the
+/// runtime's checked arithmetic.
+struct CheckedArithmetic {
+    used: bool,
+}
+
+impl VisitorMut for CheckedArithmetic {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+
+        let binary = match node {
+            Node::Binary(binary)
+                if typed_node_base_type(&binary.left) == Some(BaseType::Int)
+                    && typed_node_base_type(&binary.right) == Some(BaseType::Int) =>
+            {
+                binary
+            }
+            _ => return,
+        };
+
+        let fn_name = match CHECKED_OPERATORS.iter().find(|(op, _)| binary.op == *op) {
+            Some((_, fn_name)) => *fn_name,
+            None => return,
+        };
+
+        let left = std::mem::replace(binary.left.as_mut(), Node::Int(parser::Int { value: 0 }));
+        let right = std::mem::replace(binary.right.as_mut(), Node::Int(parser::Int { value: 0 }));
+
+        self.used = true;
+        *node = Node::Call(parser::Call {
+            fn_name: fn_name.to_string(),
+            args: vec![left, right],
+            return_type: Some(BaseType::Int),
+            arg_names: vec![],
+        });
+    }
+}
+
+fn rewrite_overflow_calls(node: &mut Node, uses_builtins: &mut bool) {
+    match node {
+        Node::AssignLocalVar(node) => rewrite_overflow_calls(node.value.as_mut(), uses_builtins),
+        Node::AssignAttributeAccess(node) => {
+            rewrite_overflow_calls(node.value.as_mut(), uses_builtins)
+        }
+        Node::Ret(node) => rewrite_overflow_calls(node.value.as_mut(), uses_builtins),
+        Node::Binary(node) => {
+            rewrite_overflow_calls(node.left.as_mut(), uses_builtins);
+            rewrite_overflow_calls(node.right.as_mut(), uses_builtins);
+        }
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite_overflow_calls(body_node, uses_builtins);
+            }
+        }
+        Node::While(node) => {
+            rewrite_overflow_calls(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.body.iter_mut() {
+                rewrite_overflow_calls(body_node, uses_builtins);
+            }
+        }
+        Node::If(node) => {
+            rewrite_overflow_calls(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite_overflow_calls(body_node, uses_builtins);
+            }
+        }
+        Node::Call(node) => {
+            for arg in node.args.iter_mut() {
+                rewrite_overflow_calls(arg, uses_builtins);
+            }
+        }
+        Node::Send(send_node) => {
+            rewrite_overflow_calls(send_node.receiver.as_mut(), uses_builtins);
+            rewrite_overflow_calls(send_node.message.as_mut(), uses_builtins);
+
+            let message = match send_node.message.as_mut() {
+                Node::Call(call_node) if call_node.args.len() == 1 => call_node,
+                _ => return,
+            };
+
+            let (variant, lowered) = match OVERFLOW_VARIANTS
+                .iter()
+                .find(|(variant, _)| message.fn_name == format!("Int.{}", variant))
+            {
+                Some((variant, lowered)) => (*variant, *lowered),
+                None => return,
+            };
+
+            let left = std::mem::replace(
+                send_node.receiver.as_mut(),
+                Node::Int(parser::Int { value: 0 }),
+            );
+            let right = message.args.remove(0);
+
+            *uses_builtins = true;
+            *node = if !lowered.starts_with(char::is_alphabetic) {
+                Node::Binary(parser::Binary {
+                    op: lowered.to_string(),
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    return_type: Some(BaseType::Int),
+                })
+            } else {
+                Node::Call(parser::Call {
+                    fn_name: lowered.to_string(),
+                    args: vec![left, right],
+                    return_type: Some(overflow_variant_type(variant)),
+                    arg_names: vec![],
+                })
+            };
+        }
+        _ => {}
+    }
+}
+
+/// The `Comparable` trait
+///
+/// A class implements `Comparable` by defining `<=>`, which takes another
+/// instance of the class and returns a negative Int, zero or a positive Int.
+/// Arrays of comparable instances can then be passed to the builtins:
+///
+/// * `sort(items)` sorts the array in place
+/// * `min(items)` and `max(items)` return the smallest and largest item, and
+///   stop the program when the array is empty
+/// * `binary_search(items, item)` and `index_of(items, item)` return the index
+///   of an item comparing equal to `item`, or -1. `binary_search` expects the
+///   array to be sorted.
+///
+/// They are lowered to runtime functions that call back into the compiled
+/// `<=>` through a function pointer. `sort_by(items, key.fn_ref())` works the
+/// same way for any class, sorting by the Int that `key` returns for an item.
+///
+/// `sort`, `min` and `max` can be sent to the array too, as in
+/// `items.sort()`, and are lowered the same way.
+fn apply_comparable_protocol(
+    module: &mut crate::parser::Module,
+    index: &mut parser::ParserResultIndex,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut comparable_classes: Vec<String> = match index.trait_index.get("Comparable") {
+        Some(classes) => classes.iter().map(|class| class.name.clone()).collect(),
+        None => vec![],
+    };
+
+    comparable_classes.sort();
+    comparable_classes.dedup();
+
+    for class_name in &comparable_classes {
+        match index.resolve_method(class_name, "<=>") {
+            Ok(Some(fn_name)) => {
+                let prototype = &index.fn_prototype_index[&fn_name];
+                let takes_same_class = prototype.args.len() == 2
+                    && prototype.args[1].return_type == BaseType::Class(class_name.clone());
+
+                if !takes_same_class || prototype.return_type != Some(BaseType::Int) {
+                    diagnostics.error(format!(
+                        "`{}` must take one {} and return Int",
+                        fn_name, class_name
+                    ));
+                }
+            }
+            Ok(None) => diagnostics.error(format!(
+                "`{}` implements Comparable but does not define <=>",
+                class_name
+            )),
+            Err(error) => diagnostics.error(error),
+        }
+    }
+
+    let mut uses_builtins = false;
+
+    for node in module.methods.iter_mut() {
+        if let Node::Def(def_node) = node {
+            for body_node in def_node.body.iter_mut() {
+                rewrite_comparable_calls(
+                    body_node,
+                    index,
+                    &comparable_classes,
+                    &mut uses_builtins,
+                    diagnostics,
+                );
+            }
+        }
+    }
+
+    if !uses_builtins {
+        return;
+    }
+
+    let args = |extra: Vec<(&'static str, BaseType)>| {
+        let mut args = vec![("items", BaseType::BytePtr)];
+        args.extend(extra);
+        args
+    };
+    let compare = || ("compare", BaseType::FnRef);
+    let item = || ("item", BaseType::BytePtr);
+
+    declare_runtime_fns(
+        module,
+        index,
+        vec![
+            ("pj_sort", args(vec![compare()]), None),
+            ("pj_min", args(vec![compare()]), Some(BaseType::BytePtr)),
+            ("pj_max", args(vec![compare()]), Some(BaseType::BytePtr)),
+            ("pj_sort_by", args(vec![("key", BaseType::FnRef)]), None),
+            (
+                "pj_binary_search",
+                args(vec![item(), compare()]),
+                Some(BaseType::Int),
+            ),
+            (
+                "pj_index_of",
+                args(vec![item(), compare()]),
+                Some(BaseType::Int),
+            ),
+        ],
+    );
+}
+
+fn rewrite_comparable_calls(
+    node: &mut Node,
+    index: &parser::ParserResultIndex,
+    comparable_classes: &[String],
+    uses_builtins: &mut bool,
+    diagnostics: &mut Diagnostics,
+) {
+    if let Node::Send(send_node) = node {
+        if let Some(call_node) = comparable_array_call(send_node) {
+            *node = call_node;
+        }
+    }
+
+    let mut rewrite = |node: &mut Node, uses_builtins: &mut bool| {
+        rewrite_comparable_calls(node, index, comparable_classes, uses_builtins, diagnostics)
+    };
+
+    match node {
+        Node::AssignLocalVar(node) => rewrite(node.value.as_mut(), uses_builtins),
+        Node::Ret(node) => rewrite(node.value.as_mut(), uses_builtins),
+        Node::Loop(node) => {
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::While(node) => {
+            rewrite(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.body.iter_mut() {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::If(node) => {
+            rewrite(node.condition.as_mut(), uses_builtins);
+
+            for body_node in node.then_body.iter_mut().chain(node.else_body.iter_mut()) {
+                rewrite(body_node, uses_builtins);
+            }
+        }
+        Node::Send(node) => {
+            rewrite(node.receiver.as_mut(), uses_builtins);
+            rewrite(node.message.as_mut(), uses_builtins);
+        }
+        Node::Call(call_node) => {
+            for arg in call_node.args.iter_mut() {
+                rewrite(arg, uses_builtins);
+            }
 
-impl SemanticAnalyzer {
-    pub fn run(result: &mut ParserResult) -> SemanticAnalyzer {
-        Self::transform_ast(result)
-    }
+            let (runtime_fn_name, arity) = match call_node.fn_name.as_str() {
+                "sort" => ("pj_sort", 1),
+                "min" => ("pj_min", 1),
+                "max" => ("pj_max", 1),
+                "sort_by" => ("pj_sort_by", 2),
+                "binary_search" => ("pj_binary_search", 2),
+                "index_of" => ("pj_index_of", 2),
+                _ => return,
+            };
 
-    pub fn transform_ast(result: &mut ParserResult) -> SemanticAnalyzer {
-        let mut attribute_index = HashMap::new();
-        let mut method_index = HashMap::new();
+            // User defined functions take precedence over the builtins
+            if index.fn_prototype_index.contains_key(&call_node.fn_name) {
+                return;
+            }
 
-        match &mut result.module {
-            Node::Module(module) => {
-                populate_class_index(&result.index.class_index, &mut attribute_index);
-                populate_method_index(module, &mut method_index);
-                run_type_inference(
-                    module,
-                    method_index,
-                    attribute_index,
-                    &result.index.struct_index,
-                );
+            let class_name = match call_node.args.first().and_then(typed_node_base_type) {
+                Some(BaseType::Array(item_type)) if call_node.args.len() == arity => {
+                    match *item_type {
+                        BaseType::Class(class_name) => class_name,
+                        _ => {
+                            diagnostics.error(format!(
+                                "`{}` expects an array of class instances",
+                                call_node.fn_name
+                            ));
+                            return;
+                        }
+                    }
+                }
+                _ => {
+                    diagnostics.error(format!(
+                        "`{}` expects an array followed by {} more argument(s)",
+                        call_node.fn_name,
+                        arity - 1
+                    ));
+                    return;
+                }
+            };
+
+            if call_node.fn_name == "sort_by" {
+                let key_fn_name = match sort_key_fn_name(&call_node.args[1], &class_name, index) {
+                    Some(fn_name) => fn_name,
+                    None => {
+                        diagnostics.error(format!(
+                            "`sort_by` expects a function reference taking a {} and returning Int",
+                            class_name
+                        ));
+                        return;
+                    }
+                };
+
+                *uses_builtins = true;
+
+                call_node.fn_name = runtime_fn_name.to_string();
+                call_node.args[1] = Node::FnRef(parser::FnRef {
+                    fn_name: key_fn_name,
+                });
+                return;
             }
-            _ => todo!(),
-        }
 
-        SemanticAnalyzer {
-            diagnostics: Diagnostics {},
+            let compare_fn_name = match index.resolve_method(&class_name, "<=>") {
+                Ok(Some(fn_name)) if comparable_classes.contains(&class_name) => fn_name,
+                _ => {
+                    diagnostics.error(format!(
+                        "`{}` requires `{}` to implement Comparable",
+                        call_node.fn_name, class_name
+                    ));
+                    return;
+                }
+            };
+
+            *uses_builtins = true;
+
+            call_node.fn_name = runtime_fn_name.to_string();
+            call_node.args.push(Node::FnRef(parser::FnRef {
+                fn_name: compare_fn_name,
+            }));
         }
+        _ => {}
     }
 }
 
-fn populate_class_index(
-    class_index: &HashMap<String, parser::Class>,
-    attribute_index: &mut HashMap<String, (i32, BaseType)>,
-) {
-    class_index.values().for_each(|class| {
-        for attribute in &class.attributes {
-            attribute_index.insert(
-                format!("{}.{}", class.name, attribute.name),
-                (attribute.index, attribute.return_type.clone()),
-            );
-        }
-    });
+/// `items.sort()`, `items.min()` and `items.max()` as the builtin call taking
+/// the array, like `sort(items)`.
+fn comparable_array_call(send_node: &mut parser::Send) -> Option<Node> {
+    let fn_name = match send_node.message.as_ref() {
+        Node::Call(call_node) => match call_node.fn_name.as_str() {
+            "Array.sort" => "sort",
+            "Array.min" => "min",
+            "Array.max" => "max",
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let mut args = vec![std::mem::replace(
+        send_node.receiver.as_mut(),
+        Node::Int(parser::Int { value: 0 }),
+    )];
+
+    if let Node::Call(call_node) = send_node.message.as_mut() {
+        args.append(&mut call_node.args);
+    }
+
+    Some(Node::Call(parser::Call {
+        fn_name: fn_name.to_string(),
+        args,
+        return_type: send_node.return_type.clone(),
+        arg_names: vec![],
+    }))
 }
 
-fn populate_method_index(
-    module: &mut crate::parser::Module,
-    method_index: &mut HashMap<String, Option<BaseType>>,
-) {
-    module.methods.iter_mut().for_each(|node| match node {
-        Node::Def(def_node) => {
-            method_index.insert(
-                def_node.prototype.name.clone(),
-                def_node.prototype.return_type.clone(),
-            );
-        }
-        Node::DefE(def_e_node) => {
-            method_index.insert(
-                def_e_node.prototype.name.clone(),
-                def_e_node.prototype.return_type.clone(),
-            );
-        }
-        _ => {}
-    });
+/// The function named by `name.fn_ref()`, when it maps an instance of the
+/// class to an Int sort key.
+fn sort_key_fn_name(
+    node: &Node,
+    class_name: &str,
+    index: &parser::ParserResultIndex,
+) -> Option<String> {
+    let fn_name = referenced_fn_name(node)?;
+    let prototype = index.fn_prototype_index.get(&fn_name)?;
+    let takes_item = prototype.args.len() == 1
+        && prototype.args[0].return_type == BaseType::Class(class_name.to_string());
+
+    if takes_item && prototype.return_type == Some(BaseType::Int) {
+        Some(fn_name)
+    } else {
+        None
+    }
+}
+
+/// The function `node` refers to, given as `name.fn_ref()`.
+fn referenced_fn_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Send(send_node) => match send_node.receiver.as_ref() {
+            Node::LocalVar(lvar) if lvar.return_type == Some(BaseType::FnRef) => {
+                Some(lvar.name.clone())
+            }
+            _ => None,
+        },
+        Node::FnRef(fn_ref) => Some(fn_ref.fn_name.clone()),
+        _ => None,
+    }
 }
 
 fn run_type_inference(
@@ -80,6 +4506,7 @@ fn run_type_inference(
     mut method_index: HashMap<String, Option<BaseType>>,
     mut attribute_index: HashMap<String, (i32, BaseType)>,
     struct_index: &HashMap<String, parser::Struct>,
+    diagnostics: &mut Diagnostics,
 ) {
     module.methods.iter_mut().for_each(|node| {
         match node {
@@ -90,365 +4517,15 @@ fn run_type_inference(
                     lvar_index.insert(arg.name.clone(), Some(arg.return_type.clone()));
                 });
 
-                def_node.body.iter_mut().for_each(|node| match node {
-                    Node::Access(access_node) => {
-                        visit_access_node(&attribute_index, &lvar_index, access_node);
-                    }
-                    Node::AssignLocalVar(assignlocalvar_node) => {
-                        let return_type = match assignlocalvar_node.value.as_mut() {
-                            Node::Binary(binary_node) => visit_binary_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                binary_node,
-                            ),
-                            Node::Call(call_node) => visit_call_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                call_node,
-                            ),
-                            Node::Send(send_node) => visit_send_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                send_node,
-                            ),
-                            Node::Access(access_node) => {
-                                visit_access_node(&attribute_index, &lvar_index, access_node)
-                            }
-                            Node::AssignAttribute(_) => todo!(),
-                            Node::AssignAttributeAccess(_) => todo!(),
-                            Node::AssignLocalVar(_) => todo!(),
-                            Node::Attribute(_) => todo!(),
-                            Node::Class(_) => todo!(),
-                            Node::Const(_) => todo!(),
-                            Node::Def(_) => todo!(),
-                            Node::DefE(_) => todo!(),
-                            Node::Impl(_) => todo!(),
-                            Node::Int(_) => Some(BaseType::Int),
-                            Node::LocalVar(_) => todo!(),
-                            Node::Loop(_) => todo!(),
-                            Node::Module(_) => todo!(),
-                            Node::Ret(_) => todo!(),
-                            Node::SelfRef(_) => todo!(),
-                            Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
-                            Node::Trait(_) => todo!(),
-                            Node::AssignConstant(_) => todo!(),
-                            Node::Array(array) => {
-                                array.items.iter_mut().for_each(|node| {
-                                    match node {
-                                        Node::Access(access_node) => visit_access_node(
-                                            &attribute_index,
-                                            &lvar_index,
-                                            access_node,
-                                        ),
-                                        Node::Binary(node) => visit_binary_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Call(node) => visit_call_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Send(node) => visit_send_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Int(node) => Some(BaseType::Int),
-                                        _ => todo!(),
-                                    };
-                                });
-
-                                Some(BaseType::Array(
-                                    array.length,
-                                    Box::new(array.item_type.clone()),
-                                ))
-                            }
-                            Node::BuildStruct(struct_node) => visit_build_struct_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                struct_node,
-                                struct_index,
-                            ),
-                            Node::Struct(_) => todo!(),
-                            Node::FnRef(_) => todo!(),
-                        };
-
-                        lvar_index.insert(assignlocalvar_node.name.clone(), return_type);
-                    }
-                    Node::Binary(binary_node) => {
-                        visit_binary_node(
-                            &attribute_index,
-                            &method_index,
-                            &lvar_index,
-                            binary_node,
-                        );
-                    }
-                    Node::Call(call_node) => {
-                        visit_call_node(&attribute_index, &method_index, &lvar_index, call_node);
-                    }
-                    Node::Send(send_node) => {
-                        visit_send_node(&attribute_index, &method_index, &lvar_index, send_node);
-                    }
-                    Node::Ret(ret_node) => {
-                        visit_ret_node(&attribute_index, &method_index, &lvar_index, ret_node);
-                    }
-                    Node::AssignConstant(_) => todo!(),
-                    Node::Attribute(_) => todo!(),
-                    Node::Class(_) => todo!(),
-                    Node::Def(_) => todo!(),
-                    Node::DefE(_) => todo!(),
-                    Node::Impl(_) => todo!(),
-                    Node::Int(_) => todo!(),
-                    Node::StringLiteral(_) => todo!(),
-                    Node::LocalVar(node) => {
-                        match node.return_type {
-                            Some(_) => {}
-                            None => todo!(),
-                        }
-                        // println!("{:#?}", node);
-                    }
-                    Node::Module(_) => todo!(),
-                    Node::SelfRef(_) => todo!(),
-                    Node::Trait(_) => todo!(),
-                    Node::AssignAttribute(assign_attr_node) => {
-                        match assign_attr_node.value.as_mut() {
-                            Node::Binary(binary_node) => visit_binary_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                binary_node,
-                            ),
-                            Node::Call(call_node) => visit_call_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                call_node,
-                            ),
-                            Node::Send(send_node) => visit_send_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                send_node,
-                            ),
-                            Node::Access(access_node) => {
-                                visit_access_node(&attribute_index, &lvar_index, access_node)
-                            }
-                            Node::AssignAttribute(_) => todo!(),
-                            Node::AssignAttributeAccess(_) => todo!(),
-                            Node::AssignLocalVar(_) => todo!(),
-                            Node::Attribute(_) => todo!(),
-                            Node::Class(_) => todo!(),
-                            Node::Const(_) => todo!(),
-                            Node::Def(_) => todo!(),
-                            Node::DefE(_) => todo!(),
-                            Node::Impl(_) => todo!(),
-                            Node::Int(_) => todo!(),
-                            Node::LocalVar(lvar) => match lvar.return_type {
-                                Some(_) => lvar.return_type.clone(),
-                                None => todo!(),
-                            },
-                            Node::Loop(_) => todo!(),
-                            Node::Module(_) => todo!(),
-                            Node::Ret(_) => todo!(),
-                            Node::SelfRef(_) => todo!(),
-                            Node::StringLiteral(_) => todo!(),
-                            Node::Trait(_) => todo!(),
-                            Node::AssignConstant(_) => todo!(),
-                            Node::Array(array) => {
-                                array.items.iter_mut().for_each(|node| {
-                                    match node {
-                                        Node::Access(access_node) => visit_access_node(
-                                            &attribute_index,
-                                            &lvar_index,
-                                            access_node,
-                                        ),
-                                        Node::Binary(node) => visit_binary_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Call(node) => visit_call_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Send(node) => visit_send_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        _ => todo!(),
-                                    };
-                                });
-
-                                Some(BaseType::Array(
-                                    array.length,
-                                    Box::new(array.item_type.clone()),
-                                ))
-                            }
-                            Node::BuildStruct(_) => todo!(),
-                            Node::Struct(_) => todo!(),
-                            Node::FnRef(_) => todo!(),
-                        };
-                    }
-                    Node::Const(_) => todo!(),
-                    Node::AssignAttributeAccess(node) => {
-                        visit_access_node(&attribute_index, &lvar_index, &mut node.access);
-
-                        match node.value.as_mut() {
-                            Node::Binary(binary_node) => visit_binary_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                binary_node,
-                            ),
-                            Node::Call(call_node) => visit_call_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                call_node,
-                            ),
-                            Node::Send(send_node) => visit_send_node(
-                                &attribute_index,
-                                &method_index,
-                                &lvar_index,
-                                send_node,
-                            ),
-                            Node::Access(access_node) => {
-                                visit_access_node(&attribute_index, &lvar_index, access_node)
-                            }
-                            Node::AssignAttribute(_) => todo!(),
-                            Node::AssignAttributeAccess(_) => todo!(),
-                            Node::AssignLocalVar(_) => todo!(),
-                            Node::Attribute(_) => todo!(),
-                            Node::Class(_) => todo!(),
-                            Node::Const(_) => todo!(),
-                            Node::Def(_) => todo!(),
-                            Node::DefE(_) => todo!(),
-                            Node::Impl(_) => todo!(),
-                            Node::Int(_) => todo!(),
-                            Node::LocalVar(lvar) => match lvar.return_type {
-                                Some(_) => lvar.return_type.clone(),
-                                None => todo!(),
-                            },
-                            Node::Loop(_) => todo!(),
-                            Node::Module(_) => todo!(),
-                            Node::Ret(_) => todo!(),
-                            Node::SelfRef(_) => todo!(),
-                            Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
-                            Node::Trait(_) => todo!(),
-                            Node::AssignConstant(_) => todo!(),
-                            Node::Array(array) => {
-                                array.items.iter_mut().for_each(|node| {
-                                    match node {
-                                        Node::Access(access_node) => visit_access_node(
-                                            &attribute_index,
-                                            &lvar_index,
-                                            access_node,
-                                        ),
-                                        Node::Binary(node) => visit_binary_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Call(node) => visit_call_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        Node::Send(node) => visit_send_node(
-                                            &attribute_index,
-                                            &method_index,
-                                            &lvar_index,
-                                            node,
-                                        ),
-                                        _ => todo!(),
-                                    };
-                                });
-
-                                Some(BaseType::Array(
-                                    array.length,
-                                    Box::new(array.item_type.clone()),
-                                ))
-                            }
-                            Node::BuildStruct(_) => todo!(),
-                            Node::Struct(_) => todo!(),
-                            Node::FnRef(_) => todo!(),
-                        };
-                    }
-                    Node::Loop(loop_node) => {
-                        loop_node.body.iter_mut().for_each(|node| {
-                            match node {
-                                Node::Access(access_node) => {
-                                    visit_access_node(&attribute_index, &lvar_index, access_node)
-                                }
-                                Node::Binary(node) => visit_binary_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                Node::Call(node) => visit_call_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                Node::Send(node) => visit_send_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                _ => todo!(),
-                            };
-                        });
-                    }
-                    Node::Array(array) => {
-                        array.items.iter_mut().for_each(|node| {
-                            match node {
-                                Node::Access(access_node) => {
-                                    visit_access_node(&attribute_index, &lvar_index, access_node)
-                                }
-                                Node::Binary(node) => visit_binary_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                Node::Call(node) => visit_call_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                Node::Send(node) => visit_send_node(
-                                    &attribute_index,
-                                    &method_index,
-                                    &lvar_index,
-                                    node,
-                                ),
-                                _ => todo!(),
-                            };
-                        });
-                    }
-                    Node::BuildStruct(_) => todo!(),
-                    Node::Struct(_) => todo!(),
-                    Node::FnRef(_) => todo!(),
+                def_node.body.iter_mut().for_each(|node| {
+                    visit_statement_node(
+                        &attribute_index,
+                        &method_index,
+                        &mut lvar_index,
+                        struct_index,
+                        diagnostics,
+                        node,
+                    )
                 })
             }
             _ => {}
@@ -456,6 +4533,306 @@ fn run_type_inference(
     });
 }
 
+/// Types a statement of a def body, or of a body nested in one. Assignments
+/// add to `lvar_index`, nested bodies share the def's locals.
+fn visit_statement_node(
+    attribute_index: &HashMap<String, (i32, BaseType)>,
+    method_index: &HashMap<String, Option<BaseType>>,
+    lvar_index: &mut HashMap<String, Option<BaseType>>,
+    struct_index: &HashMap<String, parser::Struct>,
+    diagnostics: &mut Diagnostics,
+    node: &mut Node,
+) {
+    match node {
+        Node::Access(access_node) => {
+            visit_access_node(attribute_index, lvar_index, access_node);
+        }
+        Node::AssignLocalVar(assignlocalvar_node) => {
+            let return_type = visit_value_node(
+                attribute_index,
+                method_index,
+                lvar_index,
+                struct_index,
+                diagnostics,
+                assignlocalvar_node.value.as_mut(),
+            );
+
+            if let Node::If(if_node) = assignlocalvar_node.value.as_ref() {
+                if if_node.else_body.is_empty() {
+                    diagnostics.error(format!(
+                        "The `if` assigned to `{}` has no value without an `else`",
+                        assignlocalvar_node.name
+                    ));
+                } else if return_type.is_none() {
+                    diagnostics.error(format!(
+                        "The `if` assigned to `{}` has branches ending in different types",
+                        assignlocalvar_node.name
+                    ));
+                }
+            }
+
+            lvar_index.insert(assignlocalvar_node.name.clone(), return_type);
+        }
+        Node::Binary(binary_node) => {
+            visit_binary_node(attribute_index, method_index, lvar_index, binary_node);
+        }
+        Node::Call(call_node) => {
+            visit_call_node(attribute_index, method_index, lvar_index, call_node);
+        }
+        Node::Send(send_node) => {
+            visit_send_node(attribute_index, method_index, lvar_index, send_node);
+        }
+        Node::Ret(ret_node) => {
+            visit_ret_node(attribute_index, method_index, lvar_index, ret_node);
+        }
+        Node::AssignConstant(_) => todo!(),
+        Node::Attribute(_) => todo!(),
+        Node::Class(_) => todo!(),
+        Node::Def(_) => todo!(),
+        Node::DefE(_) => todo!(),
+        Node::Error(_) => todo!(),
+        Node::Impl(_) => todo!(),
+        // The value of an `if` branch
+        Node::Bool(_) => {}
+        Node::Float(_) => {}
+        Node::Int(_) => {}
+        Node::StringLiteral(_) => {}
+        Node::Nil(_) => {}
+        Node::LocalVar(node) => {
+            if let Some(latest_return_type) = lvar_index.get(&node.name) {
+                node.return_type = latest_return_type.clone();
+            }
+        }
+        Node::Module(_) => todo!(),
+        Node::SelfRef(_) => todo!(),
+        Node::Trait(_) => todo!(),
+        Node::AssignAttribute(assign_attr_node) => {
+            visit_value_node(
+                attribute_index,
+                method_index,
+                lvar_index,
+                struct_index,
+                diagnostics,
+                assign_attr_node.value.as_mut(),
+            );
+        }
+        Node::Const(_) => todo!(),
+        Node::AssignAttributeAccess(node) => {
+            visit_access_node(attribute_index, lvar_index, &mut node.access);
+            visit_value_node(
+                attribute_index,
+                method_index,
+                lvar_index,
+                struct_index,
+                diagnostics,
+                node.value.as_mut(),
+            );
+        }
+        Node::Loop(loop_node) => {
+            for node in loop_node.body.iter_mut() {
+                visit_statement_node(
+                    attribute_index,
+                    method_index,
+                    lvar_index,
+                    struct_index,
+                    diagnostics,
+                    node,
+                );
+            }
+        }
+        Node::While(while_node) => {
+            visit_condition_node(
+                attribute_index,
+                method_index,
+                lvar_index,
+                struct_index,
+                diagnostics,
+                while_node.condition.as_mut(),
+            );
+
+            for node in while_node.body.iter_mut() {
+                visit_statement_node(
+                    attribute_index,
+                    method_index,
+                    lvar_index,
+                    struct_index,
+                    diagnostics,
+                    node,
+                );
+            }
+        }
+        Node::Break => {}
+        Node::Next => {}
+        Node::Array(array) => {
+            visit_array_node(attribute_index, method_index, lvar_index, array);
+        }
+        Node::BuildStruct(_) => todo!(),
+        Node::Struct(_) => todo!(),
+        Node::FnRef(_) => todo!(),
+        Node::If(if_node) => {
+            visit_if_node(
+                attribute_index,
+                method_index,
+                lvar_index,
+                struct_index,
+                diagnostics,
+                if_node,
+            );
+        }
+    }
+}
+
+/// Types an expression whose value is used, like the value of an assignment.
+/// Nodes that aren't expressions have no value.
+fn visit_value_node(
+    attribute_index: &HashMap<String, (i32, BaseType)>,
+    method_index: &HashMap<String, Option<BaseType>>,
+    lvar_index: &mut HashMap<String, Option<BaseType>>,
+    struct_index: &HashMap<String, parser::Struct>,
+    diagnostics: &mut Diagnostics,
+    node: &mut Node,
+) -> Option<BaseType> {
+    match node {
+        Node::Access(access_node) => visit_access_node(attribute_index, lvar_index, access_node),
+        Node::Binary(node) => visit_binary_node(attribute_index, method_index, lvar_index, node),
+        Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
+        Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
+        Node::LocalVar(lvar) => {
+            lvar.return_type = lvar_index.get(&lvar.name).cloned().flatten();
+            lvar.return_type.clone()
+        }
+        Node::Bool(_) => Some(BaseType::Bool),
+        Node::Float(_) => Some(BaseType::Float),
+        Node::Int(_) => Some(BaseType::Int),
+        Node::Nil(_) => Some(BaseType::Nil),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+        Node::FnRef(_) => Some(BaseType::FnRef),
+        Node::Array(array) => visit_array_node(attribute_index, method_index, lvar_index, array),
+        Node::BuildStruct(struct_node) => visit_build_struct_node(
+            attribute_index,
+            method_index,
+            lvar_index,
+            struct_node,
+            struct_index,
+        ),
+        Node::If(if_node) => visit_if_node(
+            attribute_index,
+            method_index,
+            lvar_index,
+            struct_index,
+            diagnostics,
+            if_node,
+        ),
+        _ => None,
+    }
+}
+
+/// Types an `if` and both of its bodies. The `if` has a value when both
+/// branches end in an expression of the same type.
+fn visit_if_node(
+    attribute_index: &HashMap<String, (i32, BaseType)>,
+    method_index: &HashMap<String, Option<BaseType>>,
+    lvar_index: &mut HashMap<String, Option<BaseType>>,
+    struct_index: &HashMap<String, parser::Struct>,
+    diagnostics: &mut Diagnostics,
+    if_node: &mut parser::If,
+) -> Option<BaseType> {
+    visit_condition_node(
+        attribute_index,
+        method_index,
+        lvar_index,
+        struct_index,
+        diagnostics,
+        if_node.condition.as_mut(),
+    );
+
+    for node in if_node
+        .then_body
+        .iter_mut()
+        .chain(if_node.else_body.iter_mut())
+    {
+        visit_statement_node(
+            attribute_index,
+            method_index,
+            lvar_index,
+            struct_index,
+            diagnostics,
+            node,
+        );
+    }
+
+    let then_type = if_node.then_body.last().and_then(typed_node_base_type);
+    let else_type = if_node.else_body.last().and_then(typed_node_base_type);
+
+    if then_type.is_some() && then_type == else_type {
+        if_node.return_type = then_type;
+    } else if let Some(optional_type) = optional_of(then_type.as_ref(), else_type.as_ref()) {
+        if_node.return_type = Some(optional_type);
+    }
+
+    if_node.return_type.clone()
+}
+
+/// The type of an `if` with an instance in one branch and nil, or an
+/// optional of its class, in the other.
+fn optional_of(then_type: Option<&BaseType>, else_type: Option<&BaseType>) -> Option<BaseType> {
+    let (base_type, other_type) = match (then_type?, else_type?) {
+        (BaseType::Nil, base_type) | (base_type, BaseType::Nil) => (base_type, &BaseType::Nil),
+        (BaseType::Optional(base_type), other_type)
+        | (other_type, BaseType::Optional(base_type)) => (base_type.as_ref(), other_type),
+        _ => return None,
+    };
+
+    match (base_type.without_optional(), other_type.without_optional()) {
+        (base_type @ BaseType::Class(_), BaseType::Nil) => {
+            Some(BaseType::Optional(Box::new(base_type.clone())))
+        }
+        (base_type @ BaseType::Class(_), other_type) if base_type == other_type => {
+            Some(BaseType::Optional(Box::new(base_type.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// Types the condition of an `if` or `while`, which is a Bool or an integer.
+fn visit_condition_node(
+    attribute_index: &HashMap<String, (i32, BaseType)>,
+    method_index: &HashMap<String, Option<BaseType>>,
+    lvar_index: &mut HashMap<String, Option<BaseType>>,
+    struct_index: &HashMap<String, parser::Struct>,
+    diagnostics: &mut Diagnostics,
+    condition: &mut Node,
+) -> Option<BaseType> {
+    let condition_type = visit_value_node(
+        attribute_index,
+        method_index,
+        lvar_index,
+        struct_index,
+        diagnostics,
+        condition,
+    );
+
+    match &condition_type {
+        Some(
+            BaseType::Bool
+            | BaseType::Byte
+            | BaseType::Int
+            | BaseType::Int16
+            | BaseType::Int32
+            | BaseType::Int64,
+        ) => {}
+        None | Some(BaseType::Void) => {
+            diagnostics.error("A condition must be a Bool or an Int, found nothing".to_string())
+        }
+        Some(condition_type) => diagnostics.error(format!(
+            "A condition must be a Bool or an Int, found {}",
+            crate::type_checker::type_name(condition_type)
+        )),
+    }
+
+    condition_type
+}
+
 fn visit_ret_node(
     attribute_index: &HashMap<String, (i32, BaseType)>,
     method_index: &HashMap<String, Option<BaseType>>,
@@ -467,6 +4844,17 @@ fn visit_ret_node(
         Node::Binary(node) => visit_binary_node(attribute_index, method_index, lvar_index, node),
         Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
         Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
+        Node::LocalVar(lvar) => {
+            let latest_return_type = lvar_index.get(&lvar.name).unwrap();
+            lvar.return_type = latest_return_type.clone();
+            latest_return_type.clone()
+        }
+        Node::Bool(_) => Some(BaseType::Bool),
+        Node::Float(_) => Some(BaseType::Float),
+        Node::Int(_) => Some(BaseType::Int),
+        Node::Nil(_) => Some(BaseType::Nil),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+        Node::Array(array) => visit_array_node(attribute_index, method_index, lvar_index, array),
         _ => todo!(),
     };
 }
@@ -486,7 +4874,7 @@ fn visit_access_node(
             let latest_return_type = lvar_index.get(&lvar.name).unwrap();
             lvar.return_type = latest_return_type.clone();
 
-            pajama_class_name(&lvar.return_type.as_ref().unwrap())
+            pajama_class_name(lvar.return_type.as_ref().unwrap().without_optional())
         }
         Node::Access(_) => todo!(),
         Node::AssignAttribute(_) => todo!(),
@@ -499,7 +4887,10 @@ fn visit_access_node(
         Node::Const(_) => todo!(),
         Node::Def(_) => todo!(),
         Node::DefE(_) => todo!(),
+        Node::Error(_) => todo!(),
         Node::Impl(_) => todo!(),
+        Node::Bool(_) => todo!(),
+        Node::Float(_) => todo!(),
         Node::Int(_) => todo!(),
         Node::Loop(_) => todo!(),
         Node::Module(_) => todo!(),
@@ -513,6 +4904,11 @@ fn visit_access_node(
         Node::BuildStruct(_) => todo!(),
         Node::Struct(_) => todo!(),
         Node::FnRef(_) => todo!(),
+        Node::If(_) => todo!(),
+        Node::While(_) => todo!(),
+        Node::Break => todo!(),
+        Node::Next => todo!(),
+        Node::Nil(_) => todo!(),
     };
 
     let attribute_name = match access_node.message.as_mut() {
@@ -535,21 +4931,41 @@ fn visit_binary_node(
     lvar_index: &HashMap<String, Option<BaseType>>,
     binary_node: &mut crate::parser::Binary,
 ) -> Option<BaseType> {
-    match binary_node.left.as_mut() {
+    let visit_operand = |node: &mut Node| match node {
         Node::Access(access_node) => visit_access_node(attribute_index, lvar_index, access_node),
         Node::Binary(node) => visit_binary_node(attribute_index, method_index, lvar_index, node),
         Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
         Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
+        Node::LocalVar(lvar) => {
+            let latest_return_type = lvar_index.get(&lvar.name).unwrap();
+            lvar.return_type = latest_return_type.clone();
+            latest_return_type.clone()
+        }
+        Node::Bool(_) => Some(BaseType::Bool),
+        Node::Float(_) => Some(BaseType::Float),
+        Node::Int(_) => None,
+        Node::Nil(_) => Some(BaseType::Nil),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
         _ => todo!(),
     };
 
-    match binary_node.right.as_mut() {
-        Node::Access(access_node) => visit_access_node(attribute_index, lvar_index, access_node),
-        Node::Binary(node) => visit_binary_node(attribute_index, method_index, lvar_index, node),
-        Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
-        Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
-        _ => todo!(),
+    let left_type = visit_operand(binary_node.left.as_mut());
+    let right_type = visit_operand(binary_node.right.as_mut());
+
+    if binary_node.is_comparison() || binary_node.is_logical() {
+        binary_node.return_type = Some(BaseType::Bool);
+        return binary_node.return_type.clone();
+    }
+
+    // Integers are converted when the other operand is a float
+    if left_type == Some(BaseType::Float) || right_type == Some(BaseType::Float) {
+        binary_node.return_type = Some(BaseType::Float);
+        return binary_node.return_type.clone();
     }
+
+    // Integer literals take the type of the other operand
+    binary_node.return_type = left_type.or(right_type).or(Some(BaseType::Int));
+    binary_node.return_type.clone()
 }
 
 fn visit_call_node(
@@ -558,14 +4974,16 @@ fn visit_call_node(
     lvar_index: &HashMap<String, Option<BaseType>>,
     call_node: &mut crate::parser::Call,
 ) -> Option<BaseType> {
-    println!("{:#?}", &call_node.fn_name);
-    println!("{:#?}", method_index);
+    tracing::trace!("{:#?}", &call_node.fn_name);
+    tracing::trace!("{:#?}", method_index);
 
-    let base_type = method_index.get(&call_node.fn_name).unwrap();
+    // Ambiguous trait defaults aren't indexed, they're reported with the
+    // inherited methods
+    let base_type = method_index.get(&call_node.fn_name).cloned().flatten();
     call_node.return_type = base_type.clone();
 
     for arg in &mut call_node.args {
-        println!("{:#?}", arg);
+        tracing::trace!("{:#?}", arg);
 
         match arg {
             Node::Access(access_node) => {
@@ -575,15 +4993,19 @@ fn visit_call_node(
                 visit_call_node(attribute_index, method_index, lvar_index, node);
             }
             Node::Send(node) => {
-                visit_send_node(attribute_index, &method_index, lvar_index, node);
+                visit_send_node(attribute_index, method_index, lvar_index, node);
             }
             Node::Binary(node) => {
                 visit_binary_node(attribute_index, method_index, lvar_index, node);
             }
+            Node::Array(array) => {
+                visit_array_node(attribute_index, method_index, lvar_index, array);
+            }
             Node::LocalVar(lvar) => {
-                match lvar.return_type {
-                    Some(_) => {}
-                    None => todo!(),
+                // Locals assigned a binary or an `if` are only typed by inference.
+                // The type checker reports the ones it couldn't type.
+                if let (None, None | Some(None)) = (&lvar.return_type, lvar_index.get(&lvar.name)) {
+                    continue;
                 }
 
                 let latest_return_type = lvar_index.get(&lvar.name).unwrap();
@@ -591,17 +5013,30 @@ fn visit_call_node(
             }
             Node::StringLiteral(_) => {}
             Node::Const(_) => {}
+            Node::Bool(_) => {}
+            Node::Float(_) => {}
             Node::Int(_) => {}
+            Node::Nil(_) => {}
             Node::SelfRef(self_ref) => {
                 // Node::SelfRef(self_ref) => pajama_class_name(&self_ref.return_type),
             }
             _ => {
-                println!("{:#?}", arg);
+                tracing::trace!("{:#?}", arg);
                 todo!()
             }
         };
     }
 
+    // The builtin `min` and `max` return an item of the array they are given
+    if base_type.is_none() && (call_node.fn_name == "min" || call_node.fn_name == "max") {
+        if let Some(BaseType::Array(item_type)) =
+            call_node.args.first().and_then(typed_node_base_type)
+        {
+            call_node.return_type = Some(*item_type);
+            return call_node.return_type.clone();
+        }
+    }
+
     base_type.clone()
 }
 
@@ -619,9 +5054,17 @@ fn visit_send_node(
     let basetype = match send_node.receiver.as_mut() {
         Node::Access(access_node) => visit_access_node(attribute_index, lvar_index, access_node),
         Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
-        Node::Send(node) => visit_send_node(attribute_index, &method_index, lvar_index, node),
+        Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
         Node::Binary(node) => visit_binary_node(attribute_index, method_index, lvar_index, node),
+        Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+        Node::Array(array) => visit_array_node(attribute_index, method_index, lvar_index, array),
         Node::LocalVar(lvar) => {
+            // Locals assigned a binary or an `if` are only typed by inference
+            if let (None, Some(Some(return_type))) = (&lvar.return_type, lvar_index.get(&lvar.name))
+            {
+                lvar.return_type = Some(return_type.clone());
+            }
+
             match lvar.return_type {
                 Some(_) => {}
                 None => {
@@ -646,6 +5089,9 @@ fn visit_send_node(
                 send_node.return_type = Some(BaseType::Class(node.name.clone()));
                 Some(BaseType::Class(node.name.clone()))
                 // return;
+            } else if method_index.contains_key(&format!("{}.{}", node.name, fn_name)) {
+                // builtin class methods, like `Int.parse`
+                Some(BaseType::Class(node.name.clone()))
             } else {
                 todo!("class methods")
             }
@@ -653,7 +5099,7 @@ fn visit_send_node(
         _ => None,
     };
 
-    let class_name = pajama_class_name(&basetype.as_ref().unwrap());
+    let class_name = pajama_class_name(basetype.as_ref().unwrap().without_optional());
     let message_name = match send_node.message.as_mut() {
         Node::Call(node) => {
             let prefixed_name = format!("{}.{}", class_name, &node.fn_name);
@@ -666,7 +5112,22 @@ fn visit_send_node(
         _ => "".to_string(),
     };
 
-    let base_type = method_index.get(&message_name).unwrap();
+    let base_type = match (method_index.get(&message_name).cloned().flatten(), basetype) {
+        (None, Some(BaseType::Array(item_type))) => {
+            array_method_return_type(&message_name, *item_type, send_node, method_index)
+        }
+        (base_type, _) => base_type,
+    };
+
+    // `&.` gives nil when the receiver is, the type checker reports messages
+    // that return something else than an instance
+    let base_type = match base_type {
+        Some(base_type @ BaseType::Class(_)) if send_node.safe_navigation => {
+            Some(BaseType::Optional(Box::new(base_type)))
+        }
+        base_type => base_type,
+    };
+
     match base_type {
         Some(bt) => {
             send_node.return_type = Some(bt.clone());
@@ -676,6 +5137,84 @@ fn visit_send_node(
     }
 }
 
+/// What a builtin Array method returns for an array of `item_type`. `map`
+/// returns an array of what the function it's given returns.
+fn array_method_return_type(
+    method_name: &str,
+    item_type: BaseType,
+    send_node: &parser::Send,
+    method_index: &HashMap<String, Option<BaseType>>,
+) -> Option<BaseType> {
+    match method_name {
+        "Array.[]" | "Array.min" | "Array.max" => Some(item_type),
+        "Array.length" => Some(BaseType::Int),
+        "Array.decode" => Some(BaseType::Class("Str".to_string())),
+        "Array.push" => Some(BaseType::Array(Box::new(item_type))),
+        "Array.map" => {
+            let fn_name = match send_node.message.as_ref() {
+                Node::Call(call_node) => referenced_fn_name(call_node.args.first()?)?,
+                _ => return None,
+            };
+            let return_type = method_index.get(&fn_name)?.clone()?;
+
+            Some(BaseType::Array(Box::new(return_type)))
+        }
+        _ => None,
+    }
+}
+
+/// Types the items of an array literal, which holds the type of its first
+/// item. An empty one holds Ints.
+fn visit_array_node(
+    attribute_index: &HashMap<String, (i32, BaseType)>,
+    method_index: &HashMap<String, Option<BaseType>>,
+    lvar_index: &HashMap<String, Option<BaseType>>,
+    array: &mut parser::Array,
+) -> Option<BaseType> {
+    let item_types: Vec<Option<BaseType>> = array
+        .items
+        .iter_mut()
+        .map(|node| match node {
+            Node::Access(access_node) => {
+                visit_access_node(attribute_index, lvar_index, access_node)
+            }
+            Node::Binary(node) => {
+                visit_binary_node(attribute_index, method_index, lvar_index, node)
+            }
+            Node::Call(node) => visit_call_node(attribute_index, method_index, lvar_index, node),
+            Node::Send(node) => visit_send_node(attribute_index, method_index, lvar_index, node),
+            Node::Array(node) => visit_array_node(attribute_index, method_index, lvar_index, node),
+            Node::LocalVar(lvar) => {
+                let latest_return_type = lvar_index.get(&lvar.name).unwrap();
+                lvar.return_type = latest_return_type.clone();
+                latest_return_type.clone()
+            }
+            Node::Bool(_) => Some(BaseType::Bool),
+            Node::Float(_) => Some(BaseType::Float),
+            Node::Int(_) => Some(BaseType::Int),
+            Node::StringLiteral(_) => Some(BaseType::Class("Str".to_string())),
+            Node::Nil(_) => Some(BaseType::Nil),
+            _ => todo!(),
+        })
+        .collect();
+
+    // Nil items make an array of optionals of the other items' type
+    let has_nil = item_types.contains(&Some(BaseType::Nil));
+
+    let first_item_type = item_types
+        .into_iter()
+        .find(|item_type| *item_type != Some(BaseType::Nil));
+
+    if let Some(Some(item_type)) = first_item_type {
+        array.item_type = match item_type {
+            BaseType::Class(_) if has_nil => BaseType::Optional(Box::new(item_type)),
+            item_type => item_type,
+        };
+    }
+
+    Some(BaseType::Array(Box::new(array.item_type.clone())))
+}
+
 fn visit_build_struct_node(
     attribute_index: &HashMap<String, (i32, BaseType)>,
     method_index: &HashMap<String, Option<BaseType>>,
@@ -686,22 +5225,22 @@ fn visit_build_struct_node(
     build_struct_node.args.iter_mut().for_each(|node| {
         match node {
             Node::Access(access_node) => {
-                visit_access_node(&attribute_index, &lvar_index, access_node);
+                visit_access_node(attribute_index, lvar_index, access_node);
             }
             Node::Binary(node) => {
-                visit_binary_node(&attribute_index, &method_index, &lvar_index, node);
+                visit_binary_node(attribute_index, method_index, lvar_index, node);
             }
             Node::Call(node) => {
-                visit_call_node(&attribute_index, &method_index, &lvar_index, node);
+                visit_call_node(attribute_index, method_index, lvar_index, node);
             }
             Node::Send(node) => {
-                visit_send_node(&attribute_index, &method_index, &lvar_index, node);
+                visit_send_node(attribute_index, method_index, lvar_index, node);
             }
             Node::BuildStruct(node) => {
                 visit_build_struct_node(
-                    &attribute_index,
-                    &method_index,
-                    &lvar_index,
+                    attribute_index,
+                    method_index,
+                    lvar_index,
                     node,
                     struct_index,
                 );
@@ -721,10 +5260,12 @@ fn visit_build_struct_node(
 
 pub fn pajama_class_name(base_type: &BaseType) -> String {
     match base_type {
-        BaseType::Array(_, _) => "Array".to_string(),
+        BaseType::Array(_) => "Array".to_string(),
+        BaseType::Bool => "Bool".to_string(),
         BaseType::Byte => "Byte".to_string(),
         BaseType::BytePtr => "BytePtr".to_string(),
         BaseType::Class(class_name) => class_name.to_string(),
+        BaseType::Float => "Float".to_string(),
         BaseType::Int => "Int".to_string(),
         BaseType::Int16 => "Int16".to_string(),
         BaseType::Int32 => "Int32".to_string(),
@@ -732,5 +5273,7 @@ pub fn pajama_class_name(base_type: &BaseType) -> String {
         BaseType::Void => "".to_string(),
         BaseType::Struct(_) => "Struct".to_string(),
         BaseType::FnRef => "FnRef".to_string(),
+        BaseType::Optional(base_type) => format!("{}?", pajama_class_name(base_type)),
+        BaseType::Nil => "Nil".to_string(),
     }
 }
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::Diagnostic;
+use crate::graph::ImportGraph;
+use crate::lexer::{Lexer, Token, TokenPosition};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub path: String,
+    pub input: String,
+}
+
+/// Reads a source file, stripping a UTF-8 byte order mark.
+///
+/// Invalid UTF-8 is reported with the byte offset it starts at, unless
+/// `latin1` is set, in which case the whole file is decoded as Latin-1.
+pub fn read_source(path: &str, latin1: bool) -> Result<String, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("{}: {}", path, err)),
+    };
+
+    decode_source(&bytes, latin1).map_err(|err| format!("{}: {}", path, err))
+}
+
+pub fn decode_source(bytes: &[u8], latin1: bool) -> Result<String, String> {
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+
+    match std::str::from_utf8(bytes) {
+        Ok(source) => Ok(source.to_string()),
+        // Every byte is a Latin-1 code point, so this can't fail
+        Err(_) if latin1 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+        Err(err) => Err(format!(
+            "invalid UTF-8 at byte {}, pass --latin1 to read the file as Latin-1",
+            err.valid_up_to()
+        )),
+    }
+}
+
+/// `sources` with the files their `import "path"` lines name, each after the
+/// files it imports. Paths are relative to the importing file. A file is
+/// loaded once however many files import it, so the parser's
+/// duplicate-definition errors only catch a class or function defined twice.
+///
+/// Imports that can't be read are reported at the `import` line, and ones
+/// that lead back to a file importing them at the `import` line closing the
+/// cycle, naming every import along it. The files loaded so far are returned
+/// either way so the diagnostics can be rendered.
+pub fn resolve_imports(
+    sources: Vec<SourceFile>,
+    latin1: bool,
+) -> (Vec<SourceFile>, Vec<Diagnostic>) {
+    let (sources, _, diagnostics) = resolve_import_graph(sources, latin1);
+
+    (sources, diagnostics)
+}
+
+/// `resolve_imports`, with which of the files loaded import which, for
+/// `pajama graph --imports`.
+pub fn resolve_import_graph(
+    sources: Vec<SourceFile>,
+    latin1: bool,
+) -> (Vec<SourceFile>, ImportGraph, Vec<Diagnostic>) {
+    let mut loader = ImportLoader {
+        latin1,
+        loaded: vec![],
+        loaded_keys: vec![],
+        importing: vec![],
+        import_positions: vec![],
+        imports: vec![],
+        diagnostics: vec![],
+    };
+
+    for source in sources {
+        let key = import_key(Path::new(&source.path));
+        loader.load(source, key);
+    }
+
+    // Files that couldn't be read aren't in the graph, they're diagnostics
+    let path_of = |key: &PathBuf| {
+        let index = loader.loaded_keys.iter().position(|loaded| loaded == key)?;
+
+        Some(loader.loaded[index].path.clone())
+    };
+
+    let imports = loader
+        .imports
+        .iter()
+        .filter_map(|(importer, imported)| Some((path_of(importer)?, path_of(imported)?)))
+        .collect();
+
+    let graph = ImportGraph {
+        files: loader
+            .loaded
+            .iter()
+            .map(|source| source.path.clone())
+            .collect(),
+        imports,
+    };
+
+    (loader.loaded, graph, loader.diagnostics)
+}
+
+struct ImportLoader {
+    latin1: bool,
+    loaded: Vec<SourceFile>,
+    loaded_keys: Vec<PathBuf>,
+    /// The files being loaded, each imported by the one before it
+    importing: Vec<(PathBuf, String)>,
+    /// Where each file being loaded imports the next one
+    import_positions: Vec<TokenPosition>,
+    /// Every import read, as the keys of the importing and imported files
+    imports: Vec<(PathBuf, PathBuf)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ImportLoader {
+    fn load(&mut self, source: SourceFile, key: PathBuf) {
+        if self.loaded_keys.contains(&key) {
+            return;
+        }
+
+        self.importing.push((key.clone(), source.path.clone()));
+
+        let dir = Path::new(&source.path).parent().unwrap_or(Path::new(""));
+
+        for (import, position) in imports(&source.input) {
+            let path = dir.join(&import);
+            let import_key = import_key(&path);
+            let path = path.to_string_lossy().into_owned();
+
+            self.imports.push((key.clone(), import_key.clone()));
+
+            let error = |message: String| Diagnostic {
+                message,
+                path: Some(source.path.clone()),
+                position: Some(position.clone()),
+            };
+
+            if let Some(start) = self
+                .importing
+                .iter()
+                .position(|(key, _)| *key == import_key)
+            {
+                let mut cycle: Vec<String> = self.importing[start..]
+                    .windows(2)
+                    .zip(&self.import_positions[start..])
+                    .map(|(files, position)| {
+                        format!(
+                            "{}:{}:{} imports {}",
+                            files[0].1, position.line, position.start_column, files[1].1
+                        )
+                    })
+                    .collect();
+                // Named as it was first, not as however this import spells it
+                cycle.push(format!(
+                    "{}:{}:{} imports {}",
+                    source.path, position.line, position.start_column, self.importing[start].1
+                ));
+
+                self.diagnostics
+                    .push(error(format!("import cycle: {}", cycle.join(", "))));
+                continue;
+            }
+
+            if self.loaded_keys.contains(&import_key) {
+                continue;
+            }
+
+            match read_source(&path, self.latin1) {
+                Ok(input) => {
+                    self.import_positions.push(position.clone());
+                    self.load(SourceFile { path, input }, import_key);
+                    self.import_positions.pop();
+                }
+                Err(err) => self.diagnostics.push(error(err)),
+            }
+        }
+
+        self.importing.pop();
+        self.loaded_keys.push(key);
+        self.loaded.push(source);
+    }
+}
+
+/// Identifies a file however it's named, falling back to the path as given
+/// for files that aren't on disk, like a program compiled from a string.
+fn import_key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The paths named by the `import` lines of `input`, with their positions.
+fn imports(input: &str) -> Vec<(String, TokenPosition)> {
+    let tokens = Lexer::new(input).tokenize();
+
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| matches!(token, Token::Import))
+        .filter_map(|(index, _)| {
+            match tokens[index + 1..]
+                .iter()
+                .find(|token| !matches!(token, Token::Space(_)))
+            {
+                Some(Token::StringLiteral(position, path)) => {
+                    Some((path.clone(), position.clone()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
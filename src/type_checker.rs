@@ -0,0 +1,436 @@
+use crate::ast::{visit_nodes, walk_node, Visitor};
+use crate::parser::{Arg, BaseType, Def, Node, ParserResult, ParserResultIndex};
+use crate::semantic_analyzer::{pajama_class_name, typed_node_base_type, Diagnostics};
+
+/// Type checking
+///
+/// Runs once inference has typed every expression, before any code is
+/// generated, and reports:
+///
+/// * arithmetic and ordering between values that aren't both numbers,
+///   bitwise operators between values that aren't both integers, and `==` or
+///   `!=` between unrelated types, like `1 + "abc"`
+/// * calls to a function that isn't defined
+/// * calls and sends with the wrong number of arguments, or with an argument
+///   that doesn't match the type in the prototype
+/// * a returned value, given to `return` or last in a def, that doesn't match
+///   the declared return type
+/// * a default value that doesn't match the type declared for its argument
+/// * array literals with an item that doesn't match the first one
+/// * sends to, and attribute reads from, an optional that might be nil, see
+///   `apply_nil_checks`, and `&.` sends whose result can't be nil
+/// * optionals of anything but a class, and locals assigned `nil`, whose
+///   type isn't known
+///
+/// Integer types convert into each other, and an integer operand is
+/// converted when the other one is a `Float`. Anywhere else a `Float` is only
+/// given where a `Float` is expected, with `to_f` and `to_i` converting
+/// explicitly. The `BytePtr`s runtime functions take and return stand for any
+/// instance or array, as codegen casts between them. An instance or `nil` can
+/// be given where an optional of its class is expected. A subclass instance, or
+/// an instance of a class implementing a trait, can be given where the
+/// superclass or the trait is expected. Expressions that inference couldn't
+/// type are left unchecked.
+///
+/// Nodes don't carry their positions, so errors name the def they're in.
+pub fn check_types(result: &ParserResult, diagnostics: &mut Diagnostics) {
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => todo!(),
+    };
+
+    for node in &module.methods {
+        let def = match node {
+            Node::Def(def) => def,
+            _ => continue,
+        };
+
+        let mut errors = vec![];
+
+        for arg in &def.prototype.args {
+            check_default_value(arg, &result.index, &mut errors);
+
+            if !can_be_declared(&arg.return_type) {
+                errors.push(format!(
+                    "`{}` is declared as {}, but only classes can be optional",
+                    arg.name,
+                    type_name(&arg.return_type)
+                ));
+            }
+        }
+
+        if let Some(return_type) = def.prototype.return_type.as_ref() {
+            if !can_be_declared(return_type) {
+                errors.push(format!(
+                    "Returns {}, but only classes can be optional",
+                    type_name(return_type)
+                ));
+            }
+        }
+
+        for body_node in &def.body {
+            visit_nodes(body_node, &mut |node| {
+                check_node(node, def, &result.index, &mut errors)
+            });
+        }
+
+        let mut callees = UndefinedCallees {
+            index: &result.index,
+            errors: &mut errors,
+        };
+
+        for body_node in &def.body {
+            callees.visit_node(body_node);
+        }
+
+        for error in errors {
+            diagnostics.error(format!("{} in `{}`", error, def.prototype.name));
+        }
+    }
+
+    let mut class_names: Vec<&String> = result.index.class_index.keys().collect();
+    class_names.sort();
+
+    for class_name in class_names {
+        for attribute in &result.index.class_index[class_name].attributes {
+            if !can_be_declared(&attribute.return_type) {
+                diagnostics.error(format!(
+                    "`@{}` is declared as {}, but only classes can be optional in `{}`",
+                    attribute.name,
+                    type_name(&attribute.return_type),
+                    class_name
+                ));
+            }
+        }
+    }
+}
+
+fn check_node(node: &Node, def: &Def, index: &ParserResultIndex, errors: &mut Vec<String>) {
+    match node {
+        Node::Binary(binary) if !binary.is_logical() => {
+            let (left, right) = match (known_type(&binary.left), known_type(&binary.right)) {
+                (Some(left), Some(right)) => (left, right),
+                _ => return,
+            };
+
+            let valid = match binary.op.as_str() {
+                "==" | "!=" => {
+                    (is_numeric(&left) && is_numeric(&right))
+                        || compatible(&left, &right, index)
+                        || compatible(&right, &left, index)
+                }
+                "&" | "|" | "^" | "<<" | ">>" => is_integer(&left) && is_integer(&right),
+                _ => is_numeric(&left) && is_numeric(&right),
+            };
+
+            if !valid {
+                errors.push(format!(
+                    "`{}` can't be applied to {} and {}",
+                    binary.op,
+                    type_name(&left),
+                    type_name(&right)
+                ));
+            }
+        }
+        // Named args analysis couldn't put in order, which it reported
+        Node::Call(call) if !call.arg_names.is_empty() => {}
+        Node::Call(call) => {
+            // Locals inference couldn't type are left untyped
+            let untyped = call.args.iter().find_map(|arg| match arg {
+                Node::LocalVar(lvar) if lvar.return_type.is_none() => Some(&lvar.name),
+                _ => None,
+            });
+
+            if let Some(name) = untyped {
+                errors.push(format!(
+                    "Could not infer the type of `{}` passed to `{}`",
+                    name, call.fn_name
+                ));
+                return;
+            }
+
+            let prototype = match resolve_prototype(&call.fn_name, index) {
+                Some(prototype) => prototype,
+                // Builtins lowered during analysis
+                None => return,
+            };
+
+            // Sends pass the receiver as `sret`, calls generated during
+            // analysis, like `init` from `new`, give it as the first argument
+            let (expected, given): (&[Arg], &[Node]) = match prototype.args.first() {
+                Some(arg) if arg.name == "sret" && call.args.len() == prototype.args.len() => {
+                    (&prototype.args[1..], &call.args[1..])
+                }
+                Some(arg) if arg.name == "sret" => (&prototype.args[1..], &call.args),
+                _ => (&prototype.args, &call.args),
+            };
+
+            if expected.len() != given.len() {
+                errors.push(format!(
+                    "`{}` takes {} argument{}, given {}",
+                    call.fn_name,
+                    expected.len(),
+                    if expected.len() == 1 { "" } else { "s" },
+                    given.len()
+                ));
+                return;
+            }
+
+            for (arg, value) in expected.iter().zip(given) {
+                let found = match known_type(value) {
+                    Some(found) => found,
+                    None => continue,
+                };
+
+                if !compatible(&normalize(arg.return_type.clone()), &found, index) {
+                    errors.push(format!(
+                        "`{}` expects {} for `{}`, given {}",
+                        call.fn_name,
+                        type_name(&arg.return_type),
+                        arg.name,
+                        type_name(&found)
+                    ));
+                }
+            }
+        }
+        Node::Array(array) => {
+            let mismatched = array
+                .items
+                .iter()
+                .filter_map(known_type)
+                .find(|found| !compatible(&normalize(array.item_type.clone()), found, index));
+
+            if let Some(found) = mismatched {
+                errors.push(format!(
+                    "An array of {} can't hold {}",
+                    type_name(&array.item_type),
+                    type_name(&found)
+                ));
+            }
+        }
+        Node::Send(send) => {
+            let message_name = match send.message.as_ref() {
+                Node::Call(call) => call.fn_name.rsplit('.').next().unwrap_or_default(),
+                _ => return,
+            };
+
+            if !send.safe_navigation && might_be_nil(&send.receiver) {
+                errors.push(format!(
+                    "`{}` is sent to {}, which might be nil; check that it isn't or send it with `&.`",
+                    message_name,
+                    receiver_name(&send.receiver)
+                ));
+            }
+
+            if let (true, Some(return_type)) = (send.safe_navigation, send.return_type.as_ref()) {
+                if !matches!(return_type, BaseType::Optional(_) | BaseType::Void) {
+                    errors.push(format!(
+                        "`&.{}` returns {}, which can't be nil",
+                        message_name,
+                        type_name(return_type)
+                    ));
+                }
+            }
+        }
+        Node::Access(access) if might_be_nil(&access.receiver) => {
+            if let Node::Attribute(attribute) = access.message.as_ref() {
+                errors.push(format!(
+                    "`{}` is read from {}, which might be nil; check that it isn't first",
+                    attribute.name,
+                    receiver_name(&access.receiver)
+                ));
+            }
+        }
+        Node::AssignLocalVar(assignment) if matches!(assignment.value.as_ref(), Node::Nil(_)) => {
+            errors.push(format!(
+                "`{}` can't be assigned nil, locals are typed by the value they're assigned",
+                assignment.name
+            ));
+        }
+        Node::Ret(ret) => {
+            let (expected, found) = match (&def.prototype.return_type, known_type(&ret.value)) {
+                (Some(expected), Some(found)) => (normalize(expected.clone()), found),
+                _ => return,
+            };
+
+            if !compatible(&expected, &found, index) {
+                errors.push(format!(
+                    "Returns {} but {} is declared",
+                    type_name(&found),
+                    type_name(&expected)
+                ));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Finds calls to functions that aren't defined. Builtins are lowered to
+/// `pj_` runtime functions, which analysis declares, or reports why it can't,
+/// like a missing Str class. The message of a send is named after its
+/// receiver's class instead, and resolved by the backends.
+struct UndefinedCallees<'a> {
+    index: &'a ParserResultIndex,
+    errors: &'a mut Vec<String>,
+}
+
+impl Visitor for UndefinedCallees<'_> {
+    fn visit_node(&mut self, node: &Node) {
+        match node {
+            Node::Send(send) => {
+                self.visit_node(&send.receiver);
+
+                match send.message.as_ref() {
+                    Node::Call(call) => call.args.iter().for_each(|arg| self.visit_node(arg)),
+                    message => self.visit_node(message),
+                }
+            }
+            Node::Call(call) => {
+                if !call.fn_name.starts_with("pj_")
+                    && resolve_prototype(&call.fn_name, self.index).is_none()
+                {
+                    self.errors
+                        .push(format!("`{}` isn't defined", call.fn_name));
+                }
+
+                walk_node(self, node);
+            }
+            _ => walk_node(self, node),
+        }
+    }
+}
+
+fn check_default_value(arg: &Arg, index: &ParserResultIndex, errors: &mut Vec<String>) {
+    let found = match arg.default.as_ref().and_then(known_type) {
+        Some(found) => found,
+        None => return,
+    };
+
+    if !compatible(&normalize(arg.return_type.clone()), &found, index) {
+        errors.push(format!(
+            "`{}` is declared as {} but defaults to {}",
+            arg.name,
+            type_name(&arg.return_type),
+            type_name(&found)
+        ));
+    }
+}
+
+/// The prototype `fn_name` calls, which can be inherited from a superclass
+/// or a trait default.
+pub fn resolve_prototype<'a>(
+    fn_name: &str,
+    index: &'a ParserResultIndex,
+) -> Option<&'a crate::parser::Prototype> {
+    if let Some(prototype) = index.fn_prototype_index.get(fn_name) {
+        return Some(prototype);
+    }
+
+    // Inherited methods and trait defaults
+    let (class_name, method_name) = fn_name.split_once('.')?;
+
+    match index.resolve_method(class_name, method_name) {
+        Ok(Some(resolved_name)) => index.fn_prototype_index.get(&resolved_name),
+        _ => None,
+    }
+}
+
+/// Whether `base_type` is a type arguments, returns and attributes can be
+/// declared as: anything but an optional of something else than a class.
+fn can_be_declared(base_type: &BaseType) -> bool {
+    match base_type {
+        BaseType::Optional(base_type) => {
+            matches!(normalize(*base_type.clone()), BaseType::Class(_))
+        }
+        _ => true,
+    }
+}
+
+fn might_be_nil(node: &Node) -> bool {
+    matches!(
+        known_type(node),
+        Some(BaseType::Optional(_) | BaseType::Nil)
+    )
+}
+
+/// How an error names the receiver of a send or an attribute read.
+fn receiver_name(node: &Node) -> String {
+    match (node, known_type(node)) {
+        (Node::LocalVar(lvar), _) => format!("`{}`", lvar.name),
+        (_, Some(base_type)) => format!("a {}", type_name(&base_type)),
+        _ => "a value".to_string(),
+    }
+}
+
+/// The inferred type of `node`, `None` when it's unknown.
+fn known_type(node: &Node) -> Option<BaseType> {
+    match typed_node_base_type(node).map(normalize) {
+        Some(BaseType::Class(class_name)) if class_name.is_empty() => None,
+        base_type => base_type,
+    }
+}
+
+/// Locals and arguments of builtin types are typed as a class of that name,
+/// e.g. `Class("Int")` for `Int`.
+fn normalize(base_type: BaseType) -> BaseType {
+    match &base_type {
+        BaseType::Class(class_name) => match class_name.as_str() {
+            "Bool" => BaseType::Bool,
+            "Byte" => BaseType::Byte,
+            "BytePtr" => BaseType::BytePtr,
+            "Float" => BaseType::Float,
+            "FnRef" => BaseType::FnRef,
+            "Int" => BaseType::Int,
+            "Int16" => BaseType::Int16,
+            "Int32" => BaseType::Int32,
+            "Int64" => BaseType::Int64,
+            _ => base_type,
+        },
+        _ => base_type,
+    }
+}
+
+fn is_integer(base_type: &BaseType) -> bool {
+    matches!(
+        base_type,
+        BaseType::Byte | BaseType::Int | BaseType::Int16 | BaseType::Int32 | BaseType::Int64
+    )
+}
+
+fn is_numeric(base_type: &BaseType) -> bool {
+    is_integer(base_type) || *base_type == BaseType::Float
+}
+
+/// Whether a value of type `found` can be given where `expected` is.
+fn compatible(expected: &BaseType, found: &BaseType, index: &ParserResultIndex) -> bool {
+    match (expected, found) {
+        (expected, found) if expected == found => true,
+        (expected, found) if is_integer(expected) && is_integer(found) => true,
+        (BaseType::Optional(_), BaseType::Nil) => true,
+        (BaseType::Optional(expected), BaseType::Optional(found)) => {
+            compatible(expected, found, index)
+        }
+        (BaseType::Optional(expected), found) => compatible(expected, found, index),
+        (BaseType::BytePtr, other) | (other, BaseType::BytePtr) => {
+            !is_numeric(other) && *other != BaseType::Bool
+        }
+        (BaseType::Class(expected), BaseType::Class(found)) => {
+            let ancestors = index.class_ancestors(found);
+
+            ancestors.contains(expected) || index.implemented_traits(&ancestors).contains(expected)
+        }
+        (BaseType::Array(expected), BaseType::Array(found)) => compatible(expected, found, index),
+        _ => false,
+    }
+}
+
+/// How `base_type` is written in Pajama, for messages.
+pub fn type_name(base_type: &BaseType) -> String {
+    match base_type {
+        BaseType::Void => "nothing".to_string(),
+        BaseType::Nil => "nil".to_string(),
+        BaseType::Array(item_type) => format!("[{}]", type_name(item_type)),
+        BaseType::Optional(base_type) => format!("{}?", type_name(base_type)),
+        base_type => pajama_class_name(base_type),
+    }
+}
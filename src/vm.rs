@@ -0,0 +1,274 @@
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::bytecode::{Op, Program, NATIVE_FNS};
+use crate::resource_limits::ResourceLimits;
+
+/// How deep calls can nest before the program is stopped, rather than
+/// growing the VM's stack until it runs out of memory
+pub const MAX_FRAMES: usize = 100_000;
+
+/// How many ops run between checks of `ResourceLimits::max_time`
+const TIME_CHECK_INTERVAL: u64 = 4096;
+
+/// A value on the VM's stack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    /// What a function without a value returns
+    Void,
+}
+
+struct Frame {
+    function: usize,
+    pc: usize,
+    locals: Vec<Value>,
+}
+
+/// Runs the program's `main`, writing what it prints to `out`, and returns
+/// the status it exits with: what it passes to `exit`, or 0.
+///
+/// Ints wrap on overflow like compiled code's do. What compiled code would
+/// crash on, like dividing by zero, stops the program with an error instead.
+pub fn run(program: &Program, out: &mut dyn Write) -> Result<i32, String> {
+    run_limited(program, out, &ResourceLimits::default())
+}
+
+/// Like `run`, stopping the program with an error once the Strs it builds
+/// add up to more than `max_heap` bytes, or once it has run for longer than
+/// `max_time`. Unlike compiled programs, going over a limit doesn't end the
+/// process, so a service can run programs it doesn't trust.
+pub fn run_limited(
+    program: &Program,
+    out: &mut dyn Write,
+    limits: &ResourceLimits,
+) -> Result<i32, String> {
+    let started = Instant::now();
+    let max_heap = limits.max_heap.unwrap_or(u64::MAX);
+    let mut heap_used = 0;
+    let mut ops_run: u64 = 0;
+
+    let mut stack: Vec<Value> = vec![];
+    let mut frames = vec![enter(program, program.main as usize, &mut stack)];
+
+    loop {
+        ops_run += 1;
+
+        if let Some(max_time) = limits.max_time {
+            if ops_run.is_multiple_of(TIME_CHECK_INTERVAL) && started.elapsed() > max_time {
+                return Err(format!("time limit of {:?} exceeded", max_time));
+            }
+        }
+
+        let frame = frames.last_mut().unwrap();
+        let function = &program.functions[frame.function];
+        let op = function.code[frame.pc];
+        frame.pc += 1;
+
+        match op {
+            Op::Int(value) => stack.push(Value::Int(value)),
+            Op::Float(value) => stack.push(Value::Float(value)),
+            Op::Bool(value) => stack.push(Value::Bool(value)),
+            Op::Str(index) => {
+                stack.push(Value::Str(program.strings[index as usize].as_str().into()))
+            }
+            Op::Void => stack.push(Value::Void),
+            Op::Load(slot) => stack.push(frame.locals[slot as usize].clone()),
+            Op::Store(slot) => frame.locals[slot as usize] = pop(&mut stack)?,
+            Op::Pop => {
+                pop(&mut stack)?;
+            }
+            Op::Jump(target) => frame.pc = target as usize,
+            Op::JumpIfFalse(target) => match pop(&mut stack)? {
+                Value::Bool(true) => {}
+                Value::Bool(false) => frame.pc = target as usize,
+                value => return Err(format!("expected a Bool to branch on, found {:?}", value)),
+            },
+            Op::Call(index) => {
+                if frames.len() == MAX_FRAMES {
+                    return Err(format!(
+                        "stack overflow, calls nested more than {} deep",
+                        MAX_FRAMES
+                    ));
+                }
+
+                let callee = enter(program, index as usize, &mut stack);
+                frames.push(callee);
+            }
+            Op::Native(index) => {
+                let name = NATIVE_FNS[index as usize];
+
+                if name == "exit" {
+                    return match pop(&mut stack)? {
+                        Value::Int(status) => Ok(status as i32),
+                        value => Err(format!(
+                            "expected an Int status for exit, found {:?}",
+                            value
+                        )),
+                    };
+                }
+
+                let value = native(name, &mut stack, out)?;
+
+                // Nothing is freed, like in compiled code, so the count only grows
+                if let Value::Str(text) = &value {
+                    heap_used += text.len() as u64;
+
+                    if heap_used > max_heap {
+                        return Err(format!("heap limit of {} bytes exceeded", max_heap));
+                    }
+                }
+
+                stack.push(value);
+            }
+            Op::Return => {
+                let value = pop(&mut stack)?;
+                frames.pop();
+
+                if frames.is_empty() {
+                    return Ok(0);
+                }
+
+                stack.push(value);
+            }
+            op => {
+                let right = pop(&mut stack)?;
+                let left = pop(&mut stack)?;
+
+                stack.push(binary(op, left, right)?);
+            }
+        }
+    }
+}
+
+/// A frame for calling `function`, with its args popped off the stack.
+fn enter(program: &Program, function: usize, stack: &mut Vec<Value>) -> Frame {
+    let callee = &program.functions[function];
+    let args = stack.len().saturating_sub(callee.arity as usize);
+    let mut locals = stack.split_off(args);
+
+    locals.resize(callee.locals as usize, Value::Void);
+
+    Frame {
+        function,
+        pc: 0,
+        locals,
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, String> {
+    stack.pop().ok_or_else(|| "the stack is empty".to_string())
+}
+
+fn binary(op: Op, left: Value, right: Value) -> Result<Value, String> {
+    use Value::*;
+
+    Ok(match (op, left, right) {
+        (op, Int(left), Int(right)) => match op {
+            Op::Add => Int(left.wrapping_add(right)),
+            Op::Sub => Int(left.wrapping_sub(right)),
+            Op::Mul => Int(left.wrapping_mul(right)),
+            Op::Div if right == 0 => return Err("division by zero".to_string()),
+            Op::Div => Int(left.wrapping_div(right)),
+            Op::BitAnd => Int(left & right),
+            Op::BitOr => Int(left | right),
+            Op::BitXor => Int(left ^ right),
+            Op::Shl => Int(left.wrapping_shl(right as u32)),
+            Op::Shr => Int(left.wrapping_shr(right as u32)),
+            op => compare(op, left.cmp(&right)),
+        },
+        (op, Bool(left), Bool(right)) => match op {
+            Op::BitAnd => Bool(left & right),
+            Op::BitOr => Bool(left | right),
+            Op::BitXor | Op::Ne => Bool(left != right),
+            Op::Eq => Bool(left == right),
+            op => return Err(format!("{:?} isn't defined for Bools", op)),
+        },
+        // Mixed Ints and Floats are compared and computed as Floats
+        (op, left, right) => {
+            let (left, right) = match (float(&left), float(&right)) {
+                (Some(left), Some(right)) => (left, right),
+                _ => {
+                    return Err(format!(
+                        "{:?} isn't defined for {:?} and {:?}",
+                        op, left, right
+                    ))
+                }
+            };
+
+            match op {
+                Op::Add => Float(left + right),
+                Op::Sub => Float(left - right),
+                Op::Mul => Float(left * right),
+                Op::Div => Float(left / right),
+                Op::Eq => Bool(left == right),
+                Op::Ne => Bool(left != right),
+                Op::Lt => Bool(left < right),
+                Op::Le => Bool(left <= right),
+                Op::Gt => Bool(left > right),
+                Op::Ge => Bool(left >= right),
+                op => return Err(format!("{:?} isn't defined for Floats", op)),
+            }
+        }
+    })
+}
+
+fn compare(op: Op, ordering: std::cmp::Ordering) -> Value {
+    Value::Bool(match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(value) => Some(*value as f64),
+        Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Calls the runtime function `name`, formatting values like `pajama_lib`
+/// does.
+fn native(name: &str, stack: &mut Vec<Value>, out: &mut dyn Write) -> Result<Value, String> {
+    let mut write = |text: String| {
+        out.write_all(text.as_bytes())
+            .map_err(|err| format!("couldn't write the output: {}", err))
+    };
+
+    Ok(match (name, pop(stack)?) {
+        ("pj_puts", Value::Str(text)) => {
+            write(format!("{}\n", text))?;
+            Value::Void
+        }
+        ("pj_print", Value::Str(text)) => {
+            write(text.to_string())?;
+            Value::Void
+        }
+        ("print_int", Value::Int(value)) => {
+            write(format!("print_int: {}\n", value))?;
+            Value::Void
+        }
+        ("pj_int_to_s", Value::Int(value)) => Value::Str(value.to_string().into()),
+        ("pj_float_to_s", Value::Float(value)) => Value::Str(format!("{:?}", value).into()),
+        ("pj_bool_to_s", Value::Bool(value)) => Value::Str(value.to_string().into()),
+        ("pj_int_to_f", Value::Int(value)) => Value::Float(value as f64),
+        ("pj_float_to_i", Value::Float(value)) => Value::Int(value as i64),
+        ("pj_sandbox_denied", Value::Str(name)) => {
+            return Err(format!("sandbox: `{}` isn't allowed", name))
+        }
+        ("pj_str_concat", Value::Str(right)) => match pop(stack)? {
+            Value::Str(left) => Value::Str(format!("{}{}", left, right).into()),
+            value => return Err(format!("expected a Str to concatenate, found {:?}", value)),
+        },
+        (name, value) => return Err(format!("`{}` can't be called with {:?}", name, value)),
+    })
+}
@@ -0,0 +1,103 @@
+use std::ffi::c_void;
+use std::process::Command;
+
+use pajama::pajama_lib::{
+    pj_array_get_bool, pj_array_get_float, pj_array_get_int, pj_array_get_ptr, pj_array_length,
+    pj_array_new, pj_array_push_bool, pj_array_push_float, pj_array_push_int, pj_array_push_ptr,
+    pj_array_set_float, pj_array_set_int, pj_max, pj_min, PjArray,
+};
+
+fn array() -> &'static mut PjArray {
+    unsafe { &mut *pj_array_new(0) }
+}
+
+#[test]
+fn arrays_grow_past_their_capacity() {
+    let numbers = array();
+
+    for number in 0..100 {
+        pj_array_push_int(numbers, number * 3);
+    }
+
+    assert_eq!(pj_array_length(numbers), 100);
+    assert_eq!(pj_array_get_int(numbers, 0), 0);
+    assert_eq!(pj_array_get_int(numbers, 99), 297);
+
+    pj_array_set_int(numbers, 99, -1);
+
+    assert_eq!(pj_array_get_int(numbers, 99), -1);
+    assert_eq!(pj_array_length(numbers), 100);
+}
+
+#[test]
+fn pushing_returns_the_array_so_pushes_chain() {
+    let empty = array();
+    let numbers = pj_array_push_int(pj_array_push_int(empty, 1), 2);
+
+    assert_eq!(pj_array_length(numbers), 2);
+    assert_eq!(pj_array_get_int(numbers, 1), 2);
+}
+
+#[test]
+fn every_item_type_round_trips_through_its_slot() {
+    let floats = pj_array_push_float(array(), -0.5);
+    pj_array_push_float(floats, f64::MAX);
+    pj_array_set_float(floats, 0, 1.25);
+
+    assert_eq!(pj_array_get_float(floats, 0), 1.25);
+    assert_eq!(pj_array_get_float(floats, 1), f64::MAX);
+
+    let bools = pj_array_push_bool(pj_array_push_bool(array(), true), false);
+
+    assert!(pj_array_get_bool(bools, 0));
+    assert!(!pj_array_get_bool(bools, 1));
+
+    let mut item = 7_i64;
+    let pointer = &mut item as *mut i64 as *mut c_void;
+    let pointers = pj_array_push_ptr(array(), pointer);
+
+    assert_eq!(pj_array_get_ptr(pointers, 0), pointer);
+}
+
+extern "C" fn compare(left: *mut c_void, right: *mut c_void) -> i64 {
+    unsafe { *(left as *const i64) - *(right as *const i64) }
+}
+
+#[test]
+fn min_and_max_of_an_empty_array_stop_the_program() {
+    // They exit, so they're run in a copy of this test
+    match std::env::var("PJ_EMPTY_ARRAY").as_deref() {
+        Ok("min") => {
+            pj_min(array(), compare);
+        }
+        Ok("max") => {
+            pj_max(array(), compare);
+        }
+        _ => {}
+    }
+
+    for name in ["min", "max"] {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "min_and_max_of_an_empty_array_stop_the_program",
+                "--nocapture",
+            ])
+            .env("PJ_EMPTY_ARRAY", name)
+            .output()
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("an empty array has no {}", name)));
+    }
+
+    let (mut one, mut two) = (1_i64, 2_i64);
+    let one = &mut one as *mut i64 as *mut c_void;
+    let two = &mut two as *mut i64 as *mut c_void;
+    let numbers = pj_array_push_ptr(array(), two);
+    pj_array_push_ptr(numbers, one);
+
+    assert_eq!(pj_min(numbers, compare), one);
+    assert_eq!(pj_max(numbers, compare), two);
+}
@@ -0,0 +1,58 @@
+use pajama::ast::{walk_node, walk_node_mut, Visitor, VisitorMut};
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Node, Parser};
+
+use indoc::indoc;
+
+struct CallNames(Vec<String>);
+
+impl Visitor for CallNames {
+    fn visit_node(&mut self, node: &Node) {
+        if let Node::Call(call) = node {
+            self.0.push(call.fn_name.clone());
+        }
+
+        walk_node(self, node);
+    }
+}
+
+struct RenameCalls;
+
+impl VisitorMut for RenameCalls {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        if let Node::Call(call) = node {
+            call.fn_name = format!("pj_{}", call.fn_name);
+        }
+
+        walk_node_mut(self, node);
+    }
+}
+
+#[test]
+fn visitors_reach_nested_nodes() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          n = 0
+          while n < 3
+            if n < 1
+              print_int(n)
+            else
+              print_int(abs(n))
+            end
+            n = n + 1
+          end
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+
+    RenameCalls.visit_node_mut(&mut result.module);
+
+    let mut call_names = CallNames(vec![]);
+    call_names.visit_node(&result.module);
+
+    assert_eq!(call_names.0, vec!["pj_print_int", "pj_print_int", "pj_abs"]);
+}
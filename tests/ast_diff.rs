@@ -0,0 +1,110 @@
+use pajama::ast_diff::{diff_modules, AstDiff};
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Node, Parser};
+
+use indoc::indoc;
+
+fn parse(input: &str) -> Node {
+    Parser::start_parse(Lexer::new(input).tokenize(), &mut default_op_precedence()).module
+}
+
+#[test]
+fn only_edited_items_are_reported() {
+    let old = parse(indoc! {"
+        def_e print_int(n Int)
+
+        def one() -> Int
+           ret 1
+        end
+
+        def two() -> Int
+           ret 2
+        end
+    "});
+
+    let new = parse(indoc! {"
+        def_e print_int(n Int)
+
+        # moved down a line
+        def one() -> Int
+           ret 1
+        end
+
+        def two() -> Int
+           ret 3
+        end
+
+        def three() -> Int
+           ret 3
+        end
+    "});
+
+    assert_eq!(
+        diff_modules(&old, &new),
+        AstDiff {
+            added: vec!["def three".to_string()],
+            removed: vec![],
+            changed: vec!["def two/0:Ret/0:Int".to_string()],
+        }
+    );
+    assert!(diff_modules(&new, &new).is_empty());
+}
+
+#[test]
+fn changes_are_reported_at_the_smallest_subtree_they_are_in() {
+    let old = parse(indoc! {"
+        def_e print_int(n Int)
+
+        def main
+          a = 1
+          b = a + 2
+          print_int(b)
+          print_int(a)
+        end
+    "});
+
+    let new = parse(indoc! {"
+        def_e print_int(n Int)
+
+        def main
+          a = 1
+          c = 5
+          b = a - 2
+          print_int(b)
+          print_int(c)
+          print_int(a)
+        end
+    "});
+
+    assert_eq!(
+        diff_modules(&old, &new),
+        AstDiff {
+            added: vec![
+                "def main/1:AssignLocalVar".to_string(),
+                "def main/4:Call".to_string(),
+            ],
+            removed: vec![],
+            changed: vec!["def main/2:AssignLocalVar/0:Binary".to_string()],
+        }
+    );
+
+    let replaced = parse(indoc! {"
+        def_e print_int(n Int)
+
+        def main
+          a = 1
+          b = a + 2
+          print_int(b)
+          print_int(a + 1)
+        end
+    "});
+
+    assert_eq!(
+        diff_modules(&old, &replaced),
+        AstDiff {
+            added: vec!["def main/3:Call/0:Binary".to_string()],
+            removed: vec!["def main/3:Call/0:LocalVar".to_string()],
+            changed: vec![],
+        }
+    );
+}
@@ -0,0 +1,112 @@
+use pajama::backend::{find_backend, load_backend, Backend};
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::parser::{Node, ParserResult};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+/// Emits the names of the program's defs, one per line
+struct DefNames;
+
+impl Backend for DefNames {
+    fn name(&self) -> &str {
+        "def-names"
+    }
+
+    fn emit(&self, result: &ParserResult) -> Result<Vec<u8>, String> {
+        let module = match &result.module {
+            Node::Module(module) => module,
+            _ => return Err("Expected a module to emit".to_string()),
+        };
+
+        let names: Vec<String> = module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) => Some(format!("{}\n", def.prototype.name)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(names.concat().into_bytes())
+    }
+}
+
+/// Takes the place of the builtin C backend
+struct NoC;
+
+impl Backend for NoC {
+    fn name(&self) -> &str {
+        "c"
+    }
+
+    fn emit(&self, _result: &ParserResult) -> Result<Vec<u8>, String> {
+        Ok(vec![])
+    }
+}
+
+#[test]
+fn registered_backends_emit_the_checked_program() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            def double(n Int) -> Int
+              ret n * 2
+            end
+
+            def main
+              n = 1
+              a = double(n)
+            end
+        "}
+        .to_string(),
+    }];
+    let options = CompileOptions {
+        backends: vec![Box::new(DefNames)],
+        ..Default::default()
+    };
+
+    let backend = find_backend(&options.backends, "def-names").unwrap();
+    let artifact = PajamaCompiler::compile_with_backend(&sources, &options, backend)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(artifact).unwrap(), "double\nmain\n");
+}
+
+#[test]
+fn registered_backends_take_the_place_of_builtin_ones() {
+    let backends: Vec<Box<dyn Backend>> = vec![Box::new(NoC)];
+
+    assert!(find_backend(&[], "c").is_some());
+    assert!(find_backend(&[], "js").is_some());
+    assert!(find_backend(&[], "wasm").is_none());
+
+    let result = pajama::compile_to_ast("def main\nend\n").unwrap();
+
+    assert_eq!(
+        find_backend(&backends, "c").unwrap().emit(&result),
+        Ok(vec![])
+    );
+    assert!(!find_backend(&[], "c")
+        .unwrap()
+        .emit(&result)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn libraries_without_a_backend_are_refused() {
+    let missing = "/nonexistent/libpajama_backend.so";
+
+    assert!(load_backend(missing)
+        .err()
+        .unwrap()
+        .starts_with(&format!("{}: ", missing)));
+
+    // Loads, but exports no backend
+    assert_eq!(
+        load_backend("libc.so.6").err(),
+        Some("libc.so.6: not a backend, it doesn't export `pajama_backend_abi`".to_string())
+    );
+}
@@ -0,0 +1,68 @@
+use std::process::Command;
+
+use pajama::build_info::{read_build_info, BuildInfo};
+use pajama::optimization::OptLevel;
+
+#[test]
+fn build_info_is_encoded_with_an_optional_timestamp() {
+    let build_info = BuildInfo::new(OptLevel::O1, true);
+
+    assert_eq!(build_info.opt_level, "O1");
+    assert_eq!(build_info.timestamp, None);
+    assert_eq!(BuildInfo::decode(&build_info.encode()), Ok(build_info));
+
+    let build_info = BuildInfo::new(OptLevel::O2, false);
+
+    assert!(build_info.timestamp.is_some());
+    assert_eq!(BuildInfo::decode(&build_info.encode()), Ok(build_info));
+    assert_eq!(
+        BuildInfo::decode("version=0.1.0\n"),
+        Err("the build info has no `target`".to_string())
+    );
+}
+
+#[test]
+fn build_info_is_read_back_from_the_section_it_is_linked_into() {
+    let dir = std::env::temp_dir();
+    let c_path = dir.join(format!("pajama_build_info_{}.c", std::process::id()));
+    let object_path = c_path.with_extension("o");
+    let build_info = BuildInfo::new(OptLevel::O0, false);
+
+    std::fs::write(&c_path, build_info.to_c_source()).unwrap();
+
+    let status = Command::new("cc")
+        .arg("-c")
+        .arg(&c_path)
+        .arg("-o")
+        .arg(&object_path)
+        .status()
+        .unwrap();
+    let object = std::fs::read(&object_path).unwrap();
+
+    std::fs::remove_file(&c_path).unwrap();
+    std::fs::remove_file(&object_path).unwrap();
+
+    assert!(status.success());
+    assert_eq!(read_build_info(&object), Ok(build_info));
+}
+
+#[test]
+fn binaries_without_build_info_are_refused() {
+    let test_binary = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+
+    assert_eq!(
+        read_build_info(&test_binary),
+        Err(
+            "no __nilla_build_info section, the binary wasn't built by pajama --emit=exe"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        read_build_info(b"NLB\0\x01"),
+        Err("not an ELF binary".to_string())
+    );
+    assert_eq!(
+        read_build_info(&test_binary[..0x30]),
+        Err("the binary is cut short".to_string())
+    );
+}
@@ -0,0 +1,134 @@
+use pajama::bytecode::{emit_bytecode, Program};
+use pajama::vm;
+
+use indoc::indoc;
+
+fn compile(input: &str) -> Result<Program, String> {
+    emit_bytecode(&pajama::compile_to_ast(input).unwrap())
+}
+
+/// Round trips the program through its .nlb encoding, then runs it, returning
+/// the status and what it printed
+fn run(input: &str) -> (i32, String) {
+    let program = compile(input).unwrap();
+    let program = Program::decode(&program.encode()).unwrap();
+    let mut out = vec![];
+    let status = vm::run(&program, &mut out).unwrap();
+
+    (status, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn programs_run_on_the_vm_like_compiled_code() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def fib(n Int) -> Int
+          if n < 2
+            ret n
+          end
+
+          ret fib(n - 1) + fib(n - 2)
+        end
+
+        def main
+          n = 20
+          total = 0
+          i = 0
+
+          while i < 10
+            i = i + 1
+
+            if i == 5 || i == 7
+              next
+            end
+
+            total = total + i
+          end
+
+          half = total / 2.0
+          puts(\"fib(#{n}) = #{fib(n)}, total #{total}, half #{half}, #{n > 10 && total > 0}\")
+          print(\"no newline\")
+        end
+    "};
+
+    assert_eq!(
+        run(input),
+        (
+            0,
+            "fib(20) = 6765, total 43, half 21.5, true\nno newline".to_string()
+        )
+    );
+}
+
+#[test]
+fn main_exits_with_the_status_given_to_exit() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+        def_e exit(status Int)
+
+        def main
+          n = 3
+          loop {
+            n = n - 1
+
+            if n == 0
+              break
+            end
+          }
+
+          print_int(n)
+          exit(n + 4)
+          print_int(n)
+        end
+    "};
+
+    assert_eq!(run(input), (4, "print_int: 0\n".to_string()));
+}
+
+#[test]
+fn unsupported_constructs_are_reported_rather_than_emitted() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Dog
+          @legs Int
+        end
+
+        def main
+          dog = Dog.new(4)
+          print_int(dog.legs)
+        end
+    "};
+
+    let err = compile(input).unwrap_err();
+
+    assert!(err.starts_with("The bytecode backend"), "{}", err);
+}
+
+#[test]
+fn other_files_and_versions_are_refused() {
+    let bytes = compile("def main\nend\n").unwrap().encode();
+
+    assert_eq!(&bytes[..4], b"NLB\0");
+    assert_eq!(
+        Program::decode(b"\x7fELF"),
+        Err("not a .nlb file".to_string())
+    );
+
+    let mut newer = bytes.clone();
+    newer[4] = 99;
+
+    assert_eq!(
+        Program::decode(&newer),
+        Err("written for bytecode version 99, this VM runs version 1".to_string())
+    );
+    assert_eq!(
+        Program::decode(&bytes[..bytes.len() - 1]),
+        Err("the program is cut short".to_string())
+    );
+}
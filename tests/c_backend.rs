@@ -0,0 +1,352 @@
+use pajama::c_backend::emit_c;
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Parser};
+use pajama::semantic_analyzer::SemanticAnalyzer;
+
+use indoc::indoc;
+
+fn emit(input: &str) -> Result<String, String> {
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    emit_c(&result)
+}
+
+#[test]
+fn classes_and_methods_emit_as_structs_and_functions() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Dog
+          @legs Int
+
+          def walk(steps Int) -> Int
+            ret steps * 2
+          end
+        end
+
+        def run(legs Int)
+          dog = Dog.new(legs)
+          steps = dog.walk(legs)
+          print_int(steps + legs)
+        end
+
+        def main
+          run(4)
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "struct Dog {\n    int64_t legs;\n};",
+        "void print_int(int64_t int_);",
+        "Dog *Dog_new(Dog *self, int64_t legs) {\n    self->legs = legs;\n    return self;\n}",
+        "    Dog *dog = Dog_new(&(Dog){0}, legs);",
+        "    int64_t steps = Dog_walk(dog, legs);",
+        "int main(void) {\n    run(4);\n}",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn ifs_emit_as_statements_and_conditional_expressions() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def sign(n Int) -> Int
+          if n & 1
+            print_int(1)
+          elsif n
+            print_int(2)
+          else
+            print_int(0)
+          end
+
+          half = if n & 1
+            n - 1
+          else
+            n
+          end
+
+          ret half / 2
+        end
+
+        def main
+          sign(7)
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "    if (n & 1) {\n        print_int(1);\n    } else {\n        if (n) {\n            print_int(2);\n        } else {\n            print_int(0);\n        }\n    }",
+        "    int64_t half = (n & 1 ? n - 1 : n);",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn while_loops_emit_with_break_and_continue() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          n = 10
+
+          while n
+            n = n - 1
+
+            if n & 1
+              next
+            end
+
+            if n - 2
+              print_int(n)
+            else
+              break
+            end
+          end
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    let line = "    while (n) {\n        n = n - 1;\n        if (n & 1) {\n            continue;\n        }\n        if (n - 2) {\n            print_int(n);\n        } else {\n            break;\n        }\n    }";
+
+    assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+}
+
+#[test]
+fn comparisons_and_logical_operators_emit_as_bools() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          n = 3
+          small = n <= 5 && n != 4
+          done = false
+
+          while small == true || done
+            print_int(n)
+            small = false
+          end
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "    bool small = (n <= 5) && (n != 4);",
+        "    bool done = false;",
+        "    while ((small == true) || done) {",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn new_runs_init_when_the_class_defines_one() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Counter
+          @count Int
+          @step  Int
+
+          def init(start Int)
+            self.count = start * 10
+            self.step = 1
+          end
+
+          def tick -> Int
+            self.count = @count + @step
+            ret @count
+          end
+        end
+
+        def main
+          counter = Counter.new(4)
+          print_int(counter.tick())
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "Counter *Counter_new(Counter *self, int64_t start) {\n    Counter_init(self, start);\n    return self;\n}",
+        "    Counter *counter = Counter_new(&(Counter){0}, 4);",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn indexing_sends_the_index_operator_methods() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Pair
+          @left  Int
+          @right Int
+
+          def [](index Int) -> Int
+            if index == 0
+              ret @left
+            end
+
+            ret @right
+          end
+
+          def []=(index Int, value Int)
+            if index == 0
+              self.left = value
+            else
+              self.right = value
+            end
+          end
+        end
+
+        def main
+          pair = Pair.new(1, 2)
+          pair[1] = pair[0] + 40
+          print_int(pair[1])
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "int64_t Pair_op_index(Pair *self, int64_t index) {",
+        "void Pair_op_index_set(Pair *self, int64_t index, int64_t value) {",
+        "    Pair_op_index_set(pair, 1, Pair_op_index(pair, 0) + 40);",
+        "    print_int(Pair_op_index(pair, 1));",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn calling_an_instance_sends_it_call() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Adder
+          @amount Int
+
+          def call(n Int) -> Int
+            ret n + @amount
+          end
+        end
+
+        def apply(adder Adder, n Int) -> Int
+          ret adder(n)
+        end
+
+        def main
+          add_two = Adder.new(2)
+          print_int(add_two(40))
+          print_int(add_two.(1))
+          print_int(apply(add_two, 5))
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "    return Adder_call(adder, n);",
+        "    print_int(Adder_call(add_two, 40));",
+        "    print_int(Adder_call(add_two, 1));",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn floats_mix_with_ints_and_convert_explicitly() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def area(radius Float) -> Float
+          ret 3.14 * radius * radius
+        end
+
+        def main
+          r = 2
+          a = area(r.to_f())
+          half = a / 2
+          print_int(half.to_i())
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    for line in [
+        "double area(double radius);",
+        "    return (3.14 * radius) * radius;",
+        "    double a = area(pj_int_to_f(r));",
+        "    double half = a / 2;",
+        "    print_int(pj_float_to_i(half));",
+    ] {
+        assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+    }
+}
+
+#[test]
+fn args_and_operands_with_side_effects_are_only_emitted_in_order() {
+    let input = indoc! {"
+        def_e read -> Int
+
+        def add(a Int, b Int) -> Int
+          a + b
+        end
+
+        def main
+          n = add(read(), 1) + 2
+        end
+    "};
+
+    assert!(emit(input).unwrap().contains("add(read(), 1) + 2"));
+
+    // C could evaluate either call first
+    let input = indoc! {"
+        def_e read -> Int
+
+        def main
+          n = read() - read()
+        end
+    "};
+
+    assert_eq!(
+        emit(input),
+        Err(
+            "The C backend can't keep the side effects in `-` in order, assign them to locals first"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn locals_first_assigned_in_a_branch_are_declared_before_it() {
+    let input = indoc! {"
+        def sign(n Int) -> Int
+          if n < 0
+            s = 0 - 1
+          else
+            s = 1
+          end
+          s
+        end
+    "};
+
+    let c = emit(input).unwrap();
+
+    let line = "    int64_t s;\n    if (n < 0) {\n        s = 0 - 1;\n    } else {\n        s = 1;\n    }\n    return s;";
+
+    assert!(c.contains(line), "Expected {:?} in:\n{}", line, c);
+}
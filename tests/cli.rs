@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use pajama::allocator::Allocator;
+use pajama::cli::{parse_args, Emit};
+use pajama::columns::TabWidth;
+use pajama::graph::GraphFormat;
+use pajama::metrics::MetricsFormat;
+use pajama::optimization::OptLevel;
+use pajama::runtime_profile::RuntimeProfile;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn files_options_and_emit_targets_are_parsed() {
+    let cli_args = parse_args(&args(&[
+        "main.pjs",
+        "--emit=c",
+        "-o",
+        "out.c",
+        "lib.pjs",
+        "--verbose",
+        "--max-time=5s",
+        "--strict",
+        "--freeze-literals",
+    ]))
+    .unwrap();
+
+    assert_eq!(cli_args.paths, vec!["main.pjs", "lib.pjs"]);
+    assert_eq!(cli_args.emit, Emit::C);
+    assert_eq!(cli_args.output, Some("out.c".to_string()));
+    assert!(cli_args.verbose);
+    assert!(cli_args.strict);
+    assert!(cli_args.freeze_literals);
+    assert_eq!(cli_args.limits.max_time, Some(Duration::from_secs(5)));
+
+    assert_eq!(parse_args(&args(&[])), Err("no input files".to_string()));
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--emit=wasm"])),
+        Err("unknown --emit target `wasm`, expected ir, obj, exe, c, js or bytecode".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--backend=libvm.so", "--emit=vm"]))
+            .unwrap()
+            .emit,
+        Emit::Backend("vm".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--emit=bytecode", "-o", "main.nlb"]))
+            .unwrap()
+            .emit,
+        Emit::Bytecode
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--emit=exe", "-o", "main"]))
+            .unwrap()
+            .emit,
+        Emit::Exe
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--emit=obj"])),
+        Err("--emit=obj and --emit=exe need -o <path>".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--fast"])),
+        Err("unknown option `--fast`".to_string())
+    );
+    assert!(parse_args(&args(&["main.pjs", "-o", "out"])).is_err());
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--allocator=bump"]))
+            .unwrap()
+            .allocator,
+        Allocator::Bump
+    );
+    assert!(parse_args(&args(&["main.pjs", "--allocator=jemalloc"])).is_err());
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--stats-json=stats.json"]))
+            .unwrap()
+            .stats_json,
+        Some("stats.json".to_string())
+    );
+    assert_eq!(parse_args(&args(&["main.pjs"])).unwrap().locale, "en");
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--locale=fr"]))
+            .unwrap()
+            .locale,
+        "fr"
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--runtime=minimal"]))
+            .unwrap()
+            .runtime,
+        RuntimeProfile::Minimal
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs"])).unwrap().opt_level,
+        OptLevel::O2
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "-O0"])).unwrap().opt_level,
+        OptLevel::O0
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "-O3"])),
+        Err("unknown optimization level `-O3`, expected -O0, -O1 or -O2".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--profile-heap=heap.folded"]))
+            .unwrap()
+            .profile_heap,
+        Some("heap.folded".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--tab-width=8"]))
+            .unwrap()
+            .tab_width,
+        TabWidth(8)
+    );
+    assert!(parse_args(&args(&["--help"])).unwrap().help);
+}
+
+#[test]
+fn run_is_the_same_as_no_subcommand() {
+    assert_eq!(
+        parse_args(&args(&["run", "main.pjs", "-O1"])),
+        parse_args(&args(&["main.pjs", "-O1"]))
+    );
+    assert_eq!(
+        parse_args(&args(&["run"])),
+        Err("no input files".to_string())
+    );
+}
+
+#[test]
+fn nlb_files_are_run_on_their_own() {
+    assert_eq!(
+        parse_args(&args(&["run", "main.nlb"])).unwrap().paths,
+        vec!["main.nlb"]
+    );
+
+    let error = Err("a .nlb file is already compiled, it can only be run on its own".to_string());
+
+    assert_eq!(parse_args(&args(&["main.nlb", "lib.pjs"])), error);
+    assert_eq!(parse_args(&args(&["main.nlb", "--emit=c"])), error);
+    assert_eq!(parse_args(&args(&["metrics", "main.nlb"])), error);
+}
+
+#[test]
+fn repl_takes_optional_files_and_no_output() {
+    let cli_args = parse_args(&args(&["repl"])).unwrap();
+
+    assert!(cli_args.repl);
+    assert!(cli_args.paths.is_empty());
+
+    let cli_args = parse_args(&args(&["repl", "lib.pjs", "--verbose"])).unwrap();
+
+    assert_eq!(cli_args.paths, vec!["lib.pjs"]);
+    assert!(cli_args.verbose);
+
+    assert!(!parse_args(&args(&["main.pjs"])).unwrap().repl);
+    assert_eq!(
+        parse_args(&args(&["repl", "--emit=js"])),
+        Err("repl runs each input, it can't --emit or -o".to_string())
+    );
+}
+
+#[test]
+fn metrics_takes_files_a_format_and_an_output() {
+    let cli_args = parse_args(&args(&["metrics", "main.pjs"])).unwrap();
+
+    assert!(cli_args.metrics);
+    assert_eq!(cli_args.metrics_format, MetricsFormat::Table);
+
+    let cli_args = parse_args(&args(&[
+        "metrics",
+        "--format=json",
+        "-o",
+        "metrics.json",
+        "main.pjs",
+    ]))
+    .unwrap();
+
+    assert_eq!(cli_args.metrics_format, MetricsFormat::Json);
+    assert_eq!(cli_args.output, Some("metrics.json".to_string()));
+
+    assert_eq!(
+        parse_args(&args(&["metrics", "--format=csv", "main.pjs"])),
+        Err("unknown --format `csv`, expected table or json".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["main.pjs", "--format=json"])),
+        Err("--format is only for metrics and graph".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["metrics", "--emit=c", "main.pjs"])),
+        Err("metrics, graph, explain and profile print a report, they can't --emit".to_string())
+    );
+}
+
+#[test]
+fn graph_defaults_to_dot() {
+    let cli_args = parse_args(&args(&["graph", "main.pjs"])).unwrap();
+
+    assert!(cli_args.graph);
+    assert!(!cli_args.metrics);
+    assert_eq!(cli_args.graph_format, GraphFormat::Dot);
+    assert_eq!(
+        parse_args(&args(&["graph", "--format=json", "main.pjs"]))
+            .unwrap()
+            .graph_format,
+        GraphFormat::Json
+    );
+    assert_eq!(
+        parse_args(&args(&["graph", "--format=table", "main.pjs"])),
+        Err("unknown --format `table`, expected dot or json".to_string())
+    );
+    assert!(
+        parse_args(&args(&["graph", "--imports", "main.pjs"]))
+            .unwrap()
+            .graph_imports
+    );
+    assert_eq!(
+        parse_args(&args(&["metrics", "--imports", "main.pjs"])),
+        Err("--imports is only for graph".to_string())
+    );
+}
+
+#[test]
+fn explain_needs_a_position() {
+    let cli_args = parse_args(&args(&["explain", "main.pjs", "--at", "3:5"])).unwrap();
+
+    assert!(cli_args.explain);
+    assert_eq!(cli_args.explain_at, Some((3, 5)));
+
+    let needs_at =
+        Err("explain needs --at <line>:<column>, and --at is only for explain".to_string());
+
+    assert_eq!(parse_args(&args(&["explain", "main.pjs"])), needs_at);
+    assert_eq!(parse_args(&args(&["main.pjs", "--at", "3:5"])), needs_at);
+    assert_eq!(
+        parse_args(&args(&["explain", "main.pjs", "--at", "3"])),
+        Err("invalid position `3`, expected <line>:<column>, e.g. 3:5".to_string())
+    );
+}
+
+#[test]
+fn explain_takes_an_error_code_instead() {
+    let cli_args = parse_args(&args(&["explain", "E0001"])).unwrap();
+
+    assert!(cli_args.explain);
+    assert_eq!(cli_args.explain_code, Some("E0001".to_string()));
+    assert_eq!(cli_args.paths, Vec::<String>::new());
+    assert_eq!(
+        parse_args(&args(&["explain", "E0001", "main.pjs"])),
+        Err("explain <code> prints the explanation, it takes no files".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["explain", "E01"])),
+        Err("explain needs --at <line>:<column>, and --at is only for explain".to_string())
+    );
+}
+
+#[test]
+fn profile_run_keeps_frame_pointers() {
+    let cli_args = parse_args(&args(&["profile", "run", "main.pjs", "-O1"])).unwrap();
+
+    assert!(cli_args.profile);
+    assert!(cli_args.frame_pointers);
+    assert_eq!(cli_args.opt_level, OptLevel::O1);
+    assert!(!parse_args(&args(&["main.pjs"])).unwrap().frame_pointers);
+    assert!(
+        parse_args(&args(&[
+            "main.pjs",
+            "--emit=exe",
+            "-o",
+            "main",
+            "--frame-pointers"
+        ]))
+        .unwrap()
+        .frame_pointers
+    );
+    assert_eq!(
+        parse_args(&args(&["profile", "run", "main.pjs", "-o", "profile.txt"])),
+        Err("profile prints its summary after the program's output, it can't -o".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["profile", "main.pjs"])),
+        Err("expected `profile run <file>...`".to_string())
+    );
+}
+
+#[test]
+fn dump_operators_takes_no_files() {
+    assert!(
+        parse_args(&args(&["dump-operators"]))
+            .unwrap()
+            .dump_operators
+    );
+    assert_eq!(
+        parse_args(&args(&["dump-operators", "main.pjs"])),
+        Err("dump-operators prints the operator table, it takes no files".to_string())
+    );
+}
+
+#[test]
+fn inspect_binary_takes_one_executable() {
+    let cli_args = parse_args(&args(&["inspect-binary", "hello"])).unwrap();
+
+    assert!(cli_args.inspect_binary);
+    assert_eq!(cli_args.paths, vec!["hello".to_string()]);
+    assert_eq!(
+        parse_args(&args(&["inspect-binary", "hello", "world"])),
+        Err("inspect-binary prints the build info of one executable".to_string())
+    );
+    assert!(
+        parse_args(&args(&[
+            "--emit=exe",
+            "--reproducible",
+            "-o",
+            "hello",
+            "main.pjs"
+        ]))
+        .unwrap()
+        .reproducible
+    );
+}
@@ -0,0 +1,52 @@
+use pajama::columns::{display_column, expand_tabs, parse_tab_width, TabWidth};
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Parser};
+use pajama::source::SourceFile;
+
+#[test]
+fn tabs_and_wide_characters_move_the_display_column() {
+    let tab_width = TabWidth(4);
+
+    assert_eq!(display_column("n = 1", 5, tab_width), 5);
+    assert_eq!(display_column("\tn = 1", 2, tab_width), 5);
+    assert_eq!(display_column("ab\tn", 4, tab_width), 5);
+    assert_eq!(display_column("\tn", 2, TabWidth(8)), 9);
+    assert_eq!(display_column("s = \"日本\" $", 10, tab_width), 12);
+    // A combining acute accent takes no column of its own
+    assert_eq!(display_column("e\u{301} $", 4, tab_width), 3);
+    assert_eq!(display_column("n", 3, tab_width), 3);
+
+    assert_eq!(expand_tabs("\tn =\t1", tab_width), "    n = 1");
+    assert_eq!(parse_tab_width("8"), Ok(TabWidth(8)));
+    assert_eq!(
+        parse_tab_width("0"),
+        Err("invalid --tab-width `0`, expected a number of columns, e.g. 4".to_string())
+    );
+}
+
+#[test]
+fn carets_line_up_under_tabs_and_wide_characters() {
+    let input = "def main\n  s = \"\t日本\" $\nend\n";
+    let files = vec![("main.pjs".to_string(), Lexer::new(input).tokenize())];
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: input.to_string(),
+    }];
+
+    let diagnostics = match Parser::start_parse_files(files, &mut default_op_precedence()) {
+        Err(diagnostics) => diagnostics,
+        Ok(_) => panic!("Expected the stray `$` to be reported"),
+    };
+
+    assert_eq!(
+        diagnostics[0].render(&sources, TabWidth(4)),
+        [
+            "error[E0002]: Unknown expression.",
+            " --> main.pjs:2:13",
+            "  |",
+            "2 |   s = \" 日本\" $",
+            "  |               ^",
+        ]
+        .join("\n")
+    );
+}
@@ -0,0 +1,127 @@
+use pajama::diagnostic::Diagnostic;
+use pajama::expected_diagnostics::{
+    parse_expected_diagnostics, unmatched_diagnostics, ExpectedDiagnostic,
+};
+use pajama::lexer::TokenPosition;
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+/// Compiles each fixture in `tests/compile_fail` and checks it fails with
+/// the errors its `#~ ERROR` comments expect, and no others.
+#[test]
+fn fixtures_fail_with_the_errors_they_expect() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/compile_fail");
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "pjs"))
+        .collect();
+    paths.sort();
+
+    assert!(!paths.is_empty());
+
+    let mut failures = vec![];
+
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let input = std::fs::read_to_string(&path).unwrap();
+        let expected = match parse_expected_diagnostics(&input) {
+            Ok(expected) if expected.is_empty() => {
+                failures.push(format!("{}: expects no errors", name));
+                continue;
+            }
+            Ok(expected) => expected,
+            Err(message) => {
+                failures.push(format!("{}: {}", name, message));
+                continue;
+            }
+        };
+
+        let sources = vec![SourceFile {
+            path: name.clone(),
+            input,
+        }];
+        let diagnostics =
+            match PajamaCompiler::compile_to_ast(&sources, &CompileOptions::default()).unwrap() {
+                Ok(_) => vec![],
+                Err(diagnostics) => diagnostics,
+            };
+
+        for problem in unmatched_diagnostics(&expected, &diagnostics) {
+            failures.push(format!("{}: {}", name, problem));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+#[test]
+fn expectations_are_read_from_comments() {
+    let input = indoc! {"
+        #~ ERROR Expected ')' at 3:12
+        def main
+          n = (1 + 2  #~ ERROR takes no arguments
+        end
+    "};
+
+    assert_eq!(
+        parse_expected_diagnostics(input),
+        Ok(vec![
+            ExpectedDiagnostic {
+                line: 1,
+                message: "Expected ')'".to_string(),
+                position: Some((3, 12)),
+            },
+            ExpectedDiagnostic {
+                line: 3,
+                message: "takes no arguments".to_string(),
+                position: None,
+            },
+        ])
+    );
+
+    assert_eq!(
+        parse_expected_diagnostics("#~ EROR typo\n"),
+        Err("line 1: expected `#~ ERROR <message>`, got `#~ EROR typo`".to_string())
+    );
+}
+
+#[test]
+fn each_error_is_matched_once() {
+    let expected = vec![
+        ExpectedDiagnostic {
+            line: 1,
+            message: "Expected ')'".to_string(),
+            position: Some((3, 12)),
+        },
+        ExpectedDiagnostic {
+            line: 2,
+            message: "takes no arguments".to_string(),
+            position: None,
+        },
+    ];
+    let diagnostics = vec![
+        Diagnostic {
+            message: "Expected ')' character at end of parenthesized expression.".to_string(),
+            path: None,
+            position: Some(TokenPosition {
+                line: 3,
+                start_column: 11,
+                end_column: 11,
+            }),
+        },
+        Diagnostic::new("`now` takes no arguments"),
+        Diagnostic::new("`today` takes no arguments"),
+    ];
+
+    assert_eq!(
+        unmatched_diagnostics(&expected, &diagnostics),
+        vec![
+            "line 1: expected an error containing \"Expected ')'\" at 3:12",
+            "unexpected error: 3:11: Expected ')' character at end of parenthesized expression.",
+            "unexpected error: `today` takes no arguments",
+        ]
+    );
+}
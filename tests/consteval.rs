@@ -0,0 +1,81 @@
+use pajama::consteval::{fold_constant_calls, DEFAULT_FUEL};
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Node, Parser};
+use pajama::semantic_analyzer::SemanticAnalyzer;
+
+use indoc::indoc;
+
+#[test]
+fn calls_with_constant_arguments_are_evaluated() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def kib(n Int) -> Int
+          ret n * 1024
+        end
+
+        def buffer_size(pages Int) -> Int
+          size = kib(pages) + 64
+
+          if size > 8192
+            8192
+          else
+            size
+          end
+        end
+
+        def countdown(n Int) -> Int
+          print_int(n)
+          ret n - 1
+        end
+
+        def main
+          small = buffer_size(4)
+          large = buffer_size(16)
+          left = countdown(3)
+          print_int(small + large + left)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    assert_eq!(fold_constant_calls(&mut result, DEFAULT_FUEL), 2);
+
+    let main = match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .find_map(|node| match node {
+                Node::Def(def) if def.main_fn => Some(def),
+                _ => None,
+            })
+            .unwrap(),
+        _ => panic!("Expected a module"),
+    };
+
+    let assigned: Vec<String> = main.body[..3]
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Int(int) => int.value.to_string(),
+                Node::Call(call) => format!("{}()", call.fn_name),
+                node => panic!("Unexpected node {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    // countdown prints, so it has to run
+    assert_eq!(assigned, vec!["4160", "8192", "countdown()"]);
+
+    // Without the fuel to evaluate them nothing is replaced
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(fold_constant_calls(&mut result, 3), 0);
+}
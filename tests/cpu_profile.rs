@@ -0,0 +1,49 @@
+use pajama::cpu_profile::{format_summary, parse_perf_report, HotFunction};
+
+use indoc::indoc;
+
+#[test]
+fn perf_reports_are_summarized_by_function() {
+    let report = indoc! {"
+        # To display the perf.data header info, please use --header/--header-only options.
+        #
+        # Samples: 4K of event 'cycles'
+        # Event count (approx.): 3571906411
+        #
+        # Overhead  Symbol
+        # ........  .............................
+        #
+            61.52%  [.] Dog.walk
+            20.03%  [.] pajama::pajama_lib::pj_str_concat
+             4.10%  [k] clear_page_erms
+    "};
+
+    let hot_functions = parse_perf_report(report);
+
+    assert_eq!(
+        hot_functions,
+        vec![
+            HotFunction {
+                percent: 61.52,
+                name: "Dog.walk".to_string(),
+            },
+            HotFunction {
+                percent: 20.03,
+                name: "pajama::pajama_lib::pj_str_concat".to_string(),
+            },
+            HotFunction {
+                percent: 4.1,
+                name: "clear_page_erms".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        format_summary(&hot_functions),
+        concat!(
+            "    self  function\n",
+            "  61.52%  Dog.walk\n",
+            "  20.03%  pajama::pajama_lib::pj_str_concat\n",
+            "   4.10%  clear_page_erms\n",
+        )
+    );
+}
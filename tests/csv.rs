@@ -0,0 +1,85 @@
+use std::ffi::c_void;
+
+use pajama::pajama_lib::{
+    pj_array_get_ptr, pj_array_length, pj_array_new, pj_array_push_ptr, pj_csv_parse, pj_csv_write,
+    pjstr_to_str, string_to_pjstr, PjArray, PjStr,
+};
+
+fn pj_str(text: &str) -> &'static PjStr {
+    unsafe { &*string_to_pjstr(text.to_string()) }
+}
+
+fn text(pj_str: *mut PjStr) -> String {
+    pjstr_to_str(unsafe { &*pj_str }).to_string()
+}
+
+fn rows(array: *mut PjArray) -> Vec<Vec<String>> {
+    let array = unsafe { &*array };
+
+    (0..pj_array_length(array))
+        .map(|index| {
+            let row = unsafe { &*(pj_array_get_ptr(array, index) as *mut PjArray) };
+
+            (0..pj_array_length(row))
+                .map(|index| text(pj_array_get_ptr(row, index) as *mut PjStr))
+                .collect()
+        })
+        .collect()
+}
+
+fn rows_array(rows: &[&[&str]]) -> &'static PjArray {
+    let array = unsafe { &mut *pj_array_new(0) };
+
+    for row in rows {
+        let row_array = pj_array_new(0);
+
+        for field in row.iter() {
+            let field = string_to_pjstr(field.to_string()) as *mut c_void;
+            pj_array_push_ptr(unsafe { &mut *row_array }, field);
+        }
+
+        pj_array_push_ptr(array, row_array as *mut c_void);
+    }
+
+    array
+}
+
+#[test]
+fn parse_splits_rows_and_unquotes_fields() {
+    let parsed = pj_csv_parse(pj_str(
+        "name,age\r\n\"Smith, J\",42\n\n\"say \"\"hi\"\"\",\"two\nlines\"\nlast,\n",
+    ));
+
+    assert_eq!(
+        rows(parsed),
+        vec![
+            vec!["name", "age"],
+            vec!["Smith, J", "42"],
+            vec!["say \"hi\"", "two\nlines"],
+            vec!["last", ""],
+        ]
+    );
+    assert_eq!(rows(pj_csv_parse(pj_str(""))), Vec::<Vec<String>>::new());
+}
+
+#[test]
+fn write_quotes_only_the_fields_that_need_it() {
+    let written = pj_csv_write(rows_array(&[
+        &["name", "note"],
+        &["Smith, J", "say \"hi\""],
+        &["two\nlines", ""],
+    ]));
+
+    assert_eq!(
+        text(written),
+        "name,note\n\"Smith, J\",\"say \"\"hi\"\"\"\n\"two\nlines\",\n"
+    );
+    assert_eq!(
+        rows(pj_csv_parse(unsafe { &*written })),
+        vec![
+            vec!["name", "note"],
+            vec!["Smith, J", "say \"hi\""],
+            vec!["two\nlines", ""],
+        ]
+    );
+}
@@ -0,0 +1,127 @@
+use pajama::pajama_lib::{
+    parse_frozen_time, pj_date_add_days, pj_date_days_until, pj_date_format, pj_date_iso8601,
+    pj_date_parse, pj_date_weekday, pj_datetime_add_days, pj_datetime_add_seconds,
+    pj_datetime_advance_clock, pj_datetime_day, pj_datetime_format, pj_datetime_freeze_clock,
+    pj_datetime_from_unix, pj_datetime_hour, pj_datetime_iso8601, pj_datetime_now_utc,
+    pj_datetime_parse, pj_datetime_seconds_until, pj_datetime_to_date, pj_datetime_to_unix,
+    pj_datetime_to_utc, pj_datetime_unfreeze_clock, pj_datetime_year, pjstr_to_str,
+    string_to_pjstr, PjDate, PjDateTime, PjStr,
+};
+
+fn pj_str(text: &str) -> &'static PjStr {
+    unsafe { &*string_to_pjstr(text.to_string()) }
+}
+
+fn text(pj_str: *mut PjStr) -> &'static str {
+    pjstr_to_str(unsafe { &*pj_str })
+}
+
+fn datetime(text: &str) -> &'static PjDateTime {
+    unsafe { &*pj_datetime_parse(pj_str(text)) }
+}
+
+fn date(text: &str) -> &'static PjDate {
+    unsafe { &*pj_date_parse(pj_str(text)) }
+}
+
+#[test]
+fn datetimes_keep_the_offset_they_were_parsed_with() {
+    let leap_night = datetime("2024-02-28T23:30:00+02:00");
+
+    assert_eq!(pj_datetime_year(leap_night), 2024);
+    assert_eq!(pj_datetime_day(leap_night), 28);
+    assert_eq!(pj_datetime_hour(leap_night), 23);
+    assert_eq!(pj_datetime_to_unix(leap_night), 1_709_155_800);
+    assert_eq!(
+        text(pj_datetime_iso8601(leap_night)),
+        "2024-02-28T23:30:00+02:00"
+    );
+
+    let utc = unsafe { &*pj_datetime_to_utc(leap_night) };
+
+    assert_eq!(pj_datetime_hour(utc), 21);
+    assert_eq!(text(pj_datetime_iso8601(utc)), "2024-02-28T21:30:00Z");
+    assert_eq!(pj_datetime_to_unix(utc), pj_datetime_to_unix(leap_night));
+}
+
+#[test]
+fn datetime_arithmetic_crosses_months_and_leap_days() {
+    let leap_night = datetime("2024-02-28T23:30:00+02:00");
+    let next_day = unsafe { &*pj_datetime_add_days(leap_night, 1) };
+
+    assert_eq!(
+        text(pj_datetime_iso8601(next_day)),
+        "2024-02-29T23:30:00+02:00"
+    );
+    assert_eq!(pj_datetime_seconds_until(leap_night, next_day), 86_400);
+    assert_eq!(pj_datetime_seconds_until(next_day, leap_night), -86_400);
+
+    let march = unsafe { &*pj_datetime_add_seconds(next_day, 1_800) };
+
+    assert_eq!(
+        text(pj_datetime_format(march, pj_str("%Y/%m/%d %H:%M %z"))),
+        "2024/03/01 00:00 +0200"
+    );
+
+    let epoch = unsafe { &*pj_datetime_from_unix(0) };
+
+    assert_eq!(text(pj_datetime_iso8601(epoch)), "1970-01-01T00:00:00Z");
+}
+
+#[test]
+fn dates_count_whole_days() {
+    let epoch = date("1970-01-01");
+    let leap_day = unsafe { &*pj_datetime_to_date(datetime("2024-02-29T23:30:00+02:00")) };
+
+    assert_eq!(text(pj_date_iso8601(leap_day)), "2024-02-29");
+    assert_eq!(pj_date_days_until(epoch, leap_day), 19_782);
+    assert_eq!(pj_date_weekday(leap_day), 4);
+    assert_eq!(
+        text(pj_date_iso8601(unsafe { &*pj_date_add_days(epoch, -1) })),
+        "1969-12-31"
+    );
+    assert_eq!(
+        text(pj_date_format(leap_day, pj_str("%A %-d %B"))),
+        "Thursday 29 February"
+    );
+}
+
+#[test]
+fn the_clock_stays_where_it_is_frozen() {
+    pj_datetime_freeze_clock(datetime("2024-02-29T12:00:00+02:00"));
+
+    let now = unsafe { &*pj_datetime_now_utc() };
+
+    assert_eq!(text(pj_datetime_iso8601(now)), "2024-02-29T10:00:00Z");
+
+    pj_datetime_advance_clock(90);
+
+    let later = unsafe { &*pj_datetime_now_utc() };
+
+    assert_eq!(text(pj_datetime_iso8601(later)), "2024-02-29T10:01:30Z");
+
+    pj_datetime_unfreeze_clock();
+
+    let running = unsafe { &*pj_datetime_now_utc() };
+
+    assert!(pj_datetime_year(running) > 2024);
+}
+
+#[test]
+fn frozen_time_is_parsed_from_rfc3339() {
+    assert_eq!(parse_frozen_time(None), Ok(None));
+    assert_eq!(parse_frozen_time(Some("")), Ok(None));
+    assert_eq!(
+        parse_frozen_time(Some("2024-01-01T01:00:00+01:00"))
+            .unwrap()
+            .map(|time| time.timestamp()),
+        Some(1_704_067_200)
+    );
+    assert_eq!(
+        parse_frozen_time(Some("tomorrow")),
+        Err(
+            "PAJAMA_FROZEN_TIME is `tomorrow`, expected a time like 2024-01-01T00:00:00Z"
+                .to_string()
+        )
+    );
+}
@@ -0,0 +1,81 @@
+use pajama::dead_code::eliminate_dead_methods;
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Node, Parser};
+use pajama::semantic_analyzer::SemanticAnalyzer;
+
+use indoc::indoc;
+
+#[test]
+fn defs_main_never_reaches_are_removed() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+        def_e print_bytes(bytes BytePtr, length Int)
+
+        class Animal
+          @legs Int
+
+          def walk(steps Int) -> Int
+            ret steps * 2
+          end
+
+          def sleep(hours Int) -> Int
+            ret hours * 3
+          end
+        end
+
+        class Dog < Animal
+          @tail Int
+        end
+
+        def run(legs Int)
+          dog = Dog.new(legs, 1)
+          steps = dog.walk(legs)
+          print_int(steps)
+        end
+
+        def unused(n Int)
+          print_int(n)
+        end
+
+        def main
+          run(4)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let removed = eliminate_dead_methods(&mut result);
+
+    assert_eq!(
+        removed,
+        vec![
+            "Animal.alloca",
+            "Animal.new",
+            "Animal.sleep",
+            "Dog.alloca",
+            "unused"
+        ]
+    );
+    assert!(!result.index.fn_prototype_index.contains_key("unused"));
+
+    let kept: Vec<&str> = match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::Def(def) => Some(def.prototype.name.as_str()),
+                Node::DefE(def_e) => Some(def_e.prototype.name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        _ => panic!("Expected a module"),
+    };
+
+    for name in ["print_bytes", "Animal.walk", "Dog.new", "run", "main"] {
+        assert!(kept.contains(&name), "Expected {} in {:?}", name, kept);
+    }
+}
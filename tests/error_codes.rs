@@ -0,0 +1,102 @@
+use pajama::columns::TabWidth;
+use pajama::diagnostic::Diagnostic;
+use pajama::error_codes::{
+    error_code_for, find_error_code, is_error_code, match_message, ERROR_CODES,
+};
+
+#[test]
+fn codes_are_numbered_in_order() {
+    for (index, error_code) in ERROR_CODES.iter().enumerate() {
+        assert_eq!(error_code.code, format!("E{:04}", index + 1));
+        assert!(is_error_code(error_code.code));
+        assert!(
+            error_code.explanation.ends_with('\n'),
+            "{}",
+            error_code.code
+        );
+    }
+
+    assert!(!is_error_code("main.pjs"));
+    assert!(!is_error_code("E12345"));
+}
+
+/// A code whose message the compiler no longer reports would never be
+/// shown, so the text around each `{}` has to still be in the source.
+#[test]
+fn each_message_is_reported_somewhere() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+    let source: String = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap() != "error_codes.rs")
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect();
+
+    for error_code in ERROR_CODES {
+        for literal in error_code.message.split("{}") {
+            assert!(
+                source.contains(literal),
+                "{}: no diagnostic reports {:?}",
+                error_code.code,
+                error_code.message
+            );
+        }
+    }
+}
+
+#[test]
+fn diagnostics_are_rendered_with_their_code() {
+    let diagnostic = Diagnostic::new("`n` might be read before it's assigned in `main`");
+
+    assert_eq!(diagnostic.code(), Some("E0012"));
+    assert_eq!(
+        diagnostic.render(&[], TabWidth::default()),
+        "error[E0012]: `n` might be read before it's assigned in `main`"
+    );
+    assert_eq!(
+        error_code_for("`Dog` is already defined in main.pjs"),
+        find_error_code("E0011")
+    );
+
+    let diagnostic = Diagnostic::new("Something else went wrong");
+
+    assert_eq!(diagnostic.code(), None);
+    assert_eq!(
+        diagnostic.render(&[], TabWidth::default()),
+        "error: Something else went wrong"
+    );
+    assert_eq!(find_error_code("E9999"), None);
+}
+
+#[test]
+fn messages_are_matched_with_the_parts_in_their_placeholders() {
+    assert_eq!(
+        match_message(
+            "`{}` is declared as {}, but only classes can be optional",
+            "`@age` is declared as Int?, but only classes can be optional in `Dog`"
+        ),
+        Some(("", vec!["@age", "Int?"], " in `Dog`"))
+    );
+    assert_eq!(
+        match_message(
+            "{} requires the {} class to be defined",
+            "Signal.trap requires the Str class to be defined"
+        ),
+        Some(("", vec!["Signal.trap", "Str"], ""))
+    );
+    assert_eq!(
+        match_message(
+            "`{}` is already defined in {}",
+            "`Dog` is already defined in a.pjs"
+        ),
+        Some(("", vec!["Dog", "a.pjs"], ""))
+    );
+    assert_eq!(
+        match_message("`{}` can only be used in a loop", "Unknown expression."),
+        None
+    );
+    assert_eq!(
+        error_code_for("Path functions require the Str class to be defined"),
+        find_error_code("E0023")
+    );
+}
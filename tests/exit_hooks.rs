@@ -0,0 +1,24 @@
+use std::sync::Mutex;
+
+use pajama::pajama_lib::{pj_at_exit, run_exit_handlers};
+
+static RAN: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+extern "C" fn first() {
+    RAN.lock().unwrap().push(1);
+}
+
+extern "C" fn second() {
+    RAN.lock().unwrap().push(2);
+}
+
+#[test]
+fn exit_handlers_run_once_latest_first() {
+    pj_at_exit(first);
+    pj_at_exit(second);
+
+    run_exit_handlers();
+    run_exit_handlers();
+
+    assert_eq!(*RAN.lock().unwrap(), vec![2, 1]);
+}
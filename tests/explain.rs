@@ -0,0 +1,88 @@
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+fn explain_at(line: usize, column: usize) -> String {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            def_e print_int(int Int)
+
+            class Dog
+              @legs Int
+
+              def walk(steps Int) -> Int
+                total = steps * @legs
+                total
+              end
+            end
+
+            def main
+              dog = Dog.new(4)
+              print_int(dog.walk(2))
+            end
+        "}
+        .to_string(),
+    }];
+
+    match PajamaCompiler::compile_to_explanation(
+        &sources,
+        &CompileOptions::default(),
+        "main.pjs",
+        line,
+        column,
+    )
+    .unwrap()
+    .unwrap()
+    {
+        Some(explanation) => explanation.to_string(),
+        None => "nothing".to_string(),
+    }
+}
+
+#[test]
+fn names_are_explained_with_their_type_and_what_they_are_in() {
+    assert_eq!(
+        explain_at(6, 7),
+        indoc! {"
+            def `Dog.walk`: Int
+              in class `Dog`
+              in file `main.pjs`
+        "}
+    );
+    assert_eq!(
+        explain_at(7, 13),
+        indoc! {"
+            argument `steps`: Int
+              in def `Dog.walk`
+              in class `Dog`
+              in file `main.pjs`
+        "}
+    );
+    assert_eq!(
+        explain_at(7, 5),
+        indoc! {"
+            local `total`: Int
+              in def `Dog.walk`
+              in class `Dog`
+              in file `main.pjs`
+        "}
+    );
+    assert!(explain_at(7, 21).starts_with("attribute `@legs`: Int\n"));
+    assert!(explain_at(13, 3).starts_with("local `dog`: Dog\n"));
+    assert!(explain_at(13, 9).starts_with("class `Dog`\n"));
+    assert!(explain_at(13, 17).starts_with("literal `4`: Int\n"));
+    assert!(explain_at(14, 3).starts_with("call to `print_int`: nothing\n"));
+    assert_eq!(
+        explain_at(14, 17),
+        indoc! {"
+            send of `Dog.walk`: Int
+              in def `main`
+              in file `main.pjs`
+        "}
+    );
+
+    // The `def` keyword
+    assert_eq!(explain_at(12, 1), "nothing");
+}
@@ -0,0 +1,158 @@
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Parser};
+
+/// A program from a `tests/grammar` file: one that must parse, or one that
+/// must fail to with an error containing `error`.
+struct Snippet {
+    line: usize,
+    error: Option<String>,
+    input: String,
+}
+
+/// The lowercase productions of `grammar.ebnf`, the ones made of other
+/// productions. Uppercase ones are tokens, which the snippets cover along
+/// with everything else.
+fn productions() -> Vec<String> {
+    let grammar =
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/grammar.ebnf")).unwrap();
+
+    grammar
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, _)| name.trim_end())
+        .filter(|name| {
+            !name.is_empty() && name.chars().all(|ch| ch.is_ascii_lowercase() || ch == '_')
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Splits a file into snippets, each starting at a `#= pass` or
+/// `#= fail <error>` line and running until the next one.
+fn snippets(input: &str) -> Result<Vec<Snippet>, String> {
+    let mut snippets: Vec<Snippet> = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        let header = match line.strip_prefix("#=") {
+            Some(header) => header.trim(),
+            None => {
+                match snippets.last_mut() {
+                    Some(snippet) => {
+                        snippet.input.push_str(line);
+                        snippet.input.push('\n');
+                    }
+                    None if line.trim().is_empty() || line.starts_with('#') => {}
+                    None => return Err(format!("line {}: code before `#= pass`", index + 1)),
+                }
+                continue;
+            }
+        };
+
+        let error = match header.split_once(' ') {
+            None if header == "pass" => None,
+            Some(("fail", error)) => Some(error.trim().to_string()),
+            _ => {
+                return Err(format!(
+                    "line {}: expected `#= pass` or `#= fail <error>`, got `{}`",
+                    index + 1,
+                    line
+                ))
+            }
+        };
+
+        snippets.push(Snippet {
+            line: index + 1,
+            error,
+            input: String::new(),
+        });
+    }
+
+    Ok(snippets)
+}
+
+/// Parses `input` on its own, returning the messages of its errors.
+fn parse_errors(input: &str) -> Vec<String> {
+    let tokens = Lexer::new(input).tokenize();
+
+    match Parser::start_parse_files(
+        vec![("snippet.pjs".to_string(), tokens)],
+        &mut default_op_precedence(),
+    ) {
+        Ok(_) => vec![],
+        Err(diagnostics) => diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect(),
+    }
+}
+
+/// Checks the parser against the snippets of each production in
+/// `grammar.ebnf`, and that each production has a file of snippets, with
+/// at least one that parses and one that doesn't.
+#[test]
+fn the_parser_conforms_to_the_grammar() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/grammar");
+    let productions = productions();
+    let mut failures = vec![];
+
+    assert!(!productions.is_empty());
+
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+
+        if !productions.contains(&name) {
+            failures.push(format!(
+                "{}: no production named {} in grammar.ebnf",
+                path.file_name().unwrap().to_string_lossy(),
+                name
+            ));
+        }
+    }
+
+    for production in &productions {
+        let name = format!("{}.pjs", production);
+        let input = match std::fs::read_to_string(format!("{}/{}", dir, name)) {
+            Ok(input) => input,
+            Err(_) => {
+                failures.push(format!("{}: no snippets for the production", name));
+                continue;
+            }
+        };
+
+        let snippets = match snippets(&input) {
+            Ok(snippets) => snippets,
+            Err(message) => {
+                failures.push(format!("{}: {}", name, message));
+                continue;
+            }
+        };
+
+        if !snippets.iter().any(|snippet| snippet.error.is_none()) {
+            failures.push(format!("{}: no `#= pass` snippet", name));
+        }
+
+        if !snippets.iter().any(|snippet| snippet.error.is_some()) {
+            failures.push(format!("{}: no `#= fail` snippet", name));
+        }
+
+        for snippet in snippets {
+            let errors = parse_errors(&snippet.input);
+
+            match snippet.error {
+                None if !errors.is_empty() => failures.push(format!(
+                    "{}:{}: expected to parse, got {:?}",
+                    name, snippet.line, errors
+                )),
+                Some(error) if !errors.iter().any(|message| message.contains(&error)) => failures
+                    .push(format!(
+                        "{}:{}: expected an error containing {:?}, got {:?}",
+                        name, snippet.line, error, errors
+                    )),
+                _ => {}
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
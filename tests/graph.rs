@@ -0,0 +1,119 @@
+use pajama::graph::{format_graph, format_import_graph, GraphFormat, ImportGraph};
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+#[test]
+fn calls_superclasses_and_traits_are_graphed() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            def_e print_int(int Int)
+
+            trait Shape
+              def area() -> Int
+            end
+
+            class Square
+              @size Int
+
+              impl Shape
+                def area() -> Int
+                  @size * @size
+                end
+              end
+            end
+
+            class Tile < Square
+              @color Int
+            end
+
+            def report(square Square)
+              print_int(square.area())
+            end
+
+            def unused
+              report(Square.new(1))
+            end
+
+            def main
+              report(Tile.new(2, 3))
+            end
+        "}
+        .to_string(),
+    }];
+
+    let graph = PajamaCompiler::compile_to_graph(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap();
+
+    // Only `Square` implements `Shape` itself, `Tile` inherits it
+    assert_eq!(
+        format_graph(&graph, &GraphFormat::Dot),
+        indoc! {r#"
+            digraph program {
+              "report" -> "Square.area";
+              "unused" -> "report";
+              "unused" -> "Square.new";
+              "main" -> "report";
+              "main" -> "Tile.new";
+              "Square" [shape=box];
+              "Square" -> "Shape" [arrowhead=empty, style=dashed];
+              "Tile" [shape=box];
+              "Tile" -> "Square" [arrowhead=empty];
+            }
+        "#}
+    );
+    assert_eq!(
+        format_graph(&graph, &GraphFormat::Json),
+        indoc! {r#"
+            {
+              "calls": [
+                {"caller": "report", "callee": "Square.area"},
+                {"caller": "unused", "callee": "report"},
+                {"caller": "unused", "callee": "Square.new"},
+                {"caller": "main", "callee": "report"},
+                {"caller": "main", "callee": "Tile.new"}
+              ],
+              "classes": [
+                {"name": "Square", "superclass": null, "traits": ["Shape"]},
+                {"name": "Tile", "superclass": "Square", "traits": []}
+              ]
+            }
+        "#}
+    );
+}
+
+#[test]
+fn imports_are_graphed() {
+    let graph = ImportGraph {
+        files: vec!["lib/strings.pjs".to_string(), "main.pjs".to_string()],
+        imports: vec![("main.pjs".to_string(), "lib/strings.pjs".to_string())],
+    };
+
+    assert_eq!(
+        format_import_graph(&graph, &GraphFormat::Dot),
+        indoc! {r#"
+            digraph imports {
+              "lib/strings.pjs" [shape=note];
+              "main.pjs" [shape=note];
+              "main.pjs" -> "lib/strings.pjs";
+            }
+        "#}
+    );
+    assert_eq!(
+        format_import_graph(&graph, &GraphFormat::Json),
+        indoc! {r#"
+            {
+              "files": [
+                "lib/strings.pjs",
+                "main.pjs"
+              ],
+              "imports": [
+                {"importer": "main.pjs", "imported": "lib/strings.pjs"}
+              ]
+            }
+        "#}
+    );
+}
@@ -0,0 +1,45 @@
+use pajama::heap_profile::{self, SiteStats};
+use pajama::pajama_lib::{pj_int_to_s, pj_str_concat};
+
+#[test]
+fn allocations_are_counted_by_the_runtime_function_making_them() {
+    // Not recorded, profiling hasn't started
+    pj_int_to_s(7);
+
+    heap_profile::start();
+
+    let left = unsafe { &*pj_int_to_s(12345) };
+    let right = unsafe { &*pj_int_to_s(67890) };
+    pj_str_concat(left, right);
+
+    let sites = heap_profile::finish();
+
+    // Each Str is its buffer and a 24 byte `PjStr`
+    assert_eq!(
+        sites,
+        vec![
+            (
+                "pj_int_to_s".to_string(),
+                SiteStats {
+                    allocations: 4,
+                    bytes: 58,
+                }
+            ),
+            (
+                "pj_str_concat".to_string(),
+                SiteStats {
+                    allocations: 2,
+                    bytes: 34,
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        heap_profile::to_folded(&sites),
+        "pj_int_to_s 58\npj_str_concat 34\n"
+    );
+
+    pj_int_to_s(7);
+
+    assert!(heap_profile::finish().is_empty());
+}
@@ -0,0 +1,192 @@
+use pajama::js_backend::emit_js;
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Parser};
+use pajama::semantic_analyzer::SemanticAnalyzer;
+
+use indoc::indoc;
+
+fn emit(input: &str) -> Result<String, String> {
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    emit_js(&result)
+}
+
+#[test]
+fn classes_emit_as_js_classes_with_bigint_arithmetic() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Animal
+          @legs Int
+
+          def describe(width Int)
+            print_int(@legs + width)
+          end
+        end
+
+        class Dog < Animal
+        end
+
+        def adopt(legs Int)
+          dog = Dog.new(legs)
+          dog.describe(legs & ~1)
+        end
+
+        def main
+          adopt(4)
+        end
+    "};
+
+    let js = emit(input).unwrap();
+
+    for line in [
+        "function print_int(int_) {",
+        "class Animal {\n  constructor(legs) {\n    this.legs = legs;\n  }\n\n  describe(width) {",
+        "class Dog extends Animal {\n  constructor(legs) {\n    super();\n    this.legs = legs;\n  }\n}",
+        "  let dog = new Dog(legs);\n  dog.describe(legs & (1n ^ -1n));",
+        "  adopt(4n);",
+        "\nmain();\n",
+    ] {
+        assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+    }
+}
+
+#[test]
+fn early_and_implicit_returns_emit_js_returns() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def first_square_over(limit Int) -> Int
+          n = 0
+          while n < 100
+            if n * n > limit
+              return n
+            end
+            n = n + 1
+          end
+          0
+        end
+
+        def sign(n Int) -> Int
+          if n < 0
+            0 - 1
+          else
+            1
+          end
+        end
+
+        def main
+          print_int(first_square_over(50))
+          print_int(sign(0 - 5))
+        end
+    "};
+
+    let js = emit(input).unwrap();
+
+    for line in [
+        "    if ((n * n) > limit) {\n      return n;\n    }\n    n = n + 1n;\n  }\n  return 0n;\n}",
+        "  if (n < 0n) {\n    return 0n - 1n;\n  } else {\n    return 1n;\n  }\n}",
+    ] {
+        assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+    }
+}
+
+#[test]
+fn at_exit_blocks_run_when_the_process_exits() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          at_exit do
+            print_int(2)
+          end
+          print_int(1)
+        end
+    "};
+
+    let js = emit(input).unwrap();
+
+    for line in [
+        "function __at_exit_1() {\n  print_int(2n);\n}",
+        "  pj_at_exit(__at_exit_1);\n  print_int(1n);",
+    ] {
+        assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+    }
+}
+
+#[test]
+fn defers_run_when_main_returns() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          defer print_int(3)
+          defer print_int(2)
+          print_int(1)
+        end
+    "};
+
+    let js = emit(input).unwrap();
+    let line = "  print_int(1n);\n  print_int(2n);\n  print_int(3n);";
+
+    assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+}
+
+#[test]
+fn nil_and_safe_navigation_emit_null_and_optional_chaining() {
+    let input = indoc! {"
+        class Dog
+          @legs Int
+          @friend Dog?
+
+          def friend() -> Dog?
+            @friend
+          end
+        end
+
+        def friend_of_friend(dog Dog) -> Dog?
+          dog.friend()&.friend()
+        end
+
+        def main
+          friend_of_friend(Dog.new(4, nil))
+        end
+    "};
+
+    let js = emit(input).unwrap();
+
+    for line in [
+        "return dog.friend()?.friend();",
+        "friend_of_friend(new Dog(4n, null));",
+    ] {
+        assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+    }
+}
+
+#[test]
+fn locals_first_assigned_in_a_branch_are_declared_before_it() {
+    let input = indoc! {"
+        def sign(n Int) -> Int
+          if n < 0
+            s = 0 - 1
+          else
+            s = 1
+          end
+          s
+        end
+
+        def main
+          n = sign(3)
+        end
+    "};
+
+    let js = emit(input).unwrap();
+
+    let line = "  let s;\n  if (n < 0n) {\n    s = 0n - 1n;\n  } else {\n    s = 1n;\n  }\n  return s;";
+
+    assert!(js.contains(line), "Expected {:?} in:\n{}", line, js);
+}
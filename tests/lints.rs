@@ -0,0 +1,267 @@
+use pajama::ast::visit_nodes;
+use pajama::lints::{ConstantConditions, LintPlugin, NamingConventions, UnclosedResources};
+use pajama::parser::{Node, ParserResult};
+use pajama::queries::Database;
+
+use indoc::indoc;
+
+struct DescriptiveArgs;
+
+impl LintPlugin for DescriptiveArgs {
+    fn name(&self) -> &str {
+        "descriptive-args"
+    }
+
+    fn check(&self, result: &ParserResult) -> Vec<String> {
+        let mut messages = vec![];
+
+        visit_nodes(&result.module, &mut |node| {
+            if let Node::Def(def) = node {
+                for arg in &def.prototype.args {
+                    if arg.name.len() == 1 {
+                        messages.push(format!(
+                            "`{}` in `{}` needs a longer name",
+                            arg.name, def.prototype.name
+                        ));
+                    }
+                }
+            }
+        });
+
+        messages
+    }
+}
+
+struct BannedCalls(&'static str);
+
+impl LintPlugin for BannedCalls {
+    fn name(&self) -> &str {
+        "banned-calls"
+    }
+
+    fn check(&self, result: &ParserResult) -> Vec<String> {
+        let mut messages = vec![];
+
+        visit_nodes(&result.module, &mut |node| {
+            if let Node::Call(call) = node {
+                if call.fn_name == self.0 {
+                    messages.push(format!("`{}` is banned", call.fn_name));
+                }
+            }
+        });
+
+        messages
+    }
+}
+
+#[test]
+fn lints_report_after_semantic_analysis() {
+    let mut db = Database::new();
+    db.set_file_text(
+        "a.pjs",
+        "def legacy(count Int)\nend\n\ndef run_all(n Int)\n  legacy(n)\nend\n",
+    );
+
+    assert!(db.program().errors.is_empty());
+
+    db.add_lint(Box::new(DescriptiveArgs));
+    db.add_lint(Box::new(BannedCalls("legacy")));
+
+    assert_eq!(
+        db.program().errors,
+        vec![
+            "descriptive-args: `n` in `run_all` needs a longer name",
+            "banned-calls: `legacy` is banned"
+        ]
+    );
+}
+
+#[test]
+fn resources_left_open_on_some_path_are_reported() {
+    let mut db = Database::new();
+    db.set_file_text(
+        "a.pjs",
+        indoc! {"
+            def_e open(path Str, flags Int) -> Int
+            def_e close(fd Int) -> Int
+            def_e read_byte(fd Int) -> Int
+            def_e print_int(int Int)
+
+            class File
+              @fd Int
+
+              def close() -> Int
+                close(@fd)
+              end
+            end
+
+            def first_byte(path Str) -> Int
+              fd = open(path, 0)
+              byte = read_byte(fd)
+              if byte < 0
+                return 0
+              end
+              close(fd)
+              byte
+            end
+
+            def deferred_byte(path Str) -> Int
+              fd = open(path, 0)
+              defer close(fd)
+              byte = read_byte(fd)
+              if byte < 0
+                return 0
+              end
+              byte
+            end
+
+            def open_file(path Str) -> File
+              file = File.new(open(path, 0))
+              file
+            end
+
+            def main
+              file = open_file(\"a.txt\")
+              print_int(first_byte(\"a.txt\") + deferred_byte(\"b.txt\"))
+            end
+        "},
+    );
+    db.add_lint(Box::new(UnclosedResources));
+
+    assert_eq!(
+        db.program().errors,
+        vec![
+            "unclosed-resources: `fd` is opened in `first_byte` but not closed on every path, `defer close(fd)` closes it when `first_byte` returns",
+            "unclosed-resources: `file` is opened in `main` but not closed on every path, `defer file.close()` closes it when `main` returns",
+        ]
+    );
+}
+
+#[test]
+fn conditions_known_before_the_program_runs_are_reported() {
+    let mut db = Database::new();
+    db.set_file_text(
+        "a.pjs",
+        indoc! {"
+            def_e print_int(int Int)
+
+            class Counter
+              @count Int
+
+              def stuck() -> Bool
+                @count != @count
+              end
+            end
+
+            def main
+              x = 3
+              ratio = 0.5
+
+              if true
+                print_int(x)
+              end
+
+              if x == x && x > 1
+                print_int(1)
+              end
+
+              if ratio == ratio
+                print_int(2)
+              end
+
+              while 2 < 1
+                print_int(3)
+              end
+
+              i = 0
+              while i < 10
+                print_int(i)
+              end
+
+              j = 0
+              while j < 3
+                print_int(j)
+                j = j + 1
+              end
+
+              while x > 0
+                if x == 2
+                  break
+                end
+              end
+
+              matches = 1 == \"1\"
+            end
+        "},
+    );
+    db.add_lint(Box::new(ConstantConditions));
+
+    assert_eq!(
+        db.program().errors,
+        vec![
+            "`==` can't be applied to Int and Str in `main`",
+            "constant-conditions: `@count != @count` is always false in `Counter.stuck`, because `@count` is compared with itself",
+            "constant-conditions: `true` is always true in `main`, so the `if` always runs its then branch",
+            "constant-conditions: `x == x` is always true in `main`, because `x` is compared with itself",
+            "constant-conditions: `2 < 1` is always false in `main`, so the `while` never runs",
+            "constant-conditions: `i < 10` never changes in the `while` in `main`, which doesn't assign `i`, so it runs forever or not at all",
+            "constant-conditions: `1 == \"1\"` is always false in `main`, because an Int is never equal to a Str",
+        ]
+    );
+}
+
+#[test]
+fn names_are_checked_against_the_conventions_that_are_on() {
+    let input = indoc! {"
+        class Http_server
+          @port Int
+
+          def startServer() -> Int
+            maxConnections = 10
+            maxConnections + @port
+          end
+        end
+
+        def printTotal(itemCount Int)
+          runningTotal = itemCount + 1
+          runningTotal = runningTotal + 1
+        end
+
+        def main
+          server = Http_server.new(80)
+          printTotal(server.startServer())
+        end
+    "};
+
+    let mut db = Database::new();
+    db.set_file_text("a.pjs", input);
+    db.add_lint(Box::new(NamingConventions::default()));
+
+    assert_eq!(
+        db.program().errors,
+        vec![
+            "naming-conventions: class `Http_server` should be CamelCase, like `HttpServer`",
+            "naming-conventions: def `Http_server.startServer` should be snake_case, like `start_server`",
+            "naming-conventions: `maxConnections` in `Http_server.startServer` should be snake_case, like `max_connections`",
+            "naming-conventions: def `printTotal` should be snake_case, like `print_total`",
+            "naming-conventions: `itemCount` in `printTotal` should be snake_case, like `item_count`",
+            "naming-conventions: `runningTotal` in `printTotal` should be snake_case, like `running_total`",
+        ]
+    );
+
+    let mut db = Database::new();
+    db.set_file_text("a.pjs", input);
+    db.add_lint(Box::new(NamingConventions {
+        locals: false,
+        types: false,
+        ..NamingConventions::default()
+    }));
+
+    assert_eq!(
+        db.program().errors,
+        vec![
+            "naming-conventions: def `Http_server.startServer` should be snake_case, like `start_server`",
+            "naming-conventions: def `printTotal` should be snake_case, like `print_total`",
+        ]
+    );
+}
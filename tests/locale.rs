@@ -0,0 +1,65 @@
+use pajama::locale::Catalog;
+
+use indoc::indoc;
+
+#[test]
+fn messages_are_translated_by_code() {
+    let catalog = Catalog::parse(indoc! {"
+        # French
+        E0002 = Expression inconnue.
+        E0011 = {1} définit déjà `{0}`
+    "})
+    .unwrap();
+
+    assert_eq!(
+        catalog.translate("Unknown expression."),
+        "Expression inconnue."
+    );
+    assert_eq!(
+        catalog.translate("`Dog` is already defined in a.pjs"),
+        "a.pjs définit déjà `Dog`"
+    );
+    // Without a translation, or a code, messages stay in English
+    assert_eq!(
+        catalog.translate("`n` might be read before it's assigned in `main`"),
+        "`n` might be read before it's assigned in `main`"
+    );
+    assert_eq!(catalog.translate("Something else"), "Something else");
+}
+
+#[test]
+fn catalogs_are_checked_against_the_codes() {
+    assert_eq!(
+        Catalog::parse("E9999 = Inconnu"),
+        Err("line 1: unknown error code `E9999`".to_string())
+    );
+    assert_eq!(
+        Catalog::parse("\nE0002 = {0} inconnue"),
+        Err("line 2: E0002 has 0 part(s) to place, not {0}".to_string())
+    );
+    assert_eq!(
+        Catalog::parse("E0002 Expression inconnue."),
+        Err("line 1: expected `<code> = <message>`".to_string())
+    );
+}
+
+#[test]
+fn locales_are_loaded_from_the_locale_dir() {
+    assert_eq!(Catalog::load("en"), Ok(Catalog::default()));
+
+    let dir = std::env::temp_dir().join(format!("pajama-locales-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("fr.catalog"), "E0002 = Expression inconnue.\n").unwrap();
+    std::env::set_var("PAJAMA_LOCALE_DIR", &dir);
+
+    let catalog = Catalog::load("fr").unwrap();
+    let missing = Catalog::load("de");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        catalog.translate("Unknown expression."),
+        "Expression inconnue."
+    );
+    assert!(missing.unwrap_err().starts_with("locale `de`: "));
+}
@@ -0,0 +1,36 @@
+use chrono::{FixedOffset, TimeZone};
+
+use pajama::pajama_lib::{log_line, parse_log_level};
+
+#[test]
+fn the_log_level_defaults_to_info() {
+    assert_eq!(parse_log_level(None), Ok(0));
+    assert_eq!(parse_log_level(Some("")), Ok(0));
+    assert_eq!(parse_log_level(Some("warn")), Ok(1));
+    assert_eq!(parse_log_level(Some("ERROR")), Ok(2));
+    assert_eq!(
+        parse_log_level(Some("debug")),
+        Err("PAJAMA_LOG_LEVEL is `debug`, expected info, warn or error".to_string())
+    );
+}
+
+#[test]
+fn lines_start_with_the_time_and_level() {
+    let time = FixedOffset::east_opt(2 * 3600)
+        .unwrap()
+        .with_ymd_and_hms(2024, 5, 1, 9, 30, 0)
+        .unwrap();
+
+    assert_eq!(
+        log_line(1, "disk almost full", time),
+        "2024-05-01T09:30:00.000+02:00 WARN  disk almost full"
+    );
+    assert_eq!(
+        log_line(
+            2,
+            "giving up",
+            time.with_timezone(&FixedOffset::east_opt(0).unwrap())
+        ),
+        "2024-05-01T07:30:00.000Z ERROR giving up"
+    );
+}
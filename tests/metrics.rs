@@ -0,0 +1,77 @@
+use pajama::metrics::{format_metrics, DefMetrics, MetricsFormat};
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+#[test]
+fn defs_are_measured_by_statements_nesting_and_branches() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+        def_e print_int(int Int)
+
+        def double(x Int) -> Int
+          ret x * 2
+        end
+
+        def main() -> Int
+          i = 0
+
+          while i < 10
+            if i > 2 && i < 8
+              print_int(i)
+            else
+              print_int(0)
+            end
+
+            i = i + 1
+          end
+
+          ret 0
+        end
+    "}
+        .to_string(),
+    }];
+
+    // `double` is measured even though `main` never calls it
+    let metrics = PajamaCompiler::compile_to_metrics(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        metrics,
+        vec![
+            DefMetrics {
+                name: "double".to_string(),
+                statements: 1,
+                nesting_depth: 0,
+                complexity: 1,
+            },
+            DefMetrics {
+                name: "main".to_string(),
+                statements: 7,
+                nesting_depth: 2,
+                complexity: 4,
+            },
+        ]
+    );
+
+    assert_eq!(
+        format_metrics(&metrics, &MetricsFormat::Table),
+        indoc! {"
+            def    statements   depth complexity
+            double          1       0          1
+            main            7       2          4
+        "}
+    );
+    assert_eq!(
+        format_metrics(&metrics, &MetricsFormat::Json),
+        indoc! {r#"
+            [
+              {"name": "double", "statements": 1, "nesting_depth": 0, "complexity": 1},
+              {"name": "main", "statements": 7, "nesting_depth": 2, "complexity": 4}
+            ]
+        "#}
+    );
+}
@@ -0,0 +1,33 @@
+use pajama::operators::{format_operators, OPERATORS};
+use pajama::parser::default_op_precedence;
+
+#[test]
+fn the_parser_reads_binary_precedences_from_the_table() {
+    let op_precedence = default_op_precedence();
+
+    for operator in &OPERATORS {
+        assert_eq!(
+            op_precedence.get(operator.symbol),
+            operator.precedence.as_ref()
+        );
+    }
+
+    assert_eq!(op_precedence.len(), OPERATORS.len() - 1);
+}
+
+#[test]
+fn operators_are_dumped_as_json_in_table_order() {
+    let dump = format_operators();
+    let lines: Vec<&str> = dump.lines().collect();
+
+    assert_eq!(lines.len(), OPERATORS.len() + 2);
+    assert_eq!(
+        lines[1],
+        "  {\"symbol\": \"||\", \"precedence\": 5, \"associativity\": \"left\", \"arity\": 2},"
+    );
+    assert_eq!(
+        lines[OPERATORS.len()],
+        "  {\"symbol\": \"~\", \"precedence\": null, \"associativity\": \"right\", \"arity\": 1}"
+    );
+    assert_eq!(dump, format_operators());
+}
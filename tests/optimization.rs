@@ -0,0 +1,54 @@
+use pajama::lexer::Lexer;
+use pajama::optimization::is_small;
+use pajama::parser::{default_op_precedence, Node, Parser};
+
+use indoc::indoc;
+
+#[test]
+fn short_defs_without_loops_are_small() {
+    let input = indoc! {"
+        def double(n Int) -> Int
+          n * 2
+        end
+
+        def count_to(limit Int) -> Int
+          n = 0
+          while n < limit
+            n = n + 1
+          end
+          n
+        end
+
+        def clamp(n Int) -> Int
+          if n < 0
+            loop {
+              break
+            }
+          end
+          n
+        end
+
+        def main
+          double(1)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let result = Parser::start_parse(tokens, &mut default_op_precedence());
+
+    let module = match &result.module {
+        Node::Module(module) => module,
+        _ => panic!("Expected a module"),
+    };
+
+    let small: Vec<_> = module
+        .methods
+        .iter()
+        .filter_map(|node| match node {
+            Node::Def(def) if is_small(def) => Some(def.prototype.name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(small, vec!["double"]);
+}
@@ -31,7 +31,13 @@
 //   }
 // }
 
-use pajama::pajama_compiler::PajamaCompiler;
+use std::time::Duration;
+
+use pajama::diagnostic::Diagnostic;
+use pajama::optimization::OptLevel;
+use pajama::pajama_compiler::{CompileOptions, EvalResult, PajamaCompiler};
+use pajama::resource_limits::ResourceLimits;
+use pajama::source::SourceFile;
 
 use indoc::indoc;
 
@@ -56,3 +62,849 @@ fn int_constant() {
 
     assert_eq!(output, expected_output);
 }
+
+#[test]
+fn if_statement() {
+    let input = "
+        def _mlir_ciface_main
+            a = 1
+            if a
+                a = 2
+            end
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // The scf.if is lowered to a branch on `a != 0` around the then body
+    assert!(output.contains("llvm.icmp \"ne\""));
+    assert!(output.contains("llvm.cond_br"));
+    assert!(!output.contains("scf."));
+}
+
+#[test]
+fn if_value() {
+    let input = "
+        def _mlir_ciface_main
+            a = 1
+            b = if a
+                2
+            else
+                3
+            end
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // Both branches jump to a block taking the value as its argument
+    assert!(output.contains("llvm.cond_br"));
+    assert!(output
+        .lines()
+        .any(|line| line.trim_start().starts_with("^bb") && line.contains(": i64)")));
+    assert!(!output.contains("scf."));
+}
+
+#[test]
+fn overflow_flags() {
+    let input = "
+        def _mlir_ciface_main
+            a = 1
+            b = a.add_overflows(2)
+            c = a.checked_mul(3)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // The flag is the second field of the intrinsic's result
+    assert!(output.contains("llvm.intr.sadd.with.overflow"));
+    assert!(output.contains("llvm.extractvalue"));
+    assert!(!output.contains("llvm.call @pj_add_overflows"));
+    assert!(output.contains("llvm.call @pj_checked_mul"));
+}
+
+#[test]
+fn bitwise_operators() {
+    let input = "
+        def _mlir_ciface_main
+            a = 12
+            b = a & 6
+            c = a | 1
+            d = a ^ 3
+            e = a << 2
+            f = a >> 1
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    assert!(output.contains("llvm.and"));
+    assert!(output.contains("llvm.or"));
+    assert!(output.contains("llvm.xor"));
+    assert!(output.contains("llvm.shl"));
+
+    // `>>` keeps the sign
+    assert!(output.contains("llvm.ashr"));
+}
+
+#[test]
+fn while_loops_with_break_and_next() {
+    let input = "
+        def _mlir_ciface_main
+            a = 10
+            while a
+                a = a - 1
+                b = 3
+                while b
+                    b = b - 1
+                    if b - 1
+                        next
+                    end
+                    break
+                end
+                if a - 5
+                    next
+                end
+            end
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // Each loop goes around again while its condition holds and its `break`
+    // flag isn't set
+    assert!(output.contains("llvm.xor"));
+    assert!(output.contains("llvm.and"));
+    assert!(output.matches("llvm.cond_br").count() >= 2);
+    assert!(!output.contains("scf."));
+
+    // A slot for each local, which the loop bodies store to again, and the
+    // two flags of each loop
+    assert_eq!(output.matches("llvm.alloca").count(), 6);
+}
+
+#[test]
+fn comparisons_and_logical_operators() {
+    let input = "
+        def _mlir_ciface_main
+            a = 1
+            b = a < 2
+            c = a >= 0 && b
+            d = false || a != 1
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    assert!(output.contains("llvm.icmp \"slt\""));
+    assert!(output.contains("llvm.icmp \"sge\""));
+    assert!(output.contains("llvm.icmp \"ne\""));
+
+    // The right operand is only run on one branch, which both jump to a
+    // block taking the Bool as its argument
+    assert!(output
+        .lines()
+        .any(|line| line.trim_start().starts_with("^bb") && line.contains(": i1)")));
+    assert!(!output.contains("scf."));
+}
+
+#[test]
+fn float_arithmetic() {
+    let input = "
+        def _mlir_ciface_main
+            a = 1.5
+            b = a * 2.0
+            c = b + 1
+            d = a < c
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    assert!(output.contains("llvm.fmul"));
+    assert!(output.contains("llvm.fadd"));
+    assert!(output.contains("llvm.fcmp \"olt\""));
+
+    // The Int operand of `b + 1` is converted to a Float
+    assert!(output.contains("llvm.sitofp"));
+}
+
+#[test]
+fn arrays() {
+    let input = "
+        def double(n Int) -> Int
+            ret n * 2
+        end
+
+        def _mlir_ciface_main
+            a = [1, 2, 3]
+            a[0] = 4
+            b = a[1]
+            c = a.map(double.fn_ref())
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    assert!(output.contains("llvm.call @pj_array_new"));
+    assert!(output.contains("llvm.call @pj_array_push_int"));
+    assert!(output.contains("llvm.call @pj_array_set_int"));
+    assert!(output.contains("llvm.call @pj_array_get_int"));
+
+    // `map` loops up to the length, calling `double` with each item
+    assert!(output.contains("llvm.call @pj_array_length"));
+    assert!(output.contains("llvm.call @double"));
+}
+
+#[test]
+fn early_returns() {
+    let input = "
+        def clamp(n Int) -> Int
+            if n > 10
+                return 10
+            end
+            n
+        end
+
+        def _mlir_ciface_main
+            a = clamp(12)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // The early `return` stores its value and sets a flag, the rest of the
+    // body is skipped on it, and the def returns once at the end
+    let returns = output
+        .lines()
+        .filter(|line| line.contains("llvm.return") && line.contains(": i64"))
+        .count();
+
+    assert_eq!(returns, 1);
+    assert!(output.contains("llvm.call @clamp"));
+    assert!(!output.contains("scf."));
+}
+
+#[test]
+fn log_calls() {
+    let input = "
+        class Str
+            @buffer BytePtr
+            @length Int
+            @max_length Int
+        end
+
+        def _mlir_ciface_main
+            Log.info(\"starting\")
+            Log.error(\"stopped\")
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    assert!(output.contains("llvm.call @pj_log_info"));
+    assert!(output.contains("llvm.call @pj_log_error"));
+}
+
+#[test]
+fn at_exit_handlers() {
+    let input = "
+        def flush
+            a = 1
+        end
+
+        def _mlir_ciface_main
+            at_exit(flush.fn_ref())
+            at_exit do
+                b = 2
+            end
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // The block is a def of its own, registered by its address like `flush`
+    assert!(output.contains("llvm.func @__at_exit_1"));
+    assert!(output.contains("llvm.mlir.addressof @flush"));
+    assert!(output.contains("llvm.mlir.addressof @__at_exit_1"));
+    assert_eq!(output.matches("llvm.call @pj_at_exit").count(), 2);
+}
+
+#[test]
+fn small_defs_are_always_inlined_when_optimizing() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            def double(n Int) -> Int
+              ret n * 2
+            end
+
+            def main
+              a = double(1)
+            end
+        "}
+        .to_string(),
+    }];
+    let func_line = |output: &str, name: &str| {
+        let func = format!("llvm.func @{}(", name);
+
+        output
+            .lines()
+            .find(|line| line.contains(&func))
+            .unwrap()
+            .to_string()
+    };
+
+    let output = PajamaCompiler::compile_to_ir(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap();
+
+    assert!(func_line(&output, "double").contains("\"alwaysinline\""));
+    assert!(!func_line(&output, "main").contains("\"alwaysinline\""));
+
+    let options = CompileOptions {
+        opt_level: OptLevel::O0,
+        ..Default::default()
+    };
+    let output = PajamaCompiler::compile_to_ir(&sources, &options)
+        .unwrap()
+        .unwrap();
+
+    assert!(!output.contains("alwaysinline"));
+}
+
+#[test]
+fn frame_pointers_are_kept_in_every_def() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            def double(n Int) -> Int
+              ret n * 2
+            end
+
+            def main
+              a = double(1)
+            end
+        "}
+        .to_string(),
+    }];
+    let func_line = |output: &str, name: &str| {
+        let func = format!("llvm.func @{}(", name);
+
+        output
+            .lines()
+            .find(|line| line.contains(&func))
+            .unwrap()
+            .to_string()
+    };
+
+    let options = CompileOptions {
+        frame_pointers: true,
+        ..Default::default()
+    };
+    let output = PajamaCompiler::compile_to_ir(&sources, &options)
+        .unwrap()
+        .unwrap();
+
+    // Both go in the same passthrough list when a small def is also inlined
+    assert!(func_line(&output, "double")
+        .contains("passthrough = [\"alwaysinline\", [\"frame-pointer\", \"all\"]]"));
+    assert!(func_line(&output, "main").contains("passthrough = [[\"frame-pointer\", \"all\"]]"));
+
+    let output = PajamaCompiler::compile_to_ir(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap();
+
+    assert!(!output.contains("frame-pointer"));
+}
+
+#[test]
+fn nil_and_safe_navigation() {
+    let input = "
+        class Dog
+            @legs Int
+            @friend Dog?
+
+            def friend() -> Dog?
+                @friend
+            end
+        end
+
+        def friend_of_friend(dog Dog) -> Dog?
+            dog.friend()&.friend()
+        end
+
+        def friend_of(dog Dog?) -> Dog?
+            dog&.friend()
+        end
+
+        def _mlir_ciface_main
+            a = friend_of_friend(Dog.new(4, nil))
+            b = a == nil
+            c = friend_of(a)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // nil is a null pointer, and a pointer is nil when its address is 0
+    assert!(output.contains("llvm.mlir.zero"));
+    assert!(output.contains("llvm.ptrtoint"));
+    assert!(output.contains("llvm.icmp \"eq\""));
+
+    // The `&.` send is only called on the branch where its receiver isn't nil
+    assert_eq!(output.matches("llvm.call @Dog.friend").count(), 3);
+
+    // An optional argument is used as the pointer it's given, like an
+    // instance, rather than kept in a slot
+    let friend_of = output
+        .split("llvm.func @friend_of(")
+        .nth(1)
+        .unwrap()
+        .split("llvm.func")
+        .next()
+        .unwrap();
+
+    assert!(!friend_of.contains("llvm.alloca"));
+    assert!(output.contains("llvm.cond_br"));
+    assert!(!output.contains("scf."));
+}
+
+#[test]
+fn locals_assigned_in_every_branch() {
+    let input = "
+        def sign(n Int) -> Int
+            if n < 0
+                s = 0 - 1
+            else
+                s = 1
+            end
+            s
+        end
+
+        def _mlir_ciface_main
+            a = sign(3)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+    let sign = output
+        .split("llvm.func @sign")
+        .nth(1)
+        .unwrap()
+        .split("llvm.func")
+        .next()
+        .unwrap();
+
+    // The slots of `n` and of `s`, which both branches store to and which is
+    // loaded after them
+    assert_eq!(sign.matches("llvm.alloca").count(), 2);
+    assert_eq!(sign.matches("llvm.store").count(), 3);
+    assert!(sign.contains("llvm.load"));
+}
+
+#[test]
+fn inherited_methods_and_super() {
+    let input = "
+        class Animal
+            @legs Int
+
+            def legs() -> Int
+                @legs
+            end
+
+            def name() -> Int
+                0
+            end
+        end
+
+        class Dog < Animal
+            @tail Int
+
+            def legs() -> Int
+                super
+            end
+        end
+
+        def _mlir_ciface_main
+            dog = Dog.new(4, 1)
+            a = dog.legs()
+            b = dog.name()
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // `super` calls the superclass's method, and a method Dog doesn't define
+    // is the one it inherits
+    assert!(output.contains("llvm.call @Dog.legs"));
+    assert!(output.contains("llvm.call @Animal.legs"));
+    assert!(output.contains("llvm.call @Animal.name"));
+    assert!(!output.contains("@Dog.name"));
+}
+
+#[test]
+fn puts_calls_to_s() {
+    let input = "
+        class Str
+            @buffer BytePtr
+            @length Int
+            @max_length Int
+        end
+
+        class Dog
+            @legs Int
+        end
+
+        class Cat
+            @lives Int
+
+            def to_s() -> Str
+                \"cat\"
+            end
+        end
+
+        def _mlir_ciface_main
+            dog = Dog.new(4)
+            cat = Cat.new(9)
+            puts(dog)
+            puts(cat)
+            puts(7)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // Dog gets a generated `to_s`, Cat keeps its own
+    assert!(output.contains("llvm.func @Dog.to_s("));
+    assert!(output.contains("llvm.call @Dog.to_s"));
+    assert!(output.contains("llvm.call @Cat.to_s"));
+    assert!(output.contains("llvm.call @pj_int_to_s"));
+    assert_eq!(output.matches("llvm.call @pj_puts").count(), 3);
+}
+
+#[test]
+fn operator_methods() {
+    let input = "
+        class Vec
+            @x Int
+
+            def +(other Vec) -> Int
+                @x
+            end
+
+            def [](index Int) -> Int
+                index
+            end
+
+            impl Comparable
+                def <=>(other Vec) -> Int
+                    @x
+                end
+            end
+        end
+
+        def _mlir_ciface_main
+            a = Vec.new(1)
+            b = Vec.new(2)
+            c = a <=> b
+            d = a[0]
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // Symbols can't contain the operators, so they're named after them
+    assert!(output.contains("llvm.func @Vec.op_add("));
+    assert!(output.contains("llvm.func @Vec.op_index("));
+    assert!(output.contains("llvm.func @Vec.op_cmp("));
+    assert!(output.contains("llvm.call @Vec.op_cmp"));
+    assert!(output.contains("llvm.call @Vec.op_index"));
+    assert!(!output.contains("@\"Vec."));
+}
+
+#[test]
+fn comparable_arrays_sort_with_the_comparator() {
+    let input = "
+        class Dog
+            @age Int
+
+            impl Comparable
+                def <=>(other Dog) -> Int
+                    @age
+                end
+            end
+        end
+
+        def _mlir_ciface_main
+            a = Dog.new(3)
+            b = Dog.new(1)
+            dogs = [a, b]
+            sort(dogs)
+            youngest = min(dogs)
+            oldest = max(dogs)
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+
+    // The runtime is given the address of Dog's `<=>` to compare with
+    assert!(output.contains("llvm.func @Dog.op_cmp("));
+    assert_eq!(output.matches("llvm.mlir.addressof @Dog.op_cmp").count(), 3);
+    assert!(output.contains("llvm.call @pj_sort"));
+    assert!(output.contains("llvm.call @pj_min"));
+    assert!(output.contains("llvm.call @pj_max"));
+}
+
+#[test]
+fn args_and_operands_are_evaluated_left_to_right() {
+    let input = "
+        def first -> Int
+            ret 1
+        end
+
+        def second -> Int
+            ret 2
+        end
+
+        def add(a Int, b Int) -> Int
+            a + b
+        end
+
+        def _mlir_ciface_main
+            c = add(first(), second())
+            d = first() - second()
+            e = add(b: second(), a: first())
+        end
+    ";
+    let output = PajamaCompiler::compile_to_string(&input);
+    let main = output
+        .split("llvm.func @_mlir_ciface_main(")
+        .nth(1)
+        .unwrap();
+
+    let calls: Vec<&str> = main
+        .lines()
+        .filter_map(|line| match line {
+            line if line.contains("llvm.call @first") => Some("first"),
+            line if line.contains("llvm.call @second") => Some("second"),
+            _ => None,
+        })
+        .collect();
+
+    // Named args run in the order they're written, not the order `add`
+    // declares them in
+    assert_eq!(
+        calls,
+        vec!["first", "second", "first", "second", "second", "first"]
+    );
+}
+
+#[test]
+fn main_returns_a_success_status() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: "
+            def main
+                a = 1
+            end
+        "
+        .to_string(),
+    }];
+    let options = CompileOptions {
+        opt_level: OptLevel::O0,
+        ..Default::default()
+    };
+
+    let output = PajamaCompiler::compile_to_ir(&sources, &options)
+        .unwrap()
+        .unwrap();
+    let main = output.split("llvm.func @main(").nth(1).unwrap();
+
+    // A native executable exits with what main returns
+    assert!(main.lines().next().unwrap().contains("-> i32"));
+    assert!(main.contains("llvm.mlir.constant(0 : i32) : i32"));
+}
+
+#[test]
+fn object_files_define_main_for_the_linker() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: "
+            def main
+                a = 1
+            end
+        "
+        .to_string(),
+    }];
+    let path = std::env::temp_dir().join("pajama_object_files_define_main.o");
+    let path = path.to_str().unwrap();
+
+    PajamaCompiler::compile_to_object(&sources, &CompileOptions::default(), path)
+        .unwrap()
+        .unwrap();
+
+    let object = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert!(!object.is_empty());
+    assert!(object.windows(4).any(|bytes| bytes == b"main"));
+}
+
+#[test]
+fn the_library_api_returns_diagnostics_instead_of_printing() {
+    let parse_errors = pajama::compile_to_ast(indoc! {"
+        def main
+          n = (1 + 2
+        end
+    "})
+    .unwrap_err();
+
+    assert_eq!(parse_errors.len(), 1);
+    assert_eq!(parse_errors[0].path, Some("main.pjs".to_string()));
+    assert_eq!(
+        parse_errors[0].message,
+        "Expected ')' character at end of parenthesized expression."
+    );
+
+    let analysis_errors = pajama::compile_to_ast(indoc! {"
+        def main
+          text = \"hello\".trim()
+        end
+    "})
+    .unwrap_err();
+
+    // Analysis errors have no position yet
+    assert!(analysis_errors.contains(&Diagnostic::new(
+        "Str methods require the Str class to be defined"
+    )));
+
+    assert!(pajama::compile_to_ast(indoc! {"
+        def main
+          n = 1 + 2
+        end
+    "})
+    .is_ok());
+
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: "def main\n  n = (1 + 2\nend\n".to_string(),
+    }];
+    let emit_errors = PajamaCompiler::compile_to_c(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap_err();
+
+    assert_eq!(emit_errors, parse_errors);
+}
+
+#[test]
+fn tokens_lexed_once_can_be_parsed_again() {
+    let tokens = pajama::lex_str(indoc! {"
+        def main
+          n = 1 + 2
+        end
+    "});
+
+    let first = pajama::parse_tokens(&tokens).unwrap();
+    let second = pajama::parse_tokens(&tokens).unwrap();
+
+    assert_eq!(
+        format!("{:?}", first.module),
+        format!("{:?}", second.module)
+    );
+
+    let broken = pajama::lex_str("def main\n  n = (1 + 2\nend\n");
+
+    assert_eq!(
+        pajama::parse_tokens(&broken).unwrap_err()[0].message,
+        "Expected ')' character at end of parenthesized expression."
+    );
+}
+
+#[test]
+fn sandboxed_evaluation_returns_the_output_and_stops_at_limits() {
+    let limits = ResourceLimits {
+        max_heap: Some(1 << 20),
+        max_time: Some(Duration::from_millis(50)),
+    };
+    let str_class = "class Str\n  @buffer BytePtr\n  @length Int\n  @max_length Int\nend\n";
+
+    assert_eq!(
+        PajamaCompiler::eval_sandboxed(
+            &format!(
+                "{}def main\n  n = 6\n  puts(\"#{{n * 7}}\")\nend\n",
+                str_class
+            ),
+            &limits
+        ),
+        EvalResult::Exited {
+            status: 0,
+            output: "42\n".to_string()
+        }
+    );
+    assert_eq!(
+        PajamaCompiler::eval_sandboxed(
+            &format!(
+                "{}def_e fork -> Int\n\ndef main\n  pid = fork()\nend\n",
+                str_class
+            ),
+            &limits
+        ),
+        EvalResult::Stopped {
+            error: "sandbox: `fork` isn't allowed".to_string(),
+            output: "".to_string()
+        }
+    );
+    assert_eq!(
+        PajamaCompiler::eval_sandboxed(
+            "def main\n  n = 0\n  loop {\n    n = n + 1\n  }\nend\n",
+            &limits
+        ),
+        EvalResult::Stopped {
+            error: "time limit of 50ms exceeded".to_string(),
+            output: "".to_string()
+        }
+    );
+    assert_eq!(
+        PajamaCompiler::eval_sandboxed("import \"secrets.pjs\"\n", &limits),
+        EvalResult::Failed(vec![Diagnostic::new(
+            "imports aren't available when evaluating sandboxed"
+        )])
+    );
+    assert!(matches!(
+        PajamaCompiler::eval_sandboxed("def main\n  n = (1 + 2\nend\n", &limits),
+        EvalResult::Failed(_)
+    ));
+}
+
+#[test]
+fn stats_are_written_as_json() {
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: indoc! {"
+            class Dog
+              @age Int
+            end
+
+            def double(n Int) -> Int
+              ret n * 2
+            end
+
+            def main
+              a = double(1)
+            end
+        "}
+        .to_string(),
+    }];
+    let path = std::env::temp_dir().join(format!("pajama-stats-{}.json", std::process::id()));
+    let options = CompileOptions {
+        stats_json: Some(path.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+
+    PajamaCompiler::compile_to_js(&sources, &options)
+        .unwrap()
+        .unwrap();
+
+    let stats = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        stats.starts_with("{\"defs\": 2, \"classes\": 1, \"nodes\": "),
+        "{}",
+        stats
+    );
+    assert!(stats.contains(", \"diagnostics\": 0, \"phases\": [{\"name\": \"lex\", \"micros\": "));
+
+    for phase in ["parse", "analyze", "emit_js"] {
+        assert!(
+            stats.contains(&format!("{{\"name\": \"{}\", ", phase)),
+            "{}",
+            stats
+        );
+    }
+}
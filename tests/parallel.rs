@@ -0,0 +1,9 @@
+use pajama::parallel::par_map_with;
+
+#[test]
+fn par_map_keeps_the_order_of_its_items() {
+    let items: Vec<u64> = (0..103).collect();
+    let expected: Vec<u64> = items.iter().map(|item| item * item).collect();
+
+    assert_eq!(par_map_with(4, items, |item| item * item), expected);
+}
@@ -0,0 +1,744 @@
+use std::collections::HashMap;
+
+use pajama::columns::TabWidth;
+use pajama::lexer::{Lexer, TokenKind};
+use pajama::parser::{
+    default_op_precedence, mangle_method_name, BaseType, Node, Parser, ParserResult,
+};
+use pajama::source::SourceFile;
+
+use indoc::indoc;
+
+fn parse(input: &str) -> ParserResult {
+    let mut lexer = Lexer::new(input);
+    let tokens = lexer.tokenize();
+
+    let mut precedence_map = HashMap::new();
+    precedence_map.insert("<".to_string(), 10);
+    precedence_map.insert("|".to_string(), 12);
+    precedence_map.insert("^".to_string(), 13);
+    precedence_map.insert("&".to_string(), 14);
+    precedence_map.insert("<<".to_string(), 15);
+    precedence_map.insert(">>".to_string(), 15);
+    precedence_map.insert("+".to_string(), 20);
+    precedence_map.insert("-".to_string(), 20);
+    precedence_map.insert("*".to_string(), 40);
+    precedence_map.insert("/".to_string(), 40);
+
+    Parser::start_parse(tokens, &mut precedence_map)
+}
+
+fn find_def<'a>(result: &'a ParserResult, name: &str) -> &'a pajama::parser::Def {
+    match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .find_map(|node| match node {
+                Node::Def(def) if def.prototype.name == name => Some(def),
+                _ => None,
+            })
+            .unwrap(),
+        _ => panic!("Expected a module"),
+    }
+}
+
+#[test]
+fn forward_referenced_defs_are_predeclared() {
+    let input = indoc! {"
+        def main
+           count = is_even(4)
+           print_int(count)
+        end
+
+        def is_even(n Int) -> Int
+           ret is_odd(n)
+        end
+
+        def is_odd(n Int) -> Int
+           ret is_even(n)
+        end
+    "};
+
+    let result = parse(input);
+
+    assert!(result.index.fn_prototype_index.contains_key("is_even"));
+    assert!(result.index.fn_prototype_index.contains_key("is_odd"));
+
+    let main = find_def(&result, "main");
+
+    match &main.body[1] {
+        Node::Call(call) => match &call.args[0] {
+            Node::LocalVar(lvar) => assert_eq!(lvar.return_type, Some(BaseType::Int)),
+            node => panic!("Expected a local variable, got {:#?}", node),
+        },
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn methods_after_blocks_are_predeclared_in_their_class() {
+    let input = indoc! {"
+        class Foo
+          @count Int
+
+          def bar() -> Int
+            if @count < 1
+              ret 0
+            end
+
+            n = 0
+
+            while n < 10
+              n = n + 1
+            end
+
+            @count
+          end
+
+          def baz() -> Int
+            1
+          end
+        end
+
+        def main
+          foo = Foo.new(0)
+          foo.baz()
+        end
+    "};
+
+    let result = parse(input);
+
+    assert!(result.index.fn_prototype_index.contains_key("Foo.baz"));
+    assert!(!result.index.fn_prototype_index.contains_key("baz"));
+}
+
+#[test]
+fn subclasses_inherit_attributes_and_resolve_super() {
+    let input = indoc! {"
+        class Animal
+          @legs Int
+
+          def legs() -> Int
+            @legs
+          end
+
+          def name() -> Int
+            0
+          end
+        end
+
+        class Dog < Animal
+          @tail Int
+
+          def legs() -> Int
+            super
+          end
+        end
+    "};
+
+    let result = parse(input);
+
+    let dog = &result.index.class_index["Dog"];
+    let attributes: Vec<(&str, i32)> = dog
+        .attributes
+        .iter()
+        .map(|attribute| (attribute.name.as_str(), attribute.index))
+        .collect();
+
+    assert_eq!(dog.superclass, Some("Animal".to_string()));
+    assert_eq!(attributes, vec![("legs", 0), ("tail", 1)]);
+
+    match &find_def(&result, "Dog.legs").body[0] {
+        Node::Call(call) => assert_eq!(call.fn_name, "Animal.legs"),
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    assert_eq!(
+        result.index.resolve_method("Dog", "name"),
+        Ok(Some("Animal.name".to_string()))
+    );
+    assert_eq!(
+        result.index.resolve_method("Dog", "legs"),
+        Ok(Some("Dog.legs".to_string()))
+    );
+}
+
+#[test]
+fn classes_define_operator_methods() {
+    let input = indoc! {"
+        class Vec
+          @x Int
+
+          def +(other Vec) -> Int
+            @x
+          end
+
+          def ==(other Vec) -> Int
+            1
+          end
+
+          def [](index Int) -> Int
+            index
+          end
+        end
+    "};
+
+    let result = parse(input);
+
+    for name in ["Vec.+", "Vec.==", "Vec.[]"] {
+        let def = find_def(&result, name);
+
+        assert!(def.prototype.is_op);
+        assert_eq!(def.prototype.args.len(), 2);
+    }
+
+    assert_eq!(mangle_method_name("Vec.+"), "Vec.op_add");
+    assert_eq!(mangle_method_name("Vec.[]"), "Vec.op_index");
+    assert_eq!(mangle_method_name("Vec.add"), "Vec.add");
+}
+
+#[test]
+fn bitwise_operators_bind_like_rust() {
+    let input = indoc! {"
+        def main
+           flags = a | b & c << 1 + 1
+           mask = ~a ^ b >> 2
+        end
+    "};
+
+    let result = parse(input);
+
+    assert_eq!(
+        assigned_expressions(find_def(&result, "main")),
+        vec!["(a | (b & (c << (1 + 1))))", "((a ^ -1) ^ (b >> 2))"]
+    );
+}
+
+#[test]
+fn unary_minus_negates_constants_and_subtracts_from_zero() {
+    let input = indoc! {"
+        def main
+           n = -5
+           m = -a * 2
+           k = 1 - -2
+        end
+    "};
+
+    let result = parse(input);
+
+    assert_eq!(
+        assigned_expressions(find_def(&result, "main")),
+        vec!["-5", "((0 - a) * 2)", "(1 - -2)"]
+    );
+}
+
+#[test]
+fn comparisons_and_logical_operators_bind_looser_than_arithmetic() {
+    let input = indoc! {"
+        def main
+           ok = a + 1 <= b * 2 && c != 0 || d == true
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let result = Parser::start_parse(tokens, &mut default_op_precedence());
+
+    assert_eq!(
+        assigned_expressions(find_def(&result, "main")),
+        vec!["((((a + 1) <= (b * 2)) && (c != 0)) || (d == true))"]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unknown binary operator.")]
+fn operators_without_a_precedence_are_rejected() {
+    let input = indoc! {"
+        def main
+           n = a + b
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut precedence_map = HashMap::new();
+    precedence_map.insert("-".to_string(), 20);
+
+    Parser::start_parse(tokens, &mut precedence_map);
+}
+
+/// The value of each assignment in `def`, with binary expressions
+/// parenthesized.
+fn assigned_expressions(def: &pajama::parser::Def) -> Vec<String> {
+    fn show(node: &Node) -> String {
+        match node {
+            Node::Binary(binary) => {
+                format!(
+                    "({} {} {})",
+                    show(&binary.left),
+                    binary.op,
+                    show(&binary.right)
+                )
+            }
+            Node::Bool(boolean) => boolean.value.to_string(),
+            Node::LocalVar(lvar) => lvar.name.clone(),
+            Node::Int(int) => (int.value as i64).to_string(),
+            node => panic!("Unexpected node {:#?}", node),
+        }
+    }
+
+    def.body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => show(&assignment.value),
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect()
+}
+
+#[test]
+#[should_panic(expected = "expression too deeply nested")]
+fn deeply_nested_expressions_are_rejected() {
+    let depth = 10_000;
+    let input = format!(
+        "def main\n   n = {}1{}\nend\n",
+        "(".repeat(depth),
+        ")".repeat(depth)
+    );
+
+    parse(&input);
+}
+
+#[test]
+#[should_panic(expected = "expression too deeply nested")]
+fn long_binary_chains_are_rejected() {
+    let input = format!("def main\n   n = 1{}\nend\n", " + 1".repeat(2000));
+
+    parse(&input);
+}
+
+#[test]
+#[should_panic(expected = "expression too deeply nested")]
+fn long_send_chains_are_rejected() {
+    let input = format!("def main\n   n = b{}\nend\n", ".c()".repeat(2000));
+
+    parse(&input);
+}
+
+#[test]
+fn definitions_repeated_across_files_are_reported() {
+    let files = vec![
+        (
+            "a.pjs".to_string(),
+            Lexer::new("def_e print_int(n Int)\n\ndef helper\nend\n").tokenize(),
+        ),
+        (
+            "b.pjs".to_string(),
+            Lexer::new("def_e print_int(n Int)\n\ndef helper\nend\n\ndef main\nend\n").tokenize(),
+        ),
+    ];
+
+    let mut precedence_map = HashMap::new();
+
+    match Parser::start_parse_files(files, &mut precedence_map) {
+        Err(errors) => assert_eq!(
+            errors[0].to_string(),
+            "b.pjs: `helper` is already defined in a.pjs"
+        ),
+        Ok(_) => panic!("Expected the duplicate definition to be reported"),
+    }
+}
+
+#[test]
+fn imports_are_skipped_once_their_files_are_loaded() {
+    let input = indoc! {"
+        import \"lib/util.pjs\"
+
+        def main
+        end
+
+        import util
+
+        def other
+        end
+    "};
+
+    let files = vec![("main.pjs".to_string(), Lexer::new(input).tokenize())];
+
+    let diagnostics = match Parser::start_parse_files(files, &mut default_op_precedence()) {
+        Err(diagnostics) => diagnostics,
+        Ok(_) => panic!("Expected the import without a path to be reported"),
+    };
+
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>(),
+        vec!["main.pjs:6:8: Expected a path string after import"]
+    );
+
+    let result = parse("import \"lib/util.pjs\"\n\ndef main\nend\n");
+
+    match &result.module {
+        Node::Module(module) => assert_eq!(module.methods.len(), 1),
+        _ => panic!("Expected a module"),
+    }
+}
+
+#[test]
+fn each_broken_item_is_reported_with_its_line() {
+    let input = indoc! {"
+        def first
+          n = (1 + 2
+        end
+
+        def second
+          m = 3
+        end
+
+        def third
+          k = 4 $
+        end
+    "};
+
+    let files = vec![("main.pjs".to_string(), Lexer::new(input).tokenize())];
+    let sources = vec![SourceFile {
+        path: "main.pjs".to_string(),
+        input: input.to_string(),
+    }];
+
+    let diagnostics = match Parser::start_parse_files(files, &mut default_op_precedence()) {
+        Err(diagnostics) => diagnostics,
+        Ok(_) => panic!("Expected the broken defs to be reported"),
+    };
+
+    let rendered: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(&sources, TabWidth::default()))
+        .collect();
+
+    assert_eq!(
+        rendered,
+        vec![
+            indoc! {"
+                error[E0001]: Expected ')' character at end of parenthesized expression.
+                 --> main.pjs:2:12
+                  |
+                2 |   n = (1 + 2
+                  |            ^"},
+            indoc! {"
+                error[E0002]: Unknown expression.
+                 --> main.pjs:10:9
+                   |
+                10 |   k = 4 $
+                   |         ^"},
+        ]
+    );
+}
+
+#[test]
+fn definitions_calls_and_class_methods_are_indexed() {
+    let lib = indoc! {"
+        class Dog
+          @legs Int
+
+          def walk(steps Int) -> Int
+            double(steps)
+          end
+        end
+    "};
+    let main = indoc! {"
+        def double(n Int) -> Int
+          n * 2
+        end
+
+        def main
+          dog = Dog.new(4)
+          double(dog.walk(1))
+        end
+    "};
+
+    let files = vec![
+        ("lib.pjs".to_string(), Lexer::new(lib).tokenize()),
+        ("main.pjs".to_string(), Lexer::new(main).tokenize()),
+    ];
+    let result = Parser::start_parse_files(files, &mut default_op_precedence()).unwrap();
+    let index = &result.index;
+
+    let location = |name: &str| {
+        let location = &index.definition_index[name];
+        let position = location.position.as_ref().unwrap();
+
+        format!(
+            "{}:{}:{}",
+            location.path.as_ref().unwrap(),
+            position.line,
+            position.start_column
+        )
+    };
+
+    assert_eq!(location("Dog"), "lib.pjs:1:7");
+    assert_eq!(location("Dog.walk"), "lib.pjs:4:7");
+    assert_eq!(location("double"), "main.pjs:1:5");
+
+    let callers: Vec<&str> = index.call_site_index["double"]
+        .iter()
+        .map(|call_site| call_site.caller.as_str())
+        .collect();
+
+    assert_eq!(callers, vec!["Dog.walk", "main"]);
+    assert_eq!(
+        index.class_method_index["Dog"],
+        vec!["Dog.walk", "Dog.new", "Dog.alloca"]
+    );
+}
+
+#[test]
+fn default_values_and_named_args_are_parsed() {
+    let input = indoc! {"
+        def greet(name Str, greeting Str = \"Hello\", times Int = 1)
+        end
+
+        def main
+          greet(\"Rex\", times: 2)
+        end
+    "};
+
+    let result = parse(input);
+    let args = &find_def(&result, "greet").prototype.args;
+
+    assert!(args[0].default.is_none());
+    assert!(matches!(&args[1].default, Some(Node::StringLiteral(s)) if s.value == "Hello"));
+    assert!(matches!(&args[2].default, Some(Node::Int(int)) if int.value == 1));
+
+    match &find_def(&result, "main").body[0] {
+        Node::Call(call) => {
+            assert_eq!(call.args.len(), 2);
+            assert_eq!(call.arg_names, vec!["times"]);
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn broken_statements_and_items_leave_a_partial_ast() {
+    let input = indoc! {"
+        def first
+          a = 1
+          b = (2 + 3
+          if a < 2
+            c = 3 $
+          end
+          d = 4
+        end
+
+        def second
+          e = 5
+
+        def third
+          f = 6
+        end
+    "};
+
+    let (result, diagnostics) =
+        Parser::start_parse_partial(Lexer::new(input).tokenize(), &mut default_op_precedence());
+
+    let errors: Vec<(usize, &str)> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.position.as_ref().unwrap().line,
+                diagnostic.message.as_str(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        errors,
+        vec![
+            (
+                3,
+                "Expected ')' character at end of parenthesized expression."
+            ),
+            (5, "Unknown expression."),
+            (11, "Expected 'end' before the next definition."),
+        ]
+    );
+
+    let kinds: Vec<&str> = find_def(&result, "first")
+        .body
+        .iter()
+        .map(Node::kind)
+        .collect();
+
+    assert_eq!(
+        kinds,
+        vec!["assignment", "syntax error", "syntax error", "assignment"]
+    );
+
+    match &result.module {
+        Node::Module(module) => {
+            let kinds: Vec<&str> = module.methods.iter().map(Node::kind).collect();
+
+            assert_eq!(kinds, vec!["def", "syntax error", "def"]);
+        }
+        _ => panic!("Expected a module"),
+    }
+}
+
+#[test]
+fn a_shebang_line_is_skipped() {
+    let input = indoc! {"
+        #!/usr/bin/env pajama
+        def main
+          b = (2 + 3
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+
+    assert_eq!(tokens[0].kind(), TokenKind::Def);
+
+    let (_, diagnostics) = Parser::start_parse_partial(tokens, &mut default_op_precedence());
+
+    assert_eq!(diagnostics[0].position.as_ref().unwrap().line, 3);
+    assert!(Lexer::new("#!").tokenize().is_empty());
+}
+
+#[test]
+fn integer_literals_too_big_for_64_bits_are_reported() {
+    let input = indoc! {"
+        def main
+          n = 9223372036854775807
+          m = 9223372036854775808
+          k = 18446744073709551615
+          j = 18446744073709551616
+        end
+    "};
+
+    let (_, diagnostics) =
+        Parser::start_parse_partial(Lexer::new(input).tokenize(), &mut default_op_precedence());
+
+    assert_eq!(diagnostics.len(), 3);
+
+    for (diagnostic, line) in diagnostics.iter().zip([3, 4, 5]) {
+        assert_eq!(diagnostic.message, "Integer literal too big for 64 bits.");
+        assert_eq!(diagnostic.position.as_ref().unwrap().line, line);
+        assert_eq!(diagnostic.code(), Some("E0024"));
+    }
+}
+
+#[test]
+fn broken_interpolations_are_reported_with_their_code() {
+    let cases = [
+        (
+            "def main\n  puts(\"Hi #{name\")\nend\n",
+            "Expected '}' at the end of an interpolation.",
+            "E0027",
+        ),
+        (
+            "def main\n  puts(\"#{n 2} dogs\")\nend\n",
+            "Expected a single expression in an interpolation.",
+            "E0028",
+        ),
+    ];
+
+    for (input, message, code) in cases {
+        let (_, diagnostics) =
+            Parser::start_parse_partial(Lexer::new(input).tokenize(), &mut default_op_precedence());
+
+        assert_eq!(diagnostics.len(), 1, "{:?}", input);
+        assert_eq!(diagnostics[0].message, message);
+        assert_eq!(diagnostics[0].position.as_ref().unwrap().line, 2);
+        assert_eq!(diagnostics[0].code(), Some(code));
+    }
+}
+
+#[test]
+fn input_ending_inside_a_construct_is_reported_at_its_opener() {
+    let cases = [
+        ("def main\n  n = (1 + 2", "`(` is never closed", 2, 7),
+        ("def main\n  n = [1, 2\n", "`[` is never closed", 2, 7),
+        ("def main\n  puts(\"hi\", 2", "`(` is never closed", 2, 7),
+        ("def main\n  n = items[1", "`[` is never closed", 2, 12),
+        ("def main\n  s = \"abc\nend\n", "`\"` is never closed", 2, 7),
+        ("\"", "`\"` is never closed", 1, 1),
+        ("def main\nend\n\"", "`\"` is never closed", 3, 1),
+        (
+            "def main\n  if ready\n    n = 1\n",
+            "`if` is never closed",
+            2,
+            3,
+        ),
+        (
+            "def main\n  loop {\n    n = 1\n",
+            "`loop` is never closed",
+            2,
+            3,
+        ),
+        (
+            "def main\n  at_exit do\n    n = 1\n",
+            "`do` is never closed",
+            2,
+            11,
+        ),
+        ("def main\n  n = 1 +", "`def` is never closed", 1, 1),
+        (
+            "class Dog\n  def bark\n    n = 1\n  # woof\n",
+            "`def` is never closed",
+            2,
+            3,
+        ),
+    ];
+
+    for (input, message, line, column) in cases {
+        let (_, diagnostics) =
+            Parser::start_parse_partial(Lexer::new(input).tokenize(), &mut default_op_precedence());
+
+        assert_eq!(diagnostics.len(), 1, "{:?}", input);
+        assert_eq!(diagnostics[0].message, message);
+
+        let position = diagnostics[0].position.as_ref().unwrap();
+
+        assert_eq!(
+            (position.line, position.start_column),
+            (line, column),
+            "{:?}",
+            input
+        );
+    }
+
+    let (_, diagnostics) = Parser::start_parse_partial(
+        Lexer::new("def main\n  n = (1").tokenize(),
+        &mut default_op_precedence(),
+    );
+
+    assert_eq!(diagnostics[0].code(), Some("E0025"));
+}
+
+#[test]
+fn every_prefix_of_a_program_parses_without_panicking() {
+    let input = indoc! {r#"
+        # Greets everyone
+        class Dog
+          @name Str
+
+          def bark(times Int) -> Str
+            s = "Woof #{@name}"
+            items = [1, 2][0..1]
+            loop {
+              if times > 1 && items[0] == 1
+                puts(s)
+              end
+              break
+            }
+            s
+          end
+        end
+    "#};
+
+    for (end, _) in input.char_indices() {
+        let tokens = Lexer::new(&input[..end]).tokenize();
+
+        Parser::start_parse_partial(tokens, &mut default_op_precedence());
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+
+use pajama::pajama_lib::{
+    pj_array_get_ptr, pj_array_length, pj_path_absolute, pj_path_basename, pj_path_dirname,
+    pj_path_extension, pj_path_glob, pj_path_join, pjstr_to_str, string_to_pjstr, PjStr,
+};
+
+fn pj_str(text: &str) -> &'static PjStr {
+    unsafe { &*string_to_pjstr(text.to_string()) }
+}
+
+fn text(pj_str: *mut PjStr) -> String {
+    pjstr_to_str(unsafe { &*pj_str }).to_string()
+}
+
+#[test]
+fn join_appends_relative_paths_and_keeps_absolute_ones() {
+    assert_eq!(
+        text(pj_path_join(pj_str("logs"), pj_str("today.log"))),
+        "logs/today.log"
+    );
+    assert_eq!(
+        text(pj_path_join(pj_str("logs/"), pj_str("today.log"))),
+        "logs/today.log"
+    );
+    assert_eq!(
+        text(pj_path_join(pj_str(""), pj_str("today.log"))),
+        "today.log"
+    );
+    assert_eq!(
+        text(pj_path_join(pj_str("logs"), pj_str("/var/log"))),
+        "/var/log"
+    );
+}
+
+#[test]
+fn paths_split_into_dirname_basename_and_extension() {
+    let archive = pj_str("backups/2024/site.tar.gz");
+
+    assert_eq!(text(pj_path_dirname(archive)), "backups/2024");
+    assert_eq!(text(pj_path_basename(archive)), "site.tar.gz");
+    assert_eq!(text(pj_path_extension(archive)), "gz");
+
+    assert_eq!(text(pj_path_dirname(pj_str("notes.txt"))), ".");
+    assert_eq!(text(pj_path_dirname(pj_str("/"))), "/");
+    assert_eq!(text(pj_path_basename(pj_str("/"))), "");
+    assert_eq!(text(pj_path_basename(pj_str("backups/2024/"))), "2024");
+    assert_eq!(text(pj_path_extension(pj_str(".bashrc"))), "");
+    assert_eq!(text(pj_path_extension(pj_str("Makefile"))), "");
+}
+
+#[test]
+fn absolute_resolves_against_the_working_directory() {
+    let cwd = std::env::current_dir().unwrap();
+
+    assert_eq!(
+        text(pj_path_absolute(pj_str("src/../tests/./paths.rs"))),
+        cwd.join("tests/paths.rs").to_str().unwrap()
+    );
+    assert_eq!(
+        text(pj_path_absolute(pj_str("/usr/lib/../bin"))),
+        "/usr/bin"
+    );
+
+    if let Ok(home) = std::env::var("HOME") {
+        assert_eq!(
+            text(pj_path_absolute(pj_str("~/notes.txt"))),
+            format!("{}/notes.txt", home.trim_end_matches('/'))
+        );
+    }
+}
+
+#[test]
+fn glob_lists_matching_paths_in_order() {
+    let dir = std::env::temp_dir().join(format!("pajama_paths_{}", std::process::id()));
+
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("b.log"), "").unwrap();
+    fs::write(dir.join("a.log"), "").unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    fs::write(dir.join("nested/c.log"), "").unwrap();
+
+    let pattern = format!("{}/**/*.log", dir.to_str().unwrap());
+    let matches = unsafe { &*pj_path_glob(pj_str(&pattern)) };
+    let matches: Vec<String> = (0..pj_array_length(matches))
+        .map(|index| text(pj_array_get_ptr(matches, index) as *mut PjStr))
+        .collect();
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    let expected: Vec<String> = ["a.log", "b.log", "nested/c.log"]
+        .iter()
+        .map(|name| dir.join(name).to_str().unwrap().to_string())
+        .collect();
+
+    assert_eq!(matches, expected);
+}
@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use pajama::cancellation::{CancellationToken, Cancelled};
+use pajama::lexer::Lexer;
+use pajama::queries::{Database, QueryStats};
+
+use indoc::indoc;
+
+#[test]
+fn queries_rerun_only_when_their_inputs_change() {
+    let mut db = Database::new();
+
+    db.set_file_text("a.pjs", "def one\nend\n");
+    db.set_file_text("b.pjs", "def two\nend\n");
+
+    assert!(db.program().errors.is_empty());
+    assert!(db.program().errors.is_empty());
+    assert_eq!(
+        db.stats,
+        QueryStats {
+            tokens: 2,
+            program: 1,
+            relexed_lines: 0
+        }
+    );
+
+    // Unchanged text isn't an edit
+    db.set_file_text("a.pjs", "def one\nend\n");
+    db.program();
+    assert_eq!(db.stats.program, 1);
+
+    // Only the edited file is lexed again
+    db.set_file_text("b.pjs", "def one\nend\n");
+
+    assert_eq!(
+        db.program().errors,
+        vec!["b.pjs: `one` is already defined in a.pjs"]
+    );
+    assert_eq!(
+        db.stats,
+        QueryStats {
+            tokens: 3,
+            program: 2,
+            relexed_lines: 0
+        }
+    );
+}
+
+#[test]
+fn cancelled_programs_are_not_remembered() {
+    let mut db = Database::new();
+    db.set_file_text("a.pjs", "def one\nend\n");
+
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+
+    assert!(matches!(
+        db.program_cancellable(&cancellation),
+        Err(Cancelled)
+    ));
+
+    // The next request compiles from scratch instead of reusing a partial
+    // result
+    assert!(db.program().errors.is_empty());
+    assert_eq!(db.stats.program, 2);
+}
+
+/// Applies `edit` to the file the way an editor would, then checks its
+/// tokens are the ones lexing the whole edited text gives
+fn edit_and_compare(db: &mut Database, text: &mut String, range: Range<usize>, inserted: &str) {
+    db.edit_file("a.pjs", range.clone(), inserted);
+    text.replace_range(range, inserted);
+
+    assert_eq!(
+        format!("{:?}", db.tokens("a.pjs").unwrap()),
+        format!("{:?}", Lexer::new(text).tokenize()),
+        "after editing to:\n{}",
+        text
+    );
+}
+
+#[test]
+fn edits_relex_only_the_lines_around_them() {
+    let mut text = String::new();
+
+    for i in 0..200 {
+        text.push_str(&format!(
+            "def f{}(n Int) -> Int\n  ret n + {}\nend\n\n",
+            i, i
+        ));
+    }
+
+    let mut db = Database::new();
+    db.set_file_text("a.pjs", &text);
+    db.tokens("a.pjs");
+
+    // Adds a line to the middle of f100, every token below moves down one
+    let at = text.find("  ret n + 100\n").unwrap();
+    edit_and_compare(&mut db, &mut text, at..at, "  n = n * 2\n");
+
+    assert_eq!(db.stats.tokens, 1);
+    assert!(db.stats.relexed_lines <= 4, "{:?}", db.stats);
+
+    assert!(db.program().errors.is_empty());
+}
+
+#[test]
+fn edits_spanning_string_literals_and_comments_match_a_full_relex() {
+    let mut text = indoc! {r#"
+        #!/usr/bin/env pajama
+        def main
+          # says "hi"
+          greeting = "hi # not a comment"
+
+
+          puts(greeting)
+          puts("two
+        lines")
+          n = 1
+        end
+    "#}
+    .to_string();
+
+    let mut db = Database::new();
+    db.set_file_text("a.pjs", &text);
+    db.tokens("a.pjs");
+
+    // An opened string literal runs over the lines after it, until closed
+    let at = text.find("puts(greeting)").unwrap();
+    edit_and_compare(&mut db, &mut text, at..at, "\"");
+    edit_and_compare(&mut db, &mut text, at..at + 1, "");
+
+    // Commenting out a line and its string literal, then uncommenting it
+    let at = text.find("greeting =").unwrap();
+    edit_and_compare(&mut db, &mut text, at..at, "# ");
+    edit_and_compare(&mut db, &mut text, at..at + 2, "");
+
+    // A quote in a comment doesn't start a string literal
+    let at = text.find("says").unwrap();
+    edit_and_compare(&mut db, &mut text, at..at, "\"");
+
+    // Deleting from inside a comment into the string literal after it
+    let start = text.find("hi\"\n").unwrap();
+    let end = text.find("not a comment").unwrap();
+    edit_and_compare(&mut db, &mut text, start..end, "");
+
+    // Joining the multi-line string literal's lines shifts the lines after it
+    let at = text.find("two\n").unwrap() + 3;
+    edit_and_compare(&mut db, &mut text, at..at + 1, " ");
+    edit_and_compare(&mut db, &mut text, at..at + 1, "\n\n");
+
+    // Newlines next to blank lines are one NewLine token, and only the first
+    // line skips `#!`
+    let at = text.find("\n\n\n").unwrap() + 1;
+    edit_and_compare(&mut db, &mut text, at..at, "\n");
+    let at = text.find("  n = 1").unwrap();
+    edit_and_compare(&mut db, &mut text, at..at, "#!");
+    edit_and_compare(&mut db, &mut text, 0..2, "");
+    edit_and_compare(&mut db, &mut text, 0..0, "#!");
+
+    assert_eq!(db.stats.tokens, 1);
+}
@@ -0,0 +1,178 @@
+use pajama::pajama_compiler::{CompileOptions, PajamaCompiler};
+use pajama::parser::{BaseType, Node};
+use pajama::repl::{
+    input_kind, is_complete, last_value_type, print_last_value, saved_inputs, InputKind, Session,
+};
+
+#[test]
+fn inputs_are_definitions_assignments_or_expressions() {
+    assert_eq!(
+        input_kind("def double(x Int) -> Int\n  x * 2\nend\n"),
+        InputKind::Definition
+    );
+    assert_eq!(input_kind("  class Point\n  end\n"), InputKind::Definition);
+    assert_eq!(input_kind("LIMIT = 10\n"), InputKind::Definition);
+    assert_eq!(input_kind("import \"lib.pjs\"\n"), InputKind::Definition);
+    assert_eq!(
+        input_kind("total = 1 + 2\n"),
+        InputKind::Assignment("total".to_string())
+    );
+    assert_eq!(input_kind("total + 1\n"), InputKind::Expression);
+    assert_eq!(input_kind("total == 3\n"), InputKind::Expression);
+}
+
+#[test]
+fn inputs_are_complete_once_every_block_ends() {
+    assert!(is_complete("1 + 2\n"));
+    assert!(!is_complete("def double(x Int) -> Int\n"));
+    assert!(!is_complete(
+        "def double(x Int) -> Int\n  if x < 0\n    0\n  end\n"
+    ));
+    assert!(is_complete("def double(x Int) -> Int\n  x * 2\nend\n"));
+}
+
+#[test]
+fn expressions_run_after_the_assignments_before_them() {
+    let mut session = Session::new();
+
+    session.commit("def double(x Int) -> Int\n  x * 2\nend\n");
+    session.commit("total = double(4)\n");
+    session.commit("total + 1\n");
+
+    assert_eq!(session.definitions.len(), 1);
+    assert_eq!(session.definitions[0].path, "(input 1)");
+    assert_eq!(session.assignments, vec!["total = double(4)\n"]);
+
+    let sources = session.sources("total - 1\n");
+
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[1].path, "(input 4)");
+    assert_eq!(
+        sources[1].input,
+        "def main\ntotal = double(4)\ntotal - 1\nend\n"
+    );
+
+    let sources = session.sources("half = total / 2\n");
+
+    assert_eq!(
+        sources[1].input,
+        "def main\ntotal = double(4)\nhalf = total / 2\nhalf\nend\n"
+    );
+
+    let sources = session.sources("def triple(x Int) -> Int\n  x * 3\nend\n");
+
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[1].input, "def triple(x Int) -> Int\n  x * 3\nend\n");
+}
+
+#[test]
+fn the_last_value_is_passed_to_a_print_function() {
+    let mut session = Session::new();
+
+    session.commit("def double(x Int) -> Int\n  x * 2\nend\n");
+
+    let sources = session.sources("double(21)\n");
+    let mut parser_result = PajamaCompiler::compile_to_ast(&sources, &CompileOptions::default())
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(print_last_value(&mut parser_result), None);
+    assert!(parser_result
+        .index
+        .fn_prototype_index
+        .contains_key("pj_repl_print_int"));
+
+    let module = match &parser_result.module {
+        Node::Module(module) => module,
+        _ => panic!("Expected a module"),
+    };
+    let main = module
+        .methods
+        .iter()
+        .find_map(|node| match node {
+            Node::Def(def) if def.main_fn => Some(def),
+            _ => None,
+        })
+        .unwrap();
+
+    match main.body.last() {
+        Some(Node::Call(call)) => assert_eq!(call.fn_name, "pj_repl_print_int"),
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn instances_are_shown_with_to_s_and_their_type() {
+    let mut session = Session::new();
+
+    session.commit("class Str\n  @buffer BytePtr\n  @length Int\n  @max_length Int\nend\n");
+    session.commit("class Dog\n  @legs Int\nend\n");
+
+    let input = "Dog.new(4)\n";
+    let mut parser_result =
+        PajamaCompiler::compile_to_ast(&session.sources(input), &CompileOptions::default())
+            .unwrap()
+            .unwrap();
+
+    assert_eq!(
+        last_value_type(&parser_result),
+        Some(BaseType::Class("Dog".to_string()))
+    );
+    assert_eq!(
+        print_last_value(&mut parser_result),
+        Some("#<Dog> : Dog".to_string())
+    );
+
+    let sources = session.inspect_sources(input, "Dog");
+
+    assert_eq!(
+        sources[2].input,
+        "def main\n__repl_value = Dog.new(4)\nputs(\"=> #{__repl_value} : Dog\")\nend\n"
+    );
+    assert!(
+        PajamaCompiler::compile_to_ast(&sources, &CompileOptions::default())
+            .unwrap()
+            .is_ok()
+    );
+
+    let sources = session.inspect_sources("rex = Dog.new(4)\n", "Dog");
+
+    assert_eq!(
+        sources[2].input,
+        "def main\nrex = Dog.new(4)\nputs(\"=> #{rex} : Dog\")\nend\n"
+    );
+}
+
+#[test]
+fn saved_sessions_are_restored_by_replaying_their_inputs() {
+    let mut session = Session::new();
+
+    session.commit("def double(x Int) -> Int\n  # twice x\n  x * 2\nend\n");
+    session.commit("total = double(4)\n");
+    session.commit("total + 1\n");
+
+    let saved = session.save();
+
+    assert!(saved.starts_with("# pajama repl session"));
+    assert_eq!(saved_inputs(&saved), session.history);
+
+    let mut restored = Session::new();
+
+    for input in saved_inputs(&saved) {
+        restored.commit(&input);
+    }
+
+    assert_eq!(restored.definitions.len(), 1);
+    assert_eq!(restored.definitions[0].path, "(input 1)");
+    assert_eq!(restored.definitions[0].input, session.definitions[0].input);
+    assert_eq!(restored.assignments, session.assignments);
+    assert_eq!(
+        restored.sources("total\n")[1].input,
+        "def main\ntotal = double(4)\ntotal\nend\n"
+    );
+
+    assert_eq!(
+        saved_inputs("# notes\n\n\n1 + 2\n# done\n"),
+        vec!["1 + 2\n# done\n"]
+    );
+}
@@ -0,0 +1,52 @@
+use std::process::Command;
+use std::time::Duration;
+
+use pajama::pajama_lib::{pj_array_new, pj_array_push_int, set_heap_limit};
+use pajama::resource_limits::{parse_duration, parse_size, Watchdog, HEAP_LIMIT_EXIT_CODE};
+
+#[test]
+fn limits_parse_with_units() {
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("64K"), Ok(64 * 1024));
+    assert_eq!(parse_size("16mb"), Ok(16 * 1024 * 1024));
+    assert!(parse_size("16T").is_err());
+    assert!(parse_size("99999999999G").is_err());
+
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert!(parse_duration("s").is_err());
+
+    // Dropped before it fires, so the test process keeps running
+    drop(Watchdog::start(Duration::from_millis(10)));
+    std::thread::sleep(Duration::from_millis(50));
+}
+
+#[test]
+fn growing_arrays_count_against_the_heap_limit() {
+    // Going over the limit exits, so that's done in a copy of this test
+    if std::env::var("PJ_GROW_ARRAY").is_ok() {
+        set_heap_limit(Some(1024));
+
+        let numbers = unsafe { &mut *pj_array_new(0) };
+
+        for number in 0..1000 {
+            pj_array_push_int(numbers, number);
+        }
+
+        return;
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "growing_arrays_count_against_the_heap_limit",
+            "--nocapture",
+        ])
+        .env("PJ_GROW_ARRAY", "1")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(HEAP_LIMIT_EXIT_CODE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("heap limit of 1024 bytes exceeded"));
+}
@@ -0,0 +1,2110 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use pajama::lexer::Lexer;
+use pajama::parser::{BaseType, Node, Parser, ParserResult};
+use pajama::runtime_profile::{check_runtime_profile, RuntimeProfile};
+use pajama::semantic_analyzer::{
+    apply_freeze_literals, apply_sandbox, apply_strict, SemanticAnalyzer,
+};
+
+use indoc::indoc;
+
+fn analyze(input: &str) -> (ParserResult, SemanticAnalyzer) {
+    let mut lexer = Lexer::new(input);
+    let tokens = lexer.tokenize();
+
+    let mut precedence_map = HashMap::new();
+    precedence_map.insert("<".to_string(), 10);
+    precedence_map.insert("+".to_string(), 20);
+    precedence_map.insert("-".to_string(), 20);
+    precedence_map.insert("*".to_string(), 40);
+    precedence_map.insert("/".to_string(), 40);
+
+    let mut result = Parser::start_parse(tokens, &mut precedence_map);
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    (result, analyzer)
+}
+
+fn find_def<'a>(result: &'a ParserResult, name: &str) -> &'a pajama::parser::Def {
+    match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .find_map(|node| match node {
+                Node::Def(def) if def.prototype.name == name => Some(def),
+                _ => None,
+            })
+            .unwrap(),
+        _ => panic!("Expected a module"),
+    }
+}
+
+#[test]
+fn puts_converts_values_with_to_s() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        class Dog
+          @legs Int
+        end
+
+        def main
+          dog = Dog.new(4)
+          puts(dog)
+          puts(7)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let main = find_def(&result, "main");
+
+    match &main.body[1] {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_puts");
+
+            match &call.args[0] {
+                Node::Send(send) => {
+                    assert_eq!(send.return_type, Some(BaseType::Class("Str".to_string())))
+                }
+                node => panic!("Expected a send, got {:#?}", node),
+            }
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    match &main.body[2] {
+        Node::Call(call) => match &call.args[0] {
+            Node::Call(call) => assert_eq!(call.fn_name, "pj_int_to_s"),
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    let to_s = find_def(&result, "Dog.to_s");
+
+    assert_eq!(
+        to_s.prototype.return_type,
+        Some(BaseType::Class("Str".to_string()))
+    );
+    assert!(result
+        .index
+        .fn_prototype_index
+        .contains_key("pj_str_concat"));
+}
+
+#[test]
+fn puts_in_conditional_expressions_converts_with_to_s() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          n = if true
+            puts(7)
+            1
+          else
+            2
+          end
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let then_body = match &find_def(&result, "main").body[0] {
+        Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+            Node::If(if_node) => &if_node.then_body,
+            node => panic!("Expected an if, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    };
+
+    match &then_body[0] {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_puts");
+
+            match &call.args[0] {
+                Node::Call(call) => assert_eq!(call.fn_name, "pj_int_to_s"),
+                node => panic!("Expected a call, got {:#?}", node),
+            }
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn interpolated_strings_concatenate_their_parts() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def greet(name Str, age Int) -> Str
+          ret \"Hello #{name}, you are #{age + 1}\"
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let greet = find_def(&result, "greet");
+
+    let value = match &greet.body[0] {
+        Node::Ret(ret) => ret.value.as_ref(),
+        node => panic!("Expected a ret, got {:#?}", node),
+    };
+
+    // ((("Hello " + name) + ", you are ") + (age + 1).to_s)
+    match value {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_str_concat");
+
+            match &call.args[1] {
+                Node::Call(call) => assert_eq!(call.fn_name, "pj_int_to_s"),
+                node => panic!("Expected a call, got {:#?}", node),
+            }
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn constant_interpolated_parts_are_joined_at_compile_time() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def remaining(n Int) -> Str
+          ret \"#{n} of #{10} left, #{true}\"
+        end
+
+        def total -> Str
+          ret \"#{1}#{2} in all\"
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let returned = |name: &str| match &find_def(&result, name).body[0] {
+        Node::Ret(ret) => *ret.value.clone(),
+        node => panic!("Expected a ret, got {:#?}", node),
+    };
+
+    // n.to_s + " of 10 left, true"
+    match returned("remaining") {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_str_concat");
+            assert!(matches!(&call.args[0], Node::Call(call) if call.fn_name == "pj_int_to_s"));
+            assert!(
+                matches!(&call.args[1], Node::StringLiteral(literal) if literal.value == " of 10 left, true")
+            );
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    match returned("total") {
+        Node::StringLiteral(literal) => assert_eq!(literal.value, "12 in all"),
+        node => panic!("Expected a string, got {:#?}", node),
+    }
+}
+
+#[test]
+fn strs_are_added_and_any_builtin_value_is_printed() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def greet(name Str) -> Str
+          ret \"Hello \" + name
+        end
+
+        def main
+          count = 42
+          print(greet(\"Rex\"))
+          puts(true)
+          puts(count.to_string())
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    match &find_def(&result, "greet").body[0] {
+        Node::Ret(ret) => match ret.value.as_ref() {
+            Node::Call(call) => assert_eq!(call.fn_name, "pj_str_concat"),
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected a ret, got {:#?}", node),
+    }
+
+    let main = find_def(&result, "main");
+    let printed: Vec<(&str, &str)> = main.body[1..]
+        .iter()
+        .map(|node| match node {
+            Node::Call(call) => match &call.args[0] {
+                Node::Call(arg) => (call.fn_name.as_str(), arg.fn_name.as_str()),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected a call, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        printed,
+        vec![
+            ("pj_print", "greet"),
+            ("pj_puts", "pj_bool_to_s"),
+            ("pj_puts", "pj_int_to_s_base"),
+        ]
+    );
+}
+
+#[test]
+fn comparable_arrays_sort_with_the_compiled_comparator() {
+    let input = indoc! {"
+        class Dog
+          @age Int
+
+          impl Comparable
+            def <=>(other Dog) -> Int
+              @age
+            end
+          end
+        end
+
+        def main
+          a = Dog.new(3)
+          b = Dog.new(1)
+          dogs = [a, b]
+          sort(dogs)
+          youngest = min(dogs)
+          order = a <=> b
+          position = binary_search(dogs, a)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let main = find_def(&result, "main");
+
+    match &main.body[3] {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_sort");
+
+            match &call.args[1] {
+                Node::FnRef(fn_ref) => assert_eq!(fn_ref.fn_name, "Dog.<=>"),
+                node => panic!("Expected a function reference, got {:#?}", node),
+            }
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    match &main.body[4] {
+        Node::AssignLocalVar(assign) => match assign.value.as_ref() {
+            Node::Call(call) => {
+                assert_eq!(call.fn_name, "pj_min");
+                assert_eq!(call.return_type, Some(BaseType::Class("Dog".to_string())));
+            }
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    match &main.body[5] {
+        Node::AssignLocalVar(assign) => match assign.value.as_ref() {
+            Node::Send(send) => assert_eq!(send.return_type, Some(BaseType::Int)),
+            node => panic!("Expected a send, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    match &main.body[6] {
+        Node::AssignLocalVar(assign) => match assign.value.as_ref() {
+            Node::Call(call) => {
+                assert_eq!(call.fn_name, "pj_binary_search");
+                assert_eq!(call.args.len(), 3);
+                assert_eq!(call.return_type, Some(BaseType::Int));
+            }
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+}
+
+#[test]
+fn sort_min_and_max_can_be_sent_to_comparable_arrays() {
+    let input = indoc! {"
+        class Dog
+          @age Int
+
+          impl Comparable
+            def <=>(other Dog) -> Int
+              @age
+            end
+          end
+        end
+
+        def main
+          dogs = [Dog.new(3), Dog.new(1)]
+          dogs.sort()
+          youngest = dogs.min()
+          oldest = dogs.max()
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let main = find_def(&result, "main");
+    let calls: Vec<(String, usize, Option<BaseType>)> = main.body[1..4]
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => assignment.value.as_ref(),
+            node => node,
+        })
+        .map(|node| match node {
+            Node::Call(call) => (
+                call.fn_name.clone(),
+                call.args.len(),
+                call.return_type.clone(),
+            ),
+            node => panic!("Expected a call, got {:#?}", node),
+        })
+        .collect();
+    let dog = Some(BaseType::Class("Dog".to_string()));
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_sort".to_string(), 2, None),
+            ("pj_min".to_string(), 2, dog.clone()),
+            ("pj_max".to_string(), 2, dog),
+        ]
+    );
+}
+
+#[test]
+fn trait_implementations_match_the_declared_methods() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        trait Shape
+          def area() -> Int
+          def sides() -> Int
+
+          def corners() -> Int
+            0
+          end
+        end
+
+        class Square
+          @size Int
+
+          impl Shape
+            def area() -> Int
+              @size * @size
+            end
+
+            def sides() -> Int
+              4
+            end
+          end
+        end
+
+        class Circle
+          @radius Int
+
+          impl Shape
+            def area(scale Int) -> Int
+              @radius * scale
+            end
+          end
+        end
+
+        def main
+          square = Square.new(2)
+          print_int(square.area() + square.corners())
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`Circle.area` does not match `Shape.area`: expected () -> Int, found (Int) -> Int",
+            "`Circle` implements Shape but does not define sides",
+        ]
+    );
+
+    let required: Vec<(&str, bool)> = result.index.trait_method_index["Shape"]
+        .iter()
+        .map(|method| (method.prototype.name.as_str(), method.required))
+        .collect();
+
+    assert_eq!(
+        required,
+        vec![
+            ("Shape.area", true),
+            ("Shape.sides", true),
+            ("Shape.corners", false)
+        ]
+    );
+
+    let input = indoc! {"
+        trait Named
+          def name() -> Int
+        end
+
+        def shout(named Named) -> Int
+          named.name()
+        end
+    "};
+
+    let (_, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Can't send `name` to a Named: it has no default, and sends aren't dispatched to the implementing class"]
+    );
+}
+
+#[test]
+fn defaults_from_two_traits_are_ambiguous() {
+    let input = indoc! {"
+        trait Walker
+          def speed -> Int
+            1
+          end
+        end
+
+        trait Swimmer
+          def speed -> Int
+            2
+          end
+        end
+
+        class Duck
+          @legs Int
+
+          impl Walker
+          end
+
+          impl Swimmer
+          end
+        end
+
+        def main
+          duck = Duck.new(2)
+          duck.speed()
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert_eq!(
+        result.index.resolve_method("Duck", "speed"),
+        Err("Ambiguous method `speed` for `Duck`: default implementations are provided by Swimmer.speed, Walker.speed".to_string())
+    );
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Ambiguous method `speed` for `Duck`: default implementations are provided by Swimmer.speed, Walker.speed"]
+    );
+}
+
+#[test]
+fn attribute_assignments_check_for_frozen_receivers() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        class Dog
+          @legs Int
+        end
+
+        def grow(dog Dog, legs Int)
+          dog.legs = legs
+        end
+
+        def main
+          dog = Dog.new(4)
+          dog.freeze()
+          grow(dog, 5)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    match &find_def(&result, "Dog.freeze").body[0] {
+        Node::Call(call) => assert_eq!(call.fn_name, "pj_freeze"),
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    match &find_def(&result, "Dog.new").body[0] {
+        Node::Call(call) => assert_eq!(call.fn_name, "pj_unfreeze"),
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+
+    match &find_def(&result, "grow").body[0] {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_check_frozen");
+
+            match &call.args[1] {
+                Node::StringLiteral(site) => assert_eq!(site.value, "legs= in grow"),
+                node => panic!("Expected a string literal, got {:#?}", node),
+            }
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn nested_attribute_assignments_check_for_frozen_receivers() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        class Dog
+          @legs Int
+        end
+
+        def grow(dog Dog, legs Int)
+          if legs < 9
+            dog.legs = legs
+          end
+
+          while legs < 4
+            dog.legs = legs
+            legs = legs + 1
+          end
+        end
+
+        def main
+          dog = Dog.new(4)
+          dog.freeze()
+          grow(dog, 5)
+        end
+    "};
+
+    let (result, _) = analyze(input);
+    let grow = find_def(&result, "grow");
+
+    let (if_body, while_body) = match (&grow.body[0], &grow.body[1]) {
+        (Node::If(if_node), Node::While(while_node)) => (&if_node.then_body, &while_node.body),
+        nodes => panic!("Expected an if and a while, got {:#?}", nodes),
+    };
+
+    for body in [if_body, while_body] {
+        match &body[0] {
+            Node::Call(call) => assert_eq!(call.fn_name, "pj_check_frozen"),
+            node => panic!("Expected a call, got {:#?}", node),
+        }
+    }
+}
+
+#[test]
+fn freeze_is_found_wherever_it_is_sent() {
+    let program = |main: &str| {
+        format!(
+            indoc! {"
+                class Str
+                  @buffer     BytePtr
+                  @length     Int
+                  @max_length Int
+                end
+
+                class Dog
+                  @legs Int
+                end
+
+                def grow(dog Dog, legs Int)
+                  dog.legs = legs
+                end
+
+                def keep(dog Dog)
+                end
+
+                def freeze(dog Dog)
+                  ret dog.freeze()
+                end
+
+                def main
+                  dog = Dog.new(4)
+                  {}
+                end
+            "},
+            main
+        )
+    };
+
+    for main in ["frozen = dog.freeze()", "freeze(dog)", "keep(dog.freeze())"] {
+        let (result, _) = analyze(&program(main));
+
+        match &find_def(&result, "grow").body[0] {
+            Node::Call(call) => assert_eq!(call.fn_name, "pj_check_frozen", "{}", main),
+            node => panic!("Expected a call for `{}`, got {:#?}", main, node),
+        }
+    }
+}
+
+#[test]
+fn freeze_literals_freezes_locals_assigned_a_string_literal() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          if true
+            name = \"rex\"
+          end
+        end
+    "};
+
+    let mut result = Parser::start_parse(Lexer::new(input).tokenize(), &mut HashMap::new());
+    apply_freeze_literals(&mut result);
+    SemanticAnalyzer::run(&mut result);
+
+    let then_body = match &find_def(&result, "main").body[0] {
+        Node::If(if_node) => &if_node.then_body,
+        node => panic!("Expected an if, got {:#?}", node),
+    };
+
+    match &then_body[1] {
+        Node::Send(send) => match send.message.as_ref() {
+            Node::Call(call) => assert_eq!(call.fn_name, "Str.freeze"),
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected a send, got {:#?}", node),
+    }
+}
+
+#[test]
+fn int_to_s_and_parse_take_a_base() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          n = Int.parse(\"ff\", 16)
+          hex = n.to_s(16)
+          decimal = n.to_s()
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<(&str, usize)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => (call.fn_name.as_str(), call.args.len()),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_int_parse", 2),
+            ("pj_int_to_s_base", 2),
+            ("pj_int_to_s_base", 2)
+        ]
+    );
+}
+
+#[test]
+fn str_methods_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+
+          def index_of(needle Str) -> Int
+            ret 0
+          end
+        end
+
+        def main
+          text = \"  hello world  \".trim()
+          hello = text[0..5]
+          world = text.sub(6, 5)
+          found = text.index_of(\"world\")
+          greeting = text.starts_with?(\"hello\")
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let values: Vec<(String, usize)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => (call.fn_name.clone(), call.args.len()),
+                Node::Send(send) => match send.message.as_ref() {
+                    Node::Call(call) => (call.fn_name.clone(), 0),
+                    node => panic!("Expected a call, got {:#?}", node),
+                },
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    // Methods the Str class defines itself are still sent
+    assert_eq!(
+        values,
+        vec![
+            ("pj_str_trim".to_string(), 1),
+            ("pj_str_slice".to_string(), 3),
+            ("pj_str_sub".to_string(), 3),
+            ("Str.index_of".to_string(), 0),
+            ("pj_str_starts_with".to_string(), 2)
+        ]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          text = \"hello\"
+          first = text[0]
+          padded = text.trim(1)
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "Str indexing takes a range, like `s[0..5]`",
+            "`trim` takes no arguments"
+        ]
+    );
+}
+
+#[test]
+fn encode_and_decode_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          bytes = \"caf\u{e9}\".encode(\"utf-16\", \"error\")
+          text = bytes.decode(\"utf-16\", \"replace\")
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<(String, usize, Option<BaseType>)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => (
+                    call.fn_name.clone(),
+                    call.args.len(),
+                    call.return_type.clone(),
+                ),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            (
+                "pj_str_encode".to_string(),
+                3,
+                Some(BaseType::Array(Box::new(BaseType::Int)))
+            ),
+            (
+                "pj_bytes_decode".to_string(),
+                3,
+                Some(BaseType::Class("Str".to_string()))
+            ),
+        ]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          flags = [true]
+          text = flags.decode(\"utf-8\", \"error\")
+          bytes = \"hi\".encode(\"utf-8\")
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`encode` takes encoding and on_invalid",
+            "`decode` is sent to bytes, an [Int], not an array of Bool"
+        ]
+    );
+}
+
+#[test]
+fn date_methods_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        class DateTime
+          @seconds Int
+          @nanos   Int
+          @offset  Int
+        end
+
+        class Date
+          @days Int
+        end
+
+        def main
+          start = DateTime.parse(\"2024-01-01T00:00:00Z\")
+          later = start.add_days(3)
+          hour = later.hour()
+          gap = start.seconds_until(later)
+          day = later.to_date()
+          label = day.format(\"%d %b\")
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<(String, usize)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => (call.fn_name.clone(), call.args.len()),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_datetime_parse".to_string(), 1),
+            ("pj_datetime_add_days".to_string(), 2),
+            ("pj_datetime_hour".to_string(), 1),
+            ("pj_datetime_seconds_until".to_string(), 2),
+            ("pj_datetime_to_date".to_string(), 1),
+            ("pj_date_format".to_string(), 2),
+        ]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        class DateTime
+          @seconds Int
+          @nanos   Int
+          @offset  Int
+        end
+
+        def main
+          start = DateTime.now(1)
+          later = start.now()
+          year = start.year()
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`now` takes no arguments",
+            "`now` is sent to the class, like `DateTime.now()`"
+        ]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        class DateTime
+          @seconds Int
+          @nanos   Int
+          @offset  Int
+        end
+
+        def main
+          now = DateTime.now()
+          day = now.to_date()
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Date and DateTime methods require the Date class to be defined"]
+    );
+}
+
+#[test]
+fn the_clock_is_frozen_by_sends_to_datetime() {
+    let input = indoc! {"
+        class DateTime
+          @seconds Int
+          @nanos   Int
+          @offset  Int
+        end
+
+        def main
+          DateTime.freeze_clock(DateTime.from_unix(0))
+          DateTime.advance_clock(90)
+          DateTime.unfreeze_clock()
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<(String, Option<BaseType>)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::Call(call) => (call.fn_name.clone(), call.return_type.clone()),
+            node => panic!("Expected a call, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_datetime_freeze_clock".to_string(), None),
+            ("pj_datetime_advance_clock".to_string(), None),
+            ("pj_datetime_unfreeze_clock".to_string(), None),
+        ]
+    );
+    assert_eq!(
+        result.index.fn_prototype_index["pj_datetime_freeze_clock"].return_type,
+        None
+    );
+}
+
+#[test]
+fn path_functions_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          log = Path.join(\"logs\", \"today.log\")
+          name = Path.basename(log)
+          extension = Path.extension(log)
+          full = Path.absolute(log)
+          logs = Path.glob(\"logs/*.log\")
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<(String, usize)> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => (call.fn_name.clone(), call.args.len()),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_path_join".to_string(), 2),
+            ("pj_path_basename".to_string(), 1),
+            ("pj_path_extension".to_string(), 1),
+            ("pj_path_absolute".to_string(), 1),
+            ("pj_path_glob".to_string(), 1),
+        ]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          log = Path.join(\"logs\")
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`Path.join` takes path and other"]
+    );
+
+    let (_, analyzer) = analyze(indoc! {"
+        def main
+          logs = Path.glob(\"logs/*.log\")
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Path functions require the Str class to be defined"]
+    );
+}
+
+#[test]
+fn csv_functions_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          rows = Csv.parse(\"name,age\")
+          text = Csv.write(rows)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let calls: Vec<String> = find_def(&result, "main")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Call(call) => call.fn_name.clone(),
+                node => panic!("Expected a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(calls, vec!["pj_csv_parse", "pj_csv_write"]);
+
+    let (_, analyzer) = analyze(indoc! {"
+        def main
+          rows = Csv.parse()
+        end
+    "});
+
+    assert_eq!(analyzer.diagnostics.errors, vec!["`Csv.parse` takes text"]);
+}
+
+#[test]
+fn log_functions_lower_to_runtime_fns() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          Log.info(\"starting\")
+          if 1 < 2
+            Log.warn(\"disk almost full\")
+          end
+          Log.error(\"giving up\")
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let body = &find_def(&result, "main").body;
+    let call_name = |node: &Node| match node {
+        Node::Call(call) => call.fn_name.clone(),
+        node => panic!("Expected a call, got {:#?}", node),
+    };
+
+    assert_eq!(call_name(&body[0]), "pj_log_info");
+    assert_eq!(call_name(&body[2]), "pj_log_error");
+
+    match &body[1] {
+        Node::If(if_node) => assert_eq!(call_name(&if_node.then_body[0]), "pj_log_warn"),
+        node => panic!("Expected an if, got {:#?}", node),
+    }
+
+    let (_, analyzer) = analyze(indoc! {"
+        def main
+          Log.info()
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`Log.info` takes message"]
+    );
+}
+
+#[test]
+fn last_expressions_are_returned() {
+    let input = indoc! {"
+        def sign(n Int) -> Int
+          if n < 0
+            return 0 - 1
+          end
+          if n < 1
+            0
+          else
+            1
+          end
+        end
+
+        def main
+          sign(3)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let body = &find_def(&result, "sign").body;
+    let returns = |nodes: &[Node]| matches!(nodes.last(), Some(Node::Ret(_)));
+
+    match (&body[0], &body[1]) {
+        (Node::If(early), Node::If(last)) => {
+            assert!(returns(&early.then_body));
+            assert!(returns(&last.then_body));
+            assert!(returns(&last.else_body));
+            assert_eq!(last.return_type, None);
+        }
+        nodes => panic!("Expected two ifs, got {:#?}", nodes),
+    }
+
+    // main has no value of its own to return
+    assert!(matches!(
+        find_def(&result, "main").body.last(),
+        Some(Node::Call(_))
+    ));
+
+    let (_, analyzer) = analyze(indoc! {"
+        def sign(n Int) -> Int
+          if n < 0
+            0 - 1
+          end
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`sign` should return Int, but its last statement has no value"]
+    );
+}
+
+#[test]
+fn ifs_assigned_to_locals_have_a_value_in_both_branches() {
+    let input = indoc! {"
+        def pick(c Int) -> Int
+          n = c
+          half = if 0 < n
+            1
+          else
+            0
+          end
+          half
+        end
+
+        def main
+          pick(3)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    match &find_def(&result, "pick").body[0] {
+        Node::AssignLocalVar(assign) => match assign.value.as_ref() {
+            Node::LocalVar(lvar) => assert_eq!(lvar.return_type, Some(BaseType::Int)),
+            node => panic!("Expected a local, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    let (_, analyzer) = analyze(indoc! {"
+        def pick(c Int) -> Int
+          half = if 0 < c
+            1
+          end
+          half
+        end
+
+        def main
+          pick(3)
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["The `if` assigned to `half` has no value without an `else`"]
+    );
+}
+
+#[test]
+fn conditions_are_bools_or_ints() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def_e print_int(int Int)
+
+        def main
+          n = 3
+          while n
+            n = n - 1
+          end
+          if 1 < 2
+            print_int(n)
+          end
+          while \"abc\"
+            print_int(n)
+          end
+          if print_int(1)
+            print_int(n)
+          end
+        end
+    "};
+
+    let (_, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "A condition must be a Bool or an Int, found Str",
+            "A condition must be a Bool or an Int, found nothing"
+        ]
+    );
+}
+
+#[test]
+fn array_methods_lower_to_runtime_fns() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def double(n Int) -> Int
+          ret n * 2
+        end
+
+        def show(n Int)
+          print_int(n)
+        end
+
+        def main
+          numbers = [1, 2, 3]
+          numbers.push(4)
+          numbers[0] = 5
+          first = numbers[0]
+          count = numbers.length()
+          doubled = numbers.map(double.fn_ref())
+          numbers.each(show.fn_ref())
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let main = find_def(&result, "main");
+    let numbers = BaseType::Array(Box::new(BaseType::Int));
+
+    match &main.body[0] {
+        Node::AssignLocalVar(assign) => match assign.value.as_ref() {
+            Node::Array(array) => assert_eq!(array.item_type, BaseType::Int),
+            node => panic!("Expected an array, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    let calls: Vec<(String, usize, Option<BaseType>)> = main.body[1..5]
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => assignment.value.as_ref(),
+            node => node,
+        })
+        .map(|node| match node {
+            Node::Call(call) => (
+                call.fn_name.clone(),
+                call.args.len(),
+                call.return_type.clone(),
+            ),
+            node => panic!("Expected a call, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        calls,
+        vec![
+            ("pj_array_push_int".to_string(), 2, Some(numbers.clone())),
+            ("pj_array_set_int".to_string(), 3, None),
+            ("pj_array_get_int".to_string(), 2, Some(BaseType::Int)),
+            ("pj_array_length".to_string(), 1, Some(BaseType::Int)),
+        ]
+    );
+
+    // `each` and `map` are compiled to a loop calling the function they're given
+    for (node, fn_name) in [(&main.body[5], "double"), (&main.body[6], "show")] {
+        let send = match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Send(send) => send,
+                node => panic!("Expected a send, got {:#?}", node),
+            },
+            Node::Send(send) => send,
+            node => panic!("Expected a send, got {:#?}", node),
+        };
+
+        match send.message.as_ref() {
+            Node::Call(call) => match &call.args[0] {
+                Node::FnRef(fn_ref) => assert_eq!(fn_ref.fn_name, fn_name),
+                node => panic!("Expected a function reference, got {:#?}", node),
+            },
+            node => panic!("Expected a call, got {:#?}", node),
+        }
+    }
+
+    match &main.body[5] {
+        Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+            Node::Send(send) => assert_eq!(send.return_type, Some(numbers)),
+            node => panic!("Expected a send, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    let (_, analyzer) = analyze(indoc! {"
+        def_e print_int(int Int)
+
+        def show(n Int)
+          print_int(n)
+        end
+
+        def main
+          numbers = [1, 2]
+          numbers.push()
+          shown = numbers.map(show.fn_ref())
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`push` takes one argument",
+            "`map` expects a function reference taking a Int and returning a value arrays can hold"
+        ]
+    );
+}
+
+#[test]
+fn overflow_arithmetic_lowers_to_operators_intrinsics_and_runtime_fns() {
+    let input = indoc! {"
+        def mix(a Int, b Int)
+          c = a.wrapping_mul(b)
+          d = a.saturating_add(c)
+          e = d.sub_overflows(b)
+          f = a.checked_add(d)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let lowered: Vec<String> = find_def(&result, "mix")
+        .body
+        .iter()
+        .map(|node| match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Binary(binary) => binary.op.clone(),
+                Node::Call(call) => call.fn_name.clone(),
+                node => panic!("Expected a binary or a call, got {:#?}", node),
+            },
+            node => panic!("Expected an assignment, got {:#?}", node),
+        })
+        .collect();
+
+    assert_eq!(
+        lowered,
+        vec![
+            "*",
+            "llvm.sadd.sat.i64",
+            "pj_sub_overflows",
+            "pj_checked_add"
+        ]
+    );
+    assert_eq!(
+        result.index.fn_prototype_index["pj_sub_overflows"].return_type,
+        Some(BaseType::Bool)
+    );
+    assert!(result
+        .index
+        .fn_prototype_index
+        .contains_key("llvm.sadd.sat.i64"));
+}
+
+#[test]
+fn diagnostics_stream_to_a_sink_as_they_are_found() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main(n Int)
+          a = n.to_s(1)
+          b = n.to_s(40)
+        end
+    "};
+
+    let mut lexer = Lexer::new(input);
+    let tokens = lexer.tokenize();
+    let mut result = Parser::start_parse(tokens, &mut pajama::parser::default_op_precedence());
+
+    let reported = Rc::new(RefCell::new(vec![]));
+    let sink = {
+        let reported = reported.clone();
+        move |message: &str| reported.borrow_mut().push(message.to_string())
+    };
+
+    let analyzer = SemanticAnalyzer::run_with_sink(&mut result, Box::new(sink));
+
+    assert_eq!(reported.borrow().len(), 2);
+    assert_eq!(*reported.borrow(), analyzer.diagnostics.errors);
+}
+
+#[test]
+fn sandbox_stubs_out_every_fn_it_does_not_allow() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def_e pj_listen(server Int)
+        def_e fork() -> Int
+        def_e rand() -> Int
+        def_e print_int(n Int)
+
+        def main
+          fork()
+        end
+    "};
+
+    let (mut result, mut analyzer) = analyze(input);
+
+    apply_sandbox(&mut result, &mut analyzer.diagnostics);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let denied = |name: &str| match &find_def(&result, name).body[..] {
+        [Node::Call(call)] => call.fn_name.clone(),
+        [Node::Ret(ret)] => match ret.value.as_ref() {
+            Node::Call(call) => call.fn_name.clone(),
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        body => panic!("Expected a single call, got {:#?}", body),
+    };
+
+    assert_eq!(denied("pj_listen"), "pj_sandbox_denied");
+    assert_eq!(denied("fork"), "pj_sandbox_denied");
+    // Only the allowed functions are kept, not only the known dangerous ones
+    assert_eq!(denied("rand"), "pj_sandbox_denied");
+
+    let untouched = match &result.module {
+        Node::Module(module) => module.methods.iter().any(|node| match node {
+            Node::DefE(def_e) => def_e.prototype.name == "print_int",
+            _ => false,
+        }),
+        _ => false,
+    };
+
+    assert!(untouched);
+
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def main
+          name = Path.basename(\"logs/today.log\")
+          full = Path.absolute(\"today.log\")
+          logs = Path.glob(\"logs/*.log\")
+        end
+    "};
+
+    let (mut result, mut analyzer) = analyze(input);
+
+    apply_sandbox(&mut result, &mut analyzer.diagnostics);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`pj_path_absolute` isn't available with --sandbox",
+            "`pj_path_glob` isn't available with --sandbox",
+        ]
+    );
+}
+
+#[test]
+fn strict_mode_checks_int_arithmetic() {
+    let input = indoc! {"
+        def_e print_int(n Int)
+
+        def area(width Int, height Int) -> Int
+          width * height + 1
+        end
+
+        def half(x Float) -> Float
+          x - 0.5
+        end
+
+        def main
+          print_int(area(2, 3))
+        end
+    "};
+
+    let (mut result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    apply_strict(&mut result);
+
+    let call = |node: &Node| match node {
+        Node::Call(call) => (call.fn_name.clone(), call.args.clone()),
+        node => panic!("Expected a call, got {:#?}", node),
+    };
+
+    let (add, args) = match &find_def(&result, "area").body[..] {
+        [Node::Ret(ret)] => call(&ret.value),
+        body => panic!("Expected a return, got {:#?}", body),
+    };
+
+    assert_eq!(add, "pj_checked_add");
+    assert_eq!(call(&args[0]).0, "pj_checked_mul");
+
+    // Floats don't overflow
+    assert!(matches!(
+        &find_def(&result, "half").body[..],
+        [Node::Ret(ret)] if matches!(ret.value.as_ref(), Node::Binary(_))
+    ));
+
+    let declared: Vec<&str> = match &result.module {
+        Node::Module(module) => module
+            .methods
+            .iter()
+            .filter_map(|node| match node {
+                Node::DefE(def_e) => Some(def_e.prototype.name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    assert!(declared.contains(&"pj_checked_add"));
+    assert!(declared.contains(&"pj_checked_mul"));
+}
+
+#[test]
+fn minimal_runtime_rejects_fns_that_need_an_operating_system() {
+    let input = indoc! {"
+        def_e pj_listen(server Int)
+        def_e fork() -> Int
+        def_e print_int(n Int)
+
+        def main
+          print_int(1)
+        end
+    "};
+
+    let (result, mut analyzer) = analyze(input);
+
+    check_runtime_profile(&result, RuntimeProfile::Full, &mut analyzer.diagnostics);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    check_runtime_profile(&result, RuntimeProfile::Minimal, &mut analyzer.diagnostics);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`pj_listen` isn't available with --runtime=minimal, it needs an operating system",
+            "`fork` isn't available with --runtime=minimal, it needs an operating system",
+        ]
+    );
+}
+
+#[test]
+fn signal_traps_register_their_handlers_with_the_runtime() {
+    let input = indoc! {"
+        class Str
+          @buffer     BytePtr
+          @length     Int
+          @max_length Int
+        end
+
+        def on_interrupt
+          puts(\"bye\")
+        end
+
+        def main
+          Signal.trap(\"INT\", on_interrupt.fn_ref())
+          Signal.trap(\"BREAK\", on_interrupt.fn_ref())
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Can't trap `BREAK`, expected one of INT, TERM, HUP, QUIT, USR1, USR2"]
+    );
+
+    match &find_def(&result, "main").body[0] {
+        Node::Call(call) => {
+            assert_eq!(call.fn_name, "pj_signal_trap");
+            assert!(matches!(call.args[1], Node::Send(_)));
+        }
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn at_exit_blocks_are_registered_as_exit_hooks() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def flush
+          print_int(1)
+        end
+
+        def main
+          at_exit(flush.fn_ref())
+          at_exit do
+            print_int(2)
+          end
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+    assert!(result.index.fn_prototype_index.contains_key("pj_at_exit"));
+
+    for node in &find_def(&result, "main").body {
+        match node {
+            Node::Call(call) => assert_eq!(call.fn_name, "pj_at_exit"),
+            node => panic!("Expected a call, got {:#?}", node),
+        }
+    }
+
+    match &find_def(&result, "__at_exit_1").body[0] {
+        Node::Call(call) => assert_eq!(call.fn_name, "print_int"),
+        node => panic!("Expected a call, got {:#?}", node),
+    }
+}
+
+#[test]
+fn defers_run_latest_first_before_each_return() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def check(n Int) -> Int
+          defer print_int(1)
+          if n < 2
+            return n
+          end
+          defer print_int(2)
+          n + 1
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let printed = |node: &Node| match node {
+        Node::Call(call) if call.fn_name == "print_int" => match &call.args[0] {
+            Node::Int(int) => int.value,
+            node => panic!("Expected an int, got {:#?}", node),
+        },
+        node => panic!("Expected a call to print_int, got {:#?}", node),
+    };
+
+    let body = &find_def(&result, "check").body;
+
+    match &body[0] {
+        Node::If(if_node) => {
+            assert!(matches!(&if_node.then_body[0], Node::AssignLocalVar(_)));
+            assert_eq!(printed(&if_node.then_body[1]), 1);
+            assert!(matches!(&if_node.then_body[2], Node::Ret(_)));
+        }
+        node => panic!("Expected an if, got {:#?}", node),
+    }
+
+    assert!(matches!(&body[1], Node::AssignLocalVar(_)));
+    assert_eq!(printed(&body[2]), 2);
+    assert_eq!(printed(&body[3]), 1);
+    assert!(matches!(&body[4], Node::Ret(_)));
+}
+
+#[test]
+fn named_args_are_put_in_order_and_defaults_filled_in() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def area(width Int, height Int = 1, depth Int = 1) -> Int
+          width * height * depth
+        end
+
+        class Dog
+          @legs Int
+
+          def walk(steps Int, speed Int = 2) -> Int
+            steps * speed
+          end
+        end
+
+        def main
+          print_int(area(2))
+          print_int(area(depth: 4, width: 3))
+          dog = Dog.new(4)
+          print_int(dog.walk(5))
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let values = |node: &Node| -> Vec<u64> {
+        let call = match node {
+            Node::Call(call) => match &call.args[0] {
+                Node::Call(call) => call,
+                Node::Send(send) => match send.message.as_ref() {
+                    Node::Call(call) => call,
+                    node => panic!("Expected a call, got {:#?}", node),
+                },
+                node => panic!("Expected a call or send, got {:#?}", node),
+            },
+            node => panic!("Expected a call, got {:#?}", node),
+        };
+
+        assert!(call.arg_names.is_empty());
+
+        call.args
+            .iter()
+            .map(|arg| match arg {
+                Node::Int(int) => int.value,
+                node => panic!("Expected an int, got {:#?}", node),
+            })
+            .collect()
+    };
+
+    let body = &find_def(&result, "main").body;
+
+    assert_eq!(values(&body[0]), vec![2, 1, 1]);
+    assert_eq!(values(&body[1]), vec![3, 1, 4]);
+    assert_eq!(values(&body[3]), vec![5, 2]);
+}
+
+#[test]
+fn named_args_with_side_effects_run_in_source_order() {
+    let input = indoc! {"
+        def_e read -> Int
+        def_e print_int(int Int)
+
+        def draw(x Int, y Int) -> Int
+          x - y
+        end
+
+        def main
+          n = draw(y: read(), x: read())
+          m = draw(x: read(), y: read())
+          print_int(n + m)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let assignment = |node: &Node| -> (String, Node) {
+        match node {
+            Node::AssignLocalVar(assignment) => {
+                (assignment.name.clone(), *assignment.value.clone())
+            }
+            node => panic!("Expected an assignment, got {:#?}", node),
+        }
+    };
+    let args = |node: Node| -> Vec<Node> {
+        match node {
+            Node::Call(call) => call.args,
+            node => panic!("Expected a call, got {:#?}", node),
+        }
+    };
+    let local_name = |node: &Node| match node {
+        Node::LocalVar(local_var) => local_var.name.clone(),
+        node => panic!("Expected a local, got {:#?}", node),
+    };
+
+    let body = &find_def(&result, "main").body;
+
+    // `y` is read first, then `x`, as they're given
+    let (first, value) = assignment(&body[0]);
+    assert_eq!(first, "__arg_0");
+    assert!(matches!(value, Node::Call(call) if call.fn_name == "read"));
+
+    let (second, _) = assignment(&body[1]);
+    assert_eq!(second, "__arg_1");
+
+    let (name, value) = assignment(&body[2]);
+    assert_eq!(name, "n");
+    assert_eq!(
+        args(value).iter().map(local_name).collect::<Vec<_>>(),
+        vec!["__arg_1", "__arg_0"]
+    );
+
+    // Already in order, so nothing is kept in locals
+    let (name, value) = assignment(&body[3]);
+    assert_eq!(name, "m");
+    assert!(args(value)
+        .iter()
+        .all(|arg| matches!(arg, Node::Call(call) if call.fn_name == "read")));
+}
+
+#[test]
+fn nested_named_args_with_side_effects_out_of_order_are_reported() {
+    let input = indoc! {"
+        def_e read -> Int
+        def_e print_int(int Int)
+
+        def draw(x Int, y Int) -> Int
+          x - y
+        end
+
+        def main
+          print_int(draw(y: read(), x: 1))
+          print_int(draw(y: read(), x: read()))
+        end
+    "};
+
+    let (_, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`draw` is given named arguments with side effects out of order; give them in order or assign them to locals first"]
+    );
+}
+
+#[test]
+fn locals_are_assigned_on_every_path_before_reads() {
+    let input = indoc! {"
+        def sign(n Int) -> Int
+          if n < 0
+            s = 0
+          end
+          s
+        end
+
+        def clamp(n Int) -> Int
+          if n < 10
+            c = n + 1
+          else
+            return 10
+          end
+          c
+        end
+
+        def count(n Int) -> Int
+          while n < 10
+            i = n + 1
+            n = n + 1
+          end
+          i
+        end
+
+        def first(n Int) -> Int
+          loop {
+            found = n + 1
+            if n < 10
+              break
+            end
+            n = n - 1
+          }
+          found
+        end
+    "};
+
+    let (_, analyzer) = analyze(input);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`s` might be read before it's assigned in `sign`",
+            "`i` might be read before it's assigned in `count`",
+        ]
+    );
+}
+
+#[test]
+fn defs_referred_to_with_ampersand_are_called_and_passed_on() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def double(n Int) -> Int
+          ret n * 2
+        end
+
+        def show(n Int)
+          print_int(n)
+        end
+
+        def main
+          f = &double
+          n = f(3)
+          numbers = [1, 2]
+          doubled = numbers.map(f)
+          doubled.each(&show)
+        end
+    "};
+
+    let (result, analyzer) = analyze(input);
+
+    assert!(analyzer.diagnostics.errors.is_empty());
+
+    let main = find_def(&result, "main");
+
+    // Calling through the reference calls the def
+    match &main.body[1] {
+        Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+            Node::Call(call) => {
+                assert_eq!(call.fn_name, "double");
+                assert_eq!(call.return_type, Some(BaseType::Int));
+            }
+            node => panic!("Expected a call, got {:#?}", node),
+        },
+        node => panic!("Expected an assignment, got {:#?}", node),
+    }
+
+    for (node, fn_name) in [(&main.body[3], "double"), (&main.body[4], "show")] {
+        let send = match node {
+            Node::AssignLocalVar(assignment) => match assignment.value.as_ref() {
+                Node::Send(send) => send,
+                node => panic!("Expected a send, got {:#?}", node),
+            },
+            Node::Send(send) => send,
+            node => panic!("Expected a send, got {:#?}", node),
+        };
+
+        match send.message.as_ref() {
+            Node::Call(call) => match &call.args[0] {
+                Node::FnRef(fn_ref) => assert_eq!(fn_ref.fn_name, fn_name),
+                node => panic!("Expected a function reference, got {:#?}", node),
+            },
+            node => panic!("Expected a call, got {:#?}", node),
+        }
+    }
+
+    let (_, analyzer) = analyze(indoc! {"
+        def_e print_int(int Int)
+
+        def show(n Int)
+          print_int(n)
+        end
+
+        def main
+          f = &show
+          f(true)
+        end
+    "});
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`show` expects Int for `n`, given Bool in `main`"]
+    );
+}
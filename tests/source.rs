@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pajama::source::{decode_source, resolve_import_graph, resolve_imports, SourceFile};
+
+#[test]
+fn a_utf8_bom_is_stripped() {
+    assert_eq!(
+        decode_source(b"\xEF\xBB\xBFdef main\nend\n", false),
+        Ok("def main\nend\n".to_string())
+    );
+}
+
+#[test]
+fn invalid_utf8_is_reported_with_its_offset_or_read_as_latin1() {
+    let bytes = b"# caf\xE9\ndef main\nend\n";
+
+    assert_eq!(
+        decode_source(bytes, false),
+        Err("invalid UTF-8 at byte 5, pass --latin1 to read the file as Latin-1".to_string())
+    );
+    assert_eq!(
+        decode_source(bytes, true),
+        Ok("# caf\u{e9}\ndef main\nend\n".to_string())
+    );
+}
+
+/// A directory of its own under the temp dir, holding `files`
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pajama_{}_{}", name, std::process::id()));
+
+    for (path, input) in files {
+        let path = dir.join(path);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, input).unwrap();
+    }
+
+    dir
+}
+
+fn main_source(dir: &Path) -> SourceFile {
+    let path = dir.join("main.pjs").to_str().unwrap().to_string();
+    let input = fs::read_to_string(&path).unwrap();
+
+    SourceFile { path, input }
+}
+
+#[test]
+fn imports_are_loaded_once_before_the_files_importing_them() {
+    let dir = write_files(
+        "imports",
+        &[
+            (
+                "main.pjs",
+                "import \"lib/util.pjs\"\nimport \"lib/strings.pjs\"\n\ndef main\nend\n",
+            ),
+            ("lib/util.pjs", "import \"strings.pjs\"\n\ndef util\nend\n"),
+            ("lib/strings.pjs", "def strings\nend\n"),
+        ],
+    );
+
+    let (sources, diagnostics) = resolve_imports(vec![main_source(&dir)], false);
+    let paths: Vec<&str> = sources.iter().map(|source| source.path.as_str()).collect();
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(diagnostics.is_empty());
+    assert_eq!(
+        paths,
+        vec![
+            dir.join("lib/strings.pjs").to_str().unwrap(),
+            dir.join("lib/util.pjs").to_str().unwrap(),
+            dir.join("main.pjs").to_str().unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn import_cycles_and_missing_files_are_reported_at_the_import() {
+    let dir = write_files(
+        "import_cycles",
+        &[
+            ("main.pjs", "import \"a.pjs\"\nimport \"missing.pjs\"\n"),
+            ("a.pjs", "import \"b.pjs\"\n"),
+            ("b.pjs", "\nimport \"a.pjs\"\n"),
+        ],
+    );
+
+    let (_, diagnostics) = resolve_imports(vec![main_source(&dir)], false);
+    let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>(),
+        vec![
+            format!(
+                "{}:2:8: import cycle: {}:1:8 imports {}, {}:2:8 imports {}",
+                file("b.pjs"),
+                file("a.pjs"),
+                file("b.pjs"),
+                file("b.pjs"),
+                file("a.pjs")
+            ),
+            format!(
+                "{}:2:8: {}: No such file or directory (os error 2)",
+                file("main.pjs"),
+                file("missing.pjs")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn the_import_graph_names_each_file_once() {
+    let dir = write_files(
+        "import_graph",
+        &[
+            (
+                "main.pjs",
+                "import \"lib/util.pjs\"\nimport \"lib/strings.pjs\"\n\ndef main\nend\n",
+            ),
+            ("lib/util.pjs", "import \"strings.pjs\"\n\ndef util\nend\n"),
+            ("lib/strings.pjs", "def strings\nend\n"),
+        ],
+    );
+
+    let (_, graph, diagnostics) = resolve_import_graph(vec![main_source(&dir)], false);
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    let main = dir.join("main.pjs").to_str().unwrap().to_string();
+    let util = dir.join("lib/util.pjs").to_str().unwrap().to_string();
+    let strings = dir.join("lib/strings.pjs").to_str().unwrap().to_string();
+
+    assert!(diagnostics.is_empty());
+    assert_eq!(
+        graph.files,
+        vec![strings.clone(), util.clone(), main.clone()]
+    );
+    assert_eq!(
+        graph.imports,
+        vec![
+            (main.clone(), util.clone()),
+            (util, strings.clone()),
+            (main, strings),
+        ]
+    );
+}
@@ -0,0 +1,203 @@
+use pajama::pajama_lib::{
+    pj_array_get_int, pj_array_get_ptr, pj_array_length, pj_array_new, pj_array_push_int,
+    pj_bool_to_s, pj_bytes_decode, pj_str_byte_length, pj_str_chars, pj_str_codepoints,
+    pj_str_concat, pj_str_encode, pj_str_ends_with, pj_str_index_of, pj_str_length, pj_str_slice,
+    pj_str_starts_with, pj_str_sub, pj_str_to_nfc, pj_str_to_nfd, pj_str_to_nfkc, pj_str_trim,
+    pjstr_to_str, string_to_pjstr, PjArray, PjStr,
+};
+
+fn pj_str(text: &str) -> &'static PjStr {
+    unsafe { &*string_to_pjstr(text.to_string()) }
+}
+
+fn text(pj_str: *mut PjStr) -> &'static str {
+    pjstr_to_str(unsafe { &*pj_str })
+}
+
+fn bytes(array: *mut PjArray) -> Vec<i64> {
+    let array = unsafe { &*array };
+
+    (0..pj_array_length(array))
+        .map(|index| pj_array_get_int(array, index))
+        .collect()
+}
+
+fn byte_array(bytes: &[i64]) -> &'static PjArray {
+    let array = unsafe { &mut *pj_array_new(0) };
+
+    for byte in bytes {
+        pj_array_push_int(array, *byte);
+    }
+
+    array
+}
+
+#[test]
+fn slices_are_clamped_to_the_ends_of_the_str() {
+    let hello = pj_str("hello world");
+
+    assert_eq!(text(pj_str_slice(hello, 0, 5)), "hello");
+    assert_eq!(text(pj_str_slice(hello, 6, 11)), "world");
+    assert_eq!(text(pj_str_slice(hello, 6, 100)), "world");
+    assert_eq!(text(pj_str_slice(hello, -3, 2)), "he");
+    assert_eq!(text(pj_str_slice(hello, 5, 2)), "");
+    assert_eq!(text(pj_str_slice(hello, 20, 30)), "");
+    assert_eq!(text(pj_str_slice(pj_str(""), 0, 1)), "");
+
+    assert_eq!(text(pj_str_sub(hello, 6, 3)), "wor");
+    assert_eq!(text(pj_str_sub(hello, 6, 100)), "world");
+    assert_eq!(text(pj_str_sub(hello, 2, -1)), "");
+    assert_eq!(text(pj_str_sub(hello, 0, i64::MAX)), "hello world");
+}
+
+#[test]
+fn offsets_are_in_bytes() {
+    let greeting = pj_str("héllo wörld");
+
+    assert_eq!(text(pj_str_slice(greeting, 0, 3)), "hé");
+    assert_eq!(text(pj_str_sub(greeting, 7, 6)), "wörld");
+    assert_eq!(pj_str_index_of(greeting, pj_str("wörld")), 7);
+    assert_eq!(pj_str_index_of(greeting, pj_str("ld")), 11);
+}
+
+#[test]
+fn trim_drops_whitespace_at_either_end() {
+    assert_eq!(text(pj_str_trim(pj_str("  padded\t\n"))), "padded");
+    assert_eq!(text(pj_str_trim(pj_str("in  side"))), "in  side");
+    assert_eq!(text(pj_str_trim(pj_str(" \n "))), "");
+    assert_eq!(text(pj_str_trim(pj_str(""))), "");
+}
+
+#[test]
+fn prefixes_suffixes_and_needles_are_found() {
+    let path = pj_str("src/main.pjs");
+
+    assert!(pj_str_starts_with(path, pj_str("src/")));
+    assert!(pj_str_starts_with(path, pj_str("")));
+    assert!(!pj_str_starts_with(path, pj_str("main")));
+    assert!(!pj_str_starts_with(pj_str("sr"), pj_str("src")));
+
+    assert!(pj_str_ends_with(path, pj_str(".pjs")));
+    assert!(pj_str_ends_with(path, path));
+    assert!(!pj_str_ends_with(path, pj_str(".rs")));
+
+    assert_eq!(pj_str_index_of(path, pj_str("main")), 4);
+    assert_eq!(pj_str_index_of(path, pj_str("s")), 0);
+    assert_eq!(pj_str_index_of(path, pj_str("")), 0);
+    assert_eq!(pj_str_index_of(path, pj_str("lib")), -1);
+    assert_eq!(pj_str_index_of(pj_str(""), pj_str("a")), -1);
+}
+
+#[test]
+fn characters_are_grapheme_clusters() {
+    // "e" followed by a combining acute accent, and a family emoji joined by
+    // zero width joiners
+    let mixed = pj_str("ne\u{301}e 👨‍👩‍👧");
+
+    assert_eq!(pj_str_length(mixed), 5);
+    assert_eq!(pj_str_byte_length(mixed), 24);
+
+    let chars = unsafe { &*pj_str_chars(mixed) };
+    let chars: Vec<&str> = (0..pj_array_length(chars))
+        .map(|index| text(pj_array_get_ptr(chars, index) as *mut PjStr))
+        .collect();
+
+    assert_eq!(chars, vec!["n", "e\u{301}", "e", " ", "👨‍👩‍👧"]);
+}
+
+#[test]
+fn codepoints_are_unicode_scalar_values() {
+    let codepoints = unsafe { &*pj_str_codepoints(pj_str("aé€")) };
+    let codepoints: Vec<i64> = (0..pj_array_length(codepoints))
+        .map(|index| pj_array_get_int(codepoints, index))
+        .collect();
+
+    assert_eq!(codepoints, vec![0x61, 0xe9, 0x20ac]);
+    assert_eq!(
+        pj_array_length(unsafe { &*pj_str_codepoints(pj_str("")) }),
+        0
+    );
+}
+
+#[test]
+fn normalization_makes_equal_looking_strs_equal() {
+    let composed = pj_str("caf\u{e9}");
+    let decomposed = pj_str("cafe\u{301}");
+
+    assert_eq!(text(pj_str_to_nfc(decomposed)), "caf\u{e9}");
+    assert_eq!(text(pj_str_to_nfd(composed)), "cafe\u{301}");
+    assert_eq!(pj_str_length(composed), pj_str_length(decomposed));
+    assert_eq!(text(pj_str_to_nfkc(pj_str("\u{fb01}le"))), "file");
+}
+
+#[test]
+fn encoding_converts_to_and_from_bytes() {
+    let cafe = pj_str("caf\u{e9}");
+    let error = pj_str("error");
+
+    assert_eq!(
+        bytes(pj_str_encode(cafe, pj_str("utf-8"), error)),
+        vec![0x63, 0x61, 0x66, 0xc3, 0xa9]
+    );
+    assert_eq!(
+        bytes(pj_str_encode(cafe, pj_str("UTF-16"), error)),
+        vec![0x63, 0, 0x61, 0, 0x66, 0, 0xe9, 0]
+    );
+    assert_eq!(
+        bytes(pj_str_encode(pj_str("\u{20ac}"), pj_str("utf-16be"), error)),
+        vec![0x20, 0xac]
+    );
+    assert_eq!(
+        bytes(pj_str_encode(cafe, pj_str("latin-1"), error)),
+        vec![0x63, 0x61, 0x66, 0xe9]
+    );
+
+    for encoding in ["utf-8", "utf-16", "utf-16be", "latin-1"] {
+        let encoding = pj_str(encoding);
+        let encoded = unsafe { &*pj_str_encode(cafe, encoding, error) };
+
+        assert_eq!(text(pj_bytes_decode(encoded, encoding, error)), "caf\u{e9}");
+    }
+}
+
+#[test]
+fn invalid_sequences_are_replaced_when_asked() {
+    let replace = pj_str("replace");
+
+    assert_eq!(
+        bytes(pj_str_encode(
+            pj_str("5\u{20ac}"),
+            pj_str("latin-1"),
+            replace
+        )),
+        vec![0x35, 0x3f]
+    );
+    assert_eq!(
+        text(pj_bytes_decode(
+            byte_array(&[0x61, 0xff]),
+            pj_str("utf-8"),
+            replace
+        )),
+        "a\u{fffd}"
+    );
+    // An unpaired high surrogate, then a dangling byte
+    assert_eq!(
+        text(pj_bytes_decode(
+            byte_array(&[0x00, 0xd8, 0x62, 0x00, 0x63]),
+            pj_str("utf-16"),
+            replace
+        )),
+        "\u{fffd}b\u{fffd}"
+    );
+}
+
+#[test]
+fn strs_concatenate_and_bools_format_as_words() {
+    assert_eq!(
+        text(pj_str_concat(pj_str("Hello, "), pj_str("wörld"))),
+        "Hello, wörld"
+    );
+    assert_eq!(text(pj_str_concat(pj_str(""), pj_str(""))), "");
+    assert_eq!(text(pj_bool_to_s(true)), "true");
+    assert_eq!(text(pj_bool_to_s(false)), "false");
+}
@@ -0,0 +1,284 @@
+use pajama::lexer::Lexer;
+use pajama::parser::{default_op_precedence, Parser};
+use pajama::semantic_analyzer::SemanticAnalyzer;
+
+use indoc::indoc;
+
+#[test]
+fn mismatched_types_are_reported_before_codegen() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        class Animal
+          @legs Int
+        end
+
+        class Dog < Animal
+          @tail Int
+        end
+
+        def legs(animal Animal) -> Int
+          ret 4
+        end
+
+        def twice(n Int) -> Int
+          ret n * 2
+        end
+
+        def name(dog Dog) -> Int
+          ret \"rex\"
+        end
+
+        def main
+          sum = 1 + \"abc\"
+          dog = Dog.new(4, 1)
+          print_int(legs(dog))
+          print_int(twice(dog))
+          print_int(twice(1, 2))
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "Returns Str but Int is declared in `name`",
+            "`+` can't be applied to Int and Str in `main`",
+            "`twice` expects Int for `n`, given Dog in `main`",
+            "`twice` takes 1 argument, given 2 in `main`",
+        ]
+    );
+}
+
+#[test]
+fn calls_to_undefined_functions_are_reported() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+
+        def main
+          n = 2
+          print_int(-n)
+          print_int(-1)
+          foo(1)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`foo` isn't defined in `main`"]
+    );
+}
+
+#[test]
+fn returned_values_are_checked_against_the_return_type() {
+    let input = indoc! {"
+        def name(n Int) -> Int
+          \"rex\"
+        end
+
+        def pick(n Int) -> Int
+          if n < 0
+            return \"negative\"
+          end
+          n
+        end
+
+        def count(n Int) -> Int
+          total = n + 1
+        end
+
+        def main
+          pick(name(1))
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`count` should return Int, but its last statement has no value",
+            "Returns Str but Int is declared in `name`",
+            "Returns Str but Int is declared in `pick`",
+        ]
+    );
+}
+
+#[test]
+fn named_args_and_default_values_are_checked() {
+    let input = indoc! {"
+        def area(width Int, height Int = 1) -> Int
+          width * height
+        end
+
+        def greet(name Str = 1)
+        end
+
+        def main
+          area(height: 2)
+          area(1, width: 2)
+          area(1, size: 2)
+          area(1)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`area` is missing an argument for `width`",
+            "`area` is given `width` more than once",
+            "`area` has no argument named `size`",
+            "`name` is declared as Str but defaults to Int in `greet`",
+        ]
+    );
+}
+
+#[test]
+fn optionals_are_checked_before_they_are_sent_to() {
+    let input = indoc! {"
+        class Dog
+          @legs Int
+          @friend Dog?
+
+          def legs() -> Int
+            @legs
+          end
+
+          def friend() -> Dog?
+            @friend
+          end
+        end
+
+        def walk(dog Dog?) -> Int
+          if dog != nil
+            dog.legs()
+          else
+            0
+          end
+        end
+
+        def guard(dog Dog?) -> Int
+          if dog == nil
+            ret 0
+          end
+
+          dog.legs()
+        end
+
+        def unchecked(dog Dog?) -> Int
+          dog.legs()
+        end
+
+        def friend(dog Dog?) -> Dog?
+          dog&.friend()
+        end
+
+        def legs(dog Dog?) -> Int
+          dog&.legs()
+        end
+
+        def count(n Int?) -> Int
+          1
+        end
+
+        def main
+          walk(nil)
+          walk(Dog.new(4, nil))
+          lost = nil
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec![
+            "`legs` is sent to `dog`, which might be nil; check that it isn't or send it with `&.` in `unchecked`",
+            "`&.legs` returns Int, which can't be nil in `legs`",
+            "`n` is declared as Int?, but only classes can be optional in `count`",
+            "`lost` can't be assigned nil, locals are typed by the value they're assigned in `main`",
+        ]
+    );
+}
+
+#[test]
+fn nil_is_given_wherever_an_optional_is_expected() {
+    let input = indoc! {"
+        class Dog
+          @legs Int
+          @friend Dog?
+
+          def befriend(friend Dog?)
+            self.friend = friend
+          end
+        end
+
+        def pick(dog Dog? = nil, legs Int = 4) -> Dog?
+          ret dog
+        end
+
+        def count(dogs [Dog?]) -> Int
+          1
+        end
+
+        def legs(dog Dog) -> Int
+          1
+        end
+
+        def main
+          dog = Dog.new(4, nil)
+          dog.befriend(nil)
+          picked = pick(nil, 3)
+          named = pick(dog: nil)
+          n = count([dog, nil])
+          m = count([nil, dog])
+          l = legs(nil)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["`legs` expects Dog for `dog`, given nil in `main`"]
+    );
+}
+
+#[test]
+fn locals_inference_could_not_type_are_reported() {
+    let input = indoc! {"
+        def_e print_int(int Int)
+        def_e nothing()
+
+        def main
+          x = nothing()
+          print_int(x)
+        end
+    "};
+
+    let tokens = Lexer::new(input).tokenize();
+    let mut result = Parser::start_parse(tokens, &mut default_op_precedence());
+    let analyzer = SemanticAnalyzer::run(&mut result);
+
+    assert_eq!(
+        analyzer.diagnostics.errors,
+        vec!["Could not infer the type of `x` passed to `print_int` in `main`"]
+    );
+}